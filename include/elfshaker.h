@@ -0,0 +1,136 @@
+/*
+ * SPDX-License-Identifier: Apache-2.0
+ * Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+ */
+
+#ifndef ELFSHAKER_H
+#define ELFSHAKER_H
+
+/* This file is generated by cbindgen from src/capi.rs. Do not edit it. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Verify the checksums of the extracted objects.
+ */
+#define ELFSHAKER_EXTRACT_VERIFY 1
+
+/**
+ * Extract all files, even if the snapshot is already extracted (HEAD).
+ */
+#define ELFSHAKER_EXTRACT_RESET 2
+
+/**
+ * Skip the checks guarding against overwriting user-modified files.
+ */
+#define ELFSHAKER_EXTRACT_FORCE 4
+
+/**
+ * An open elfshaker repository.
+ */
+typedef struct ElfshakerRepository ElfshakerRepository;
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns the message describing the last error which occurred on the
+ * calling thread, or NULL. The string is valid until the next call to an
+ * elfshaker function on the same thread.
+ */
+const char *elfshaker_last_error(void);
+
+/**
+ * Opens the repository whose working directory is `path`. `data_dir` is the
+ * path of the elfshaker_data directory, or NULL to use the one in `path`.
+ * The repository must be freed with [`elfshaker_repository_free`].
+ *
+ * # Safety
+ *
+ * `path` and `data_dir` must be NULL or valid NUL-terminated strings.
+ */
+struct ElfshakerRepository *elfshaker_repository_open(const char *path, const char *data_dir);
+
+/**
+ * Closes the repository.
+ *
+ * # Safety
+ *
+ * `repo` must be NULL or a pointer returned by [`elfshaker_repository_open`]
+ * which has not been freed yet.
+ */
+void elfshaker_repository_free(struct ElfshakerRepository *repo);
+
+/**
+ * Lists the snapshots in the repository (as `<pack>:<tag>`). On success,
+ * `*snapshots` points to an array of `*count` strings, which must be freed
+ * with [`elfshaker_string_list_free`].
+ *
+ * # Safety
+ *
+ * `repo` must be a pointer returned by [`elfshaker_repository_open`],
+ * `snapshots` and `count` must be valid pointers.
+ */
+int elfshaker_list_snapshots(struct ElfshakerRepository *repo, char ***snapshots, uintptr_t *count);
+
+/**
+ * Frees a list returned by [`elfshaker_list_snapshots`].
+ *
+ * # Safety
+ *
+ * `list` and `count` must have been returned by [`elfshaker_list_snapshots`]
+ * and not freed yet.
+ */
+void elfshaker_string_list_free(char **list, uintptr_t count);
+
+/**
+ * Extracts the snapshot into the working directory of the repository, like
+ * `elfshaker extract`. `flags` is a combination of the `ELFSHAKER_EXTRACT_*`
+ * flags.
+ *
+ * # Safety
+ *
+ * `repo` must be a pointer returned by [`elfshaker_repository_open`] and
+ * `snapshot` a valid NUL-terminated string.
+ */
+int elfshaker_extract_snapshot(struct ElfshakerRepository *repo,
+                               const char *snapshot,
+                               uint32_t flags);
+
+/**
+ * Reads the contents of the file at `path` in the snapshot into memory,
+ * without writing anything to disk. The checksum of the object is verified.
+ * On success, `*data` points to `*size` bytes, which must be freed with
+ * [`elfshaker_buffer_free`].
+ *
+ * # Safety
+ *
+ * `repo` must be a pointer returned by [`elfshaker_repository_open`],
+ * `snapshot` and `path` valid NUL-terminated strings, and `data` and `size`
+ * valid pointers.
+ */
+int elfshaker_read_file(struct ElfshakerRepository *repo,
+                        const char *snapshot,
+                        const char *path,
+                        uint8_t **data,
+                        uintptr_t *size);
+
+/**
+ * Frees a buffer returned by [`elfshaker_read_file`].
+ *
+ * # Safety
+ *
+ * `data` and `size` must have been returned by [`elfshaker_read_file`] and
+ * not freed yet.
+ */
+void elfshaker_buffer_free(uint8_t *data, uintptr_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ELFSHAKER_H */
@@ -7,8 +7,6 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use std::os::unix::fs::MetadataExt;
-
 /// AtomicCreateFile provides an API for atomically creating a file, determining
 /// if it exists before proceeding to do potentially expensive work to fill it.
 /// The primitives should be used like this:
@@ -64,14 +62,26 @@ fn lock_name(name: &Path, fd: &File) -> io::Result<()> {
     fd.try_lock_exclusive()?;
     // Lock acquired. Ensure that the name on the filesystem corresponds
     // to the lock now held.
-    let i0 = fd.metadata()?.ino();
-    let i1 = fs::metadata(name)?.ino();
-    if i0 != i1 {
+    if !is_same_file(name, fd)? {
         return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
     }
     Ok(())
 }
 
+#[cfg(unix)]
+fn is_same_file(name: &Path, fd: &File) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fd.metadata()?.ino() == fs::metadata(name)?.ino())
+}
+
+/// Windows doesn't expose the file index in stable Rust, but it doesn't let
+/// the lock file be deleted while it is open with a lock either.
+#[cfg(not(unix))]
+fn is_same_file(name: &Path, _fd: &File) -> io::Result<bool> {
+    fs::metadata(name).map(|_| true)
+}
+
 impl<'l> AtomicCreateFile<'l> {
     pub fn new(dest: &'l Path) -> io::Result<Self> {
         let mut atomic_create_for_write = OpenOptions::new();
@@ -2,41 +2,79 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 //! Batch file operation implementations.
+use crate::checksum::ChecksumAlgorithm;
+use crate::codec::{Codec, Encoder};
 use crate::packidx::ObjectChecksum;
 use crate::progress::ProgressReporter;
 use crate::repo::run_in_parallel;
-use crypto::digest::Digest;
-use crypto::sha1::Sha1;
+use lz4_flex::frame::{BlockMode, BlockSize, FrameEncoder, FrameInfo};
 use std::{
     fs,
     io::{self, Read, Write},
     path::Path,
 };
+use xz2::stream::MtStreamBuilder;
+use xz2::write::XzEncoder;
 use zstd::stream::raw::CParameter;
-use zstd::Encoder;
 
 /// Computes the content checksums of the files at the listed paths.
-pub fn compute_checksums<P>(paths: &[P]) -> io::Result<Vec<ObjectChecksum>>
+pub fn compute_checksums<P>(
+    paths: &[P],
+    algorithm: ChecksumAlgorithm,
+) -> io::Result<Vec<ObjectChecksum>>
 where
     P: AsRef<Path> + Sync,
 {
     run_in_parallel(num_cpus::get(), paths.iter(), |path| {
         let buf = fs::read(path)?;
-        let checksum_buf = &mut [0u8; 20];
-        let mut hasher = Sha1::new();
-        hasher.input(&buf);
-        hasher.result(checksum_buf);
-        Ok(*checksum_buf)
+        Ok(algorithm.checksum(&buf))
     })
     .into_iter()
     .collect::<io::Result<Vec<_>>>()
 }
 
 /// Options for the batch compression functions.
-pub struct CompressionOptions {
+pub struct CompressionOptions<'a> {
+    pub codec: Codec,
+    /// The compression level. For [`Codec::Xz`], the level is clamped to the
+    /// XZ presets (0 to 9) and [`Codec::Lz4`] ignores it.
     pub level: i32,
+    /// The window log ([`Codec::Zstd`] only).
     pub window_log: u32,
     pub num_workers: u32,
+    /// The dictionary to compress with, empty for none ([`Codec::Zstd`] only).
+    pub dictionary: &'a [u8],
+}
+
+/// Creates an encoder writing a frame compressed with the specified options.
+fn create_encoder<'a, W: Write>(
+    writer: W,
+    opts: &CompressionOptions<'a>,
+) -> io::Result<Encoder<'a, W>> {
+    Ok(match opts.codec {
+        Codec::Zstd => {
+            let mut encoder = zstd::Encoder::with_dictionary(writer, opts.level, opts.dictionary)?;
+            // Zstandard takes NbWorkers to mean extra compression threads (0 means on same thread as IO).
+            encoder.set_parameter(CParameter::NbWorkers(opts.num_workers - 1))?;
+            encoder.set_parameter(CParameter::EnableLongDistanceMatching(true))?;
+            encoder.set_parameter(CParameter::WindowLog(opts.window_log))?;
+            Encoder::Zstd(encoder)
+        }
+        Codec::Lz4 => {
+            let mut frame_info = FrameInfo::new();
+            // Matches can reference the previous blocks.
+            frame_info.block_mode = BlockMode::Linked;
+            frame_info.block_size = BlockSize::Max4MB;
+            Encoder::Lz4(FrameEncoder::with_frame_info(frame_info, writer))
+        }
+        Codec::Xz => {
+            let stream = MtStreamBuilder::new()
+                .threads(opts.num_workers)
+                .preset(opts.level.clamp(0, 9) as u32)
+                .encoder()?;
+            Encoder::Xz(XzEncoder::new_stream(writer, stream))
+        }
+    })
 }
 
 /// Compresses the specified set of files using ZStandard compression and the specified options.
@@ -51,7 +89,7 @@ pub struct CompressionOptions {
 pub fn compress_files<W, I, R>(
     pack_file: W,
     object_readers: I,
-    opts: &CompressionOptions,
+    opts: &CompressionOptions<'_>,
     reporter: &ProgressReporter,
 ) -> io::Result<u64>
 where
@@ -61,11 +99,7 @@ where
 {
     assert!(opts.num_workers > 0);
     // Initialize encoder.
-    let mut encoder = Encoder::new(pack_file, opts.level)?;
-    // Zstandard takes NbWorkers to mean extra compression threads (0 means on same thread as IO).
-    encoder.set_parameter(CParameter::NbWorkers(opts.num_workers - 1))?;
-    encoder.set_parameter(CParameter::EnableLongDistanceMatching(true))?;
-    encoder.set_parameter(CParameter::WindowLog(opts.window_log))?;
+    let mut encoder = create_encoder(pack_file, opts)?;
 
     let n_objects = object_readers.len();
     let mut processed_bytes = 0;
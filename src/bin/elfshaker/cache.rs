@@ -0,0 +1,113 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+use url::Url;
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::repo::remote::{build_agent, fetch_build_cache_entry, upload_build_cache_entry};
+
+pub(crate) const SUBCOMMAND: &str = "cache";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap());
+    let key = matches.value_of("key").unwrap();
+    let url = matches.value_of("url").map(Url::parse).transpose()?;
+    let token = matches.value_of("token");
+
+    if let Some(input) = matches.value_of("put") {
+        let contents = match input {
+            "-" => {
+                let mut buf = vec![];
+                io::stdin().read_to_end(&mut buf)?;
+                buf
+            }
+            path => fs::read(path)?,
+        };
+        match url {
+            Some(url) => {
+                upload_build_cache_entry(&build_agent(&[]), &url, key, &contents, token)?;
+                status!("Stored {key} in {url}");
+            }
+            None => {
+                fs::create_dir_all(data_dir)?;
+                let snapshot =
+                    open_repo_from_cwd(data_dir)?.write_build_cache_entry(key, &contents)?;
+                status!("Stored {key} in {snapshot}");
+            }
+        }
+        return Ok(());
+    }
+
+    let contents = match url {
+        Some(url) => fetch_build_cache_entry(&build_agent(&[]), &url, key)?,
+        None => open_repo_from_cwd(data_dir)?.read_build_cache_entry(key)?,
+    };
+    let contents = contents.ok_or_else(|| format!("The build cache has no entry {key}!"))?;
+    match matches.value_of("output") {
+        Some(path) => fs::write(path, &contents)?,
+        None => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(&contents)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Reads (or stores, with --put) an entry of the build cache, from the repository \
+            or from the build cache served by `elfshaker serve --cache` at --url. The \
+            command fails if there is no such entry.",
+        )
+        .arg(Arg::with_name("key").required(true).index(1).help(
+            "The key of the entry (e.g. the hash of the inputs of a compilation), \
+                    which can contain / between its parts.",
+        ))
+        .arg(
+            Arg::with_name("put")
+                .long("put")
+                .takes_value(true)
+                .value_name("file")
+                .conflicts_with("output")
+                .help("Stores the contents of the file (or of stdin, for '-') as the entry."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("file")
+                .help("Writes the entry to the file, instead of stdout."),
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .takes_value(true)
+                .value_name("url")
+                .help(
+                    "The URL of the build cache (e.g. http://<address>/cache/), instead of \
+                    the repository.",
+                ),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .env("ELFSHAKER_TOKEN")
+                .hide_env_values(true)
+                .help(
+                    "Sends the token in the 'Authorization: Bearer' header, to store \
+                    entries in the build cache at --url.",
+                ),
+        )
+}
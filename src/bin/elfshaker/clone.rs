@@ -9,7 +9,10 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use super::utils::create_percentage_print_reporter;
+use super::utils::{
+    config, create_percentage_print_reporter, encryption_keys, http_auth, remote_http_auth,
+    remote_mirrors, trust_policies, trusted_keys,
+};
 use elfshaker::repo::Repository;
 
 pub(crate) const SUBCOMMAND: &str = "clone";
@@ -17,7 +20,13 @@ pub(crate) const SUBCOMMAND: &str = "clone";
 pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
     let origin_url = matches.value_of("repository").unwrap();
-    let directory = matches.value_of("directory").unwrap();
+    let fetch_packs = matches.is_present("fetch-packs");
+    let directory = match matches.value_of("directory") {
+        Some(directory) => directory.to_owned(),
+        None => default_directory(origin_url)
+            .ok_or_else(|| format!("Cannot infer the directory name from '{origin_url}'!"))?,
+    };
+    let directory = directory.as_str();
 
     let original_cwd = std::env::current_dir()?;
     if Path::new(directory).exists() {
@@ -29,7 +38,7 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         directory.to_owned(),
         create_random_name()
     ));
-    if let Err(e) = do_clone(&temp_directory, data_dir, origin_url) {
+    if let Err(e) = do_clone(&temp_directory, data_dir, origin_url, fetch_packs) {
         let _ = fs::remove_dir_all(temp_directory);
         return Err(e);
     };
@@ -41,7 +50,12 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn do_clone(work_dir: &Path, data_dir: &Path, origin_url: &str) -> Result<(), Box<dyn Error>> {
+fn do_clone(
+    work_dir: &Path,
+    data_dir: &Path,
+    origin_url: &str,
+    fetch_packs: bool,
+) -> Result<(), Box<dyn Error>> {
     // Resolve the data_dir relative to the work_dir.
     let data_dir = if data_dir.is_relative() {
         work_dir.join(data_dir)
@@ -55,11 +69,48 @@ fn do_clone(work_dir: &Path, data_dir: &Path, origin_url: &str) -> Result<(), Bo
     let mut repo = Repository::open_with_data_dir(work_dir, &data_dir)?;
 
     repo.set_progress_reporter(|msg| create_percentage_print_reporter(msg, 5));
+    let mut auth = http_auth(&data_dir)?;
+    auth.extend(remote_http_auth("origin", origin_url)?);
+    repo.set_http_auth(auth);
+    repo.set_remote_mirrors(remote_mirrors()?);
+    repo.set_trusted_keys(trusted_keys()?);
+    repo.set_trust_policies(trust_policies()?);
+    repo.set_encryption_keys(encryption_keys()?);
+    repo.set_index_disk_cache(config().get_bool("cache.indexes")?.unwrap_or(false));
+    repo.set_object_index(config().get_bool("cache.objects")?.unwrap_or(false));
     repo.add_remote("origin", origin_url)?;
     repo.update_remotes()?;
+
+    if fetch_packs {
+        for pack_id in repo.packs()? {
+            if !repo.is_pack_loose(&pack_id) && !repo.get_pack_path(&pack_id).exists() {
+                repo.update_remote_pack(&pack_id)?;
+            }
+        }
+    }
     Ok(())
 }
 
+/// Picks the name of the directory to clone into from the URL of the remote
+/// index, e.g. `https://example.com/releases/llvm.esi` -> `llvm`. Generic
+/// index names (`index.esi`) use the name of the parent directory or host instead.
+fn default_directory(origin_url: &str) -> Option<String> {
+    let url = url::Url::parse(origin_url).ok()?;
+    let segments: Vec<_> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+    let stem = segments
+        .last()
+        .and_then(|s| Path::new(s).file_stem())
+        .map(|s| s.to_string_lossy().into_owned());
+    match stem {
+        Some(stem) if stem != "index" => Some(stem),
+        _ if segments.len() > 1 => Some(segments[segments.len() - 2].to_owned()),
+        _ => url.host_str().map(|h| h.to_owned()),
+    }
+}
+
 pub(crate) fn get_app() -> App<'static, 'static> {
     App::new(SUBCOMMAND)
         .about("Clones a remote repository into a new directory")
@@ -69,12 +120,14 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .index(1)
                 .help("The URL of the remote repository index (.esi) to clone."),
         )
-        .arg(
-            Arg::with_name("directory")
-                .required(true)
-                .index(2)
-                .help("The name of a new directory to clone into."),
-        )
+        .arg(Arg::with_name("directory").index(2).help(
+            "The name of a new directory to clone into. Defaults to \
+                    the name of the remote repository index.",
+        ))
+        .arg(Arg::with_name("fetch-packs").long("fetch-packs").help(
+            "Also fetches all packs, instead of fetching them on demand \
+                    when extracting snapshots.",
+        ))
 }
 
 fn create_random_name() -> String {
@@ -0,0 +1,29 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches, Shell};
+use std::error::Error;
+
+pub(crate) const SUBCOMMAND: &str = "completions";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let shell: Shell = matches.value_of("shell").unwrap().parse()?;
+    super::get_app().gen_completions_to("elfshaker", shell, &mut std::io::stdout());
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Prints the completion script of the shell, which completes the subcommands and \
+            options of elfshaker. For example, for bash: \
+            source <(elfshaker completions bash)",
+        )
+        .arg(
+            Arg::with_name("shell")
+                .required(true)
+                .index(1)
+                .possible_values(&Shell::variants())
+                .help("The shell to print the completion script of."),
+        )
+}
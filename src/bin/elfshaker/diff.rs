@@ -0,0 +1,59 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+use super::utils::open_repo_from_cwd;
+
+pub(crate) const SUBCOMMAND: &str = "diff";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let from = matches.value_of("from").unwrap();
+    let to = matches.value_of("to").unwrap();
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let from = repo.find_snapshot(from)?;
+    let to = repo.find_snapshot(to)?;
+
+    let diff = repo.diff_snapshots(&from, &to)?;
+
+    let mut lines = vec![];
+    for entry in &diff.added {
+        lines.push((entry.path.to_string_lossy(), 'A'));
+    }
+    for entry in &diff.removed {
+        lines.push((entry.path.to_string_lossy(), 'D'));
+    }
+    for (entry, _) in &diff.modified {
+        lines.push((entry.path.to_string_lossy(), 'M'));
+    }
+    lines.sort();
+
+    for (path, status) in lines {
+        println!("{status}\t{path}");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Prints the files which were added (A), deleted (D) or modified (M) \
+            between two snapshots.",
+        )
+        .arg(
+            Arg::with_name("from")
+                .required(true)
+                .index(1)
+                .help("The snapshot to compare from."),
+        )
+        .arg(
+            Arg::with_name("to")
+                .required(true)
+                .index(2)
+                .help("The snapshot to compare to."),
+        )
+}
@@ -0,0 +1,141 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    ops::ControlFlow,
+};
+
+use super::utils::{format_size, open_repo_from_cwd};
+use elfshaker::packidx::{ObjectChecksum, PackIndex};
+use elfshaker::repo::{PackId, Repository};
+
+pub(crate) const SUBCOMMAND: &str = "du";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let packs = matches.values_of_lossy("pack");
+    let bytes = matches.is_present("bytes");
+
+    let repo = open_repo_from_cwd(data_dir)?;
+
+    let packs = packs
+        .map(|packs| packs.iter().cloned().map(PackId::Pack).collect())
+        .unwrap_or(repo.packs()?);
+
+    let size = |size: u64| {
+        if bytes {
+            size.to_string()
+        } else {
+            format_size(size)
+        }
+    };
+    for row in snapshot_usage(&repo, &packs)? {
+        println!(
+            "{}\t{}\t{}",
+            size(row.unique),
+            size(row.total),
+            row.snapshot
+        );
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Prints, for each snapshot, the size of the objects which are only \
+            used by that snapshot (which would be freed by removing it), along \
+            with the total size of its files.",
+        )
+        .arg(
+            Arg::with_name("pack")
+                .index(1)
+                .required(false)
+                .multiple(true)
+                .help("Prints the snapshots of the specified packs."),
+        )
+        .arg(
+            Arg::with_name("bytes")
+                .short("b")
+                .long("bytes")
+                .help("Prints the sizes in bytes."),
+        )
+}
+
+struct SnapshotUsage {
+    snapshot: String,
+    /// The total size of the objects only referenced by the snapshot.
+    unique: u64,
+    /// The total size of the files in the snapshot.
+    total: u64,
+}
+
+/// Counts the number of snapshots of the index referencing each object.
+fn count_references(
+    index: &PackIndex,
+    references: &mut HashMap<ObjectChecksum, usize>,
+) -> Result<(), Box<dyn Error>> {
+    index.for_each_snapshot(|_, entries| {
+        // Files with the same contents in one snapshot count as one reference.
+        let objects: HashSet<_> = entries.iter().map(|e| e.checksum).collect();
+        for checksum in objects {
+            *references.entry(*checksum).or_default() += 1;
+        }
+        ControlFlow::<(), ()>::Continue(())
+    })?;
+    Ok(())
+}
+
+fn snapshot_usage(
+    repo: &Repository,
+    packs: &[PackId],
+) -> Result<Vec<SnapshotUsage>, Box<dyn Error>> {
+    // Objects are shared between the snapshots of a pack. The loose objects
+    // are shared between all loose snapshots, whichever packs are listed.
+    let mut loose_references = HashMap::new();
+    if packs.iter().any(|p| repo.is_pack_loose(p)) {
+        for pack_id in repo.packs()? {
+            if repo.is_pack_loose(&pack_id) {
+                count_references(&*repo.load_index_cached(&pack_id)?, &mut loose_references)?;
+            }
+        }
+    }
+
+    let mut rows = vec![];
+    for pack_id in packs {
+        let index = repo.load_index_cached(pack_id)?;
+        let mut pack_references = HashMap::new();
+        let references = if repo.is_pack_loose(pack_id) {
+            &loose_references
+        } else {
+            count_references(&index, &mut pack_references)?;
+            &pack_references
+        };
+
+        index.for_each_snapshot(|tag, entries| {
+            let mut row = SnapshotUsage {
+                snapshot: format!("{pack_id}:{tag}"),
+                unique: 0,
+                total: 0,
+            };
+            // The unique objects are only freed once, whatever the number of
+            // files with the same contents in the snapshot.
+            let mut unique_objects = HashSet::new();
+            for entry in entries {
+                row.total += entry.object_metadata.size;
+                if references.get(entry.checksum) == Some(&1)
+                    && unique_objects.insert(entry.checksum)
+                {
+                    row.unique += entry.object_metadata.size;
+                }
+            }
+            rows.push(row);
+            ControlFlow::<(), ()>::Continue(())
+        })?;
+    }
+    Ok(rows)
+}
@@ -0,0 +1,118 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::packidx::bytes_to_os_string;
+use elfshaker::repo::{Repository, SnapshotId};
+
+pub(crate) const SUBCOMMAND: &str = "export";
+
+/// The zstd compression level used for .tar.zst archives.
+const TAR_ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap());
+    let snapshot = matches.value_of("snapshot").unwrap();
+    let output = matches.value_of("output");
+    let zstd = matches.is_present("zstd") || output.is_some_and(|o| o.ends_with(".zst"));
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let snapshot = repo.find_snapshot(snapshot)?;
+
+    let writer: Box<dyn Write> = match output {
+        None | Some("-") => Box::new(io::stdout()),
+        Some(path) => Box::new(fs::File::create(path)?),
+    };
+    let writer: Box<dyn Write> = if zstd {
+        Box::new(zstd::Encoder::new(writer, TAR_ZSTD_COMPRESSION_LEVEL)?.auto_finish())
+    } else {
+        writer
+    };
+    let file_count = write_tar(&repo, &snapshot, true, writer)?;
+
+    status!("Exported {file_count} files from {snapshot}");
+    Ok(())
+}
+
+/// Writes the files of the snapshot to `writer` as an (uncompressed) tar
+/// archive. Returns the number of files written.
+pub(crate) fn write_tar(
+    repo: &Repository,
+    snapshot: &SnapshotId,
+    verify: bool,
+    writer: impl Write,
+) -> Result<usize, Box<dyn Error>> {
+    let files = repo.stream_snapshot(snapshot, verify)?;
+    let writer = io::BufWriter::new(writer);
+
+    let mut builder = tar::Builder::new(writer);
+    let mut file_count = 0;
+    for file in files {
+        let (path, mut reader) = file?;
+        let file_metadata = reader.entry().file_metadata;
+        let mut header = tar::Header::new_gnu();
+        // Only store the permission bits, like tar does.
+        header.set_mode(file_metadata.mode & 0o7777);
+        // The recorded modification time (see store --record-times), or else
+        // the epoch, so that the same snapshot always gives the same archive.
+        let file_mtime = file_metadata
+            .mtime
+            .map(|t| t.div_euclid(1_000_000_000).max(0) as u64);
+        header.set_mtime(file_mtime.unwrap_or(0));
+        if file_metadata.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, path, io::empty())?;
+        } else if file_metadata.is_symlink() {
+            let mut target = vec![];
+            reader.read_to_end(&mut target)?;
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, path, bytes_to_os_string(&target))?;
+        } else {
+            header.set_size(reader.len());
+            builder.append_data(&mut header, path, reader)?;
+        }
+        file_count += 1;
+    }
+    builder.into_inner()?.flush()?;
+    Ok(file_count)
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about("Exports the contents of a snapshot as an archive.")
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .index(1)
+                .help("The snapshot to export."),
+        )
+        .arg(
+            Arg::with_name("tar")
+                .long("tar")
+                .required(true)
+                .help("Writes a tar archive (the only supported format)."),
+        )
+        .arg(
+            Arg::with_name("zstd")
+                .long("zstd")
+                .help("Compresses the archive with zstd (implied for '.zst' outputs)."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("file")
+                .help("Writes the archive to the specified file. Defaults to stdout ('-')."),
+        )
+}
@@ -4,11 +4,16 @@
 use std::error::Error;
 
 use clap::{App, Arg, ArgMatches};
+use glob::Pattern;
 use log::{info, warn};
 
-use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use super::export::write_tar;
+use super::utils::{
+    create_percentage_print_reporter, flag_or_config, open_repo_from_cwd, run_hook, value_or_config,
+};
 use elfshaker::packidx::PackError;
-use elfshaker::repo::{Error as RepoError, ExtractOptions};
+use elfshaker::repo::fs::extended_length_path;
+use elfshaker::repo::{CaseCollisions, Error as RepoError, ExtractOptions, ModifiedFiles};
 
 pub(crate) const SUBCOMMAND: &str = "extract";
 
@@ -16,8 +21,24 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
     let snapshot = matches.value_of("snapshot").unwrap();
     let is_reset = matches.is_present("reset");
-    let is_verify = matches.is_present("verify");
+    let is_verify = flag_or_config(matches, "verify", "extract.verify")?;
     let is_force = matches.is_present("force");
+    let is_reflink = matches.is_present("reflink");
+    let is_link = matches.is_present("link");
+    let is_preserve_times = matches.is_present("preserve-times");
+    let is_xattrs = matches.is_present("xattrs");
+    let case_collisions = match matches.value_of("case-collisions") {
+        Some(policy) => policy.parse::<CaseCollisions>()?,
+        None => CaseCollisions::default(),
+    };
+    let modified_files = match value_or_config(matches, "modified", "extract.modified") {
+        Some(policy) => policy.parse::<ModifiedFiles>()?,
+        None => ModifiedFiles::default(),
+    };
+    let paths = parse_patterns(matches, "path")?;
+    let excludes = parse_patterns(matches, "exclude")?;
+    let is_no_head = matches.is_present("no-head");
+    let is_stage = matches.is_present("stage");
 
     // Parse --threads
     let threads: u32 = match matches.value_of("threads").unwrap().parse()? {
@@ -42,8 +63,24 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         r => r?,
     };
 
-    match repo.read_head()? {
-        (Some(h), _) if h == new_head && !is_reset => {
+    if matches.is_present("to-stdout") {
+        // Nothing is written to disk, so HEAD is left as it is.
+        let file_count = write_tar(&repo, &new_head, is_verify, std::io::stdout().lock())?;
+        status!("Extracted {file_count} files from '{new_head}' to stdout");
+        return Ok(());
+    }
+
+    let output_dir = match matches.value_of("output") {
+        Some(output) => {
+            std::fs::create_dir_all(output)?;
+            extended_length_path(&std::fs::canonicalize(output)?)?
+        }
+        None => repo.path().to_owned(),
+    };
+
+    let previous_head = repo.read_head_of(&output_dir)?.0;
+    match &previous_head {
+        Some(h) if *h == new_head && !is_reset => {
             // The specified snapshot is already extracted and --reset is not specified,
             // so this is a no-op.
             warn!(
@@ -60,18 +97,60 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     opts.set_reset(is_reset);
     opts.set_force(is_force);
     opts.set_num_workers(threads);
+    opts.set_reflink(is_reflink);
+    opts.set_link(is_link);
+    opts.set_preserve_times(is_preserve_times);
+    opts.set_xattrs(is_xattrs);
+    opts.set_case_collisions(case_collisions);
+    opts.set_modified_files(modified_files);
+    opts.set_paths(paths);
+    opts.set_excludes(excludes);
+    opts.set_update_head(!is_no_head);
+    opts.set_stage(is_stage);
 
     repo.set_progress_reporter(|msg| create_percentage_print_reporter(msg, 5));
-    let result = repo.extract_snapshot(new_head.clone(), opts)?;
+    let result = repo.extract_snapshot_to(new_head.clone(), &output_dir, opts)?;
+
+    status!("A \t{} files", result.added_file_count);
+    status!("D \t{} files", result.removed_file_count);
+    status!("M \t{} files", result.modified_file_count);
+    for path in &result.kept_paths {
+        status!("Kept the modified file {}", path.to_string_lossy());
+    }
+    for (path, backup_path) in &result.backup_paths {
+        status!(
+            "Moved the modified file {} to {}",
+            path.to_string_lossy(),
+            backup_path.to_string_lossy()
+        );
+    }
+    status!("Extracted '{new_head}'");
 
-    eprintln!("A \t{} files", result.added_file_count);
-    eprintln!("D \t{} files", result.removed_file_count);
-    eprintln!("M \t{} files", result.modified_file_count);
-    eprintln!("Extracted '{new_head}'");
+    let vars = [
+        ("ELFSHAKER_SNAPSHOT", new_head.to_string()),
+        (
+            "ELFSHAKER_PREVIOUS_HEAD",
+            previous_head.map_or_else(String::new, |h| h.to_string()),
+        ),
+        ("ELFSHAKER_OUTPUT_DIR", output_dir.display().to_string()),
+        ("ELFSHAKER_ADDED", result.added_file_count.to_string()),
+        ("ELFSHAKER_REMOVED", result.removed_file_count.to_string()),
+        ("ELFSHAKER_MODIFIED", result.modified_file_count.to_string()),
+    ];
+    run_hook(&repo, "post-extract", &vars)?;
 
     Ok(())
 }
 
+/// Parses the glob patterns passed to the (repeatable) option.
+fn parse_patterns(matches: &ArgMatches, name: &str) -> Result<Vec<Pattern>, glob::PatternError> {
+    matches
+        .values_of(name)
+        .map(|patterns| patterns.map(Pattern::new).collect())
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
 pub(crate) fn get_app() -> App<'static, 'static> {
     App::new(SUBCOMMAND)
         .about("Can be used to extract a snapshot.")
@@ -88,11 +167,91 @@ pub(crate) fn get_app() -> App<'static, 'static> {
         .arg(
             Arg::with_name("verify")
                 .long("verify")
-                .help("Enables SHA-1 verification of the extracted files. This has a small performance overhead."),
+                .help("Recomputes the checksum of each file as it is written, and fails if it is not \
+                    the recorded one (e.g. the pack is corrupt). This has a small performance overhead."),
         )
         .arg(Arg::with_name("force")
                 .long("force")
                 .help("Disables certain runtime checks that aim to detect unexpected file modification and prevent data loss."))
+        .arg(Arg::with_name("reflink")
+                .long("reflink")
+                .help("Decompresses each object once into a cache in the repository directory, \
+                      then reflinks the files to it (on filesystems supporting it, such as Btrfs, XFS and APFS, \
+                      otherwise the files are copied). This makes extracting snapshots which share files nearly free."))
+        .arg(Arg::with_name("link")
+                .long("link")
+                .conflicts_with("reflink")
+                .help("Decompresses each object once into a cache in the repository directory, \
+                      then creates the files as (read-only) hard links to it. This saves disk space when \
+                      snapshots are extracted to several directories sharing the same repository directory."))
+        .arg(Arg::with_name("preserve-times")
+                .long("preserve-times")
+                .conflicts_with("link")
+                .help("Sets the modification times of the files to the ones recorded by store --record-times, \
+                      so that incremental build tools don't consider them modified. Files without a recorded \
+                      time are modified at the time of extraction."))
+        .arg(Arg::with_name("xattrs")
+                .long("xattrs")
+                .conflicts_with("link")
+                .help("Restores the extended attributes of the files recorded by store --xattrs. \
+                      The attributes which can't be set (e.g. security.* ones without the privileges) are skipped."))
+        .arg(Arg::with_name("path")
+                .long("path")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("glob")
+                .help("Extracts only the files whose path, or the path of one of their parent directories, \
+                      matches the glob pattern (e.g. 'lib/**/*.o'), decompressing only the frames containing them. \
+                      Can be repeated. HEAD is not updated, since the other files are left as they are."))
+        .arg(Arg::with_name("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("glob")
+                .help("Skips the files whose path, or the path of one of their parent directories, \
+                      matches the glob pattern (e.g. 'test/**' or just 'test'). Can be repeated, and takes precedence \
+                      over --path. HEAD is not updated, since the skipped files are left as they are."))
+        .arg(Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .value_name("dir")
+                .help("Extracts the snapshot into the directory (created if needed) instead of the working directory. \
+                      The directory has a HEAD of its own, in <dir>/elfshaker_data/HEAD, so that snapshots can be \
+                      extracted incrementally to several directories side by side."))
+        .arg(Arg::with_name("to-stdout")
+                .long("to-stdout")
+                .conflicts_with_all(&["output", "no-head", "stage", "reset", "force", "reflink", "link", "preserve-times",
+                                      "xattrs", "path", "exclude", "case-collisions", "modified"])
+                .help("Writes the snapshot to stdout as an uncompressed tar archive, without touching the disk \
+                      (e.g. elfshaker extract <snapshot> --to-stdout | ssh <host> 'tar x'). HEAD is not updated."))
+        .arg(Arg::with_name("stage")
+                .long("stage")
+                .help("Extracts (and verifies) all the files to a staging directory first, then moves them into place, \
+                      so that the directory is only modified once all of them are available. \
+                      This takes more disk space, since the old and new files coexist for a while."))
+        .arg(Arg::with_name("no-head")
+                .long("no-head")
+                .help("Doesn't update HEAD after the extraction. The next extraction to the same directory \
+                      then needs --reset."))
+        .arg(Arg::with_name("case-collisions")
+                .long("case-collisions")
+                .takes_value(true)
+                .possible_values(&["allow", "error", "rename"])
+                .help("How the paths of the snapshot which differ only by case (and would overwrite each other \
+                      on a case-insensitive filesystem) are handled: 'error' fails before modifying any file, \
+                      'rename' appends ~N to the file stem of all but the first of them (in byte order) and \
+                      'allow' extracts them as is. Defaults to 'error' on Windows and macOS, 'allow' elsewhere."))
+        .arg(Arg::with_name("modified")
+                .long("modified")
+                .takes_value(true)
+                .possible_values(&["error", "backup", "keep"])
+                .help("How the files of HEAD which were modified since they were extracted (their contents \
+                      are not the ones recorded in HEAD) are handled, when they would be updated or removed: \
+                      'error' fails before modifying any file (the default), 'backup' moves them to \
+                      <path>.orig and 'keep' leaves them as they are. Ignored with --force and --reset."))
         .arg(Arg::with_name("threads")
                 .short("T")
                 .long("threads")
@@ -1,23 +1,37 @@
 //! SPDX-License-Identifier: Apache-2.0
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
-use clap::{App, Arg, ArgMatches};
-use std::error::Error;
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use glob::{MatchOptions, Pattern};
+use std::{error::Error, ffi::OsStr};
 
 use super::utils::{open_repo_from_cwd, print_table};
+use elfshaker::regex::Regex;
+use elfshaker::repo::{Repository, SnapshotId};
 
 pub(crate) const SUBCOMMAND: &str = "find";
 
 pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
     let term = matches.value_of("term").unwrap();
+    let globs = matches
+        .values_of("path")
+        .map(|globs| globs.map(Pattern::new).collect::<Result<Vec<_>, _>>())
+        .transpose()?
+        .unwrap_or_default();
+    let regexes = matches
+        .values_of("regex")
+        .map(|regexes| regexes.map(Regex::new).collect::<Result<Vec<_>, _>>())
+        .transpose()?
+        .unwrap_or_default();
     let repo = open_repo_from_cwd(data_dir)?;
 
-    let mut table = vec![];
-    for pack_id in repo.packs()? {
-        for snapshot in repo.load_index_snapshots(&pack_id)? {
-            table.push([snapshot.to_string(), pack_id.to_string()])
-        }
+    if let Some(commit) = matches.value_of("commit") {
+        return find_commit(&repo, term, commit);
+    }
+    if !globs.is_empty() || !regexes.is_empty() {
+        let is_snapshots_only = matches.is_present("snapshots_only");
+        return find_paths(&repo, term, &globs, &regexes, is_snapshots_only);
     }
 
     let table = repo
@@ -41,9 +55,102 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Prints the snapshots (whose tag contains the term) with the files whose
+/// path matches one of the glob patterns or of the regexes.
+fn find_paths(
+    repo: &Repository,
+    term: &str,
+    globs: &[Pattern],
+    regexes: &[Regex],
+    is_snapshots_only: bool,
+) -> Result<(), Box<dyn Error>> {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let is_match = |path: &OsStr| {
+        let path = path.to_string_lossy();
+        globs.iter().any(|glob| glob.matches_with(&path, options))
+            || regexes.iter().any(|regex| regex.is_match(&path))
+    };
+
+    // The packs which don't contain the paths are skipped if there are no
+    // wildcards.
+    let literals: Vec<_> = globs
+        .iter()
+        .filter(|glob| Pattern::escape(glob.as_str()) == glob.as_str())
+        .map(|glob| OsStr::new(glob.as_str()))
+        .collect();
+    let found = if regexes.is_empty() && literals.len() == globs.len() {
+        repo.find_exact_paths(&literals)?
+    } else {
+        repo.find_paths(is_match)?
+    };
+
+    let mut rows: Vec<_> = found
+        .into_iter()
+        .filter(|(snapshot, _)| snapshot.tag().contains(term))
+        .map(|(snapshot, path)| [snapshot.to_string(), path.to_string_lossy().into_owned()])
+        .collect();
+    rows.sort();
+
+    if is_snapshots_only {
+        let mut snapshots: Vec<_> = rows.into_iter().map(|[snapshot, _]| snapshot).collect();
+        snapshots.dedup();
+        for snapshot in snapshots {
+            println!("{snapshot}");
+        }
+        return Ok(());
+    }
+
+    let table = rows.into_iter().map(IntoIterator::into_iter);
+    let header = IntoIterator::into_iter(["SNAPSHOT".to_owned(), "PATH".to_owned()]);
+    print_table(Some(header), table);
+    Ok(())
+}
+
+/// Prints the snapshots (whose tag contains the term) whose `commit` attribute
+/// (see store --git) starts with the commit hash.
+fn find_commit(repo: &Repository, term: &str, commit: &str) -> Result<(), Box<dyn Error>> {
+    if commit.len() < 4 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid --commit '{commit}', expected at least 4 hex digits!").into());
+    }
+    let commit = commit.to_ascii_lowercase();
+
+    let mut rows = vec![];
+    for pack_id in repo.packs()? {
+        let index = repo.load_index_cached(&pack_id)?;
+        for tag in index.snapshot_tags() {
+            let attributes = match index.snapshot_metadata(tag) {
+                Some(metadata) if tag.contains(term) => &metadata.attributes,
+                _ => continue,
+            };
+            let hash = match attributes.get("commit") {
+                Some(hash) if hash.to_ascii_lowercase().starts_with(&commit) => hash,
+                _ => continue,
+            };
+            let hash = match attributes.get("dirty").map(String::as_str) {
+                Some("true") => format!("{hash}-dirty"),
+                _ => hash.clone(),
+            };
+            rows.push([SnapshotId::new(pack_id.clone(), tag)?.to_string(), hash]);
+        }
+    }
+    rows.sort();
+
+    let table = rows.into_iter().map(IntoIterator::into_iter);
+    let header = IntoIterator::into_iter(["SNAPSHOT".to_owned(), "COMMIT".to_owned()]);
+    print_table(Some(header), table);
+    Ok(())
+}
+
 pub(crate) fn get_app() -> App<'static, 'static> {
     App::new(SUBCOMMAND)
-        .about("Searches the repository index.")
+        .about(
+            "Searches the repository index, for the snapshots whose tag contains the term, \
+            for the files matching --path or --regex in all the snapshots, or for the \
+            snapshots of a commit with --commit.",
+        )
         .arg(
             Arg::with_name("term")
                 .required(true)
@@ -51,4 +158,52 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .default_value("")
                 .help("The search term."),
         )
+        .arg(
+            Arg::with_name("path")
+                .long("path")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("glob")
+                .help(
+                    "Lists the files whose path matches the glob pattern (e.g. 'lib/**/*.o', \
+                    where * does not match /), with the snapshots containing them. \
+                    Can be repeated.",
+                ),
+        )
+        .arg(
+            Arg::with_name("regex")
+                .long("regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("regex")
+                .help(
+                    "Lists the files whose path matches the regex somewhere (e.g. \
+                    '^lib/.*\\.so$'), with the snapshots containing them. Can be repeated.",
+                ),
+        )
+        .group(
+            ArgGroup::with_name("patterns")
+                .args(&["path", "regex"])
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("commit")
+                .long("commit")
+                .takes_value(true)
+                .value_name("sha")
+                .conflicts_with_all(&["path", "regex"])
+                .help(
+                    "Lists the snapshots whose commit attribute (recorded by store --git) \
+                    starts with the commit hash, marking the ones of a modified checkout \
+                    as -dirty.",
+                ),
+        )
+        .arg(
+            Arg::with_name("snapshots_only")
+                .long("snapshots-only")
+                .requires("patterns")
+                .help("Only prints the snapshots containing matching files, one per line."),
+        )
 }
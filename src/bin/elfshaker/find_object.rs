@@ -0,0 +1,46 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+use super::utils::{open_repo_from_cwd, print_table};
+use elfshaker::packidx::ObjectChecksum;
+
+pub(crate) const SUBCOMMAND: &str = "find-object";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let checksum = parse_checksum(matches.value_of("checksum").unwrap())?;
+
+    let repo = open_repo_from_cwd(data_dir)?;
+
+    let mut rows: Vec<_> = repo
+        .find_object(&checksum)?
+        .into_iter()
+        .map(|(snapshot, path)| [snapshot.to_string(), path.to_string_lossy().into_owned()])
+        .collect();
+    rows.sort();
+
+    let table = rows.into_iter().map(IntoIterator::into_iter);
+    let header = IntoIterator::into_iter(["SNAPSHOT".to_owned(), "PATH".to_owned()]);
+    print_table(Some(header), table);
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about("Lists the snapshots and paths at which an object is stored.")
+        .arg(
+            Arg::with_name("checksum")
+                .required(true)
+                .index(1)
+                .help("The checksum of the object (as printed by list-files)."),
+        )
+}
+
+fn parse_checksum(s: &str) -> Result<ObjectChecksum, Box<dyn Error>> {
+    let bytes = hex::decode(s).map_err(|_| format!("Invalid object checksum '{s}'!"))?;
+    ObjectChecksum::from_slice(&bytes)
+        .ok_or_else(|| format!("Invalid object checksum '{s}'!").into())
+}
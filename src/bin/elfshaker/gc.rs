@@ -2,7 +2,7 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 use clap::{App, Arg, ArgMatches};
-use log::{error, info};
+use log::info;
 use std::error::Error;
 
 use super::utils::{format_size, open_repo_from_cwd};
@@ -19,15 +19,13 @@ pub(crate) const SUBCOMMAND: &str = "gc";
 pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
     let dry_run = matches.is_present("dry_run");
-    let loose_snapshots = matches.is_present("loose_snapshots");
-    let loose_objects = matches.is_present("loose_objects");
+    let mut loose_snapshots = matches.is_present("loose_snapshots");
+    let mut loose_objects = matches.is_present("loose_objects");
 
+    // GC everything, unless told otherwise.
     if !loose_snapshots && !loose_objects {
-        error!(
-            "Specify what to GC (--loose-snapshots, --loose-objects). \
-            Nothing to do!"
-        );
-        return Err("Invalid options!".into());
+        loose_snapshots = true;
+        loose_objects = true;
     }
 
     let repo = open_repo_from_cwd(data_dir)?;
@@ -84,7 +82,8 @@ pub(crate) fn get_app() -> App<'static, 'static> {
     App::new(SUBCOMMAND)
         .about(
             "Cleanup redundant snapshots and unreferenced objects. \
-            This frees up disk space after creating a pack.",
+            This frees up disk space after creating a pack. When neither \
+            --loose-snapshots nor --loose-objects is specified, both are cleaned up.",
         )
         .arg(
             Arg::with_name("dry_run")
@@ -0,0 +1,181 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{collections::HashMap, error::Error, io::Write};
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::packidx::{FileEntry, ObjectChecksum, PackError};
+use elfshaker::regex::Regex;
+use elfshaker::repo::{Error as RepoError, SnapshotId};
+
+pub(crate) const SUBCOMMAND: &str = "grep";
+
+/// A file is binary if there is a NUL byte in its first bytes, like for git.
+const BINARY_DETECTION_SIZE: usize = 8000;
+
+enum Matcher {
+    Regex(Regex),
+    Fixed(Vec<u8>),
+}
+
+impl Matcher {
+    fn is_match(&self, line: &[u8]) -> bool {
+        match self {
+            Self::Regex(regex) => regex.is_match(&String::from_utf8_lossy(line)),
+            Self::Fixed(needle) => {
+                needle.is_empty() || line.windows(needle.len()).any(|w| w == needle)
+            }
+        }
+    }
+}
+
+/// The matches in an object, shared by all the files referencing it.
+enum ObjectMatches {
+    Binary,
+    /// The matching lines, with their (1-based) number.
+    Lines(Vec<(usize, Vec<u8>)>),
+}
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let pattern = matches.value_of("pattern").unwrap();
+    let target = matches.value_of("snapshot").unwrap();
+    let is_text_only = matches.is_present("text");
+    let is_files_only = matches.is_present("files_with_matches");
+    let is_line_number = matches.is_present("line_number");
+    let matcher = if matches.is_present("fixed_strings") {
+        Matcher::Fixed(pattern.as_bytes().to_vec())
+    } else {
+        Matcher::Regex(Regex::new(pattern)?)
+    };
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    // All the snapshots of the pack are searched if a pack is specified.
+    let snapshots = match repo.find_snapshot(target) {
+        Ok(snapshot) => vec![snapshot],
+        Err(RepoError::PackError(PackError::SnapshotNotFound(_))) => match repo.is_pack(target)? {
+            Some(pack) => repo
+                .load_index_snapshots(&pack)?
+                .into_iter()
+                .map(|tag| SnapshotId::new(pack.clone(), &tag))
+                .collect::<Result<_, _>>()?,
+            None => return Err(format!("No snapshot or pack matches '{target}'!").into()),
+        },
+        Err(e) => return Err(e.into()),
+    };
+    let pack = snapshots[0].pack().clone();
+
+    // Each object is only decompressed and searched once, even if several
+    // files (or snapshots) reference it.
+    let mut files = vec![];
+    let mut objects: HashMap<ObjectChecksum, FileEntry> = HashMap::new();
+    for snapshot in &snapshots {
+        for entry in repo.load_snapshot_entries(snapshot)? {
+            if entry.file_metadata.is_dir() || entry.file_metadata.is_symlink() {
+                continue;
+            }
+            files.push((snapshot.to_string(), entry.path.clone(), entry.checksum));
+            objects.entry(entry.checksum).or_insert(entry);
+        }
+    }
+    let objects: Vec<_> = objects.into_values().collect();
+
+    let mut object_matches = HashMap::new();
+    repo.read_entries(&pack, &objects, true, |entry, buf| {
+        let is_binary = buf.iter().take(BINARY_DETECTION_SIZE).any(|&b| b == 0);
+        if is_binary && is_text_only {
+            return Ok(());
+        }
+        let mut lines = vec![];
+        let text = buf.strip_suffix(b"\n").unwrap_or(buf);
+        for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+            if matcher.is_match(line) {
+                if is_binary || is_files_only {
+                    lines.push((i + 1, vec![]));
+                    break;
+                }
+                lines.push((i + 1, line.to_vec()));
+            }
+        }
+        if !lines.is_empty() {
+            let matches = if is_binary {
+                ObjectMatches::Binary
+            } else {
+                ObjectMatches::Lines(lines)
+            };
+            object_matches.insert(entry.checksum, matches);
+        }
+        Ok(())
+    })?;
+
+    files.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for (snapshot, path, checksum) in files {
+        let path = path.to_string_lossy();
+        match object_matches.get(&checksum) {
+            None => {}
+            Some(_) if is_files_only => writeln!(stdout, "{snapshot}:{path}")?,
+            Some(ObjectMatches::Binary) => {
+                writeln!(stdout, "Binary file {snapshot}:{path} matches")?
+            }
+            Some(ObjectMatches::Lines(lines)) => {
+                for (number, line) in lines {
+                    write!(stdout, "{snapshot}:{path}:")?;
+                    if is_line_number {
+                        write!(stdout, "{number}:")?;
+                    }
+                    stdout.write_all(line)?;
+                    stdout.write_all(b"\n")?;
+                }
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Searches the contents of the files of the snapshot (or of all the snapshots \
+            of the pack) for the pattern, decompressing them on the fly, and prints the \
+            matching lines. The binary files are only reported as matching.",
+        )
+        .arg(
+            Arg::with_name("pattern")
+                .required(true)
+                .index(1)
+                .help("The regex to search for (see find --regex), matched on each line."),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .index(2)
+                .help("The snapshot (or pack) to search in."),
+        )
+        .arg(
+            Arg::with_name("fixed_strings")
+                .short("F")
+                .long("fixed-strings")
+                .help("Matches the pattern as a string (e.g. a symbol), not as a regex."),
+        )
+        .arg(
+            Arg::with_name("text")
+                .long("text")
+                .help("Only searches the text files, skipping the binary ones."),
+        )
+        .arg(
+            Arg::with_name("files_with_matches")
+                .short("l")
+                .long("files-with-matches")
+                .help("Only prints the files which match, as <snapshot>:<path>."),
+        )
+        .arg(
+            Arg::with_name("line_number")
+                .short("n")
+                .long("line-number")
+                .help("Prints the number of the matching lines."),
+        )
+}
@@ -0,0 +1,120 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+use super::utils::{format_date, open_repo_from_cwd, parse_date, snapshot_history, snapshot_time};
+
+pub(crate) const SUBCOMMAND: &str = "log";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let max_count = matches
+        .value_of("max_count")
+        .map(str::parse::<usize>)
+        .transpose()
+        .map_err(|e| format!("Invalid --max-count: {e}"))?;
+    let since = matches.value_of("since").map(parse_date).transpose()?;
+    let until = matches.value_of("until").map(parse_date).transpose()?;
+    let is_oneline = matches.is_present("oneline");
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let head = repo.read_head()?.0;
+    let pins = repo.pins()?;
+
+    let history = snapshot_history(&repo)?
+        .into_iter()
+        .filter(|entry| since.is_none_or(|since| entry.time >= since))
+        .filter(|entry| until.is_none_or(|until| entry.time < until))
+        .take(max_count.unwrap_or(usize::MAX));
+
+    for (i, entry) in history.enumerate() {
+        let metadata = &entry.metadata;
+        let message = metadata.message.as_deref().unwrap_or("");
+        if is_oneline {
+            let summary = message.lines().next().unwrap_or("");
+            println!(
+                "{} {} {}",
+                format_date(&entry.time),
+                entry.snapshot,
+                summary
+            );
+            continue;
+        }
+
+        if i > 0 {
+            println!();
+        }
+        let mut refs = vec![];
+        if Some(&entry.snapshot) == head.as_ref() {
+            refs.push("HEAD");
+        }
+        if pins.contains(entry.snapshot.tag()) {
+            refs.push("pinned");
+        }
+        if refs.is_empty() {
+            println!("snapshot {}", entry.snapshot);
+        } else {
+            println!("snapshot {} ({})", entry.snapshot, refs.join(", "));
+        }
+        if let Some(author) = &metadata.author {
+            println!("Author: {author}");
+        }
+        match snapshot_time(metadata) {
+            Some(time) => println!("Date:   {}", format_date(&time)),
+            // Stored by an older version of elfshaker.
+            None => println!("Date:   {} (packed)", format_date(&entry.time)),
+        }
+        for (key, value) in &metadata.attributes {
+            println!("Meta:   {key}={value}");
+        }
+        if !message.is_empty() {
+            println!();
+            for line in message.lines() {
+                println!("    {line}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Prints the snapshots of all the packs, most recent first, with their \
+            author, time, attributes and message. The snapshots stored before their \
+            time was recorded are dated by the time their pack was last written.",
+        )
+        .arg(
+            Arg::with_name("max_count")
+                .short("n")
+                .long("max-count")
+                .takes_value(true)
+                .value_name("N")
+                .help("Prints only the N most recent snapshots."),
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .value_name("date")
+                .help(
+                    "Prints only the snapshots stored since the date, which is either \
+                    relative (e.g. 2w or 3d, for 2 weeks or 3 days ago), a day \
+                    (e.g. 2022-03-01) or an RFC 3339 date and time.",
+                ),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .value_name("date")
+                .help("Prints only the snapshots stored before the date (see --since)."),
+        )
+        .arg(Arg::with_name("oneline").long("oneline").help(
+            "Prints each snapshot on a single line: its time, the snapshot \
+                    and the first line of its message.",
+        ))
+}
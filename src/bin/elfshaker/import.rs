@@ -0,0 +1,145 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use log::warn;
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use super::store::{metadata_args, parse_snapshot_metadata};
+use super::utils::open_repo_from_cwd;
+use elfshaker::packidx::{os_str_as_bytes, FileMetadata, S_IFLNK};
+use elfshaker::repo::{PackId, SnapshotId};
+
+pub(crate) const SUBCOMMAND: &str = "import";
+
+/// The file type bits of a regular file, which are not stored in tar headers.
+const S_IFREG: u32 = 0o100000;
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap());
+    let archive = matches.value_of("archive").unwrap();
+    let snapshot = matches.value_of("snapshot").unwrap();
+    let zstd = matches.is_present("zstd") || archive.ends_with(".zst");
+    let metadata = parse_snapshot_metadata(matches)?;
+
+    // Use snapshot name as pack name.
+    let pack_id = PackId::Pack(format!("loose/{snapshot}"));
+    let snapshot = SnapshotId::new(pack_id, snapshot)?;
+
+    let reader: Box<dyn Read> = match archive {
+        "-" => Box::new(io::stdin()),
+        path => Box::new(fs::File::open(path)?),
+    };
+    let reader: Box<dyn Read> = if zstd {
+        Box::new(zstd::Decoder::new(reader)?)
+    } else {
+        Box::new(io::BufReader::new(reader))
+    };
+
+    fs::create_dir_all(data_dir)?;
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    let mut archive = tar::Archive::new(reader);
+    let files = archive.entries()?.filter_map(|entry| {
+        let result = (|| -> Result<_, elfshaker::repo::Error> {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_symlink() {
+                let path = clean_archive_path(&path)?;
+                let target = match entry.link_name()? {
+                    Some(target) => target.into_owned(),
+                    None => return Ok(None),
+                };
+                let mode = S_IFLNK | 0o777;
+                let buf = os_str_as_bytes(target.as_os_str()).into_owned();
+                let file_metadata = FileMetadata { mode, mtime: None };
+                return Ok(Some((path, file_metadata, buf)));
+            }
+            if !entry_type.is_file() {
+                if !entry_type.is_dir() {
+                    warn!("Skipping {:?}, which is not a regular file", path);
+                }
+                return Ok(None);
+            }
+            let path = clean_archive_path(&path)?;
+            let mode = S_IFREG | (entry.header().mode()? & 0o7777);
+            // The size in the header is not trusted to allocate the buffer,
+            // which grows as the contents are read instead.
+            let size = entry.size();
+            let mut buf = vec![];
+            (&mut entry).take(size).read_to_end(&mut buf)?;
+            if buf.len() as u64 != size {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Archive member {path:?} is truncated!"),
+                )
+                .into());
+            }
+            let file_metadata = FileMetadata { mode, mtime: None };
+            Ok(Some((path, file_metadata, buf)))
+        })();
+        result.transpose()
+    });
+
+    let file_count = repo.create_snapshot_from_contents(&snapshot, files, metadata)?;
+    status!("Imported {file_count} files into {snapshot}");
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Creates a loose snapshot from the contents of a tar archive, \
+            without extracting it to the working directory.",
+        )
+        .arg(
+            Arg::with_name("archive")
+                .required(true)
+                .index(1)
+                .help("The tar archive to import. '-' is taken to mean stdin."),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .index(2)
+                .help("The tag for the newly created snapshot."),
+        )
+        .arg(
+            Arg::with_name("zstd")
+                .long("zstd")
+                .help("Decompresses the archive with zstd (implied for '.zst' inputs)."),
+        )
+        .args(&metadata_args())
+}
+
+/// Converts the path of an archive member to a path relative to the
+/// repository root, rejecting paths which would point outside of it.
+fn clean_archive_path(path: &Path) -> io::Result<PathBuf> {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => result.push(c),
+            Component::CurDir => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Archive member {path:?} is outside of the repository!"),
+                ))
+            }
+        }
+    }
+    if result.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Archive member {path:?} has an empty path!"),
+        ));
+    }
+    Ok(result)
+}
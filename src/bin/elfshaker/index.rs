@@ -0,0 +1,169 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::{error::Error, ops::ControlFlow, path::Path, str::FromStr};
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::repo::PackId;
+use elfshaker::sqlite::{Database, Value};
+
+pub(crate) const SUBCOMMAND: &str = "index";
+
+const EXPORT_SUBCOMMAND: &str = "export";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    match matches.subcommand() {
+        (EXPORT_SUBCOMMAND, Some(matches)) => export(matches),
+        // The subcommand is required.
+        _ => unreachable!(),
+    }
+}
+
+fn export(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap());
+    let output = matches.value_of("sqlite").unwrap();
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let packs = match matches.values_of("packs") {
+        Some(packs) => packs.map(PackId::from_str).collect::<Result<Vec<_>, _>>()?,
+        None => repo.packs()?,
+    };
+
+    let mut db = Database::create(output)?;
+    let snapshots = db.create_table(
+        "snapshots",
+        "id INTEGER PRIMARY KEY, pack TEXT, tag TEXT, position INTEGER, created INTEGER, \
+        author TEXT, message TEXT, file_count INTEGER",
+    );
+    let attributes = db.create_table("attributes", "snapshot INTEGER, key TEXT, value TEXT");
+    let entries = db.create_table(
+        "entries",
+        "snapshot INTEGER, path TEXT, checksum TEXT, size INTEGER, mode INTEGER, mtime INTEGER",
+    );
+    let objects = db.create_table(
+        "objects",
+        "pack TEXT, checksum TEXT, size INTEGER, stored_size INTEGER, stored_in TEXT",
+    );
+
+    let (mut snapshot_count, mut entry_count) = (0, 0);
+    for pack_id in &packs {
+        let index = repo.load_index_cached(pack_id)?;
+        let pack = pack_id.to_string();
+
+        let mut position = 0;
+        let result = index.for_each_snapshot(|tag, files| {
+            let metadata = index.snapshot_metadata(tag).cloned().unwrap_or_default();
+            let mut files: Vec<_> = files.iter().collect();
+            files.sort_by_key(|entry| entry.path);
+            let mut insert = || {
+                let snapshot = db.insert(
+                    snapshots,
+                    &[
+                        Value::Null,
+                        Value::Text(&pack),
+                        Value::Text(tag),
+                        Value::Integer(position),
+                        metadata.created.map_or(Value::Null, Value::Integer),
+                        metadata.author.as_deref().map_or(Value::Null, Value::Text),
+                        metadata.message.as_deref().map_or(Value::Null, Value::Text),
+                        Value::Integer(files.len() as i64),
+                    ],
+                )?;
+                for (key, value) in &metadata.attributes {
+                    db.insert(
+                        attributes,
+                        &[
+                            Value::Integer(snapshot),
+                            Value::Text(key),
+                            Value::Text(value),
+                        ],
+                    )?;
+                }
+                for entry in &files {
+                    let checksum = hex::encode(entry.checksum);
+                    db.insert(
+                        entries,
+                        &[
+                            Value::Integer(snapshot),
+                            Value::Text(&entry.path.to_string_lossy()),
+                            Value::Text(&checksum),
+                            Value::Integer(entry.object_metadata.size as i64),
+                            Value::Integer(entry.file_metadata.mode.into()),
+                            entry
+                                .file_metadata
+                                .mtime
+                                .map_or(Value::Null, Value::Integer),
+                        ],
+                    )?;
+                }
+                Ok::<_, std::io::Error>(())
+            };
+            let result = insert();
+            position += 1;
+            entry_count += files.len();
+            match result {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(e) => ControlFlow::Break(e),
+            }
+        })?;
+        if let Some(e) = result {
+            return Err(e.into());
+        }
+        snapshot_count += position;
+
+        for checksum in index.object_checksums() {
+            let metadata = index.object_metadata(checksum);
+            let hex = hex::encode(checksum);
+            db.insert(
+                objects,
+                &[
+                    Value::Text(&pack),
+                    Value::Text(&hex),
+                    Value::Integer(metadata.size as i64),
+                    Value::Integer(metadata.stored_size() as i64),
+                    metadata.pack.as_deref().map_or(Value::Null, Value::Text),
+                ],
+            )?;
+        }
+    }
+    db.finish()?;
+
+    status!(
+        "Exported {} pack(s), {} snapshot(s) and {} file(s) to {}",
+        packs.len(),
+        snapshot_count,
+        entry_count,
+        output
+    );
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about("Works with the indexes of the packs.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(get_export_app())
+}
+
+fn get_export_app() -> App<'static, 'static> {
+    SubCommand::with_name(EXPORT_SUBCOMMAND)
+        .about(
+            "Exports the indexes of the packs (the snapshots, with their attributes, the \
+            files of each snapshot and the objects of each pack) to a new SQLite database, \
+            to query them with SQL.",
+        )
+        .arg(
+            Arg::with_name("sqlite")
+                .long("sqlite")
+                .takes_value(true)
+                .required(true)
+                .value_name("file")
+                .help("The database to write, which is replaced if it exists."),
+        )
+        .arg(
+            Arg::with_name("packs").index(1).multiple(true).help(
+                "The packs to export (all of them, including the loose snapshots, by default).",
+            ),
+        )
+}
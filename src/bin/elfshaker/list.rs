@@ -1,11 +1,15 @@
 //! SPDX-License-Identifier: Apache-2.0
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
+use chrono::{DateTime, Utc};
 use clap::{App, Arg, ArgMatches};
 use elfshaker::repo::run_in_parallel;
-use std::{error::Error, ops::ControlFlow};
+use glob::Pattern;
+use serde::{Serialize, Serializer};
+use std::{collections::BTreeMap, error::Error, io::Write, ops::ControlFlow};
 
-use super::utils::{format_size, open_repo_from_cwd};
+use super::utils::{format_date, format_size, open_repo_from_cwd, parse_date, snapshot_time};
+use elfshaker::packidx::SnapshotMetadata;
 use elfshaker::repo::{PackId, Repository};
 
 pub(crate) const SUBCOMMAND: &str = "list";
@@ -16,6 +20,16 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let format = matches
         .value_of_lossy("format")
         .expect("<format> not provided");
+    let json = matches.is_present("json");
+    // --format has a default value, so only the one passed conflicts.
+    if json && matches.occurrences_of("format") > 0 {
+        return Err("--json can't be used with --format!".into());
+    }
+    let filter = matches.value_of("filter").map(Pattern::new).transpose()?;
+    let since = matches.value_of("since").map(parse_date).transpose()?;
+    let until = matches.value_of("until").map(parse_date).transpose()?;
+    let sort = matches.value_of("sort");
+    let reverse = matches.is_present("reverse");
 
     let repo = open_repo_from_cwd(data_dir)?;
 
@@ -23,7 +37,32 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         .map(|packs| packs.iter().cloned().map(PackId::Pack).collect())
         .unwrap_or(repo.packs()?);
 
-    print_snapshots(&repo, &packs, &format)?;
+    let mut rows = list_snapshots(
+        &repo,
+        &packs,
+        filter.as_ref(),
+        json || is_file_size_required(&format) || sort == Some("size"),
+    )?;
+    if since.is_some() || until.is_some() {
+        // The snapshots stored before their time was recorded are excluded.
+        rows.retain(|row| {
+            row.created.is_some_and(|created| {
+                since.is_none_or(|since| created >= since)
+                    && until.is_none_or(|until| created < until)
+            })
+        });
+    }
+    if let Some(sort) = sort {
+        sort_snapshots(&mut rows, sort);
+    }
+    if json {
+        if reverse {
+            rows.reverse();
+        }
+        print_snapshots_json(&rows)?;
+    } else {
+        print_snapshots(&rows, &format, sort.is_some(), reverse);
+    }
 
     Ok(())
 }
@@ -50,50 +89,186 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                     \t%t - snapshot tag\n\
                     \t%h - human-readable size\n\
                     \t%b - size in bytes\n\
-                    \t%n - number of files\n",
+                    \t%n - number of files\n\
+                    \t%m - snapshot message\n\
+                    \t%a - snapshot attributes (key=value, comma-separated)\n\
+                    \t%d - time the snapshot was stored (RFC 3339, UTC)\n\
+                    \t%u - author of the snapshot\n",
+                ),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .value_name("glob")
+                .help(
+                    "Prints only the snapshots whose tag matches the glob \
+                    pattern (e.g. 'release-*').",
+                ),
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .value_name("date")
+                .help(
+                    "Prints only the snapshots stored since the date, which is either \
+                    relative (e.g. 2w or 3d, for 2 weeks or 3 days ago), a day \
+                    (e.g. 2022-03-01) or an RFC 3339 date and time.",
+                ),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .value_name("date")
+                .help("Prints only the snapshots stored before the date (see --since)."),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .value_name("key")
+                .possible_values(&["name", "size", "files", "date"])
+                .help(
+                    "Sorts the snapshots by name (pack and tag), size, number of files \
+                    or date (the time they were stored), in ascending order, instead of \
+                    sorting the printed lines.",
                 ),
         )
+        .arg(
+            Arg::with_name("reverse")
+                .long("reverse")
+                .help("Prints the snapshots in the reverse order."),
+        )
+        .arg(Arg::with_name("json").long("json").help(
+            "Prints the snapshots as a JSON array of objects with the \
+                    fields \"pack\", \"snapshot\", \"size\", \"file_count\", \
+                    \"message\", \"attributes\", \"created\" and \"author\".",
+        ))
 }
 
-fn format_snapshot_row(
-    fmt: &str,
-    pack_id: &PackId,
-    snapshot: &str,
+/// A single row of the output of `list`.
+#[derive(Serialize)]
+struct SnapshotRow {
+    pack: String,
+    snapshot: String,
     size: u64,
     file_count: usize,
-) -> String {
+    message: Option<String>,
+    attributes: BTreeMap<String, String>,
+    #[serde(serialize_with = "serialize_date")]
+    created: Option<DateTime<Utc>>,
+    author: Option<String>,
+}
+
+impl SnapshotRow {
+    fn new(
+        pack_id: &PackId,
+        snapshot: String,
+        size: u64,
+        file_count: usize,
+        metadata: SnapshotMetadata,
+    ) -> Self {
+        Self {
+            pack: pack_id.to_string(),
+            snapshot,
+            size,
+            file_count,
+            created: snapshot_time(&metadata),
+            message: metadata.message,
+            attributes: metadata.attributes,
+            author: metadata.author,
+        }
+    }
+}
+
+fn serialize_date<S: Serializer>(
+    date: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    date.as_ref().map(format_date).serialize(serializer)
+}
+
+fn format_snapshot_row(fmt: &str, row: &SnapshotRow) -> String {
     fmt.to_owned()
-        .replace("%s", &format!("{pack_id}:{snapshot}"))
-        .replace("%t", snapshot)
-        .replace("%h", &format_size(size))
-        .replace("%b", &size.to_string())
-        .replace("%n", &file_count.to_string())
+        .replace("%s", &format!("{}:{}", row.pack, row.snapshot))
+        .replace("%t", &row.snapshot)
+        .replace("%h", &format_size(row.size))
+        .replace("%b", &row.size.to_string())
+        .replace("%n", &row.file_count.to_string())
+        .replace("%m", row.message.as_deref().unwrap_or(""))
+        .replace(
+            "%a",
+            &row.attributes
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .replace(
+            "%d",
+            &row.created.as_ref().map(format_date).unwrap_or_default(),
+        )
+        .replace("%u", row.author.as_deref().unwrap_or(""))
 }
 
 fn is_file_size_required(fmt: &str) -> bool {
     fmt.contains("%h") || fmt.contains("%b")
 }
 
-fn print_snapshots(
+/// Collects the snapshots in the specified packs, sorted by pack and tag.
+/// When `filter` is set, only the snapshots with a matching tag are included.
+/// Computing the snapshot size requires loading all file entries, so it is
+/// skipped (and reported as 0) unless `with_size` is set.
+fn list_snapshots(
     repo: &Repository,
     pack_ids: &[PackId],
-    fmt: &str,
-) -> Result<(), Box<dyn Error>> {
+    filter: Option<&Pattern>,
+    with_size: bool,
+) -> Result<Vec<SnapshotRow>, Box<dyn Error>> {
     // Process the packs in parallel
     let pack_results = run_in_parallel(
         num_cpus::get(),
         pack_ids.iter(),
         |pack_id| -> Result<_, elfshaker::repo::Error> {
-            let index = repo.load_index(pack_id)?;
-            let mut pack_lines = vec![];
-            let mut iter = |snapshot, file_size, file_count| {
-                pack_lines.push(format_snapshot_row(
-                    fmt, pack_id, snapshot, file_size, file_count,
+            let mut pack_rows = vec![];
+            // Flat indexes are queried in place, without loading the files.
+            if !with_size {
+                if let Some(index) = repo.load_flat_index(pack_id)? {
+                    for (snapshot, file_count) in index.snapshot_file_counts()? {
+                        if filter.is_none_or(|filter| filter.matches(&snapshot)) {
+                            let metadata = index.snapshot_metadata(&snapshot)?.unwrap_or_default();
+                            pack_rows
+                                .push(SnapshotRow::new(pack_id, snapshot, 0, file_count, metadata));
+                        }
+                    }
+                    return Ok(pack_rows);
+                }
+            }
+
+            let index = repo.load_index_cached(pack_id)?;
+            let mut iter = |snapshot: &str, size, file_count| {
+                if let Some(filter) = filter {
+                    if !filter.matches(snapshot) {
+                        return ControlFlow::<(), ()>::Continue(());
+                    }
+                }
+                let metadata = index
+                    .snapshot_metadata(snapshot)
+                    .cloned()
+                    .unwrap_or_default();
+                pack_rows.push(SnapshotRow::new(
+                    pack_id,
+                    snapshot.to_owned(),
+                    size,
+                    file_count,
+                    metadata,
                 ));
                 ControlFlow::<(), ()>::Continue(())
             };
 
-            if is_file_size_required(fmt) {
+            if with_size {
                 index.for_each_snapshot(|snapshot, entries| {
                     let file_count = entries.len();
                     let file_size = entries.iter().map(|entry| entry.object_metadata.size).sum();
@@ -105,21 +280,56 @@ fn print_snapshots(
                 })?;
             }
 
-            Ok(pack_lines)
+            Ok(pack_rows)
         },
     );
 
     // Aggregate the outputs and sort
-    let mut lines = vec![];
+    let mut rows = vec![];
     for pack_result in pack_results {
-        let mut pack_lines = pack_result?;
-        lines.append(&mut pack_lines);
+        let mut pack_rows = pack_result?;
+        rows.append(&mut pack_rows);
+    }
+    rows.sort_by(|a, b| (&a.pack, &a.snapshot).cmp(&(&b.pack, &b.snapshot)));
+
+    Ok(rows)
+}
+
+/// Sorts the snapshots (sorted by pack and tag, see [`list_snapshots`]) by the
+/// key of `--sort`. Equal snapshots stay sorted by pack and tag, and the ones
+/// with no recorded time come first by date.
+fn sort_snapshots(rows: &mut [SnapshotRow], key: &str) {
+    match key {
+        "size" => rows.sort_by_key(|row| row.size),
+        "files" => rows.sort_by_key(|row| row.file_count),
+        "date" => rows.sort_by_key(|row| row.created),
+        _ => {}
+    }
+}
+
+/// Prints the snapshots in the given format, in the order of `rows` if they
+/// are sorted (see `--sort`), or else sorted by the printed lines.
+fn print_snapshots(rows: &[SnapshotRow], fmt: &str, is_sorted: bool, reverse: bool) {
+    let mut lines: Vec<_> = rows
+        .iter()
+        .map(|row| format_snapshot_row(fmt, row))
+        .collect();
+    if !is_sorted {
+        lines.sort();
+    }
+    if reverse {
+        lines.reverse();
     }
-    lines.sort();
 
     for line in lines {
         println!("{line}");
     }
+}
 
+fn print_snapshots_json(rows: &[SnapshotRow]) -> Result<(), Box<dyn Error>> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, rows)?;
+    writeln!(stdout)?;
     Ok(())
 }
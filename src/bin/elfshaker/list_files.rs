@@ -2,7 +2,8 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 use clap::{App, Arg, ArgMatches};
-use std::{error::Error, ffi::OsStr};
+use serde::Serialize;
+use std::{error::Error, ffi::OsStr, io::Write};
 
 use super::utils::{format_size, open_repo_from_cwd};
 use elfshaker::packidx::ObjectChecksum;
@@ -18,12 +19,21 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let format = matches
         .value_of_lossy("format")
         .expect("<format> not provided");
+    let json = matches.is_present("json");
+    // --format has a default value, so only the one passed conflicts.
+    if json && matches.occurrences_of("format") > 0 {
+        return Err("--json can't be used with --format!".into());
+    }
 
     let repo = open_repo_from_cwd(data_dir)?;
 
     let snapshot_id = repo.find_snapshot(&snapshot)?;
 
-    print_files(&repo, &snapshot_id, &format)?;
+    if json {
+        print_files_json(&repo, &snapshot_id)?;
+    } else {
+        print_files(&repo, &snapshot_id, &format)?;
+    }
 
     Ok(())
 }
@@ -52,6 +62,19 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                     \t%p - permissions",
                 ),
         )
+        .arg(Arg::with_name("json").long("json").help(
+            "Prints the files as a JSON array of objects with the \
+                    fields \"path\", \"checksum\", \"size\" and \"mode\".",
+        ))
+}
+
+/// A single row of the output of `list-files --json`.
+#[derive(Serialize)]
+struct FileRow {
+    path: String,
+    checksum: String,
+    size: u64,
+    mode: u32,
 }
 
 fn format_file_row(
@@ -101,3 +124,28 @@ fn print_files(
 
     Ok(())
 }
+
+fn print_files_json(repo: &Repository, snapshot_id: &SnapshotId) -> Result<(), Box<dyn Error>> {
+    let index = repo.load_index(snapshot_id.pack())?;
+    let handles = index
+        .resolve_snapshot(snapshot_id.tag())
+        .expect("failed to resolve snapshot");
+
+    let mut rows: Vec<_> = index
+        .entries_from_handles(handles.iter())?
+        .into_iter()
+        .map(|entry| FileRow {
+            path: entry.path.to_string_lossy().into_owned(),
+            checksum: hex::encode(entry.checksum),
+            size: entry.object_metadata.size,
+            mode: entry.file_metadata.mode,
+        })
+        .collect();
+    rows.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, &rows)?;
+    writeln!(stdout)?;
+    Ok(())
+}
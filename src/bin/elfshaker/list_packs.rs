@@ -5,6 +5,7 @@ use clap::{App, Arg, ArgMatches};
 use std::error::Error;
 
 use super::utils::{format_size, open_repo_from_cwd};
+use elfshaker::packidx::CompressionParameters;
 use elfshaker::repo::{PackId, Repository};
 
 pub(crate) const SUBCOMMAND: &str = "list-packs";
@@ -36,29 +37,68 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                     \t%p - pack\n\
                     \t%h - human-readable size\n\
                     \t%b - size in bytes\n\
-                    \t%c - number of snapshots\n",
+                    \t%c - number of snapshots\n\
+                    \t%z - compression codec\n\
+                    \t%l - compression level\n\
+                    \t%w - compression window log\n\
+                    The compression parameters are printed as '-' when \
+                    unknown (loose packs and packs created by older versions).",
                 ),
         )
 }
 
-fn format_pack_row(fmt: &str, pack_id: &PackId, snapshot_count: usize, size: u64) -> String {
+/// The placeholders which require loading the whole index.
+const COMPRESSION_PLACEHOLDERS: [&str; 3] = ["%z", "%l", "%w"];
+
+fn format_pack_row(
+    fmt: &str,
+    pack_id: &PackId,
+    snapshot_count: usize,
+    size: u64,
+    compression: Option<CompressionParameters>,
+) -> String {
+    let unknown = || "-".to_owned();
     fmt.to_owned()
         .replace("%p", &format!("{pack_id}"))
         .replace("%c", &snapshot_count.to_string())
         .replace("%b", &size.to_string())
         .replace("%h", &format_size(size))
+        .replace(
+            "%z",
+            &compression.map_or_else(unknown, |c| c.codec.name().to_owned()),
+        )
+        .replace(
+            "%l",
+            &compression.map_or_else(unknown, |c| c.level.to_string()),
+        )
+        .replace(
+            "%w",
+            &compression.map_or_else(unknown, |c| c.window_log.to_string()),
+        )
 }
 
 fn print_packs(repo: &Repository, fmt: &str) -> Result<(), Box<dyn Error>> {
     let mut lines = vec![];
+    let needs_compression = COMPRESSION_PLACEHOLDERS.iter().any(|p| fmt.contains(p));
 
     for pack_id in repo.packs()? {
-        let snapshot_count = repo.load_index_snapshots(&pack_id)?.len();
+        let (snapshot_count, compression) = if needs_compression {
+            let index = repo.load_index_cached(&pack_id)?;
+            (index.snapshot_tags().len(), index.compression())
+        } else {
+            (repo.load_index_snapshots(&pack_id)?.len(), None)
+        };
         let pack_size = repo
             .open_pack(&pack_id)
             .map(|pack| pack.file_size())
             .unwrap_or(0);
-        lines.push(format_pack_row(fmt, &pack_id, snapshot_count, pack_size));
+        lines.push(format_pack_row(
+            fmt,
+            &pack_id,
+            snapshot_count,
+            pack_size,
+            compression,
+        ));
     }
 
     lines.sort();
@@ -1,23 +1,61 @@
 //! SPDX-License-Identifier: Apache-2.0
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
+/// Prints a status message to stderr, unless `--quiet` is passed.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::utils::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod cache;
 mod clone;
+mod completions;
+mod diff;
+mod du;
+mod export;
 mod extract;
 mod find;
+mod find_object;
 mod gc;
+mod grep;
+// Not named log, like the subcommand, which is the name of the log crate.
+mod history;
+mod import;
+mod index;
 mod list;
 mod list_files;
 mod list_packs;
+mod merge_packs;
+mod mount;
 mod pack;
+mod pin;
+mod prune;
+mod push;
+mod rename;
+mod repack;
+mod rm;
+mod serve;
 mod show;
+mod sign;
+mod split_pack;
+mod stats;
+mod status;
 mod store;
+mod tag;
 mod update;
+mod upgrade;
 mod utils;
+mod verify;
+mod worktree;
 
 use clap::{crate_version, App, Arg, ArgMatches};
-use elfshaker::log::Logger;
+use elfshaker::log::{LogFormat, Logger};
 use log::error;
 use std::error::Error;
+use std::path::PathBuf;
 
 const ERROR_EXIT_CODE: i32 = 1;
 
@@ -29,14 +67,51 @@ fn main() {
 
     let mut app = get_app();
     let matches = app.clone().get_matches();
-    let is_verbose = matches.is_present("verbose");
-    Logger::init(if is_verbose {
-        log::Level::Info
+
+    // The global arguments are propagated to the subcommand (and to its own
+    // subcommand, as for `index export`).
+    let (subcommand, subcommand_matches) = matches.subcommand();
+    let mut global_matches = subcommand_matches.unwrap_or(&matches);
+    while let (_, Some(matches)) = global_matches.subcommand() {
+        global_matches = matches;
+    }
+    let level = if global_matches.is_present("quiet") {
+        log::Level::Error
     } else {
-        log::Level::Warn
-    });
+        match global_matches.occurrences_of("verbose") {
+            0 => log::Level::Warn,
+            1 => log::Level::Info,
+            2 => log::Level::Debug,
+            _ => log::Level::Trace,
+        }
+    };
+    let log_format = match global_matches.value_of("log_format").unwrap() {
+        "json" => LogFormat::Json,
+        _ => LogFormat::Text,
+    };
+    Logger::init_with_format(level, log_format);
+    utils::set_quiet(global_matches.is_present("quiet"));
+    utils::set_progress_format(
+        global_matches
+            .value_of("progress")
+            .unwrap()
+            .parse()
+            .unwrap(),
+    );
 
-    if let Err(e) = run_subcommand(&mut app, matches) {
+    // The repository is searched for, unless the subcommand creates one in
+    // the current directory.
+    let discover = ![store::SUBCOMMAND, clone::SUBCOMMAND, cache::SUBCOMMAND].contains(&subcommand);
+    // The configuration of the repository is in its data directory.
+    let data_dir = global_matches
+        .value_of("data_dir")
+        .map(PathBuf::from)
+        .unwrap();
+    let result = utils::enter_repo_dir(global_matches, discover)
+        .and_then(|()| utils::load_config(&data_dir))
+        .map_err(|e| e.into())
+        .and_then(|()| run_subcommand(&mut app, matches));
+    if let Err(e) = result {
         error!("*FATAL*: {}", e);
         std::process::exit(ERROR_EXIT_CODE);
     }
@@ -48,13 +123,40 @@ fn run_subcommand(app: &mut App, matches: ArgMatches) -> Result<(), Box<dyn Erro
         (store::SUBCOMMAND, Some(matches)) => store::run(matches),
         (list::SUBCOMMAND, Some(matches)) => list::run(matches),
         (list_packs::SUBCOMMAND, Some(matches)) => list_packs::run(matches),
+        (history::SUBCOMMAND, Some(matches)) => history::run(matches),
         (list_files::SUBCOMMAND, Some(matches)) => list_files::run(matches),
         (pack::SUBCOMMAND, Some(matches)) => pack::run(matches),
+        (merge_packs::SUBCOMMAND, Some(matches)) => merge_packs::run(matches),
+        (split_pack::SUBCOMMAND, Some(matches)) => split_pack::run(matches),
         (show::SUBCOMMAND, Some(matches)) => show::run(matches),
+        (export::SUBCOMMAND, Some(matches)) => export::run(matches),
+        (import::SUBCOMMAND, Some(matches)) => import::run(matches),
+        (index::SUBCOMMAND, Some(matches)) => index::run(matches),
+        (mount::SUBCOMMAND, Some(matches)) => mount::run(matches),
+        (rm::SUBCOMMAND, Some(matches)) => rm::run(matches),
+        (pin::SUBCOMMAND, Some(matches)) => pin::run(matches),
+        (prune::SUBCOMMAND, Some(matches)) => prune::run(matches),
+        (tag::SUBCOMMAND, Some(matches)) => tag::run(matches),
+        (worktree::SUBCOMMAND, Some(matches)) => worktree::run(matches),
+        (rename::SUBCOMMAND, Some(matches)) => rename::run(matches),
+        (repack::SUBCOMMAND, Some(matches)) => repack::run(matches),
+        (status::SUBCOMMAND, Some(matches)) => status::run(matches),
+        (stats::SUBCOMMAND, Some(matches)) => stats::run(matches),
         (find::SUBCOMMAND, Some(matches)) => find::run(matches),
+        (diff::SUBCOMMAND, Some(matches)) => diff::run(matches),
+        (du::SUBCOMMAND, Some(matches)) => du::run(matches),
+        (find_object::SUBCOMMAND, Some(matches)) => find_object::run(matches),
+        (grep::SUBCOMMAND, Some(matches)) => grep::run(matches),
         (gc::SUBCOMMAND, Some(matches)) => gc::run(matches),
         (update::SUBCOMMAND, Some(matches)) => update::run(matches),
+        (upgrade::SUBCOMMAND, Some(matches)) => upgrade::run(matches),
         (clone::SUBCOMMAND, Some(matches)) => clone::run(matches),
+        (serve::SUBCOMMAND, Some(matches)) => serve::run(matches),
+        (cache::SUBCOMMAND, Some(matches)) => cache::run(matches),
+        (push::SUBCOMMAND, Some(matches)) => push::run(matches),
+        (sign::SUBCOMMAND, Some(matches)) => sign::run(matches),
+        (verify::SUBCOMMAND, Some(matches)) => verify::run(matches),
+        (completions::SUBCOMMAND, Some(matches)) => completions::run(matches),
         _ => {
             app.print_long_help()?;
             println!();
@@ -70,17 +172,92 @@ fn get_app() -> App<'static, 'static> {
         .subcommand(store::get_app())
         .subcommand(list::get_app())
         .subcommand(list_packs::get_app())
+        .subcommand(history::get_app())
         .subcommand(list_files::get_app())
         .subcommand(pack::get_app())
+        .subcommand(merge_packs::get_app())
+        .subcommand(split_pack::get_app())
         .subcommand(show::get_app())
+        .subcommand(export::get_app())
+        .subcommand(import::get_app())
+        .subcommand(index::get_app())
+        .subcommand(mount::get_app())
+        .subcommand(rm::get_app())
+        .subcommand(pin::get_app())
+        .subcommand(prune::get_app())
+        .subcommand(tag::get_app())
+        .subcommand(worktree::get_app())
+        .subcommand(rename::get_app())
+        .subcommand(repack::get_app())
+        .subcommand(status::get_app())
+        .subcommand(stats::get_app())
         .subcommand(find::get_app())
+        .subcommand(diff::get_app())
+        .subcommand(du::get_app())
+        .subcommand(find_object::get_app())
+        .subcommand(grep::get_app())
         .subcommand(gc::get_app())
         .subcommand(update::get_app())
+        .subcommand(upgrade::get_app())
         .subcommand(clone::get_app())
+        .subcommand(serve::get_app())
+        .subcommand(cache::get_app())
+        .subcommand(push::get_app())
+        .subcommand(sign::get_app())
+        .subcommand(verify::get_app())
+        .subcommand(completions::get_app())
         .arg(
             Arg::with_name("verbose")
+                .short("v")
                 .long("verbose")
-                .help("Enables verbose description of the execution process.")
+                .multiple(true)
+                .help(
+                    "Enables verbose description of the execution process. Repeat for more \
+                    details (-vv for the decisions of pack and extract, -vvv for traces).",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("log_format")
+                .long("log-format")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help(
+                    "The format of the log messages: text, or json to write them as JSON \
+                    lines, with the durations of the phases of the operations at -vv.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .conflicts_with("verbose")
+                .help("Only prints errors, without the warnings and progress reports.")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help(
+                    "The format of the progress reports: text, or json to write them to \
+                    stderr as JSON lines, for the tools wrapping elfshaker.",
+                )
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("work_dir")
+                .short("C")
+                .long("directory")
+                .takes_value(true)
+                .value_name("dir")
+                .help("Run as if elfshaker was started in <dir>.")
                 .global(true),
         )
         .arg(
@@ -0,0 +1,112 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use log::info;
+use std::{collections::HashSet, error::Error, str::FromStr};
+
+use super::pack::{compression_args, parse_pack_options};
+use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::repo::{PackId, SnapshotId};
+
+pub(crate) const SUBCOMMAND: &str = "merge-packs";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let output = PackId::from_str(matches.value_of("output").unwrap())?;
+    let keep_packs = matches.is_present("keep-packs");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    let mut packs = vec![];
+    for pack in matches.values_of("pack").unwrap() {
+        let pack_id = repo
+            .is_pack(pack)?
+            .ok_or_else(|| format!("Pack {pack} not found!"))?;
+        if !packs.contains(&pack_id) {
+            packs.push(pack_id);
+        }
+    }
+    if !packs.contains(&output) && repo.is_pack(&output.to_string())?.is_some() {
+        return Err(format!("The pack {output} already exists!").into());
+    }
+    // Fail before merging if some of the packs can't be deleted.
+    if !keep_packs {
+        for pack_id in packs.iter().filter(|&p| *p != output) {
+            repo.check_pack_unreferenced(pack_id)?;
+        }
+    }
+
+    // Collect the snapshots of all packs, in order.
+    let mut snapshots = vec![];
+    let mut tags = HashSet::new();
+    let mut object_size_total = 0;
+    for pack_id in &packs {
+        let index = repo.load_index(pack_id)?;
+        object_size_total += index.object_size_total();
+        for tag in index.snapshot_tags() {
+            if !tags.insert(tag.clone()) {
+                return Err(format!(
+                    "The snapshot tag '{tag}' is present in more than one pack! \
+                    Rename one of the snapshots first."
+                )
+                .into());
+            }
+            snapshots.push(SnapshotId::new(pack_id.clone(), tag)?);
+        }
+    }
+
+    let opts = parse_pack_options(matches, object_size_total)?;
+    status!(
+        "Merging {} snapshot(s) from {} pack(s) into {}...",
+        snapshots.len(),
+        packs.len(),
+        output
+    );
+    let reporter = create_percentage_print_reporter("Compressing objects", 5);
+    repo.create_pack_from_snapshots(&output, &snapshots, &opts, &reporter)?;
+
+    if let (Some(head), _) = repo.read_head()? {
+        if packs.contains(head.pack()) {
+            info!("Updating HEAD to point to the merged pack...");
+            repo.update_head(&SnapshotId::new(output.clone(), head.tag())?)?;
+        }
+    }
+
+    if !keep_packs {
+        for pack_id in packs.iter().filter(|&p| *p != output) {
+            status!("Deleting {pack_id}...");
+            repo.delete_pack(pack_id)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Combines the snapshots of several packs into a single pack. Objects \
+            shared between the packs are only stored once, and are compressed \
+            together. The source packs are deleted afterwards.",
+        )
+        .arg(
+            Arg::with_name("output")
+                .required(true)
+                .index(1)
+                .help("The name of the merged pack (can be one of the source packs)."),
+        )
+        .arg(
+            Arg::with_name("pack")
+                .required(true)
+                .multiple(true)
+                .index(2)
+                .help("The packs to merge."),
+        )
+        .arg(
+            Arg::with_name("keep-packs")
+                .long("keep-packs")
+                .help("Keeps the source packs, instead of deleting them."),
+        )
+        .args(&compression_args())
+}
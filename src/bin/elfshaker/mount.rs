@@ -0,0 +1,396 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+pub(crate) const SUBCOMMAND: &str = "mount";
+
+#[cfg(feature = "fuse")]
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    use super::utils::open_repo_from_cwd;
+
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let mountpoint = matches.value_of("mountpoint").unwrap();
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let fs = fuse_fs::SnapshotFs::new(repo)?;
+
+    status!("Mounted the repository at {mountpoint}, unmount it to exit.");
+    fuser::mount2(
+        fs,
+        mountpoint,
+        &[
+            fuser::MountOption::RO,
+            fuser::MountOption::FSName("elfshaker".to_owned()),
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(not(feature = "fuse"))]
+pub(crate) fn run(_matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    Err("elfshaker was built without FUSE support (rebuild it with `--features fuse`)!".into())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Mounts the repository as a read-only filesystem (using FUSE), \
+            with one directory per snapshot. Files are decompressed when opened.",
+        )
+        .arg(
+            Arg::with_name("mountpoint")
+                .required(true)
+                .index(1)
+                .help("The (empty) directory on which to mount the repository."),
+        )
+}
+
+#[cfg(feature = "fuse")]
+mod fuse_fs {
+    use fuser::{
+        FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+        ReplyOpen, Request,
+    };
+    use libc::{c_int, EIO, ENOENT, ENOTDIR};
+    use log::{error, warn};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        ffi::{OsStr, OsString},
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use elfshaker::packidx::FileEntry;
+    use elfshaker::repo::{Error, PackId, Repository, SnapshotId};
+
+    /// The shared lock held while mounted only keeps the packs from being
+    /// deleted or renamed: snapshots can still be stored meanwhile. But the
+    /// nodes are never refreshed once loaded, so caching the attributes for a
+    /// while does not make them any staler.
+    const TTL: Duration = Duration::from_secs(60);
+    const ROOT_INO: u64 = 1;
+
+    enum NodeKind {
+        Dir(BTreeMap<OsString, u64>),
+        /// The directory of a snapshot whose files have not been loaded yet.
+        Snapshot(SnapshotId),
+        File(PackId, FileEntry),
+    }
+
+    struct Node {
+        parent: u64,
+        kind: NodeKind,
+    }
+
+    /// A read-only filesystem containing a directory for each pack, with a
+    /// directory for each snapshot in the pack. Loose snapshots are placed
+    /// directly in the `loose` directory.
+    pub(super) struct SnapshotFs {
+        repo: Repository,
+        /// The node with inode `ino` is stored at index `ino - 1`.
+        nodes: Vec<Node>,
+        /// The contents and the open count of the open files, by inode.
+        open_files: HashMap<u64, (Vec<u8>, usize)>,
+        mount_time: SystemTime,
+        uid: u32,
+        gid: u32,
+    }
+
+    impl SnapshotFs {
+        pub(super) fn new(repo: Repository) -> Result<Self, Error> {
+            let mut fs = Self {
+                repo,
+                nodes: vec![Node {
+                    parent: ROOT_INO,
+                    kind: NodeKind::Dir(BTreeMap::new()),
+                }],
+                open_files: HashMap::new(),
+                mount_time: SystemTime::now(),
+                uid: unsafe { libc::getuid() },
+                gid: unsafe { libc::getgid() },
+            };
+
+            for pack_id in fs.repo.packs()? {
+                let tags = fs.repo.load_index_snapshots(&pack_id)?;
+                let pack_dir = if fs.repo.is_pack_loose(&pack_id) {
+                    // The loose pack has the same name as its single snapshot.
+                    Path::new(&pack_id.to_string())
+                        .parent()
+                        .map(|p| p.to_owned())
+                } else {
+                    Some(pack_id.to_string().into())
+                };
+                let pack_ino = match pack_dir.and_then(|dir| fs.make_dirs(ROOT_INO, &dir)) {
+                    Some(ino) => ino,
+                    None => continue,
+                };
+                for tag in tags {
+                    let snapshot = SnapshotId::new(pack_id.clone(), &tag)?;
+                    fs.add_child(pack_ino, tag.into(), NodeKind::Snapshot(snapshot));
+                }
+            }
+
+            Ok(fs)
+        }
+
+        fn node(&self, ino: u64) -> Option<&Node> {
+            ino.checked_sub(1).and_then(|i| self.nodes.get(i as usize))
+        }
+
+        fn add_child(&mut self, parent: u64, name: OsString, kind: NodeKind) -> Option<u64> {
+            let ino = self.nodes.len() as u64 + 1;
+            match &mut self.nodes[parent as usize - 1].kind {
+                NodeKind::Dir(children) if !children.contains_key(&name) => {
+                    children.insert(name, ino);
+                }
+                _ => {
+                    warn!("Skipping {:?}, which conflicts with another entry", name);
+                    return None;
+                }
+            }
+            self.nodes.push(Node { parent, kind });
+            Some(ino)
+        }
+
+        /// Returns the directory at `path` relative to `parent`, creating it
+        /// (and any missing parent directories) if needed.
+        fn make_dirs(&mut self, parent: u64, path: &Path) -> Option<u64> {
+            let mut ino = parent;
+            for component in path.iter() {
+                let existing = match &self.nodes[ino as usize - 1].kind {
+                    NodeKind::Dir(children) => children.get(component).copied(),
+                    _ => None,
+                };
+                ino = match existing {
+                    Some(child) => match self.nodes[child as usize - 1].kind {
+                        NodeKind::Dir(_) => child,
+                        _ => {
+                            warn!("Skipping {:?}, which conflicts with another entry", path);
+                            return None;
+                        }
+                    },
+                    None => {
+                        self.add_child(ino, component.to_owned(), NodeKind::Dir(BTreeMap::new()))?
+                    }
+                };
+            }
+            Some(ino)
+        }
+
+        /// Loads the files of the snapshot, if `ino` is an unloaded snapshot
+        /// directory.
+        fn ensure_loaded(&mut self, ino: u64) -> Result<(), c_int> {
+            let snapshot = match self.node(ino).map(|n| &n.kind) {
+                Some(NodeKind::Snapshot(snapshot)) => snapshot.clone(),
+                Some(_) => return Ok(()),
+                None => return Err(ENOENT),
+            };
+            let entries = self.repo.load_snapshot_entries(&snapshot).map_err(|e| {
+                error!("Failed to load {}: {}", snapshot, e);
+                EIO
+            })?;
+
+            self.nodes[ino as usize - 1].kind = NodeKind::Dir(BTreeMap::new());
+            for entry in entries {
+                let path = Path::new(&entry.path).to_owned();
+                if entry.file_metadata.is_dir() {
+                    self.make_dirs(ino, &path);
+                    continue;
+                }
+                let dir = match path.parent() {
+                    Some(parent) => self.make_dirs(ino, parent),
+                    None => Some(ino),
+                };
+                if let (Some(dir), Some(name)) = (dir, path.file_name()) {
+                    let kind = NodeKind::File(snapshot.pack().clone(), entry);
+                    self.add_child(dir, name.to_owned(), kind);
+                }
+            }
+            Ok(())
+        }
+
+        fn attr(&self, ino: u64, node: &Node) -> FileAttr {
+            let (kind, size, perm) = match &node.kind {
+                NodeKind::File(_, entry) => (
+                    file_type(entry),
+                    entry.object_metadata.size,
+                    // Drop the write permission bits, the filesystem is read-only.
+                    (entry.file_metadata.mode & 0o7555) as u16,
+                ),
+                _ => (FileType::Directory, 0, 0o555),
+            };
+            FileAttr {
+                ino,
+                size,
+                blocks: size.div_ceil(512),
+                atime: self.mount_time,
+                mtime: self.mount_time,
+                ctime: self.mount_time,
+                crtime: self.mount_time,
+                kind,
+                perm,
+                nlink: if kind == FileType::Directory { 2 } else { 1 },
+                uid: self.uid,
+                gid: self.gid,
+                rdev: 0,
+                blksize: 4096,
+                flags: 0,
+            }
+        }
+
+        fn read_file(&self, ino: u64) -> Result<Vec<u8>, c_int> {
+            let (pack_id, entry) = match self.node(ino).map(|n| &n.kind) {
+                Some(NodeKind::File(pack_id, entry)) => (pack_id, entry),
+                Some(_) => return Err(EIO),
+                None => return Err(ENOENT),
+            };
+            let mut contents = vec![];
+            self.repo
+                .read_entries(pack_id, std::slice::from_ref(entry), true, |_, buf| {
+                    contents = buf.to_vec();
+                    Ok(())
+                })
+                .map_err(|e| {
+                    error!("Failed to read {:?}: {}", entry.path, e);
+                    EIO
+                })?;
+            Ok(contents)
+        }
+    }
+
+    fn file_type(entry: &FileEntry) -> FileType {
+        if entry.file_metadata.is_symlink() {
+            FileType::Symlink
+        } else {
+            FileType::RegularFile
+        }
+    }
+
+    impl Filesystem for SnapshotFs {
+        fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+            if let Err(e) = self.ensure_loaded(parent) {
+                return reply.error(e);
+            }
+            let child = match self.node(parent).map(|n| &n.kind) {
+                Some(NodeKind::Dir(children)) => children.get(name).copied(),
+                Some(_) => return reply.error(ENOTDIR),
+                None => None,
+            };
+            match child.and_then(|ino| self.node(ino).map(|node| self.attr(ino, node))) {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(ENOENT),
+            }
+        }
+
+        fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+            match self.node(ino) {
+                Some(node) => reply.attr(&TTL, &self.attr(ino, node)),
+                None => reply.error(ENOENT),
+            }
+        }
+
+        fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+            match self.read_file(ino) {
+                Ok(target) => reply.data(&target),
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+            if let Some((_, count)) = self.open_files.get_mut(&ino) {
+                *count += 1;
+                return reply.opened(ino, 0);
+            }
+            match self.read_file(ino) {
+                Ok(contents) => {
+                    self.open_files.insert(ino, (contents, 1));
+                    reply.opened(ino, 0);
+                }
+                Err(e) => reply.error(e),
+            }
+        }
+
+        fn read(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            _fh: u64,
+            offset: i64,
+            size: u32,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            reply: ReplyData,
+        ) {
+            match self.open_files.get(&ino) {
+                Some((contents, _)) => {
+                    let start = (offset.max(0) as usize).min(contents.len());
+                    let end = start.saturating_add(size as usize).min(contents.len());
+                    reply.data(&contents[start..end]);
+                }
+                None => reply.error(EIO),
+            }
+        }
+
+        fn release(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            _fh: u64,
+            _flags: i32,
+            _lock_owner: Option<u64>,
+            _flush: bool,
+            reply: fuser::ReplyEmpty,
+        ) {
+            if let Some((_, count)) = self.open_files.get_mut(&ino) {
+                *count -= 1;
+                if *count == 0 {
+                    self.open_files.remove(&ino);
+                }
+            }
+            reply.ok();
+        }
+
+        fn readdir(
+            &mut self,
+            _req: &Request<'_>,
+            ino: u64,
+            _fh: u64,
+            offset: i64,
+            mut reply: ReplyDirectory,
+        ) {
+            if let Err(e) = self.ensure_loaded(ino) {
+                return reply.error(e);
+            }
+            let node = match self.node(ino) {
+                Some(node) => node,
+                None => return reply.error(ENOENT),
+            };
+            let children = match &node.kind {
+                NodeKind::Dir(children) => children,
+                _ => return reply.error(ENOTDIR),
+            };
+
+            let dot_entries = [
+                (ino, FileType::Directory, OsStr::new(".")),
+                (node.parent, FileType::Directory, OsStr::new("..")),
+            ];
+            let child_entries = children.iter().map(|(name, &child)| {
+                let kind = match &self.nodes[child as usize - 1].kind {
+                    NodeKind::File(_, entry) => file_type(entry),
+                    _ => FileType::Directory,
+                };
+                (child, kind, name.as_os_str())
+            });
+            let entries = IntoIterator::into_iter(dot_entries).chain(child_entries);
+            for (i, (ino, kind, name)) in entries.enumerate().skip(offset as usize) {
+                // The offset is that of the next entry.
+                if reply.add(ino, (i + 1) as i64, kind, name) {
+                    break;
+                }
+            }
+            reply.ok();
+        }
+    }
+}
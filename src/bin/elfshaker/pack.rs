@@ -4,21 +4,29 @@
 use clap::{App, Arg, ArgMatches};
 use log::error;
 use log::info;
-use std::{error::Error, fs, io, ops::ControlFlow, str::FromStr};
+use std::{collections::HashSet, error::Error, fs, io, ops::ControlFlow, path::Path, str::FromStr};
 
-use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use super::utils::{
+    create_percentage_print_reporter, flag_or_config, open_repo_from_cwd, run_hook, value_or_config,
+};
 use elfshaker::{
-    packidx::PackIndex,
-    repo::{PackId, PackOptions, Repository, SnapshotId},
+    codec::Codec,
+    packidx::{IndexFormat, ObjectChecksum, PackError, PackIndex},
+    repo::{EncryptionKey, PackId, PackOptions, Repository, SnapshotId, DEFAULT_WINDOW_LOG_MAX},
 };
 
 pub(crate) const SUBCOMMAND: &str = "pack";
 
-/// Window log is currently not configurable; We use a hopefully reasonable
-/// value of 28 == 256MiB window log. A configurable window log will require the
-/// user to specify the value during extract operations as well as pack
-/// operations.
+/// We use a hopefully reasonable default value of 28 == 256MiB window log.
+/// Larger window logs improve compression of large packs, at the cost of more
+/// memory during packing and extraction. Extraction supports window logs up to
+/// [`DEFAULT_WINDOW_LOG_MAX`], so larger values are rejected.
 const DEFAULT_COMPRESSION_WINDOW_LOG: u32 = 28;
+/// The default of --frame-size, 512 MiB, which our experiments indicate is
+/// optimal for packing builds of LLVM.
+const DEFAULT_FRAME_SIZE_MIB: u64 = 512;
+/// The smallest window log supported by zstd.
+const MIN_COMPRESSION_WINDOW_LOG: u32 = 10;
 
 pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
@@ -36,33 +44,21 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         })
         .transpose()?;
 
-    // Parse --compression-level
-    let compression_level: i32 = matches.value_of("compression-level").unwrap().parse()?;
-    let compression_level_range = zstd::compression_level_range();
-    if !compression_level_range.contains(&compression_level) {
-        return Err(format!(
-            "Invalid compression level {} (value must be between {} and {})!",
-            compression_level,
-            compression_level_range.start(),
-            compression_level_range.end(),
-        )
-        .into());
-    }
+    let mut repo = open_repo_from_cwd(data_dir)?;
+    let max_size = parse_max_size(matches)?;
 
-    // Parse --threads
-    let threads: u32 = match matches.value_of("threads").unwrap().parse()? {
-        0 => {
-            let phys_cores = num_cpus::get_physical();
-            info!(
-                "-T|--threads=0: defaulting to number of physical cores (OS reports {} cores)",
-                phys_cores
+    // The selected snapshots are copied (or cherry-picked from their packs),
+    // rather than packing the loose indexes.
+    if let Some(selection) = matches.value_of("snapshots") {
+        if snapshots_from.is_some() || snapshots0_from.is_some() || indexes.is_some() {
+            error!(
+                "Cannot specify --snapshots with --snapshots-from, --snapshots0-from or <indexes>!"
             );
-            phys_cores as u32
+            return Err("Invalid options!".into());
         }
-        n => n,
-    };
-
-    let mut repo = open_repo_from_cwd(data_dir)?;
+        let sources = pack_selection(matches, &mut repo, &pack, selection)?;
+        return finish_pack(matches, &mut repo, pack, max_size, &sources);
+    }
 
     let open = |filename| {
         if filename == "-" {
@@ -87,7 +83,9 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         return Err("There are no loose snapshots!".into());
     }
 
-    let mut new_index = PackIndex::new();
+    // Loose snapshots created by older versions of elfshaker use SHA-1.
+    let checksum_algorithm = repo.checksum_algorithm(&snapshots[0])?;
+    let mut new_index = PackIndex::with_checksum_algorithm(checksum_algorithm);
 
     for pack_id in &snapshots {
         assert!(
@@ -95,9 +93,22 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
             "packing non-loose indexes not yet supported"
         );
         let index = repo.load_index(pack_id)?;
-        eprintln!("Packing {} {}", pack_id, index.snapshot_tags().len());
+        if index.checksum_algorithm() != checksum_algorithm {
+            return Err(PackError::ChecksumAlgorithmMismatch(
+                checksum_algorithm,
+                index.checksum_algorithm(),
+            )
+            .into());
+        }
+        status!("Packing {} {}", pack_id, index.snapshot_tags().len());
         index.for_each_snapshot(|snapshot, entries| {
-            if let Err(e) = new_index.push_snapshot(snapshot.to_owned(), entries.clone()) {
+            let result = new_index
+                .push_snapshot(snapshot.to_owned(), entries.clone())
+                .and_then(|_| match index.snapshot_metadata(snapshot) {
+                    Some(metadata) => new_index.set_snapshot_metadata(snapshot, metadata.clone()),
+                    None => Ok(()),
+                });
+            if let Err(e) = result {
                 ControlFlow::Break(Result::<(), _>::Err(e))
             } else {
                 ControlFlow::Continue(())
@@ -105,45 +116,141 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         })?;
     }
 
-    // Parse --frames
-    let frames: u32 = match matches.value_of("frames").unwrap().parse()? {
-        0 => {
-            let loose_size = new_index.object_size_total();
-            let frames = get_frame_size_hint(loose_size);
-            info!("--frames=0: using suggested number of frames = {}", frames);
-            frames
-        }
-        n => n,
-    };
+    let opts = parse_pack_options(matches, new_index.object_size_total())?;
 
     // Print progress every 5%
     let reporter = create_percentage_print_reporter("Compressing objects", 5);
 
-    eprintln!("Compressing objects...");
+    status!("Compressing objects...");
     // Create a pack using the ordered "loose" index.
-    repo.create_pack(
-        &pack,
-        new_index,
-        &PackOptions {
-            compression_level,
-            // We don't expose the windowLog option yet.
-            compression_window_log: DEFAULT_COMPRESSION_WINDOW_LOG,
-            num_workers: threads,
-            num_frames: frames,
+    repo.create_pack(&pack, new_index, &opts, &reporter)?;
+
+    finish_pack(matches, &mut repo, pack, max_size, &snapshots)
+}
+
+/// Parses --max-size (0 means no limit).
+fn parse_max_size(matches: &ArgMatches) -> Result<Option<u64>, Box<dyn Error>> {
+    Ok(
+        match value_or_config(matches, "max-size", "pack.max-size") {
+            Some(value) => match value.parse::<u64>()? {
+                0 => None,
+                n => Some(n),
+            },
+            None => None,
         },
-        &reporter,
-    )?;
+    )
+}
+
+/// Packs the snapshots selected with --snapshots, which are either listed
+/// (separated by commas), or read from a file (one per line, '-' for stdin),
+/// if the value is the path of a file. Returns the loose packs of the selected
+/// snapshots.
+fn pack_selection(
+    matches: &ArgMatches,
+    repo: &mut Repository,
+    pack: &PackId,
+    selection: &str,
+) -> Result<Vec<PackId>, Box<dyn Error>> {
+    let list = if selection == "-" {
+        io::read_to_string(io::stdin())?
+    } else if Path::new(selection).is_file() {
+        fs::read_to_string(selection)?
+    } else {
+        selection.replace(',', "\n")
+    };
+    if repo.is_pack(&pack.to_string())?.is_some() {
+        return Err(format!("The pack {pack} already exists!").into());
+    }
+
+    let mut snapshots = vec![];
+    let mut tags = HashSet::new();
+    let mut object_size_total = 0;
+    for name in list.lines().map(str::trim).filter(|name| !name.is_empty()) {
+        let snapshot = repo.find_snapshot(name)?;
+        if !tags.insert(snapshot.tag().to_owned()) {
+            return Err(format!(
+                "The snapshot tag '{}' is selected more than once! \
+                Rename one of the snapshots first.",
+                snapshot.tag()
+            )
+            .into());
+        }
+        let index = repo.load_index_cached(snapshot.pack())?;
+        object_size_total += index
+            .resolve_snapshot(snapshot.tag())
+            .into_iter()
+            .flatten()
+            .map(|handle| {
+                index
+                    .handle_to_entry_ref(&handle)
+                    .map(|e| e.object_metadata.size)
+            })
+            .sum::<Result<u64, _>>()?;
+        snapshots.push(snapshot);
+    }
+    if snapshots.is_empty() {
+        return Err("No snapshots are selected!".into());
+    }
+
+    let opts = parse_pack_options(matches, object_size_total)?;
+    status!("Packing {} snapshot(s) into {}...", snapshots.len(), pack);
+    let reporter = create_percentage_print_reporter("Compressing objects", 5);
+    repo.create_pack_from_snapshots(pack, &snapshots, &opts, &reporter)?;
+
+    Ok(snapshots
+        .iter()
+        .map(|snapshot| snapshot.pack().clone())
+        .filter(|pack_id| repo.is_pack_loose(pack_id))
+        .collect())
+}
+
+/// Splits the new pack (see --max-size) and moves HEAD to it, if HEAD is one
+/// of the loose packs which were packed.
+fn finish_pack(
+    matches: &ArgMatches,
+    repo: &mut Repository,
+    pack: PackId,
+    max_size: Option<u64>,
+    snapshots: &[PackId],
+) -> Result<(), Box<dyn Error>> {
+    let packs = match max_size {
+        Some(max_size) => split_to_max_size(matches, repo, &pack, max_size)?,
+        None => vec![pack],
+    };
 
     if let (Some(head), _) = repo.read_head()? {
         if snapshots.iter().any(|pack_id| head.pack() == pack_id) {
             info!("Updating HEAD to point to the newly-created pack...");
             // The current HEAD was referencing a snapshot an index which has
             // been packed. Update HEAD to point into the new pack.
-            let new_head = SnapshotId::new(pack, head.tag()).unwrap();
-            repo.update_head(&new_head)?;
+            for pack in &packs {
+                if repo
+                    .load_index(pack)?
+                    .resolve_snapshot(head.tag())
+                    .is_some()
+                {
+                    repo.update_head(&SnapshotId::new(pack.clone(), head.tag())?)?;
+                    break;
+                }
+            }
         }
     }
 
+    for pack in &packs {
+        let vars = [
+            ("ELFSHAKER_PACK", pack.to_string()),
+            (
+                "ELFSHAKER_PACK_PATH",
+                repo.get_pack_path(pack).display().to_string(),
+            ),
+            (
+                "ELFSHAKER_INDEX_PATH",
+                repo.get_pack_index_path(pack).display().to_string(),
+            ),
+        ];
+        run_hook(repo, "post-pack", &vars)?;
+    }
+
     // TODO: New algo needs to take an exclusive repository lock and run GC.
     // // Finally, delete the loose snapshots
     // repo.remove_loose_all()?;
@@ -151,6 +258,128 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Splits the pack into packs of at most `max_size` bytes named `<pack>.0`,
+/// `<pack>.1`, etc., along snapshot boundaries, if it is larger. Returns the
+/// packs containing the snapshots, in order.
+///
+/// The size of a part is only known once it is compressed, so each part is
+/// estimated from the compression ratio of the previous ones, and packed again
+/// with half of the snapshots while it is too large.
+fn split_to_max_size(
+    matches: &ArgMatches,
+    repo: &mut Repository,
+    pack: &PackId,
+    max_size: u64,
+) -> Result<Vec<PackId>, Box<dyn Error>> {
+    let size = repo.get_pack_path(pack).metadata()?.len();
+    if size <= max_size {
+        return Ok(vec![pack.clone()]);
+    }
+
+    let index = repo.load_index(pack)?;
+    let tags = index.snapshot_tags();
+    let mut snapshot_objects = vec![];
+    index.for_each_snapshot(|_, entries| {
+        snapshot_objects.push(
+            entries
+                .iter()
+                .map(|entry| (*entry.checksum, entry.object_metadata.size))
+                .collect::<Vec<_>>(),
+        );
+        ControlFlow::<(), ()>::Continue(())
+    })?;
+    status!(
+        "{} is larger than {} bytes ({} bytes), splitting it...",
+        pack,
+        max_size,
+        size
+    );
+
+    let mut ratio = size as f64 / index.object_size_total().max(1) as f64;
+    let mut outputs = vec![];
+    let mut start = 0;
+    while start < tags.len() {
+        let output = PackId::from_str(&format!("{}.{}", pack, outputs.len()))?;
+        if repo.is_pack(&output.to_string())?.is_some() {
+            discard_parts(repo, &outputs)?;
+            return Err(format!("The pack {output} already exists!").into());
+        }
+        let sizes = cumulative_new_sizes(&snapshot_objects[start..]);
+        // Keep at least one snapshot in each part.
+        let mut count = sizes
+            .iter()
+            .take_while(|&&size| size as f64 * ratio <= max_size as f64)
+            .count()
+            .max(1);
+        loop {
+            let snapshots: Vec<_> = tags[start..start + count]
+                .iter()
+                .map(|tag| SnapshotId::new(pack.clone(), tag))
+                .collect::<Result<_, _>>()?;
+            let opts = parse_pack_options(matches, sizes[count - 1])?;
+            status!(
+                "Packing {} snapshot(s) from {} into {}...",
+                count,
+                pack,
+                output
+            );
+            let reporter = create_percentage_print_reporter("Compressing objects", 5);
+            repo.create_pack_from_snapshots(&output, &snapshots, &opts, &reporter)?;
+
+            let part_size = repo.get_pack_path(&output).metadata()?.len();
+            ratio = part_size as f64 / sizes[count - 1].max(1) as f64;
+            if part_size <= max_size {
+                break;
+            }
+            repo.delete_pack(&output)?;
+            if count == 1 {
+                discard_parts(repo, &outputs)?;
+                return Err(format!(
+                    "Cannot split {pack} into packs of at most {max_size} bytes: \
+                    the snapshot {} alone takes {part_size} bytes!",
+                    tags[start]
+                )
+                .into());
+            }
+            count /= 2;
+        }
+        outputs.push(output);
+        start += count;
+    }
+
+    status!("Deleting {pack}...");
+    repo.delete_pack(pack)?;
+    Ok(outputs)
+}
+
+/// Returns the total size of the distinct objects of the first 1, 2, etc.
+/// snapshots, which is the size of a pack containing them (before compression).
+fn cumulative_new_sizes(snapshot_objects: &[Vec<(ObjectChecksum, u64)>]) -> Vec<u64> {
+    let mut seen = HashSet::new();
+    let mut total = 0;
+    snapshot_objects
+        .iter()
+        .map(|objects| {
+            for (checksum, size) in objects {
+                if seen.insert(checksum) {
+                    total += size;
+                }
+            }
+            total
+        })
+        .collect()
+}
+
+/// Deletes the parts created by [`split_to_max_size`] when it fails, keeping
+/// the pack which was being split. The last parts are deleted first, since
+/// they may reference the objects of the first ones (see --dedup).
+fn discard_parts(repo: &Repository, parts: &[PackId]) -> Result<(), Box<dyn Error>> {
+    for part in parts.iter().rev() {
+        repo.delete_pack(part)?;
+    }
+    Ok(())
+}
+
 fn packs_from_list(
     repo: &Repository,
     mut reader: impl io::Read,
@@ -176,8 +405,6 @@ fn packs_from_list(
 }
 
 pub(crate) fn get_app() -> App<'static, 'static> {
-    let compression_level_range = zstd::compression_level_range();
-
     App::new(SUBCOMMAND)
         .about("Packs the given snapshots into a pack file.")
         .arg(
@@ -188,35 +415,18 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .value_name("name")
                 .help("Specifies the name of the pack to create."),
         )
+        .args(&compression_args())
         .arg(
-            Arg::with_name("threads")
-                .short("T")
-                .long("threads")
+            Arg::with_name("max-size")
                 .takes_value(true)
-                .help("Use the specified number of worker threads for compression. \
-                      The number of threads used is proportional to the memory needed for compression.")
-                .default_value("0"),
-        )
-        .arg(
-            Arg::with_name("compression-level")
-                .takes_value(true)
-                .long("compression-level")
-                .help(leak_static_str(format!("The ZStandard compression level to use (up to {}). Negative values enable fast compression.",
-                    compression_level_range.end())))
-                .default_value("10")
-                .allow_hyphen_values(true)
-        )
-        .arg(
-            Arg::with_name("frames")
-                .takes_value(true)
-                .long("frames")
+                .long("max-size")
+                .value_name("bytes")
                 .help(
-                    "The number of frames to emit in the pack file. \
-                    A lower number of frames limits the number of decompression \
-                    processes that can run concurrently. A higher number of \
-                    frames can result in poorer compression. Specify 0 to \
-                    auto-detect the appropriate number of frames to emit.")
-                .default_value("0")
+                    "Splits the pack into several packs named <name>.0, <name>.1, etc. \
+                    along snapshot boundaries, when it is larger than this size. This is \
+                    useful to host packs on services with object size limits. Specify 0 \
+                    for no limit.",
+                ),
         )
         .arg(
             Arg::with_name("snapshots-from")
@@ -232,6 +442,19 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .value_name("file")
                 .help("Reads the NUL-separated (ASCII \\0) list of snapshots to include in the pack from the specified file. '-' is taken to mean stdin."),
         )
+        .arg(
+            Arg::with_name("snapshots")
+                .takes_value(true)
+                .long("snapshots")
+                .value_name("list|file")
+                .help(
+                    "Packs the listed snapshots (separated by commas), or the ones listed \
+                    in the file (one per line, '-' for stdin), which may be loose or \
+                    packed, instead of the loose snapshots. The snapshots of other packs \
+                    are copied from them, so this can cherry-pick snapshots across packs \
+                    (e.g. into a release pack).",
+                ),
+        )
         .arg(
             Arg::with_name("indexes")
             .index(2)
@@ -240,6 +463,286 @@ pub(crate) fn get_app() -> App<'static, 'static> {
         )
 }
 
+/// The arguments which control pack creation, used by all subcommands which
+/// create packs. See [`parse_pack_options`].
+pub(crate) fn compression_args() -> Vec<Arg<'static, 'static>> {
+    let compression_level_range = zstd::compression_level_range();
+
+    vec![
+        Arg::with_name("threads")
+            .short("T")
+            .long("threads")
+            .takes_value(true)
+            .help("Use the specified number of worker threads for compression. \
+                  The number of threads used is proportional to the memory needed for compression.")
+            .default_value("0"),
+        Arg::with_name("codec")
+            .takes_value(true)
+            .long("codec")
+            .possible_values(&["zstd", "lz4", "xz"])
+            .help(
+                "The codec to compress the pack with. lz4 compresses poorly, but makes \
+                extracting faster. xz can compress better than zstd, but makes packing \
+                and extracting much slower. --window-log and --dictionary-size only \
+                apply to zstd, xz uses the compression level as its preset (at most 9).")
+            .default_value("zstd"),
+        Arg::with_name("compression-level")
+            .takes_value(true)
+            .long("compression-level")
+            .help(leak_static_str(format!("The ZStandard compression level to use (up to {}). Negative values enable fast compression.",
+                compression_level_range.end())))
+            .default_value("10")
+            .allow_hyphen_values(true),
+        Arg::with_name("window-log")
+            .takes_value(true)
+            .long("window-log")
+            .help(leak_static_str(format!("The ZStandard window log to use (between {} and {}). \
+                Larger values can find matches between objects which are further apart, \
+                but require more memory when packing and extracting.",
+                MIN_COMPRESSION_WINDOW_LOG, DEFAULT_WINDOW_LOG_MAX)))
+            .default_value(leak_static_str(DEFAULT_COMPRESSION_WINDOW_LOG.to_string())),
+        Arg::with_name("frames")
+            .takes_value(true)
+            .long("frames")
+            .help(
+                "The number of frames to emit in the pack file. \
+                A lower number of frames limits the number of decompression \
+                processes that can run concurrently. A higher number of \
+                frames can result in poorer compression. Specify 0 to \
+                auto-detect the appropriate number of frames to emit, \
+                according to --frame-size.")
+            .default_value("0"),
+        Arg::with_name("frame-size")
+            .takes_value(true)
+            .long("frame-size")
+            .value_name("MiB")
+            .help(
+                "The target decompressed size of each frame in the pack file, used to \
+                determine the number of frames when --frames is 0. Unlike \
+                --max-frame-size, this is not a limit: frames can be larger, e.g. when \
+                they contain a single large file.")
+            .default_value(leak_static_str(DEFAULT_FRAME_SIZE_MIB.to_string())),
+        Arg::with_name("max-frame-size")
+            .takes_value(true)
+            .long("max-frame-size")
+            .value_name("MiB")
+            .help(
+                "Limits the decompressed size of each frame in the pack file, splitting \
+                the frames further if needed. Only the frames containing the requested \
+                files are decompressed, so smaller frames make extracting a few files \
+                from the pack faster. Smaller frames result in poorer compression. \
+                Specify 0 for no limit.")
+            .default_value("0"),
+        Arg::with_name("delta")
+            .long("delta")
+            .help(
+                "Stores files as deltas against a previous version of the same path, \
+                when the delta is much smaller than the file. This can shrink packs \
+                containing many snapshots of slightly different files, but makes \
+                packing slower."),
+        Arg::with_name("chunk")
+            .long("chunk")
+            .help(
+                "Splits large files into chunks, using content-defined boundaries. \
+                Chunks are stored once, even if they are part of several files. This \
+                can shrink packs containing large files which change little between \
+                snapshots, even when the versions are too far apart to be compressed \
+                together."),
+        Arg::with_name("similarity")
+            .long("similarity")
+            .help(
+                "Orders the objects by the similarity of their contents, rather than \
+                by size, before compressing them, so that similar objects of different \
+                sizes are compressed together. This can shrink packs, but makes \
+                packing slower."),
+        Arg::with_name("dedup")
+            .long("dedup")
+            .help(
+                "Skips the files already stored in the other packs of the repository, \
+                which the pack references instead. This shrinks the packs of long-lived \
+                repositories, but the referenced packs cannot be removed while they are \
+                referenced."),
+        Arg::with_name("dictionary-size")
+            .takes_value(true)
+            .long("dictionary-size")
+            .value_name("KiB")
+            .help(
+                "Trains a compression dictionary of this size on a sample of the small \
+                files, and compresses each small file in a frame of its own with it. \
+                This can shrink packs containing many small files with similar contents \
+                (e.g. object files), especially with many frames (--frames, \
+                --max-frame-size). The dictionary is not used when there are too few \
+                small files, or when it does not shrink them. Specify 0 for no \
+                dictionary.")
+            .default_value("0"),
+        Arg::with_name("encryption-key")
+            .takes_value(true)
+            .long("encryption-key")
+            .value_name("file")
+            .help(
+                "Encrypts the pack with the key in the file (64 hex digits), so that it \
+                can be hosted on untrusted storage. The pack can then only be read with \
+                the key (see encryption.key in the configuration)."),
+        Arg::with_name("encrypt-index")
+            .long("encrypt-index")
+            .help(
+                "Encrypts the pack index too, with the key of --encryption-key. The \
+                paths and the checksums of the files can then only be read with the key."),
+        Arg::with_name("index-format")
+            .takes_value(true)
+            .long("index-format")
+            .possible_values(&["msgpack", "flat"])
+            .help(
+                "The format of the pack index. flat indexes are queried in place, \
+                without loading them, which makes listing and extracting snapshots \
+                faster in packs containing many files, but older versions of elfshaker \
+                can't read them.")
+            .default_value("msgpack"),
+    ]
+}
+
+/// Parses the arguments added by [`compression_args`].
+///
+/// # Arguments
+///
+/// * `object_size_total` - The total size of the objects to pack, used to
+///   determine the number of frames, when not specified.
+pub(crate) fn parse_pack_options(
+    matches: &ArgMatches,
+    object_size_total: u64,
+) -> Result<PackOptions, Box<dyn Error>> {
+    // Parse --codec
+    let codec: Codec = value_or_config(matches, "codec", "pack.codec")
+        .unwrap()
+        .parse()?;
+
+    // Parse --compression-level
+    let compression_level: i32 =
+        value_or_config(matches, "compression-level", "pack.compression-level")
+            .unwrap()
+            .parse()?;
+    let compression_level_range = zstd::compression_level_range();
+    if !compression_level_range.contains(&compression_level) {
+        return Err(format!(
+            "Invalid compression level {} (value must be between {} and {})!",
+            compression_level,
+            compression_level_range.start(),
+            compression_level_range.end(),
+        )
+        .into());
+    }
+
+    // Parse --window-log
+    let window_log: u32 = value_or_config(matches, "window-log", "pack.window-log")
+        .unwrap()
+        .parse()?;
+    if !(MIN_COMPRESSION_WINDOW_LOG..=DEFAULT_WINDOW_LOG_MAX).contains(&window_log) {
+        return Err(format!(
+            "Invalid window log {} (value must be between {} and {})!",
+            window_log, MIN_COMPRESSION_WINDOW_LOG, DEFAULT_WINDOW_LOG_MAX,
+        )
+        .into());
+    }
+
+    // Parse --threads
+    let threads: u32 = match value_or_config(matches, "threads", "pack.threads")
+        .unwrap()
+        .parse()?
+    {
+        0 => {
+            let phys_cores = num_cpus::get_physical();
+            info!(
+                "-T|--threads=0: defaulting to number of physical cores (OS reports {} cores)",
+                phys_cores
+            );
+            phys_cores as u32
+        }
+        n => n,
+    };
+
+    // Parse --frame-size
+    let frame_size = match value_or_config(matches, "frame-size", "pack.frame-size")
+        .unwrap()
+        .parse::<u64>()?
+    {
+        0 => return Err("--frame-size must be positive!".into()),
+        n => n
+            .checked_mul(1024 * 1024)
+            .ok_or("--frame-size is too large!")?,
+    };
+
+    // Parse --frames
+    let frames: u32 = match value_or_config(matches, "frames", "pack.frames")
+        .unwrap()
+        .parse()?
+    {
+        0 => {
+            let frames = get_frame_size_hint(object_size_total, frame_size);
+            info!("--frames=0: using suggested number of frames = {}", frames);
+            frames
+        }
+        n => n,
+    };
+
+    // Parse --max-frame-size
+    let max_frame_size = match value_or_config(matches, "max-frame-size", "pack.max-frame-size")
+        .unwrap()
+        .parse::<u64>()?
+    {
+        0 => None,
+        n => Some(
+            n.checked_mul(1024 * 1024)
+                .ok_or("--max-frame-size is too large!")?,
+        ),
+    };
+
+    // Parse --dictionary-size
+    let dictionary_size = match value_or_config(matches, "dictionary-size", "pack.dictionary-size")
+        .unwrap()
+        .parse::<usize>()?
+    {
+        0 => None,
+        n => Some(
+            n.checked_mul(1024)
+                .ok_or("--dictionary-size is too large!")?,
+        ),
+    };
+    if dictionary_size.is_some() && codec != Codec::Zstd {
+        return Err("--dictionary-size is only supported by the zstd codec!".into());
+    }
+
+    // Parse --encryption-key
+    let encryption_key = value_or_config(matches, "encryption-key", "pack.encryption-key")
+        .map(|path| EncryptionKey::load(Path::new(path)))
+        .transpose()?;
+    let encrypt_index = flag_or_config(matches, "encrypt-index", "pack.encrypt-index")?;
+    if encrypt_index && encryption_key.is_none() {
+        return Err("--encrypt-index requires --encryption-key!".into());
+    }
+
+    // Parse --index-format
+    let index_format: IndexFormat = value_or_config(matches, "index-format", "pack.index-format")
+        .unwrap()
+        .parse()?;
+
+    Ok(PackOptions {
+        codec,
+        compression_level,
+        compression_window_log: window_log,
+        num_workers: threads,
+        num_frames: frames,
+        max_frame_size,
+        delta: flag_or_config(matches, "delta", "pack.delta")?,
+        chunk: flag_or_config(matches, "chunk", "pack.chunk")?,
+        dictionary_size,
+        similarity: flag_or_config(matches, "similarity", "pack.similarity")?,
+        dedup: flag_or_config(matches, "dedup", "pack.dedup")?,
+        encryption_key,
+        encrypt_index,
+        index_format,
+    })
+}
+
 /// Extends the lifetime of the string to 'static.
 /// The memory will only be reclaimed at process exit.
 fn leak_static_str(s: String) -> &'static str {
@@ -247,9 +750,8 @@ fn leak_static_str(s: String) -> &'static str {
 }
 
 /// This is the built-in heuristic that tells us how many frames to use based on
-/// the data size. 1 frame / 512 MiB
-const FRAME_PER_DATA_SIZE: u64 = 512 * 1024 * 1024;
-fn get_frame_size_hint(object_size_total: u64) -> u32 {
-    // Divide by FRAME_PER_DATA_SIZE, rounding up
-    ((object_size_total + FRAME_PER_DATA_SIZE - 1) / FRAME_PER_DATA_SIZE) as u32
+/// the data size: 1 frame / `frame_size` bytes.
+fn get_frame_size_hint(object_size_total: u64, frame_size: u64) -> u32 {
+    // Divide by frame_size, rounding up
+    object_size_total.div_ceil(frame_size) as u32
 }
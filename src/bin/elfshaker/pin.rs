@@ -0,0 +1,73 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::packidx::PackError;
+use elfshaker::repo::Error as RepoError;
+
+pub(crate) const SUBCOMMAND: &str = "pin";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let is_remove = matches.is_present("remove");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    let snapshots = match matches.values_of("snapshot") {
+        Some(snapshots) => snapshots,
+        None => {
+            for tag in repo.pins()? {
+                println!("{tag}");
+            }
+            return Ok(());
+        }
+    };
+
+    for snapshot in snapshots {
+        if is_remove {
+            // The pinned snapshot may have been removed since.
+            let tag = match repo.find_snapshot(snapshot) {
+                Ok(snapshot) => snapshot.tag().to_owned(),
+                Err(RepoError::PackError(PackError::SnapshotNotFound(_))) => snapshot.to_owned(),
+                Err(e) => return Err(e.into()),
+            };
+            if repo.unpin_snapshot(&tag)? {
+                status!("Unpinned {tag}");
+            } else {
+                status!("{tag} is not pinned");
+            }
+        } else {
+            let snapshot = repo.find_snapshot(snapshot)?;
+            if repo.pin_snapshot(&snapshot)? {
+                status!("Pinned {}", snapshot.tag());
+            } else {
+                status!("{} is already pinned", snapshot.tag());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Pins the specified snapshots, so that they can't be removed (by rm or gc) \
+            until they are unpinned. Lists the pinned snapshots if none are specified.",
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .multiple(true)
+                .index(1)
+                .help("The snapshots to pin (or unpin)."),
+        )
+        .arg(
+            Arg::with_name("remove")
+                .long("remove")
+                .requires("snapshot")
+                .help("Unpins the snapshots, instead of pinning them."),
+        )
+}
@@ -0,0 +1,136 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use chrono::{DateTime, Utc};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use std::{collections::HashMap, error::Error};
+
+use super::pack::compression_args;
+use super::rm::remove_snapshots;
+use super::utils::{open_repo_from_cwd, parse_date, snapshot_history};
+use elfshaker::repo::SnapshotId;
+
+pub(crate) const SUBCOMMAND: &str = "prune";
+
+/// The copies of the snapshots with the same tag, which are kept or removed
+/// together.
+struct Snapshots {
+    tag: String,
+    /// The time of the most recent copy.
+    time: DateTime<Utc>,
+    copies: Vec<SnapshotId>,
+}
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let keep_last = matches
+        .value_of("keep_last")
+        .map(str::parse::<usize>)
+        .transpose()
+        .map_err(|e| format!("Invalid --keep-last: {e}"))?;
+    let keep_since = matches.value_of("keep_since").map(parse_date).transpose()?;
+    let is_dry_run = matches.is_present("dry_run");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+    // The HEADs of all the working trees sharing the repository are kept.
+    let head_tags: Vec<String> = repo
+        .worktrees()?
+        .into_iter()
+        .filter_map(|(_, head)| Some(head?.tag().to_owned()))
+        .collect();
+    let pins = repo.pins()?;
+
+    // Newest first, since the most recent copy of each tag comes first.
+    let mut all: Vec<Snapshots> = vec![];
+    let mut by_tag = HashMap::new();
+    for entry in snapshot_history(&repo)? {
+        let tag = entry.snapshot.tag().to_owned();
+        let i = *by_tag.entry(tag.clone()).or_insert_with(|| {
+            all.push(Snapshots {
+                tag,
+                time: entry.time,
+                copies: vec![],
+            });
+            all.len() - 1
+        });
+        all[i].copies.push(entry.snapshot);
+    }
+
+    let mut removed = vec![];
+    let mut kept = 0;
+    for (i, snapshots) in all.into_iter().enumerate() {
+        let is_kept = keep_last.is_some_and(|n| i < n)
+            || keep_since.is_some_and(|since| snapshots.time >= since)
+            || head_tags.contains(&snapshots.tag)
+            || pins.contains(&snapshots.tag);
+        if is_kept {
+            kept += snapshots.copies.len();
+            continue;
+        }
+        for snapshot in snapshots.copies {
+            if is_dry_run {
+                println!("Would remove {snapshot}");
+            }
+            removed.push(snapshot);
+        }
+    }
+
+    if is_dry_run {
+        println!(
+            "Would remove {} snapshot(s) and keep {} snapshot(s).",
+            removed.len(),
+            kept
+        );
+        return Ok(());
+    }
+    if removed.is_empty() {
+        status!("Nothing to prune.");
+        return Ok(());
+    }
+
+    let count = removed.len();
+    remove_snapshots(&mut repo, matches, removed)?;
+    status!("Removed {count} snapshot(s), kept {kept} snapshot(s).");
+    status!("Use elfshaker gc -o to clean up the unreferenced loose objects.");
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Removes the snapshots which are not kept by the retention policy (like rm). \
+            The HEADs (of all the working trees) and the pinned snapshots are always \
+            kept. The time of a snapshot is the one it was stored, or the one its pack \
+            was last written, for the snapshots stored before their time was recorded.",
+        )
+        .arg(
+            Arg::with_name("keep_last")
+                .long("keep-last")
+                .takes_value(true)
+                .value_name("N")
+                .help("Keeps the N most recent snapshots."),
+        )
+        .arg(
+            Arg::with_name("keep_since")
+                .long("keep-since")
+                .takes_value(true)
+                .value_name("date")
+                .help(
+                    "Keeps the snapshots more recent than the date, which is either \
+                    relative (e.g. 2w or 3d, for 2 weeks or 3 days ago), a day \
+                    (e.g. 2022-03-01) or an RFC 3339 date and time.",
+                ),
+        )
+        .group(
+            ArgGroup::with_name("policy")
+                .args(&["keep_last", "keep_since"])
+                .required(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("Prints the snapshots which would be removed, without removing them."),
+        )
+        .args(&compression_args())
+}
@@ -0,0 +1,51 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{error::Error, str::FromStr};
+
+use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::repo::PackId;
+
+pub(crate) const SUBCOMMAND: &str = "push";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let destination = matches.value_of("remote").unwrap();
+    let packs = matches.values_of("pack").unwrap();
+    let token = matches.value_of("token");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+    repo.set_progress_reporter(|msg| create_percentage_print_reporter(msg, 5));
+
+    for pack in packs {
+        let pack_id = PackId::from_str(pack)?;
+        repo.push_pack(&pack_id, destination, token)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about("Uploads packs to a remote with HTTP PUT requests.")
+        .arg(Arg::with_name("remote").required(true).index(1).help(
+            "The URL of the directory to upload to, or the name of a \
+                    remote served by `elfshaker serve`.",
+        ))
+        .arg(
+            Arg::with_name("pack")
+                .required(true)
+                .multiple(true)
+                .index(2)
+                .help("The packs to upload."),
+        )
+        .arg(
+            Arg::with_name("token")
+                .long("token")
+                .takes_value(true)
+                .env("ELFSHAKER_TOKEN")
+                .hide_env_values(true)
+                .help("Sends the token in the 'Authorization: Bearer' header."),
+        )
+}
@@ -0,0 +1,54 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{error::Error, str::FromStr};
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::repo::PackId;
+
+pub(crate) const SUBCOMMAND: &str = "rename";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let old = matches.value_of("old").unwrap();
+    let new = matches.value_of("new").unwrap();
+    let is_pack = matches.is_present("pack");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    if is_pack {
+        let pack_id = PackId::from_str(old)?;
+        let new_pack_id = PackId::from_str(new)?;
+        repo.rename_pack(&pack_id, &new_pack_id)?;
+        status!("Renamed pack {pack_id} to {new_pack_id}");
+    } else {
+        let snapshot = repo.find_snapshot(old)?;
+        let new_snapshot = repo.rename_snapshot(&snapshot, new)?;
+        status!("Renamed {snapshot} to {new_snapshot}");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about("Renames a snapshot (or a pack, with --pack).")
+        .arg(
+            Arg::with_name("old")
+                .required(true)
+                .index(1)
+                .help("The snapshot (or pack) to rename."),
+        )
+        .arg(
+            Arg::with_name("new")
+                .required(true)
+                .index(2)
+                .help("The new snapshot tag (or pack name)."),
+        )
+        .arg(
+            Arg::with_name("pack")
+                .long("pack")
+                .help("Renames the pack <old> to <new>, instead of a snapshot."),
+        )
+}
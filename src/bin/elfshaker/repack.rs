@@ -0,0 +1,87 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use log::info;
+use std::error::Error;
+
+use super::pack::{compression_args, parse_pack_options};
+use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::repo::SnapshotId;
+
+pub(crate) const SUBCOMMAND: &str = "repack";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let packs: Vec<_> = matches.values_of("pack").unwrap().collect();
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    for pack in packs {
+        let pack_id = repo
+            .is_pack(pack)?
+            .ok_or_else(|| format!("Pack {pack} not found!"))?;
+        if repo.is_pack_loose(&pack_id) {
+            return Err(format!("{pack_id} is a loose pack and cannot be repacked!").into());
+        }
+
+        let index = repo.load_index(&pack_id)?;
+        let snapshots: Vec<_> = index
+            .snapshot_tags()
+            .iter()
+            .map(|tag| SnapshotId::new(pack_id.clone(), tag))
+            .collect::<Result<_, _>>()?;
+        let mut opts = parse_pack_options(matches, index.object_size_total())?;
+        // Unless specified, keep the compression parameters of the pack.
+        if let Some(compression) = index.compression() {
+            if matches.occurrences_of("codec") == 0 {
+                opts.codec = compression.codec;
+            }
+            if matches.occurrences_of("compression-level") == 0 {
+                opts.compression_level = compression.level;
+            }
+            if matches.occurrences_of("window-log") == 0 {
+                opts.compression_window_log = compression.window_log;
+            }
+            info!(
+                "Repacking {} with {} (level {}, window log {})",
+                pack_id,
+                opts.codec.name(),
+                opts.compression_level,
+                opts.compression_window_log
+            );
+        }
+        drop(index);
+
+        let old_size = repo.get_pack_path(&pack_id).metadata()?.len();
+        status!(
+            "Repacking {} snapshot(s) in {}...",
+            snapshots.len(),
+            pack_id
+        );
+        let reporter = create_percentage_print_reporter("Compressing objects", 5);
+        repo.create_pack_from_snapshots(&pack_id, &snapshots, &opts, &reporter)?;
+        let new_size = repo.get_pack_path(&pack_id).metadata()?.len();
+        status!("Repacked {pack_id} ({old_size} -> {new_size} bytes)");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Rebuilds existing packs with different compression options. \
+            The loose snapshots the packs were created from are not needed. \
+            The codec, compression level and window log of each pack are kept, \
+            unless specified.",
+        )
+        .arg(
+            Arg::with_name("pack")
+                .required(true)
+                .multiple(true)
+                .index(1)
+                .help("The packs to rebuild."),
+        )
+        .args(&compression_args())
+}
@@ -0,0 +1,119 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{collections::BTreeMap, error::Error};
+
+use super::pack::{compression_args, parse_pack_options};
+use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::repo::{Repository, SnapshotId};
+
+pub(crate) const SUBCOMMAND: &str = "rm";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let snapshots: Vec<_> = matches.values_of("snapshot").unwrap().collect();
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+    let head = repo.read_head()?.0;
+    let worktrees = repo.worktrees()?;
+
+    let mut removed = vec![];
+    for snapshot in snapshots {
+        let snapshot = repo.find_snapshot(snapshot)?;
+        if Some(&snapshot) == head.as_ref() {
+            return Err(format!(
+                "Cannot remove {snapshot}, because it is the current HEAD! \
+                Extract another snapshot first."
+            )
+            .into());
+        }
+        let worktree = worktrees
+            .iter()
+            .find(|(_, head)| head.as_ref() == Some(&snapshot));
+        if let Some((path, _)) = worktree {
+            return Err(format!(
+                "Cannot remove {snapshot}, because it is the HEAD of the working tree {}! \
+                Extract another snapshot there first.",
+                path.display()
+            )
+            .into());
+        }
+        if repo.is_pinned(&snapshot)? {
+            return Err(format!(
+                "Cannot remove {snapshot}, because it is pinned! \
+                Unpin it first (elfshaker pin --remove {}).",
+                snapshot.tag()
+            )
+            .into());
+        }
+        removed.push(snapshot);
+    }
+
+    remove_snapshots(&mut repo, matches, removed)
+}
+
+/// Removes the snapshots, rewriting their packs without them (with the
+/// compression options of [`compression_args`]), or deleting the packs which
+/// only contain removed snapshots.
+pub(crate) fn remove_snapshots(
+    repo: &mut Repository,
+    matches: &ArgMatches,
+    snapshots: Vec<SnapshotId>,
+) -> Result<(), Box<dyn Error>> {
+    // Group the snapshots to remove by pack.
+    let mut packs = BTreeMap::new();
+    for snapshot in snapshots {
+        packs
+            .entry(snapshot.pack().clone())
+            .or_insert_with(Vec::new)
+            .push(snapshot.tag().to_owned());
+    }
+
+    for (pack_id, removed) in packs {
+        let index = repo.load_index(&pack_id)?;
+        let remaining: Vec<_> = index
+            .snapshot_tags()
+            .iter()
+            .filter(|tag| !removed.contains(tag))
+            .map(|tag| SnapshotId::new(pack_id.clone(), tag))
+            .collect::<Result<_, _>>()?;
+
+        if remaining.is_empty() {
+            // Loose packs always contain a single snapshot, so they end up here.
+            status!("Deleting {pack_id}...");
+            repo.delete_pack(&pack_id)?;
+            continue;
+        }
+
+        let opts = parse_pack_options(matches, index.object_size_total())?;
+        drop(index);
+
+        status!(
+            "Repacking {} without {} snapshot(s)...",
+            pack_id,
+            removed.len()
+        );
+        let reporter = create_percentage_print_reporter("Compressing objects", 5);
+        repo.create_pack_from_snapshots(&pack_id, &remaining, &opts, &reporter)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Removes the specified snapshots. Packs are rewritten without the \
+            snapshots, dropping the objects which are no longer referenced. \
+            Use gc to clean up the loose objects of removed loose snapshots.",
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .multiple(true)
+                .index(1)
+                .help("The snapshots to remove."),
+        )
+        .args(&compression_args())
+}
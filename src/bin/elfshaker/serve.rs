@@ -0,0 +1,584 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use chrono::DateTime;
+use clap::{App, Arg, ArgMatches};
+use log::{error, info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::OsStr,
+    fs,
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+use tiny_http::{Header, Request, Response, Server, StatusCode};
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::repo::remote::{
+    compute_checksum, format_http_date, FileChecksum, RemoteIndex, RemotePack,
+};
+use elfshaker::repo::{Error as ElfshakerError, PackId, Repository};
+
+pub(crate) const SUBCOMMAND: &str = "serve";
+
+/// The path of the remote index (.esi) on the server.
+const REMOTE_INDEX_PATH: &str = "/index.esi";
+/// The path of the directory containing the pack files on the server.
+const PACKS_PATH: &str = "/packs/";
+/// The path of the entries of the build cache on the server, with --cache.
+const CACHE_PATH: &str = "/cache/";
+/// The number of requests handled concurrently.
+const SERVER_THREADS: usize = 4;
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap());
+    let bind = matches.value_of("bind").unwrap();
+    let push_token = matches.value_of("push-token").map(|s| s.to_owned());
+    let is_cache = matches.is_present("cache");
+    let max_cache_entry_size = matches
+        .value_of("max-cache-entry-size")
+        .unwrap()
+        .parse::<u64>()?;
+    let max_pack_size = matches.value_of("max-pack-size").unwrap().parse::<u64>()?;
+    let max_index_size = matches.value_of("max-index-size").unwrap().parse::<u64>()?;
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let server = Server::http(bind).map_err(|e| format!("Failed to listen on {bind}: {e}"))?;
+    status!("Serving the repository at http://{bind}{REMOTE_INDEX_PATH}");
+    if is_cache {
+        status!("Serving the build cache at http://{bind}{CACHE_PATH}");
+    }
+
+    let state = ServerState {
+        repo,
+        data_dir: data_dir.to_owned(),
+        bind: bind.to_owned(),
+        push_token,
+        is_cache,
+        max_cache_entry_size,
+        max_pack_size,
+        max_index_size,
+        cache_lock: Mutex::new(()),
+        checksums: Mutex::new(HashMap::new()),
+    };
+
+    std::thread::scope(|s| {
+        for _ in 0..SERVER_THREADS {
+            s.spawn(|| loop {
+                match server.recv() {
+                    Ok(request) => state.handle(request),
+                    Err(e) => error!("Failed to receive the request: {}", e),
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Serves the packs in the repository over HTTP, so that other \
+            repositories can clone it with `elfshaker clone http://<address>/index.esi`.",
+        )
+        .arg(
+            Arg::with_name("bind")
+                .long("bind")
+                .takes_value(true)
+                .value_name("address")
+                .default_value("127.0.0.1:8080")
+                .help("The address and port to listen on (e.g. 0.0.0.0:8080 to accept connections from other machines)."),
+        )
+        .arg(
+            Arg::with_name("push-token")
+                .long("push-token")
+                .takes_value(true)
+                .value_name("token")
+                .env("ELFSHAKER_PUSH_TOKEN")
+                .hide_env_values(true)
+                .help(
+                    "Accepts packs uploaded with `elfshaker push --token <token>` \
+                    (HTTP PUT requests to /packs/), and the entries of the build cache \
+                    with --cache. Pushing is disabled otherwise.",
+                ),
+        )
+        .arg(
+            Arg::with_name("cache")
+                .long("cache")
+                .help(
+                    "Also serves the repository as a build cache (e.g. for ccache or sccache): \
+                    GET /cache/<key> returns the entry, which PUT /cache/<key> stores \
+                    (with the push token) as a file of the loose snapshot cache-<date>.",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-cache-entry-size")
+                .long("max-cache-entry-size")
+                .takes_value(true)
+                .value_name("bytes")
+                .default_value("1073741824")
+                .help(
+                    "The largest entry of the build cache which can be stored with --cache \
+                    (1 GiB by default). Larger entries are rejected (HTTP 413).",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-pack-size")
+                .long("max-pack-size")
+                .takes_value(true)
+                .value_name("bytes")
+                .default_value("68719476736")
+                .help(
+                    "The largest .pack which can be pushed (64 GiB by default). \
+                    Larger packs are rejected (HTTP 413).",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-index-size")
+                .long("max-index-size")
+                .takes_value(true)
+                .value_name("bytes")
+                .default_value("1073741824")
+                .help(
+                    "The largest .pack.idx (or .pack.sig) which can be pushed (1 GiB by default), \
+                    which is read to memory to be checked. Larger files are rejected (HTTP 413).",
+                ),
+        )
+}
+
+/// A pack which is available locally and can be served.
+struct ServedPack {
+    pack_id: PackId,
+    /// The name of the .pack file on the server.
+    file_name: String,
+    pack_path: PathBuf,
+    index_path: PathBuf,
+    /// The .pack.sig, which only exists if the pack is signed.
+    signature_path: PathBuf,
+}
+
+/// The modification time and size of a file.
+type FileStamp = (SystemTime, u64);
+
+struct ServerState {
+    repo: Repository,
+    /// The data directory, to open the repository when storing the entries of
+    /// the build cache, so that it is only locked for writing meanwhile.
+    data_dir: PathBuf,
+    /// The address the server is listening on, used when the request does not
+    /// specify a Host header.
+    bind: String,
+    /// The token required to upload packs, if pushing is enabled.
+    push_token: Option<String>,
+    /// Whether the build cache is served.
+    is_cache: bool,
+    /// The largest entry of the build cache which can be stored, in bytes.
+    max_cache_entry_size: u64,
+    /// The largest .pack which can be pushed, in bytes.
+    max_pack_size: u64,
+    /// The largest .pack.idx or .pack.sig which can be pushed, in bytes.
+    max_index_size: u64,
+    /// Held while storing an entry of the build cache.
+    cache_lock: Mutex<()>,
+    /// The checksums of the served files, along with the modification time and
+    /// size of the files when they were computed.
+    checksums: Mutex<HashMap<PathBuf, (FileStamp, FileChecksum)>>,
+}
+
+impl ServerState {
+    fn handle(&self, mut request: Request) {
+        let url = request.url().to_owned();
+        let method = request.method().to_string();
+        let cache_key = url.strip_prefix(CACHE_PATH).filter(|_| self.is_cache);
+        let response = if let Some(key) = cache_key {
+            self.cache_response(&mut request, &method, key)
+        } else if method == "PUT" {
+            match url.strip_prefix(PACKS_PATH) {
+                Some(file_name) => self.upload_response(&mut request, file_name),
+                None => Ok(status_response(404)),
+            }
+        } else if method != "GET" && method != "HEAD" {
+            Ok(status_response(405))
+        } else if url == "/" || url == REMOTE_INDEX_PATH {
+            self.remote_index_response(&request)
+        } else if let Some(file_name) = url.strip_prefix(PACKS_PATH) {
+            self.pack_file_response(&request, file_name)
+        } else {
+            Ok(status_response(404))
+        };
+
+        let response = response.unwrap_or_else(|e| {
+            error!("Failed to handle {} {}: {}", method, url, e);
+            status_response(500)
+        });
+        info!("{} {} -> {}", method, url, response.status_code().0);
+        if let Err(e) = request.respond(response) {
+            warn!("Failed to send the response to {} {}: {}", method, url, e);
+        }
+    }
+
+    /// Lists the packs which are available locally (loose snapshots are not
+    /// served). The packs reading some of their objects from other packs (see
+    /// [`Repository::external_packs`]) are only served along with them.
+    fn served_packs(&self) -> Result<Vec<ServedPack>, Box<dyn Error>> {
+        let mut packs: Vec<ServedPack> = vec![];
+        for pack_id in self.repo.packs()? {
+            let pack_path = self.repo.get_pack_path(&pack_id);
+            if self.repo.is_pack_loose(&pack_id) || !pack_path.exists() {
+                continue;
+            }
+            let file_name = pack_path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            if packs.iter().any(|p| p.file_name == file_name) {
+                warn!(
+                    "Not serving {}, since another pack has the same name",
+                    pack_id
+                );
+                continue;
+            }
+            packs.push(ServedPack {
+                pack_id: pack_id.clone(),
+                file_name,
+                pack_path,
+                index_path: self.repo.get_pack_index_path(&pack_id),
+                signature_path: self.repo.get_pack_signature_path(&pack_id),
+            });
+        }
+        // The clients find the packs named by the served packs next to them,
+        // so the packs naming other directories, or packs which are not
+        // served, are left out (until none is left out).
+        let mut external_packs = HashMap::new();
+        for pack in &packs {
+            let external = match self.repo.external_packs(&pack.pack_id) {
+                Ok(external) => external,
+                Err(e) => {
+                    warn!("Can't find the packs {} reads from: {}", pack.pack_id, e);
+                    vec![]
+                }
+            };
+            external_packs.insert(pack.pack_id.clone(), external);
+        }
+        loop {
+            let count = packs.len();
+            let served: HashSet<_> = packs.iter().map(|p| p.pack_id.clone()).collect();
+            packs.retain(|pack| {
+                let is_served = external_packs[&pack.pack_id].iter().all(|name| {
+                    !name.contains('/')
+                        && pack
+                            .pack_id
+                            .resolve_external(name)
+                            .is_ok_and(|id| served.contains(&id))
+                });
+                if !is_served {
+                    warn!(
+                        "Not serving {}, since it reads objects from packs which are not served",
+                        pack.pack_id
+                    );
+                }
+                is_served
+            });
+            if packs.len() == count {
+                break;
+            }
+        }
+        packs.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        Ok(packs)
+    }
+
+    fn checksum(&self, path: &Path) -> Result<FileChecksum, Box<dyn Error>> {
+        let metadata = fs::metadata(path)?;
+        let stamp = (metadata.modified()?, metadata.len());
+        if let Some((cached_stamp, checksum)) = self.checksums.lock().unwrap().get(path) {
+            if *cached_stamp == stamp {
+                return Ok(*checksum);
+            }
+        }
+        info!("Computing the checksum of {}...", path.display());
+        let checksum = compute_checksum(path)?;
+        self.checksums
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), (stamp, checksum));
+        Ok(checksum)
+    }
+
+    fn remote_index_response(&self, request: &Request) -> Result<HttpResponse, Box<dyn Error>> {
+        let packs = self.served_packs()?;
+        // The index changes whenever any of the packs changes.
+        let mut last_modified = SystemTime::UNIX_EPOCH;
+        for pack in &packs {
+            for path in [&pack.pack_path, &pack.index_path] {
+                last_modified = last_modified.max(fs::metadata(path)?.modified()?);
+            }
+        }
+        if is_not_modified(request, last_modified) {
+            return Ok(status_response(304));
+        }
+
+        let host = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Host"))
+            .map(|h| h.value.as_str().to_owned())
+            .unwrap_or_else(|| self.bind.clone());
+        let mut index = RemoteIndex::new(format!("http://{host}{REMOTE_INDEX_PATH}"));
+        for pack in &packs {
+            index.push_pack(RemotePack {
+                index_checksum: self.checksum(&pack.index_path)?,
+                pack_checksum: self.checksum(&pack.pack_path)?,
+                url: format!("{}{}", &PACKS_PATH[1..], pack.file_name),
+                mirror_urls: vec![],
+            });
+        }
+
+        let mut body = vec![];
+        index.write(&mut body)?;
+        Ok(Response::from_data(body)
+            .with_header(header("Content-Type", "text/plain"))
+            .with_header(header("Last-Modified", &format_http_date(last_modified)))
+            .boxed())
+    }
+
+    fn upload_response(
+        &self,
+        request: &mut Request,
+        file_name: &str,
+    ) -> Result<HttpResponse, Box<dyn Error>> {
+        if let Some(response) = self.check_push_token(request) {
+            return Ok(response);
+        }
+
+        match self.repo.write_pack_file(
+            file_name,
+            request.as_reader(),
+            self.max_pack_size,
+            self.max_index_size,
+        ) {
+            Ok(()) => {
+                status!("Received {file_name}");
+                Ok(status_response(201))
+            }
+            Err(
+                e @ (ElfshakerError::IdError(_)
+                | ElfshakerError::PackError(_)
+                | ElfshakerError::BadSignature(_)),
+            ) => {
+                warn!("Rejected {}: {}", file_name, e);
+                Ok(status_response(400))
+            }
+            Err(e @ ElfshakerError::MissingExternalPacks(..)) => {
+                warn!("Rejected {}: {}", file_name, e);
+                Ok(status_response(409))
+            }
+            Err(e @ ElfshakerError::FileTooLarge(..)) => {
+                warn!("Rejected {}: {}", file_name, e);
+                Ok(status_response(413))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the error response to the upload, if it does not have the push
+    /// token.
+    fn check_push_token(&self, request: &Request) -> Option<HttpResponse> {
+        let expected = match &self.push_token {
+            Some(token) => format!("Bearer {token}"),
+            None => return Some(status_response(405)),
+        };
+        let authorized = request.headers().iter().any(|h| {
+            h.field.equiv("Authorization")
+                && constant_time_eq(h.value.as_str().as_bytes(), expected.as_bytes())
+        });
+        (!authorized).then(|| status_response(401))
+    }
+
+    fn cache_response(
+        &self,
+        request: &mut Request,
+        method: &str,
+        key: &str,
+    ) -> Result<HttpResponse, Box<dyn Error>> {
+        let result = match method {
+            "GET" | "HEAD" => self.repo.read_build_cache_entry(key).map(|buf| match buf {
+                Some(buf) => Response::from_data(buf)
+                    .with_header(header("Content-Type", "application/octet-stream"))
+                    .boxed(),
+                None => status_response(404),
+            }),
+            "PUT" => {
+                if let Some(response) = self.check_push_token(request) {
+                    return Ok(response);
+                }
+                // The entries are read to memory, so their size is limited.
+                let mut buf = vec![];
+                request
+                    .as_reader()
+                    .take(self.max_cache_entry_size + 1)
+                    .read_to_end(&mut buf)?;
+                if buf.len() as u64 > self.max_cache_entry_size {
+                    warn!(
+                        "Rejected {} {}: the entry is larger than {} bytes",
+                        method, key, self.max_cache_entry_size
+                    );
+                    return Ok(status_response(413));
+                }
+                let _lock = self.cache_lock.lock().unwrap();
+                let mut repo = open_repo_from_cwd(&self.data_dir)?;
+                repo.write_build_cache_entry(key, &buf)
+                    .map(|_| status_response(201))
+            }
+            _ => return Ok(status_response(405)),
+        };
+        match result {
+            Ok(response) => Ok(response),
+            Err(e @ ElfshakerError::BadBuildCacheKey(_)) => {
+                warn!("Rejected {} {}: {}", method, key, e);
+                Ok(status_response(400))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn pack_file_response(
+        &self,
+        request: &Request,
+        file_name: &str,
+    ) -> Result<HttpResponse, Box<dyn Error>> {
+        // Clients fetch the pack index from the URL of the pack + ".idx", and
+        // its signature from the URL of the pack + ".sig".
+        let path = self.served_packs()?.into_iter().find_map(|pack| {
+            if file_name == pack.file_name {
+                Some(pack.pack_path)
+            } else if pack.index_path.file_name() == Some(OsStr::new(file_name)) {
+                Some(pack.index_path)
+            } else if pack.signature_path.file_name() == Some(OsStr::new(file_name)) {
+                Some(pack.signature_path).filter(|path| path.exists())
+            } else {
+                None
+            }
+        });
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(status_response(404)),
+        };
+
+        let mut file = fs::File::open(path)?;
+        let metadata = file.metadata()?;
+        let last_modified = metadata.modified()?;
+        if is_not_modified(request, last_modified) {
+            return Ok(status_response(304));
+        }
+
+        // Clients fetch only some frames of the packs with range requests.
+        // Requests for several ranges are answered with the whole file.
+        let range = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Range") && !h.value.as_str().contains(','))
+            .map(|h| parse_range(h.value.as_str(), metadata.len()));
+        let response = match range {
+            Some(Some(range)) => {
+                file.seek(SeekFrom::Start(range.start))?;
+                let length = range.end - range.start;
+                Response::new(
+                    StatusCode(206),
+                    vec![header(
+                        "Content-Range",
+                        &format!("bytes {}-{}/{}", range.start, range.end - 1, metadata.len()),
+                    )],
+                    Box::new(file.take(length)) as Box<dyn Read + Send>,
+                    Some(length as usize),
+                    None,
+                )
+            }
+            Some(None) => {
+                return Ok(status_response(416).with_header(header(
+                    "Content-Range",
+                    &format!("bytes */{}", metadata.len()),
+                )))
+            }
+            None => Response::from_file(file).boxed(),
+        };
+        Ok(response
+            .with_header(header("Accept-Ranges", "bytes"))
+            .with_header(header("Content-Type", "application/octet-stream"))
+            .with_header(header("Last-Modified", &format_http_date(last_modified))))
+    }
+}
+
+type HttpResponse = Response<Box<dyn std::io::Read + Send>>;
+
+fn status_response(status: u16) -> HttpResponse {
+    Response::empty(StatusCode(status)).boxed()
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("invalid header")
+}
+
+/// Compares the byte strings in a time which only depends on their lengths, so
+/// that the push token can't be guessed from the response times.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parses the value of a Range header with a single range of bytes (`a-b`,
+/// `a-` or `-n`) of a file of the specified size. Returns [`None`] if the
+/// range is not satisfiable (or not supported).
+fn parse_range(value: &str, size: u64) -> Option<Range<u64>> {
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let range = match (start.trim(), end.trim()) {
+        ("", suffix) => size.saturating_sub(suffix.parse().ok()?)..size,
+        (start, "") => start.parse().ok()?..size,
+        (start, end) => {
+            start.parse().ok()?..std::cmp::min(end.parse::<u64>().ok()?.saturating_add(1), size)
+        }
+    };
+    (range.start < range.end).then_some(range)
+}
+
+/// Checks whether the resource was modified since the time in the
+/// If-Modified-Since header of the request (if any). HTTP dates only have a
+/// precision of seconds.
+fn is_not_modified(request: &Request, last_modified: SystemTime) -> bool {
+    let if_modified_since = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("If-Modified-Since"))
+        .and_then(|h| DateTime::parse_from_rfc2822(h.value.as_str()).ok());
+    match (
+        if_modified_since,
+        last_modified.duration_since(SystemTime::UNIX_EPOCH),
+    ) {
+        (Some(since), Ok(modified)) => modified.as_secs() as i64 <= since.timestamp(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_works() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some(0..10));
+        assert_eq!(parse_range("bytes=90-", 100), Some(90..100));
+        assert_eq!(parse_range("bytes=-10", 100), Some(90..100));
+        assert_eq!(parse_range("bytes=50-200", 100), Some(50..100));
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 100),
+            Some(0..100)
+        );
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=9-0", 100), None);
+        assert_eq!(parse_range("items=0-9", 100), None);
+    }
+}
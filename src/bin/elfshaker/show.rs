@@ -2,12 +2,9 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 use clap::{App, Arg, ArgMatches};
-use rand::RngCore;
-use std::{collections::HashMap, error::Error};
+use std::{collections::HashMap, error::Error, ffi::OsStr, io::Write};
 
 use super::utils::open_repo_from_cwd;
-use elfshaker::repo::fs::open_file;
-use elfshaker::repo::ExtractOptions;
 
 pub(crate) const SUBCOMMAND: &str = "show";
 
@@ -16,8 +13,15 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let snapshot = matches.value_of("snapshot").unwrap();
     let paths: Vec<_> = matches.values_of_os("path").unwrap().collect();
 
-    let mut repo = open_repo_from_cwd(data_dir)?;
+    let repo = open_repo_from_cwd(data_dir)?;
     let snapshot = repo.find_snapshot(snapshot)?;
+    // Fail-fast, without loading the index, if the pack surely doesn't contain
+    // some of the paths.
+    for path in &paths {
+        if !repo.may_contain_path(snapshot.pack(), path)? {
+            return Err("Some of the paths did not match files in the snapshot!".into());
+        }
+    }
     let pack_index = repo.load_index(snapshot.pack())?;
 
     let entries: HashMap<_, _> = pack_index
@@ -41,31 +45,52 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         Some(e) => e,
         None => return Err("Some of the paths did not match files in the snapshot!".into()),
     };
+    // Each object is read once, even if its path is repeated.
+    let mut remaining: HashMap<&OsStr, usize> = HashMap::new();
+    for path in &paths {
+        *remaining.entry(path).or_default() += 1;
+    }
+    let mut unique_entries = selected_entries;
+    unique_entries.sort_by(|a, b| a.path.cmp(&b.path));
+    unique_entries.dedup_by(|a, b| a.path == b.path);
 
-    let temp_dir = {
-        let mut bytes = [0u8; 32];
-        rand::thread_rng().fill_bytes(&mut bytes);
-        hex::encode(bytes)
-    };
-    let temp_dir = std::env::temp_dir().join(temp_dir);
-
-    let mut do_extract = || -> Result<(), Box<dyn Error>> {
-        let mut opts = ExtractOptions::default();
-        opts.set_verify(true);
-
-        repo.extract_entries(snapshot.pack(), &selected_entries, &temp_dir, opts)?;
-
-        // Dump the contents of all entries to stdout.
-        for e in &selected_entries {
-            let path = temp_dir.join(&e.path);
-            std::io::copy(&mut open_file(path)?, &mut std::io::stdout())?;
+    // Objects are read in pack order. Each one is written as soon as the ones
+    // requested before it on the command line are, so only the objects read
+    // before their turn are buffered.
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut pending: HashMap<&OsStr, Vec<u8>> = HashMap::new();
+    let mut next = 0;
+    repo.read_entries(snapshot.pack(), &unique_entries, true, |entry, buf| {
+        let path = remaining
+            .get_key_value(entry.path.as_os_str())
+            .map(|(path, _)| *path)
+            .expect("read an entry which was not requested");
+        if paths.get(next) != Some(&path) {
+            pending.insert(path, buf.to_vec());
+            return Ok(());
+        }
+        stdout.write_all(buf)?;
+        next += 1;
+        let count = remaining.get_mut(path).unwrap();
+        *count -= 1;
+        if *count > 0 {
+            pending.insert(path, buf.to_vec());
+        }
+        // Write the buffered objects which are now next.
+        while let Some(buf) = paths.get(next).and_then(|path| pending.get(path)) {
+            stdout.write_all(buf)?;
+            let path = paths[next];
+            next += 1;
+            let count = remaining.get_mut(path).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                pending.remove(path);
+            }
         }
         Ok(())
-    };
-
-    let extract_result = do_extract();
-    std::fs::remove_dir_all(temp_dir)?;
-    extract_result?;
+    })?;
+    stdout.flush()?;
 
     Ok(())
 }
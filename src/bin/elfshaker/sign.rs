@@ -0,0 +1,74 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{error::Error, fs, path::Path, str::FromStr};
+
+use super::utils::{open_repo_from_cwd, value_or_config};
+use elfshaker::repo::{PackId, SigningKey};
+
+pub(crate) const SUBCOMMAND: &str = "sign";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = Path::new(matches.value_of("data_dir").unwrap());
+
+    if let Some(path) = matches.value_of("generate_key") {
+        let path = Path::new(path);
+        let key = SigningKey::generate();
+        key.save(path)?;
+        let public_key_path = format!("{}.pub", path.display());
+        fs::write(&public_key_path, format!("{}\n", key.public_key()))?;
+        status!(
+            "Wrote the secret key to {} and the public key to {}",
+            path.display(),
+            public_key_path
+        );
+        println!("{}", key.public_key());
+        return Ok(());
+    }
+
+    let key_path = value_or_config(matches, "key", "sign.key")
+        .ok_or("Specify the secret key with --key (or sign.key)")?;
+    let key = SigningKey::load(Path::new(key_path))?;
+    let repo = open_repo_from_cwd(data_dir)?;
+    for pack in matches.values_of("pack").unwrap() {
+        let pack_id = PackId::from_str(pack)?;
+        repo.sign_pack(&pack_id, &key)?;
+        status!("Signed {} with {}", pack_id, key.public_key());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Signs packs with an Ed25519 key, to the .pack.sig files next to them, \
+             which are served and pushed with the packs.",
+        )
+        .arg(
+            Arg::with_name("pack")
+                .multiple(true)
+                .index(1)
+                .required_unless("generate_key")
+                .help("The packs to sign."),
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .takes_value(true)
+                .value_name("file")
+                .help("The file of the secret key (default: sign.key in the configuration)."),
+        )
+        .arg(
+            Arg::with_name("generate_key")
+                .long("generate-key")
+                .takes_value(true)
+                .value_name("file")
+                .conflicts_with_all(&["pack", "key"])
+                .help(
+                    "Generates a new key, writing the secret key to <file> and the public \
+                     key to <file>.pub, and prints the public key.",
+                ),
+        )
+}
@@ -0,0 +1,163 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use log::info;
+use std::{collections::HashSet, error::Error, ops::ControlFlow, str::FromStr};
+
+use super::pack::{compression_args, parse_pack_options};
+use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::packidx::PackIndex;
+use elfshaker::repo::{PackId, SnapshotId};
+
+pub(crate) const SUBCOMMAND: &str = "split-pack";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let pack = matches.value_of("pack").unwrap();
+    let parts: usize = matches.value_of("parts").unwrap().parse()?;
+    let keep_pack = matches.is_present("keep-pack");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+    let pack_id = repo
+        .is_pack(pack)?
+        .ok_or_else(|| format!("Pack {pack} not found!"))?;
+    if repo.is_pack_loose(&pack_id) {
+        return Err("Loose packs contain a single snapshot and cannot be split!".into());
+    }
+    // Fail before splitting if the pack can't be deleted.
+    if !keep_pack {
+        repo.check_pack_unreferenced(&pack_id)?;
+    }
+
+    let index = repo.load_index(&pack_id)?;
+    let tags = index.snapshot_tags();
+    if parts < 2 || parts > tags.len() {
+        return Err(format!(
+            "Cannot split {} snapshot(s) into {} pack(s)! \
+            The number of parts must be between 2 and the number of snapshots.",
+            tags.len(),
+            parts
+        )
+        .into());
+    }
+
+    let prefix = matches.value_of("prefix").unwrap_or(pack);
+    let mut outputs = vec![];
+    for i in 1..=parts {
+        let output = PackId::from_str(&format!("{prefix}-{i}"))?;
+        if repo.is_pack(&output.to_string())?.is_some() {
+            return Err(format!("The pack {output} already exists!").into());
+        }
+        outputs.push(output);
+    }
+
+    let boundaries = split_boundaries(&index, parts)?;
+    let mut start = 0;
+    for (output, &end) in outputs.iter().zip(&boundaries) {
+        let snapshots: Vec<_> = tags[start..end]
+            .iter()
+            .map(|tag| SnapshotId::new(pack_id.clone(), tag))
+            .collect::<Result<_, _>>()?;
+        start = end;
+
+        // Over-estimates the size of the part, which is only used as a hint.
+        let opts = parse_pack_options(matches, index.object_size_total() / parts as u64)?;
+        status!(
+            "Packing {} snapshot(s) from {} into {}...",
+            snapshots.len(),
+            pack_id,
+            output
+        );
+        let reporter = create_percentage_print_reporter("Compressing objects", 5);
+        repo.create_pack_from_snapshots(output, &snapshots, &opts, &reporter)?;
+    }
+
+    if let (Some(head), _) = repo.read_head()? {
+        if *head.pack() == pack_id {
+            let position = tags.iter().position(|tag| tag == head.tag()).unwrap();
+            let output = outputs
+                .iter()
+                .zip(&boundaries)
+                .find_map(|(output, &end)| (position < end).then_some(output))
+                .unwrap();
+            info!("Updating HEAD to point to {}...", output);
+            repo.update_head(&SnapshotId::new(output.clone(), head.tag())?)?;
+        }
+    }
+
+    if !keep_pack {
+        status!("Deleting {pack_id}...");
+        repo.delete_pack(&pack_id)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Splits a pack into several smaller packs, along snapshot boundaries. \
+            The snapshots are kept in order, and each part contains roughly the \
+            same amount of new data. The source pack is deleted afterwards.",
+        )
+        .arg(
+            Arg::with_name("pack")
+                .required(true)
+                .index(1)
+                .help("The pack to split."),
+        )
+        .arg(
+            Arg::with_name("parts")
+                .required(true)
+                .index(2)
+                .help("The number of packs to create."),
+        )
+        .arg(
+            Arg::with_name("prefix")
+                .long("prefix")
+                .takes_value(true)
+                .value_name("name")
+                .help("Names the new packs <name>-1, <name>-2, etc. Defaults to the name of the source pack."),
+        )
+        .arg(
+            Arg::with_name("keep-pack")
+                .long("keep-pack")
+                .help("Keeps the source pack, instead of deleting it."),
+        )
+        .args(&compression_args())
+}
+
+/// Divides the snapshots of the pack into `parts` non-empty ranges, such that
+/// each range introduces roughly the same number of bytes of objects not used
+/// by the snapshots before it. Returns the (exclusive) end of each range.
+fn split_boundaries(index: &PackIndex, parts: usize) -> Result<Vec<usize>, Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    let mut cumulative_sizes = vec![];
+    let mut total = 0;
+    index.for_each_snapshot(|_, entries| {
+        for entry in entries {
+            if seen.insert(entry.checksum) {
+                total += entry.object_metadata.size;
+            }
+        }
+        cumulative_sizes.push(total);
+        ControlFlow::<(), ()>::Continue(())
+    })?;
+
+    let snapshot_count = cumulative_sizes.len();
+    let mut boundaries = vec![];
+    let mut end = 0;
+    for part in 1..parts {
+        let target = (total as u128 * part as u128 / parts as u128) as u64;
+        let candidate = cumulative_sizes
+            .iter()
+            .position(|&size| size >= target)
+            .map_or(snapshot_count, |i| i + 1);
+        // Keep at least one snapshot for each of the parts.
+        end = candidate.max(end + 1).min(snapshot_count - (parts - part));
+        boundaries.push(end);
+    }
+    boundaries.push(snapshot_count);
+    Ok(boundaries)
+}
@@ -0,0 +1,270 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    io::{self, Write},
+    ops::ControlFlow,
+};
+
+use super::utils::{format_size, open_repo_from_cwd};
+use elfshaker::packidx::ObjectChecksum;
+use elfshaker::repo::{PackId, Repository};
+
+pub(crate) const SUBCOMMAND: &str = "stats";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let top: usize = matches.value_of("top").unwrap().parse()?;
+    let json = matches.is_present("json");
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    let stats = compute_stats(&repo, matches.values_of("pack"), top)?;
+
+    if json {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        serde_json::to_writer_pretty(&mut stdout, &stats)?;
+        writeln!(stdout)?;
+    } else {
+        print_stats(&stats);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Prints storage statistics for each pack and for the whole repository: \
+            the number of objects, the unique and referenced sizes of the objects, \
+            the size on disk, the deduplication and compression ratios, and the \
+            largest objects.",
+        )
+        .arg(
+            Arg::with_name("pack")
+                .multiple(true)
+                .index(1)
+                .help("Only considers the specified packs. Defaults to all packs."),
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("10")
+                .help("The number of largest objects to print."),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Prints the statistics as a JSON object."),
+        )
+}
+
+/// The storage statistics of a set of objects.
+#[derive(Serialize, Default)]
+struct Stats {
+    snapshots: usize,
+    objects: usize,
+    /// The total size of the distinct objects.
+    unique_size: u64,
+    /// The total size of the files in all snapshots.
+    referenced_size: u64,
+    /// The size of the files on disk.
+    stored_size: u64,
+}
+
+#[derive(Serialize)]
+struct PackStats {
+    pack: String,
+    #[serde(flatten)]
+    stats: Stats,
+    /// The compression parameters the pack was created with, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<CompressionStats>,
+}
+
+#[derive(Serialize)]
+struct CompressionStats {
+    codec: &'static str,
+    level: i32,
+    window_log: u32,
+}
+
+#[derive(Serialize)]
+struct ObjectRow {
+    checksum: String,
+    size: u64,
+    pack: String,
+    /// One of the paths at which the object is found.
+    path: String,
+}
+
+#[derive(Serialize)]
+struct RepositoryStats {
+    packs: Vec<PackStats>,
+    total: Stats,
+    largest_objects: Vec<ObjectRow>,
+}
+
+fn compute_stats<'a>(
+    repo: &Repository,
+    packs: Option<impl Iterator<Item = &'a str>>,
+    top: usize,
+) -> Result<RepositoryStats, Box<dyn Error>> {
+    let pack_ids = match packs {
+        Some(packs) => packs
+            .map(|pack| {
+                repo.is_pack(pack)?
+                    .ok_or_else(|| format!("Pack {pack} not found!").into())
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+        None => {
+            let mut pack_ids = repo.packs()?;
+            pack_ids.sort_by_key(|p| p.to_string());
+            pack_ids
+        }
+    };
+
+    let mut total = Stats::default();
+    let mut pack_stats = vec![];
+    // The size, pack and a path of each distinct object in the repository.
+    let mut objects: HashMap<ObjectChecksum, (u64, PackId, String)> = HashMap::new();
+    // Loose objects are shared between the loose packs, only count them once.
+    let mut loose_objects = HashSet::new();
+
+    for pack_id in pack_ids {
+        let index = repo.load_index(&pack_id)?;
+        let is_loose = repo.is_pack_loose(&pack_id);
+        let mut stats = Stats {
+            snapshots: index.snapshot_tags().len(),
+            objects: index.object_checksums().len(),
+            unique_size: index.object_size_total(),
+            ..Default::default()
+        };
+
+        index.for_each_snapshot(|_, entries| {
+            for entry in entries {
+                stats.referenced_size += entry.object_metadata.size;
+                objects.entry(*entry.checksum).or_insert_with(|| {
+                    (
+                        entry.object_metadata.size,
+                        pack_id.clone(),
+                        entry.path.to_string_lossy().into_owned(),
+                    )
+                });
+            }
+            ControlFlow::<(), ()>::Continue(())
+        })?;
+
+        if is_loose {
+            for checksum in index.object_checksums() {
+                let size = fs::metadata(repo.loose_object_path(checksum)).map_or(0, |m| m.len());
+                stats.stored_size += size;
+                if loose_objects.insert(*checksum) {
+                    total.stored_size += size;
+                }
+            }
+        } else {
+            stats.stored_size = repo.get_pack_path(&pack_id).metadata()?.len();
+            total.stored_size += stats.stored_size;
+        }
+        stats.stored_size += fs::metadata(repo.get_pack_index_path(&pack_id))?.len();
+        total.stored_size += fs::metadata(repo.get_pack_index_path(&pack_id))?.len();
+
+        total.snapshots += stats.snapshots;
+        total.referenced_size += stats.referenced_size;
+        pack_stats.push(PackStats {
+            pack: pack_id.to_string(),
+            stats,
+            compression: index.compression().map(|c| CompressionStats {
+                codec: c.codec.name(),
+                level: c.level,
+                window_log: c.window_log,
+            }),
+        });
+    }
+
+    total.objects = objects.len();
+    total.unique_size = objects.values().map(|(size, _, _)| size).sum();
+
+    let mut largest_objects: Vec<_> = objects.into_iter().collect();
+    largest_objects.sort_by(|(c1, (s1, _, _)), (c2, (s2, _, _))| s2.cmp(s1).then(c1.cmp(c2)));
+    let largest_objects = largest_objects
+        .into_iter()
+        .take(top)
+        .map(|(checksum, (size, pack_id, path))| ObjectRow {
+            checksum: hex::encode(checksum),
+            size,
+            pack: pack_id.to_string(),
+            path,
+        })
+        .collect();
+
+    Ok(RepositoryStats {
+        packs: pack_stats,
+        total,
+        largest_objects,
+    })
+}
+
+/// Formats the ratio between two sizes, e.g. "3.25x".
+fn format_ratio(numerator: u64, denominator: u64) -> String {
+    if denominator == 0 {
+        return "-".to_owned();
+    }
+    format!("{:.2}x", numerator as f64 / denominator as f64)
+}
+
+fn print_pack_stats(name: &str, stats: &Stats, compression: Option<&CompressionStats>) {
+    println!("{name}");
+    println!("  snapshots:         {}", stats.snapshots);
+    println!("  objects:           {}", stats.objects);
+    println!("  unique size:       {}", format_size(stats.unique_size));
+    println!(
+        "  referenced size:   {}",
+        format_size(stats.referenced_size)
+    );
+    println!("  stored size:       {}", format_size(stats.stored_size));
+    println!(
+        "  dedup ratio:       {}",
+        format_ratio(stats.referenced_size, stats.unique_size)
+    );
+    println!(
+        "  compression ratio: {}",
+        format_ratio(stats.unique_size, stats.stored_size)
+    );
+    if let Some(c) = compression {
+        println!(
+            "  compression:       {} (level {}, window log {})",
+            c.codec, c.level, c.window_log
+        );
+    }
+}
+
+fn print_stats(stats: &RepositoryStats) {
+    for pack in &stats.packs {
+        print_pack_stats(&pack.pack, &pack.stats, pack.compression.as_ref());
+        println!();
+    }
+    print_pack_stats("total", &stats.total, None);
+
+    if !stats.largest_objects.is_empty() {
+        println!();
+        println!("largest objects");
+        for object in &stats.largest_objects {
+            println!(
+                "  {}\t{}\t{}\t{}",
+                object.checksum,
+                format_size(object.size),
+                object.pack,
+                object.path
+            );
+        }
+    }
+}
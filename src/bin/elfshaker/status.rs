@@ -0,0 +1,53 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::error::Error;
+
+use super::utils::{find_files, open_repo_from_cwd};
+
+pub(crate) const SUBCOMMAND: &str = "status";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+
+    let repo = open_repo_from_cwd(data_dir)?;
+    match repo.read_head()?.0 {
+        Some(head) => status!("HEAD is {head}"),
+        None => status!("HEAD is not set"),
+    }
+
+    let diff =
+        repo.compute_work_dir_diff(find_files(matches.is_present("empty-dirs"))?.into_iter())?;
+
+    let mut lines = vec![];
+    for (entry, _) in &diff.modified {
+        lines.push((entry.path.to_string_lossy(), "M"));
+    }
+    for entry in &diff.removed {
+        lines.push((entry.path.to_string_lossy(), "D"));
+    }
+    for entry in &diff.added {
+        lines.push((entry.path.to_string_lossy(), "?"));
+    }
+    lines.sort();
+
+    for (path, status) in lines {
+        println!("{status}\t{path}");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Compares the working directory to the HEAD snapshot and prints the files \
+            which were modified (M), deleted (D) or are not present in HEAD (?).",
+        )
+        .arg(
+            Arg::with_name("empty-dirs")
+                .long("empty-dirs")
+                .help("Also compares the empty directories (see store --empty-dirs)."),
+        )
+}
@@ -1,13 +1,22 @@
 //! SPDX-License-Identifier: Apache-2.0
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
+use chrono::Utc;
 use clap::{App, Arg, ArgMatches};
 use log::error;
-use std::{error::Error, ffi::OsStr, fs, io, path::PathBuf};
-use walkdir::WalkDir;
+use std::{
+    error::Error,
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
 
-use super::utils::open_repo_from_cwd;
-use elfshaker::repo::{PackId, SnapshotId};
+use super::utils::{find_files, open_repo_from_cwd, run_hook, value_or_config};
+use elfshaker::packidx::SnapshotMetadata;
+use elfshaker::repo::watch::Watcher;
+use elfshaker::repo::{PackId, Repository, SnapshotId, StoreOptions};
 
 pub(crate) const SUBCOMMAND: &str = "store";
 
@@ -16,34 +25,159 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let files_from = matches.value_of("files-from");
     let files0_from = matches.value_of("files0-from");
     let snapshot = matches.value_of("snapshot").unwrap();
-    // Use snapshot name as pack name.
-    let pack_id = PathBuf::from(format!("loose/{snapshot}"));
-    let pack_id = PackId::Pack(pack_id.to_str().unwrap().to_owned());
-    let snapshot = SnapshotId::new(pack_id, snapshot)?;
+    let mut metadata = parse_snapshot_metadata(matches)?;
+    let is_git = matches.is_present("git");
 
     if files_from.is_some() && files0_from.is_some() {
         error!("Cannot specify both --files-from and --files0-from!");
         return Err("Invalid options!".into());
     }
 
+    // Like tar --null, --null makes --files-from read NUL-separated paths.
+    let files_from_delim = if matches.is_present("null") {
+        b'\0'
+    } else {
+        b'\n'
+    };
     let files_from_and_delim = files_from
-        .map(|file| (file, b'\n'))
+        .map(|file| (file, files_from_delim))
         .or_else(|| files0_from.map(|file| (file, b'\0')));
 
+    let mut opts = StoreOptions::default();
+    opts.set_record_times(matches.is_present("record-times"));
+    opts.set_xattrs(matches.is_present("xattrs"));
+    opts.set_ignore_cache(matches.is_present("no-cache"));
+
+    fs::create_dir_all(data_dir)?;
+
+    if matches.is_present("watch") {
+        let settle: f64 = matches.value_of("settle").unwrap().parse()?;
+        if !settle.is_finite() || settle < 0.0 {
+            return Err(format!("Invalid --settle '{settle}'!").into());
+        }
+        return watch(
+            data_dir,
+            snapshot,
+            Duration::from_secs_f64(settle),
+            matches.is_present("empty-dirs"),
+            is_git,
+            metadata,
+            opts,
+        );
+    }
+
     let files: Vec<_> = match files_from_and_delim {
         Some(("-", delim)) => read_files_list(std::io::stdin(), delim)?,
         Some((file, delim)) => read_files_list(&*fs::read(file)?, delim)?,
-        _ => find_files(),
+        _ => find_files(matches.is_present("empty-dirs"))?,
     };
 
-    fs::create_dir_all(data_dir)?;
-
     let mut repo = open_repo_from_cwd(data_dir)?;
-    repo.create_snapshot(&snapshot, files.into_iter())?;
+    let snapshot = loose_snapshot_id(snapshot)?;
+    if is_git {
+        add_git_attributes(&mut metadata)?;
+    }
+    run_pre_store_hook(&repo, &snapshot)?;
+    repo.create_snapshot(&snapshot, files.into_iter(), metadata, opts)?;
+
+    Ok(())
+}
 
+/// The ID of the loose snapshot with the specified tag.
+fn loose_snapshot_id(tag: &str) -> Result<SnapshotId, Box<dyn Error>> {
+    // Use snapshot name as pack name.
+    let pack_id = PathBuf::from(format!("loose/{tag}"));
+    let pack_id = PackId::Pack(pack_id.to_str().unwrap().to_owned());
+    Ok(SnapshotId::new(pack_id, tag)?)
+}
+
+/// Records the commit checked out in the enclosing git repository, and whether
+/// its tracked files are modified, as the `commit` and `dirty` attributes of
+/// the snapshot, unless they are set explicitly with --meta.
+fn add_git_attributes(metadata: &mut SnapshotMetadata) -> Result<(), Box<dyn Error>> {
+    let commit = run_git(&["rev-parse", "--verify", "HEAD"])?;
+    let changes = run_git(&["status", "--porcelain", "--untracked-files=no"])?;
+    let attributes = &mut metadata.attributes;
+    attributes.entry("commit".to_owned()).or_insert(commit);
+    attributes
+        .entry("dirty".to_owned())
+        .or_insert_with(|| (!changes.is_empty()).to_string());
     Ok(())
 }
 
+/// Runs git in the working directory, and returns its (trimmed) output.
+fn run_git(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("git {} failed: {}", args[0], stderr.trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Runs the pre-store hook, which can refuse to store the snapshot.
+fn run_pre_store_hook(repo: &Repository, snapshot: &SnapshotId) -> Result<(), Box<dyn Error>> {
+    run_hook(
+        repo,
+        "pre-store",
+        &[("ELFSHAKER_SNAPSHOT", snapshot.to_string())],
+    )
+}
+
+/// Stores a snapshot of the working directory each time it settles after
+/// changes, until interrupted. The snapshots are named after `template`,
+/// where `%n` is replaced by the first number which gives an unused tag and
+/// `%d` by the current date and time.
+fn watch(
+    data_dir: &Path,
+    template: &str,
+    settle: Duration,
+    empty_dirs: bool,
+    is_git: bool,
+    metadata: SnapshotMetadata,
+    opts: StoreOptions,
+) -> Result<(), Box<dyn Error>> {
+    if !template.contains("%n") && !template.contains("%d") {
+        error!("The snapshot tag must contain %n or %d with --watch!");
+        return Err("Invalid options!".into());
+    }
+    let work_dir = std::env::current_dir()?.canonicalize()?;
+    let mut watcher = Watcher::new(&work_dir, &data_dir.canonicalize()?)?;
+    println!("Watching for changes...");
+    let mut n = 1;
+    loop {
+        watcher.wait_for_changes(settle)?;
+        // The repository is only opened while storing, so that e.g. gc can
+        // run in the meantime.
+        let mut repo = open_repo_from_cwd(data_dir)?;
+        let snapshot = loop {
+            let tag = template
+                .replace("%n", &n.to_string())
+                .replace("%d", &Utc::now().format("%Y%m%dT%H%M%SZ").to_string());
+            if !template.contains("%n") || repo.find_snapshot(&tag).is_err() {
+                break loose_snapshot_id(&tag)?;
+            }
+            n += 1;
+        };
+        let files = find_files(empty_dirs)?;
+        // The commit may change between the snapshots.
+        let mut metadata = metadata.clone();
+        if is_git {
+            add_git_attributes(&mut metadata)?;
+        }
+        // A snapshot refused by the hook is skipped, until the next changes.
+        if let Err(e) = run_pre_store_hook(&repo, &snapshot) {
+            error!("Skipped {}: {}", snapshot, e);
+            continue;
+        }
+        repo.create_snapshot(&snapshot, files.into_iter(), metadata, opts.clone())?;
+        println!("Stored {snapshot}");
+    }
+}
+
 pub(crate) fn get_app() -> App<'static, 'static> {
     App::new(SUBCOMMAND)
         .about(
@@ -71,6 +205,104 @@ pub(crate) fn get_app() -> App<'static, 'static> {
                 .value_name("file")
                 .help("Reads the NUL-separated (ASCII \\0) list of files to include in the snapshot from the specified file. '-' is taken to mean stdin."),
         )
+        .arg(
+            Arg::with_name("null")
+                .long("null")
+                .requires("files-from")
+                .help("Reads the list of files from --files-from as NUL-separated (ASCII \\0) paths, like tar --null (e.g. from find -print0)."),
+        )
+        .arg(
+            Arg::with_name("empty-dirs")
+                .long("empty-dirs")
+                .help("Also stores the empty directories, which are recreated when the snapshot is extracted."),
+        )
+        .arg(
+            Arg::with_name("record-times")
+                .long("record-times")
+                .help("Records the modification times of the files, which are restored by extract --preserve-times."),
+        )
+        .arg(
+            Arg::with_name("xattrs")
+                .long("xattrs")
+                .help("Records the extended attributes of the files, which are restored by extract --xattrs."),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .conflicts_with_all(&["files-from", "files0-from"])
+                .help("Keeps running, and stores a new snapshot each time the working directory settles after changes. \
+                    The snapshot tag is a template, where %n is replaced by the first number which gives an unused tag, \
+                    and %d by the current date and time (e.g. build-%n)."),
+        )
+        .arg(
+            Arg::with_name("settle")
+                .takes_value(true)
+                .long("settle")
+                .value_name("seconds")
+                .default_value("2")
+                .help("With --watch, the time without changes after which the working directory is considered settled."),
+        )
+        .arg(
+            Arg::with_name("git")
+                .long("git")
+                .help("Records the commit checked out in the enclosing git repository, and whether its tracked files are modified, \
+                    as the commit and dirty attributes of the snapshot (see find --commit)."),
+        )
+        .arg(
+            Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Reads all the files, instead of reusing the checksums of the files whose size and times did not change since the last store."),
+        )
+        .args(&metadata_args())
+}
+
+/// Returns the arguments used to attach [`SnapshotMetadata`] to a new snapshot.
+pub(crate) fn metadata_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("message")
+            .takes_value(true)
+            .short("m")
+            .long("message")
+            .value_name("message")
+            .help("Attaches a free-form message to the snapshot."),
+        Arg::with_name("meta")
+            .takes_value(true)
+            .long("meta")
+            .value_name("key=value")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Attaches a key/value pair to the snapshot (e.g. commit=abc123). Can be specified multiple times."),
+        Arg::with_name("author")
+            .takes_value(true)
+            .long("author")
+            .value_name("name")
+            .help("Records who (or which builder) stored the snapshot (defaults to the store.author configuration)."),
+    ]
+}
+
+/// Parses the arguments returned by [`metadata_args`].
+pub(crate) fn parse_snapshot_metadata(
+    matches: &ArgMatches,
+) -> Result<SnapshotMetadata, Box<dyn Error>> {
+    Ok(SnapshotMetadata {
+        message: matches.value_of("message").map(|s| s.to_owned()),
+        attributes: matches
+            .values_of("meta")
+            .into_iter()
+            .flatten()
+            .map(parse_attribute)
+            .collect::<Result<_, _>>()?,
+        // The time is recorded when the snapshot is written.
+        created: None,
+        author: value_or_config(matches, "author", "store.author").map(|s| s.to_owned()),
+    })
+}
+
+fn parse_attribute(s: &str) -> Result<(String, String), Box<dyn Error>> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("Invalid --meta '{s}', expected <key>=<value>!").into()),
+    }
 }
 
 #[cfg(unix)]
@@ -97,13 +329,3 @@ fn read_files_list(mut reader: impl io::Read, separator: u8) -> io::Result<Vec<P
         })
         .collect()
 }
-
-fn find_files() -> Vec<PathBuf> {
-    WalkDir::new(".")
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file() || e.file_type().is_symlink())
-        .map(|e| e.path().into())
-        .collect()
-}
@@ -0,0 +1,89 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use log::warn;
+use std::error::Error;
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::packidx::PackError;
+use elfshaker::repo::Error as RepoError;
+
+pub(crate) const SUBCOMMAND: &str = "tag";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    if let Some(names) = matches.values_of("delete") {
+        for name in names {
+            if repo.delete_ref(name)? {
+                status!("Deleted the ref {name}");
+            } else {
+                return Err(format!("The ref {name} does not exist!").into());
+            }
+        }
+        return Ok(());
+    }
+
+    let name = match matches.value_of("name") {
+        Some(name) => name,
+        None => {
+            for (name, snapshot) in repo.refs()? {
+                println!("{name} {snapshot}");
+            }
+            return Ok(());
+        }
+    };
+
+    let snapshot = match matches.value_of("snapshot") {
+        Some(snapshot) => repo.find_snapshot(snapshot)?,
+        None => repo
+            .read_head()?
+            .0
+            .ok_or("There is no HEAD to tag! Specify the snapshot.")?,
+    };
+    match repo.find_pack_with_snapshot(name) {
+        Err(RepoError::PackError(PackError::SnapshotNotFound(_))) => {}
+        _ => warn!("{name} is also the tag of a snapshot, which takes precedence over the ref"),
+    }
+
+    match repo.set_ref(name, &snapshot)? {
+        Some(old) if old != snapshot => status!("Updated the ref {name} to {snapshot} (was {old})"),
+        Some(_) => status!("The ref {name} already points to {snapshot}"),
+        None => status!("Created the ref {name}, pointing to {snapshot}"),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Points the ref (e.g. latest or release-1.0) to the snapshot (HEAD by default), \
+            so that the ref can be used instead of the snapshot by the other commands. \
+            Lists the refs if none is specified.",
+        )
+        .arg(
+            Arg::with_name("name")
+                .index(1)
+                .help("The name of the ref to create or update."),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .index(2)
+                .requires("name")
+                .help("The snapshot the ref points to (HEAD by default)."),
+        )
+        .arg(
+            Arg::with_name("delete")
+                .short("d")
+                .long("delete")
+                .takes_value(true)
+                .value_name("name")
+                .multiple(true)
+                .conflicts_with_all(&["name", "snapshot"])
+                .help("Deletes the refs."),
+        )
+}
@@ -4,7 +4,8 @@
 use clap::{App, ArgMatches};
 use std::error::Error;
 
-use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use super::utils::{config, create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::repo::REMOTES_DIR;
 
 pub(crate) const SUBCOMMAND: &str = "update";
 
@@ -13,11 +14,27 @@ pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let mut repo = open_repo_from_cwd(data_dir)?;
 
     repo.set_progress_reporter(|msg| create_percentage_print_reporter(msg, 5));
+    // Add the configured remotes which were not added yet.
+    for name in config().subsections("remote") {
+        let Some(url) = config().get(&format!("remote.{name}.url")) else {
+            continue;
+        };
+        let esi_path = repo
+            .data_dir()
+            .join(REMOTES_DIR)
+            .join(format!("{name}.esi"));
+        if !esi_path.exists() {
+            repo.add_remote(name, url)?;
+        }
+    }
     repo.update_remotes()?;
 
     Ok(())
 }
 
 pub(crate) fn get_app() -> App<'static, 'static> {
-    App::new(SUBCOMMAND).about("Updates the remote indexes.")
+    App::new(SUBCOMMAND).about(
+        "Updates the remote indexes. The remotes of the configuration ([remote \"<name>\"] url = <url>) \
+        are added first, if needed.",
+    )
 }
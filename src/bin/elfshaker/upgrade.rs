@@ -0,0 +1,91 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{error::Error, fs};
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::checksum::ChecksumAlgorithm;
+use elfshaker::repo::PackId;
+
+pub(crate) const SUBCOMMAND: &str = "upgrade";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let checksum_algorithm = matches
+        .value_of("checksum-algorithm")
+        .map(str::parse::<ChecksumAlgorithm>)
+        .transpose()?;
+
+    let repo = match checksum_algorithm {
+        Some(algorithm) => {
+            // Allows choosing the algorithm before creating the first snapshot.
+            fs::create_dir_all(data_dir)?;
+            let mut repo = open_repo_from_cwd(data_dir)?;
+            repo.set_default_checksum_algorithm(algorithm)?;
+            repo
+        }
+        None => open_repo_from_cwd(data_dir)?,
+    };
+    let algorithm = repo.default_checksum_algorithm()?;
+
+    let packs = match matches.values_of("pack") {
+        Some(packs) => packs
+            .map(|pack| {
+                repo.is_pack(pack)?
+                    .ok_or_else(|| format!("Pack {pack} not found!").into())
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+        // The indexes of remote packs are replaced when the remote is updated,
+        // so only the local packs are upgraded by default.
+        None => repo
+            .packs()?
+            .into_iter()
+            .filter(|pack_id| {
+                let PackId::Pack(name) = pack_id;
+                repo.is_pack_loose(pack_id) || !name.contains('/')
+            })
+            .collect(),
+    };
+
+    let mut upgraded = 0;
+    for pack_id in &packs {
+        if repo.checksum_algorithm(pack_id)? == algorithm {
+            continue;
+        }
+        status!("Upgrading {pack_id} to {algorithm}...");
+        if repo.upgrade_pack(pack_id)? {
+            upgraded += 1;
+        }
+    }
+    status!("Upgraded {upgraded} pack(s)");
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Converts the pack indexes to the checksum algorithm of the repository \
+            (BLAKE3 unless changed with --checksum-algorithm), recomputing the \
+            checksums of the objects. This also upgrades the packs created by older \
+            versions of elfshaker, which use SHA-1. The .pack files are not modified. \
+            Use gc to clean up the loose objects of the old loose snapshots.",
+        )
+        .arg(
+            Arg::with_name("checksum-algorithm")
+                .long("checksum-algorithm")
+                .takes_value(true)
+                .value_name("algorithm")
+                .possible_values(&["sha1", "sha256", "blake3"])
+                .help(
+                    "Changes the checksum algorithm of the repository, which is used \
+                    for all new snapshots, before converting the packs.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pack").multiple(true).index(1).help(
+                "The packs to upgrade. Defaults to all local packs (not fetched from a remote).",
+            ),
+        )
+}
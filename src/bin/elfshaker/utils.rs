@@ -2,17 +2,293 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 use elfshaker::log::measure;
+use elfshaker::packidx::SnapshotMetadata;
 use elfshaker::progress::ProgressReporter;
-use elfshaker::repo::{Error as RepoError, Repository};
+use elfshaker::repo::remote::{HttpAuth, RemoteIndex};
+use elfshaker::repo::{
+    Config, EncryptionKey, Error as RepoError, IgnoreFile, PublicKey, Repository, SnapshotId,
+    TrustPolicy, HOOKS_DIR, IGNORE_FILE, REMOTES_DIR, REPO_DIR,
+};
 
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeZone, Utc};
+use clap::ArgMatches;
 use lazy_static::lazy_static;
-use log::info;
-use std::io::Write;
-use std::path::Path;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
 use std::sync::{
     atomic::{AtomicIsize, Ordering},
-    Arc,
+    Arc, Mutex, OnceLock,
 };
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+/// The format of the progress reports, set at startup (see `--progress`).
+static PROGRESS_FORMAT: OnceLock<ProgressFormat> = OnceLock::new();
+
+/// The format of the progress reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ProgressFormat {
+    /// Progress bars, or lines of text when stdout is not a terminal.
+    Text,
+    /// JSON objects, one per line, written to stderr.
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown progress format '{s}'!")),
+        }
+    }
+}
+
+/// Sets the format of the progress reports of
+/// [`create_percentage_print_reporter`].
+pub(crate) fn set_progress_format(format: ProgressFormat) {
+    PROGRESS_FORMAT
+        .set(format)
+        .expect("The progress format is only set once!");
+}
+
+/// Whether the progress reports are silenced, set at startup (see `--quiet`).
+static QUIET: OnceLock<bool> = OnceLock::new();
+
+/// Silences the progress reports of [`create_percentage_print_reporter`]
+/// (except the JSON ones, which are requested explicitly).
+pub(crate) fn set_quiet(quiet: bool) {
+    QUIET.set(quiet).expect("Quiet is only set once!");
+}
+
+/// Whether `--quiet` is passed.
+pub(crate) fn is_quiet() -> bool {
+    QUIET.get() == Some(&true)
+}
+
+/// The configuration loaded at startup (see [`load_config`]).
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Loads the configuration of the user and of the repository (see
+/// [`Config::load_layered`]), which sets the defaults of the options.
+pub(crate) fn load_config(data_dir: &Path) -> Result<(), RepoError> {
+    let config = Config::load_layered(data_dir)?;
+    CONFIG
+        .set(config)
+        .expect("The configuration is only loaded once!");
+    Ok(())
+}
+
+/// The configuration loaded at startup.
+pub(crate) fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Returns the value of the argument, if it is specified on the command-line,
+/// otherwise the value of `key` in the configuration, if any, otherwise the
+/// default value of the argument.
+pub(crate) fn value_or_config<'a>(
+    matches: &'a ArgMatches,
+    name: &str,
+    key: &str,
+) -> Option<&'a str> {
+    match config().get(key) {
+        Some(value) if matches.occurrences_of(name) == 0 => Some(value),
+        _ => matches.value_of(name),
+    }
+}
+
+/// Returns whether the flag is specified on the command-line, or enabled
+/// by `key` in the configuration.
+pub(crate) fn flag_or_config(
+    matches: &ArgMatches,
+    name: &str,
+    key: &str,
+) -> Result<bool, RepoError> {
+    Ok(matches.is_present(name) || config().get_bool(key)?.unwrap_or(false))
+}
+
+/// The credentials of the HTTP requests: the ones of the environment variables
+/// (`ELFSHAKER_HTTP_TOKEN`, `ELFSHAKER_HTTP_USER` and `ELFSHAKER_HTTP_PASSWORD`,
+/// and `ELFSHAKER_HTTP_HEADER`), which apply to all the servers and take
+/// precedence, then the ones of the remotes of the configuration (see
+/// [`remote_http_auth`]).
+pub(crate) fn http_auth(data_dir: &Path) -> Result<Vec<HttpAuth>, RepoError> {
+    let env = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let headers = match env("ELFSHAKER_HTTP_HEADER") {
+        Some(headers) => headers
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| parse_header("ELFSHAKER_HTTP_HEADER", line))
+            .collect::<Result<_, _>>()?,
+        None => vec![],
+    };
+    let env_auth = HttpAuth {
+        url_prefix: None,
+        token: env("ELFSHAKER_HTTP_TOKEN"),
+        basic: env("ELFSHAKER_HTTP_USER")
+            .map(|user| (user, env("ELFSHAKER_HTTP_PASSWORD").unwrap_or_default())),
+        headers,
+    };
+
+    let mut auth = vec![];
+    if env_auth != HttpAuth::default() {
+        auth.push(env_auth);
+    }
+    for name in config().subsections("remote") {
+        // The remotes which were cloned are not in the configuration.
+        let url = match config().get(&format!("remote.{name}.url")) {
+            Some(url) => url.to_owned(),
+            None => match RemoteIndex::load(data_dir.join(REMOTES_DIR).join(format!("{name}.esi")))
+            {
+                Ok(remote) => remote.url().to_owned(),
+                Err(_) => continue,
+            },
+        };
+        auth.extend(remote_http_auth(name, &url)?);
+    }
+    Ok(auth)
+}
+
+/// The credentials of the remote in the configuration (`remote.<name>.token`,
+/// `remote.<name>.user` and `remote.<name>.password`, and the repeatable
+/// `remote.<name>.header`), which only apply to the server of its `url`, and
+/// to the HTTP servers of its mirrors (see [`remote_mirrors`]).
+pub(crate) fn remote_http_auth(name: &str, url: &str) -> Result<Vec<HttpAuth>, RepoError> {
+    let key = |value: &str| format!("remote.{name}.{value}");
+    let get = |value: &str| config().get(&key(value)).map(|v| v.to_owned());
+    let header_key = key("header");
+    let headers = config()
+        .get_all(&header_key)
+        .map(|header| parse_header(&header_key, header))
+        .collect::<Result<Vec<_>, _>>()?;
+    let auth = HttpAuth {
+        url_prefix: None,
+        token: get("token"),
+        basic: get("user").map(|user| (user, get("password").unwrap_or_default())),
+        headers,
+    };
+    if auth == HttpAuth::default() {
+        return Ok(vec![]);
+    }
+    let origin = url::Url::parse(url)
+        .map(|url| url.origin())
+        .ok()
+        .filter(|origin| origin.is_tuple())
+        .ok_or_else(|| {
+            RepoError::BadConfig(format!("{}: '{url}' is not an HTTP URL", key("url")))
+        })?;
+    let mut origins = vec![origin];
+    // The mirrors in object storage are authorized by their own credentials.
+    for mirror in remote_mirrors()?.remove(name).unwrap_or_default() {
+        let origin = url::Url::parse(&mirror).unwrap().origin();
+        if origin.is_tuple() && !origins.contains(&origin) {
+            origins.push(origin);
+        }
+    }
+    Ok(origins
+        .into_iter()
+        .map(|origin| HttpAuth {
+            url_prefix: Some(format!("{}/", origin.ascii_serialization())),
+            ..auth.clone()
+        })
+        .collect())
+}
+
+/// The keys trusted to sign the packs of all the remotes (the repeatable
+/// `trust.key`, the public key of `elfshaker sign --generate-key`).
+pub(crate) fn trusted_keys() -> Result<Vec<PublicKey>, RepoError> {
+    parse_keys("trust.key")
+}
+
+/// The keys to decrypt the encrypted packs with (the files of the repeatable
+/// `encryption.key`, and the one of `pack.encryption-key`, which packs are
+/// encrypted with).
+pub(crate) fn encryption_keys() -> Result<Vec<EncryptionKey>, RepoError> {
+    config()
+        .get_all("encryption.key")
+        .chain(config().get("pack.encryption-key"))
+        .map(|path| {
+            EncryptionKey::load(Path::new(path)).map_err(|e| match e {
+                RepoError::Encryption(e) => RepoError::BadConfig(e),
+                e => RepoError::BadConfig(format!("Failed to read the key {path}: {e}")),
+            })
+        })
+        .collect()
+}
+
+/// The trust policies of the remotes in the configuration (the repeatable
+/// `remote.<name>.signer`, the keys which must all sign the packs, and
+/// `remote.<name>.allow-unsigned`), by name of remote.
+pub(crate) fn trust_policies() -> Result<HashMap<String, TrustPolicy>, RepoError> {
+    let mut policies = HashMap::new();
+    for name in config().subsections("remote") {
+        let policy = TrustPolicy {
+            trusted_keys: vec![],
+            required_signers: parse_keys(&format!("remote.{name}.signer"))?,
+            allow_unsigned: config()
+                .get_bool(&format!("remote.{name}.allow-unsigned"))?
+                .unwrap_or(false),
+        };
+        if policy != TrustPolicy::default() {
+            policies.insert(name.to_owned(), policy);
+        }
+    }
+    Ok(policies)
+}
+
+/// Parses the public keys of the repeatable key of the configuration.
+fn parse_keys(key: &str) -> Result<Vec<PublicKey>, RepoError> {
+    config()
+        .get_all(key)
+        .map(|value| {
+            value.parse().map_err(|e| match e {
+                RepoError::BadSignature(e) => RepoError::BadConfig(format!("{key}: {e}")),
+                e => e,
+            })
+        })
+        .collect()
+}
+
+/// The mirrors of the remotes in the configuration (the repeatable
+/// `remote.<name>.mirror`, the URL of the .esi of a copy of the remote), by
+/// name of remote.
+pub(crate) fn remote_mirrors() -> Result<HashMap<String, Vec<String>>, RepoError> {
+    let mut mirrors = HashMap::new();
+    for name in config().subsections("remote") {
+        let key = format!("remote.{name}.mirror");
+        let urls = config()
+            .get_all(&key)
+            .map(|url| match url::Url::parse(url) {
+                Ok(_) => Ok(url.to_owned()),
+                Err(e) => Err(RepoError::BadConfig(format!("{key}: '{url}': {e}"))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !urls.is_empty() {
+            mirrors.insert(name.to_owned(), urls);
+        }
+    }
+    Ok(mirrors)
+}
+
+/// Parses a `<name>: <value>` header.
+fn parse_header(origin: &str, header: &str) -> Result<(String, String), RepoError> {
+    match header.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_owned(), value.trim().to_owned()))
+        }
+        _ => Err(RepoError::BadConfig(format!(
+            "{origin}: expected '<name>: <value>', got '{header}'"
+        ))),
+    }
+}
 
 /// Do not print to `stderr` in case of a panic caused by a broken pipe.
 ///
@@ -106,6 +382,171 @@ pub fn format_size(bytes: u64) -> String {
     unreachable!()
 }
 
+/// Parses a date, which is either relative to now (`<N>s`, `<N>m`, `<N>h`,
+/// `<N>d` or `<N>w`, meaning that long ago), a day (`YYYY-MM-DD`, at midnight
+/// UTC) or an RFC 3339 date and time (e.g. `2022-03-01T12:00:00Z`).
+pub(crate) fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    let units = [
+        ('s', 1),
+        ('m', 60),
+        ('h', 60 * 60),
+        ('d', 24 * 60 * 60),
+        ('w', 7 * 24 * 60 * 60),
+    ];
+    for (unit, seconds) in units {
+        if let Some(Ok(n)) = s.strip_suffix(unit).map(i64::from_str) {
+            return n
+                .checked_mul(seconds)
+                // Duration::seconds panics beyond i64::MAX milliseconds.
+                .filter(|ago| (0..=i64::MAX / 1000).contains(ago))
+                .map(chrono::Duration::seconds)
+                .and_then(|ago| Utc::now().checked_sub_signed(ago))
+                .ok_or_else(|| format!("The date '{s}' is out of range"));
+        }
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap()));
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| {
+            format!("Invalid date '{s}' (expected e.g. 2w, 3d, 2022-03-01 or 2022-03-01T12:00:00Z)")
+        })
+}
+
+/// Formats a date as RFC 3339, in UTC (e.g. `2022-03-01T12:00:00Z`), which
+/// [`parse_date`] accepts.
+pub(crate) fn format_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// The time the snapshot was stored, if it was recorded (see
+/// [`SnapshotMetadata::created`]).
+pub(crate) fn snapshot_time(metadata: &SnapshotMetadata) -> Option<DateTime<Utc>> {
+    metadata
+        .created
+        .and_then(|created| Utc.timestamp_opt(created, 0).single())
+}
+
+/// A snapshot of the history of the repository (see [`snapshot_history`]).
+pub(crate) struct HistoryEntry {
+    pub snapshot: SnapshotId,
+    pub metadata: SnapshotMetadata,
+    /// The time the snapshot was stored, if it was recorded, otherwise the
+    /// time its pack was last written.
+    pub time: DateTime<Utc>,
+    /// The time the pack was written and the position of the snapshot in the
+    /// pack, to order the snapshots stored at the same time (the times are
+    /// recorded in seconds).
+    order: (DateTime<Utc>, usize),
+}
+
+/// Returns the snapshots of all the packs, most recent first.
+pub(crate) fn snapshot_history(repo: &Repository) -> Result<Vec<HistoryEntry>, RepoError> {
+    let mut history = vec![];
+    for pack_id in repo.packs()? {
+        let pack_time: DateTime<Utc> = fs::metadata(repo.get_pack_index_path(&pack_id))?
+            .modified()?
+            .into();
+        let index = repo.load_index_cached(&pack_id)?;
+        for (position, tag) in index.snapshot_tags().iter().enumerate() {
+            let metadata = index.snapshot_metadata(tag).cloned().unwrap_or_default();
+            history.push(HistoryEntry {
+                snapshot: SnapshotId::new(pack_id.clone(), tag)?,
+                time: snapshot_time(&metadata).unwrap_or(pack_time),
+                metadata,
+                order: (pack_time, position),
+            });
+        }
+    }
+    history.sort_by(|a, b| {
+        (b.time, b.order, b.snapshot.to_string()).cmp(&(a.time, a.order, a.snapshot.to_string()))
+    });
+    Ok(history)
+}
+
+/// Changes the current work directory to the one of the repository, which is
+/// the directory passed with `-C`, if any, otherwise the current one. Unless
+/// the data directory is set explicitly (with `--data-dir` or `ELFSHAKER_DATA`)
+/// or `discover` is false, the closest directory (upwards from there) which
+/// contains an `elfshaker_data` directory is used instead, if any, like git
+/// does.
+pub(crate) fn enter_repo_dir(matches: &ArgMatches, discover: bool) -> Result<(), RepoError> {
+    if let Some(dir) = matches.value_of("work_dir") {
+        std::env::set_current_dir(dir)?;
+    }
+    let is_data_dir_explicit =
+        matches.occurrences_of("data_dir") > 0 || std::env::var_os("ELFSHAKER_DATA").is_some();
+    if !discover || is_data_dir_explicit {
+        return Ok(());
+    }
+    let cwd = std::env::current_dir()?;
+    if let Some(repo_dir) = cwd.ancestors().find(|dir| dir.join(REPO_DIR).is_dir()) {
+        if repo_dir != cwd {
+            info!("Using the repository in {}", repo_dir.display());
+            std::env::set_current_dir(repo_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the hook `elfshaker_data/hooks/<name>`, if it exists and is executable,
+/// in the working directory of the repository, with the variables in its
+/// environment, along with ELFSHAKER_HOOK, ELFSHAKER_DATA_DIR and
+/// ELFSHAKER_WORK_DIR. The failure of a `pre-` hook is an error, which aborts
+/// the command, while the failure of the other hooks, which run once the
+/// command is done, is only reported.
+pub(crate) fn run_hook(
+    repo: &Repository,
+    name: &str,
+    vars: &[(&str, String)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = repo.data_dir().join(HOOKS_DIR).join(name);
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.is_file() || !is_executable(&metadata) {
+        warn!(
+            "Ignoring the hook {}, which is not executable",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    info!("Running the {} hook...", name);
+    let status = Command::new(&path)
+        .current_dir(repo.path())
+        .env("ELFSHAKER_HOOK", name)
+        .env("ELFSHAKER_DATA_DIR", repo.data_dir())
+        .env("ELFSHAKER_WORK_DIR", repo.path())
+        .envs(vars.iter().cloned())
+        .status()
+        .map_err(|e| format!("Failed to run the {name} hook: {e}"))?;
+    if status.success() {
+        Ok(())
+    } else if name.starts_with("pre-") {
+        Err(format!("The {name} hook failed ({status})!").into())
+    } else {
+        warn!("The {} hook failed ({})!", name, status);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+/// There are no execute permissions on Windows: any file is a hook.
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
 /// Opens the repo from the current work directory and logs some standard
 /// stats about the process.
 pub fn open_repo_from_cwd(data_dir: &Path) -> Result<Repository, RepoError> {
@@ -114,7 +555,40 @@ pub fn open_repo_from_cwd(data_dir: &Path) -> Result<Repository, RepoError> {
     info!("Opening repository...");
     let (elapsed, open_result) = measure(|| Repository::open_with_data_dir(repo_path, data_dir));
     info!("Opening repository took {:?}", elapsed);
-    open_result
+    let mut repo = open_result?;
+    // The data directory of a linked working tree does not contain the remotes.
+    repo.set_http_auth(http_auth(repo.data_dir())?);
+    repo.set_remote_mirrors(remote_mirrors()?);
+    repo.set_trusted_keys(trusted_keys()?);
+    repo.set_trust_policies(trust_policies()?);
+    repo.set_encryption_keys(encryption_keys()?);
+    repo.set_index_disk_cache(config().get_bool("cache.indexes")?.unwrap_or(false));
+    repo.set_object_index(config().get_bool("cache.objects")?.unwrap_or(false));
+    Ok(repo)
+}
+
+/// Lists the files in the current work directory, which are stored by
+/// default when creating a snapshot. The empty directories are listed too if
+/// `empty_dirs` is set. The files ignored by the `.elfshakerignore` file (or
+/// by the `store.ignore` patterns of the configuration) are skipped.
+pub(crate) fn find_files(empty_dirs: bool) -> Result<Vec<PathBuf>, RepoError> {
+    // The patterns of the file take precedence over the configured ones.
+    let config_patterns: Vec<_> = config().get_all("store.ignore").collect();
+    let mut ignore = IgnoreFile::parse(&config_patterns.join("\n"))?;
+    ignore.extend(IgnoreFile::load(Path::new(IGNORE_FILE))?);
+    Ok(WalkDir::new(".")
+        .min_depth(1)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !ignore.is_ignored(e.path(), e.file_type().is_dir()))
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            e.file_type().is_file()
+                || e.file_type().is_symlink()
+                || (empty_dirs && e.file_type().is_dir() && is_empty_dir(e.path()))
+        })
+        .map(|e| e.path().into())
+        .collect())
 }
 
 /// The default progress bar width is the same as cargo's.
@@ -163,6 +637,8 @@ fn is_terminal_escape_supported<Fd>(_: Fd) -> bool {
 lazy_static! {
     static ref STDOUT_TERMINAL_ESCAPE_SUPPORTED: bool =
         is_terminal_escape_supported(std::io::stdout());
+    static ref STDERR_TERMINAL_ESCAPE_SUPPORTED: bool =
+        is_terminal_escape_supported(std::io::stderr());
 }
 
 /// Clears the current line
@@ -175,6 +651,15 @@ const ESC_RESET: &str = "\x1b[32;0m";
 
 pub fn create_percentage_print_reporter(message: &str, step: u32) -> ProgressReporter<'static> {
     assert!(step <= 100);
+    if PROGRESS_FORMAT.get() == Some(&ProgressFormat::Json) {
+        return create_json_reporter(message, step);
+    }
+    if is_quiet() {
+        return ProgressReporter::dummy();
+    }
+    if *STDERR_TERMINAL_ESCAPE_SUPPORTED {
+        return create_terminal_reporter(message);
+    }
 
     let current = Arc::new(AtomicIsize::new(-100));
     let message = message.to_owned();
@@ -238,3 +723,144 @@ pub fn create_percentage_print_reporter(message: &str, step: u32) -> ProgressRep
         }
     })
 }
+
+/// How often the progress bars of [`create_terminal_reporter`] are redrawn.
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Builds a reporter which draws the progress of the stage on stderr (which
+/// must be a terminal), as a progress bar refreshed in place, with the
+/// throughput (in bytes per second, if known, otherwise in items per second)
+/// and the estimated time remaining.
+fn create_terminal_reporter(message: &str) -> ProgressReporter<'static> {
+    let message = message.to_owned();
+    let started = Instant::now();
+    // The time of the last redraw, and whether the completion was drawn.
+    let state = Mutex::new((None::<Instant>, false));
+    ProgressReporter::new(move |checkpoint| {
+        let now = Instant::now();
+        let total = checkpoint
+            .remaining
+            .map(|remaining| checkpoint.done + remaining);
+        let is_done = total == Some(checkpoint.done);
+        {
+            let (last_draw, is_done_drawn) = &mut *state.lock().unwrap();
+            let is_due = last_draw.is_none_or(|last| now - last >= PROGRESS_REDRAW_INTERVAL);
+            if (is_done && *is_done_drawn) || !(is_due || is_done) {
+                return;
+            }
+            *last_draw = Some(now);
+            *is_done_drawn = is_done;
+        }
+
+        let elapsed = now - started;
+        let throughput = match checkpoint.bytes {
+            Some(bytes) => format!("{}/s", format_size(per_second(bytes, elapsed))),
+            None => format!("{}/s", per_second(checkpoint.done as u64, elapsed)),
+        };
+        let mut line = format!("{ESC_CLEAR_LINE}\r");
+        match total {
+            Some(total) if is_done => {
+                line += &format!(
+                    "{}{}{}{} [{}/{}] in {} ({})",
+                    ESC_BOLD_ON,
+                    ESC_GREEN,
+                    message,
+                    ESC_RESET,
+                    total,
+                    total,
+                    format_duration(elapsed),
+                    throughput
+                );
+            }
+            Some(total) => {
+                let percentage = 100 * checkpoint.done / std::cmp::max(1, total);
+                line += &format!(
+                    "{}{}{}{} {} {}% [{}/{}] {}",
+                    ESC_BOLD_ON,
+                    ESC_CYAN,
+                    message,
+                    ESC_RESET,
+                    create_progress_bar(percentage as i32, DEFAULT_PROGRESS_BAR_WIDTH),
+                    percentage,
+                    checkpoint.done,
+                    total,
+                    throughput
+                );
+                if checkpoint.done > 0 {
+                    let eta =
+                        elapsed.mul_f64((total - checkpoint.done) as f64 / checkpoint.done as f64);
+                    line += &format!(" ETA {}", format_duration(eta));
+                }
+            }
+            None => {
+                line += &format!(
+                    "{}{}{}{}... [{}/?] {}",
+                    ESC_BOLD_ON, ESC_CYAN, message, ESC_RESET, checkpoint.done, throughput
+                );
+            }
+        }
+        if let (false, Some(detail)) = (is_done, &checkpoint.detail) {
+            line += &format!(": {detail}");
+        }
+        if is_done {
+            line += "\n";
+        }
+        let mut stderr = std::io::stderr().lock();
+        let _ = stderr.write_all(line.as_bytes());
+        let _ = stderr.flush();
+    })
+}
+
+/// The rate of `count` per second, over `elapsed`.
+fn per_second(count: u64, elapsed: Duration) -> u64 {
+    (count as f64 / elapsed.as_secs_f64().max(0.001)) as u64
+}
+
+/// Formats durations to human-readable format (e.g. `1h02m`, `3m05s`, `12s`).
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}.{}s", secs, duration.subsec_millis() / 100)
+    }
+}
+
+/// Builds a reporter which writes the progress of the stage to stderr as JSON
+/// lines, e.g. `{"bytes":1024,"detail":null,"done":3,"stage":"Compressing
+/// objects","total":10}`, each time the percentage increases by `step`.
+/// `total` and `bytes` are `null` when they are not known.
+fn create_json_reporter(message: &str, step: u32) -> ProgressReporter<'static> {
+    let current = Arc::new(AtomicIsize::new(-100));
+    let stage = message.to_owned();
+    ProgressReporter::new(move |checkpoint| {
+        let total = checkpoint
+            .remaining
+            .map(|remaining| checkpoint.done + remaining);
+        if let Some(total) = total {
+            let percentage = (100 * checkpoint.done / std::cmp::max(1, total)) as isize;
+            let last = current.load(Ordering::Acquire);
+            // The completion is reported once, whatever the step.
+            let needs_update = percentage - last >= step as isize
+                || (checkpoint.done == total && percentage != last);
+            if !needs_update {
+                return;
+            }
+            current.store(percentage, Ordering::Release);
+        }
+        let line = serde_json::json!({
+            "stage": stage,
+            "done": checkpoint.done,
+            "total": total,
+            "bytes": checkpoint.bytes,
+            "detail": checkpoint.detail,
+        });
+        eprintln!("{line}");
+    })
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
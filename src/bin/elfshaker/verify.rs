@@ -0,0 +1,91 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use log::error;
+use std::error::Error;
+
+use super::utils::open_repo_from_cwd;
+use elfshaker::repo::{Error as RepoError, PackId, VerifyIssue};
+
+pub(crate) const SUBCOMMAND: &str = "verify";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let packs = matches.values_of_lossy("pack");
+
+    let repo = open_repo_from_cwd(data_dir)?;
+
+    let packs = packs
+        .map(|packs| packs.iter().cloned().map(PackId::Pack).collect())
+        .unwrap_or(repo.packs()?);
+
+    let mut problem_count = 0;
+
+    // HEAD must reference an existing snapshot.
+    match repo.read_head() {
+        Ok((Some(head), _)) => {
+            if let Err(e) = repo.load_snapshot_entries(&head) {
+                println!("HEAD: dangling reference to {head}: {e}");
+                problem_count += 1;
+            }
+        }
+        Ok((None, _)) => {}
+        Err(e) => {
+            println!("HEAD: {e}");
+            problem_count += 1;
+        }
+    }
+
+    for pack_id in &packs {
+        match repo.verify_pack(pack_id) {
+            Ok(issues) if issues.is_empty() => println!("{pack_id}: OK"),
+            Ok(issues) => {
+                for issue in &issues {
+                    match issue {
+                        VerifyIssue::CorruptObject(expected, actual) => println!(
+                            "{}: corrupt object {} (checksum is {})",
+                            pack_id,
+                            hex::encode(expected),
+                            hex::encode(actual)
+                        ),
+                        VerifyIssue::MissingObject(checksum) => {
+                            println!("{}: missing object {}", pack_id, hex::encode(checksum))
+                        }
+                    }
+                }
+                problem_count += issues.len();
+            }
+            Err(RepoError::PackNotFound(_)) => {
+                println!("{pack_id}: skipped (the pack is not available locally)");
+            }
+            Err(e) => {
+                println!("{pack_id}: {e}");
+                problem_count += 1;
+            }
+        }
+    }
+
+    if problem_count > 0 {
+        error!("Found {} problem(s)!", problem_count);
+        return Err("Verification failed!".into());
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .alias("fsck")
+        .about(
+            "Verifies the integrity of the repository. Checks the consistency of \
+            the pack indexes and recomputes the checksums of all objects.",
+        )
+        .arg(
+            Arg::with_name("pack")
+                .index(1)
+                .required(false)
+                .multiple(true)
+                .help("Verifies only the specified packs (defaults to all packs)."),
+        )
+}
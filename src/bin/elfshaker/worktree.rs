@@ -0,0 +1,111 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+use clap::{App, Arg, ArgMatches};
+use std::{error::Error, path::Path};
+
+use super::utils::{create_percentage_print_reporter, open_repo_from_cwd};
+use elfshaker::repo::{ExtractOptions, REPO_DIR};
+
+pub(crate) const SUBCOMMAND: &str = "worktree";
+
+pub(crate) fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let data_dir = std::path::Path::new(matches.value_of("data_dir").unwrap());
+    let is_force = matches.is_present("force");
+
+    let mut repo = open_repo_from_cwd(data_dir)?;
+
+    if let Some(paths) = matches.values_of("remove") {
+        for path in paths {
+            repo.remove_worktree(Path::new(path), is_force)?;
+            status!("Removed the working tree {path}");
+        }
+        return Ok(());
+    }
+    if matches.is_present("prune") {
+        for path in repo.prune_worktrees()? {
+            status!("Unregistered the missing working tree {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let path = match matches.value_of("path") {
+        Some(path) => Path::new(path),
+        None => {
+            for (path, head) in repo.worktrees()? {
+                let head = match head {
+                    Some(head) => head.to_string(),
+                    None => "(no HEAD)".to_owned(),
+                };
+                if path.join(REPO_DIR).exists() {
+                    println!("{} {head}", path.display());
+                } else {
+                    println!("{} {head} (missing)", path.display());
+                }
+            }
+            return Ok(());
+        }
+    };
+
+    let snapshot = match matches.value_of("snapshot") {
+        Some(snapshot) => Some(repo.find_snapshot(snapshot)?),
+        None => repo.read_head()?.0,
+    };
+    let path = repo.add_worktree(path)?;
+    status!("Created the working tree {}", path.display());
+
+    if let Some(snapshot) = snapshot {
+        repo.set_progress_reporter(|msg| create_percentage_print_reporter(msg, 5));
+        let result =
+            repo.extract_snapshot_to(snapshot.clone(), &path, ExtractOptions::default())?;
+        status!("A \t{} files", result.added_file_count);
+        status!("Extracted '{snapshot}'");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn get_app() -> App<'static, 'static> {
+    App::new(SUBCOMMAND)
+        .about(
+            "Creates a working tree at the path, which shares the repository (without \
+            copying the packs) but has a HEAD of its own, and extracts the snapshot (HEAD \
+            by default) to it. The other commands run in the working tree use its HEAD. \
+            Lists the working trees if no path is specified.",
+        )
+        .arg(
+            Arg::with_name("path")
+                .index(1)
+                .help("The directory of the working tree to create."),
+        )
+        .arg(
+            Arg::with_name("snapshot")
+                .index(2)
+                .requires("path")
+                .help("The snapshot to extract to the working tree (HEAD by default)."),
+        )
+        .arg(
+            Arg::with_name("remove")
+                .long("remove")
+                .takes_value(true)
+                .value_name("path")
+                .multiple(true)
+                .conflicts_with_all(&["path", "snapshot", "prune"])
+                .help(
+                    "Removes the working trees, with their files. Fails if some files are \
+                    more recent than their HEAD, unless --force is specified.",
+                ),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .requires("remove")
+                .help("Removes the working trees even if some files would be lost."),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .conflicts_with_all(&["path", "snapshot"])
+                .help("Unregisters the working trees whose directory was deleted."),
+        )
+}
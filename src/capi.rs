@@ -0,0 +1,314 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! C bindings for the core repository operations (enabled with the `capi`
+//! feature). The declarations are in `include/elfshaker.h`, which is generated
+//! with `cbindgen --config cbindgen.toml --output include/elfshaker.h`.
+//!
+//! Functions returning `int` return 0 on success and -1 on failure, functions
+//! returning pointers return NULL on failure. After a failure,
+//! [`elfshaker_last_error`] describes the error.
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use crate::repo::{ExtractOptions, Repository, SnapshotId, REPO_DIR};
+
+/// Verify the checksums of the extracted objects.
+pub const ELFSHAKER_EXTRACT_VERIFY: u32 = 1;
+/// Extract all files, even if the snapshot is already extracted (HEAD).
+pub const ELFSHAKER_EXTRACT_RESET: u32 = 2;
+/// Skip the checks guarding against overwriting user-modified files.
+pub const ELFSHAKER_EXTRACT_FORCE: u32 = 4;
+
+/// An open elfshaker repository.
+pub struct ElfshakerRepository {
+    repo: Repository,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Interior NUL bytes cannot be represented in a C string.
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, converting errors and panics (which must not unwind into C) into
+/// the last error.
+fn ffi_call<T>(f: impl FnOnce() -> Result<T, Box<dyn Error>>) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            None
+        }
+        Err(_) => {
+            set_last_error("elfshaker panicked".to_owned());
+            None
+        }
+    }
+}
+
+fn status(result: Option<()>) -> c_int {
+    match result {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+/// Converts a C string argument.
+///
+/// # Safety
+///
+/// `s` must be NULL or a valid NUL-terminated string.
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, Box<dyn Error>> {
+    if s.is_null() {
+        return Err(format!("{name} is NULL").into());
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+/// Converts a repository argument.
+///
+/// # Safety
+///
+/// `repo` must be NULL or a pointer returned by [`elfshaker_repository_open`].
+unsafe fn to_repo<'a>(
+    repo: *mut ElfshakerRepository,
+) -> Result<&'a mut ElfshakerRepository, Box<dyn Error>> {
+    repo.as_mut().ok_or_else(|| "repo is NULL".into())
+}
+
+/// Returns the message describing the last error which occurred on the
+/// calling thread, or NULL. The string is valid until the next call to an
+/// elfshaker function on the same thread.
+#[no_mangle]
+pub extern "C" fn elfshaker_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Opens the repository whose working directory is `path`. `data_dir` is the
+/// path of the elfshaker_data directory, or NULL to use the one in `path`.
+/// The repository must be freed with [`elfshaker_repository_free`].
+///
+/// # Safety
+///
+/// `path` and `data_dir` must be NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_repository_open(
+    path: *const c_char,
+    data_dir: *const c_char,
+) -> *mut ElfshakerRepository {
+    ffi_call(|| {
+        let path = PathBuf::from(to_str(path, "path")?);
+        let data_dir = if data_dir.is_null() {
+            path.join(REPO_DIR)
+        } else {
+            PathBuf::from(to_str(data_dir, "data_dir")?)
+        };
+        let repo = Repository::open_with_data_dir(path, data_dir)?;
+        Ok(Box::into_raw(Box::new(ElfshakerRepository { repo })))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Closes the repository.
+///
+/// # Safety
+///
+/// `repo` must be NULL or a pointer returned by [`elfshaker_repository_open`]
+/// which has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_repository_free(repo: *mut ElfshakerRepository) {
+    if !repo.is_null() {
+        drop(Box::from_raw(repo));
+    }
+}
+
+/// Lists the snapshots in the repository (as `<pack>:<tag>`). On success,
+/// `*snapshots` points to an array of `*count` strings, which must be freed
+/// with [`elfshaker_string_list_free`].
+///
+/// # Safety
+///
+/// `repo` must be a pointer returned by [`elfshaker_repository_open`],
+/// `snapshots` and `count` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_list_snapshots(
+    repo: *mut ElfshakerRepository,
+    snapshots: *mut *mut *mut c_char,
+    count: *mut usize,
+) -> c_int {
+    status(ffi_call(|| {
+        let repo = &to_repo(repo)?.repo;
+        if snapshots.is_null() || count.is_null() {
+            return Err("snapshots or count is NULL".into());
+        }
+        let mut list = vec![];
+        for pack_id in repo.packs()? {
+            for tag in repo.load_index_snapshots(&pack_id)? {
+                let snapshot = SnapshotId::new(pack_id.clone(), &tag)?;
+                list.push(CString::new(snapshot.to_string())?);
+            }
+        }
+        let list: Box<[*mut c_char]> = list.into_iter().map(CString::into_raw).collect();
+        *count = list.len();
+        *snapshots = Box::into_raw(list) as *mut *mut c_char;
+        Ok(())
+    }))
+}
+
+/// Frees a list returned by [`elfshaker_list_snapshots`].
+///
+/// # Safety
+///
+/// `list` and `count` must have been returned by [`elfshaker_list_snapshots`]
+/// and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_string_list_free(list: *mut *mut c_char, count: usize) {
+    if list.is_null() {
+        return;
+    }
+    let list = Box::from_raw(ptr::slice_from_raw_parts_mut(list, count));
+    for s in list.iter() {
+        drop(CString::from_raw(*s));
+    }
+}
+
+/// Extracts the snapshot into the working directory of the repository, like
+/// `elfshaker extract`. `flags` is a combination of the `ELFSHAKER_EXTRACT_*`
+/// flags.
+///
+/// # Safety
+///
+/// `repo` must be a pointer returned by [`elfshaker_repository_open`] and
+/// `snapshot` a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_extract_snapshot(
+    repo: *mut ElfshakerRepository,
+    snapshot: *const c_char,
+    flags: u32,
+) -> c_int {
+    status(ffi_call(|| {
+        let repo = &mut to_repo(repo)?.repo;
+        let snapshot = repo.find_snapshot(to_str(snapshot, "snapshot")?)?;
+
+        let mut opts = ExtractOptions::default();
+        opts.set_verify(flags & ELFSHAKER_EXTRACT_VERIFY != 0);
+        opts.set_reset(flags & ELFSHAKER_EXTRACT_RESET != 0);
+        opts.set_force(flags & ELFSHAKER_EXTRACT_FORCE != 0);
+        repo.extract_snapshot(snapshot, opts)?;
+        Ok(())
+    }))
+}
+
+/// Reads the contents of the file at `path` in the snapshot into memory,
+/// without writing anything to disk. The checksum of the object is verified.
+/// On success, `*data` points to `*size` bytes, which must be freed with
+/// [`elfshaker_buffer_free`].
+///
+/// # Safety
+///
+/// `repo` must be a pointer returned by [`elfshaker_repository_open`],
+/// `snapshot` and `path` valid NUL-terminated strings, and `data` and `size`
+/// valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_read_file(
+    repo: *mut ElfshakerRepository,
+    snapshot: *const c_char,
+    path: *const c_char,
+    data: *mut *mut u8,
+    size: *mut usize,
+) -> c_int {
+    status(ffi_call(|| {
+        let repo = &to_repo(repo)?.repo;
+        let path = Path::new(to_str(path, "path")?);
+        if data.is_null() || size.is_null() {
+            return Err("data or size is NULL".into());
+        }
+        let snapshot = repo.find_snapshot(to_str(snapshot, "snapshot")?)?;
+        let contents = repo
+            .read_snapshot_file(&snapshot, path)?
+            .ok_or_else(|| format!("'{}' is not in {}", path.display(), snapshot))?;
+        let contents = contents.into_boxed_slice();
+        *size = contents.len();
+        *data = Box::into_raw(contents) as *mut u8;
+        Ok(())
+    }))
+}
+
+/// Frees a buffer returned by [`elfshaker_read_file`].
+///
+/// # Safety
+///
+/// `data` and `size` must have been returned by [`elfshaker_read_file`] and
+/// not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn elfshaker_buffer_free(data: *mut u8, size: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, size)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packidx::{FileMetadata, SnapshotMetadata};
+    use crate::repo::PackId;
+    use std::fs;
+
+    #[test]
+    fn capi_works() {
+        let dir = std::env::temp_dir().join(format!("elfshaker_capi_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join(REPO_DIR)).unwrap();
+        let mut repo = Repository::open_with_data_dir(&dir, dir.join(REPO_DIR)).unwrap();
+        let snapshot = SnapshotId::new(PackId::Pack("loose/s1".into()), "s1").unwrap();
+        let files = vec![Ok(("a".into(), FileMetadata::default(), b"elf".to_vec()))];
+        repo.create_snapshot_from_contents(&snapshot, files, SnapshotMetadata::default())
+            .unwrap();
+        drop(repo);
+
+        let path = CString::new(dir.to_str().unwrap()).unwrap();
+        unsafe {
+            let repo = elfshaker_repository_open(path.as_ptr(), ptr::null());
+            assert!(!repo.is_null());
+
+            let mut list = ptr::null_mut();
+            let mut count = 0;
+            assert_eq!(0, elfshaker_list_snapshots(repo, &mut list, &mut count));
+            assert_eq!(1, count);
+            assert_eq!("loose/s1:s1", CStr::from_ptr(*list).to_str().unwrap());
+            elfshaker_string_list_free(list, count);
+
+            let tag = CString::new("s1").unwrap();
+            let file = CString::new("a").unwrap();
+            let mut data = ptr::null_mut();
+            let mut size = 0;
+            assert_eq!(
+                0,
+                elfshaker_read_file(repo, tag.as_ptr(), file.as_ptr(), &mut data, &mut size)
+            );
+            assert_eq!(b"elf", std::slice::from_raw_parts(data, size));
+            elfshaker_buffer_free(data, size);
+
+            let missing = CString::new("b").unwrap();
+            assert_eq!(
+                -1,
+                elfshaker_read_file(repo, tag.as_ptr(), missing.as_ptr(), &mut data, &mut size)
+            );
+            assert!(!elfshaker_last_error().is_null());
+
+            assert_eq!(0, elfshaker_extract_snapshot(repo, tag.as_ptr(), 0));
+            assert_eq!(b"elf", &fs::read(dir.join("a")).unwrap()[..]);
+            elfshaker_repository_free(repo);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
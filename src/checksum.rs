@@ -0,0 +1,309 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Computes the content checksums of objects and snapshots.
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// The size of the largest [`ObjectChecksum`] (BLAKE3 and SHA-256).
+pub const MAX_CHECKSUM_SIZE: usize = 32;
+
+/// The content checksum of an object. Its size depends on the
+/// [`ChecksumAlgorithm`] used to compute it (see [`ChecksumAlgorithm::size`]).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectChecksum {
+    size: u8,
+    bytes: [u8; MAX_CHECKSUM_SIZE],
+}
+
+impl ObjectChecksum {
+    /// Creates a checksum from its bytes. Returns None if the slice is empty
+    /// or larger than [`MAX_CHECKSUM_SIZE`].
+    pub fn from_slice(slice: &[u8]) -> Option<Self> {
+        if slice.is_empty() || slice.len() > MAX_CHECKSUM_SIZE {
+            return None;
+        }
+        let mut bytes = [0; MAX_CHECKSUM_SIZE];
+        bytes[..slice.len()].copy_from_slice(slice);
+        Some(Self {
+            size: slice.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.size as usize]
+    }
+}
+
+impl Deref for ObjectChecksum {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl AsRef<[u8]> for ObjectChecksum {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for ObjectChecksum {
+    fn from(bytes: [u8; N]) -> Self {
+        Self::from_slice(&bytes).expect("bad checksum size")
+    }
+}
+
+impl fmt::Debug for ObjectChecksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self))
+    }
+}
+
+/// Serialized in the same way as a byte array of the checksum size, which is
+/// how the SHA-1 checksums were stored before other algorithms were supported.
+impl Serialize for ObjectChecksum {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(self.size as usize)?;
+        for byte in self.as_bytes() {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+struct ObjectChecksumVisitor;
+
+impl<'de> Visitor<'de> for ObjectChecksumVisitor {
+    type Value = ObjectChecksum;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an array of at most {MAX_CHECKSUM_SIZE} bytes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<ObjectChecksum, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = [0; MAX_CHECKSUM_SIZE];
+        let mut size = 0;
+        while let Some(byte) = seq.next_element()? {
+            if size == MAX_CHECKSUM_SIZE {
+                return Err(de::Error::invalid_length(size + 1, &self));
+            }
+            bytes[size] = byte;
+            size += 1;
+        }
+        ObjectChecksum::from_slice(&bytes[..size])
+            .ok_or_else(|| de::Error::invalid_length(size, &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectChecksum {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(ObjectChecksumVisitor)
+    }
+}
+
+/// The hash algorithm used to compute the [`ObjectChecksum`]s stored in a
+/// pack index. All checksums in an index are computed with the same algorithm,
+/// which is recorded in the index header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChecksumAlgorithm {
+    /// SHA-1, used by the indexes created before version 4.
+    Sha1,
+    /// BLAKE3.
+    Blake3,
+    /// SHA-256.
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// All the supported algorithms.
+    pub const ALL: [ChecksumAlgorithm; 3] = [Self::Sha1, Self::Blake3, Self::Sha256];
+
+    /// Computes the checksum of the buffer.
+    pub fn checksum(self, buf: &[u8]) -> ObjectChecksum {
+        let mut hasher = self.hasher();
+        hasher.update(buf);
+        hasher.finalize()
+    }
+
+    /// Creates a hasher for computing the checksum of data which is not
+    /// available all at once.
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// The size of the checksums computed with the algorithm, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Blake3 | Self::Sha256 => 32,
+        }
+    }
+
+    /// The name of the algorithm, as accepted by [`ChecksumAlgorithm::from_str`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "sha1",
+            Self::Blake3 => "blake3",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// The identifier of the algorithm in the pack index header.
+    pub(crate) fn id(self) -> u32 {
+        match self {
+            Self::Sha1 => 0,
+            Self::Blake3 => 1,
+            Self::Sha256 => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Self::Sha1),
+            1 => Some(Self::Blake3),
+            2 => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// New pack indexes use BLAKE3.
+impl Default for ChecksumAlgorithm {
+    fn default() -> Self {
+        Self::Blake3
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sha1 => write!(f, "SHA-1"),
+            Self::Blake3 => write!(f, "BLAKE3"),
+            Self::Sha256 => write!(f, "SHA-256"),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| s.eq_ignore_ascii_case(algorithm.name()))
+            .ok_or_else(|| {
+                format!("Unknown checksum algorithm '{s}' (expected sha1, sha256 or blake3)")
+            })
+    }
+}
+
+/// Computes checksums incrementally (see [`ChecksumAlgorithm::hasher`]).
+pub enum Hasher {
+    Sha1(Sha1),
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, buf: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(buf),
+            Self::Sha256(hasher) => hasher.update(buf),
+            Self::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> ObjectChecksum {
+        match self {
+            Self::Sha1(hasher) => ObjectChecksum::from_slice(&hasher.finalize()),
+            Self::Sha256(hasher) => ObjectChecksum::from_slice(&hasher.finalize()),
+            Self::Blake3(hasher) => ObjectChecksum::from_slice(hasher.finalize().as_bytes()),
+        }
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_works() {
+        assert_eq!(
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            hex::encode(ChecksumAlgorithm::Sha1.checksum(b""))
+        );
+        assert_eq!(
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+            hex::encode(ChecksumAlgorithm::Blake3.checksum(b""))
+        );
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hex::encode(ChecksumAlgorithm::Sha256.checksum(b""))
+        );
+    }
+
+    #[test]
+    fn incremental_checksum_works() {
+        for algorithm in ChecksumAlgorithm::ALL {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"elf");
+            hasher.update(b"shaker");
+            assert_eq!(algorithm.checksum(b"elfshaker"), hasher.finalize());
+        }
+    }
+
+    #[test]
+    fn checksum_serialization_works() {
+        // Must be compatible with the byte arrays written by older versions.
+        let sha1 = [7u8; 20];
+        let checksum = ObjectChecksum::from(sha1);
+        let buf = rmp_serde::encode::to_vec(&checksum).unwrap();
+        assert_eq!(rmp_serde::encode::to_vec(&sha1).unwrap(), buf);
+        assert_eq!(checksum, rmp_serde::decode::from_slice(&buf).unwrap());
+
+        let sha256 = ChecksumAlgorithm::Sha256.checksum(b"elfshaker");
+        let buf = rmp_serde::encode::to_vec(&sha256).unwrap();
+        let decoded: ObjectChecksum = rmp_serde::decode::from_slice(&buf).unwrap();
+        assert_eq!(sha256, decoded);
+        assert_eq!(32, decoded.len());
+
+        let too_large = rmp_serde::encode::to_vec(&vec![0u8; MAX_CHECKSUM_SIZE + 1]).unwrap();
+        assert!(rmp_serde::decode::from_slice::<ObjectChecksum>(&too_large).is_err());
+    }
+
+    #[test]
+    fn checksum_algorithm_from_str_works() {
+        for algorithm in ChecksumAlgorithm::ALL {
+            assert_eq!(Ok(algorithm), algorithm.name().parse());
+            assert_eq!(Some(algorithm), ChecksumAlgorithm::from_id(algorithm.id()));
+        }
+        assert_eq!(Ok(ChecksumAlgorithm::Sha256), "SHA256".parse());
+        assert!("md5".parse::<ChecksumAlgorithm>().is_err());
+    }
+}
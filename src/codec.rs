@@ -0,0 +1,298 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the compression codecs which can be used for the frames of a pack.
+use serde::de::{self, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+#[cfg(feature = "repo")]
+use std::io::Write;
+use std::io::{self, BufRead, Read};
+use std::str::FromStr;
+#[cfg(feature = "zstd")]
+use zstd::stream::raw::DParameter;
+
+#[cfg(not(any(feature = "zstd", feature = "ruzstd")))]
+compile_error!("Decompressing Zstandard frames requires the zstd or the ruzstd feature!");
+
+// 2^30 = 1024MiB window log
+pub const DEFAULT_WINDOW_LOG_MAX: u32 = 30;
+
+/// The codec used to compress the frames of a pack. All frames of a pack are
+/// compressed with the same codec, which is recorded in the pack header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Zstandard, used by the packs created before other codecs were supported.
+    #[default]
+    Zstd,
+    /// LZ4, which compresses poorly, but decompresses very fast.
+    Lz4,
+    /// XZ (LZMA2), which compresses better than Zstandard for some inputs, but
+    /// is much slower.
+    Xz,
+}
+
+impl Codec {
+    /// All the supported codecs.
+    pub const ALL: [Codec; 3] = [Self::Zstd, Self::Lz4, Self::Xz];
+
+    /// The name of the codec, as accepted by [`Codec::from_str`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Lz4 => "lz4",
+            Self::Xz => "xz",
+        }
+    }
+
+    /// The identifier of the codec in the pack header.
+    pub(crate) fn id(self) -> u32 {
+        match self {
+            Self::Zstd => 0,
+            Self::Lz4 => 1,
+            Self::Xz => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Self::Zstd),
+            1 => Some(Self::Lz4),
+            2 => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    /// Detects the codec of a compressed frame from its first bytes (the
+    /// magic number of the frame).
+    pub fn detect(frame: &[u8]) -> Option<Self> {
+        if frame.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if frame.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Some(Self::Lz4)
+        } else if frame.starts_with(b"\xfd7zXZ\0") {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Zstd => write!(f, "Zstandard"),
+            Self::Lz4 => write!(f, "LZ4"),
+            Self::Xz => write!(f, "XZ"),
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|codec| s.eq_ignore_ascii_case(codec.name()))
+            .ok_or_else(|| format!("Unknown codec '{s}' (expected zstd, lz4 or xz)"))
+    }
+}
+
+/// Serialized as its identifier (see [`Codec::id`]).
+impl Serialize for Codec {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Codec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let id = u32::deserialize(deserializer)?;
+        Self::from_id(id).ok_or_else(|| {
+            de::Error::invalid_value(Unexpected::Unsigned(id.into()), &"a codec identifier")
+        })
+    }
+}
+
+/// Compresses the data written to it into a single frame, with any [`Codec`]
+/// (see [`crate::batch::compress_files`]).
+#[cfg(feature = "repo")]
+pub(crate) enum Encoder<'a, W: Write> {
+    Zstd(zstd::Encoder<'a, W>),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    Xz(xz2::write::XzEncoder<W>),
+}
+
+#[cfg(feature = "repo")]
+impl<'a, W: Write> Encoder<'a, W> {
+    /// Finishes the frame, returning the underlying writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Zstd(encoder) => encoder.finish(),
+            Self::Lz4(encoder) => Ok(encoder.finish()?),
+            Self::Xz(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "repo")]
+impl<'a, W: Write> Write for Encoder<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Zstd(encoder) => encoder.write(buf),
+            Self::Lz4(encoder) => encoder.write(buf),
+            Self::Xz(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Zstd(encoder) => encoder.flush(),
+            Self::Lz4(encoder) => encoder.flush(),
+            Self::Xz(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decompresses a frame compressed with any [`Codec`]. XZ frames can only be
+/// decompressed with the `xz` feature (which requires liblzma). Without the
+/// `zstd` feature, Zstandard frames are decompressed with ruzstd, which only
+/// supports windows of up to 100 MiB.
+pub(crate) enum Decoder<R: BufRead> {
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Decoder<'static, R>),
+    #[cfg(not(feature = "zstd"))]
+    Zstd(Box<ruzstd::StreamingDecoder<R, ruzstd::FrameDecoder>>),
+    Lz4(lz4_flex::frame::FrameDecoder<R>),
+    #[cfg(feature = "xz")]
+    Xz(xz2::bufread::XzDecoder<R>),
+}
+
+impl<R: BufRead> Decoder<R> {
+    /// Decompresses the frame read from `reader`, compressed with the codec
+    /// and the dictionary (empty for none, only used by Zstandard).
+    pub(crate) fn new(codec: Codec, reader: R, dictionary: &[u8]) -> io::Result<Self> {
+        Ok(match codec {
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                let mut decoder = zstd::Decoder::with_dictionary(reader, dictionary)?;
+                decoder.set_parameter(DParameter::WindowLogMax(DEFAULT_WINDOW_LOG_MAX))?;
+                Self::Zstd(decoder)
+            }
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => {
+                let mut decoder = ruzstd::FrameDecoder::new();
+                if !dictionary.is_empty() {
+                    // The frames refer to the dictionary by its identifier.
+                    let dictionary =
+                        ruzstd::decoding::dictionary::Dictionary::decode_dict(dictionary)
+                            .map_err(ruzstd_error)?;
+                    decoder.add_dict(dictionary).map_err(ruzstd_error)?;
+                }
+                Self::Zstd(Box::new(
+                    ruzstd::StreamingDecoder::new_with_decoder(reader, decoder)
+                        .map_err(ruzstd_error)?,
+                ))
+            }
+            Codec::Lz4 => Self::Lz4(lz4_flex::frame::FrameDecoder::new(reader)),
+            #[cfg(feature = "xz")]
+            Codec::Xz => Self::Xz(xz2::bufread::XzDecoder::new(reader)),
+            #[cfg(not(feature = "xz"))]
+            Codec::Xz => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Decompressing XZ frames requires the xz feature!",
+                ))
+            }
+        })
+    }
+}
+
+/// Converts the errors of ruzstd, which are all caused by invalid frames (or
+/// dictionaries).
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn ruzstd_error(e: impl fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Zstd(decoder) => decoder.read(buf),
+            Self::Lz4(decoder) => decoder.read(buf),
+            #[cfg(feature = "xz")]
+            Self::Xz(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn codec_names_work() {
+        for codec in Codec::ALL {
+            assert_eq!(Ok(codec), codec.name().parse());
+            assert_eq!(Some(codec), Codec::from_id(codec.id()));
+        }
+        assert_eq!(Ok(Codec::Lz4), "LZ4".parse());
+        assert!("gzip".parse::<Codec>().is_err());
+        assert_eq!(None, Codec::from_id(3));
+    }
+
+    #[test]
+    fn codec_detection_works() {
+        let data = b"elfshaker elfshaker elfshaker";
+        let mut zstd = zstd::Encoder::new(vec![], 3).unwrap();
+        zstd.write_all(data).unwrap();
+        let mut lz4 = lz4_flex::frame::FrameEncoder::new(vec![]);
+        lz4.write_all(data).unwrap();
+
+        assert_eq!(Some(Codec::Zstd), Codec::detect(&zstd.finish().unwrap()));
+        assert_eq!(Some(Codec::Lz4), Codec::detect(&lz4.finish().unwrap()));
+        #[cfg(feature = "xz")]
+        {
+            let mut xz = xz2::write::XzEncoder::new(vec![], 6);
+            xz.write_all(data).unwrap();
+            assert_eq!(Some(Codec::Xz), Codec::detect(&xz.finish().unwrap()));
+        }
+        assert_eq!(None, Codec::detect(data));
+        assert_eq!(None, Codec::detect(&[]));
+    }
+    #[test]
+    fn zstd_dictionary_works() {
+        let samples: Vec<Vec<u8>> = (0..200u32)
+            .map(|i| format!("elfshaker object {} of {}\n", i, i * 7919 % 200).repeat(20))
+            .map(String::into_bytes)
+            .collect();
+        let dictionary = zstd::dict::from_samples(&samples, 4096).unwrap();
+        let data = &samples[42];
+        let mut encoder = zstd::Encoder::with_dictionary(vec![], 3, &dictionary).unwrap();
+        encoder.write_all(data).unwrap();
+        let frame = encoder.finish().unwrap();
+
+        let mut decoded = vec![];
+        Decoder::new(Codec::Zstd, &frame[..], &dictionary)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(data, &decoded);
+        // The frame can't be decompressed without the dictionary.
+        let decoder = Decoder::new(Codec::Zstd, &frame[..], &[]);
+        assert!(decoder.map_or(true, |mut d| d.read_to_end(&mut vec![]).is_err()));
+    }
+}
@@ -0,0 +1,143 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the encoding of objects stored as deltas against another (base)
+//! object of the same pack (see [`PackOptions::delta`](crate::repo::PackOptions::delta)).
+//! A delta is a Zstandard frame compressed using the base as a prefix, so any
+//! content shared with the base is encoded as matches into it.
+use std::io;
+
+#[cfg(any(feature = "zstd", test))]
+use zstd::stream::raw::CParameter;
+#[cfg(feature = "zstd")]
+use zstd::stream::raw::DParameter;
+#[cfg(any(feature = "zstd", test))]
+use zstd::zstd_safe;
+
+#[cfg(not(feature = "zstd"))]
+use crate::codec::ruzstd_error;
+#[cfg(any(feature = "zstd", test))]
+use crate::codec::DEFAULT_WINDOW_LOG_MAX;
+
+/// Deltas larger than this fraction of the size of the object are not worth
+/// storing: the object is stored in full instead (and becomes the new base).
+pub(crate) const MAX_DELTA_RATIO: f64 = 0.5;
+
+/// Encodes `object` as a delta against `base`.
+#[cfg(any(feature = "zstd", test))]
+pub(crate) fn encode_delta(base: &[u8], object: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    let mut cctx = zstd_safe::create_cctx();
+    // The window must cover the base for matches into any part of it.
+    let window_log = window_log(base.len() + object.len());
+    cctx.set_parameter(CParameter::CompressionLevel(level))
+        .map_err(to_io_error)?;
+    cctx.set_parameter(CParameter::WindowLog(window_log))
+        .map_err(to_io_error)?;
+    cctx.set_parameter(CParameter::EnableLongDistanceMatching(true))
+        .map_err(to_io_error)?;
+    cctx.ref_prefix(base).map_err(to_io_error)?;
+
+    let mut delta = Vec::with_capacity(zstd_safe::compress_bound(object.len()));
+    cctx.compress2(&mut delta, object).map_err(to_io_error)?;
+    Ok(delta)
+}
+
+/// Decodes an object of the specified size from its delta against `base`.
+#[cfg(feature = "zstd")]
+pub(crate) fn decode_delta(base: &[u8], delta: &[u8], size: u64) -> io::Result<Vec<u8>> {
+    let mut dctx = zstd_safe::create_dctx();
+    dctx.set_parameter(DParameter::WindowLogMax(DEFAULT_WINDOW_LOG_MAX))
+        .map_err(to_io_error)?;
+    dctx.ref_prefix(base).map_err(to_io_error)?;
+
+    let mut object = Vec::with_capacity(size as usize);
+    dctx.decompress(&mut object, delta).map_err(to_io_error)?;
+    check_size(object, size)
+}
+
+/// Decodes an object of the specified size from its delta against `base`,
+/// with ruzstd. The base is used as a raw content dictionary, which is what
+/// the prefix of the encoder is.
+#[cfg(not(feature = "zstd"))]
+pub(crate) fn decode_delta(base: &[u8], delta: &[u8], size: u64) -> io::Result<Vec<u8>> {
+    use ruzstd::decoding::dictionary::Dictionary;
+    use ruzstd::decoding::scratch::{FSEScratch, HuffmanScratch};
+    use ruzstd::{BlockDecodingStrategy, FrameDecoder};
+
+    // Any identifier but 0 does, since the frames of the deltas don't have one.
+    const BASE_DICTIONARY_ID: u32 = 1;
+
+    let mut decoder = FrameDecoder::new();
+    decoder
+        .add_dict(Dictionary {
+            id: BASE_DICTIONARY_ID,
+            fse: FSEScratch::new(),
+            huf: HuffmanScratch::new(),
+            dict_content: base.to_vec(),
+            // The initial repeat offsets of the format.
+            offset_hist: [1, 4, 8],
+        })
+        .map_err(ruzstd_error)?;
+    let mut source = delta;
+    decoder.reset(&mut source).map_err(ruzstd_error)?;
+    decoder
+        .force_dict(BASE_DICTIONARY_ID)
+        .map_err(ruzstd_error)?;
+
+    let mut object = Vec::with_capacity(size as usize);
+    // The delta is decoded one block at a time, so that a bad delta can't
+    // decode to much more than the object.
+    while !decoder.is_finished() && object.len() as u64 <= size {
+        decoder
+            .decode_blocks(&mut source, BlockDecodingStrategy::UptoBlocks(1))
+            .map_err(ruzstd_error)?;
+        decoder.collect_to_writer(&mut object)?;
+    }
+    decoder.collect_to_writer(&mut object)?;
+    check_size(object, size)
+}
+
+fn check_size(object: Vec<u8>, size: u64) -> io::Result<Vec<u8>> {
+    if object.len() as u64 != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The decoded delta has an unexpected size!",
+        ));
+    }
+    Ok(object)
+}
+
+/// Returns the smallest window log covering `size` bytes.
+#[cfg(any(feature = "zstd", test))]
+fn window_log(size: usize) -> u32 {
+    let log = usize::BITS - size.saturating_sub(1).leading_zeros();
+    log.clamp(10, DEFAULT_WINDOW_LOG_MAX)
+}
+
+#[cfg(any(feature = "zstd", test))]
+fn to_io_error(code: usize) -> io::Error {
+    io::Error::other(zstd_safe::get_error_name(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip_works() {
+        let base: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| (i * 7).to_le_bytes())
+            .collect();
+        let mut object = base.clone();
+        object[1234] ^= 0xff;
+        object.extend_from_slice(b"elfshaker");
+
+        let delta = encode_delta(&base, &object, 10).unwrap();
+        assert!(delta.len() < object.len() / 100);
+        assert_eq!(
+            object,
+            decode_delta(&base, &delta, object.len() as u64).unwrap()
+        );
+        assert!(decode_delta(&base, &delta, object.len() as u64 - 1).is_err());
+    }
+}
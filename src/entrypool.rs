@@ -27,10 +27,10 @@ where
     }
 
     /// get returns the entry for the intern'd Handle, or None if absent.
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<Handle>
+    pub fn get<Q>(&self, k: &Q) -> Option<Handle>
     where
         T: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         self.entry_map.get(k).copied()
     }
@@ -195,7 +195,7 @@ mod tests {
         assert_eq!(handles, decoded_handles);
 
         // Handles preserved across a roundtrip.
-        for (path, orig_handle) in paths.iter().zip(handles.into_iter()) {
+        for (path, orig_handle) in paths.iter().zip(handles) {
             assert_eq!(decoded.lookup(orig_handle), Some(path));
         }
     }
@@ -0,0 +1,537 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the read-only core of the .pack format: the header of the packs
+//! (see [`PackHeader`]), and the decompression of their frames and the reading
+//! of their objects from memory (see [`PackLayout`]). Like the parsing of the
+//! pack indexes (see [`PackIndex::parse`](crate::packidx::PackIndex::parse)),
+//! it doesn't touch the filesystem, so the library built without the `repo`
+//! feature can read the packs from any source, and compiles to wasm32 with the
+//! `ruzstd` feature (e.g. for a pack explorer running in a browser, which
+//! fetches the frames it needs with HTTP range requests).
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use crate::codec::{Codec, Decoder};
+use crate::delta::decode_delta;
+use crate::packidx::ObjectMetadata;
+
+/// Valid pack headers have this value set in the [`PackHeader::magic`] field.
+pub const PACK_HEADER_MAGIC: u64 = 848629801635942891;
+
+/// A magic constant that has no use other than to indicate a custom .pack header
+/// stored in a Zstandard skippable frame.
+const SKIPPABLE_MAGIC_MASK: u32 = 0x184D2A50;
+
+/// Reads a Zstandard skippable frame from reader and writes the result to `buf`.
+/// Returns the size of the frame, *not* the number of bytes read.
+pub fn read_skippable_frame(mut reader: impl Read, buf: &mut Vec<u8>) -> io::Result<u64> {
+    fn read_u32_le(mut reader: impl Read) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    // Ensure this is a skippable frame.
+    let magic = read_u32_le(&mut reader)?;
+    if magic & SKIPPABLE_MAGIC_MASK != SKIPPABLE_MAGIC_MASK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a Zstandard skippable frame!",
+        ));
+    }
+
+    let frame_size = read_u32_le(&mut reader)?;
+    buf.resize(frame_size as usize, 0);
+    reader.read_exact(buf)?;
+    // Compute overall frame size.
+    Ok((std::mem::size_of::<u32>() * 2 + buf.len()) as u64)
+}
+
+/// Writes a Zstandard skippable frame with the given user data.
+/// Returns the number of bytes written to writer (the size of the frame, including the magic number and header).
+pub fn write_skippable_frame(mut writer: impl Write, buf: &[u8]) -> io::Result<u64> {
+    fn write_u32_le(mut writer: impl Write, value: u32) -> io::Result<()> {
+        writer.write_all(&value.to_le_bytes())
+    }
+
+    // Ensure this is a skippable frame.
+    write_u32_le(&mut writer, SKIPPABLE_MAGIC_MASK)?;
+    write_u32_le(&mut writer, buf.len() as u32)?;
+    writer.write_all(buf)?;
+    // Compute overall frame size.
+    Ok((std::mem::size_of::<u32>() * 2 + buf.len()) as u64)
+}
+
+/// Represents a compressed Zstandard frame in the .pack file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackFrame {
+    /// The size of the frame, in bytes.
+    pub frame_size: u64,
+    /// The size of the data stream in the frame, once decompressed, in bytes.
+    pub decompressed_size: u64,
+}
+
+/// Represents the custom header in the beginning of a .pack file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackHeader {
+    /// Valid pack headers have this value set to [`PACK_HEADER_MAGIC`].
+    pub(crate) magic: u64,
+    /// The list of frames in the pack file, sorted by their byte offsets.
+    pub(crate) frames: Vec<PackFrame>,
+    /// The Zstandard dictionary the frames are compressed with, if any (see
+    /// [`PackOptions::dictionary_size`](crate::repo::PackOptions::dictionary_size)).
+    /// Always serialized (as nil if there is none), since the fields are
+    /// serialized in order, without their names.
+    #[serde(default, with = "serde_bytes")]
+    pub(crate) dictionary: Option<Vec<u8>>,
+    /// The codec the frames are compressed with. Only serialized if it is not
+    /// the default, so that older versions can read Zstandard packs.
+    #[serde(default, skip_serializing_if = "Codec::is_default")]
+    pub(crate) codec: Codec,
+    /// How the frames are encrypted, if they are. Encrypted headers are
+    /// serialized with the field names (see [`PackHeader::to_bytes`]), so that
+    /// the fields which are not serialized don't shift this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) encryption: Option<PackEncryption>,
+}
+
+impl PackHeader {
+    /// Create a new pack header.
+    pub fn new(frames: Vec<PackFrame>) -> Self {
+        Self {
+            magic: PACK_HEADER_MAGIC,
+            frames,
+            dictionary: None,
+            codec: Codec::default(),
+            encryption: None,
+        }
+    }
+    /// Create a new pack header, for frames compressed with the specified
+    /// dictionary.
+    pub fn with_dictionary(frames: Vec<PackFrame>, dictionary: Vec<u8>) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            ..Self::new(frames)
+        }
+    }
+    /// Create a new pack header, for frames compressed with the specified
+    /// codec.
+    pub fn with_codec(frames: Vec<PackFrame>, codec: Codec) -> Self {
+        Self {
+            codec,
+            ..Self::new(frames)
+        }
+    }
+    /// The codec the frames are compressed with.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+    /// The dictionary the frames are compressed with (empty for none).
+    pub fn dictionary(&self) -> &[u8] {
+        self.dictionary.as_deref().unwrap_or_default()
+    }
+    /// Whether the frames are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+    /// Verifies the header magic.
+    pub fn is_valid(&self) -> bool {
+        self.magic == PACK_HEADER_MAGIC
+    }
+    /// Serializes the header. Headers of unencrypted packs are serialized
+    /// without the field names, so that older versions can read them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.encryption.is_some() {
+            rmp_serde::encode::to_vec_named(self)
+        } else {
+            rmp_serde::encode::to_vec(self)
+        }
+        .expect("Serialization failed!")
+    }
+    /// Deserializes the header serialized by [`PackHeader::to_bytes`],
+    /// checking its magic.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let header: PackHeader = rmp_serde::decode::from_read(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if !header.is_valid() {
+            return Err(invalid_data("Not an elfshaker pack header!"));
+        }
+        Ok(header)
+    }
+    /// The frames of the pack, in the order in which they are stored.
+    pub fn frames(&self) -> &[PackFrame] {
+        &self.frames
+    }
+}
+
+impl Default for PackHeader {
+    fn default() -> Self {
+        Self {
+            magic: PACK_HEADER_MAGIC,
+            frames: vec![],
+            dictionary: None,
+            codec: Codec::default(),
+            encryption: None,
+        }
+    }
+}
+
+/// How the frames of a pack are encrypted, as recorded in the pack header
+/// (see the `repo::encryption` module, which decrypts them).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct PackEncryption {
+    #[serde(with = "serde_bytes")]
+    pub(crate) key_id: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub(crate) salt: Vec<u8>,
+}
+
+/// Where the frames of a .pack file are, in the file and in the decompressed
+/// stream of the pack, as read from its header. Reads the objects of the pack
+/// from the frames held in memory, so the .pack file can be fetched piecewise
+/// (e.g. only the frames containing the objects of a snapshot, see
+/// [`PackLayout::frames_of`]). The packs created before the pack header was
+/// introduced, and the encrypted packs, are not supported.
+pub struct PackLayout {
+    header: PackHeader,
+    /// The byte offsets of the frames in the .pack file.
+    frame_offsets: Vec<u64>,
+    /// The offsets of the frames in the decompressed stream.
+    decompressed_offsets: Vec<u64>,
+}
+
+impl PackLayout {
+    /// The number of bytes at the start of the .pack file which give the size
+    /// of the header (see [`PackLayout::header_size`]).
+    pub const PREFIX_SIZE: usize = 8;
+
+    /// The size of the header at the start of the .pack file (the offset of
+    /// its first frame), from the first [`PackLayout::PREFIX_SIZE`] bytes of
+    /// the file.
+    pub fn header_size(prefix: &[u8]) -> io::Result<u64> {
+        let prefix = prefix
+            .get(..Self::PREFIX_SIZE)
+            .ok_or_else(|| invalid_data("The pack header is truncated!"))?;
+        let magic = u32::from_le_bytes(prefix[..4].try_into().unwrap());
+        if magic & SKIPPABLE_MAGIC_MASK != SKIPPABLE_MAGIC_MASK {
+            return Err(invalid_data("Not a Zstandard skippable frame!"));
+        }
+        let size = u32::from_le_bytes(prefix[4..].try_into().unwrap());
+        Ok(Self::PREFIX_SIZE as u64 + u64::from(size))
+    }
+
+    /// Parses the header at the start of the .pack file. `bytes` must contain
+    /// at least the whole header (see [`PackLayout::header_size`]).
+    pub fn parse(mut bytes: &[u8]) -> io::Result<Self> {
+        let mut buf = vec![];
+        let header_size = read_skippable_frame(&mut bytes, &mut buf)?;
+        let header = PackHeader::from_bytes(&buf)?;
+        let frame_offsets = compute_frame_offsets(&header.frames)
+            .into_iter()
+            .map(|offset| header_size + offset)
+            .collect();
+        let decompressed_offsets = compute_frame_decompressed_offset(&header.frames);
+        Ok(Self {
+            header,
+            frame_offsets,
+            decompressed_offsets,
+        })
+    }
+
+    /// The header of the pack.
+    pub fn header(&self) -> &PackHeader {
+        &self.header
+    }
+
+    /// The range of bytes of the frame in the .pack file.
+    pub fn frame_range(&self, frame: usize) -> Range<u64> {
+        let offset = self.frame_offsets[frame];
+        offset..offset + self.header.frames[frame].frame_size
+    }
+
+    /// The frames to decompress to read the object, in order. Delta bases are
+    /// stored in the same frame as their deltas, while the chunks of the
+    /// objects stored as chunks can be in several frames.
+    pub fn frames_of(&self, metadata: &ObjectMetadata) -> io::Result<Vec<usize>> {
+        check_stored_here(metadata)?;
+        let mut frames: Vec<usize> = match &metadata.chunks {
+            Some(chunks) => chunks
+                .iter()
+                .map(|chunk| self.frame_containing(chunk.offset))
+                .collect::<io::Result<_>>()?,
+            None => vec![self.frame_containing(metadata.offset)?],
+        };
+        frames.dedup();
+        Ok(frames)
+    }
+
+    /// Decompresses the frame, from the bytes of its range in the .pack file
+    /// (see [`PackLayout::frame_range`]).
+    pub fn decompress_frame(&self, frame: usize, compressed: &[u8]) -> io::Result<Vec<u8>> {
+        if self.header.is_encrypted() {
+            return Err(io::Error::other(
+                "Encrypted packs can only be read from a repository!",
+            ));
+        }
+        let PackFrame {
+            frame_size,
+            decompressed_size,
+        } = self.header.frames[frame];
+        if compressed.len() as u64 != frame_size {
+            return Err(invalid_data("The frame has an unexpected size!"));
+        }
+        // Catch packs whose header does not match the frames.
+        if let Some(codec) = Codec::detect(compressed) {
+            if codec != self.header.codec {
+                return Err(invalid_data("The frame has an unexpected codec!"));
+            }
+        }
+        let mut decoder = Decoder::new(self.header.codec, compressed, self.header.dictionary())?;
+        let mut buf = Vec::with_capacity(decompressed_size as usize);
+        decoder.read_to_end(&mut buf)?;
+        if buf.len() as u64 != decompressed_size {
+            return Err(invalid_data(
+                "The decompressed frame has an unexpected size!",
+            ));
+        }
+        Ok(buf)
+    }
+
+    /// Reads the object from the decompressed frames containing it (see
+    /// [`PackLayout::frames_of`]), by their index. The checksum of the object is
+    /// not verified.
+    pub fn read_object(
+        &self,
+        metadata: &ObjectMetadata,
+        frames: &HashMap<usize, Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        check_stored_here(metadata)?;
+        if let Some(chunks) = &metadata.chunks {
+            let mut buf = Vec::with_capacity(metadata.size as usize);
+            for chunk in chunks {
+                buf.extend_from_slice(self.read_at(frames, chunk.offset, chunk.size)?);
+            }
+            return Ok(buf);
+        }
+        match metadata.delta {
+            Some(delta) => {
+                let base = self.read_at(frames, delta.base_offset, delta.base_size)?;
+                let buf = self.read_at(frames, metadata.offset, delta.delta_size)?;
+                decode_delta(base, buf, metadata.size)
+            }
+            None => Ok(self
+                .read_at(frames, metadata.offset, metadata.size)?
+                .to_vec()),
+        }
+    }
+
+    /// The index of the (last) frame starting at or before the offset in the
+    /// decompressed stream.
+    fn frame_containing(&self, offset: u64) -> io::Result<usize> {
+        self.decompressed_offsets
+            .partition_point(|&x| x <= offset)
+            .checked_sub(1)
+            .ok_or_else(|| invalid_data("The object is not in any frame!"))
+    }
+
+    fn read_at<'a>(
+        &self,
+        frames: &'a HashMap<usize, Vec<u8>>,
+        offset: u64,
+        size: u64,
+    ) -> io::Result<&'a [u8]> {
+        let frame = self.frame_containing(offset)?;
+        let buf = frames.get(&frame).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Frame {frame} is needed to read the object!"),
+            )
+        })?;
+        let start = (offset - self.decompressed_offsets[frame]) as usize;
+        buf.get(start..start + size as usize)
+            .ok_or_else(|| invalid_data("The object is not in its frame!"))
+    }
+}
+
+/// Fails for the objects stored in another pack (see [`ObjectMetadata::pack`]).
+fn check_stored_here(metadata: &ObjectMetadata) -> io::Result<()> {
+    match &metadata.pack {
+        Some(pack) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The object is stored in the pack {pack}!"),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns a list of the frame offsets, computed
+/// using the order and sizes of the given frames.
+pub(crate) fn compute_frame_offsets(frames: &[PackFrame]) -> Vec<u64> {
+    let mut frame_offsets: Vec<_> = vec![0; frames.len()];
+    for i in 1..frame_offsets.len() {
+        frame_offsets[i] = frames[i - 1].frame_size + frame_offsets[i - 1];
+    }
+    frame_offsets
+}
+
+/// Returns a list of the data offsets, computed using the order and
+/// decompressed sizes of the given frames.
+pub(crate) fn compute_frame_decompressed_offset(frames: &[PackFrame]) -> Vec<u64> {
+    let mut frame_decompressed_offset: Vec<_> = vec![0; frames.len()];
+    for i in 1..frame_decompressed_offset.len() {
+        frame_decompressed_offset[i] =
+            frames[i - 1].decompressed_size + frame_decompressed_offset[i - 1];
+    }
+    frame_decompressed_offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::encode_delta;
+    use crate::packidx::{ObjectChunk, ObjectDelta};
+
+    #[test]
+    fn pack_header_dictionary_works() {
+        let frames = vec![PackFrame {
+            frame_size: 10,
+            decompressed_size: 20,
+        }];
+        let header = PackHeader::new(frames.clone());
+        let bytes = rmp_serde::encode::to_vec(&header).unwrap();
+        let header: PackHeader = rmp_serde::decode::from_read(&bytes[..]).unwrap();
+        assert!(header.is_valid());
+        assert!(header.dictionary().is_empty());
+
+        let header = PackHeader::with_dictionary(frames, vec![0xff; 100]);
+        let dict_bytes = rmp_serde::encode::to_vec(&header).unwrap();
+        // The dictionary is stored as a binary blob.
+        assert!(dict_bytes.len() < bytes.len() + 110);
+        let header: PackHeader = rmp_serde::decode::from_read(&dict_bytes[..]).unwrap();
+        assert_eq!(&[0xff; 100], header.dictionary());
+        assert_eq!(1, header.frames.len());
+    }
+
+    #[test]
+    fn pack_header_codec_works() {
+        let frames = vec![PackFrame {
+            frame_size: 10,
+            decompressed_size: 20,
+        }];
+        // Headers written before codecs were supported.
+        let bytes = rmp_serde::encode::to_vec(&(PACK_HEADER_MAGIC, &frames)).unwrap();
+        let header: PackHeader = rmp_serde::decode::from_read(&bytes[..]).unwrap();
+        assert!(header.is_valid());
+        assert_eq!(Codec::Zstd, header.codec());
+
+        for codec in Codec::ALL {
+            let header = PackHeader::with_codec(frames.clone(), codec);
+            let bytes = rmp_serde::encode::to_vec(&header).unwrap();
+            let header: PackHeader = rmp_serde::decode::from_read(&bytes[..]).unwrap();
+            assert_eq!(codec, header.codec());
+            assert!(header.dictionary().is_empty());
+        }
+    }
+
+    #[test]
+    fn pack_layout_works() {
+        // The first frame ends with an object stored as a delta against its
+        // first 20 bytes.
+        let mut first = b"elfshaker ".repeat(100);
+        let delta = encode_delta(&first[..20], b"elfshaker elfshaker!", 3).unwrap();
+        first.extend_from_slice(&delta);
+        let contents = vec![first, b"frame 2".to_vec()];
+        let mut frames = vec![];
+        let mut compressed = vec![];
+        for data in &contents {
+            let frame = zstd::encode_all(&data[..], 3).unwrap();
+            frames.push(PackFrame {
+                frame_size: frame.len() as u64,
+                decompressed_size: data.len() as u64,
+            });
+            compressed.push(frame);
+        }
+        let mut pack = vec![];
+        write_skippable_frame(&mut pack, &PackHeader::new(frames).to_bytes()).unwrap();
+        for frame in &compressed {
+            pack.extend_from_slice(frame);
+        }
+
+        let header_size = PackLayout::header_size(&pack).unwrap();
+        let layout = PackLayout::parse(&pack[..header_size as usize]).unwrap();
+        let mut decompressed = HashMap::new();
+        for (i, data) in contents.iter().enumerate() {
+            let range = layout.frame_range(i);
+            let frame = &pack[range.start as usize..range.end as usize];
+            assert_eq!(data, &layout.decompress_frame(i, frame).unwrap());
+            decompressed.insert(i, data.clone());
+        }
+        assert!(layout.decompress_frame(1, &compressed[0]).is_err());
+
+        let object = |offset, size| ObjectMetadata {
+            offset,
+            size,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let second = contents[0].len() as u64;
+        assert_eq!(vec![1], layout.frames_of(&object(second, 5)).unwrap());
+        assert_eq!(
+            b"frame".to_vec(),
+            layout
+                .read_object(&object(second, 5), &decompressed)
+                .unwrap()
+        );
+        assert_eq!(
+            b"elfshaker".to_vec(),
+            layout.read_object(&object(10, 9), &decompressed).unwrap()
+        );
+        // The object overflows its frame.
+        assert!(layout
+            .read_object(&object(second - 5, 10), &decompressed)
+            .is_err());
+
+        let deltified = ObjectMetadata {
+            delta: Some(ObjectDelta {
+                base_offset: 0,
+                base_size: 20,
+                delta_size: delta.len() as u64,
+            }),
+            ..object(1000, 20)
+        };
+        assert_eq!(
+            b"elfshaker elfshaker!".to_vec(),
+            layout.read_object(&deltified, &decompressed).unwrap()
+        );
+        let chunked = ObjectMetadata {
+            chunks: Some(vec![
+                ObjectChunk {
+                    offset: 0,
+                    size: 10,
+                },
+                ObjectChunk {
+                    offset: second,
+                    size: 5,
+                },
+            ]),
+            ..object(0, 15)
+        };
+        assert_eq!(vec![0, 1], layout.frames_of(&chunked).unwrap());
+        assert_eq!(
+            b"elfshaker frame".to_vec(),
+            layout.read_object(&chunked, &decompressed).unwrap()
+        );
+        // The frames must be decompressed first.
+        decompressed.remove(&1);
+        assert!(layout.read_object(&chunked, &decompressed).is_err());
+    }
+}
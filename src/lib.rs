@@ -1,10 +1,35 @@
 //! SPDX-License-Identifier: Apache-2.0
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
+//! Without the (default) `repo` feature, the library only contains the modules
+//! which read the packs from memory (the pack indexes, see [`packidx`], and
+//! the frames of the packs, see [`frame`]), without touching the filesystem.
+//! With the `ruzstd` feature instead of `zstd`, it doesn't depend on any C
+//! library either, and compiles to wasm32.
+#[cfg(feature = "repo")]
 pub mod atomicfile;
+#[cfg(feature = "repo")]
 pub mod batch;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod checksum;
+pub mod codec;
+// The deltas are only encoded when creating packs.
+#[cfg_attr(not(feature = "repo"), allow(dead_code))]
+mod delta;
 pub mod entrypool;
+pub mod frame;
+#[cfg(feature = "repo")]
 pub mod log;
+// Some of the index is only used when creating packs, too.
+#[cfg_attr(not(feature = "repo"), allow(dead_code))]
 pub mod packidx;
+#[cfg(feature = "repo")]
 pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod regex;
+#[cfg(feature = "repo")]
 pub mod repo;
+#[cfg(feature = "repo")]
+pub mod sqlite;
@@ -3,40 +3,110 @@
 
 //! Tools for logging.
 use lazy_static::lazy_static;
-use log::{set_logger, set_max_level, Level, LevelFilter, Log, Metadata, Record};
+use log::{debug, set_logger, set_max_level, Level, Log, Metadata, Record};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::RwLock;
 use std::time::{Duration, Instant};
 
 lazy_static! {
-    static ref INIT_LOG_LEVEL: RwLock<Level> = RwLock::new(Level::Error);
+    static ref INIT_LOG_LEVEL: RwLock<(Level, LogFormat)> =
+        RwLock::new((Level::Error, LogFormat::Text));
     static ref LOGGER: Box<Logger> = Box::new(Logger::static_init());
 }
 
+/// Whether [`Logger::init`] installed the [`Logger`].
+static IS_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// The target of the records of the [`Span`]s.
+const SPAN_TARGET: &str = "span";
+
+/// The format of the log records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `[LEVEL (thread) elapsed]: message` lines.
+    Text,
+    /// JSON objects, one per line, for log analyzers. The records of the
+    /// [`Span`]s also have the `span` and `duration_us` fields.
+    Json,
+}
+
 /// The [`Log`] implementation used by elfshaker.
 pub struct Logger {
     level: Level,
+    format: LogFormat,
     init_at: Instant,
 }
 
 impl Logger {
     /// Used to initialise the static LOGGER instance.
     fn static_init() -> Logger {
+        let (level, format) = *INIT_LOG_LEVEL.read().unwrap();
         Logger {
-            level: *INIT_LOG_LEVEL.read().unwrap(),
+            level,
+            format,
             init_at: Instant::now(),
         }
     }
     /// Initialise with the specified log level.
     /// This function should only be called once, during program initialisation.
     pub fn init(level: Level) {
+        Self::init_with_format(level, LogFormat::Text)
+    }
+    /// Initialise with the specified log level and format.
+    /// This function should only be called once, during program initialisation.
+    pub fn init_with_format(level: Level, format: LogFormat) {
         {
             let mut w = INIT_LOG_LEVEL.write().unwrap();
-            *w = level;
+            *w = (level, format);
         }
         // Trigger the lazy initialisation
         let instance = &**LOGGER;
         set_logger(instance).unwrap();
-        set_max_level(LevelFilter::Info);
+        set_max_level(level.to_level_filter());
+        IS_INSTALLED.store(true, Ordering::Release);
+    }
+
+    fn write(
+        &self,
+        level: Level,
+        target: &str,
+        args: &fmt::Arguments,
+        span: Option<(&str, Duration)>,
+    ) {
+        let level_string = match level {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        let elapsed = Instant::now() - self.init_at;
+        let thread = std::thread::current();
+        let thread_string = match thread.name() {
+            Some(name) => name.to_owned(),
+            None => format!("{:?}", thread.id()),
+        };
+        match self.format {
+            LogFormat::Text => eprintln!(
+                "[{} ({}) {:?}]: {}",
+                level_string, thread_string, elapsed, args
+            ),
+            LogFormat::Json => {
+                let mut record = serde_json::json!({
+                    "elapsed_us": elapsed.as_micros() as u64,
+                    "level": level_string,
+                    "thread": thread_string,
+                    "target": target,
+                    "message": args.to_string(),
+                });
+                if let Some((name, duration)) = span {
+                    record["span"] = name.into();
+                    record["duration_us"] = (duration.as_micros() as u64).into();
+                }
+                eprintln!("{record}");
+            }
+        }
     }
 }
 
@@ -47,31 +117,47 @@ impl Log for Logger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let level_string = match record.level() {
-                Level::Error => "ERROR",
-                Level::Warn => "WARN",
-                Level::Info => "INFO",
-                Level::Debug => "DEBUG",
-                Level::Trace => "TRACE",
-            };
-            let elapsed = Instant::now() - self.init_at;
-            let thread = std::thread::current();
-            let thread_string = match thread.name() {
-                Some(name) => name.to_owned(),
-                None => format!("{:?}", thread.id()),
-            };
-            eprintln!(
-                "[{} ({}) {:?}]: {}",
-                level_string,
-                thread_string,
-                elapsed,
-                record.args()
-            );
+            self.write(record.level(), record.target(), record.args(), None);
         }
     }
     fn flush(&self) {}
 }
 
+/// A phase of an operation (e.g. computing the checksums of the files of a
+/// snapshot, or compressing the frames of a pack), whose duration is logged
+/// at the debug level when the [`Span`] is dropped.
+pub struct Span {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Span {
+    /// Starts the phase.
+    pub fn enter(name: &'static str) -> Self {
+        Self {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let duration = self.start.elapsed();
+        if !IS_INSTALLED.load(Ordering::Acquire) {
+            // Another logger is in use, which only gets the message.
+            debug!(target: SPAN_TARGET, "{} took {:?}", self.name, duration);
+        } else if LOGGER.level >= Level::Debug {
+            LOGGER.write(
+                Level::Debug,
+                SPAN_TARGET,
+                &format_args!("{} took {:?}", self.name, duration),
+                Some((self.name, duration)),
+            );
+        }
+    }
+}
+
 /// Measures the time it took to run the specified block.
 pub fn measure<F, T>(f: F) -> (Duration, T)
 where
@@ -3,25 +3,35 @@
 
 //! Contains types and function for parsing `.pack.idx` files created by
 //! elfshaker.
+pub use crate::checksum::ObjectChecksum;
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::codec::Codec;
 use crate::entrypool::{EntryPool, Handle};
+#[cfg(feature = "repo")]
 use crate::repo::{
-    fs::{create_file, open_file},
-    partition_by_u64,
+    fs::{create_file, open_file, replace_file},
+    partition_by_u64, split_by_u64,
 };
 
-use crypto::digest::Digest;
-use crypto::sha1::Sha1;
 use serde::de::{SeqAccess, Visitor};
 use serde::{ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Cow;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
 use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::hash::Hash;
-use std::io::{BufReader, BufWriter, Read, Write};
+#[cfg(feature = "repo")]
+use std::io::BufWriter;
+use std::io::{BufReader, Read, Write};
 use std::iter::FromIterator;
 use std::ops::ControlFlow;
-use std::path::Path;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+mod flat;
+pub use flat::FlatIndex;
 
 /// Error type used in the packidx module.
 #[derive(Debug)]
@@ -38,6 +48,16 @@ pub enum PackError {
     SerializeError(rmp_serde::encode::Error),
     BadMagic,
     BadPackVersion([u8; 4]),
+    /// The checksum algorithm recorded in the index header is not known.
+    BadChecksumAlgorithm(u32),
+    /// The checksums of the pack indexes were computed with different
+    /// algorithms, so the objects cannot be compared.
+    ChecksumAlgorithmMismatch(ChecksumAlgorithm, ChecksumAlgorithm),
+    /// The index contents are inconsistent (see [`PackIndex::check_consistency`]).
+    InconsistentIndex(String),
+    /// A file of the snapshot is in a directory which is a symbolic link of
+    /// the same snapshot, so it would be extracted through the link.
+    SymlinkInPath(String, OsString),
 }
 
 impl std::error::Error for PackError {}
@@ -74,6 +94,21 @@ impl std::fmt::Display for PackError {
                 f,
                 "Pack version is too recent ({v:?}), please upgrade elfshaker!"
             ),
+            PackError::BadChecksumAlgorithm(id) => write!(
+                f,
+                "Unknown checksum algorithm ({id}), please upgrade elfshaker!"
+            ),
+            PackError::ChecksumAlgorithmMismatch(a, b) => write!(
+                f,
+                "The pack indexes use different checksum algorithms ({a} and {b})! \
+                Run `elfshaker upgrade` to convert the older indexes."
+            ),
+            PackError::InconsistentIndex(s) => write!(f, "Corrupt pack index: {s}"),
+            PackError::SymlinkInPath(s, path) => write!(
+                f,
+                "The path {} of the snapshot '{s}' goes through one of its symbolic links!",
+                path.to_string_lossy()
+            ),
         }
     }
 }
@@ -96,11 +131,9 @@ impl From<rmp_serde::encode::Error> for PackError {
     }
 }
 
-/// The content checksum of an object.
-pub type ObjectChecksum = [u8; 20];
 /// The offset used for [`ObjectEntry::offset`], when the object is loose (not
 /// in a pack file).
-pub const LOOSE_OBJECT_OFFSET: u64 = std::u64::MAX;
+pub const LOOSE_OBJECT_OFFSET: u64 = u64::MAX;
 
 /// A [`FileHandle`] identifies a file stored in a pack. It contains two
 /// handles: a path, which can be used to get the path of the file
@@ -118,11 +151,46 @@ pub struct FileHandle {
     pub object: Handle, // offset into object_pool
     #[serde(default)] // New in PackIndex version 2, default None.
     pub file_metadata: FileMetadata,
+    /// The extended attributes of the file (offset into xattrs_pool), if any
+    /// were recorded. New in PackIndex version 8, only serialized if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattrs: Option<Handle>,
+}
+
+/// An extended attribute of a file (see [`FileEntry::xattrs`]).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Xattr {
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct FileMetadata {
     pub mode: u32,
+    /// The modification time of the file, in nanoseconds since the Unix epoch,
+    /// if it was recorded. New in PackIndex version 7, only serialized if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+}
+
+/// The file type bits of [`FileMetadata::mode`] (as in `st_mode`).
+const S_IFMT: u32 = 0o170000;
+/// The file type of symbolic links (see [`FileMetadata::is_symlink`]).
+pub const S_IFLNK: u32 = 0o120000;
+/// The file type of directories (see [`FileMetadata::is_dir`]).
+pub const S_IFDIR: u32 = 0o040000;
+
+impl FileMetadata {
+    /// Whether the entry is a symbolic link. The object of a symbolic link
+    /// contains the path it points to.
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    /// Whether the entry is an empty directory, whose object is empty.
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
 }
 
 /// Format version 1 packs will use a default file metadata.
@@ -132,6 +200,7 @@ impl Default for FileMetadata {
             // The default mode bits are owner can rw, everyone else can read,
             // which is the same behaviour as in version 1 of the pack format.
             mode: 0o100664,
+            mtime: None,
         }
     }
 }
@@ -144,6 +213,7 @@ impl FileHandle {
             path,
             object,
             file_metadata,
+            xattrs: None,
         }
     }
 }
@@ -190,6 +260,23 @@ impl<T> ChangeSet<T> {
     }
 }
 
+/// Optional user-provided metadata attached to a snapshot when it is stored.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SnapshotMetadata {
+    /// A free-form message describing the snapshot.
+    pub message: Option<String>,
+    /// Key/value pairs, such as the commit hash or builder id.
+    pub attributes: BTreeMap<String, String>,
+    /// The time the snapshot was stored, in seconds since the Unix epoch
+    /// (UTC), if it was recorded. Appended without bumping the version, since
+    /// older versions ignore the trailing values.
+    #[serde(default)]
+    pub created: Option<i64>,
+    /// Who (or which builder) stored the snapshot, if specified.
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
 /// A snapshot is identified by a string tag and specifies a list of files.
 ///
 /// The list of files can be a complete list or a list diff.
@@ -274,6 +361,9 @@ pub struct FileEntry {
     pub checksum: ObjectChecksum,
     pub object_metadata: ObjectMetadata,
     pub file_metadata: FileMetadata,
+    /// The extended attributes of the file, sorted by name. Empty if none were
+    /// recorded.
+    pub xattrs: Vec<Xattr>,
 }
 
 impl FileEntry {
@@ -288,6 +378,7 @@ impl FileEntry {
             checksum,
             object_metadata,
             file_metadata,
+            xattrs: vec![],
         }
     }
 }
@@ -298,6 +389,7 @@ pub struct FileEntryRef<'a> {
     pub checksum: &'a ObjectChecksum,
     pub object_metadata: &'a ObjectMetadata,
     pub file_metadata: FileMetadata,
+    pub xattrs: &'a [Xattr],
 }
 
 impl<'a> FileEntryRef<'a> {
@@ -312,6 +404,7 @@ impl<'a> FileEntryRef<'a> {
             checksum,
             object_metadata,
             file_metadata,
+            xattrs: &[],
         }
     }
 }
@@ -321,26 +414,138 @@ impl<'a> From<FileEntryRef<'a>> for FileEntry {
         FileEntry {
             path: entry_ref.path.to_owned(),
             checksum: *entry_ref.checksum,
-            object_metadata: *entry_ref.object_metadata,
+            object_metadata: entry_ref.object_metadata.clone(),
             file_metadata: entry_ref.file_metadata,
+            xattrs: entry_ref.xattrs.to_vec(),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct ObjectMetadata {
+    /// The offset of the object in the pack (of its first chunk, if it is
+    /// stored as chunks).
+    pub offset: u64,
+    /// The size of the object (not of its delta, if it is stored as one).
+    pub size: u64,
+    /// Set if the object is stored as a delta. Omitted from the serialized
+    /// metadata otherwise, so objects stored in full are serialized as in
+    /// indexes created before deltas were introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<ObjectDelta>,
+    /// Set if the object is stored as chunks, which are concatenated to get
+    /// the object. The chunks are serialized separately (see
+    /// [`PackIndex::object_chunks`]).
+    #[serde(skip)]
+    pub chunks: Option<Vec<ObjectChunk>>,
+    /// Set if the object is not stored in the pack, but in the named pack
+    /// (see [`PackIndex::set_external_objects`]), whose index gives its
+    /// location. The offset is then 0, with no delta nor chunks. Serialized
+    /// separately, like the chunks.
+    #[serde(skip)]
+    pub pack: Option<String>,
+}
+
+impl ObjectMetadata {
+    /// The number of bytes stored at the offset of the object.
+    pub fn stored_size(&self) -> u64 {
+        if self.pack.is_some() {
+            return 0;
+        }
+        match (&self.delta, &self.chunks) {
+            (Some(delta), _) => delta.delta_size,
+            (None, Some(_)) => 0,
+            (None, None) => self.size,
+        }
+    }
+}
+
+/// Describes an object stored as a delta against another (base) object of the
+/// same pack. The base object is stored in full, before the object, in the same
+/// frame.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct ObjectDelta {
+    pub base_offset: u64,
+    pub base_size: u64,
+    /// The size of the delta, which is stored at the offset of the object.
+    pub delta_size: u64,
+}
+
+/// A chunk of an object stored as chunks. Chunks are stored once per pack,
+/// even if they are part of several objects.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct ObjectChunk {
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// The compression parameters a pack was created with (see
+/// [`PackIndex::compression`]).
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CompressionParameters {
+    pub codec: Codec,
+    pub level: i32,
+    pub window_log: u32,
+}
+
+/// The location of a frame in the .pack file (see [`PackIndex::frames`]).
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FrameBoundary {
+    /// The offset of the frame in the .pack file, in bytes.
     pub offset: u64,
+    /// The size of the frame, in bytes.
     pub size: u64,
+    /// The size of the data stream in the frame, once decompressed, in bytes.
+    pub decompressed_size: u64,
+}
+
+/// How a [`PackIndex`] is serialized (after its header).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// The index is a MessagePack tuple, which is deserialized in full.
+    #[default]
+    Msgpack,
+    /// The index consists of arrays of fixed-size records, which can be
+    /// queried in place, without deserializing the index (see [`FlatIndex`]).
+    /// Only read by the versions of elfshaker supporting index version 9.
+    Flat,
+}
+
+impl FromStr for IndexFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "msgpack" => Ok(IndexFormat::Msgpack),
+            "flat" => Ok(IndexFormat::Flat),
+            _ => Err(format!(
+                "Unknown index format '{s}' (expected msgpack or flat)"
+            )),
+        }
+    }
 }
 
 /// Contains the metadata needed to extract files from a pack file.
 pub struct PackIndex {
     snapshot_tags: Vec<String>,
     snapshot_deltas: Vec<ChangeSet<FileHandle>>,
+    snapshot_metadata: Vec<SnapshotMetadata>,
+    /// Stored in the index header, rather than in the serialized index.
+    checksum_algorithm: ChecksumAlgorithm,
+    /// The compression parameters of the pack, unknown for loose snapshots and
+    /// for the packs created before they were recorded.
+    compression: Option<CompressionParameters>,
+    /// The frames of the pack, as in the header of the .pack file, empty for
+    /// loose snapshots and for the packs created before they were recorded.
+    frames: Vec<FrameBoundary>,
+    /// The format the index is written in (and was read from).
+    format: IndexFormat,
 
     path_pool: EntryPool<OsString>,
     object_pool: EntryPool<ObjectChecksum>,
     object_metadata: BTreeMap<Handle, ObjectMetadata>,
+    /// The distinct sets of extended attributes of the files.
+    xattrs_pool: EntryPool<Vec<Xattr>>,
 
     // When snapshots are pushed, maintain the current state of the filesystem.
     // Not stored on disk.
@@ -357,42 +562,169 @@ impl Default for PackIndex {
 
 impl PackIndex {
     pub fn new() -> Self {
+        Self::with_checksum_algorithm(ChecksumAlgorithm::default())
+    }
+
+    /// Creates an empty index, for objects whose checksums are computed with
+    /// the specified algorithm.
+    pub fn with_checksum_algorithm(checksum_algorithm: ChecksumAlgorithm) -> Self {
         Self {
             snapshot_tags: Vec::new(),
             snapshot_deltas: Vec::new(),
+            snapshot_metadata: Vec::new(),
+            checksum_algorithm,
+            compression: None,
+            frames: Vec::new(),
+            format: IndexFormat::default(),
 
             path_pool: EntryPool::new(),
             object_pool: EntryPool::new(),
             object_metadata: BTreeMap::new(),
+            xattrs_pool: EntryPool::new(),
 
             current: HashSet::new(),
         }
     }
 
+    /// The algorithm used to compute the checksums of the objects.
+    pub fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.checksum_algorithm
+    }
+    /// The compression parameters the pack was created with, if known.
+    pub fn compression(&self) -> Option<CompressionParameters> {
+        self.compression
+    }
+    pub fn set_compression(&mut self, compression: CompressionParameters) {
+        self.compression = Some(compression);
+    }
+    /// The frames of the pack, in order, if known. The object offsets are
+    /// offsets into the concatenation of the decompressed frames, so the
+    /// frames containing an object can be found without reading the .pack
+    /// file (e.g. to fetch only these frames of a remote pack).
+    pub fn frames(&self) -> &[FrameBoundary] {
+        &self.frames
+    }
+    pub fn set_frames(&mut self, frames: Vec<FrameBoundary>) {
+        self.frames = frames;
+    }
+    /// The format the index is serialized in (see [`IndexFormat`]).
+    pub fn format(&self) -> IndexFormat {
+        self.format
+    }
+    pub fn set_format(&mut self, format: IndexFormat) {
+        self.format = format;
+    }
     pub fn object_size_total(&self) -> u64 {
         self.object_metadata.values().map(|x| x.size).sum()
     }
     pub fn object_checksums(&self) -> impl ExactSizeIterator<Item = &ObjectChecksum> {
         self.object_pool.iter()
     }
+    /// The paths of the files of the snapshots (and of the removed snapshots,
+    /// until the index is compacted).
+    pub fn file_paths(&self) -> impl ExactSizeIterator<Item = &OsString> {
+        self.path_pool.iter()
+    }
     pub fn object_metadata(&self, checksum: &ObjectChecksum) -> &ObjectMetadata {
         let handle = self.object_pool.get(checksum).unwrap();
         self.object_metadata.get(&handle).unwrap()
     }
+    /// Same as [`PackIndex::object_metadata`], or [`None`] if the index has no
+    /// such object.
+    pub fn lookup_object_metadata(&self, checksum: &ObjectChecksum) -> Option<&ObjectMetadata> {
+        let handle = self.object_pool.get(checksum)?;
+        self.object_metadata.get(&handle)
+    }
+    /// The chunks of the objects stored as chunks, which are serialized
+    /// separately from the rest of the object metadata.
+    fn object_chunks(&self) -> BTreeMap<Handle, &[ObjectChunk]> {
+        self.object_metadata
+            .iter()
+            .filter_map(|(handle, md)| Some((*handle, md.chunks.as_deref()?)))
+            .collect()
+    }
+    /// The packs storing the objects which are not stored in this pack (see
+    /// [`ObjectMetadata::pack`]), serialized separately from the rest of the
+    /// object metadata.
+    fn object_packs(&self) -> BTreeMap<Handle, &str> {
+        self.object_metadata
+            .iter()
+            .filter_map(|(handle, md)| Some((*handle, md.pack.as_deref()?)))
+            .collect()
+    }
+    /// The names of the packs storing some of the objects of the index.
+    pub fn referenced_packs(&self) -> BTreeSet<&str> {
+        self.object_metadata
+            .values()
+            .filter_map(|md| md.pack.as_deref())
+            .collect()
+    }
+    /// Records that the objects are stored in the named packs (see
+    /// [`ObjectMetadata::pack`]), instead of in this pack. The objects which are
+    /// not in `objects` are stored in this pack (at an offset which is computed
+    /// when the pack is written).
+    pub(crate) fn set_external_objects(&mut self, objects: &HashMap<ObjectChecksum, String>) {
+        for (handle, md) in self.object_metadata.iter_mut() {
+            let checksum = self.object_pool.lookup(*handle).unwrap();
+            md.pack = objects.get(checksum).cloned();
+            if md.pack.is_some() {
+                md.offset = 0;
+                md.delta = None;
+                md.chunks = None;
+            }
+        }
+    }
+    /// The number of bytes stored for the object group (see
+    /// [`PackIndex::compute_object_offsets_and_ordering`]).
+    pub(crate) fn group_stored_size(&self, group: &[Handle]) -> u64 {
+        group
+            .iter()
+            .map(|handle| self.object_metadata.get(handle).unwrap().stored_size())
+            .sum()
+    }
+    #[cfg(feature = "repo")]
     pub(crate) fn objects_partitioned_by_size<'l>(
         &self,
         partitions: u32,
-        handles: &'l [Handle],
-    ) -> Vec<&'l [Handle]> {
-        partition_by_u64(handles, partitions, |handle| {
-            self.object_metadata.get(handle).unwrap().size
-        })
+        groups: &'l [Vec<Handle>],
+    ) -> Vec<&'l [Vec<Handle>]> {
+        partition_by_u64(groups, partitions, |group| self.group_stored_size(group))
+    }
+    /// Splits the object groups into consecutive runs, whose total size is at
+    /// most `max_size` (unless a single group is larger than that).
+    #[cfg(feature = "repo")]
+    pub(crate) fn objects_split_by_size<'l>(
+        &self,
+        max_size: u64,
+        groups: &'l [Vec<Handle>],
+    ) -> Vec<&'l [Vec<Handle>]> {
+        split_by_u64(groups, max_size, |group| self.group_stored_size(group))
     }
-    pub(crate) fn compute_object_offsets_and_ordering(mut self) -> (Self, Vec<Handle>) {
+    /// Computes the offsets of the objects in the pack and the order in which
+    /// they are stored. `deltas` maps the objects to store as deltas to their
+    /// base object and the size of their delta. `chunks` maps the objects to
+    /// store as chunks to their chunks, whose offsets are relative to the end
+    /// of the other objects, where the chunks are stored.
+    ///
+    /// The objects which are not stored as chunks are returned in groups, each
+    /// made of an object stored in full, followed by the objects stored as
+    /// deltas against it. Groups must not be split across frames.
+    ///
+    /// The groups are sorted by the key of their base object in `keys` (e.g.
+    /// its similarity signature), then by its size.
+    pub(crate) fn compute_object_offsets_and_ordering<K: Ord>(
+        mut self,
+        deltas: &HashMap<Handle, (Handle, u64)>,
+        chunks: &HashMap<Handle, Vec<ObjectChunk>>,
+        keys: &HashMap<Handle, K>,
+    ) -> (Self, Vec<Vec<Handle>>) {
         let mut size_handle = self
             .object_metadata
             .iter()
-            .map(|(handle, om)| (om.size, *handle))
+            .filter(|(handle, om)| {
+                om.pack.is_none() && !deltas.contains_key(handle) && !chunks.contains_key(handle)
+            })
+            .map(|(handle, om)| (keys.get(handle), om.size, *handle))
             .collect::<Vec<_>>();
 
         // Heuristic for good compression: Sort objects by size. This happens to
@@ -401,19 +733,74 @@ impl PackIndex {
         // were added to the object pool.
         size_handle.sort_unstable();
 
+        let mut groups: Vec<Vec<Handle>> = size_handle
+            .into_iter()
+            .map(|(_key, _size, handle)| vec![handle])
+            .collect();
+        let group_of_base: HashMap<Handle, usize> = groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| (group[0], i))
+            .collect();
+        let mut delta_handles: Vec<_> = deltas.keys().copied().collect();
+        delta_handles.sort_unstable();
+        for handle in delta_handles {
+            let (base, _) = deltas[&handle];
+            groups[group_of_base[&base]].push(handle);
+        }
+
         // Update object metadata to reference new offsets.
         let mut offset = 0;
-        for (size, handle) in &size_handle {
-            self.object_metadata.get_mut(handle).unwrap().offset = offset;
-            offset += size;
+        for group in &groups {
+            // The base is the first object of the group.
+            let base_offset = offset;
+            let base_size = self.object_metadata[&group[0]].size;
+            for handle in group {
+                let md = self.object_metadata.get_mut(handle).unwrap();
+                md.offset = offset;
+                md.delta = deltas.get(handle).map(|&(_, delta_size)| ObjectDelta {
+                    base_offset,
+                    base_size,
+                    delta_size,
+                });
+                md.chunks = None;
+                offset += md.stored_size();
+            }
         }
 
-        let handles = size_handle
-            .into_iter()
-            .map(|(_size, handle)| handle)
-            .collect();
+        for (handle, chunks) in chunks {
+            let chunks: Vec<_> = chunks
+                .iter()
+                .map(|chunk| ObjectChunk {
+                    offset: offset + chunk.offset,
+                    size: chunk.size,
+                })
+                .collect();
+            let md = self.object_metadata.get_mut(handle).unwrap();
+            md.offset = chunks.first().map_or(offset, |chunk| chunk.offset);
+            md.delta = None;
+            md.chunks = Some(chunks);
+        }
+
+        (self, groups)
+    }
 
-        (self, handles)
+    /// Lists the distinct objects of each path, in the order in which they are
+    /// added by the snapshots. Objects which are referenced by several paths are
+    /// only listed once, for the first path referencing them. Empty objects and
+    /// objects stored in other packs are not listed.
+    pub(crate) fn object_versions_by_path(&self) -> Vec<Vec<Handle>> {
+        let mut seen = HashSet::new();
+        let mut versions: BTreeMap<Handle, Vec<Handle>> = BTreeMap::new();
+        for changes in &self.snapshot_deltas {
+            for file in changes.added() {
+                let md = &self.object_metadata[&file.object];
+                if md.size != 0 && md.pack.is_none() && seen.insert(file.object) {
+                    versions.entry(file.path).or_default().push(file.object);
+                }
+            }
+        }
+        versions.into_values().collect()
     }
 
     pub fn handle_to_checksum(&self, h: Handle) -> &ObjectChecksum {
@@ -430,8 +817,9 @@ impl PackIndex {
                 .object_pool
                 .lookup(handle.object)
                 .ok_or(PackError::ObjectNotFound)?,
-            object_metadata: *self.object_metadata.get(&handle.object).unwrap(),
+            object_metadata: self.object_metadata[&handle.object].clone(),
             file_metadata: handle.file_metadata,
+            xattrs: self.lookup_xattrs(handle)?.to_vec(),
         })
     }
     pub fn handle_to_entry_ref<'a>(
@@ -449,16 +837,33 @@ impl PackIndex {
                 .ok_or(PackError::ObjectNotFound)?,
             object_metadata: self.object_metadata.get(&handle.object).unwrap(),
             file_metadata: handle.file_metadata,
+            xattrs: self.lookup_xattrs(handle)?,
         })
     }
+    fn lookup_xattrs(&self, handle: &FileHandle) -> Result<&[Xattr], PackError> {
+        match handle.xattrs {
+            None => Ok(&[]),
+            Some(xattrs) => self
+                .xattrs_pool
+                .lookup(xattrs)
+                .map(|xattrs| &xattrs[..])
+                .ok_or_else(|| PackError::InconsistentIndex(format!("xattrs {xattrs} not found"))),
+        }
+    }
     pub fn entry_to_handle(&mut self, entry: &FileEntry) -> Result<FileHandle, PackError> {
         let object_handle = self.object_pool.get_or_insert(&entry.checksum);
         self.object_metadata
-            .insert(object_handle, entry.object_metadata);
+            .insert(object_handle, entry.object_metadata.clone());
+        let xattrs = if entry.xattrs.is_empty() {
+            None
+        } else {
+            Some(self.xattrs_pool.get_or_insert(&entry.xattrs[..]))
+        };
         Ok(FileHandle {
             path: self.path_pool.get_or_insert(&entry.path),
             object: object_handle,
             file_metadata: entry.file_metadata,
+            xattrs,
         })
     }
 
@@ -505,9 +910,16 @@ impl PackIndex {
             return Err(PackError::SnapshotAlreadyExists("<unknown>".into(), tag));
         }
 
-        let files = input
-            .into_iter()
-            .map(|e| self.entry_to_handle(&e.into()))
+        let entries: Vec<FileEntry> = input.into_iter().map(Into::into).collect();
+        check_symlink_parents(
+            &tag,
+            entries
+                .iter()
+                .map(|e| (e.path.as_os_str(), e.file_metadata.is_symlink())),
+        )?;
+        let files = entries
+            .iter()
+            .map(|e| self.entry_to_handle(e))
             .collect::<Result<_, _>>()?;
 
         // Compute delta against last pushed snapshot (temporary implementation).
@@ -515,6 +927,71 @@ impl PackIndex {
         self.current = files;
         self.snapshot_tags.push(tag);
         self.snapshot_deltas.push(delta);
+        self.snapshot_metadata.push(SnapshotMetadata::default());
+        Ok(())
+    }
+    /// Returns the metadata of the snapshot, or [`None`] if no such snapshot exists.
+    pub fn snapshot_metadata(&self, tag: &str) -> Option<&SnapshotMetadata> {
+        self.snapshot_tags
+            .iter()
+            .position(|s| s == tag)
+            .map(|i| &self.snapshot_metadata[i])
+    }
+    /// Replaces the metadata of the snapshot.
+    pub fn set_snapshot_metadata(
+        &mut self,
+        tag: &str,
+        metadata: SnapshotMetadata,
+    ) -> Result<(), PackError> {
+        let i = self
+            .snapshot_tags
+            .iter()
+            .position(|s| s == tag)
+            .ok_or_else(|| PackError::SnapshotNotFound(tag.to_owned()))?;
+        self.snapshot_metadata[i] = metadata;
+        Ok(())
+    }
+    /// Changes the tag of the snapshot.
+    pub fn rename_snapshot(&mut self, tag: &str, new_tag: &str) -> Result<(), PackError> {
+        if self.has_snapshot(new_tag) {
+            return Err(PackError::SnapshotAlreadyExists(
+                "<unknown>".into(),
+                new_tag.to_owned(),
+            ));
+        }
+        let snapshot = self
+            .snapshot_tags
+            .iter_mut()
+            .find(|s| *s == tag)
+            .ok_or_else(|| PackError::SnapshotNotFound(tag.to_owned()))?;
+        *snapshot = new_tag.to_owned();
+        Ok(())
+    }
+    /// Replaces the checksum of each object with the one returned by `f`,
+    /// which must be computed with the specified algorithm. Used to convert
+    /// the index to another checksum algorithm.
+    pub fn convert_checksums<F>(
+        &mut self,
+        checksum_algorithm: ChecksumAlgorithm,
+        mut f: F,
+    ) -> Result<(), PackError>
+    where
+        F: FnMut(&ObjectChecksum) -> Option<ObjectChecksum>,
+    {
+        let checksums = self
+            .object_pool
+            .iter()
+            .map(|checksum| f(checksum).ok_or(PackError::ObjectNotFound))
+            .collect::<Result<Vec<_>, _>>()?;
+        // The handles are preserved, since the pool is rebuilt in order.
+        let object_pool: EntryPool<ObjectChecksum> = checksums.iter().collect();
+        if object_pool.iter().len() != checksums.len() {
+            return Err(PackError::InconsistentIndex(
+                "the converted object checksums are not unique".into(),
+            ));
+        }
+        self.object_pool = object_pool;
+        self.checksum_algorithm = checksum_algorithm;
         Ok(())
     }
     // Call the closure F with materialized file entries for each snapshot.
@@ -552,35 +1029,349 @@ impl PackIndex {
         }
         Ok(None)
     }
+    /// Finds the snapshots which contain the object with the given checksum.
+    /// Returns the tag of each such snapshot (in the order they are stored in
+    /// the index), along with the paths at which the object is found.
+    pub fn find_object(
+        &self,
+        checksum: &ObjectChecksum,
+    ) -> Result<Vec<(&str, Vec<&OsStr>)>, PackError> {
+        let object = match self.object_pool.get(checksum) {
+            Some(handle) => handle,
+            None => return Ok(vec![]),
+        };
+
+        let mut results = vec![];
+        // Only track the files referencing the object
+        let mut current = hashbrown::HashSet::new();
+        let snapshot_deltas = self.snapshot_tags.iter().zip(self.snapshot_deltas.iter());
+        for (snapshot, deltas) in snapshot_deltas {
+            let filter = |handles: &Vec<FileHandle>| -> Result<_, PackError> {
+                Ok(handles
+                    .iter()
+                    .filter(|h| h.object == object)
+                    .copied()
+                    .collect::<Vec<_>>())
+            };
+            Snapshot::apply_changes(&mut current, &deltas.map(filter)?);
+            if !current.is_empty() {
+                let mut paths = current
+                    .iter()
+                    .map(|h| {
+                        self.path_pool
+                            .lookup(h.path)
+                            .map(|p| p.as_os_str())
+                            .ok_or(PackError::PathNotFound(h.path))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                paths.sort();
+                results.push((snapshot.as_str(), paths));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Finds the snapshots containing files whose path matches, along with
+    /// the matching paths (like [`PackIndex::find_object`]).
+    pub fn find_paths<F>(&self, is_match: F) -> Result<Vec<(&str, Vec<&OsStr>)>, PackError>
+    where
+        F: Fn(&OsStr) -> bool,
+    {
+        // Each path is only matched once, rather than once per snapshot.
+        let matching: hashbrown::HashSet<Handle> = self
+            .path_pool
+            .iter()
+            .enumerate()
+            .filter(|(_, path)| is_match(path))
+            .map(|(handle, _)| handle as Handle)
+            .collect();
+        if matching.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut results = vec![];
+        let mut current = hashbrown::HashSet::new();
+        let snapshot_deltas = self.snapshot_tags.iter().zip(self.snapshot_deltas.iter());
+        for (snapshot, deltas) in snapshot_deltas {
+            let filter = |handles: &Vec<FileHandle>| -> Result<_, PackError> {
+                Ok(handles
+                    .iter()
+                    .filter(|h| matching.contains(&h.path))
+                    .copied()
+                    .collect::<Vec<_>>())
+            };
+            Snapshot::apply_changes(&mut current, &deltas.map(filter)?);
+            if !current.is_empty() {
+                let mut paths = current
+                    .iter()
+                    .map(|h| {
+                        self.path_pool
+                            .lookup(h.path)
+                            .map(|p| p.as_os_str())
+                            .ok_or(PackError::PathNotFound(h.path))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                paths.sort();
+                results.push((snapshot.as_str(), paths));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Checks that the snapshot deltas can be applied in order (only files
+    /// which are present get removed and only absent files get added), and
+    /// that all handles reference existing paths and objects.
+    pub fn check_consistency(&self) -> Result<(), PackError> {
+        self.check_paths()?;
+        let object_count = self.object_pool.iter().len();
+        if self.object_metadata.len() != object_count {
+            return Err(PackError::InconsistentIndex(format!(
+                "{} objects, but {} object metadata entries",
+                object_count,
+                self.object_metadata.len()
+            )));
+        }
+        if self.snapshot_tags.len() != self.snapshot_deltas.len() {
+            return Err(PackError::InconsistentIndex(format!(
+                "{} snapshots, but {} snapshot deltas",
+                self.snapshot_tags.len(),
+                self.snapshot_deltas.len()
+            )));
+        }
+        if self.snapshot_tags.len() != self.snapshot_metadata.len() {
+            return Err(PackError::InconsistentIndex(format!(
+                "{} snapshots, but {} snapshot metadata entries",
+                self.snapshot_tags.len(),
+                self.snapshot_metadata.len()
+            )));
+        }
+
+        let checksum_size = self.checksum_algorithm.size();
+        if let Some(checksum) = self
+            .object_pool
+            .iter()
+            .find(|checksum| checksum.len() != checksum_size)
+        {
+            return Err(PackError::InconsistentIndex(format!(
+                "object {} is not a {} checksum",
+                hex::encode(checksum),
+                self.checksum_algorithm
+            )));
+        }
+
+        // Delta bases must be objects stored in full, before the delta.
+        let full_objects: HashSet<_> = self
+            .object_metadata
+            .values()
+            .filter(|md| md.pack.is_none() && md.delta.is_none() && md.chunks.is_none())
+            .map(|md| (md.offset, md.size))
+            .collect();
+        // The objects stored in other packs are checked against their index.
+        for (handle, md) in self
+            .object_metadata
+            .iter()
+            .filter(|(_, md)| md.pack.is_none())
+        {
+            if let Some(chunks) = &md.chunks {
+                if md.delta.is_some()
+                    || chunks.iter().map(|chunk| chunk.size).sum::<u64>() != md.size
+                {
+                    return Err(PackError::InconsistentIndex(format!(
+                        "object {} has inconsistent chunks",
+                        hex::encode(self.handle_to_checksum(*handle))
+                    )));
+                }
+            }
+            if let Some(delta) = md.delta {
+                if delta.base_offset >= md.offset
+                    || !full_objects.contains(&(delta.base_offset, delta.base_size))
+                {
+                    return Err(PackError::InconsistentIndex(format!(
+                        "object {} is a delta against a missing object",
+                        hex::encode(self.handle_to_checksum(*handle))
+                    )));
+                }
+            }
+        }
+
+        let mut current = hashbrown::HashSet::new();
+        let snapshot_deltas = self.snapshot_tags.iter().zip(self.snapshot_deltas.iter());
+        for (snapshot, deltas) in snapshot_deltas {
+            let error = |message: &str, handle: &FileHandle| {
+                PackError::InconsistentIndex(format!(
+                    "snapshot '{snapshot}': {message} ({handle:?})"
+                ))
+            };
+            for removed in &deltas.removed {
+                if !current.remove(removed) {
+                    return Err(error("removes a file which is not present", removed));
+                }
+            }
+            for added in &deltas.added {
+                if self.path_pool.lookup(added.path).is_none() {
+                    return Err(error("references a missing path", added));
+                }
+                if self.object_pool.lookup(added.object).is_none() {
+                    return Err(error("references a missing object", added));
+                }
+                if !current.insert(*added) {
+                    return Err(error("adds a file which is already present", added));
+                }
+            }
+        }
+
+        let result = self.for_each_snapshot(|snapshot, entries| {
+            let paths = entries
+                .iter()
+                .map(|e| (e.path, e.file_metadata.is_symlink()));
+            match check_symlink_parents(snapshot, paths) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(e) => ControlFlow::Break(e),
+            }
+        })?;
+        result.map_or(Ok(()), Err)
+    }
+
+    /// Checks that the paths of the files are valid (see
+    /// [`is_valid_entry_path`]), since the indexes can come from anywhere.
+    fn check_paths(&self) -> Result<(), PackError> {
+        match self
+            .path_pool
+            .iter()
+            .find(|path| !is_valid_entry_path(path))
+        {
+            Some(path) => Err(invalid_path_error(path)),
+            None => Ok(()),
+        }
+    }
+
     /// Computes the checksum of the contents of the snapshot.
     pub fn compute_snapshot_checksum(&self, snapshot: &str) -> Option<ObjectChecksum> {
         let handles = self.resolve_snapshot(snapshot)?;
         // Map FileHandle to FileEntry, which contains path and checksum
         let mut entries = self.entry_refs_from_handles(handles.iter()).ok()?;
         entries.sort_by(|a, b| a.checksum.cmp(b.checksum).then(a.path.cmp(b.path)));
-        let mut hasher = entries.into_iter().fold(Sha1::new(), |mut hasher, entry| {
-            hasher.input(&os_str_as_bytes(entry.path));
-            hasher.input(entry.checksum);
-            hasher.input(&entry.file_metadata.mode.to_be_bytes());
-            hasher
-        });
-        let mut checksum: ObjectChecksum = [0; 20];
-        hasher.result(&mut checksum);
-        Some(checksum)
+        let mut hasher = self.checksum_algorithm.hasher();
+        for entry in entries {
+            hasher.update(&os_str_as_bytes(entry.path));
+            hasher.update(entry.checksum);
+            hasher.update(&entry.file_metadata.mode.to_be_bytes());
+        }
+        Some(hasher.finalize())
+    }
+}
+
+/// Whether the path of a file of a snapshot is relative, without `.` or `..`
+/// components, so that it is extracted inside the directory it is extracted to.
+pub(crate) fn is_valid_entry_path(path: &OsStr) -> bool {
+    let mut components = Path::new(path).components().peekable();
+    components.peek().is_some()
+        && components.all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// The error for an invalid path (see [`is_valid_entry_path`]) of the index.
+fn invalid_path_error(path: &OsStr) -> PackError {
+    PackError::InconsistentIndex(format!(
+        "the path {} is not a relative path inside the snapshot",
+        path.to_string_lossy()
+    ))
+}
+
+/// Checks that none of the paths of the snapshot (along with whether each is
+/// a symbolic link) is inside one of its symbolic links, since extracting the
+/// snapshot would then write through the link, possibly outside of the
+/// extracted directory.
+fn check_symlink_parents<'a>(
+    tag: &str,
+    paths: impl Iterator<Item = (&'a OsStr, bool)> + Clone,
+) -> Result<(), PackError> {
+    let symlinks: HashSet<&Path> = paths
+        .clone()
+        .filter(|(_, is_symlink)| *is_symlink)
+        .map(|(path, _)| Path::new(path))
+        .collect();
+    if symlinks.is_empty() {
+        return Ok(());
+    }
+    for (path, _) in paths {
+        if Path::new(path)
+            .ancestors()
+            .skip(1)
+            .any(|parent| symlinks.contains(parent))
+        {
+            return Err(PackError::SymlinkInPath(tag.to_owned(), path.to_owned()));
+        }
     }
+    Ok(())
 }
 
+/// Returns the bytes of the path (or of the target of a symbolic link), as
+/// stored in the indexes. The paths which are not valid Unicode are stored
+/// lossily on the systems whose paths are not bytes (i.e. Windows).
 #[cfg(unix)]
-fn os_str_as_bytes(os_str: &OsStr) -> Cow<[u8]> {
+pub fn os_str_as_bytes(os_str: &OsStr) -> Cow<'_, [u8]> {
     Cow::Borrowed(std::os::unix::ffi::OsStrExt::as_bytes(os_str))
 }
 
 #[cfg(not(unix))]
-fn os_str_as_bytes(os_str: &OsStr) -> Cow<[u8]> {
-    Cow::Owned(os_str.to_string_lossy().as_bytes())
+pub fn os_str_as_bytes(os_str: &OsStr) -> Cow<[u8]> {
+    Cow::Owned(os_str.to_string_lossy().into_owned().into_bytes())
+}
+
+/// The inverse of [`os_str_as_bytes`].
+#[cfg(unix)]
+pub fn bytes_to_os_string(bytes: &[u8]) -> OsString {
+    <OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(bytes).to_owned()
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_os_string(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+/// The paths of the path pool are serialized as Serde serializes an `OsString`
+/// on Unix (the `Unix` variant, with the bytes of the path), whatever the
+/// system creating the index, so that the indexes can be read everywhere.
+struct SerializedPathPool<'a>(&'a EntryPool<OsString>);
+
+impl Serialize for SerializedPathPool<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(|path| SerializedPathRef(path)))
+    }
+}
+
+struct SerializedPathRef<'a>(&'a OsStr);
+
+impl Serialize for SerializedPathRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_variant("OsString", 0, "Unix", &*os_str_as_bytes(self.0))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "OsString")]
+enum SerializedPath {
+    Unix(Vec<u8>),
+    Windows(Vec<u16>),
+}
+
+impl From<SerializedPath> for OsString {
+    fn from(path: SerializedPath) -> Self {
+        match path {
+            SerializedPath::Unix(bytes) => bytes_to_os_string(&bytes),
+            SerializedPath::Windows(wide) => String::from_utf16_lossy(&wide).into(),
+        }
+    }
 }
 
 impl PackIndex {
+    #[cfg(feature = "repo")]
     pub fn load<P: AsRef<Path>>(p: P) -> Result<PackIndex, PackError> {
         let rd = open_file(p.as_ref())?;
         Self::parse(rd)
@@ -588,51 +1379,195 @@ impl PackIndex {
 
     pub fn parse<R: Read>(rd: R) -> Result<PackIndex, PackError> {
         let mut rd = BufReader::new(rd);
-        Self::read_magic(&mut rd)?;
+        let (version, checksum_algorithm) = Self::read_header(&mut rd)?;
+        if version == flat::VERSION {
+            return Self::read_flat(rd, checksum_algorithm)?.to_pack_index();
+        }
 
-        Ok(rmp_serde::decode::from_read(rd)?)
+        let mut index: PackIndex = rmp_serde::decode::from_read(rd)?;
+        index.checksum_algorithm = checksum_algorithm;
+        index.check_paths()?;
+        Ok(index)
     }
 
+    #[cfg(feature = "repo")]
     pub fn load_only_snapshots<P: AsRef<Path>>(p: P) -> Result<Vec<String>, PackError> {
-        let rd = open_file(p.as_ref())?;
+        Self::parse_only_snapshots(open_file(p.as_ref())?)
+    }
+
+    pub fn parse_only_snapshots<R: Read>(rd: R) -> Result<Vec<String>, PackError> {
         let mut rd = BufReader::new(rd);
-        Self::read_magic(&mut rd)?;
+        let (version, checksum_algorithm) = Self::read_header(&mut rd)?;
+        if version == flat::VERSION {
+            return Self::read_flat(rd, checksum_algorithm)?.snapshot_tags();
+        }
         let mut d = rmp_serde::Deserializer::new(rd);
         Ok(PackIndex::deserialize_only_snapshots(&mut d)?.snapshot_tags)
     }
 
+    /// Reads the checksum algorithm of the index from its header, without
+    /// loading the rest of the index.
+    #[cfg(feature = "repo")]
+    pub fn load_only_checksum_algorithm<P: AsRef<Path>>(
+        p: P,
+    ) -> Result<ChecksumAlgorithm, PackError> {
+        Self::parse_only_checksum_algorithm(open_file(p.as_ref())?)
+    }
+
+    pub fn parse_only_checksum_algorithm<R: Read>(
+        mut rd: R,
+    ) -> Result<ChecksumAlgorithm, PackError> {
+        Ok(Self::read_header(&mut rd)?.1)
+    }
+
+    /// Reads the rest of a flat index, after its header.
+    fn read_flat(
+        mut rd: impl Read,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> Result<FlatIndex, PackError> {
+        let mut bytes = vec![];
+        Self::write_header(&mut bytes, flat::VERSION, checksum_algorithm)?;
+        rd.read_to_end(&mut bytes)?;
+        FlatIndex::from_bytes(bytes)
+    }
+
+    /// Saves the index to the file, which is replaced rather than modified in
+    /// place, since flat indexes are mapped into memory by the processes
+    /// reading them (see [`FlatIndex::map`]).
+    #[cfg(feature = "repo")]
     pub fn save<P: AsRef<Path>>(&self, p: P) -> Result<(), PackError> {
-        // TODO: Use AtomicCreateFile.
-        let wr = create_file(p.as_ref(), None)?;
-        let mut wr = BufWriter::new(wr);
-        Self::write_magic(&mut wr)?;
+        let mut write_error = None;
+        replace_file(p.as_ref(), |temp_path| {
+            let mut wr = BufWriter::new(create_file(temp_path, None)?);
+            if let Err(e) = self.write(&mut wr) {
+                let io_error = std::io::Error::other(e.to_string());
+                write_error = Some(e);
+                return Err(io_error);
+            }
+            // The index may be renamed in place once saved, so make sure that
+            // its contents are on disk before its name is.
+            wr.into_inner().map_err(|e| e.into_error())?.sync_data()
+        })
+        .map_err(|e| write_error.unwrap_or(PackError::IOError(e)))
+    }
+
+    pub fn write<W: Write>(&self, wr: W) -> Result<(), PackError> {
+        self.write_as(self.format, wr)
+    }
 
-        rmp_serde::encode::write(&mut wr, self)?;
+    /// Writes the index in the format, whichever format it was read from.
+    pub fn write_as<W: Write>(&self, format: IndexFormat, mut wr: W) -> Result<(), PackError> {
+        Self::write_header(&mut wr, self.version_as(format), self.checksum_algorithm)?;
+        match format {
+            IndexFormat::Msgpack => rmp_serde::encode::write(&mut wr, self)?,
+            IndexFormat::Flat => flat::write(self, &mut wr)?,
+        }
+        wr.flush()?;
         Ok(())
     }
 
     // Max supported version of the pack index by this version of elfshaker.
     // It's intended to remain backwards compatible with previous packs.
     // When changing the on-disk format it's necessary to bump this integer.
-    const MAX_VERSION: u32 = 2;
+    //
+    // Version 3 adds the snapshot metadata.
+    // Version 4 adds the checksum algorithm to the header (SHA-1 before).
+    // Version 5 adds the object deltas. Indexes without deltas are still
+    // written as version 4, so older versions of elfshaker can read them.
+    // Version 6 adds the object chunks (also only written when needed).
+    // The compression parameters are appended without bumping the version,
+    // since older versions ignore the trailing values.
+    // Version 7 adds the modification times of the files (also only written
+    // when needed).
+    // Version 8 adds the extended attributes of the files (also only written
+    // when needed).
+    // The frames of the pack are appended without bumping the version, like
+    // the compression parameters.
+    // Version 9 is the flat format (see the flat module), which is only
+    // written when requested (see IndexFormat).
+    // Version 10 adds the objects stored in other packs (also only written
+    // when needed).
+    const MAX_VERSION: u32 = 10;
+
+    /// The version written in the header of the index.
+    #[cfg(test)]
+    fn version(&self) -> u32 {
+        self.version_as(self.format)
+    }
+
+    /// The version written in the header of the index, in the format.
+    fn version_as(&self, format: IndexFormat) -> u32 {
+        if format == IndexFormat::Flat {
+            return flat::VERSION;
+        }
+        if self.object_metadata.values().any(|md| md.pack.is_some()) {
+            return 10;
+        }
+        if self.xattrs_pool.iter().len() > 0 {
+            return 8;
+        }
+        let has_mtimes = self.snapshot_deltas.iter().any(|delta| {
+            delta
+                .added()
+                .iter()
+                .chain(delta.removed())
+                .any(|handle| handle.file_metadata.mtime.is_some())
+        });
+        if has_mtimes {
+            7
+        } else if self.object_metadata.values().any(|md| md.chunks.is_some()) {
+            6
+        } else if self.object_metadata.values().any(|md| md.delta.is_some()) {
+            5
+        } else {
+            4
+        }
+    }
 
-    fn read_magic(rd: &mut impl Read) -> Result<(), PackError> {
+    /// Whether the index starting with the header may store some of its
+    /// objects in other packs (see [`ObjectMetadata::pack`]). Only the indexes
+    /// created since version 10 (or whose header is unknown, e.g. encrypted)
+    /// may.
+    pub(crate) fn may_have_external_objects(header: &[u8]) -> bool {
+        match (header.get(..4), header.get(4..8)) {
+            (Some(b"ELFS"), Some(version)) => u32::from_be_bytes(version.try_into().unwrap()) >= 10,
+            _ => true,
+        }
+    }
+
+    /// Reads the magic and version of the index, then the checksum algorithm
+    /// (since version 4).
+    fn read_header(rd: &mut impl Read) -> Result<(u32, ChecksumAlgorithm), PackError> {
         let mut magic = [0; 4];
         rd.read_exact(&mut magic)?;
         if magic.ne(b"ELFS") {
             return Err(PackError::BadMagic);
         }
-        let mut version = [0; 4];
-        rd.read_exact(&mut version)?;
-        if u32::from_be_bytes(version) > PackIndex::MAX_VERSION {
-            return Err(PackError::BadPackVersion(version));
+        let mut version_bytes = [0; 4];
+        rd.read_exact(&mut version_bytes)?;
+        let version = u32::from_be_bytes(version_bytes);
+        if version > PackIndex::MAX_VERSION {
+            return Err(PackError::BadPackVersion(version_bytes));
         }
-        Ok(())
+        if version < 4 {
+            return Ok((version, ChecksumAlgorithm::Sha1));
+        }
+        let mut id = [0; 4];
+        rd.read_exact(&mut id)?;
+        let id = u32::from_be_bytes(id);
+        let checksum_algorithm =
+            ChecksumAlgorithm::from_id(id).ok_or(PackError::BadChecksumAlgorithm(id))?;
+        Ok((version, checksum_algorithm))
     }
 
-    fn write_magic(wr: &mut impl Write) -> std::io::Result<()> {
+    fn write_header(
+        wr: &mut impl Write,
+        version: u32,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> std::io::Result<()> {
         wr.write_all(b"ELFS")?;
-        wr.write_all(&u32::to_be_bytes(PackIndex::MAX_VERSION)[..])?;
+        wr.write_all(&u32::to_be_bytes(version)[..])?;
+        wr.write_all(&u32::to_be_bytes(checksum_algorithm.id())[..])?;
         Ok(())
     }
 }
@@ -670,7 +1605,10 @@ impl<'de> Visitor<'de> for VisitPackIndex {
             return Ok(result);
         }
         result.snapshot_deltas = next_expecting(&mut seq)?;
-        result.path_pool = next_expecting(&mut seq)?;
+        let paths: Vec<SerializedPath> = next_expecting(&mut seq)?;
+        for path in paths {
+            result.path_pool.get_or_insert(&OsString::from(path));
+        }
         result.object_pool = next_expecting(&mut seq)?;
         let md: Vec<ObjectMetadata> = next_expecting(&mut seq)?;
         result.object_metadata = md
@@ -678,6 +1616,36 @@ impl<'de> Visitor<'de> for VisitPackIndex {
             .enumerate()
             .map(|(i, md)| ((i as Handle), md))
             .collect();
+        // Indexes created before version 3 do not have snapshot metadata.
+        result.snapshot_metadata = seq
+            .next_element()?
+            .unwrap_or_else(|| vec![SnapshotMetadata::default(); result.snapshot_tags.len()]);
+        // Only indexes containing chunks (since version 6) have them.
+        let object_chunks: BTreeMap<Handle, Vec<ObjectChunk>> =
+            seq.next_element()?.unwrap_or_default();
+        for (handle, chunks) in object_chunks {
+            let md = result
+                .object_metadata
+                .get_mut(&handle)
+                .ok_or_else(|| serde::de::Error::custom("chunks of a missing object"))?;
+            md.chunks = Some(chunks);
+        }
+        // The compression parameters are only recorded in packed indexes (and
+        // are nil in the loose indexes followed by extended attributes).
+        result.compression = seq.next_element()?.flatten();
+        // Only indexes containing extended attributes (since version 8) have them.
+        result.xattrs_pool = seq.next_element()?.unwrap_or_default();
+        // Only the packed indexes created since the frames are recorded have them.
+        result.frames = seq.next_element()?.unwrap_or_default();
+        // Only indexes referencing other packs (since version 10) have them.
+        let object_packs: BTreeMap<Handle, String> = seq.next_element()?.unwrap_or_default();
+        for (handle, pack) in object_packs {
+            let md = result
+                .object_metadata
+                .get_mut(&handle)
+                .ok_or_else(|| serde::de::Error::custom("pack of a missing object"))?;
+            md.pack = Some(pack);
+        }
 
         Ok(result)
     }
@@ -713,10 +1681,31 @@ impl Serialize for PackIndex {
     where
         S: Serializer,
     {
-        let mut s = serializer.serialize_tuple(5)?;
+        let object_chunks = self.object_chunks();
+        // The chunks are only serialized if there are any (or if they are
+        // followed by the compression parameters), so that indexes without
+        // chunks can be read by older versions of elfshaker, which ignore the
+        // trailing compression parameters.
+        let has_xattrs = self.xattrs_pool.iter().len() > 0;
+        let has_frames = !self.frames.is_empty();
+        let object_packs = self.object_packs();
+        let len = if !object_packs.is_empty() {
+            11
+        } else if has_frames {
+            10
+        } else if has_xattrs {
+            9
+        } else if self.compression.is_some() {
+            8
+        } else if !object_chunks.is_empty() {
+            7
+        } else {
+            6
+        };
+        let mut s = serializer.serialize_tuple(len)?;
         s.serialize_element(&self.snapshot_tags)?;
         s.serialize_element(&self.snapshot_deltas)?;
-        s.serialize_element(&self.path_pool)?;
+        s.serialize_element(&SerializedPathPool(&self.path_pool))?;
         s.serialize_element(&self.object_pool)?;
         // Ordering comes from BTreeMap keys, so is for free.
         s.serialize_element(
@@ -726,6 +1715,661 @@ impl Serialize for PackIndex {
                 .cloned()
                 .collect::<Vec<ObjectMetadata>>(),
         )?;
+        s.serialize_element(&self.snapshot_metadata)?;
+        if len > 6 {
+            s.serialize_element(&object_chunks)?;
+        }
+        if len > 8 {
+            s.serialize_element(&self.compression)?;
+            s.serialize_element(&self.xattrs_pool)?;
+        } else if let Some(compression) = &self.compression {
+            s.serialize_element(compression)?;
+        }
+        if len > 9 {
+            s.serialize_element(&self.frames)?;
+        }
+        if len > 10 {
+            s.serialize_element(&object_packs)?;
+        }
         s.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_index() -> PackIndex {
+        let mut index = PackIndex::new();
+        let entry = FileEntry::new(
+            "a".into(),
+            ObjectChecksum::from([1; 32]),
+            ObjectMetadata {
+                offset: 0,
+                size: 1,
+                delta: None,
+                chunks: None,
+                pack: None,
+            },
+            FileMetadata::default(),
+        );
+        index
+            .push_snapshot("s1".into(), vec![entry.clone()])
+            .unwrap();
+        index.push_snapshot("s2".into(), vec![entry]).unwrap();
+        index
+    }
+
+    #[test]
+    fn file_metadata_types_work() {
+        let file = FileMetadata::default();
+        assert!(!file.is_symlink() && !file.is_dir());
+        let symlink = FileMetadata {
+            mode: 0o120777,
+            mtime: None,
+        };
+        assert!(symlink.is_symlink() && !symlink.is_dir());
+        let dir = FileMetadata {
+            mode: 0o40755,
+            mtime: None,
+        };
+        assert!(!dir.is_symlink() && dir.is_dir());
+    }
+
+    #[test]
+    fn snapshot_metadata_serde_works() {
+        let mut index = make_index();
+        let metadata = SnapshotMetadata {
+            message: Some("message".into()),
+            attributes: [("commit".to_owned(), "abc".to_owned())].into(),
+            created: Some(1646136000),
+            author: Some("ci-1".into()),
+        };
+        index.set_snapshot_metadata("s2", metadata.clone()).unwrap();
+
+        let buf = rmp_serde::to_vec(&index).unwrap();
+        let index: PackIndex = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(
+            Some(&SnapshotMetadata::default()),
+            index.snapshot_metadata("s1")
+        );
+        assert_eq!(Some(&metadata), index.snapshot_metadata("s2"));
+        assert_eq!(None, index.snapshot_metadata("s3"));
+        index.check_consistency().unwrap();
+    }
+
+    #[test]
+    fn snapshot_metadata_without_time_works() {
+        // The metadata written before the time and author were recorded.
+        let old_format = (Some("message"), BTreeMap::from([("key", "value")]));
+        let buf = rmp_serde::to_vec(&old_format).unwrap();
+        let metadata: SnapshotMetadata = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(Some("message"), metadata.message.as_deref());
+        assert_eq!(None, metadata.created);
+        assert_eq!(None, metadata.author);
+    }
+
+    #[test]
+    fn snapshot_metadata_missing_in_old_index_works() {
+        let index = make_index();
+        // The format used before version 3, without the snapshot metadata.
+        let old_format = (
+            &index.snapshot_tags,
+            &index.snapshot_deltas,
+            &index.path_pool,
+            &index.object_pool,
+            index.object_metadata.values().collect::<Vec<_>>(),
+        );
+
+        let buf = rmp_serde::to_vec(&old_format).unwrap();
+        let index: PackIndex = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(
+            Some(&SnapshotMetadata::default()),
+            index.snapshot_metadata("s1")
+        );
+        assert_eq!(
+            Some(&SnapshotMetadata::default()),
+            index.snapshot_metadata("s2")
+        );
+        index.check_consistency().unwrap();
+    }
+
+    #[test]
+    fn checksum_algorithm_header_works() {
+        let mut buf = vec![];
+        make_index().write(&mut buf).unwrap();
+        let index = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(ChecksumAlgorithm::Blake3, index.checksum_algorithm());
+
+        // Indexes created before version 4 use SHA-1.
+        let mut buf = b"ELFS".to_vec();
+        buf.extend_from_slice(&3u32.to_be_bytes());
+        rmp_serde::encode::write(&mut buf, &make_index()).unwrap();
+        let index = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(ChecksumAlgorithm::Sha1, index.checksum_algorithm());
+        assert_eq!(vec!["s1", "s2"], index.snapshot_tags());
+
+        // The checksums of SHA-256 indexes are larger.
+        let mut index = make_index();
+        index
+            .convert_checksums(ChecksumAlgorithm::Sha256, |_| {
+                Some(ObjectChecksum::from([3; 32]))
+            })
+            .unwrap();
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        let index = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(ChecksumAlgorithm::Sha256, index.checksum_algorithm());
+        let handles = index.resolve_snapshot("s2").unwrap();
+        let entries = index.entries_from_handles(handles.iter()).unwrap();
+        assert_eq!(ObjectChecksum::from([3; 32]), entries[0].checksum);
+        index.check_consistency().unwrap();
+    }
+
+    #[test]
+    fn compression_parameters_work() {
+        let mut buf = vec![];
+        make_index().write(&mut buf).unwrap();
+        assert_eq!(
+            None,
+            PackIndex::parse(buf.as_slice()).unwrap().compression()
+        );
+
+        let compression = CompressionParameters {
+            codec: Codec::Xz,
+            level: 9,
+            window_log: 27,
+        };
+        let mut index = make_index();
+        index.set_compression(compression);
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        let index = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(Some(compression), index.compression());
+        assert_eq!(4, index.version());
+        assert_eq!(vec!["s1", "s2"], index.snapshot_tags());
+    }
+
+    #[test]
+    fn frames_work() {
+        let mut buf = vec![];
+        make_index().write(&mut buf).unwrap();
+        assert!(PackIndex::parse(buf.as_slice())
+            .unwrap()
+            .frames()
+            .is_empty());
+
+        let frames = vec![
+            FrameBoundary {
+                offset: 20,
+                size: 10,
+                decompressed_size: 100,
+            },
+            FrameBoundary {
+                offset: 30,
+                size: 5,
+                decompressed_size: 50,
+            },
+        ];
+        let mut index = make_index();
+        index.set_frames(frames.clone());
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        let index = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(frames, index.frames());
+        assert_eq!(None, index.compression());
+        assert_eq!(4, index.version());
+        assert_eq!(vec!["s1", "s2"], index.snapshot_tags());
+    }
+
+    #[test]
+    fn object_deltas_work() {
+        let mut index = PackIndex::new();
+        let entry = |path: &str, id: u8, size: u64| {
+            FileEntry::new(
+                path.into(),
+                ObjectChecksum::from([id; 32]),
+                ObjectMetadata {
+                    offset: LOOSE_OBJECT_OFFSET,
+                    size,
+                    delta: None,
+                    chunks: None,
+                    pack: None,
+                },
+                FileMetadata::default(),
+            )
+        };
+        for (tag, a) in [("s1", 1), ("s2", 2), ("s3", 3)] {
+            let files = vec![entry("a", a, 100 + a as u64), entry("b", 9, 10)];
+            index.push_snapshot(tag.into(), files).unwrap();
+        }
+        let handle = |id| {
+            index
+                .object_pool
+                .get(&ObjectChecksum::from([id; 32]))
+                .unwrap()
+        };
+        let (a1, a2, a3, b) = (handle(1), handle(2), handle(3), handle(9));
+        assert_eq!(
+            vec![vec![a1, a2, a3], vec![b]],
+            index.object_versions_by_path()
+        );
+        assert_eq!(4, index.version());
+
+        let deltas = HashMap::from([(a2, (a1, 5)), (a3, (a1, 7))]);
+        let (index, groups) = index.compute_object_offsets_and_ordering(
+            &deltas,
+            &HashMap::new(),
+            &HashMap::<_, ()>::new(),
+        );
+        assert_eq!(vec![vec![b], vec![a1, a2, a3]], groups);
+        let md = |h| index.object_metadata[&h].clone();
+        assert_eq!((0, 10, None), (md(b).offset, md(b).size, md(b).delta));
+        assert_eq!((10, None), (md(a1).offset, md(a1).delta));
+        assert_eq!(111, md(a2).offset);
+        assert_eq!(
+            Some(ObjectDelta {
+                base_offset: 10,
+                base_size: 101,
+                delta_size: 7
+            }),
+            md(a3).delta
+        );
+        assert_eq!(113, index.group_stored_size(&groups[1]));
+        assert_eq!(5, index.version());
+        index.check_consistency().unwrap();
+
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        let parsed = PackIndex::parse(buf.as_slice()).unwrap();
+        let handle = parsed
+            .object_pool
+            .get(&ObjectChecksum::from([2; 32]))
+            .unwrap();
+        assert_eq!(md(a2), parsed.object_metadata[&handle]);
+
+        // The base must be stored in full, before the delta.
+        let delta = md(a3).delta.map(|delta| ObjectDelta {
+            base_offset: 111,
+            ..delta
+        });
+        let mut index = index;
+        index.object_metadata.get_mut(&a3).unwrap().delta = delta;
+        assert!(index.check_consistency().is_err());
+    }
+
+    #[test]
+    fn object_chunks_work() {
+        let mut index = PackIndex::new();
+        let md = |size| ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let files = vec![
+            FileEntry::new("a".into(), [1; 32].into(), md(30), FileMetadata::default()),
+            FileEntry::new("b".into(), [2; 32].into(), md(5), FileMetadata::default()),
+            FileEntry::new("c".into(), [3; 32].into(), md(25), FileMetadata::default()),
+        ];
+        index.push_snapshot("s1".into(), files).unwrap();
+        let handle = |id| {
+            index
+                .object_pool
+                .get(&ObjectChecksum::from([id; 32]))
+                .unwrap()
+        };
+        let (a, b, c) = (handle(1), handle(2), handle(3));
+
+        // a and c share their first chunk.
+        let chunk = |offset, size| ObjectChunk { offset, size };
+        let chunks = HashMap::from([
+            (a, vec![chunk(0, 20), chunk(20, 10)]),
+            (c, vec![chunk(0, 20), chunk(30, 5)]),
+        ]);
+        let (index, groups) = index.compute_object_offsets_and_ordering(
+            &HashMap::new(),
+            &chunks,
+            &HashMap::<_, ()>::new(),
+        );
+        assert_eq!(vec![vec![b]], groups);
+        let md = |h| index.object_metadata[&h].clone();
+        assert_eq!((0, None), (md(b).offset, md(b).chunks));
+        assert_eq!(5, md(c).offset);
+        assert_eq!(0, md(c).stored_size());
+        assert_eq!(Some(vec![chunk(5, 20), chunk(35, 5)]), md(c).chunks);
+        assert_eq!(6, index.version());
+        index.check_consistency().unwrap();
+
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        let parsed = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(md(a), parsed.object_metadata[&a]);
+        assert_eq!(md(c), parsed.object_metadata[&c]);
+        assert_eq!(None, parsed.object_metadata[&b].chunks);
+
+        // The chunks must add up to the object.
+        let mut index = index;
+        index.object_metadata.get_mut(&c).unwrap().chunks = Some(vec![chunk(5, 20)]);
+        assert!(index.check_consistency().is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn path_serialization_works() {
+        use std::os::unix::ffi::OsStrExt;
+
+        // The paths are serialized as before, when Serde serialized them.
+        let path = OsStr::from_bytes(b"a/\xff");
+        let buf = rmp_serde::encode::to_vec(&SerializedPathRef(path)).unwrap();
+        assert_eq!(rmp_serde::encode::to_vec(path).unwrap(), buf);
+        let decoded: SerializedPath = rmp_serde::decode::from_slice(&buf).unwrap();
+        assert_eq!(path, OsString::from(decoded));
+    }
+
+    #[test]
+    fn file_mtimes_work() {
+        let mut index = PackIndex::new();
+        let md = ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size: 1,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let with_mtime = FileMetadata {
+            mtime: Some(1_600_000_000_123_456_789),
+            ..FileMetadata::default()
+        };
+        let files = vec![
+            FileEntry::new("a".into(), [1; 32].into(), md.clone(), with_mtime),
+            FileEntry::new("b".into(), [2; 32].into(), md, FileMetadata::default()),
+        ];
+        index.push_snapshot("s1".into(), files).unwrap();
+        assert_eq!(7, index.version());
+
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        let parsed = PackIndex::parse(buf.as_slice()).unwrap();
+        let handles = parsed.resolve_snapshot("s1").unwrap();
+        let mut entries = parsed.entries_from_handles(handles.iter()).unwrap();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(with_mtime, entries[0].file_metadata);
+        assert_eq!(FileMetadata::default(), entries[1].file_metadata);
+    }
+
+    #[test]
+    fn file_xattrs_work() {
+        let mut index = PackIndex::new();
+        let md = ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size: 1,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let entry = |path: &str, xattrs: &[Xattr]| {
+            let mut entry =
+                FileEntry::new(path.into(), [1; 32].into(), md.clone(), Default::default());
+            entry.xattrs = xattrs.to_vec();
+            entry
+        };
+        let xattrs = [Xattr {
+            name: b"user.a".to_vec(),
+            value: b"value".to_vec(),
+        }];
+        let files = vec![entry("a", &xattrs), entry("b", &[]), entry("c", &xattrs)];
+        index.push_snapshot("s1".into(), files.clone()).unwrap();
+        // Identical attributes are only stored once.
+        assert_eq!(1, index.xattrs_pool.iter().len());
+        assert_eq!(8, index.version());
+
+        // With and without the compression parameters, which precede them.
+        let compression = CompressionParameters {
+            codec: Codec::Zstd,
+            level: 3,
+            window_log: 0,
+        };
+        for compression in [None, Some(compression)] {
+            if let Some(compression) = compression {
+                index.set_compression(compression);
+            }
+            let mut buf = vec![];
+            index.write(&mut buf).unwrap();
+            let parsed = PackIndex::parse(buf.as_slice()).unwrap();
+            assert_eq!(compression, parsed.compression());
+            let handles = parsed.resolve_snapshot("s1").unwrap();
+            let mut entries = parsed.entries_from_handles(handles.iter()).unwrap();
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(files, entries);
+        }
+    }
+
+    #[test]
+    fn external_objects_work() {
+        let mut index = PackIndex::new();
+        let md = |size| ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let files = vec![
+            FileEntry::new("a".into(), [1; 32].into(), md(10), FileMetadata::default()),
+            FileEntry::new("b".into(), [2; 32].into(), md(20), FileMetadata::default()),
+        ];
+        index.push_snapshot("s1".into(), files).unwrap();
+        let handle = |id| {
+            index
+                .object_pool
+                .get(&ObjectChecksum::from([id; 32]))
+                .unwrap()
+        };
+        let (a, b) = (handle(1), handle(2));
+
+        // b is stored in another pack, so only a is stored in the pack.
+        index.set_external_objects(&HashMap::from([(
+            ObjectChecksum::from([2; 32]),
+            "other".to_owned(),
+        )]));
+        assert_eq!(vec![vec![a]], index.object_versions_by_path());
+        let (index, groups) = index.compute_object_offsets_and_ordering(
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::<_, ()>::new(),
+        );
+        assert_eq!(vec![vec![a]], groups);
+        let md = |h| index.object_metadata[&h].clone();
+        assert_eq!(Some("other".to_owned()), md(b).pack);
+        assert_eq!((0, 20, 0), (md(b).offset, md(b).size, md(b).stored_size()));
+        assert_eq!(10, index.version());
+        assert_eq!(BTreeSet::from(["other"]), index.referenced_packs());
+        index.check_consistency().unwrap();
+
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        assert!(PackIndex::may_have_external_objects(&buf));
+        let parsed = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(md(a), parsed.object_metadata[&a]);
+        assert_eq!(md(b), parsed.object_metadata[&b]);
+
+        // The flat format can't reference other packs.
+        let mut index = index;
+        index.set_format(IndexFormat::Flat);
+        assert!(index.write(&mut vec![]).is_err());
+        index.set_external_objects(&HashMap::new());
+        assert_eq!(None, index.object_metadata[&b].pack);
+        let mut buf = vec![];
+        index.set_format(IndexFormat::Msgpack);
+        index.write(&mut buf).unwrap();
+        assert!(!PackIndex::may_have_external_objects(&buf));
+    }
+
+    #[test]
+    fn flat_format_works() {
+        let mut index = PackIndex::new();
+        let md = |size| ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let with_mtime = FileMetadata {
+            mtime: Some(-1),
+            ..FileMetadata::default()
+        };
+        let mut a = FileEntry::new("a".into(), [1; 32].into(), md(1), with_mtime);
+        a.xattrs = vec![Xattr {
+            name: b"user.a".to_vec(),
+            value: b"value".to_vec(),
+        }];
+        let b = FileEntry::new("b".into(), [2; 32].into(), md(2), FileMetadata::default());
+        let c = FileEntry::new("d/c".into(), [3; 32].into(), md(3), FileMetadata::default());
+        index
+            .push_snapshot("s1".into(), vec![a.clone(), b])
+            .unwrap();
+        index.push_snapshot("s2".into(), vec![a, c]).unwrap();
+        let metadata = SnapshotMetadata {
+            message: Some("message".into()),
+            attributes: BTreeMap::from([("key".into(), "value".into())]),
+            created: Some(1646136000),
+            author: None,
+        };
+        index.set_snapshot_metadata("s2", metadata.clone()).unwrap();
+        index.object_metadata.get_mut(&1).unwrap().delta = Some(ObjectDelta {
+            base_offset: 0,
+            base_size: 1,
+            delta_size: 1,
+        });
+        index.object_metadata.get_mut(&2).unwrap().chunks =
+            Some(vec![ObjectChunk { offset: 0, size: 3 }]);
+        index.set_compression(CompressionParameters {
+            codec: Codec::Zstd,
+            level: 3,
+            window_log: 0,
+        });
+        index.set_frames(vec![FrameBoundary {
+            offset: 8,
+            size: 4,
+            decompressed_size: 6,
+        }]);
+        index.set_format(IndexFormat::Flat);
+        assert_eq!(9, index.version());
+
+        let mut buf = vec![];
+        index.write(&mut buf).unwrap();
+        assert!(FlatIndex::is_flat(&buf));
+        let entries = |index: &PackIndex, tag| {
+            let handles = index.resolve_snapshot(tag).unwrap();
+            let mut entries = index.entries_from_handles(handles.iter()).unwrap();
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            entries
+        };
+
+        // The flat index is queried in place.
+        let flat = FlatIndex::from_bytes(buf.clone()).unwrap();
+        assert_eq!(index.snapshot_tags(), flat.snapshot_tags().unwrap());
+        assert_eq!(
+            vec![("s1".to_owned(), 2), ("s2".to_owned(), 2)],
+            flat.snapshot_file_counts().unwrap()
+        );
+        assert_eq!(Some(metadata), flat.snapshot_metadata("s2").unwrap());
+        assert_eq!(
+            Some(SnapshotMetadata::default()),
+            flat.snapshot_metadata("s1").unwrap()
+        );
+        for tag in ["s1", "s2"] {
+            let mut flat_entries = flat.snapshot_entries(tag).unwrap().unwrap();
+            flat_entries.sort_by(|a, b| a.path.cmp(&b.path));
+            assert_eq!(entries(&index, tag), flat_entries);
+        }
+        assert!(flat.snapshot_entries("s3").unwrap().is_none());
+        for checksum in index.object_checksums() {
+            assert_eq!(
+                Some(index.object_metadata(checksum)),
+                flat.object_metadata(checksum).unwrap().as_ref()
+            );
+        }
+        assert_eq!(None, flat.object_metadata(&[4; 32].into()).unwrap());
+
+        // And loaded in full, keeping its format.
+        let parsed = PackIndex::parse(buf.as_slice()).unwrap();
+        assert_eq!(IndexFormat::Flat, parsed.format());
+        assert_eq!(index.compression(), parsed.compression());
+        assert_eq!(index.frames(), parsed.frames());
+        assert_eq!(index.object_metadata, parsed.object_metadata);
+        assert_eq!(entries(&index, "s2"), entries(&parsed, "s2"));
+        assert_eq!(
+            vec!["s1", "s2"],
+            PackIndex::parse_only_snapshots(buf.as_slice()).unwrap()
+        );
+        let mut rewritten = vec![];
+        parsed.write(&mut rewritten).unwrap();
+        assert_eq!(buf, rewritten);
+
+        assert!(FlatIndex::from_bytes(buf[..buf.len() - 1].to_vec()).is_err());
+    }
+
+    #[test]
+    fn convert_checksums_works() {
+        let mut index = make_index();
+        index
+            .convert_checksums(ChecksumAlgorithm::Sha1, |checksum| {
+                assert_eq!(&ObjectChecksum::from([1; 32]), checksum);
+                Some(ObjectChecksum::from([2; 20]))
+            })
+            .unwrap();
+        assert_eq!(ChecksumAlgorithm::Sha1, index.checksum_algorithm());
+        let handles = index.resolve_snapshot("s2").unwrap();
+        let entries = index.entries_from_handles(handles.iter()).unwrap();
+        assert_eq!(ObjectChecksum::from([2; 20]), entries[0].checksum);
+        index.check_consistency().unwrap();
+
+        assert!(index
+            .convert_checksums(ChecksumAlgorithm::Blake3, |_| None)
+            .is_err());
+    }
+
+    #[test]
+    fn symlink_parents_are_rejected() {
+        let entry = |path: &str, mode| {
+            FileEntry::new(
+                path.into(),
+                ObjectChecksum::from([1; 32]),
+                ObjectMetadata {
+                    offset: 0,
+                    size: 1,
+                    delta: None,
+                    chunks: None,
+                    pack: None,
+                },
+                FileMetadata { mode, mtime: None },
+            )
+        };
+        let mut index = PackIndex::new();
+        let snapshot = vec![
+            entry("l", 0o120777),
+            entry("lx", 0o100644),
+            entry("m/l", 0o120777),
+            entry("m/x", 0o100644),
+        ];
+        index.push_snapshot("s1".into(), snapshot).unwrap();
+        index.check_consistency().unwrap();
+
+        for path in ["l/x", "m/l/a/b"] {
+            let snapshot = vec![
+                entry("l", 0o120777),
+                entry("m/l", 0o120777),
+                entry(path, 0o100644),
+            ];
+            assert!(matches!(
+                index.push_snapshot("s2".into(), snapshot),
+                Err(PackError::SymlinkInPath(tag, p)) if tag == "s2" && p == path
+            ));
+        }
+        assert_eq!(&["s1".to_owned()], index.snapshot_tags());
+    }
+}
@@ -0,0 +1,651 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the flat format of pack indexes (version 9), which can be queried
+//! in place (e.g. memory-mapped), without deserializing the whole index into
+//! heap structures first (see [`FlatIndex`]).
+//!
+//! After the header of the index, the flat format consists of a table of
+//! sections, followed by the sections. The table starts with the number of
+//! sections, followed by the offset and the size of each section (relative to
+//! the start of the table). The integers are little-endian, and most sections
+//! are arrays of fixed-size records, indexed by the handles of the index:
+//!
+//! | Section      | Records                                                       |
+//! |--------------|---------------------------------------------------------------|
+//! | snapshots    | tag, added files, removed files, metadata (u64 start and size each) |
+//! | files        | path u32, object u32, mode u32, flags u32, mtime i64, xattrs u32, padding u32 |
+//! | paths        | start u64, size u64 (in the strings)                          |
+//! | checksums    | the checksums of the objects                                  |
+//! | objects      | offset, size, flags, base offset, base size, delta size, chunks (start and count), all u64 |
+//! | object order | the object handles (u32), sorted by checksum                  |
+//! | chunks       | offset u64, size u64                                          |
+//! | xattrs       | start u64, size u64 (in the blobs)                            |
+//! | frames       | offset u64, size u64, decompressed size u64                   |
+//! | strings      | the tags and the paths                                        |
+//! | blobs        | the MessagePack-encoded snapshot metadata and xattrs          |
+//! | compression  | the MessagePack-encoded compression parameters, if any        |
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::io::Write;
+use std::ops::Deref;
+
+use super::{
+    bytes_to_os_string, invalid_path_error, is_valid_entry_path, os_str_as_bytes, ChangeSet,
+    CompressionParameters, FileEntry, FileHandle, FileMetadata, FrameBoundary, IndexFormat,
+    ObjectChecksum, ObjectChunk, ObjectDelta, ObjectMetadata, PackError, PackIndex,
+    SnapshotMetadata, Xattr,
+};
+use crate::checksum::ChecksumAlgorithm;
+use crate::entrypool::{EntryPool, Handle};
+
+/// The version of the pack indexes in the flat format.
+pub(super) const VERSION: u32 = 9;
+
+const SNAPSHOTS: usize = 0;
+const FILES: usize = 1;
+const PATHS: usize = 2;
+const CHECKSUMS: usize = 3;
+const OBJECTS: usize = 4;
+const OBJECT_ORDER: usize = 5;
+const CHUNKS: usize = 6;
+const XATTRS: usize = 7;
+const FRAMES: usize = 8;
+const STRINGS: usize = 9;
+const BLOBS: usize = 10;
+const COMPRESSION: usize = 11;
+const NUM_SECTIONS: usize = 12;
+
+const SNAPSHOT_SIZE: usize = 64;
+const FILE_SIZE: usize = 32;
+const RANGE_SIZE: usize = 16;
+const OBJECT_SIZE: usize = 64;
+const HANDLE_SIZE: usize = 4;
+const FRAME_SIZE: usize = 24;
+
+const FILE_HAS_MTIME: u32 = 1;
+const FILE_HAS_XATTRS: u32 = 2;
+const OBJECT_HAS_DELTA: u64 = 1;
+const OBJECT_HAS_CHUNKS: u64 = 2;
+
+/// The size of the header of the index (see [`PackIndex::read_header`]).
+const HEADER_SIZE: usize = 12;
+/// The sections are aligned to 8 bytes.
+const ALIGNMENT: usize = 8;
+
+/// The bytes of a flat index, including its header.
+enum FlatData {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl Deref for FlatData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FlatData::Owned(bytes) => bytes,
+            #[cfg(feature = "mmap")]
+            FlatData::Mapped(map) => map,
+        }
+    }
+}
+
+/// A pack index in the flat format, which is queried in place: only the
+/// records needed to answer a query are decoded, so listing the snapshots or
+/// the files of one snapshot doesn't require loading all the paths and the
+/// objects of the pack. [`FlatIndex::to_pack_index`] loads the whole index.
+pub struct FlatIndex {
+    data: FlatData,
+    checksum_algorithm: ChecksumAlgorithm,
+    /// The start and the end of each section in the data.
+    sections: [(usize, usize); NUM_SECTIONS],
+}
+
+fn corrupt(what: &str) -> PackError {
+    PackError::InconsistentIndex(format!("the flat index is truncated ({what})"))
+}
+
+fn read_u32(record: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(record[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(record: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(record[offset..offset + 8].try_into().unwrap())
+}
+
+fn to_usize(value: u64, what: &str) -> Result<usize, PackError> {
+    value.try_into().map_err(|_| corrupt(what))
+}
+
+impl FlatIndex {
+    /// Whether the header of the index (its first 8 bytes, at least) is the
+    /// one of a flat index.
+    pub fn is_flat(header: &[u8]) -> bool {
+        header.len() >= 8 && header.starts_with(b"ELFS") && header[4..8] == VERSION.to_be_bytes()
+    }
+
+    /// Parses the flat index (including its header) held in memory.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, PackError> {
+        Self::new(FlatData::Owned(bytes))
+    }
+
+    /// Maps the flat index into memory.
+    #[cfg(feature = "mmap")]
+    pub fn map(file: &std::fs::File) -> Result<Self, PackError> {
+        // SAFETY: Pack indexes are never modified in place: they are written
+        // to a temporary file which then replaces them (see `PackIndex::save`,
+        // and the pack indexes fetched from remotes or received by `serve`),
+        // so the mapping stays valid.
+        let map = unsafe { memmap2::Mmap::map(file)? };
+        Self::new(FlatData::Mapped(map))
+    }
+
+    /// Opens the flat index, which is mapped into memory with the `mmap`
+    /// feature, and read otherwise.
+    #[cfg(feature = "repo")]
+    pub fn from_file(file: std::fs::File) -> Result<Self, PackError> {
+        #[cfg(feature = "mmap")]
+        return Self::map(&file);
+        #[cfg(not(feature = "mmap"))]
+        {
+            let mut bytes = vec![];
+            std::io::Read::read_to_end(&mut &file, &mut bytes)?;
+            Self::from_bytes(bytes)
+        }
+    }
+
+    fn new(data: FlatData) -> Result<Self, PackError> {
+        let (version, checksum_algorithm) = PackIndex::read_header(&mut &data[..])?;
+        if version != VERSION {
+            return Err(PackError::InconsistentIndex(format!(
+                "expected a flat index (version {VERSION}), got version {version}"
+            )));
+        }
+        let table = data.get(HEADER_SIZE..).ok_or_else(|| corrupt("header"))?;
+        if table.len() < 8 || read_u64(table, 0) != NUM_SECTIONS as u64 {
+            return Err(PackError::InconsistentIndex(
+                "unexpected number of sections in the flat index".into(),
+            ));
+        }
+        let mut sections = [(0, 0); NUM_SECTIONS];
+        for (i, section) in sections.iter_mut().enumerate() {
+            let entry = table
+                .get(8 + i * RANGE_SIZE..8 + (i + 1) * RANGE_SIZE)
+                .ok_or_else(|| corrupt("section table"))?;
+            let start = to_usize(read_u64(entry, 0), "section table")?;
+            let size = to_usize(read_u64(entry, 8), "section table")?;
+            let end = start
+                .checked_add(size)
+                .filter(|end| *end <= table.len())
+                .ok_or_else(|| corrupt("section"))?;
+            *section = (HEADER_SIZE + start, HEADER_SIZE + end);
+        }
+        Ok(Self {
+            data,
+            checksum_algorithm,
+            sections,
+        })
+    }
+
+    /// The algorithm used to compute the checksums of the objects.
+    pub fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.checksum_algorithm
+    }
+
+    fn section(&self, section: usize) -> &[u8] {
+        let (start, end) = self.sections[section];
+        &self.data[start..end]
+    }
+
+    fn count(&self, section: usize, record_size: usize) -> usize {
+        self.section(section).len() / record_size
+    }
+
+    fn record(&self, section: usize, record_size: usize, i: usize) -> Result<&[u8], PackError> {
+        self.section(section)
+            .get(i * record_size..(i + 1) * record_size)
+            .ok_or_else(|| corrupt("record"))
+    }
+
+    /// The bytes of a (start, size) range of the section.
+    fn range(&self, section: usize, record: &[u8], offset: usize) -> Result<&[u8], PackError> {
+        let start = to_usize(read_u64(record, offset), "range")?;
+        let size = to_usize(read_u64(record, offset + 8), "range")?;
+        self.section(section)
+            .get(start..start.checked_add(size).ok_or_else(|| corrupt("range"))?)
+            .ok_or_else(|| corrupt("range"))
+    }
+
+    /// The number of snapshots in the index.
+    pub fn snapshot_count(&self) -> usize {
+        self.count(SNAPSHOTS, SNAPSHOT_SIZE)
+    }
+
+    fn snapshot_tag(&self, i: usize) -> Result<&str, PackError> {
+        let record = self.record(SNAPSHOTS, SNAPSHOT_SIZE, i)?;
+        std::str::from_utf8(self.range(STRINGS, record, 0)?)
+            .map_err(|_| PackError::InconsistentIndex("snapshot tag is not UTF-8".into()))
+    }
+
+    /// The tags of the snapshots, in order.
+    pub fn snapshot_tags(&self) -> Result<Vec<String>, PackError> {
+        (0..self.snapshot_count())
+            .map(|i| self.snapshot_tag(i).map(str::to_owned))
+            .collect()
+    }
+
+    fn find_snapshot(&self, tag: &str) -> Result<Option<usize>, PackError> {
+        for i in 0..self.snapshot_count() {
+            if self.snapshot_tag(i)? == tag {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The number of files of each snapshot, in order.
+    pub fn snapshot_file_counts(&self) -> Result<Vec<(String, usize)>, PackError> {
+        let mut file_count = 0i64;
+        let mut counts = Vec::with_capacity(self.snapshot_count());
+        for i in 0..self.snapshot_count() {
+            let record = self.record(SNAPSHOTS, SNAPSHOT_SIZE, i)?;
+            file_count += (read_u64(record, 24) / FILE_SIZE as u64) as i64;
+            file_count -= (read_u64(record, 40) / FILE_SIZE as u64) as i64;
+            counts.push((self.snapshot_tag(i)?.to_owned(), file_count as usize));
+        }
+        Ok(counts)
+    }
+
+    fn metadata_at(&self, i: usize) -> Result<SnapshotMetadata, PackError> {
+        let record = self.record(SNAPSHOTS, SNAPSHOT_SIZE, i)?;
+        let bytes = self.range(BLOBS, record, 48)?;
+        // The default metadata is not stored.
+        if bytes.is_empty() {
+            return Ok(SnapshotMetadata::default());
+        }
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+
+    /// Returns the metadata of the snapshot, or [`None`] if no such snapshot exists.
+    pub fn snapshot_metadata(&self, tag: &str) -> Result<Option<SnapshotMetadata>, PackError> {
+        self.find_snapshot(tag)?
+            .map(|i| self.metadata_at(i))
+            .transpose()
+    }
+
+    /// The file handles added and removed by the snapshot.
+    fn snapshot_delta(&self, i: usize) -> Result<ChangeSet<FileHandle>, PackError> {
+        let record = self.record(SNAPSHOTS, SNAPSHOT_SIZE, i)?;
+        Ok(ChangeSet::new(
+            self.file_handles(record, 16)?,
+            self.file_handles(record, 32)?,
+        ))
+    }
+
+    fn file_handles(&self, record: &[u8], offset: usize) -> Result<Vec<FileHandle>, PackError> {
+        let bytes = self.range(FILES, record, offset)?;
+        Ok(bytes
+            .chunks_exact(FILE_SIZE)
+            .map(|file| {
+                let flags = read_u32(file, 12);
+                FileHandle {
+                    path: read_u32(file, 0),
+                    object: read_u32(file, 4),
+                    file_metadata: FileMetadata {
+                        mode: read_u32(file, 8),
+                        mtime: (flags & FILE_HAS_MTIME != 0).then(|| read_u64(file, 16) as i64),
+                    },
+                    xattrs: (flags & FILE_HAS_XATTRS != 0).then(|| read_u32(file, 24)),
+                }
+            })
+            .collect())
+    }
+
+    /// The handles of the files of the snapshot, or [`None`] if no such
+    /// snapshot exists (see [`PackIndex::resolve_snapshot`]).
+    pub fn resolve_snapshot(&self, tag: &str) -> Result<Option<Vec<FileHandle>>, PackError> {
+        let last = match self.find_snapshot(tag)? {
+            Some(last) => last,
+            None => return Ok(None),
+        };
+        let mut current = hashbrown::HashSet::new();
+        for i in 0..=last {
+            let delta = self.snapshot_delta(i)?;
+            for removed in delta.removed() {
+                if !current.remove(removed) {
+                    return Err(PackError::InconsistentIndex(format!(
+                        "snapshot {i} removes a missing file"
+                    )));
+                }
+            }
+            for added in delta.added() {
+                if !current.insert(*added) {
+                    return Err(PackError::InconsistentIndex(format!(
+                        "snapshot {i} adds a file twice"
+                    )));
+                }
+            }
+        }
+        Ok(Some(current.into_iter().collect()))
+    }
+
+    /// The files of the snapshot, or [`None`] if no such snapshot exists. Only
+    /// the paths and the objects of these files are decoded.
+    pub fn snapshot_entries(&self, tag: &str) -> Result<Option<Vec<FileEntry>>, PackError> {
+        self.resolve_snapshot(tag)?
+            .map(|handles| handles.iter().map(|h| self.handle_to_entry(h)).collect())
+            .transpose()
+    }
+
+    fn path(&self, handle: Handle) -> Result<OsString, PackError> {
+        let record = self
+            .record(PATHS, RANGE_SIZE, handle as usize)
+            .map_err(|_| PackError::PathNotFound(handle))?;
+        // The paths are checked as they are read, since the index is queried
+        // in place (see `PackIndex::check_paths`).
+        let path = bytes_to_os_string(self.range(STRINGS, record, 0)?);
+        if !is_valid_entry_path(&path) {
+            return Err(invalid_path_error(&path));
+        }
+        Ok(path)
+    }
+
+    fn checksum(&self, handle: Handle) -> Result<ObjectChecksum, PackError> {
+        let size = self.checksum_algorithm.size();
+        let bytes = self
+            .record(CHECKSUMS, size, handle as usize)
+            .map_err(|_| PackError::ObjectNotFound)?;
+        Ok(ObjectChecksum::from_slice(bytes).unwrap())
+    }
+
+    fn object_metadata_at(&self, handle: Handle) -> Result<ObjectMetadata, PackError> {
+        let record = self
+            .record(OBJECTS, OBJECT_SIZE, handle as usize)
+            .map_err(|_| PackError::ObjectNotFound)?;
+        let flags = read_u64(record, 16);
+        let delta = (flags & OBJECT_HAS_DELTA != 0).then(|| ObjectDelta {
+            base_offset: read_u64(record, 24),
+            base_size: read_u64(record, 32),
+            delta_size: read_u64(record, 40),
+        });
+        let chunks = if flags & OBJECT_HAS_CHUNKS != 0 {
+            let start = to_usize(read_u64(record, 48), "chunks")?;
+            let count = to_usize(read_u64(record, 56), "chunks")?;
+            let end = start.checked_add(count).ok_or_else(|| corrupt("chunks"))?;
+            let chunks = (start..end)
+                .map(|i| {
+                    let chunk = self.record(CHUNKS, RANGE_SIZE, i)?;
+                    Ok(ObjectChunk {
+                        offset: read_u64(chunk, 0),
+                        size: read_u64(chunk, 8),
+                    })
+                })
+                .collect::<Result<_, PackError>>()?;
+            Some(chunks)
+        } else {
+            None
+        };
+        Ok(ObjectMetadata {
+            offset: read_u64(record, 0),
+            size: read_u64(record, 8),
+            delta,
+            chunks,
+            pack: None,
+        })
+    }
+
+    /// Looks up the metadata of the object, by binary search of the checksums.
+    pub fn object_metadata(
+        &self,
+        checksum: &ObjectChecksum,
+    ) -> Result<Option<ObjectMetadata>, PackError> {
+        let (mut low, mut high) = (0, self.count(OBJECT_ORDER, HANDLE_SIZE));
+        while low < high {
+            let middle = (low + high) / 2;
+            let handle = read_u32(self.record(OBJECT_ORDER, HANDLE_SIZE, middle)?, 0);
+            match self.checksum(handle)?.cmp(checksum) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.object_metadata_at(handle).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    fn xattrs(&self, handle: Handle) -> Result<Vec<Xattr>, PackError> {
+        let record = self
+            .record(XATTRS, RANGE_SIZE, handle as usize)
+            .map_err(|_| PackError::InconsistentIndex(format!("xattrs {handle} not found")))?;
+        Ok(rmp_serde::from_slice(self.range(BLOBS, record, 0)?)?)
+    }
+
+    /// Looks up the path, the object and the extended attributes of the file.
+    pub fn handle_to_entry(&self, handle: &FileHandle) -> Result<FileEntry, PackError> {
+        Ok(FileEntry {
+            path: self.path(handle.path)?,
+            checksum: self.checksum(handle.object)?,
+            object_metadata: self.object_metadata_at(handle.object)?,
+            file_metadata: handle.file_metadata,
+            xattrs: match handle.xattrs {
+                Some(xattrs) => self.xattrs(xattrs)?,
+                None => vec![],
+            },
+        })
+    }
+
+    /// Loads the whole index (which is then written in the flat format too).
+    pub fn to_pack_index(&self) -> Result<PackIndex, PackError> {
+        let mut index = PackIndex::with_checksum_algorithm(self.checksum_algorithm);
+        index.format = IndexFormat::Flat;
+        for i in 0..self.snapshot_count() {
+            index.snapshot_tags.push(self.snapshot_tag(i)?.to_owned());
+            index.snapshot_deltas.push(self.snapshot_delta(i)?);
+            index.snapshot_metadata.push(self.metadata_at(i)?);
+        }
+        let paths = (0..self.count(PATHS, RANGE_SIZE))
+            .map(|h| self.path(h as Handle))
+            .collect::<Result<Vec<_>, _>>()?;
+        index.path_pool = pool(paths, "paths")?;
+        let object_count = self.count(OBJECTS, OBJECT_SIZE);
+        let checksums = (0..object_count)
+            .map(|h| self.checksum(h as Handle))
+            .collect::<Result<Vec<_>, _>>()?;
+        index.object_pool = pool(checksums, "object checksums")?;
+        for h in 0..object_count as Handle {
+            index.object_metadata.insert(h, self.object_metadata_at(h)?);
+        }
+        let xattrs = (0..self.count(XATTRS, RANGE_SIZE))
+            .map(|h| self.xattrs(h as Handle))
+            .collect::<Result<Vec<_>, _>>()?;
+        index.xattrs_pool = pool(xattrs, "xattrs")?;
+        index.frames = self
+            .section(FRAMES)
+            .chunks_exact(FRAME_SIZE)
+            .map(|frame| FrameBoundary {
+                offset: read_u64(frame, 0),
+                size: read_u64(frame, 8),
+                decompressed_size: read_u64(frame, 16),
+            })
+            .collect();
+        let compression = self.section(COMPRESSION);
+        if !compression.is_empty() {
+            index.compression = Some(rmp_serde::from_slice::<CompressionParameters>(compression)?);
+        }
+        Ok(index)
+    }
+}
+
+/// Interns the values in order, so that their handles are preserved.
+fn pool<T>(values: Vec<T>, what: &str) -> Result<EntryPool<T>, PackError>
+where
+    T: std::hash::Hash + Eq + Clone,
+{
+    let pool: EntryPool<T> = values.iter().collect();
+    if pool.iter().len() != values.len() {
+        return Err(PackError::InconsistentIndex(format!(
+            "the {what} of the flat index are not unique"
+        )));
+    }
+    Ok(pool)
+}
+
+/// Appends the bytes to the section, returning their range in it.
+fn push_bytes(section: &mut Vec<u8>, bytes: &[u8]) -> [u64; 2] {
+    let start = section.len() as u64;
+    section.extend_from_slice(bytes);
+    [start, bytes.len() as u64]
+}
+
+fn push_u64s(section: &mut Vec<u8>, values: &[u64]) {
+    for value in values {
+        section.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Appends the file handles to the files section, returning their range in it.
+fn push_files(section: &mut Vec<u8>, files: &[FileHandle]) -> [u64; 2] {
+    let start = section.len() as u64;
+    for file in files {
+        let mut flags = 0;
+        if file.file_metadata.mtime.is_some() {
+            flags |= FILE_HAS_MTIME;
+        }
+        if file.xattrs.is_some() {
+            flags |= FILE_HAS_XATTRS;
+        }
+        for value in [file.path, file.object, file.file_metadata.mode, flags] {
+            section.extend_from_slice(&value.to_le_bytes());
+        }
+        section.extend_from_slice(&file.file_metadata.mtime.unwrap_or(0).to_le_bytes());
+        section.extend_from_slice(&file.xattrs.unwrap_or(0).to_le_bytes());
+        section.extend_from_slice(&0u32.to_le_bytes());
+    }
+    [start, section.len() as u64 - start]
+}
+
+/// Writes the index in the flat format, after its header.
+pub(super) fn write<W: Write>(index: &PackIndex, wr: &mut W) -> Result<(), PackError> {
+    let mut sections = vec![Vec::new(); NUM_SECTIONS];
+
+    for ((tag, delta), metadata) in index
+        .snapshot_tags
+        .iter()
+        .zip(&index.snapshot_deltas)
+        .zip(&index.snapshot_metadata)
+    {
+        let tag = push_bytes(&mut sections[STRINGS], tag.as_bytes());
+        let added = push_files(&mut sections[FILES], delta.added());
+        let removed = push_files(&mut sections[FILES], delta.removed());
+        let metadata = if *metadata == SnapshotMetadata::default() {
+            [0, 0]
+        } else {
+            push_bytes(&mut sections[BLOBS], &rmp_serde::to_vec(metadata)?)
+        };
+        push_u64s(
+            &mut sections[SNAPSHOTS],
+            &[tag, added, removed, metadata].concat(),
+        );
+    }
+
+    for path in index.path_pool.iter() {
+        let range = push_bytes(&mut sections[STRINGS], &os_str_as_bytes(path));
+        push_u64s(&mut sections[PATHS], &range);
+    }
+
+    if !index.referenced_packs().is_empty() {
+        return Err(PackError::InconsistentIndex(
+            "objects stored in other packs cannot be written in the flat format".into(),
+        ));
+    }
+    let checksum_size = index.checksum_algorithm.size();
+    for checksum in index.object_pool.iter() {
+        if checksum.len() != checksum_size {
+            return Err(PackError::InconsistentIndex(format!(
+                "checksum {} is not a {} checksum",
+                hex::encode(checksum),
+                index.checksum_algorithm
+            )));
+        }
+        sections[CHECKSUMS].extend_from_slice(checksum);
+    }
+
+    let object_count = index.object_pool.iter().len() as Handle;
+    for handle in 0..object_count {
+        let md = index.object_metadata.get(&handle).ok_or_else(|| {
+            PackError::InconsistentIndex(format!("object {handle} has no metadata"))
+        })?;
+        let mut flags = 0;
+        let delta = md.delta.unwrap_or(ObjectDelta {
+            base_offset: 0,
+            base_size: 0,
+            delta_size: 0,
+        });
+        if md.delta.is_some() {
+            flags |= OBJECT_HAS_DELTA;
+        }
+        let chunks_start = (sections[CHUNKS].len() / RANGE_SIZE) as u64;
+        let mut chunks_count = 0;
+        if let Some(chunks) = &md.chunks {
+            flags |= OBJECT_HAS_CHUNKS;
+            for chunk in chunks {
+                push_u64s(&mut sections[CHUNKS], &[chunk.offset, chunk.size]);
+            }
+            chunks_count = chunks.len() as u64;
+        }
+        push_u64s(
+            &mut sections[OBJECTS],
+            &[
+                md.offset,
+                md.size,
+                flags,
+                delta.base_offset,
+                delta.base_size,
+                delta.delta_size,
+                chunks_start,
+                chunks_count,
+            ],
+        );
+    }
+
+    let mut order = (0..object_count).collect::<Vec<_>>();
+    order.sort_by_key(|handle| index.object_pool.lookup(*handle).unwrap());
+    for handle in order {
+        sections[OBJECT_ORDER].extend_from_slice(&handle.to_le_bytes());
+    }
+
+    for xattrs in index.xattrs_pool.iter() {
+        let range = push_bytes(&mut sections[BLOBS], &rmp_serde::to_vec(xattrs)?);
+        push_u64s(&mut sections[XATTRS], &range);
+    }
+
+    for frame in &index.frames {
+        push_u64s(
+            &mut sections[FRAMES],
+            &[frame.offset, frame.size, frame.decompressed_size],
+        );
+    }
+
+    if let Some(compression) = &index.compression {
+        sections[COMPRESSION] = rmp_serde::to_vec(compression)?;
+    }
+
+    // The table of sections, followed by the (aligned) sections.
+    let mut table = vec![];
+    push_u64s(&mut table, &[NUM_SECTIONS as u64]);
+    let mut offset = 8 + NUM_SECTIONS * RANGE_SIZE;
+    let mut padding = vec![];
+    for section in &sections {
+        let aligned = offset.div_ceil(ALIGNMENT) * ALIGNMENT;
+        padding.push(aligned - offset);
+        push_u64s(&mut table, &[aligned as u64, section.len() as u64]);
+        offset = aligned + section.len();
+    }
+    wr.write_all(&table)?;
+    for (section, padding) in sections.iter().zip(padding) {
+        wr.write_all(&[0; ALIGNMENT][..padding])?;
+        wr.write_all(section)?;
+    }
+    Ok(())
+}
@@ -7,6 +7,8 @@ pub struct Checkpoint {
     pub done: usize,
     pub remaining: Option<usize>,
     pub detail: Option<String>,
+    /// The number of bytes processed so far, if known.
+    pub bytes: Option<u64>,
 }
 
 type CallbackFn<'a> = Option<Box<dyn Fn(&Checkpoint) + Sync + 'a>>;
@@ -37,6 +39,7 @@ impl<'a> ProgressReporter<'a> {
                 done,
                 remaining,
                 detail: None,
+                bytes: None,
             });
         }
     }
@@ -47,6 +50,18 @@ impl<'a> ProgressReporter<'a> {
                 done,
                 remaining,
                 detail: Some(detail),
+                bytes: None,
+            });
+        }
+    }
+
+    pub fn checkpoint_with_bytes(&self, done: usize, remaining: Option<usize>, bytes: u64) {
+        if let Some(callback) = &self.callback {
+            callback(&Checkpoint {
+                done,
+                remaining,
+                detail: None,
+                bytes: Some(bytes),
             });
         }
     }
@@ -86,7 +101,8 @@ impl<'a, T: Write> Write for ProgressWriter<'a, T> {
         if let Some(remaining) = self.remaining.as_mut() {
             *remaining -= bytes_written
         }
-        self.reporter.checkpoint(self.written, self.remaining);
+        self.reporter
+            .checkpoint_with_bytes(self.written, self.remaining, self.written as u64);
         Ok(bytes_written)
     }
 
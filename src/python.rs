@@ -0,0 +1,161 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Python bindings for the core repository operations (enabled with the
+//! `python` feature). The `pyelfshaker` module is built with
+//! [maturin](https://www.maturin.rs), see pyproject.toml.
+
+// The methods generated by #[pymethods] convert the PyErr of PyResult into
+// itself.
+#![allow(clippy::useless_conversion)]
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::repo::{ExtractOptions, PackId, Repository, SnapshotId, REPO_DIR};
+
+create_exception!(
+    pyelfshaker,
+    ElfshakerError,
+    PyException,
+    "Raised when an elfshaker operation fails."
+);
+
+fn to_py_err(e: impl std::fmt::Display) -> PyErr {
+    ElfshakerError::new_err(e.to_string())
+}
+
+/// An elfshaker repository.
+///
+/// Repository(path, data_dir=None) opens the repository whose working
+/// directory is path. data_dir defaults to path/elfshaker_data.
+#[pyclass(name = "Repository", module = "pyelfshaker")]
+pub struct PyRepository {
+    repo: Repository,
+}
+
+#[pymethods]
+impl PyRepository {
+    #[new]
+    #[pyo3(signature = (path, data_dir=None))]
+    fn new(path: PathBuf, data_dir: Option<PathBuf>) -> PyResult<Self> {
+        let data_dir = data_dir.unwrap_or_else(|| path.join(REPO_DIR));
+        let repo = Repository::open_with_data_dir(path, data_dir).map_err(to_py_err)?;
+        Ok(Self { repo })
+    }
+
+    /// Returns the names of the packs in the repository.
+    fn packs(&self) -> PyResult<Vec<String>> {
+        let packs = self.repo.packs().map_err(to_py_err)?;
+        Ok(packs.iter().map(PackId::to_string).collect())
+    }
+
+    /// Returns the snapshots in the repository (or only the ones in the
+    /// specified pack), as "<pack>:<tag>".
+    #[pyo3(signature = (pack=None))]
+    fn snapshots(&self, pack: Option<&str>) -> PyResult<Vec<String>> {
+        let packs = match pack {
+            Some(pack) => vec![pack.parse::<PackId>().map_err(to_py_err)?],
+            None => self.repo.packs().map_err(to_py_err)?,
+        };
+        let mut snapshots = vec![];
+        for pack_id in packs {
+            for tag in self
+                .repo
+                .load_index_snapshots(&pack_id)
+                .map_err(to_py_err)?
+            {
+                let snapshot = SnapshotId::new(pack_id.clone(), &tag).map_err(to_py_err)?;
+                snapshots.push(snapshot.to_string());
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Returns the full name ("<pack>:<tag>") of the snapshot.
+    fn find_snapshot(&self, snapshot: &str) -> PyResult<String> {
+        Ok(self.find(snapshot)?.to_string())
+    }
+
+    /// Returns the paths of the files in the snapshot.
+    fn files(&self, snapshot: &str) -> PyResult<Vec<OsString>> {
+        let snapshot = self.find(snapshot)?;
+        let entries = self
+            .repo
+            .load_snapshot_entries(&snapshot)
+            .map_err(to_py_err)?;
+        Ok(entries.into_iter().map(|entry| entry.path).collect())
+    }
+
+    /// Returns the contents of a file in the snapshot, without writing
+    /// anything to disk. The checksum of the contents is verified.
+    fn read_file<'py>(
+        &self,
+        py: Python<'py>,
+        snapshot: &str,
+        path: PathBuf,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let snapshot = self.find(snapshot)?;
+        let repo = &self.repo;
+        let contents = py
+            .allow_threads(|| repo.read_snapshot_file(&snapshot, &path))
+            .map_err(to_py_err)?
+            .ok_or_else(|| to_py_err(format!("'{}' is not in {}", path.display(), snapshot)))?;
+        Ok(PyBytes::new_bound(py, &contents))
+    }
+
+    /// Extracts the snapshot into the working directory of the repository,
+    /// like `elfshaker extract`. Returns the number of added, removed and
+    /// modified files.
+    #[pyo3(signature = (snapshot, verify=false, reset=false, force=false))]
+    fn extract(
+        &mut self,
+        py: Python<'_>,
+        snapshot: &str,
+        verify: bool,
+        reset: bool,
+        force: bool,
+    ) -> PyResult<HashMap<&'static str, u32>> {
+        let snapshot = self.find(snapshot)?;
+        let mut opts = ExtractOptions::default();
+        opts.set_verify(verify);
+        opts.set_reset(reset);
+        opts.set_force(force);
+
+        let repo = &mut self.repo;
+        let result = py
+            .allow_threads(|| repo.extract_snapshot(snapshot, opts))
+            .map_err(to_py_err)?;
+        Ok(HashMap::from([
+            ("added", result.added_file_count),
+            ("removed", result.removed_file_count),
+            ("modified", result.modified_file_count),
+        ]))
+    }
+
+    /// Fetches the latest indexes of the remotes (like `elfshaker update`).
+    fn update_remotes(&self, py: Python<'_>) -> PyResult<()> {
+        let repo = &self.repo;
+        py.allow_threads(|| repo.update_remotes())
+            .map_err(to_py_err)
+    }
+}
+
+impl PyRepository {
+    fn find(&self, snapshot: &str) -> PyResult<SnapshotId> {
+        self.repo.find_snapshot(snapshot).map_err(to_py_err)
+    }
+}
+
+#[pymodule]
+fn pyelfshaker(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyRepository>()?;
+    m.add("Error", m.py().get_type_bound::<ElfshakerError>())?;
+    Ok(())
+}
@@ -0,0 +1,577 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! A small regular expression engine, used to search the paths of the files
+//! of the snapshots. It supports the usual syntax (like `grep -E`): `.`,
+//! `[...]` and `[^...]` classes, `\d`, `\w`, `\s` (and their negations),
+//! `^`, `$`, groups, `|`, and the `*`, `+`, `?` and `{m,n}` repetitions.
+//! Expressions are compiled to a program run by a Pike VM, so that matching
+//! takes linear time in the length of the text.
+use std::fmt;
+use std::str::FromStr;
+
+/// The largest bound of a `{m,n}` repetition.
+const MAX_REPETITION: u32 = 1000;
+
+/// The deepest nesting of groups and of repetitions, which bounds the stack
+/// used to parse and compile the expression.
+const MAX_NESTING: usize = 256;
+
+/// The largest number of instructions of a compiled program, which bounds the
+/// memory used by the program and the time taken to match each character.
+const MAX_PROGRAM_SIZE: usize = 100_000;
+
+/// A compiled regular expression.
+#[derive(Clone)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegexError {
+    source: String,
+    message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid regex '{}': {}!", self.source, self.message)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+/// A set of characters, as ranges.
+#[derive(Clone, Debug)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn char(c: char) -> Self {
+        Self::of(&[(c, c)], false)
+    }
+
+    fn of(ranges: &[(char, char)], negated: bool) -> Self {
+        Self {
+            negated,
+            ranges: ranges.to_vec(),
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != self.negated
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>),
+}
+
+impl Node {
+    /// The number of instructions of the compiled node (saturating, since
+    /// nested repetitions multiply it).
+    fn program_size(&self) -> usize {
+        match self {
+            Node::Empty => 0,
+            Node::Class(_) | Node::Start | Node::End => 1,
+            Node::Concat(nodes) => nodes
+                .iter()
+                .fold(0, |size, node| size.saturating_add(node.program_size())),
+            Node::Alternate(alternatives) => alternatives
+                .iter()
+                .fold(2 * (alternatives.len() - 1), |size, alternative| {
+                    size.saturating_add(alternative.program_size())
+                }),
+            Node::Repeat(node, min, max) => {
+                let size = node.program_size();
+                let optional_size = match max {
+                    None => size.saturating_add(2),
+                    Some(max) => size.saturating_add(1).saturating_mul((max - min) as usize),
+                };
+                size.saturating_mul(*min as usize)
+                    .saturating_add(optional_size)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Class(Class),
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+    /// The number of groups being parsed.
+    depth: usize,
+    /// The height of the last parsed node.
+    height: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> RegexError {
+        RegexError {
+            source: self.source.to_owned(),
+            message: message.into(),
+        }
+    }
+
+    /// Sets the height of the last parsed node, which must not be nested too
+    /// deeply.
+    fn set_height(&mut self, height: usize) -> Result<(), RegexError> {
+        if height > MAX_NESTING {
+            return Err(self.error(format!("nesting is limited to {MAX_NESTING} levels")));
+        }
+        self.height = height;
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += c.is_some() as usize;
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let is_next = self.peek() == Some(c);
+        self.pos += is_next as usize;
+        is_next
+    }
+
+    fn parse_alternate(&mut self) -> Result<Node, RegexError> {
+        let mut alternatives = vec![self.parse_concat()?];
+        let mut height = self.height;
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+            height = height.max(self.height);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => {
+                self.set_height(height + 1)?;
+                Node::Alternate(alternatives)
+            }
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = vec![];
+        let mut height = 0;
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repetitions(atom)?);
+            height = height.max(self.height);
+        }
+        Ok(match nodes.len() {
+            0 => {
+                self.height = 0;
+                Node::Empty
+            }
+            1 => nodes.pop().unwrap(),
+            _ => {
+                self.set_height(height + 1)?;
+                Node::Concat(nodes)
+            }
+        })
+    }
+
+    fn parse_repetitions(&mut self, mut node: Node) -> Result<Node, RegexError> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.next();
+                    let range = self.parse_range()?;
+                    self.set_height(self.height + 1)?;
+                    node = Node::Repeat(Box::new(node), range.0, range.1);
+                    continue;
+                }
+                _ => return Ok(node),
+            };
+            self.next();
+            // Lazy repetitions match the same texts.
+            self.eat('?');
+            self.set_height(self.height + 1)?;
+            node = Node::Repeat(Box::new(node), min, max);
+        }
+    }
+
+    /// Parses the rest of a `{m}`, `{m,}` or `{m,n}` repetition.
+    fn parse_range(&mut self) -> Result<(u32, Option<u32>), RegexError> {
+        let min = self.parse_number()?;
+        let max = if self.eat(',') {
+            match self.peek() {
+                Some('}') => None,
+                _ => Some(self.parse_number()?),
+            }
+        } else {
+            Some(min)
+        };
+        if !self.eat('}') {
+            return Err(self.error("unterminated repetition"));
+        }
+        match max {
+            Some(max) if max < min => {
+                Err(self.error(format!("invalid repetition {{{min},{max}}}")))
+            }
+            _ => Ok((min, max)),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<u32, RegexError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.next();
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        match digits.parse() {
+            Ok(n) if n <= MAX_REPETITION => Ok(n),
+            Ok(_) => Err(self.error(format!("repetitions are limited to {MAX_REPETITION}"))),
+            Err(_) => Err(self.error("expected a number in the repetition")),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        self.height = 0;
+        match self.next().unwrap() {
+            '(' => {
+                if self.depth == MAX_NESTING {
+                    return Err(self.error(format!("nesting is limited to {MAX_NESTING} levels")));
+                }
+                // Non-capturing groups are the same, since nothing is captured.
+                if self.peek() == Some('?') && self.chars.get(self.pos + 1) == Some(&':') {
+                    self.pos += 2;
+                }
+                self.depth += 1;
+                let node = self.parse_alternate()?;
+                self.depth -= 1;
+                if !self.eat(')') {
+                    return Err(self.error("missing )"));
+                }
+                Ok(node)
+            }
+            '[' => self.parse_class().map(Node::Class),
+            '.' => Ok(Node::Class(Class::of(&[], true))),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '\\' => self.parse_escape().map(Node::Class),
+            c @ ('*' | '+' | '?' | '{') => Err(self.error(format!("nothing to repeat with {c}"))),
+            c => Ok(Node::Class(Class::char(c))),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Class, RegexError> {
+        Ok(match self.next() {
+            Some('d') => Class::of(DIGIT, false),
+            Some('D') => Class::of(DIGIT, true),
+            Some('w') => Class::of(WORD, false),
+            Some('W') => Class::of(WORD, true),
+            Some('s') => Class::of(SPACE, false),
+            Some('S') => Class::of(SPACE, true),
+            Some('n') => Class::char('\n'),
+            Some('t') => Class::char('\t'),
+            Some(c) if c.is_ascii_alphanumeric() => {
+                return Err(self.error(format!("unsupported escape \\{c}")))
+            }
+            Some(c) => Class::char(c),
+            None => return Err(self.error("trailing \\")),
+        })
+    }
+
+    /// Parses the rest of a `[...]` class.
+    fn parse_class(&mut self) -> Result<Class, RegexError> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        let mut is_first = true;
+        loop {
+            let lo = match self.next() {
+                None => return Err(self.error("missing ]")),
+                Some(']') if !is_first => break,
+                Some('\\') => {
+                    let class = self.parse_escape()?;
+                    if class.negated {
+                        return Err(self.error("negated escapes are not supported in classes"));
+                    }
+                    match class.ranges[..] {
+                        [(lo, hi)] if lo == hi => lo,
+                        _ => {
+                            ranges.extend(class.ranges);
+                            is_first = false;
+                            continue;
+                        }
+                    }
+                }
+                Some(c) => c,
+            };
+            is_first = false;
+            let is_range = self.peek() == Some('-')
+                && !matches!(self.chars.get(self.pos + 1), None | Some(']'));
+            if is_range {
+                self.next();
+                let hi = match self.next().unwrap() {
+                    '\\' => match self.parse_escape()?.ranges[..] {
+                        [(lo, hi)] if lo == hi => lo,
+                        _ => return Err(self.error("invalid range in class")),
+                    },
+                    c => c,
+                };
+                if hi < lo {
+                    return Err(self.error(format!("invalid range {lo}-{hi}")));
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Class { negated, ranges })
+    }
+}
+
+/// Compiles the node to instructions appended to the program.
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, program)),
+        Node::Alternate(alternatives) => {
+            let mut jumps = vec![];
+            for (i, alternative) in alternatives.iter().enumerate() {
+                if i + 1 == alternatives.len() {
+                    compile(alternative, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(alternative, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(node, min, max) => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(source: &str) -> Result<Self, RegexError> {
+        let mut parser = Parser {
+            source,
+            chars: source.chars().collect(),
+            pos: 0,
+            depth: 0,
+            height: 0,
+        };
+        let node = parser.parse_alternate()?;
+        if parser.pos != parser.chars.len() {
+            return Err(parser.error("unmatched )"));
+        }
+        if node.program_size() >= MAX_PROGRAM_SIZE {
+            return Err(parser.error(format!(
+                "compiled expressions are limited to {MAX_PROGRAM_SIZE} instructions"
+            )));
+        }
+        let mut program = vec![];
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+        Ok(Self {
+            source: source.to_owned(),
+            program,
+        })
+    }
+
+    /// The expression the regex was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns true if the regex matches somewhere in the text.
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut current = vec![];
+        let mut next = vec![];
+        let mut visited = vec![usize::MAX; self.program.len()];
+        for pos in 0..=chars.len() {
+            // The match can start at any position.
+            if self.add_thread(&mut current, &mut visited, 0, pos, chars.len()) {
+                return true;
+            }
+            let c = match chars.get(pos) {
+                Some(&c) => c,
+                None => break,
+            };
+            for &pc in &current {
+                if let Inst::Class(class) = &self.program[pc] {
+                    if class.matches(c)
+                        && self.add_thread(&mut next, &mut visited, pc + 1, pos + 1, chars.len())
+                    {
+                        return true;
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    /// Adds the threads reached from `pc` at `pos` (following the splits and
+    /// jumps) to `threads`. Returns true if one of them matches.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<usize>,
+        visited: &mut [usize],
+        pc: usize,
+        pos: usize,
+        len: usize,
+    ) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if visited[pc] == pos {
+                continue;
+            }
+            visited[pc] = pos;
+            match self.program[pc] {
+                Inst::Class(_) => threads.push(pc),
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == len => stack.push(pc + 1),
+                Inst::Start | Inst::End => {}
+                Inst::Split(a, b) => {
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Inst::Jump(target) => stack.push(target),
+                Inst::Match => return true,
+            }
+        }
+        false
+    }
+}
+
+impl FromStr for Regex {
+    type Err = RegexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.source).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regex_works() {
+        let cases = [
+            ("abc", "xxabcxx", true),
+            ("abc", "ab", false),
+            ("^lib/.*\\.o$", "lib/a/b.o", true),
+            ("^lib/.*\\.o$", "src/lib/b.o", false),
+            ("^lib/.*\\.o$", "lib/b.os", false),
+            ("a|b|c", "xxc", true),
+            ("(foo|bar)+baz", "foobarfoobaz", true),
+            ("(foo|bar)+baz", "baz", false),
+            ("colou?r", "color", true),
+            ("^a{2,3}$", "aaa", true),
+            ("^a{2,3}$", "aaaa", false),
+            ("^a{2}$", "aa", true),
+            ("^a{2,}$", "aaaaa", true),
+            ("[a-c]+\\d", "xbc7", true),
+            ("[^/]+\\.so$", "lib/libfoo.so", true),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("\\w+\\s\\W", "ab .", true),
+            ("^$", "", true),
+            ("x*", "", true),
+            ("(a*)*b", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaac", false),
+        ];
+        for (regex, text, is_match) in cases {
+            let compiled = Regex::new(regex).unwrap();
+            assert_eq!(is_match, compiled.is_match(text), "{regex} on {text}");
+        }
+        for invalid in ["(a", "a)", "*a", "[a", "a{3,2}", "\\q", "a{1001}", "[z-a]"] {
+            assert!(Regex::new(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn regex_limits_work() {
+        assert!(Regex::new("((a{1000}){1000}){1000}").is_err());
+        assert!(Regex::new("(a{1000}){1000}").is_err());
+        assert!(Regex::new("(a{1000}){50}").is_ok());
+
+        let nested = |n| format!("{}a{}", "(".repeat(n), ")".repeat(n));
+        assert!(Regex::new(&nested(MAX_NESTING)).is_ok());
+        assert!(Regex::new(&nested(MAX_NESTING + 1)).is_err());
+        assert!(Regex::new(&nested(100_000)).is_err());
+        assert!(Regex::new(&format!("a{}", "*".repeat(100_000))).is_err());
+        assert!(Regex::new(&"(a|b)*".repeat(20_000)).is_err());
+        assert!(Regex::new(&format!("^{}$", "(x|(y*z)+)*".repeat(MAX_NESTING / 4))).is_ok());
+    }
+}
@@ -2,6 +2,7 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 use std::panic;
+use std::sync::Mutex;
 
 /// run_in_parallel splits the input items into `nthread` even-sized groups, and
 /// spawns one thread to handle each group. The `workload()` function is run on
@@ -49,6 +50,61 @@ where
     .unwrap()
 }
 
+/// run_in_pool spawns up to `nthread` threads, which run the `workload()`
+/// function on the next input item until all items are processed, so that
+/// items whose workload takes longer are balanced across the threads. This
+/// suits a few items of varying cost (e.g. frames to decompress), rather than
+/// many cheap items (see [`run_in_parallel`]). The order of the outputs
+/// matches that of the input.
+pub fn run_in_pool<Item, Output, Workload>(
+    nthread: usize,
+    items: impl ExactSizeIterator<Item = Item> + Send,
+    workload: Workload,
+) -> Vec<Output>
+where
+    Workload: Sync + Fn(Item) -> Output,
+    Item: Send,
+    Output: Send,
+{
+    assert!(nthread != 0, "nthread == 0");
+    let nthread = std::cmp::min(nthread, items.len());
+    let queue = Mutex::new(items.enumerate());
+    let (queue, workload) = (&queue, &workload);
+    let mut outputs = crossbeam_utils::thread::scope(|s| {
+        let workers: Vec<_> = (0..nthread)
+            .map(|_| {
+                s.spawn(move |_| {
+                    let mut outputs = vec![];
+                    loop {
+                        // The lock is released before running the workload.
+                        let next = queue.lock().unwrap().next();
+                        match next {
+                            Some((i, item)) => outputs.push((i, workload(item))),
+                            None => return outputs,
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|w| w.join())
+            .collect::<Result<Vec<_>, _>>()
+            .map(|v| v.into_iter().flatten().collect::<Vec<_>>())
+    })
+    // As in run_in_parallel, these errors only contain panic information.
+    .map_err(|payload| {
+        panic::resume_unwind(payload);
+    })
+    .unwrap()
+    .map_err(|payload| {
+        panic::resume_unwind(payload);
+    })
+    .unwrap();
+    outputs.sort_unstable_by_key(|&(i, _)| i);
+    outputs.into_iter().map(|(_, output)| output).collect()
+}
+
 /// Partition the dataset into *up to* `n` partitions, using the values returned by `eval` as the partitioning metric.
 pub fn partition_by_u64<T, F: Fn(&T) -> u64>(xs: &[T], n: u32, eval: F) -> Vec<&[T]> {
     if xs.is_empty() {
@@ -83,6 +139,28 @@ pub fn partition_by_u64<T, F: Fn(&T) -> u64>(xs: &[T], n: u32, eval: F) -> Vec<&
     partitions
 }
 
+/// Split the dataset into consecutive chunks, such that the sum of the values
+/// returned by `eval` for each chunk does not exceed `max`. Items exceeding
+/// `max` on their own are put into separate chunks.
+pub fn split_by_u64<T, F: Fn(&T) -> u64>(xs: &[T], max: u64, eval: F) -> Vec<&[T]> {
+    let mut chunks: Vec<&[T]> = vec![];
+    let mut begin = 0;
+    let mut curr = 0;
+    for (i, x) in xs.iter().enumerate() {
+        let v = eval(x);
+        if i != begin && curr + v > max {
+            chunks.push(&xs[begin..i]);
+            begin = i;
+            curr = 0;
+        }
+        curr += v;
+    }
+    if begin < xs.len() {
+        chunks.push(&xs[begin..]);
+    }
+    chunks
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -96,6 +174,21 @@ mod test {
         assert!(expected.eq(&result));
     }
 
+    #[test]
+    fn run_in_pool_works() {
+        let tasks = (0..1000u64).collect::<Vec<_>>();
+        let result = run_in_pool(8, tasks.iter(), |&x| {
+            // Uneven workloads.
+            if x % 100 == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            x * 2
+        });
+        let expected = tasks.into_iter().map(|x| x * 2).collect::<Vec<_>>();
+        assert_eq!(expected, result);
+        assert!(run_in_pool(4, std::iter::empty::<u64>(), |x| x).is_empty());
+    }
+
     #[test]
     fn partition_by_u64_simple_works() {
         let partitions = partition_by_u64(&[1, 2, 3, 4, 5, 6, 7, 8, 9], 2, |&x| x as u64);
@@ -118,4 +211,15 @@ mod test {
         assert_eq!(&[1], partitions[1]);
         assert_eq!(&[1], partitions[2]);
     }
+    #[test]
+    fn split_by_u64_works() {
+        let chunks = split_by_u64(&[1, 2, 3, 9, 4, 4, 1], 5, |&x| x as u64);
+        assert_eq!(5, chunks.len());
+        assert_eq!(&[1, 2], chunks[0]);
+        assert_eq!(&[3], chunks[1]);
+        assert_eq!(&[9], chunks[2]);
+        assert_eq!(&[4], chunks[3]);
+        assert_eq!(&[4, 1], chunks[4]);
+        assert!(split_by_u64(&[0u64; 0], 5, |&x| x).is_empty());
+    }
 }
@@ -0,0 +1,214 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains an asynchronous interface to elfshaker repositories (enabled with
+//! the `async` feature).
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::mpsc;
+use tokio::task;
+
+use super::error::Error;
+use super::repository::{ExtractOptions, ExtractResult, Repository};
+use super::stream::ObjectReader;
+use super::{PackId, SnapshotId};
+use crate::packidx::{FileEntry, PackIndex};
+
+/// The number of objects buffered by the channel returned by
+/// [`AsyncRepository::stream_snapshot`].
+const STREAM_CHANNEL_CAPACITY: usize = 16;
+
+/// The items received from [`AsyncRepository::stream_snapshot`].
+pub type StreamedObject = Result<(PathBuf, ObjectReader), Error>;
+
+/// Wraps a [`Repository`] for use from async code. The (blocking) repository
+/// operations run on the blocking thread pool of the tokio runtime, so they do
+/// not block the executor threads.
+///
+/// Clones share the same [`Repository`]. Operations which only read the
+/// repository can run concurrently, the ones which modify it (e.g.
+/// [`AsyncRepository::extract_snapshot`]) have exclusive access.
+#[derive(Clone)]
+pub struct AsyncRepository {
+    repo: Arc<RwLock<Repository>>,
+}
+
+impl AsyncRepository {
+    pub fn new(repo: Repository) -> Self {
+        Self {
+            repo: Arc::new(RwLock::new(repo)),
+        }
+    }
+
+    /// Opens the specified repository (see [`Repository::open_with_data_dir`]).
+    pub async fn open(path: PathBuf, data_dir: PathBuf) -> Result<Self, Error> {
+        let repo = spawn_blocking(move || Repository::open_with_data_dir(path, data_dir)).await?;
+        Ok(Self::new(repo))
+    }
+
+    /// Runs `f` with shared access to the repository, on the blocking thread
+    /// pool. Used to call the [`Repository`] methods which have no async
+    /// equivalent.
+    pub async fn run<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Repository) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let repo = self.repo.clone();
+        spawn_blocking(move || f(&repo.read().unwrap())).await
+    }
+
+    /// Same as [`AsyncRepository::run`], but with exclusive access to the
+    /// repository.
+    pub async fn run_mut<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Repository) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let repo = self.repo.clone();
+        spawn_blocking(move || f(&mut repo.write().unwrap())).await
+    }
+
+    /// See [`Repository::update_remotes`].
+    pub async fn update_remotes(&self) -> Result<(), Error> {
+        self.run(|repo| repo.update_remotes()).await
+    }
+
+    /// See [`Repository::update_remote_pack`].
+    pub async fn update_remote_pack(&self, pack_id: PackId) -> Result<(), Error> {
+        self.run(move |repo| repo.update_remote_pack(&pack_id))
+            .await
+    }
+
+    /// See [`Repository::packs`].
+    pub async fn packs(&self) -> Result<Vec<PackId>, Error> {
+        self.run(|repo| repo.packs()).await
+    }
+
+    /// See [`Repository::load_index`].
+    pub async fn load_index(&self, pack_id: PackId) -> Result<PackIndex, Error> {
+        self.run(move |repo| repo.load_index(&pack_id)).await
+    }
+
+    /// See [`Repository::find_snapshot`].
+    pub async fn find_snapshot(&self, snapshot: String) -> Result<SnapshotId, Error> {
+        self.run(move |repo| repo.find_snapshot(&snapshot)).await
+    }
+
+    /// See [`Repository::load_snapshot_entries`].
+    pub async fn load_snapshot_entries(
+        &self,
+        snapshot: SnapshotId,
+    ) -> Result<Vec<FileEntry>, Error> {
+        self.run(move |repo| repo.load_snapshot_entries(&snapshot))
+            .await
+    }
+
+    /// See [`Repository::extract_snapshot`].
+    pub async fn extract_snapshot(
+        &self,
+        snapshot: SnapshotId,
+        opts: ExtractOptions,
+    ) -> Result<ExtractResult, Error> {
+        self.run_mut(move |repo| repo.extract_snapshot(snapshot, opts))
+            .await
+    }
+
+    /// Reads the files of the snapshot without writing them to disk (see
+    /// [`Repository::stream_snapshot`]). The objects are read on the blocking
+    /// thread pool and sent to the returned channel, which buffers a few of
+    /// them. Reading stops when the receiver is dropped.
+    pub async fn stream_snapshot(
+        &self,
+        snapshot: SnapshotId,
+        verify: bool,
+    ) -> Result<mpsc::Receiver<StreamedObject>, Error> {
+        let (sender, receiver) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+        let repo = self.repo.clone();
+        let stream = spawn_blocking(move || -> Result<_, Error> {
+            repo.read().unwrap().stream_snapshot(&snapshot, verify)
+        })
+        .await?;
+        // The stream does not borrow the repository, so it can be read without
+        // holding the lock.
+        task::spawn_blocking(move || {
+            for object in stream {
+                if sender.blocking_send(object).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+}
+
+/// Runs `f` on the blocking thread pool, propagating its panics.
+async fn spawn_blocking<F, T>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packidx::{FileMetadata, SnapshotMetadata};
+    use std::io::Read;
+
+    #[test]
+    fn stream_snapshot_works() {
+        let dir = std::env::temp_dir().join(format!("elfshaker_async_test_{}", std::process::id()));
+        let data_dir = dir.join(super::super::REPO_DIR);
+        std::fs::create_dir_all(&data_dir).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let files = runtime.block_on(async {
+            let repo = AsyncRepository::open(dir.clone(), data_dir).await?;
+            let snapshot = SnapshotId::new(PackId::Pack("loose/s1".into()), "s1")?;
+            let snapshot_clone = snapshot.clone();
+            repo.run_mut(move |repo| {
+                let files = vec![
+                    Ok(("a".into(), FileMetadata::default(), b"elf".to_vec())),
+                    Ok(("b".into(), FileMetadata::default(), b"shaker".to_vec())),
+                ];
+                repo.create_snapshot_from_contents(
+                    &snapshot_clone,
+                    files,
+                    SnapshotMetadata::default(),
+                )
+            })
+            .await?;
+            assert_eq!(snapshot, repo.find_snapshot("s1".into()).await?);
+
+            let mut receiver = repo.stream_snapshot(snapshot, true).await?;
+            let mut files = vec![];
+            while let Some(object) = receiver.recv().await {
+                let (path, mut reader) = object?;
+                let mut contents = String::new();
+                reader.read_to_string(&mut contents).unwrap();
+                files.push((path, contents));
+            }
+            Ok::<_, Error>(files)
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut files = files.unwrap();
+        files.sort();
+        assert_eq!(
+            vec![
+                (PathBuf::from("a"), "elf".to_owned()),
+                (PathBuf::from("b"), "shaker".to_owned())
+            ],
+            files
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the Bloom filters of the file paths and of the object checksums of
+//! the packs (see [`BloomFilter`]), which let the lookups of a path or of an
+//! object skip the packs which don't contain it, without loading their index.
+use std::convert::TryInto;
+use std::ffi::OsStr;
+
+use super::index_cache::IndexKey;
+use crate::packidx::{os_str_as_bytes, ObjectChecksum, PackIndex};
+
+const MAGIC: &[u8; 4] = b"ELFB";
+const VERSION: u32 = 1;
+/// About 1% of false positives, with [`NUM_HASHES`] hashes.
+const BITS_PER_ITEM: u64 = 10;
+const NUM_HASHES: u32 = 7;
+/// The size of the header: magic, version, number of hashes and key of the
+/// index.
+const HEADER_SIZE: usize = 4 + 4 + 4 + IndexKey::SIZE;
+
+/// The keys are prefixed by their kind, so that a path can't match a checksum.
+const PATH_KIND: u8 = b'p';
+const OBJECT_KIND: u8 = b'o';
+
+/// A Bloom filter of the file paths and of the object checksums of a pack
+/// index. A path (or object) which is not in the filter is not in the index,
+/// while one which is in the filter is very likely in the index.
+pub(crate) struct BloomFilter {
+    num_hashes: u32,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    fn with_capacity(num_items: usize) -> Self {
+        let num_bytes = (num_items as u64 * BITS_PER_ITEM).div_ceil(8).max(8);
+        Self {
+            num_hashes: NUM_HASHES,
+            bits: vec![0; num_bytes as usize],
+        }
+    }
+
+    /// The filter of the paths and of the objects of the index.
+    pub(crate) fn of_index(index: &PackIndex) -> Self {
+        let paths = index.file_paths();
+        let objects = index.object_checksums();
+        let mut filter = Self::with_capacity(paths.len() + objects.len());
+        for path in paths {
+            filter.insert(PATH_KIND, &os_str_as_bytes(path.as_os_str()));
+        }
+        for checksum in objects {
+            filter.insert(OBJECT_KIND, checksum.as_bytes());
+        }
+        filter
+    }
+
+    pub(crate) fn contains_path(&self, path: &OsStr) -> bool {
+        self.contains(PATH_KIND, &os_str_as_bytes(path))
+    }
+
+    pub(crate) fn contains_object(&self, checksum: &ObjectChecksum) -> bool {
+        self.contains(OBJECT_KIND, checksum.as_bytes())
+    }
+
+    /// The positions of the bits of the key, computed by double hashing.
+    fn positions(&self, kind: u8, key: &[u8]) -> impl Iterator<Item = usize> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[kind]);
+        hasher.update(key);
+        let hash = hasher.finalize();
+        let hash = hash.as_bytes();
+        let h1 = u64::from_le_bytes(hash[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+        let num_bits = self.bits.len() as u64 * 8;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    fn insert(&mut self, kind: u8, key: &[u8]) {
+        for position in self.positions(kind, key).collect::<Vec<_>>() {
+            self.bits[position / 8] |= 1 << (position % 8);
+        }
+    }
+
+    fn contains(&self, kind: u8, key: &[u8]) -> bool {
+        self.positions(kind, key)
+            .all(|position| self.bits[position / 8] & (1 << (position % 8)) != 0)
+    }
+
+    /// Serializes the filter of the index identified by the key.
+    pub(crate) fn to_bytes(&self, key: IndexKey) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.bits.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        bytes.extend_from_slice(&self.num_hashes.to_be_bytes());
+        bytes.extend_from_slice(&key.to_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    /// Deserializes the filter, if it is the one of the index identified by
+    /// the key (i.e. it is not stale) and is valid.
+    pub(crate) fn from_bytes(bytes: &[u8], key: IndexKey) -> Option<Self> {
+        if bytes.len() <= HEADER_SIZE || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let version = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        let num_hashes = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
+        if version != VERSION || num_hashes == 0 || bytes[12..HEADER_SIZE] != key.to_bytes() {
+            return None;
+        }
+        Some(Self {
+            num_hashes,
+            bits: bytes[HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packidx::{FileEntry, FileMetadata, ObjectMetadata, LOOSE_OBJECT_OFFSET};
+    use crate::repo::fs::create_temp_path;
+    use std::ffi::OsString;
+    use std::fs;
+
+    #[test]
+    fn bloom_filter_works() {
+        let mut index = PackIndex::new();
+        let checksum = |c: u8| ObjectChecksum::from_slice(&[c; 20]).unwrap();
+        let entries: Vec<_> = (0..100u8)
+            .map(|i| {
+                FileEntry::new(
+                    OsString::from(format!("dir/file-{i}")),
+                    checksum(i),
+                    ObjectMetadata {
+                        offset: LOOSE_OBJECT_OFFSET,
+                        size: 1,
+                        delta: None,
+                        chunks: None,
+                        pack: None,
+                    },
+                    FileMetadata::default(),
+                )
+            })
+            .collect();
+        index.push_snapshot("a".into(), entries).unwrap();
+        let filter = BloomFilter::of_index(&index);
+        for i in 0..100u8 {
+            assert!(filter.contains_path(OsStr::new(&format!("dir/file-{i}"))));
+            assert!(filter.contains_object(&checksum(i)));
+        }
+        let false_positives = (100..=255u8)
+            .filter(|&i| filter.contains_object(&checksum(i)))
+            .count();
+        assert!(false_positives < 10);
+        // The paths and the checksums are distinct keys.
+        let path = crate::packidx::bytes_to_os_string(checksum(0).as_bytes());
+        assert!(!filter.contains_path(&path));
+
+        let dir = create_temp_path(&std::env::temp_dir());
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("index");
+        fs::write(&path, b"index").unwrap();
+        let key = IndexKey::of(&path).unwrap();
+        let bytes = filter.to_bytes(key);
+        let read = BloomFilter::from_bytes(&bytes, key).unwrap();
+        assert!(read.contains_path(OsStr::new("dir/file-42")));
+        // The filter of an older index is stale.
+        fs::write(&path, b"new index").unwrap();
+        assert!(BloomFilter::from_bytes(&bytes, IndexKey::of(&path).unwrap()).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the cache of the checksums of the files of the working directory
+//! (see [`StatCache`]), which lets `store` skip reading the files which did
+//! not change since the last snapshot.
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::fs::{modified_time, write_file_atomic};
+use crate::checksum::ChecksumAlgorithm;
+use crate::packidx::{bytes_to_os_string, os_str_as_bytes, ObjectChecksum};
+
+/// Bumped when the format of the cache changes, which discards older caches.
+const STAT_CACHE_VERSION: u32 = 1;
+
+/// Files modified less than this long (in nanoseconds) before the cache was
+/// computed are not cached, since a later modification could leave their
+/// size and times unchanged, if the timestamps are coarse.
+const RACY_INTERVAL: i64 = 2_000_000_000;
+
+/// The status of a file when its checksum was computed. The file is assumed
+/// to be unchanged if its status is the same.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+struct CachedStat {
+    size: u64,
+    mtime: i64,
+    ctime: i64,
+    ino: u64,
+    checksum: ObjectChecksum,
+}
+
+impl CachedStat {
+    #[cfg(unix)]
+    fn new(metadata: &fs::Metadata, checksum: ObjectChecksum) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            size: metadata.size(),
+            mtime: modified_time(metadata),
+            ctime: metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec(),
+            ino: metadata.ino(),
+            checksum,
+        }
+    }
+
+    /// There are no change times and inodes on the other systems (i.e.
+    /// Windows), so only the size and the modification time are compared.
+    #[cfg(not(unix))]
+    fn new(metadata: &fs::Metadata, checksum: ObjectChecksum) -> Self {
+        let mtime = modified_time(metadata);
+        Self {
+            size: metadata.len(),
+            mtime,
+            ctime: mtime,
+            ino: 0,
+            checksum,
+        }
+    }
+}
+
+/// The serialized form of the [`StatCache`].
+#[derive(Serialize, Deserialize)]
+struct StatCacheFile {
+    version: u32,
+    work_dir: Vec<u8>,
+    algorithm: u32,
+    entries: Vec<(Vec<u8>, CachedStat)>,
+}
+
+/// The checksums of the regular files of a working directory, along with the
+/// size, times and inode of the files when they were computed. The cache only
+/// applies to the working directory and the checksum algorithm it was computed
+/// for, and is discarded otherwise.
+#[derive(Debug)]
+pub(crate) struct StatCache {
+    work_dir: OsString,
+    algorithm: ChecksumAlgorithm,
+    /// The time (in nanoseconds since the Unix epoch) when the checksums
+    /// started being computed (see [`RACY_INTERVAL`]).
+    started: i64,
+    entries: HashMap<OsString, CachedStat>,
+}
+
+impl StatCache {
+    /// Creates an empty cache.
+    pub(crate) fn new(work_dir: &Path, algorithm: ChecksumAlgorithm) -> Self {
+        let started = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as i64);
+        Self {
+            work_dir: work_dir.as_os_str().to_owned(),
+            algorithm,
+            started,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Loads the cache from `path`. A missing or unreadable cache, or one
+    /// computed for another working directory or checksum algorithm, is
+    /// treated as empty.
+    pub(crate) fn load(path: &Path, work_dir: &Path, algorithm: ChecksumAlgorithm) -> Self {
+        let mut cache = Self::new(work_dir, algorithm);
+        let file = match fs::read(path) {
+            Ok(buf) => rmp_serde::from_slice::<StatCacheFile>(&buf),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return cache,
+            Err(e) => {
+                warn!("Ignoring the unreadable cache {}: {}", path.display(), e);
+                return cache;
+            }
+        };
+        match file {
+            Ok(file)
+                if file.version == STAT_CACHE_VERSION
+                    && file.work_dir == *os_str_as_bytes(&cache.work_dir)
+                    && file.algorithm == algorithm.id() =>
+            {
+                cache.entries = file
+                    .entries
+                    .into_iter()
+                    .map(|(path, stat)| (bytes_to_os_string(&path), stat))
+                    .collect();
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Ignoring the corrupt cache {}: {}", path.display(), e),
+        }
+        cache
+    }
+
+    /// Atomically writes the cache to `path`.
+    pub(crate) fn save(&self, path: &Path, temp_dir: &Path) -> io::Result<()> {
+        let file = StatCacheFile {
+            version: STAT_CACHE_VERSION,
+            work_dir: os_str_as_bytes(&self.work_dir).into_owned(),
+            algorithm: self.algorithm.id(),
+            entries: self
+                .entries
+                .iter()
+                .map(|(path, stat)| (os_str_as_bytes(path).into_owned(), *stat))
+                .collect(),
+        };
+        let buf = rmp_serde::to_vec(&file).expect("Serialization failed!");
+        write_file_atomic(&*buf, temp_dir, path)
+    }
+
+    /// Returns the cached checksum of the file at `path` (the path of its
+    /// entry), if the file did not change since the checksum was computed.
+    pub(crate) fn get(&self, path: &OsStr, metadata: &fs::Metadata) -> Option<ObjectChecksum> {
+        let stat = self.entries.get(path)?;
+        if !metadata.is_file() || *stat != CachedStat::new(metadata, stat.checksum) {
+            return None;
+        }
+        Some(stat.checksum)
+    }
+
+    /// Records the checksum of the file at `path` (the path of its entry).
+    /// Only regular files are recorded, unless they were modified too recently
+    /// to detect further modifications.
+    pub(crate) fn insert(
+        &mut self,
+        path: OsString,
+        metadata: &fs::Metadata,
+        checksum: ObjectChecksum,
+    ) {
+        let stat = CachedStat::new(metadata, checksum);
+        if metadata.is_file()
+            && std::cmp::max(stat.mtime, stat.ctime) + RACY_INTERVAL <= self.started
+        {
+            self.entries.insert(path, stat);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::create_temp_path;
+
+    #[test]
+    fn stat_cache_works() {
+        let temp_dir = std::env::temp_dir();
+        let file_path = create_temp_path(&temp_dir);
+        let cache_path = create_temp_path(&temp_dir);
+        fs::write(&file_path, b"stat cache").unwrap();
+        let checksum = ChecksumAlgorithm::Sha1.checksum(b"stat cache");
+        let work_dir = Path::new("/work");
+        let path = OsStr::new("a/b");
+
+        // The file was just modified, so it is not cached.
+        let mut cache = StatCache::new(work_dir, ChecksumAlgorithm::Sha1);
+        cache.insert(path.into(), &fs::metadata(&file_path).unwrap(), checksum);
+        assert_eq!(0, cache.entries.len());
+        cache.started += RACY_INTERVAL;
+        cache.insert(path.into(), &fs::metadata(&file_path).unwrap(), checksum);
+        cache.save(&cache_path, &temp_dir).unwrap();
+
+        let cache = StatCache::load(&cache_path, work_dir, ChecksumAlgorithm::Sha1);
+        let metadata = fs::metadata(&file_path).unwrap();
+        assert_eq!(Some(checksum), cache.get(path, &metadata));
+        assert_eq!(None, cache.get(OsStr::new("a/c"), &metadata));
+        // The cache only applies to the same working directory and algorithm.
+        let cache_of = |work_dir, algorithm| StatCache::load(&cache_path, work_dir, algorithm);
+        assert_eq!(
+            0,
+            cache_of(Path::new("/other"), ChecksumAlgorithm::Sha1)
+                .entries
+                .len()
+        );
+        assert_eq!(
+            0,
+            cache_of(work_dir, ChecksumAlgorithm::Blake3).entries.len()
+        );
+
+        fs::write(&file_path, b"stat cache 2").unwrap();
+        assert_eq!(None, cache.get(path, &fs::metadata(&file_path).unwrap()));
+
+        fs::write(&cache_path, b"corrupt").unwrap();
+        assert_eq!(0, cache_of(work_dir, ChecksumAlgorithm::Sha1).entries.len());
+        fs::remove_file(&cache_path).unwrap();
+        assert_eq!(0, cache_of(work_dir, ChecksumAlgorithm::Sha1).entries.len());
+        fs::remove_file(&file_path).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the handling of the paths of a snapshot which differ only by case
+//! (see [`CaseCollisions`]). Such paths refer to the same file on
+//! case-insensitive filesystems (the default on Windows and macOS), so
+//! extracting them as is would silently overwrite some of the files.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::packidx::{bytes_to_os_string, os_str_as_bytes, FileEntry};
+
+/// How the paths of a snapshot which differ only by case are handled when
+/// extracting it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaseCollisions {
+    /// The paths are extracted as is (the default on case-sensitive platforms).
+    Allow,
+    /// The extraction fails before modifying any file, listing the colliding
+    /// paths (the default on Windows and macOS).
+    Error,
+    /// All but the first of the colliding paths (in byte order) are
+    /// extracted under a new name, with `~N` appended to the file stem of
+    /// their colliding component (which may be a directory).
+    Rename,
+}
+
+impl CaseCollisions {
+    /// All the supported policies.
+    pub const ALL: [CaseCollisions; 3] = [Self::Allow, Self::Error, Self::Rename];
+
+    /// The name of the policy, as accepted by [`CaseCollisions::from_str`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Error => "error",
+            Self::Rename => "rename",
+        }
+    }
+}
+
+impl Default for CaseCollisions {
+    fn default() -> Self {
+        if cfg!(any(windows, target_os = "macos")) {
+            Self::Error
+        } else {
+            Self::Allow
+        }
+    }
+}
+
+impl FromStr for CaseCollisions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|policy| s.eq_ignore_ascii_case(policy.name()))
+            .ok_or_else(|| format!("Unknown policy '{s}' (expected allow, error or rename)"))
+    }
+}
+
+/// The key of a path on a case-insensitive filesystem.
+fn fold_case(path: &OsString) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// The prefixes of the path, from its first component to the whole path, e.g.
+/// `a`, `a/b` and `a/b/c` for `a/b/c`.
+fn prefixes(path: &OsStr) -> Vec<OsString> {
+    let bytes = os_str_as_bytes(path);
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|(_, &c)| c == b'/')
+        .map(|(i, _)| i)
+        .chain(std::iter::once(bytes.len()))
+        .map(|end| bytes_to_os_string(&bytes[..end]))
+        .collect()
+}
+
+/// Splits the path into its parent (if any) and its last component.
+fn split_parent(path: &OsStr) -> (Option<OsString>, OsString) {
+    let bytes = os_str_as_bytes(path);
+    match bytes.iter().rposition(|&c| c == b'/') {
+        Some(i) => (
+            Some(bytes_to_os_string(&bytes[..i])),
+            bytes_to_os_string(&bytes[i + 1..]),
+        ),
+        None => (None, path.to_owned()),
+    }
+}
+
+fn join(parent: &OsStr, name: &OsStr) -> OsString {
+    let mut path = parent.to_owned();
+    path.push("/");
+    path.push(name);
+    path
+}
+
+/// Returns the groups of paths of the entries which differ only by case. The
+/// directories of the entries are compared too, both to each other and to the
+/// files, since e.g. `inc/foo/bar.h` can't be created next to `inc/Foo`. The
+/// paths of each group, and the groups, are sorted.
+pub(crate) fn find_case_collisions(entries: &[FileEntry]) -> Vec<Vec<OsString>> {
+    let mut seen = HashSet::new();
+    let mut groups: HashMap<String, Vec<OsString>> = HashMap::new();
+    for prefix in entries.iter().flat_map(|e| prefixes(&e.path)) {
+        if seen.insert(prefix.clone()) {
+            groups.entry(fold_case(&prefix)).or_default().push(prefix);
+        }
+    }
+    let mut collisions: Vec<_> = groups
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .map(|mut paths| {
+            paths.sort();
+            paths
+        })
+        .collect();
+    collisions.sort();
+    collisions
+}
+
+/// Renames all but the first of the paths of each group returned by
+/// [`find_case_collisions`], so that no two paths differ only by case. The
+/// colliding directories are renamed along with the paths they contain, from
+/// the shallowest ones. The renaming only depends on the paths of the entries,
+/// so renaming the entries of the same snapshot again gives the same paths.
+pub(crate) fn rename_case_collisions(entries: &mut [FileEntry]) {
+    if find_case_collisions(entries).is_empty() {
+        return;
+    }
+    let mut by_depth: Vec<BTreeSet<OsString>> = vec![];
+    for entry in entries.iter() {
+        for (depth, prefix) in prefixes(&entry.path).into_iter().enumerate() {
+            if by_depth.len() == depth {
+                by_depth.push(BTreeSet::new());
+            }
+            by_depth[depth].insert(prefix);
+        }
+    }
+
+    // The new path of each prefix, once its parent is renamed.
+    let mut renamed: HashMap<OsString, OsString> = HashMap::new();
+    for prefixes in by_depth {
+        let mut groups: BTreeMap<String, Vec<(OsString, OsString)>> = BTreeMap::new();
+        for prefix in prefixes {
+            let new_prefix = match split_parent(&prefix) {
+                (Some(parent), name) => join(&renamed[&parent], &name),
+                (None, _) => prefix.clone(),
+            };
+            groups
+                .entry(fold_case(&new_prefix))
+                .or_default()
+                .push((prefix, new_prefix));
+        }
+        let mut taken: HashSet<String> = groups.keys().cloned().collect();
+        for (_, mut paths) in groups {
+            paths.sort_by(|a, b| a.1.cmp(&b.1));
+            let mut paths = paths.into_iter();
+            let (prefix, new_prefix) = paths.next().unwrap();
+            renamed.insert(prefix, new_prefix);
+            for (prefix, new_prefix) in paths {
+                let (parent, name) = split_parent(&new_prefix);
+                let new_prefix = (1..)
+                    .map(|n| {
+                        let name = with_suffix(&name, n);
+                        match &parent {
+                            Some(parent) => join(parent, &name),
+                            None => name,
+                        }
+                    })
+                    .find(|p| !taken.contains(&fold_case(p)))
+                    .unwrap();
+                taken.insert(fold_case(&new_prefix));
+                renamed.insert(prefix, new_prefix);
+            }
+        }
+    }
+    for entry in entries {
+        entry.path = renamed[&entry.path].clone();
+    }
+}
+
+/// Appends `~N` to the file stem of the file name, e.g. `b.o` becomes `b~1.o`.
+fn with_suffix(file_name: &OsStr, n: u32) -> OsString {
+    let path = Path::new(file_name);
+    let mut new_name = path.file_stem().unwrap_or_default().to_owned();
+    new_name.push(format!("~{n}"));
+    if let Some(extension) = path.extension() {
+        new_name.push(".");
+        new_name.push(extension);
+    }
+    new_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::ObjectChecksum;
+    use crate::packidx::ObjectMetadata;
+
+    fn make_entries(paths: &[&str]) -> Vec<FileEntry> {
+        paths
+            .iter()
+            .map(|path| {
+                FileEntry::new(
+                    (*path).into(),
+                    ObjectChecksum::from([0; 32]),
+                    ObjectMetadata {
+                        offset: 0,
+                        size: 0,
+                        delta: None,
+                        chunks: None,
+                        pack: None,
+                    },
+                    Default::default(),
+                )
+            })
+            .collect()
+    }
+
+    fn paths(entries: &[FileEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn find_case_collisions_works() {
+        let entries = make_entries(&["lib/a.o", "Makefile", "lib/A.o", "LIB/a.o", "makefile2"]);
+        assert_eq!(
+            vec![
+                vec![OsString::from("LIB"), OsString::from("lib")],
+                vec![
+                    OsString::from("LIB/a.o"),
+                    OsString::from("lib/A.o"),
+                    OsString::from("lib/a.o")
+                ]
+            ],
+            find_case_collisions(&entries)
+        );
+        assert!(find_case_collisions(&make_entries(&["a", "b", "a/b"])).is_empty());
+
+        // A file and a directory.
+        let entries = make_entries(&["inc/Foo", "inc/foo/bar.h"]);
+        assert_eq!(
+            vec![vec![OsString::from("inc/Foo"), OsString::from("inc/foo")]],
+            find_case_collisions(&entries)
+        );
+        // Two directories.
+        let entries = make_entries(&["Src/a.c", "src/b.c"]);
+        assert_eq!(
+            vec![vec![OsString::from("Src"), OsString::from("src")]],
+            find_case_collisions(&entries)
+        );
+    }
+
+    #[test]
+    fn rename_case_collisions_works() {
+        let mut entries = make_entries(&["lib/a.o", "lib/A.o", "LIB/a.o", "lib/a~1.o", "README"]);
+        rename_case_collisions(&mut entries);
+        assert_eq!(
+            vec![
+                "lib~1/a~2.o",
+                "lib~1/A.o",
+                "LIB/a.o",
+                "lib~1/a~1.o",
+                "README"
+            ],
+            paths(&entries)
+        );
+        assert!(find_case_collisions(&entries).is_empty());
+
+        let mut entries = make_entries(&["inc/foo/bar.h", "inc/Foo", "inc/foo/baz.h"]);
+        rename_case_collisions(&mut entries);
+        assert_eq!(
+            vec!["inc/foo~1/bar.h", "inc/Foo", "inc/foo~1/baz.h"],
+            paths(&entries)
+        );
+        assert!(find_case_collisions(&entries).is_empty());
+
+        let mut entries = make_entries(&["README", "readme"]);
+        rename_case_collisions(&mut entries);
+        assert_eq!(vec!["README", "readme~1"], paths(&entries));
+    }
+
+    #[test]
+    fn case_collisions_names_work() {
+        for policy in CaseCollisions::ALL {
+            assert_eq!(Ok(policy), policy.name().parse());
+        }
+        assert!("ignore".parse::<CaseCollisions>().is_err());
+    }
+}
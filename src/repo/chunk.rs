@@ -0,0 +1,121 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the content-defined chunking of large objects (see
+//! [`PackOptions::chunk`](super::PackOptions::chunk)). The chunk boundaries
+//! are found with a rolling (gear) hash of the contents, so they only depend on
+//! the bytes preceding them: modifying an object only changes the chunks close
+//! to the modification, the other chunks are shared with the previous version.
+
+/// Objects smaller than this are not split into chunks.
+pub(crate) const MIN_CHUNKED_OBJECT_SIZE: u64 = 1024 * 1024;
+
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// Past the minimum chunk size, a position is a boundary when these bits of the
+/// hash are all 0, which gives chunks of about 80 KiB on average. The high bits
+/// are used, since they depend on more bytes than the low ones.
+const BOUNDARY_MASK: u64 = 0xffff << 48;
+
+/// The random values added to the hash for each byte.
+pub(crate) const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // Generated with SplitMix64, so the table is the same in every build.
+    let mut table = [0; 256];
+    let mut state: u64 = 0x656c_6673_6861_6b65;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Splits `data` into chunks, returning the offset of the end of each chunk.
+pub(crate) fn chunk_boundaries(data: &[u8]) -> Vec<usize> {
+    let mut boundaries = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        start += chunk_size(&data[start..]);
+        boundaries.push(start);
+    }
+    boundaries
+}
+
+/// Returns the size of the chunk at the start of `data`.
+fn chunk_size(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK_SIZE {
+        return data.len();
+    }
+    let end = std::cmp::min(data.len(), MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data[..end].iter().enumerate().skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn random_bytes(size: usize, mut state: u64) -> Vec<u8> {
+        (0..size)
+            .map(|_| {
+                // xorshift64
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks(data: &[u8]) -> HashSet<&[u8]> {
+        let mut start = 0;
+        chunk_boundaries(data)
+            .into_iter()
+            .map(|end| {
+                let chunk = &data[start..end];
+                start = end;
+                chunk
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunk_boundaries_work() {
+        assert!(chunk_boundaries(&[]).is_empty());
+        assert_eq!(vec![100], chunk_boundaries(&[0; 100]));
+        // Without any boundary, the chunks have the maximum size.
+        assert_eq!(
+            vec![MAX_CHUNK_SIZE, MAX_CHUNK_SIZE + 10],
+            chunk_boundaries(&vec![0; MAX_CHUNK_SIZE + 10])
+        );
+
+        let data = random_bytes(4 * 1024 * 1024, 1);
+        let boundaries = chunk_boundaries(&data);
+        assert_eq!(Some(&data.len()), boundaries.last());
+        let mut start = 0;
+        for &end in &boundaries[..boundaries.len() - 1] {
+            assert!((MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&(end - start)));
+            start = end;
+        }
+
+        // Inserting bytes only changes the chunks around the insertion.
+        let mut modified = data.clone();
+        modified.splice(2_000_000..2_000_000, random_bytes(100, 2));
+        let (old, new) = (chunks(&data), chunks(&modified));
+        assert!(old.len() > 20);
+        assert!(old.intersection(&new).count() >= old.len() - 2);
+    }
+}
@@ -0,0 +1,270 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains [`Config`], the parser of the configuration files, which set the
+//! defaults of the command-line options.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::constants::CONFIG_FILE;
+use super::error::Error;
+use super::worktree::common_data_dir;
+
+/// The values of the configuration files, in the syntax of git config files:
+///
+/// ```text
+/// # Comment
+/// [pack]
+///     compression-level = 19
+/// [store]
+///     ignore = *.log
+///     ignore = build/
+/// [remote "origin"]
+///     url = https://example.com/repo.esi
+/// ```
+///
+/// Each value is identified by its key, `<section>.<name>` (e.g.
+/// `pack.compression-level`), or `<section>.<subsection>.<name>` (e.g.
+/// `remote.origin.url`). The section and value names are case-insensitive.
+/// A key can have several values, the last of which applies (see
+/// [`Config::get`]), unless all of them do (see [`Config::get_all`]).
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    values: Vec<(String, String)>,
+}
+
+impl Config {
+    /// Parses the contents of a configuration file. `origin` is only used in
+    /// the error messages.
+    pub fn parse(contents: &str, origin: &Path) -> Result<Self, Error> {
+        let bad_config = |line_number: usize, message: &str| {
+            Error::BadConfig(format!(
+                "{}:{}: {}",
+                origin.display(),
+                line_number + 1,
+                message
+            ))
+        };
+        let mut values = vec![];
+        let mut section = None;
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .strip_suffix(']')
+                    .ok_or_else(|| bad_config(line_number, "expected ']'"))?;
+                section = Some(parse_section(header).ok_or_else(|| {
+                    bad_config(
+                        line_number,
+                        "expected [section] or [section \"subsection\"]",
+                    )
+                })?);
+                continue;
+            }
+            let section = section
+                .as_ref()
+                .ok_or_else(|| bad_config(line_number, "expected a [section] first"))?;
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), unquote(value.trim())),
+                // As with git, a name without a value is a true boolean.
+                None => (line, "true"),
+            };
+            if !is_valid_name(name) {
+                return Err(bad_config(line_number, &format!("bad name '{name}'")));
+            }
+            values.push((
+                format!("{}.{}", section, name.to_ascii_lowercase()),
+                value.to_owned(),
+            ));
+        }
+        Ok(Self { values })
+    }
+
+    /// Reads the configuration file at `path`. A missing file sets no values.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents, path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads the configuration of the user (see [`Config::user_config_path`]),
+    /// then the one of the repository (`<data_dir>/config`), whose values
+    /// take precedence. The linked working trees use the configuration of the
+    /// data directory they share.
+    pub fn load_layered(data_dir: &Path) -> Result<Self, Error> {
+        let mut config = match Self::user_config_path() {
+            Some(path) => Self::load(&path)?,
+            None => Self::default(),
+        };
+        let data_dir = common_data_dir(data_dir)?;
+        config.extend(Self::load(&data_dir.join(CONFIG_FILE))?);
+        Ok(config)
+    }
+
+    /// The path of the configuration of the user:
+    /// `$XDG_CONFIG_HOME/elfshaker/config`, or `~/.config/elfshaker/config`.
+    pub fn user_config_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_home.join("elfshaker").join(CONFIG_FILE))
+    }
+
+    /// Adds the values of `other`, which take precedence.
+    pub fn extend(&mut self, other: Config) {
+        self.values.extend(other.values);
+    }
+
+    /// The last value of the key, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).last()
+    }
+
+    /// All the values of the key, in order.
+    pub fn get_all<'a>(&'a self, key: &str) -> impl DoubleEndedIterator<Item = &'a str> + 'a {
+        let key = normalize_key(key);
+        self.values
+            .iter()
+            .filter(move |(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The last value of the key, parsed as a boolean (`true`, `yes`, `on`,
+    /// `1`, or `false`, `no`, `off`, `0`).
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, Error> {
+        self.get(key)
+            .map(|value| match value.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Ok(true),
+                "false" | "no" | "off" | "0" => Ok(false),
+                _ => Err(Error::BadConfig(format!(
+                    "{key}: expected a boolean, got '{value}'"
+                ))),
+            })
+            .transpose()
+    }
+
+    /// The subsections of the section, in order of first appearance (e.g.
+    /// the names of the remotes, for `remote`).
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let prefix = format!("{}.", section.to_ascii_lowercase());
+        let mut subsections = vec![];
+        for (key, _) in &self.values {
+            let subsection = key
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.rsplit_once('.'))
+                .map(|(subsection, _)| subsection);
+            if let Some(subsection) = subsection {
+                if !subsections.contains(&subsection) {
+                    subsections.push(subsection);
+                }
+            }
+        }
+        subsections
+    }
+}
+
+/// Parses the header of a section (without the brackets), returning the
+/// section part of the keys of its values.
+fn parse_section(header: &str) -> Option<String> {
+    let header = header.trim();
+    match header.split_once(char::is_whitespace) {
+        Some((name, subsection)) => {
+            let subsection = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+            (is_valid_name(name) && !subsection.is_empty())
+                .then(|| format!("{}.{}", name.to_ascii_lowercase(), subsection))
+        }
+        None => is_valid_name(header).then(|| header.to_ascii_lowercase()),
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Lowercases the section and value names of the key, but not the subsection.
+fn normalize_key(key: &str) -> String {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    let (subsection, name) = match rest.rsplit_once('.') {
+        Some((subsection, name)) => (Some(subsection), name),
+        None => (None, rest),
+    };
+    match subsection {
+        Some(subsection) => format!(
+            "{}.{}.{}",
+            section.to_ascii_lowercase(),
+            subsection,
+            name.to_ascii_lowercase()
+        ),
+        None => format!(
+            "{}.{}",
+            section.to_ascii_lowercase(),
+            name.to_ascii_lowercase()
+        ),
+    }
+}
+
+/// Removes the double quotes around the value, if any.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_works() {
+        let origin = Path::new("config");
+        let mut config = Config::parse(
+            "# Comment\n\
+             [Pack]\n\
+             \tCompression-Level = 19\n\
+             \tdelta\n\
+             [store]\n\
+             \tignore = *.log\n\
+             \tignore = \"build/\"\n\
+             ; Comment\n\
+             [remote \"Origin\"]\n\
+             \turl = https://example.com/a.esi\n\
+             [remote \"mirror\"]\n\
+             \turl = https://example.com/b.esi\n",
+            origin,
+        )
+        .unwrap();
+        assert_eq!(Some("19"), config.get("pack.compression-level"));
+        assert_eq!(Some(true), config.get_bool("pack.delta").unwrap());
+        assert_eq!(None, config.get_bool("pack.chunk").unwrap());
+        assert_eq!(
+            vec!["*.log", "build/"],
+            config.get_all("store.ignore").collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["Origin", "mirror"], config.subsections("remote"));
+        assert_eq!(
+            Some("https://example.com/a.esi"),
+            config.get("remote.Origin.url")
+        );
+        assert_eq!(None, config.get("remote.origin.url"));
+
+        // The values of the later files take precedence.
+        config.extend(Config::parse("[pack]\ncompression-level = 3\ndelta = no", origin).unwrap());
+        assert_eq!(Some("3"), config.get("pack.compression-level"));
+        assert_eq!(Some(false), config.get_bool("pack.delta").unwrap());
+
+        assert!(Config::parse("level = 3", origin).is_err());
+        assert!(Config::parse("[pack", origin).is_err());
+        assert!(Config::parse("[remote origin]", origin).is_err());
+        assert!(Config::parse("[pack]\nbad name = 3", origin).is_err());
+        let config = Config::parse("[pack]\ndelta = maybe", origin).unwrap();
+        assert!(config.get_bool("pack.delta").is_err());
+    }
+}
@@ -7,6 +7,44 @@ pub const REPO_DIR: &str = "elfshaker_data";
 pub const INDEX_FILE: &str = "index";
 /// A pointer to the extracted snapshot.
 pub const HEAD_FILE: &str = "HEAD";
+/// A pointer to the snapshot being extracted, written next to HEAD while the
+/// files are modified. It remains if the extraction is interrupted.
+pub const EXTRACTING_FILE: &str = "EXTRACTING";
+/// The file locked exclusively by the operations modifying the repository or
+/// the working directory (e.g. store, pack, extract and gc), so that they
+/// don't run concurrently, while other processes can keep reading.
+pub const WRITE_LOCK_FILE: &str = "write.lock";
+/// The checksums of the files of the working directory, along with their size
+/// and times, so that `store` only reads the files which changed.
+pub const STAT_CACHE_FILE: &str = "store.cache";
+/// The file of the working directory listing the files which are not stored
+/// (in gitignore syntax, see [`IgnoreFile`](super::IgnoreFile)).
+pub const IGNORE_FILE: &str = ".elfshakerignore";
+/// The prefix of the tags of the loose snapshots recording the entries of the
+/// build cache (see
+/// [`Repository::write_build_cache_entry`](super::Repository::write_build_cache_entry)).
+pub const BUILD_CACHE_PREFIX: &str = "cache-";
+/// The number of entries of each of these snapshots, after which a new one is
+/// started, so that adding an entry only rewrites a small index.
+pub const BUILD_CACHE_SNAPSHOT_FILES: usize = 1000;
+/// The configuration of the repository, also the name of the configuration of
+/// the user (see [`Config`](super::Config)).
+pub const CONFIG_FILE: &str = "config";
+/// The tags of the pinned snapshots, one per line (see
+/// [`Repository::pin_snapshot`](super::Repository::pin_snapshot)).
+pub const PINS_FILE: &str = "pins";
+/// A directory containing the refs, one file per ref, containing the
+/// fully-qualified snapshot it points to (see
+/// [`Repository::set_ref`](super::Repository::set_ref)).
+pub const REFS_DIR: &str = "refs";
+/// The paths of the linked working trees sharing the data directory, one per
+/// line (see [`Repository::add_worktree`](super::Repository::add_worktree)).
+pub const WORKTREES_FILE: &str = "worktrees";
+/// The file of the data directory of a linked working tree containing the
+/// path of the data directory it shares, instead of the packs.
+pub const COMMON_DIR_FILE: &str = "commondir";
+/// The name of the checksum algorithm used for new snapshots (BLAKE3 if absent).
+pub const CHECKSUM_ALGORITHM_FILE: &str = "checksum_algorithm";
 /// A directory containing a list of .pack and .pack.idx files
 pub const PACKS_DIR: &str = "packs";
 /// A directory containing the .esi files
@@ -16,14 +54,35 @@ pub const PACK_EXTENSION: &str = "pack";
 /// The file extension of a pack index file.
 pub const PACK_INDEX_EXTENSION: &str = "pack.idx";
 pub const DOT_PACK_INDEX_EXTENSION: &str = ".pack.idx";
+/// The extension of the detached signatures of the packs.
+pub const PACK_SIGNATURE_EXTENSION: &str = "pack.sig";
+/// The extension of the Bloom filters of the paths and objects of the packs,
+/// which are rebuilt from the .pack.idx when missing or stale.
+pub const PACK_BLOOM_EXTENSION: &str = "pack.bloom";
 /// The file extension of remote elfshaker indexes.
 pub const REMOTE_INDEX_EXTENSION: &str = "esi";
 /// A directory containing the object files from all loose snapshots
 pub const LOOSE_DIR: &str = "loose";
+/// A directory containing the objects extracted with
+/// [`ExtractOptions::reflink`](super::ExtractOptions::reflink), which are
+/// reflinked to the extracted files. Can be deleted safely at anytime when
+/// there is no elfshaker operation executing.
+pub const CACHE_DIR: &str = "cache";
+/// A directory containing the flat copies of the pack indexes (see
+/// [`Repository::set_index_disk_cache`](super::Repository::set_index_disk_cache)).
+/// Can be deleted safely at anytime when there is no elfshaker operation
+/// executing.
+pub const INDEX_CACHE_DIR: &str = "index_cache";
+/// The index of the objects of all the packs (see
+/// [`Repository::set_object_index`](super::Repository::set_object_index)).
+/// Can be deleted safely at anytime when there is no elfshaker operation
+/// executing.
+pub const OBJECT_INDEX_FILE: &str = "object_index";
+/// A directory containing the executable hooks run by the elfshaker commands
+/// (e.g. `pre-store`, `post-pack` and `post-extract`).
+pub const HOOKS_DIR: &str = "hooks";
 /// A directory used during store/extract operations. Can be deleted safely
 /// at anytime when there is no elfshaker operation executing.
 pub const TEMP_DIR: &str = "trash";
-// 2^30 = 1024MiB window log
-pub const DEFAULT_WINDOW_LOG_MAX: u32 = 30;
-/// Valid pack headers have this value set in the [`PackHeader::magic`] field.
-pub const PACK_HEADER_MAGIC: u64 = 848629801635942891;
+// Defined by the codecs, which build without the repositories.
+pub use crate::codec::DEFAULT_WINDOW_LOG_MAX;
@@ -0,0 +1,242 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the training of the Zstandard dictionary of a pack (see
+//! [`PackOptions::dictionary_size`](super::PackOptions::dictionary_size)).
+//! The dictionary is trained on a sample of the small objects of the pack,
+//! which are then compressed in frames of their own, so that each of them can
+//! be decompressed alone, while the content shared by many small objects (e.g.
+//! ELF headers and sections common to object files) is encoded as matches into
+//! the dictionary. The other frames are compressed with the dictionary too.
+use std::io;
+
+use zstd::block::Compressor;
+
+use crate::entrypool::Handle;
+use crate::packidx::PackIndex;
+
+/// Objects larger than this are not used as samples, nor compressed in frames
+/// of their own: they compress well enough on their own.
+pub(crate) const MAX_SAMPLE_OBJECT_SIZE: u64 = 128 * 1024;
+
+/// The total size of the samples, relative to the size of the dictionary.
+/// Zstandard recommends about 100 times the size of the dictionary.
+const SAMPLES_SIZE_RATIO: u64 = 100;
+
+/// No dictionary is trained on fewer samples than this, or on samples smaller
+/// in total than [`MIN_SAMPLES_SIZE_RATIO`] times the size of the dictionary:
+/// the dictionary would mostly be made of the samples.
+const MIN_SAMPLES: usize = 16;
+const MIN_SAMPLES_SIZE_RATIO: u64 = 10;
+
+/// The size of a frame in the pack header (see
+/// [`PackFrame`](crate::frame::PackFrame)), which is added to the size of each
+/// frame of a small object.
+const FRAME_HEADER_SIZE: u64 = 8;
+
+/// Returns whether the object group is compressed in a frame of its own when
+/// the pack has a dictionary: a single small object (not a base of deltas).
+pub(crate) fn is_small_group(index: &PackIndex, group: &[Handle]) -> bool {
+    group.len() == 1 && (1..=MAX_SAMPLE_OBJECT_SIZE).contains(&index.group_stored_size(group))
+}
+
+/// Splits the frames so that each small object (see [`is_small_group`]) is
+/// in a frame of its own. The runs of the other objects are kept together.
+pub(crate) fn split_small_objects<'l>(
+    index: &PackIndex,
+    frames: Vec<&'l [Vec<Handle>]>,
+) -> Vec<&'l [Vec<Handle>]> {
+    let mut split = vec![];
+    for groups in frames {
+        let mut begin = 0;
+        for (i, group) in groups.iter().enumerate() {
+            if is_small_group(index, group) {
+                if begin < i {
+                    split.push(&groups[begin..i]);
+                }
+                split.push(&groups[i..i + 1]);
+                begin = i + 1;
+            }
+        }
+        if begin < groups.len() {
+            split.push(&groups[begin..]);
+        }
+    }
+    split
+}
+
+/// Selects the samples to train a dictionary of the specified size on, from
+/// objects of the specified sizes. Returns the indices of the selected objects,
+/// which are spread evenly across `sizes`.
+pub(crate) fn select_samples(sizes: &[u64], dictionary_size: usize) -> Vec<usize> {
+    let candidates: Vec<usize> = (0..sizes.len())
+        .filter(|&i| sizes[i] > 0 && sizes[i] <= MAX_SAMPLE_OBJECT_SIZE)
+        .collect();
+    let total_size: u64 = candidates.iter().map(|&i| sizes[i]).sum();
+    let samples_size = SAMPLES_SIZE_RATIO * dictionary_size as u64;
+    let step = std::cmp::max(1, total_size.div_ceil(std::cmp::max(1, samples_size)));
+    candidates.into_iter().step_by(step as usize).collect()
+}
+
+/// Returns whether there are enough samples, of the specified total size, to
+/// train a dictionary of the specified size on.
+pub(crate) fn has_enough_samples(count: usize, size: u64, dictionary_size: usize) -> bool {
+    count >= MIN_SAMPLES && size >= MIN_SAMPLES_SIZE_RATIO * dictionary_size as u64
+}
+
+/// Trains a dictionary of (at most) the specified size on the samples.
+pub(crate) fn train_dictionary<S: AsRef<[u8]>>(
+    samples: &[S],
+    dictionary_size: usize,
+) -> io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, dictionary_size)
+}
+
+/// Compresses the samples, listed with the index of their frame, in the
+/// order of the frames. Returns the size of their frames compressed without
+/// the dictionary, and the size of the frames of each sample compressed with
+/// the dictionary (both including their size in the pack header).
+pub(crate) fn compressed_sizes(
+    samples: &[(usize, Vec<u8>)],
+    dictionary: &[u8],
+    level: i32,
+) -> io::Result<(u64, u64)> {
+    let mut without_dictionary = 0;
+    let mut frame = vec![];
+    for (i, (frame_index, sample)) in samples.iter().enumerate() {
+        frame.extend_from_slice(sample);
+        if samples
+            .get(i + 1)
+            .is_none_or(|(next, _)| next != frame_index)
+        {
+            without_dictionary +=
+                zstd::block::compress(&frame, level)?.len() as u64 + FRAME_HEADER_SIZE;
+            frame.clear();
+        }
+    }
+    let mut compressor = Compressor::with_dict(dictionary.to_vec());
+    let mut with_dictionary = 0;
+    for (_, sample) in samples {
+        with_dictionary += compressor.compress(sample, level)?.len() as u64 + FRAME_HEADER_SIZE;
+    }
+    Ok((without_dictionary, with_dictionary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packidx::{FileEntry, FileMetadata, ObjectChecksum, ObjectMetadata};
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use zstd::block::{Compressor, Decompressor};
+
+    #[test]
+    fn select_samples_works() {
+        assert!(select_samples(&[], 1024).is_empty());
+        // Empty and large objects are never selected.
+        assert_eq!(
+            vec![1, 3],
+            select_samples(&[0, 10, MAX_SAMPLE_OBJECT_SIZE + 1, 20], 1024)
+        );
+        // Every other object is selected to stay close to the samples size.
+        let sizes = vec![1000; 400];
+        let samples = select_samples(&sizes, 2000);
+        assert_eq!(200, samples.len());
+        assert_eq!(&[0, 2, 4], &samples[..3]);
+    }
+
+    #[test]
+    fn split_small_objects_works() {
+        let mut index = PackIndex::new();
+        let sizes = [
+            20,
+            MAX_SAMPLE_OBJECT_SIZE + 2,
+            10,
+            MAX_SAMPLE_OBJECT_SIZE + 1,
+        ];
+        let entries: Vec<_> = (0..sizes.len())
+            .map(|i| {
+                FileEntry::new(
+                    OsString::from(format!("file-{}", i)),
+                    ObjectChecksum::from_slice(&[i as u8; 20]).unwrap(),
+                    ObjectMetadata {
+                        offset: 0,
+                        size: sizes[i],
+                        delta: None,
+                        chunks: None,
+                        pack: None,
+                    },
+                    FileMetadata::default(),
+                )
+            })
+            .collect();
+        index.push_snapshot("s".into(), entries).unwrap();
+        let (index, groups) = index.compute_object_offsets_and_ordering(
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::<Handle, u64>::new(),
+        );
+        // The groups are ordered by size: the small objects come first.
+        let frames = split_small_objects(&index, vec![&groups[..3], &groups[3..]]);
+        let sizes: Vec<Vec<u64>> = frames
+            .iter()
+            .map(|frame| frame.iter().map(|g| index.group_stored_size(g)).collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec![10],
+                vec![20],
+                vec![MAX_SAMPLE_OBJECT_SIZE + 1],
+                vec![MAX_SAMPLE_OBJECT_SIZE + 2]
+            ],
+            sizes
+        );
+    }
+
+    #[test]
+    fn has_enough_samples_works() {
+        assert!(has_enough_samples(16, 10 * 1024, 1024));
+        assert!(!has_enough_samples(15, 10 * 1024, 1024));
+        assert!(!has_enough_samples(16, 10 * 1024 - 1, 1024));
+    }
+
+    #[test]
+    fn train_dictionary_works() {
+        let samples: Vec<Vec<u8>> = (0..1000u32)
+            .map(|i| format!("\x7fELF common header {} shared trailer", i * 7919).into_bytes())
+            .collect();
+        let dictionary = train_dictionary(&samples, 4096).unwrap();
+        assert!(!dictionary.is_empty() && dictionary.len() <= 4096);
+
+        let sample = &samples[123];
+        let compressed = Compressor::with_dict(dictionary.clone())
+            .compress(sample, 3)
+            .unwrap();
+        let decompressed = Decompressor::with_dict(dictionary)
+            .decompress(&compressed, sample.len())
+            .unwrap();
+        assert_eq!(sample, &decompressed);
+        assert!(compressed.len() < zstd::block::compress(sample, 3).unwrap().len());
+    }
+
+    #[test]
+    fn compressed_sizes_works() {
+        let samples: Vec<(usize, Vec<u8>)> = (0..200u32)
+            .map(|i| {
+                let sample = format!("\x7fELF common header {} shared trailer", i * 7919);
+                ((i / 100) as usize, sample.into_bytes())
+            })
+            .collect();
+        let contents: Vec<&Vec<u8>> = samples.iter().map(|(_, sample)| sample).collect();
+        let dictionary = train_dictionary(&contents, 1024).unwrap();
+        let (without_dictionary, with_dictionary) =
+            compressed_sizes(&samples, &dictionary, 3).unwrap();
+        // The two frames of 100 samples compress better than each sample alone.
+        assert!(without_dictionary < with_dictionary);
+        // But in frames of their own, the samples compress better with it.
+        let samples: Vec<_> = contents.into_iter().cloned().enumerate().collect();
+        let (without_dictionary, with_dictionary) =
+            compressed_sizes(&samples, &dictionary, 3).unwrap();
+        assert!(with_dictionary < without_dictionary);
+    }
+}
@@ -0,0 +1,278 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the encryption at rest of the packs, which lets repositories of
+//! proprietary binaries be hosted on untrusted storage.
+//!
+//! The frames of an encrypted pack are compressed as usual, then encrypted
+//! separately with ChaCha20-Poly1305, so that the frames containing some
+//! objects can still be fetched and decrypted on their own. The pack header
+//! (see [`PackHeader`](super::PackHeader)) stays readable: it records the ID
+//! of the key (see [`EncryptionKey::id`]) and a random salt, from which the
+//! key of the pack is derived (HMAC-SHA256 of the salt). The frames are
+//! encrypted with their index as the nonce, so they can't be reordered.
+//!
+//! Pack indexes can be encrypted too, in which case the whole .pack.idx is
+//! encrypted, in the following envelope:
+//!
+//! ```text
+//! ELFE | key ID (8 bytes) | salt (32 bytes) | encrypted .pack.idx | tag (16 bytes)
+//! ```
+use std::{
+    convert::TryInto,
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use rand::RngCore;
+
+use super::error::Error;
+use crate::frame::PackEncryption;
+
+/// The magic of encrypted pack indexes (the one of pack indexes is `ELFS`).
+pub(crate) const ENCRYPTED_INDEX_MAGIC: &[u8; 4] = b"ELFE";
+const KEY_ID_SIZE: usize = 8;
+const SALT_SIZE: usize = 32;
+const TAG_SIZE: usize = 16;
+const ENCRYPTED_INDEX_HEADER_SIZE: usize = ENCRYPTED_INDEX_MAGIC.len() + KEY_ID_SIZE + SALT_SIZE;
+
+/// A 256-bit secret key, formatted as hex.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl FromStr for EncryptionKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(EncryptionKey)
+            .ok_or_else(|| Error::Encryption("Not an encryption key (64 hex digits)".to_owned()))
+    }
+}
+
+impl EncryptionKey {
+    /// Generates a new random key.
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Loads the key from the file (see [`EncryptionKey::save`]).
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        fs::read_to_string(path)?.parse().map_err(|_| {
+            Error::Encryption(format!(
+                "{} does not contain an encryption key (64 hex digits)",
+                path.display()
+            ))
+        })
+    }
+
+    /// Writes the key to the file, which is only readable by the user.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        writeln!(options.open(path)?, "{}", hex::encode(self.0))
+    }
+
+    /// The ID of the key, which is recorded in the packs encrypted with it
+    /// (the first bytes of its SHA-256 digest), so that the key needed to
+    /// decrypt a pack can be found among the configured ones.
+    pub fn id(&self) -> KeyId {
+        let mut digest = [0u8; 32];
+        let mut hasher = Sha256::new();
+        hasher.input(&self.0);
+        hasher.result(&mut digest);
+        KeyId(digest[..KEY_ID_SIZE].try_into().unwrap())
+    }
+
+    /// Derives the cipher of the pack (or pack index) with the salt.
+    fn cipher(&self, salt: &[u8]) -> Cipher {
+        let mut hmac = Hmac::new(Sha256::new(), &self.0);
+        hmac.input(salt);
+        Cipher(hmac.result().code().try_into().unwrap())
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    /// Only the ID of the key is formatted, so that it doesn't end up in logs.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "EncryptionKey({})", self.id())
+    }
+}
+
+/// The ID of an [`EncryptionKey`], formatted as hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyId([u8; KEY_ID_SIZE]);
+
+impl Display for KeyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl PackEncryption {
+    /// Encrypts a new pack with the key, with a random salt.
+    pub(crate) fn new(key: &EncryptionKey) -> Self {
+        Self {
+            key_id: key.id().0.to_vec(),
+            salt: random_salt().to_vec(),
+        }
+    }
+
+    /// The ID of the key the pack is encrypted with, formatted as hex.
+    pub(crate) fn key_id(&self) -> String {
+        hex::encode(&self.key_id)
+    }
+
+    /// The cipher of the pack, if one of the keys is the one it is encrypted
+    /// with.
+    pub(crate) fn cipher(&self, keys: &[EncryptionKey]) -> Option<Cipher> {
+        keys.iter()
+            .find(|key| key.id().0[..] == self.key_id[..])
+            .map(|key| key.cipher(&self.salt))
+    }
+}
+
+/// The ChaCha20-Poly1305 key of a pack (or pack index).
+#[derive(Clone)]
+pub(crate) struct Cipher([u8; 32]);
+
+impl Cipher {
+    /// Encrypts the data, appending the authentication tag. Each nonce must
+    /// only be used once.
+    pub(crate) fn seal(&self, nonce: u64, data: &[u8]) -> Vec<u8> {
+        let mut sealed = vec![0; data.len() + TAG_SIZE];
+        let (ciphertext, tag) = sealed.split_at_mut(data.len());
+        ChaCha20Poly1305::new(&self.0, &nonce.to_le_bytes(), &[]).encrypt(data, ciphertext, tag);
+        sealed
+    }
+
+    /// Decrypts the data encrypted by [`Cipher::seal`] with the same nonce.
+    /// Returns None if the data was not encrypted with this key and nonce, or
+    /// has been tampered with.
+    pub(crate) fn open(&self, nonce: u64, sealed: &[u8]) -> Option<Vec<u8>> {
+        let size = sealed.len().checked_sub(TAG_SIZE)?;
+        let (ciphertext, tag) = sealed.split_at(size);
+        let mut data = vec![0; size];
+        ChaCha20Poly1305::new(&self.0, &nonce.to_le_bytes(), &[])
+            .decrypt(ciphertext, &mut data, tag)
+            .then_some(data)
+    }
+}
+
+fn random_salt() -> [u8; SALT_SIZE] {
+    let mut salt = [0u8; SALT_SIZE];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Whether the contents of the .pack.idx are encrypted (see the module
+/// documentation).
+pub(crate) fn is_encrypted_index(bytes: &[u8]) -> bool {
+    bytes.starts_with(ENCRYPTED_INDEX_MAGIC)
+}
+
+/// Encrypts the contents of a .pack.idx with the key.
+pub(crate) fn encrypt_index(key: &EncryptionKey, index: &[u8]) -> Vec<u8> {
+    let salt = random_salt();
+    let mut bytes = Vec::with_capacity(ENCRYPTED_INDEX_HEADER_SIZE + index.len() + TAG_SIZE);
+    bytes.extend_from_slice(ENCRYPTED_INDEX_MAGIC);
+    bytes.extend_from_slice(&key.id().0);
+    bytes.extend_from_slice(&salt);
+    bytes.extend(key.cipher(&salt).seal(0, index));
+    bytes
+}
+
+/// The ID of the key the .pack.idx is encrypted with.
+pub(crate) fn index_key_id(bytes: &[u8]) -> Option<KeyId> {
+    if !is_encrypted_index(bytes) || bytes.len() < ENCRYPTED_INDEX_HEADER_SIZE {
+        return None;
+    }
+    let start = ENCRYPTED_INDEX_MAGIC.len();
+    Some(KeyId(bytes[start..start + KEY_ID_SIZE].try_into().unwrap()))
+}
+
+/// Decrypts the contents of a .pack.idx encrypted by [`encrypt_index`] with
+/// one of the keys.
+pub(crate) fn decrypt_index(keys: &[EncryptionKey], bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let key_id = index_key_id(bytes).ok_or(Error::CorruptPackIndex)?;
+    let key = keys.iter().find(|key| key.id() == key_id).ok_or_else(|| {
+        Error::Encryption(format!(
+            "The pack index is encrypted with the key {key_id}, which is not configured \
+             (see encryption.key)"
+        ))
+    })?;
+    let salt = &bytes[ENCRYPTED_INDEX_HEADER_SIZE - SALT_SIZE..ENCRYPTED_INDEX_HEADER_SIZE];
+    key.cipher(salt)
+        .open(0, &bytes[ENCRYPTED_INDEX_HEADER_SIZE..])
+        .ok_or_else(|| {
+            Error::Encryption(format!(
+                "Failed to decrypt the pack index with the key {key_id} (it is corrupt)"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encryption_works() {
+        let key = EncryptionKey::generate();
+        let other_key = EncryptionKey::generate();
+        assert_ne!(key.id(), other_key.id());
+        assert_eq!(
+            key.id(),
+            hex::encode(key.0).parse::<EncryptionKey>().unwrap().id()
+        );
+
+        let encryption = PackEncryption::new(&key);
+        assert_eq!(key.id().to_string(), encryption.key_id());
+        assert!(encryption
+            .cipher(std::slice::from_ref(&other_key))
+            .is_none());
+        let cipher = encryption
+            .cipher(&[other_key.clone(), key.clone()])
+            .unwrap();
+        let sealed = cipher.seal(1, b"frame");
+        assert_eq!(b"frame".len() + TAG_SIZE, sealed.len());
+        assert_eq!(b"frame".to_vec(), cipher.open(1, &sealed).unwrap());
+        // The nonce, the key and the contents are authenticated.
+        assert!(cipher.open(0, &sealed).is_none());
+        let other_cipher = PackEncryption::new(&key)
+            .cipher(std::slice::from_ref(&key))
+            .unwrap();
+        assert!(other_cipher.open(1, &sealed).is_none());
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(cipher.open(1, &tampered).is_none());
+        assert!(cipher.open(1, &sealed[..TAG_SIZE - 1]).is_none());
+
+        let index = encrypt_index(&key, b"ELFS index");
+        assert!(is_encrypted_index(&index));
+        assert_eq!(Some(key.id()), index_key_id(&index));
+        assert_eq!(
+            b"ELFS index".to_vec(),
+            decrypt_index(std::slice::from_ref(&key), &index).unwrap()
+        );
+        assert!(decrypt_index(&[other_key], &index).is_err());
+        assert!(decrypt_index(&[key], &index[..index.len() - 1]).is_err());
+    }
+}
@@ -4,11 +4,13 @@
 use std::ffi::OsString;
 use std::{fmt::Display, io};
 
+use crate::checksum::ObjectChecksum;
 use crate::packidx::PackError;
 use crate::repo::pack::IdError;
 use crate::repo::remote::RemoteIndexFormatError;
 
-use super::PackId;
+use super::constants::IGNORE_FILE;
+use super::{PackId, SnapshotId};
 
 /// The type of error used by repository operations.
 #[derive(Debug)]
@@ -20,16 +22,28 @@ pub enum Error {
     IdError(IdError),
     /// Bad elfshaker_data/HEAD (missing HEAD is okay and means that nothing has been extracted so far)
     CorruptHead,
+    /// Bad elfshaker_data/checksum_algorithm
+    BadChecksumAlgorithm(String),
     /// The references snapshot/pack is missing.
     BrokenHeadRef(Box<Error>),
+    /// The ref (see [`Repository::set_ref`](super::Repository::set_ref))
+    /// points to a snapshot which no longer exists.
+    BrokenRef(String, SnapshotId),
     /// The .pack.idx is corrupt
     CorruptPackIndex,
     /// The .pack file is corrupt.
     CorruptPack,
+    /// The contents extracted for the file (at the path) don't have the
+    /// checksum recorded for it (the expected and actual checksums, see
+    /// [`ExtractOptions::verify`](super::ExtractOptions::verify)).
+    CorruptFile(OsString, ObjectChecksum, ObjectChecksum),
     /// Multiple or none snapshots match the specified description
     AmbiguousSnapshotMatch(String, Vec<PackId>),
     /// The working directory contains unexpected files
     DirtyWorkDir,
+    /// The extraction of the snapshot was interrupted, so the working
+    /// directory contains the files of neither HEAD nor the snapshot.
+    InterruptedExtraction(String),
     /// The .pack file is not available in packs/
     PackNotFound(String),
     /// The directory is not a repository
@@ -40,6 +54,39 @@ pub enum Error {
     BadRemoteIndexFormat(RemoteIndexFormatError),
     /// A type-erased error resulting from an HTTP operation.
     HttpError(Box<dyn std::error::Error + Send + Sync>),
+    /// The snapshot contains groups of paths which differ only by case (see
+    /// [`CaseCollisions`](super::CaseCollisions)).
+    CaseCollisions(Vec<Vec<OsString>>),
+    /// A line of the ignore file (see [`IgnoreFile`](super::IgnoreFile)) is
+    /// not a valid pattern.
+    BadIgnorePattern(String, glob::PatternError),
+    /// A configuration file (see [`Config`](super::Config)) is invalid.
+    BadConfig(String),
+    /// The signature of a pack (see [`PackSignature`](super::PackSignature))
+    /// is missing, invalid, or not the one of a trusted key.
+    BadSignature(String),
+    /// The pack is encrypted (see [`EncryptionKey`](super::EncryptionKey))
+    /// with a key which is not configured, or can't be decrypted with it.
+    Encryption(String),
+    /// The pack can't be deleted, renamed or rewritten, since the other packs
+    /// read some of its objects from it (see
+    /// [`PackOptions::dedup`](super::PackOptions::dedup)).
+    PackReferenced(PackId, Vec<PackId>),
+    /// The object is stored in the pack, according to the index of another
+    /// pack, but the pack doesn't store it (or is missing).
+    MissingExternalObject(ObjectChecksum, String),
+    /// The pack reads some of its objects from the other packs (see
+    /// [`PackOptions::dedup`](super::PackOptions::dedup)), which are not
+    /// available in the repository it is pushed to.
+    MissingExternalPacks(PackId, Vec<String>),
+    /// A file received from another repository (see
+    /// [`Repository::write_pack_file`](super::Repository::write_pack_file)) is
+    /// larger than the limit, in bytes.
+    FileTooLarge(String, u64),
+    /// The key of a build cache entry (see
+    /// [`Repository::write_build_cache_entry`](super::Repository::write_build_cache_entry))
+    /// is not a relative path of Latin letters, digits, -, _ and .
+    BadBuildCacheKey(String),
 }
 
 impl From<walkdir::Error> for Error {
@@ -61,11 +108,25 @@ impl Display for Error {
             Self::WalkDirError(wderr) => wderr.fmt(f),
             Self::Utf8Error(s) => write!(f, "Unable to interpret path as utf8: {s:?}"),
             Self::CorruptHead => write!(f, "HEAD is corrupt!"),
+            Self::BadChecksumAlgorithm(e) => write!(f, "Bad repository checksum algorithm: {e}"),
             Self::BrokenHeadRef(e) => write!(f, "Broken HEAD: {e}"),
+            Self::BrokenRef(name, snapshot) => write!(
+                f,
+                "The ref {name} points to {snapshot}, which no longer exists! \
+                Update it with elfshaker tag."
+            ),
             Self::CorruptPack => {
                 write!(f, "The pack file is corrupt!")
             }
             Self::CorruptPackIndex => write!(f, "The pack index is corrupt!"),
+            Self::CorruptFile(path, exp, got) => write!(
+                f,
+                "The contents of {} are corrupt! exp {} got {} \
+                 (use elfshaker verify to find the corrupt objects)",
+                path.to_string_lossy(),
+                hex::encode(exp),
+                hex::encode(got)
+            ),
             Self::AmbiguousSnapshotMatch(snapshot, packs) => {
                 write!(
                     f,
@@ -77,6 +138,11 @@ impl Display for Error {
                 "Some files in the repository have been removed or modified unexpectedly! \
                  You can use --force to skip this check, but this might result in DATA LOSS!"
             ),
+            Self::InterruptedExtraction(snapshot) => write!(
+                f,
+                "The extraction of {snapshot} was interrupted, so some files are those of HEAD \
+                 and others are those of {snapshot}! Use --reset to extract a snapshot fully."
+            ),
             Self::PackNotFound(p) => write!(
                 f,
                 "The specified pack file '{p}' could not be found in the repository index!",
@@ -85,6 +151,57 @@ impl Display for Error {
             Self::BadLooseObject(s) => write!(f, "Bad loose object: {s}"),
             Self::HttpError(e) => e.fmt(f),
             Self::BadRemoteIndexFormat(e) => e.fmt(f),
+            Self::CaseCollisions(collisions) => {
+                writeln!(
+                    f,
+                    "The snapshot contains {} groups of paths which differ only by case, \
+                     which would overwrite each other on a case-insensitive filesystem:",
+                    collisions.len()
+                )?;
+                for paths in collisions {
+                    let paths: Vec<_> = paths.iter().map(|p| p.to_string_lossy()).collect();
+                    writeln!(f, "  {}", paths.join(", "))?;
+                }
+                write!(
+                    f,
+                    "Use --case-collisions rename to extract them under new names, \
+                     or --case-collisions allow to extract them anyway."
+                )
+            }
+            Self::BadIgnorePattern(line, e) => {
+                write!(f, "Bad pattern '{line}' in {IGNORE_FILE}: {e}")
+            }
+            Self::BadConfig(e) => write!(f, "Bad configuration: {e}"),
+            Self::BadSignature(e) => write!(f, "Bad signature: {e}"),
+            Self::Encryption(e) => write!(f, "Encryption error: {e}"),
+            Self::PackReferenced(pack, packs) => {
+                let packs: Vec<_> = packs.iter().map(|pack| pack.to_string()).collect();
+                write!(
+                    f,
+                    "The pack {pack} stores objects of the packs {}! \
+                     Repack them without --dedup first.",
+                    packs.join(", ")
+                )
+            }
+            Self::MissingExternalObject(checksum, pack) => write!(
+                f,
+                "The object {} is not stored in the pack {pack}, which should store it!",
+                hex::encode(checksum)
+            ),
+            Self::MissingExternalPacks(pack, packs) => write!(
+                f,
+                "The pack {pack} reads some of its objects from the packs {}, which the \
+                 destination doesn't have! Push them first, or repack it without --dedup.",
+                packs.join(", ")
+            ),
+            Self::FileTooLarge(file_name, limit) => {
+                write!(f, "The file {file_name} is larger than {limit} bytes!")
+            }
+            Self::BadBuildCacheKey(key) => write!(
+                f,
+                "Invalid build cache key '{key}'! Latin letters, digits, -, _, . and / \
+                 (between the parts of the key) are allowed!"
+            ),
         }
     }
 }
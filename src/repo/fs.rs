@@ -2,17 +2,27 @@
 //! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
 
 use fs2::FileExt;
+use log::warn;
 use rand::RngCore;
 
 use std::{
+    ffi::{CStr, CString, OsString},
     fs,
     fs::{File, Permissions},
     io,
-    io::Read,
+    io::{Read, Write},
     path::{Path, PathBuf},
     time::SystemTime,
 };
 
+use crate::packidx::{bytes_to_os_string, is_valid_entry_path, os_str_as_bytes, Xattr};
+#[cfg(not(unix))]
+use crate::packidx::{S_IFDIR, S_IFLNK};
+
+/// The file type bits of a regular file.
+#[cfg(not(unix))]
+const S_IFREG: u32 = 0o100000;
+
 /// Returns the most recent of [`fs::Metadata::created`] and
 /// [`fs::Metadata::modified`], or [`None`], if neither succeeds.
 pub fn get_last_modified(metadata: fs::Metadata) -> Option<SystemTime> {
@@ -20,10 +30,70 @@ pub fn get_last_modified(metadata: fs::Metadata) -> Option<SystemTime> {
         .created()
         .ok()
         .into_iter()
-        .chain(metadata.modified().ok().into_iter())
+        .chain(metadata.modified().ok())
         .max()
 }
 
+/// Returns the mode (`st_mode`) of the file. On the systems without Unix
+/// permissions (i.e. Windows), it is made up from the type of the file and
+/// whether it is read-only.
+#[cfg(unix)]
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    std::os::unix::fs::MetadataExt::mode(metadata)
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        S_IFLNK | 0o777
+    } else if file_type.is_dir() {
+        S_IFDIR | 0o755
+    } else if metadata.permissions().readonly() {
+        S_IFREG | 0o444
+    } else {
+        S_IFREG | 0o644
+    }
+}
+
+/// Returns the modification time of the file, in nanoseconds since the Unix
+/// epoch.
+#[cfg(unix)]
+pub fn modified_time(metadata: &fs::Metadata) -> i64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec()
+}
+
+#[cfg(not(unix))]
+pub fn modified_time(metadata: &fs::Metadata) -> i64 {
+    match metadata
+        .modified()
+        .map(|time| time.duration_since(SystemTime::UNIX_EPOCH))
+    {
+        Ok(Ok(duration)) => duration.as_nanos() as i64,
+        Ok(Err(e)) => -(e.duration().as_nanos() as i64),
+        Err(_) => 0,
+    }
+}
+
+/// Sets the permissions of the file at `path` from its mode (`st_mode`). Only
+/// whether the file is read-only is set on the systems without Unix
+/// permissions.
+#[cfg(unix)]
+pub fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    let mut permissions = fs::symlink_metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
 /// Ensures that the directory exists.
 /// Unlike [`fs::create_dir()`], this function does not return Err if the directory already exists.
 pub fn ensure_dir(path: &Path) -> io::Result<()> {
@@ -34,23 +104,199 @@ pub fn ensure_dir(path: &Path) -> io::Result<()> {
     }
 }
 
+/// Flushes the entries of the directory to disk, so that the files created in
+/// (or renamed into) it survive a power loss.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
 /// Creates a destination file [`dest`]. Uses a temporary file in [`temp_dir`] to write to and then
 /// moves the file (via Posix rename() or equiv. atomic operation). If the process is killed,
 /// the file in [`temp_dir`] might remain.
 ///
 /// NOTE: [`temp_dir`] and [`dest`] must be on the same filesystem!
 pub fn write_file_atomic(mut r: impl Read, temp_dir: &Path, dest: &Path) -> io::Result<()> {
+    write_temp_file_and_rename(temp_dir, dest, |file| io::copy(&mut r, file).map(|_| ()))
+}
+
+/// Same as [`write_file_atomic`], but [`dest`] is left untouched, and `false`
+/// returned, if [`r`] contains more than `max_size` bytes.
+pub fn write_file_atomic_limited(
+    r: impl Read,
+    max_size: u64,
+    temp_dir: &Path,
+    dest: &Path,
+) -> io::Result<bool> {
+    let temp_path = create_temp_path(temp_dir);
+    let mut temp_file = create_file(&temp_path, None)?;
+    temp_file.try_lock_exclusive()?;
+    if io::copy(&mut r.take(max_size.saturating_add(1)), &mut temp_file)? > max_size {
+        drop(temp_file);
+        fs::remove_file(&temp_path)?;
+        return Ok(false);
+    }
+    temp_file.sync_data()?;
+    fs::rename(temp_path, dest)?;
+    Ok(true)
+}
+
+/// Same as [`write_file_atomic`], but the file is written sparsely (see
+/// [`write_sparse`]).
+pub fn write_sparse_file_atomic(buf: &[u8], temp_dir: &Path, dest: &Path) -> io::Result<()> {
+    write_temp_file_and_rename(temp_dir, dest, |file| write_sparse(file, buf))
+}
+
+fn write_temp_file_and_rename(
+    temp_dir: &Path,
+    dest: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
     let temp_path = create_temp_path(temp_dir);
     let mut temp_file = create_file(&temp_path, None)?;
     // The presence of a lock on the file indicates that this tempfile is in
     // use, in case a garbage collection process wants to know which files it
     // can delete. The lock is dropped after the rename.
     temp_file.try_lock_exclusive()?;
-    io::copy(&mut r, &mut temp_file)?;
+    write(&mut temp_file)?;
     temp_file.sync_data()?;
     fs::rename(temp_path, dest)
 }
 
+/// Creates the file at `dest` with `create`, which is called with a temporary
+/// path in the same directory, which is then renamed to `dest`. An existing
+/// file at `dest` is replaced, and is never seen half-written, even if the
+/// process is killed (the temporary file might remain then). The files are not
+/// synced, since extracting a snapshot writes many of them.
+pub fn replace_file<T>(dest: &Path, create: impl FnOnce(&Path) -> io::Result<T>) -> io::Result<T> {
+    let temp_path = create_hidden_temp_path(dest.parent().unwrap());
+    let result = create(&temp_path).and_then(|value| {
+        fs::rename(&temp_path, dest)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        remove_existing_file(&temp_path)?;
+    }
+    result
+}
+
+/// Returns a unique path in `dir` for a temporary file (see [`replace_file`])
+/// or directory. It is hidden, since it might remain in the working directory.
+pub fn create_hidden_temp_path(dir: &Path) -> PathBuf {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    dir.join(format!(".elfshaker-{}", hex::encode(bytes)))
+}
+
+/// Copies the file at `src` to `dest`, as if by [`fs::copy`], but copies the
+/// holes of large files (see [`read_sparse`] and [`write_sparse`]).
+pub fn copy_file_sparse(src: &Path, dest: &Path) -> io::Result<()> {
+    let mut src_file = open_file(src)?;
+    if src_file.metadata()?.len() < SPARSE_FILE_MIN_SIZE {
+        return fs::copy(src, dest).map(|_| ());
+    }
+    let mut buf = vec![];
+    read_sparse(&mut src_file, &mut buf)?;
+    write_sparse(&mut create_file(dest, None)?, &buf)
+}
+
+/// Files of at least this size are read and written sparsely (see
+/// [`read_sparse`] and [`write_sparse`]).
+pub const SPARSE_FILE_MIN_SIZE: u64 = 1024 * 1024;
+
+/// The (aligned) blocks of zeros of this size are written as holes.
+const SPARSE_BLOCK_SIZE: usize = 4096;
+
+/// Reads the contents of the file into `buf`. The holes of files of at least
+/// [`SPARSE_FILE_MIN_SIZE`] bytes (found with `SEEK_DATA` and `SEEK_HOLE`)
+/// are not read, but filled with zeros, which makes reading large sparse
+/// files (e.g. disk images) much faster.
+pub fn read_sparse(file: &mut File, buf: &mut Vec<u8>) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    if len < SPARSE_FILE_MIN_SIZE {
+        file.read_to_end(buf)?;
+        return Ok(());
+    }
+    let start = buf.len();
+    buf.resize(start + len as usize, 0);
+    let mut pos = 0;
+    while pos < len {
+        let (data, hole) = match find_data(file, pos)? {
+            Some(segment) => segment,
+            None => break,
+        };
+        let hole = std::cmp::min(hole, len);
+        io::Seek::seek(file, io::SeekFrom::Start(data))?;
+        file.read_exact(&mut buf[start + data as usize..start + hole as usize])?;
+        pos = hole;
+    }
+    // The file might have grown since its size was read.
+    io::Seek::seek(file, io::SeekFrom::Start(len))?;
+    file.read_to_end(buf)?;
+    Ok(())
+}
+
+/// Returns the (start, end) offsets of the first segment of data of the file
+/// at or after `pos`, or [`None`] if there is only a hole after `pos`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn find_data(file: &File, pos: u64) -> io::Result<Option<(u64, u64)>> {
+    use std::os::unix::io::AsRawFd;
+
+    let seek = |offset: u64, whence| {
+        // SAFETY: The file descriptor is open for the duration of the call.
+        match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+            -1 => Err(io::Error::last_os_error()),
+            offset => Ok(offset as u64),
+        }
+    };
+    let data = match seek(pos, libc::SEEK_DATA) {
+        Ok(data) => data,
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+        // SEEK_DATA is not supported by the filesystem.
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => return Ok(Some((pos, u64::MAX))),
+        Err(e) => return Err(e),
+    };
+    Ok(Some((data, seek(data, libc::SEEK_HOLE)?)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn find_data(_file: &File, pos: u64) -> io::Result<Option<(u64, u64)>> {
+    Ok(Some((pos, u64::MAX)))
+}
+
+/// Writes `buf` to the (empty) file. If `buf` is at least
+/// [`SPARSE_FILE_MIN_SIZE`] bytes long, its aligned blocks of zeros are left as
+/// holes (if the filesystem supports them), so sparse files don't use more
+/// disk space than the data they contain.
+pub fn write_sparse(file: &mut File, buf: &[u8]) -> io::Result<()> {
+    if (buf.len() as u64) < SPARSE_FILE_MIN_SIZE {
+        return file.write_all(buf);
+    }
+    let mut file_pos = 0;
+    let mut write_at = |data: &[u8], offset: usize| -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if offset != file_pos {
+            io::Seek::seek(file, io::SeekFrom::Start(offset as u64))?;
+        }
+        file.write_all(data)?;
+        file_pos = offset + data.len();
+        Ok(())
+    };
+    // The start of the data which is not written yet.
+    let mut data_start = 0;
+    for block_start in (0..buf.len()).step_by(SPARSE_BLOCK_SIZE) {
+        let block_end = std::cmp::min(block_start + SPARSE_BLOCK_SIZE, buf.len());
+        if buf[block_start..block_end].iter().all(|&b| b == 0) {
+            write_at(&buf[data_start..block_start], data_start)?;
+            data_start = block_end;
+        }
+    }
+    write_at(&buf[data_start..], data_start)?;
+    // Trailing holes are created by extending the file.
+    file.set_len(buf.len() as u64)
+}
+
 /// Returns a unique path suitable for a temporary file.
 pub fn create_temp_path(temp_dir: &Path) -> PathBuf {
     // Pick filename from a 128-bit random distribution.
@@ -62,6 +308,154 @@ pub fn create_temp_path(temp_dir: &Path) -> PathBuf {
     temp_dir.join(temp_filename)
 }
 
+/// Fails unless the path of an entry of a snapshot is relative, without `.`
+/// or `..` components. The indexes already reject such paths, but they are
+/// checked again before writing anything, so that no entry is ever written
+/// outside of the directory it is extracted to.
+fn check_entry_path(entry_path: &Path) -> io::Result<()> {
+    if is_valid_entry_path(entry_path.as_os_str()) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The path {} is not a relative path inside the snapshot!",
+                entry_path.display()
+            ),
+        ))
+    }
+}
+
+/// Appends the path of an entry of a snapshot to `path`. The components of
+/// the paths in the index are separated by `/` on every platform, which is not
+/// accepted in the extended-length (`\\?\`) paths used on Windows (e.g. the
+/// path of the repository, see [`extended_length_path`]), so they are appended
+/// one by one.
+pub fn push_entry_path(path: &mut PathBuf, entry_path: impl AsRef<Path>) -> io::Result<()> {
+    check_entry_path(entry_path.as_ref())?;
+    for component in entry_path.as_ref().components() {
+        path.push(component);
+    }
+    Ok(())
+}
+
+/// Returns the absolute path of `path`, as an extended-length (`\\?\`) path on
+/// Windows, whose length is not limited to `MAX_PATH` (260 characters), so
+/// that deep trees can be stored and extracted. The `.` and `..` components
+/// are resolved, since they are not in extended-length paths. The paths are
+/// returned unchanged on the other systems.
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> io::Result<PathBuf> {
+    use std::path::{Component, Prefix};
+
+    let path = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let mut components = path.components();
+    let mut result = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut result = OsString::from(r"\\?\");
+                result.push(prefix.as_os_str());
+                PathBuf::from(result)
+            }
+            Prefix::UNC(server, share) => {
+                let mut result = OsString::from(r"\\?\UNC\");
+                result.push(server);
+                result.push(r"\");
+                result.push(share);
+                PathBuf::from(result)
+            }
+            // Already an extended-length (or a device) path.
+            _ => return Ok(path),
+        },
+        _ => return Ok(path),
+    };
+    for component in components {
+        match component {
+            Component::RootDir => result.push(r"\"),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> io::Result<PathBuf> {
+    Ok(path.to_owned())
+}
+
+/// Returns `path` joined with the path of an entry (see [`push_entry_path`]).
+pub fn join_entry_path(path: &Path, entry_path: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let mut path = path.to_owned();
+    push_entry_path(&mut path, entry_path)?;
+    Ok(path)
+}
+
+/// Returns `path` joined with the path of an entry (see [`push_entry_path`]),
+/// creating the missing parent directories of the entry. The symbolic links
+/// found in place of the parent directories (e.g. the entries of the snapshot
+/// extracted previously) are replaced by directories, so that the entry is
+/// never written outside of `path` through them.
+pub fn create_entry_parents(path: &Path, entry_path: impl AsRef<Path>) -> io::Result<PathBuf> {
+    check_entry_path(entry_path.as_ref())?;
+    let mut path = path.to_owned();
+    let mut components = entry_path.as_ref().components().peekable();
+    while let Some(component) = components.next() {
+        path.push(component);
+        if components.peek().is_some() {
+            create_entry_dir(&path)?;
+        }
+    }
+    Ok(path)
+}
+
+/// Creates the directory at `path` (whose parent exists), unless there is
+/// one already. A symbolic link at `path` is replaced, rather than followed.
+/// Other files are not replaced.
+pub fn create_entry_dir(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => return Ok(()),
+        Ok(metadata) if metadata.file_type().is_symlink() => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        },
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    match fs::create_dir(path) {
+        // The entries are extracted in parallel, so another thread may have
+        // created it in the meantime.
+        Err(e)
+            if e.kind() == io::ErrorKind::AlreadyExists
+                && fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()) =>
+        {
+            Ok(())
+        }
+        r => r,
+    }
+}
+
+/// Converts a relative path to the path of an entry of a snapshot, in which
+/// the components are separated by `/`, so that the snapshots created on
+/// Windows can be extracted on other platforms (and vice versa).
+pub fn to_entry_path(path: &Path) -> OsString {
+    let mut entry_path = OsString::new();
+    for component in path.components() {
+        if !entry_path.is_empty() {
+            entry_path.push("/");
+        }
+        entry_path.push(component);
+    }
+    entry_path
+}
+
 /// Opens the file in read-only mode, as if by [`File::open`]. Any [`Error`]
 /// returned will contain the provided [`path`] in the error message.
 pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
@@ -94,6 +488,321 @@ pub fn create_file<P: AsRef<Path>>(path: P, perm: Option<Permissions>) -> io::Re
     }
 }
 
+/// Copies the file at `src` to `dest`, sharing the data of `src` if the
+/// filesystem supports it (a reflink, on e.g. Btrfs, XFS or APFS), which takes
+/// almost no time or space. Otherwise, the data is copied. Returns whether the
+/// data is shared.
+///
+/// An existing file at `dest` is replaced (rather than truncated, in case it
+/// is a hard link), see [`replace_file`].
+pub fn reflink_file(src: &Path, dest: &Path) -> io::Result<bool> {
+    replace_file(dest, |temp_path| {
+        if clone_file(src, temp_path)? {
+            return Ok(true);
+        }
+        fs::copy(src, temp_path)?;
+        Ok(false)
+    })
+}
+
+/// Creates `dest` as a hard link to `src`, replacing any existing file (see
+/// [`replace_file`]).
+pub fn hard_link_file(src: &Path, dest: &Path) -> io::Result<()> {
+    // Renaming a link over another link to the same file does nothing, which
+    // would leave the temporary link behind.
+    #[cfg(unix)]
+    if let (Ok(src_metadata), Ok(dest_metadata)) = (fs::metadata(src), fs::symlink_metadata(dest)) {
+        use std::os::unix::fs::MetadataExt;
+
+        if (src_metadata.dev(), src_metadata.ino()) == (dest_metadata.dev(), dest_metadata.ino()) {
+            return Ok(());
+        }
+    }
+    replace_file(dest, |temp_path| fs::hard_link(src, temp_path)).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("couldn't link {} to {}", dest.display(), src.display()),
+        )
+    })
+}
+
+/// Creates `path` as a symbolic link to `target`, replacing any existing file
+/// (see [`replace_file`]).
+pub fn create_symlink(target: &[u8], path: &Path) -> io::Result<()> {
+    let target = bytes_to_os_string(target);
+    replace_file(path, |temp_path| {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(&target, temp_path);
+        #[cfg(windows)]
+        return std::os::windows::fs::symlink_file(&target, temp_path);
+    })
+    .map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("couldn't create the symbolic link {}", path.display()),
+        )
+    })
+}
+
+/// Sets the modification time of the file at `path` (in nanoseconds since the
+/// Unix epoch), without following symbolic links. The access time is kept.
+#[cfg(unix)]
+pub fn set_modified_time(path: &Path, mtime: i64) -> io::Result<()> {
+    let c_path = path_to_cstring(path)?;
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: mtime.div_euclid(1_000_000_000) as libc::time_t,
+            tv_nsec: mtime.rem_euclid(1_000_000_000) as _,
+        },
+    ];
+    let flags = libc::AT_SYMLINK_NOFOLLOW;
+    // SAFETY: The path is a valid NUL-terminated string and there are two times.
+    if unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), flags) } != 0 {
+        let e = io::Error::last_os_error();
+        return Err(io::Error::new(
+            e.kind(),
+            format!("couldn't set the modification time of {}", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn set_modified_time(path: &Path, mtime: i64) -> io::Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::time::Duration;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    // Opens the symbolic links themselves, and the directories.
+    const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    let time = if mtime >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(mtime as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_nanos(mtime.unsigned_abs())
+    };
+    fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+        .and_then(|file| file.set_modified(time))
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("couldn't set the modification time of {}", path.display()),
+            )
+        })
+}
+
+/// Reads the extended attributes of the file at `path`, sorted by name,
+/// without following symbolic links. Returns no attributes if the filesystem
+/// does not support them.
+pub fn read_xattrs(path: &Path) -> io::Result<Vec<Xattr>> {
+    let c_path = path_to_cstring(path)?;
+    let names = match read_xattr_buf(|buf, size| xattr_list(&c_path, buf, size)) {
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOTSUP)) => return Ok(vec![]),
+        r => r?,
+    };
+    let mut xattrs = vec![];
+    for name in names.split(|&c| c == 0).filter(|name| !name.is_empty()) {
+        let c_name = CString::new(name)?;
+        let value = read_xattr_buf(|buf, size| xattr_get(&c_path, &c_name, buf, size))?;
+        xattrs.push(Xattr {
+            name: name.to_vec(),
+            value,
+        });
+    }
+    xattrs.sort();
+    Ok(xattrs)
+}
+
+/// Sets the extended attributes of the file at `path`, without following
+/// symbolic links. The attributes which can't be set because of the
+/// filesystem or of insufficient privileges (e.g. `security.*` attributes) are
+/// skipped with a warning.
+pub fn write_xattrs(path: &Path, xattrs: &[Xattr]) -> io::Result<()> {
+    let c_path = path_to_cstring(path)?;
+    for xattr in xattrs {
+        let c_name = CString::new(&xattr.name[..])?;
+        if xattr_set(&c_path, &c_name, &xattr.value) == 0 {
+            continue;
+        }
+        let e = io::Error::last_os_error();
+        let name = String::from_utf8_lossy(&xattr.name);
+        match e.raw_os_error() {
+            Some(libc::EPERM | libc::ENOTSUP) => {
+                warn!(
+                    "Skipped the attribute {} of {}: {}",
+                    name,
+                    path.display(),
+                    e
+                )
+            }
+            _ => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("couldn't set the attribute {} of {}", name, path.display()),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(
+        os_str_as_bytes(path.as_os_str()).into_owned(),
+    )?)
+}
+
+/// Calls `f` with a buffer of the size it returns when called with none,
+/// retrying if the size changed in the meantime.
+fn read_xattr_buf(f: impl Fn(*mut u8, usize) -> isize) -> io::Result<Vec<u8>> {
+    loop {
+        let size = f(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0; size as usize];
+        let len = f(buf.as_mut_ptr(), buf.len());
+        if len >= 0 {
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e);
+        }
+    }
+}
+
+// SAFETY (of the xattr_* functions): The paths and names are valid
+// NUL-terminated strings and the buffers are valid for `size` bytes.
+#[cfg(target_os = "linux")]
+fn xattr_list(path: &CStr, buf: *mut u8, size: usize) -> isize {
+    unsafe { libc::llistxattr(path.as_ptr(), buf.cast(), size) }
+}
+
+#[cfg(target_os = "linux")]
+fn xattr_get(path: &CStr, name: &CStr, buf: *mut u8, size: usize) -> isize {
+    unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf.cast(), size) }
+}
+
+#[cfg(target_os = "linux")]
+fn xattr_set(path: &CStr, name: &CStr, value: &[u8]) -> i32 {
+    unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn xattr_list(path: &CStr, buf: *mut u8, size: usize) -> isize {
+    unsafe { libc::listxattr(path.as_ptr(), buf.cast(), size, libc::XATTR_NOFOLLOW) }
+}
+
+#[cfg(target_os = "macos")]
+fn xattr_get(path: &CStr, name: &CStr, buf: *mut u8, size: usize) -> isize {
+    unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.cast(),
+            size,
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn xattr_set(path: &CStr, name: &CStr, value: &[u8]) -> i32 {
+    unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    }
+}
+
+// Extended attributes are not supported on other systems: there are none to
+// read and setting any fails.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn xattr_list(_path: &CStr, _buf: *mut u8, _size: usize) -> isize {
+    0
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn xattr_get(_path: &CStr, _name: &CStr, _buf: *mut u8, _size: usize) -> isize {
+    0
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn xattr_set(_path: &CStr, _name: &CStr, _value: &[u8]) -> i32 {
+    -1
+}
+
+fn remove_existing_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Creates `dest` as a clone of `src`. Returns false if the filesystem does
+/// not support it, in which case `dest` might have been created empty.
+#[cfg(target_os = "linux")]
+fn clone_file(src: &Path, dest: &Path) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let src_file = open_file(src)?;
+    let dest_file = create_file(dest, None)?;
+    // SAFETY: Both file descriptors are open for the duration of the call.
+    if unsafe { libc::ioctl(dest_file.as_raw_fd(), libc::FICLONE, src_file.as_raw_fd()) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        // Reflinks are not supported by the filesystem (or across filesystems).
+        Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::EINVAL | libc::EXDEV) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn clone_file(src: &Path, dest: &Path) -> io::Result<bool> {
+    let src_path = path_to_cstring(src)?;
+    let dest_path = path_to_cstring(dest)?;
+    // SAFETY: Both paths are valid NUL-terminated strings.
+    if unsafe { libc::clonefile(src_path.as_ptr(), dest_path.as_ptr(), 0) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ENOTSUP | libc::EXDEV) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_src: &Path, _dest: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
 #[cfg(all(unix, not(target_os = "macos")))]
 const OS_ERROR_DIR_NOT_EMPTY: i32 = 39 /* ENOTEMPTY */;
 #[cfg(windows)]
@@ -308,4 +1017,133 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_reflink_file_works() -> io::Result<()> {
+        let temp_dir = TempDir::new("test_reflink_file_works")?;
+        let src = temp_dir.0.join("src");
+        let dest = temp_dir.0.join("dest");
+        let other = temp_dir.0.join("other");
+        fs::write(&src, b"new contents")?;
+        fs::write(&other, b"old contents")?;
+        fs::hard_link(&other, &dest)?;
+
+        reflink_file(&src, &dest)?;
+
+        assert_eq!(b"new contents", &fs::read(&dest)?[..]);
+        assert_eq!(
+            b"old contents",
+            &fs::read(&other)?[..],
+            "The hard link should have been replaced, not written to!"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_file_works() -> io::Result<()> {
+        let temp_dir = TempDir::new("test_replace_file_works")?;
+        let dest = temp_dir.0.join("dest");
+        fs::write(&dest, b"old contents")?;
+
+        let result = replace_file(&dest, |temp_path| {
+            fs::write(temp_path, b"partial")?;
+            Err::<(), _>(io::Error::other("interrupted"))
+        });
+        assert!(result.is_err());
+        assert_eq!(b"old contents", &fs::read(&dest)?[..]);
+
+        replace_file(&dest, |temp_path| fs::write(temp_path, b"new contents"))?;
+        assert_eq!(b"new contents", &fs::read(&dest)?[..]);
+        assert_eq!(
+            1,
+            temp_dir.0.read_dir()?.count(),
+            "The temporary files should have been removed!"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_paths_work() {
+        let path = PathBuf::from_iter(["a", "b", "c.o"]);
+        assert_eq!(OsString::from("a/b/c.o"), to_entry_path(&path));
+        assert_eq!(OsString::from("c.o"), to_entry_path(Path::new("c.o")));
+
+        let root = PathBuf::from_iter(["/", "repo"]);
+        assert_eq!(root.join(&path), join_entry_path(&root, "a/b/c.o").unwrap());
+        assert_eq!(
+            root.join(&path),
+            join_entry_path(&root, to_entry_path(&path)).unwrap()
+        );
+        for invalid in ["", "../a", "a/../../b", "/etc/passwd", "./a"] {
+            assert!(join_entry_path(&root, invalid).is_err(), "{}", invalid);
+            assert!(create_entry_parents(&root, invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_create_symlink_works() -> io::Result<()> {
+        let temp_dir = TempDir::new("test_create_symlink_works")?;
+        let target = temp_dir.0.join("target");
+        let link = temp_dir.0.join("link");
+        fs::write(&target, b"contents")?;
+        fs::write(&link, b"old contents")?;
+
+        create_symlink(b"target", &link)?;
+        assert_eq!(Path::new("target"), fs::read_link(&link)?);
+        assert_eq!(b"contents", &fs::read(&link)?[..]);
+        // Replacing the link does not modify its target.
+        create_symlink(b"missing", &link)?;
+        assert_eq!(Path::new("missing"), fs::read_link(&link)?);
+        assert_eq!(b"contents", &fs::read(&target)?[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xattrs_work() -> io::Result<()> {
+        let temp_dir = TempDir::new("test_xattrs_work")?;
+        let path = temp_dir.0.join("file");
+        fs::write(&path, b"contents")?;
+        assert!(read_xattrs(&path)?.is_empty());
+
+        let xattr = |name: &[u8], value: &[u8]| Xattr {
+            name: name.to_vec(),
+            value: value.to_vec(),
+        };
+        let xattrs = vec![xattr(b"user.b", b"\xff\0"), xattr(b"user.a", b"")];
+        write_xattrs(&path, &xattrs)?;
+        let read = read_xattrs(&path)?;
+        if read.is_empty() {
+            // The filesystem does not support user attributes.
+            return Ok(());
+        }
+        assert_eq!(vec![xattrs[1].clone(), xattrs[0].clone()], read);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_sparse_files_work() -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = TempDir::new("test_sparse_files_work")?;
+        let path = temp_dir.0.join("sparse");
+        let mut buf = vec![0; 3 * SPARSE_FILE_MIN_SIZE as usize + 100];
+        buf[10] = 1;
+        buf[SPARSE_FILE_MIN_SIZE as usize + 5] = 2;
+        buf[2 * SPARSE_FILE_MIN_SIZE as usize..][..SPARSE_BLOCK_SIZE * 3].fill(3);
+
+        write_sparse(&mut create_file(&path, None)?, &buf)?;
+        assert_eq!(buf, fs::read(&path)?);
+        let metadata = fs::metadata(&path)?;
+        assert_eq!(buf.len() as u64, metadata.len());
+        assert!(
+            metadata.blocks() * 512 < SPARSE_FILE_MIN_SIZE,
+            "The blocks of zeros should have been written as holes!"
+        );
+
+        let mut read_buf = vec![];
+        read_sparse(&mut open_file(&path)?, &mut read_buf)?;
+        assert_eq!(buf, read_buf);
+        Ok(())
+    }
 }
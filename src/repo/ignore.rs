@@ -0,0 +1,214 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains [`IgnoreFile`], the parser and matcher of the `.elfshakerignore`
+//! file, which lists the files of the working directory which are not stored.
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::error::Error;
+use super::fs::to_entry_path;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A pattern of an [`IgnoreFile`].
+#[derive(Clone, Debug)]
+struct IgnorePattern {
+    pattern: Pattern,
+    /// The pattern started with `!`: the matching paths are not ignored.
+    negated: bool,
+    /// The pattern ended with `/`: it only matches directories.
+    dir_only: bool,
+    /// The pattern contained a `/` (other than a trailing one): it matches
+    /// the whole path, rather than the file name at any depth.
+    anchored: bool,
+}
+
+impl IgnorePattern {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.pattern.matches_with(path, MATCH_OPTIONS)
+        } else {
+            let file_name = path.rsplit('/').next().unwrap_or(path);
+            self.pattern.matches_with(file_name, MATCH_OPTIONS)
+        }
+    }
+}
+
+/// The patterns of an ignore file, in gitignore syntax: `#` starts a comment,
+/// `!` re-includes the paths excluded by a previous pattern, a trailing `/`
+/// only matches directories, and the patterns containing a `/` match the
+/// whole path (relative to the working directory), while the others match
+/// the file name at any depth. As with git, the files of an ignored
+/// directory are ignored too, and can't be re-included.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreFile {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFile {
+    /// Parses the contents of an ignore file.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let patterns = contents
+            .lines()
+            .filter_map(|line| parse_line(line).transpose())
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Reads the ignore file at `path`. A missing file ignores nothing.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Adds the patterns of `other`, which take precedence.
+    pub fn extend(&mut self, other: IgnoreFile) {
+        self.patterns.extend(other.patterns);
+    }
+
+    /// Whether the file (or directory) at `path`, relative to the working
+    /// directory, is ignored, either by the last pattern which matches it, or
+    /// because one of its parent directories is.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let path = to_entry_path(path);
+        let path = path.to_string_lossy();
+        let path = path.trim_start_matches("./");
+        let is_parent_ignored = path
+            .match_indices('/')
+            .any(|(i, _)| self.is_path_ignored(&path[..i], true));
+        is_parent_ignored || self.is_path_ignored(path, is_dir)
+    }
+
+    fn is_path_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Parses a line of an ignore file, returning [`None`] for blank lines and
+/// comments.
+fn parse_line(line: &str) -> Result<Option<IgnorePattern>, Error> {
+    // Trailing spaces are ignored, unless escaped.
+    let trimmed = line.trim_end_matches([' ', '\t', '\r']);
+    let line = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    };
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let line = line.strip_prefix('/').unwrap_or(line);
+    let pattern =
+        Pattern::new(&to_glob(line)).map_err(|e| Error::BadIgnorePattern(line.to_owned(), e))?;
+    Ok(Some(IgnorePattern {
+        pattern,
+        negated,
+        dir_only,
+        anchored,
+    }))
+}
+
+/// Converts the backslash escapes of gitignore patterns (e.g. `\#`, `\!` or
+/// `\*`) to the equivalent glob patterns.
+fn to_glob(pattern: &str) -> String {
+    let mut glob = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('*' | '?' | '[' | ']')) => {
+                    glob.push('[');
+                    glob.push(c);
+                    glob.push(']');
+                }
+                Some(c) => glob.push(c),
+                None => {}
+            },
+            c => glob.push(c),
+        }
+    }
+    glob
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_file_works() {
+        let ignore = IgnoreFile::parse(
+            "# Comment\n\
+             \n\
+             *.log\n\
+             !keep.log\n\
+             /build/\n\
+             tmp/\n\
+             docs/*.html\n\
+             cache/**\n\
+             \\#hash\n\
+             trailing \n\
+             escaped\\ \n",
+        )
+        .unwrap();
+        let is_ignored = |path: &str| ignore.is_ignored(Path::new(path), false);
+        let is_ignored_dir = |path: &str| ignore.is_ignored(Path::new(path), true);
+
+        assert!(is_ignored("a.log"));
+        assert!(is_ignored("./a/b/c.log"));
+        assert!(!is_ignored("keep.log"));
+        assert!(!is_ignored("a/keep.log"));
+        assert!(!is_ignored("a.logs"));
+
+        // Only the build directory at the top is ignored.
+        assert!(is_ignored_dir("build"));
+        assert!(is_ignored("build/a.o"));
+        assert!(!is_ignored("build"));
+        assert!(!is_ignored("src/build/a.o"));
+        // tmp directories are ignored at any depth.
+        assert!(is_ignored("a/tmp/b"));
+        assert!(!is_ignored("a/tmp"));
+
+        assert!(is_ignored("docs/index.html"));
+        assert!(!is_ignored("docs/api/index.html"));
+        assert!(!is_ignored("a/docs/index.html"));
+        assert!(is_ignored("cache/a/b"));
+        assert!(!is_ignored("cache"));
+
+        assert!(is_ignored("#hash"));
+        assert!(is_ignored("trailing"));
+        assert!(is_ignored("escaped "));
+        assert!(!is_ignored("README"));
+
+        assert!(!IgnoreFile::default().is_ignored(Path::new("a.log"), false));
+        assert!(IgnoreFile::parse("[").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the cache of the parsed pack indexes (see [`IndexCache`]), which
+//! lets the commands reading many packs (e.g. `list` and `find`) parse each
+//! index only once.
+use log::{info, warn};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use super::error::Error;
+use super::fs::{ensure_dir, open_file, write_file_atomic};
+use super::pack::PackId;
+use crate::packidx::{FlatIndex, IndexFormat, PackIndex};
+
+/// The maximum number of indexes kept in memory.
+const MAX_CACHED_INDEXES: usize = 16;
+
+/// Identifies the contents of a .pack.idx, which are assumed to be unchanged
+/// as long as its modification time and size are.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct IndexKey {
+    mtime: u128,
+    len: u64,
+}
+
+impl IndexKey {
+    /// The size of the key serialized with [`IndexKey::to_bytes`].
+    pub(crate) const SIZE: usize = 16 + 8;
+
+    pub(crate) fn of(path: &Path) -> io::Result<Self> {
+        let metadata = open_file(path)?.metadata()?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |mtime| mtime.as_nanos());
+        Ok(Self {
+            mtime,
+            len: metadata.len(),
+        })
+    }
+
+    /// Deserializes the key serialized with [`IndexKey::to_bytes`].
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SIZE {
+            return None;
+        }
+        Some(Self {
+            mtime: u128::from_be_bytes(bytes[..16].try_into().unwrap()),
+            len: u64::from_be_bytes(bytes[16..].try_into().unwrap()),
+        })
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..16].copy_from_slice(&self.mtime.to_be_bytes());
+        bytes[16..].copy_from_slice(&self.len.to_be_bytes());
+        bytes
+    }
+}
+
+struct CachedIndex {
+    key: IndexKey,
+    index: Arc<PackIndex>,
+    /// When the index was last used, to evict the least recently used one.
+    last_use: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    indexes: HashMap<PackId, CachedIndex>,
+    uses: u64,
+}
+
+/// Caches the parsed pack indexes in memory, by pack and [`IndexKey`], and
+/// optionally on disk, in the flat format (see [`FlatIndex`]), so that the
+/// later commands don't parse them again either.
+pub(crate) struct IndexCache {
+    entries: Mutex<Entries>,
+    /// The directory of the indexes cached on disk, if they are.
+    dir: Option<PathBuf>,
+}
+
+impl IndexCache {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            dir: None,
+        }
+    }
+
+    /// Caches the indexes on disk, in the directory, or only in memory.
+    pub(crate) fn set_dir(&mut self, dir: Option<PathBuf>) {
+        self.dir = dir;
+    }
+
+    pub(crate) fn is_on_disk(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// The index of the pack, if it is cached in memory with the same key.
+    pub(crate) fn get(&self, pack_id: &PackId, key: IndexKey) -> Option<Arc<PackIndex>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.uses += 1;
+        let uses = entries.uses;
+        let cached = entries.indexes.get_mut(pack_id).filter(|c| c.key == key)?;
+        cached.last_use = uses;
+        Some(cached.index.clone())
+    }
+
+    /// Keeps the index in memory, evicting the least recently used one if
+    /// there are too many.
+    pub(crate) fn insert(&self, pack_id: &PackId, key: IndexKey, index: Arc<PackIndex>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.indexes.len() >= MAX_CACHED_INDEXES && !entries.indexes.contains_key(pack_id) {
+            let evicted = entries
+                .indexes
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_use)
+                .map(|(pack_id, _)| pack_id.clone());
+            if let Some(evicted) = evicted {
+                entries.indexes.remove(&evicted);
+            }
+        }
+        entries.uses += 1;
+        let last_use = entries.uses;
+        entries.indexes.insert(
+            pack_id.clone(),
+            CachedIndex {
+                key,
+                index,
+                last_use,
+            },
+        );
+    }
+
+    /// The prefix of the names of the files caching the index of the pack.
+    fn file_prefix(pack_id: &PackId) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input_str(&pack_id.to_string());
+        format!("{}-", &hasher.result_str()[..32])
+    }
+
+    fn path(&self, pack_id: &PackId, key: IndexKey) -> Option<PathBuf> {
+        let file_name = format!(
+            "{}{}-{}.pack.idx",
+            Self::file_prefix(pack_id),
+            key.mtime,
+            key.len
+        );
+        self.dir.as_ref().map(|dir| dir.join(file_name))
+    }
+
+    /// Opens the copy of the index cached on disk, if there is one with the
+    /// same key.
+    pub(crate) fn load_flat(&self, pack_id: &PackId, key: IndexKey) -> Option<FlatIndex> {
+        let path = self.path(pack_id, key)?;
+        let file = match open_file(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!("Failed to open the cached index {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        match FlatIndex::from_file(file) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                // The cache is rewritten, rather than being an error.
+                warn!(
+                    "Ignoring the corrupt cached index {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Loads the copy of the index cached on disk, if there is one with the
+    /// same key.
+    pub(crate) fn load(&self, pack_id: &PackId, key: IndexKey) -> Option<PackIndex> {
+        let flat = self.load_flat(pack_id, key)?;
+        match flat.to_pack_index() {
+            Ok(mut index) => {
+                // The index is not flat itself.
+                index.set_format(IndexFormat::Msgpack);
+                Some(index)
+            }
+            Err(e) => {
+                warn!("Ignoring the corrupt cached index of {}: {}", pack_id, e);
+                None
+            }
+        }
+    }
+
+    /// Writes a flat copy of the index to the disk cache, if enabled,
+    /// replacing the older copies of the index of the pack.
+    pub(crate) fn save(
+        &self,
+        pack_id: &PackId,
+        key: IndexKey,
+        index: &PackIndex,
+        temp_dir: &Path,
+    ) -> Result<(), Error> {
+        let path = match self.path(pack_id, key) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.remove_files(pack_id)?;
+        info!("Cache index {} {}", pack_id, path.display());
+        let mut buf = vec![];
+        index.write_as(IndexFormat::Flat, &mut buf)?;
+        ensure_dir(path.parent().unwrap())?;
+        ensure_dir(temp_dir)?;
+        write_file_atomic(buf.as_slice(), temp_dir, &path)?;
+        Ok(())
+    }
+
+    /// Forgets the index of the pack, e.g. when the pack is deleted.
+    pub(crate) fn remove(&self, pack_id: &PackId) -> io::Result<()> {
+        self.entries.lock().unwrap().indexes.remove(pack_id);
+        self.remove_files(pack_id)
+    }
+
+    fn remove_files(&self, pack_id: &PackId) -> io::Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let prefix = Self::file_prefix(pack_id);
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+                continue;
+            }
+            match fs::remove_file(entry.path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packidx::{FileEntry, FileMetadata, ObjectMetadata, LOOSE_OBJECT_OFFSET};
+    use crate::repo::fs::create_temp_path;
+    use std::str::FromStr;
+
+    #[test]
+    fn index_cache_works() {
+        let mut index = PackIndex::new();
+        let md = ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size: 1,
+            delta: None,
+            chunks: None,
+            pack: None,
+        };
+        let entry = FileEntry::new("a".into(), [1; 32].into(), md, FileMetadata::default());
+        index.push_snapshot("s1".into(), vec![entry]).unwrap();
+        let index = Arc::new(index);
+        let key = IndexKey { mtime: 1, len: 2 };
+        let other_key = IndexKey { mtime: 1, len: 3 };
+
+        // In memory, the indexes are only found with the same key.
+        let mut cache = IndexCache::new();
+        let packs: Vec<_> = (0..=MAX_CACHED_INDEXES)
+            .map(|i| PackId::from_str(&format!("pack{i}")).unwrap())
+            .collect();
+        cache.insert(&packs[0], key, index.clone());
+        assert!(cache.get(&packs[0], key).is_some());
+        assert!(cache.get(&packs[0], other_key).is_none());
+        assert!(cache.get(&packs[1], key).is_none());
+        // The least recently used index is evicted.
+        for pack in &packs[1..MAX_CACHED_INDEXES] {
+            cache.insert(pack, key, index.clone());
+        }
+        cache.get(&packs[0], key).unwrap();
+        cache.insert(&packs[MAX_CACHED_INDEXES], key, index.clone());
+        assert!(cache.get(&packs[0], key).is_some());
+        assert!(cache.get(&packs[1], key).is_none());
+        cache.remove(&packs[0]).unwrap();
+        assert!(cache.get(&packs[0], key).is_none());
+
+        // On disk, the copy is flat and replaced when the index changes.
+        let temp_dir = std::env::temp_dir();
+        let dir = create_temp_path(&temp_dir);
+        assert!(cache.load(&packs[0], key).is_none());
+        cache.set_dir(Some(dir.clone()));
+        assert!(cache.load(&packs[0], key).is_none());
+        cache.save(&packs[0], key, &index, &temp_dir).unwrap();
+        let loaded = cache.load(&packs[0], key).unwrap();
+        assert_eq!(IndexFormat::Msgpack, loaded.format());
+        assert_eq!(index.snapshot_tags(), loaded.snapshot_tags());
+        assert_eq!(1, cache.load_flat(&packs[0], key).unwrap().snapshot_count());
+        assert!(cache.load(&packs[0], other_key).is_none());
+        cache.save(&packs[0], other_key, &index, &temp_dir).unwrap();
+        assert!(cache.load(&packs[0], key).is_none());
+        assert!(cache.load(&packs[0], other_key).is_some());
+        cache.save(&packs[1], key, &index, &temp_dir).unwrap();
+        cache.remove(&packs[0]).unwrap();
+        assert!(cache.load(&packs[0], other_key).is_none());
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,21 +3,57 @@
 
 //! Contains core types for interfacing with elfshaker repositories.
 mod algo;
+#[cfg(feature = "async")]
+mod async_repository;
+mod bloom;
+mod cache;
+mod case;
+mod chunk;
+mod config;
 mod constants;
+mod dictionary;
+mod encryption;
 mod error;
 #[doc(hidden)]
 pub mod fs;
+mod ignore;
+mod index_cache;
+mod modified;
+mod object_index;
+mod object_storage;
 mod pack;
-mod remote;
+#[doc(hidden)]
+pub mod remote;
 mod repository;
+mod signature;
+mod similarity;
+mod stream;
+#[doc(hidden)]
+pub mod watch;
+mod worktree;
 
 #[doc(hidden)]
-pub use algo::{partition_by_u64, run_in_parallel};
+pub use crate::frame::write_skippable_frame;
+pub use crate::frame::{PackFrame, PackHeader};
+#[doc(hidden)]
+pub use algo::{partition_by_u64, run_in_parallel, run_in_pool, split_by_u64};
+#[cfg(feature = "async")]
+pub use async_repository::{AsyncRepository, StreamedObject};
+pub use case::CaseCollisions;
+pub use config::Config;
 pub use constants::{
-    HEAD_FILE, INDEX_FILE, LOOSE_DIR, PACKS_DIR, PACK_EXTENSION, PACK_INDEX_EXTENSION, REPO_DIR,
+    CHECKSUM_ALGORITHM_FILE, CONFIG_FILE, DEFAULT_WINDOW_LOG_MAX, HEAD_FILE, HOOKS_DIR,
+    IGNORE_FILE, INDEX_FILE, LOOSE_DIR, PACKS_DIR, PACK_EXTENSION, PACK_INDEX_EXTENSION,
+    PACK_SIGNATURE_EXTENSION, REMOTES_DIR, REPO_DIR,
 };
+pub use encryption::{EncryptionKey, KeyId};
 pub use error::Error;
-#[doc(hidden)]
-pub use pack::write_skippable_frame;
-pub use pack::{Pack, PackFrame, PackHeader, PackId, SnapshotId};
-pub use repository::{ExtractOptions, PackOptions, Repository};
+pub use ignore::IgnoreFile;
+pub use modified::ModifiedFiles;
+pub use object_index::ObjectLocation;
+pub use pack::{Pack, PackId, SnapshotId};
+pub use repository::{
+    ExtractOptions, PackOptions, Repository, SnapshotDiff, StoreOptions, VerifyIssue,
+};
+pub use signature::{PackSignature, PublicKey, SigningKey, TrustPolicy};
+pub use stream::{ObjectReader, SnapshotStream};
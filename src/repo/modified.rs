@@ -0,0 +1,93 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the handling of the files of HEAD which were modified in the
+//! working directory since they were extracted (see [`ModifiedFiles`]), when
+//! extracting another snapshot over them.
+use std::ffi::OsString;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How the files of HEAD which were modified since they were extracted are
+/// handled when extracting another snapshot, which would overwrite or remove
+/// them. A file is modified if its contents (or type) are not the ones
+/// recorded in HEAD.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ModifiedFiles {
+    /// The extraction fails before modifying any file, as soon as a file is
+    /// more recent than HEAD (the default).
+    #[default]
+    Error,
+    /// The modified files are moved to `<path>.orig` (or `<path>.orig~N`),
+    /// then the files of the snapshot are extracted.
+    Backup,
+    /// The modified files are left as they are, rather than being updated or
+    /// removed.
+    Keep,
+}
+
+impl ModifiedFiles {
+    /// All the supported policies.
+    pub const ALL: [ModifiedFiles; 3] = [Self::Error, Self::Backup, Self::Keep];
+
+    /// The name of the policy, as accepted by [`ModifiedFiles::from_str`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Backup => "backup",
+            Self::Keep => "keep",
+        }
+    }
+}
+
+impl FromStr for ModifiedFiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|policy| s.eq_ignore_ascii_case(policy.name()))
+            .ok_or_else(|| format!("Unknown policy '{s}' (expected error, backup or keep)"))
+    }
+}
+
+/// The path the modified file at `path` is moved to by
+/// [`ModifiedFiles::Backup`]: `<path>.orig`, or `<path>.orig~N` if the
+/// previous backups still exist.
+pub(crate) fn backup_path(dir: &Path, path: &OsString) -> OsString {
+    let mut backup = path.clone();
+    backup.push(".orig");
+    let mut n = 0;
+    while dir.join(&backup).symlink_metadata().is_ok() {
+        n += 1;
+        backup = path.clone();
+        backup.push(format!(".orig~{n}"));
+    }
+    backup
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::create_temp_path;
+    use std::fs;
+
+    #[test]
+    fn modified_files_works() {
+        for policy in ModifiedFiles::ALL {
+            assert_eq!(Ok(policy), policy.name().parse());
+        }
+        assert!("overwrite".parse::<ModifiedFiles>().is_err());
+
+        let dir = create_temp_path(&std::env::temp_dir());
+        fs::create_dir_all(dir.join("a")).unwrap();
+        let path = OsString::from("a/b.o");
+        assert_eq!("a/b.o.orig", backup_path(&dir, &path));
+        fs::write(dir.join("a/b.o.orig"), b"").unwrap();
+        assert_eq!("a/b.o.orig~1", backup_path(&dir, &path));
+        fs::write(dir.join("a/b.o.orig~1"), b"").unwrap();
+        assert_eq!("a/b.o.orig~2", backup_path(&dir, &path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
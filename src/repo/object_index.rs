@@ -0,0 +1,279 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the index of the objects of all the packs of a repository (see
+//! [`ObjectIndex`]), which lets the lookups of an object only load the indexes
+//! of the packs which contain it.
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::fs::write_file_atomic;
+use super::index_cache::IndexKey;
+use super::pack::PackId;
+use crate::packidx::{ObjectChecksum, PackIndex};
+
+/// Bumped when the format of the index changes, which discards older indexes.
+const OBJECT_INDEX_VERSION: u32 = 1;
+
+/// A pack containing an object, with the offset of the object in the pack,
+/// or which may contain it, with no offset, if its objects are not recorded.
+pub type ObjectLocation = (PackId, Option<u64>);
+
+/// A pack, as recorded by the [`ObjectIndex`].
+struct IndexedPack {
+    pack_id: PackId,
+    /// The key of the .pack.idx when the objects of the pack were recorded.
+    key: IndexKey,
+    /// Whether the objects of the pack are recorded. They are not for the
+    /// packs whose index is encrypted, which may contain any object.
+    is_opaque: bool,
+}
+
+/// The serialized form of the [`ObjectIndex`]. The objects reference the
+/// packs by their position.
+#[derive(Serialize, Deserialize)]
+struct ObjectIndexFile {
+    version: u32,
+    packs: Vec<(String, Vec<u8>, bool)>,
+    objects: Vec<(ObjectChecksum, u32, u64)>,
+}
+
+/// The packs containing each object, with the offset of the object in the
+/// pack. A pack is only recorded while its .pack.idx is unchanged (has the same
+/// [`IndexKey`]), so the index is updated by recording the packs which
+/// changed since, and forgetting the ones which were deleted.
+#[derive(Default)]
+pub(crate) struct ObjectIndex {
+    packs: Vec<Option<IndexedPack>>,
+    objects: HashMap<ObjectChecksum, Vec<(u32, u64)>>,
+}
+
+impl ObjectIndex {
+    /// Loads the index from `path`. A missing, unreadable or corrupt index is
+    /// treated as empty.
+    pub(crate) fn load(path: &Path) -> Self {
+        let file = match fs::read(path) {
+            Ok(buf) => rmp_serde::from_slice::<ObjectIndexFile>(&buf),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                warn!("Ignoring the unreadable index {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+        let file = match file {
+            Ok(file) if file.version == OBJECT_INDEX_VERSION => file,
+            Ok(_) => return Self::default(),
+            Err(e) => {
+                warn!("Ignoring the corrupt index {}: {}", path.display(), e);
+                return Self::default();
+            }
+        };
+
+        let mut index = Self::default();
+        for (pack, key, is_opaque) in file.packs {
+            let pack_id = PackId::from_str(&pack).ok();
+            let key = IndexKey::from_bytes(&key);
+            index
+                .packs
+                .push(pack_id.zip(key).map(|(pack_id, key)| IndexedPack {
+                    pack_id,
+                    key,
+                    is_opaque,
+                }));
+        }
+        for (checksum, pack, offset) in file.objects {
+            if index.packs.get(pack as usize).is_some_and(Option::is_some) {
+                index
+                    .objects
+                    .entry(checksum)
+                    .or_default()
+                    .push((pack, offset));
+            }
+        }
+        index
+    }
+
+    /// Atomically writes the index to `path`, without the forgotten packs.
+    pub(crate) fn save(&self, path: &Path, temp_dir: &Path) -> io::Result<()> {
+        let mut positions = HashMap::new();
+        let mut packs = vec![];
+        for (i, pack) in self.packs.iter().enumerate() {
+            if let Some(pack) = pack {
+                positions.insert(i as u32, packs.len() as u32);
+                packs.push((
+                    pack.pack_id.to_string(),
+                    pack.key.to_bytes().to_vec(),
+                    pack.is_opaque,
+                ));
+            }
+        }
+        let mut objects = vec![];
+        for (checksum, locations) in &self.objects {
+            for (pack, offset) in locations {
+                if let Some(&pack) = positions.get(pack) {
+                    objects.push((*checksum, pack, *offset));
+                }
+            }
+        }
+        let file = ObjectIndexFile {
+            version: OBJECT_INDEX_VERSION,
+            packs,
+            objects,
+        };
+        let buf = rmp_serde::to_vec(&file).expect("Serialization failed!");
+        write_file_atomic(&*buf, temp_dir, path)
+    }
+
+    /// Whether the objects of the pack are recorded, for its current index.
+    pub(crate) fn is_up_to_date(&self, pack_id: &PackId, key: IndexKey) -> bool {
+        self.packs
+            .iter()
+            .flatten()
+            .any(|pack| pack.pack_id == *pack_id && pack.key == key)
+    }
+
+    /// Records the objects of the pack index, replacing the ones recorded for
+    /// the pack before, or records that the pack may contain any object.
+    pub(crate) fn insert(&mut self, pack_id: &PackId, key: IndexKey, index: Option<&PackIndex>) {
+        self.forget(pack_id);
+        let handle = self.packs.len() as u32;
+        self.packs.push(Some(IndexedPack {
+            pack_id: pack_id.clone(),
+            key,
+            is_opaque: index.is_none(),
+        }));
+        if let Some(index) = index {
+            for checksum in index.object_checksums() {
+                let offset = index.object_metadata(checksum).offset;
+                self.objects
+                    .entry(*checksum)
+                    .or_default()
+                    .push((handle, offset));
+            }
+        }
+    }
+
+    /// Forgets the pack (e.g. since it was deleted). Its objects are skipped
+    /// until the index is saved.
+    pub(crate) fn forget(&mut self, pack_id: &PackId) {
+        for pack in &mut self.packs {
+            if pack.as_ref().is_some_and(|pack| pack.pack_id == *pack_id) {
+                *pack = None;
+            }
+        }
+    }
+
+    /// Forgets the packs which are not in the list. Returns whether there
+    /// were any.
+    pub(crate) fn retain(&mut self, pack_ids: &[PackId]) -> bool {
+        let retained: HashSet<_> = pack_ids.iter().collect();
+        let mut is_changed = false;
+        for pack in &mut self.packs {
+            if pack
+                .as_ref()
+                .is_some_and(|p| !retained.contains(&p.pack_id))
+            {
+                *pack = None;
+                is_changed = true;
+            }
+        }
+        is_changed
+    }
+
+    /// The packs which contain the object, with its offset in each, followed
+    /// by the packs which may contain it (with no offset), since their objects
+    /// are not recorded.
+    pub(crate) fn get(&self, checksum: &ObjectChecksum) -> Vec<ObjectLocation> {
+        let mut result: Vec<_> = self
+            .objects
+            .get(checksum)
+            .into_iter()
+            .flatten()
+            .filter_map(|&(pack, offset)| {
+                let pack = self.packs[pack as usize].as_ref()?;
+                Some((pack.pack_id.clone(), Some(offset)))
+            })
+            .collect();
+        for pack in self.packs.iter().flatten().filter(|pack| pack.is_opaque) {
+            result.push((pack.pack_id.clone(), None));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packidx::{FileEntry, FileMetadata, ObjectMetadata, LOOSE_OBJECT_OFFSET};
+    use crate::repo::fs::create_temp_path;
+    use std::ffi::OsString;
+
+    #[test]
+    fn object_index_works() {
+        let dir = create_temp_path(&std::env::temp_dir());
+        fs::create_dir_all(&dir).unwrap();
+        let key_path = dir.join("index");
+        fs::write(&key_path, b"index").unwrap();
+        let key = IndexKey::of(&key_path).unwrap();
+
+        let checksum = |c: u8| ObjectChecksum::from([c; 20]);
+        let pack_index = |checksums: &[u8]| {
+            let mut index = PackIndex::new();
+            let entries = checksums.iter().map(|&c| {
+                let metadata = ObjectMetadata {
+                    offset: LOOSE_OBJECT_OFFSET,
+                    size: 1,
+                    delta: None,
+                    chunks: None,
+                    pack: None,
+                };
+                let path = OsString::from(format!("file-{c}"));
+                FileEntry::new(path, checksum(c), metadata, FileMetadata::default())
+            });
+            index.push_snapshot("s".into(), entries).unwrap();
+            index
+        };
+        let (a, b, c) = (
+            PackId::Pack("a".into()),
+            PackId::Pack("b".into()),
+            PackId::Pack("c".into()),
+        );
+
+        let mut index = ObjectIndex::default();
+        index.insert(&a, key, Some(&pack_index(&[1, 2])));
+        index.insert(&b, key, Some(&pack_index(&[2, 3])));
+        assert!(index.is_up_to_date(&a, key));
+        assert!(!index.is_up_to_date(&c, key));
+        let packs = |index: &ObjectIndex, c: u8| -> Vec<PackId> {
+            let mut packs: Vec<_> = index.get(&checksum(c)).into_iter().map(|p| p.0).collect();
+            packs.sort();
+            packs
+        };
+        assert_eq!(vec![a.clone(), b.clone()], packs(&index, 2));
+        assert_eq!(vec![b.clone()], packs(&index, 3));
+        assert!(packs(&index, 4).is_empty());
+
+        // The objects of a pack are replaced when it changes.
+        index.insert(&a, key, Some(&pack_index(&[4])));
+        assert_eq!(vec![b.clone()], packs(&index, 2));
+        assert_eq!(vec![a.clone()], packs(&index, 4));
+
+        // The opaque packs may contain any object.
+        index.insert(&c, key, None);
+        assert_eq!(vec![(c.clone(), None)], index.get(&checksum(5)));
+
+        let path = dir.join("objects");
+        assert!(index.retain(&[b.clone(), c.clone()]));
+        index.save(&path, &dir).unwrap();
+        let index = ObjectIndex::load(&path);
+        assert!(!index.is_up_to_date(&a, key));
+        assert_eq!(vec![b.clone(), c.clone()], packs(&index, 3));
+        assert_eq!(vec![c], packs(&index, 4));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the requests to the `az://<account>/<container>/<blob>` URLs of the
+//! blobs of Azure Blob Storage. The requests are authorized with the Shared
+//! Key of the account (`AZURE_STORAGE_KEY`), or with a SAS token
+//! (`AZURE_STORAGE_SAS_TOKEN`), or else anonymous (for public containers).
+use std::time::SystemTime;
+
+use ureq::{Agent, Request};
+use url::Url;
+
+use super::{env, hmac_sha256};
+use crate::repo::error::Error;
+use crate::repo::remote::format_http_date;
+
+/// The version of the REST API, which sets the semantics of the requests
+/// (e.g. that an empty Content-Length is signed as an empty string).
+const API_VERSION: &str = "2021-08-06";
+
+/// Starts a request to the blob at the `az://<account>/<container>/<blob>` URL.
+pub(crate) fn request(
+    agent: &Agent,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+) -> Result<Request, Error> {
+    let account = url
+        .host_str()
+        .filter(|account| !account.is_empty())
+        .ok_or_else(|| Error::HttpError(format!("{url} does not name an account").into()))?;
+    if !url.path().trim_start_matches('/').contains('/') {
+        return Err(Error::HttpError(
+            format!("{url} does not name a container and a blob").into(),
+        ));
+    }
+    // The emulator (Azurite) has the account in the path of the endpoint,
+    // e.g. http://127.0.0.1:10000/devstoreaccount1.
+    let endpoint = env("AZURE_STORAGE_ENDPOINT")
+        .unwrap_or_else(|| format!("https://{account}.blob.core.windows.net"));
+    let mut blob_url = Url::parse(&format!("{}{}", endpoint.trim_end_matches('/'), url.path()))
+        .map_err(|e| Error::HttpError(e.into()))?;
+
+    let date = format_http_date(SystemTime::now());
+    let mut all_headers = headers.to_vec();
+    all_headers.push(("x-ms-date", &date));
+    all_headers.push(("x-ms-version", API_VERSION));
+    if method == "PUT" {
+        all_headers.push(("x-ms-blob-type", "BlockBlob"));
+    }
+
+    let authorization = match (env("AZURE_STORAGE_KEY"), env("AZURE_STORAGE_SAS_TOKEN")) {
+        (Some(key), _) => {
+            let key = base64::decode(key).map_err(|e| {
+                Error::HttpError(format!("AZURE_STORAGE_KEY is not base64: {e}").into())
+            })?;
+            let resource = format!("/{}{}", account, blob_url.path());
+            let string_to_sign = string_to_sign(method, &all_headers, &resource);
+            let signature = base64::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+            Some(format!("SharedKey {account}:{signature}"))
+        }
+        (None, Some(sas_token)) => {
+            blob_url.set_query(Some(sas_token.trim_start_matches('?')));
+            None
+        }
+        (None, None) => None,
+    };
+
+    let mut request = agent.request_url(method, &blob_url);
+    for (name, value) in &all_headers {
+        request = request.set(name, value);
+    }
+    if let Some(authorization) = authorization {
+        request = request.set("Authorization", &authorization);
+    }
+    Ok(request)
+}
+
+/// The string signed with the Shared Key, see
+/// <https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key>.
+/// `resource` is `/<account>/<path>`, since the requests have no query string.
+fn string_to_sign(method: &str, headers: &[(&str, &str)], resource: &str) -> String {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .rev()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map_or("", |(_, value)| value.trim())
+    };
+    let content_length = match header("Content-Length") {
+        "0" => "",
+        content_length => content_length,
+    };
+    let mut string_to_sign = [
+        method,
+        header("Content-Encoding"),
+        header("Content-Language"),
+        content_length,
+        header("Content-MD5"),
+        header("Content-Type"),
+        header("Date"),
+        header("If-Modified-Since"),
+        header("If-Match"),
+        header("If-None-Match"),
+        header("If-Unmodified-Since"),
+        header("Range"),
+    ]
+    .join("\n");
+    string_to_sign.push('\n');
+
+    let mut ms_headers: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.trim()))
+        .filter(|(name, _)| name.starts_with("x-ms-"))
+        .collect();
+    ms_headers.sort();
+    for (name, value) in ms_headers {
+        string_to_sign.push_str(&name);
+        string_to_sign.push(':');
+        string_to_sign.push_str(value);
+        string_to_sign.push('\n');
+    }
+    string_to_sign.push_str(resource);
+    string_to_sign
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_to_sign_works() {
+        let headers = [
+            ("Range", "bytes=0-9"),
+            ("x-ms-version", API_VERSION),
+            ("Content-Length", "0"),
+            ("x-ms-date", "Thu, 01 Jan 1970 00:00:00 GMT"),
+        ];
+        let string_to_sign = string_to_sign("GET", &headers, "/account/container/index.esi");
+        assert_eq!(
+            "GET\n\n\n\n\n\n\n\n\n\n\nbytes=0-9\n\
+             x-ms-date:Thu, 01 Jan 1970 00:00:00 GMT\n\
+             x-ms-version:2021-08-06\n\
+             /account/container/index.esi",
+            string_to_sign
+        );
+        assert_eq!(
+            "SWAxWtGdLfylHSBK6RCNYjl9H0eOgpwsM6mfmoyft7U=",
+            base64::encode(hmac_sha256(b"key", string_to_sign.as_bytes()))
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the requests to the `gs://<bucket>/<object>` URLs of the objects of
+//! Google Cloud Storage buckets, through its XML API. The requests are
+//! authorized with the OAuth 2.0 access token of `GOOGLE_OAUTH_ACCESS_TOKEN`
+//! (or `CLOUDSDK_AUTH_ACCESS_TOKEN`), e.g. from `gcloud auth
+//! print-access-token`, or else anonymous (for public buckets).
+use ureq::{Agent, Request};
+use url::Url;
+
+use super::env;
+use crate::repo::error::Error;
+
+const DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// Starts a request to the object at the `gs://<bucket>/<object>` URL.
+pub(crate) fn request(
+    agent: &Agent,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+) -> Result<Request, Error> {
+    // The emulators (e.g. fake-gcs-server) are set by host, as with the
+    // client libraries.
+    let endpoint = env("STORAGE_EMULATOR_HOST").map(|host| {
+        if host.contains("://") {
+            host
+        } else {
+            format!("http://{host}")
+        }
+    });
+    let object_url = object_url(endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT), url)?;
+
+    let mut request = agent.request_url(method, &object_url);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    if let Some(token) =
+        env("GOOGLE_OAUTH_ACCESS_TOKEN").or_else(|| env("CLOUDSDK_AUTH_ACCESS_TOKEN"))
+    {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+    Ok(request)
+}
+
+/// The path-style URL of the object of the `gs://` URL below the endpoint.
+fn object_url(endpoint: &str, url: &Url) -> Result<Url, Error> {
+    let bucket = url
+        .host_str()
+        .filter(|bucket| !bucket.is_empty())
+        .ok_or_else(|| Error::HttpError(format!("{url} does not name a bucket").into()))?;
+    Url::parse(&format!(
+        "{}/{}{}",
+        endpoint.trim_end_matches('/'),
+        bucket,
+        url.path()
+    ))
+    .map_err(|e| Error::HttpError(e.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_url_works() {
+        let url = |endpoint, url: &str| {
+            object_url(endpoint, &url.parse().unwrap())
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            "https://storage.googleapis.com/bucket/repo/packs/a%20b.pack",
+            url(DEFAULT_ENDPOINT, "gs://bucket/repo/packs/a b.pack")
+        );
+        assert_eq!(
+            "http://localhost:4443/bucket/index.esi",
+            url("http://localhost:4443/", "gs://bucket/index.esi")
+        );
+        assert!(object_url(DEFAULT_ENDPOINT, &"gs:///index.esi".parse().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the requests to the objects of the object storage services, which
+//! host the repositories of the `s3://`, `az://` and `gs://` remotes. Each
+//! service is enabled by a feature (`s3`, `azure` and `gcs`), and translates
+//! the URLs of the objects to the ones of its HTTP API, with the credentials
+//! of the user.
+use std::io::Read;
+
+use ureq::{Agent, Request, Response};
+use url::Url;
+
+use super::error::Error;
+
+#[cfg(feature = "azure")]
+mod azure;
+#[cfg(feature = "gcs")]
+mod gcs;
+#[cfg(feature = "s3")]
+mod s3;
+
+/// The URL schemes of the object storage services, and the features which
+/// enable them.
+const SCHEMES: [(&str, &str); 3] = [("s3", "s3"), ("az", "azure"), ("gs", "gcs")];
+
+/// Whether the URL is the one of an object of an object storage service.
+pub(crate) fn is_object_storage_url(url: &Url) -> bool {
+    SCHEMES.iter().any(|(scheme, _)| url.scheme() == *scheme)
+}
+
+/// Starts a request to the object at the URL (see [`is_object_storage_url`]),
+/// with the headers.
+#[cfg_attr(
+    not(any(feature = "s3", feature = "azure", feature = "gcs")),
+    allow(unused_variables)
+)]
+pub(crate) fn request(
+    agent: &Agent,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+) -> Result<Request, Error> {
+    match url.scheme() {
+        #[cfg(feature = "s3")]
+        "s3" => s3::request(agent, method, url, headers),
+        #[cfg(feature = "azure")]
+        "az" => azure::request(agent, method, url, headers),
+        #[cfg(feature = "gcs")]
+        "gs" => gcs::request(agent, method, url, headers),
+        scheme => {
+            let feature = SCHEMES
+                .iter()
+                .find(|(s, _)| *s == scheme)
+                .map_or("?", |(_, feature)| *feature);
+            Err(Error::HttpError(
+                format!(
+                    "{scheme}:// URLs require elfshaker to be built with the {feature} feature"
+                )
+                .into(),
+            ))
+        }
+    }
+}
+
+/// The headers of a PUT which only replaces the object at the URL if it is
+/// still the version of the `response` to a GET of it, or only creates it if
+/// the GET found no object (`response` is [`None`]). The PUT then fails with
+/// 412 Precondition Failed if the object was written in between. Google Cloud
+/// Storage compares the generations of the objects, the other services (and
+/// the HTTP servers) their ETags.
+pub(crate) fn precondition_headers(
+    url: &Url,
+    response: Option<&Response>,
+) -> Vec<(&'static str, String)> {
+    if url.scheme() == "gs" {
+        // The generation 0 is the one of the missing objects.
+        let generation = response.map_or(Some("0"), |r| r.header("x-goog-generation"));
+        return generation
+            .map(|generation| ("x-goog-if-generation-match", generation.to_owned()))
+            .into_iter()
+            .collect();
+    }
+    match response {
+        Some(response) => response
+            .header("ETag")
+            .map(|etag| ("If-Match", etag.to_owned()))
+            .into_iter()
+            .collect(),
+        None => vec![("If-None-Match", "*".to_owned())],
+    }
+}
+
+/// Whether the file of `content_length` bytes is uploaded to the URL with a
+/// multipart upload (see [`upload_multipart`]) rather than a single PUT, which
+/// is the case of the files larger than a part of the S3 multipart uploads.
+#[cfg_attr(not(feature = "s3"), allow(unused_variables))]
+pub(crate) fn is_multipart_upload(url: &Url, content_length: u64) -> bool {
+    #[cfg(feature = "s3")]
+    if url.scheme() == "s3" {
+        return content_length > s3::MULTIPART_PART_SIZE;
+    }
+    false
+}
+
+/// Uploads the `content_length` bytes of the reader to the object at the URL
+/// in parts (see [`is_multipart_upload`]).
+#[cfg_attr(not(feature = "s3"), allow(unused_variables))]
+pub(crate) fn upload_multipart(
+    agent: &Agent,
+    url: &Url,
+    reader: impl Read,
+    content_length: u64,
+) -> Result<(), Error> {
+    match url.scheme() {
+        #[cfg(feature = "s3")]
+        "s3" => s3::upload_multipart(agent, url, reader, content_length),
+        scheme => Err(Error::HttpError(
+            format!("{scheme}:// URLs don't support multipart uploads").into(),
+        )),
+    }
+}
+
+/// The non-empty value of the environment variable.
+#[cfg(any(feature = "s3", feature = "azure", feature = "gcs"))]
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+#[cfg(any(feature = "s3", feature = "azure"))]
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::sha2::Sha256;
+
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precondition_headers_works() {
+        let response: Response = "HTTP/1.1 200 OK\r\n\
+                                  ETag: \"abc\"\r\n\
+                                  x-goog-generation: 42\r\n\r\n"
+            .parse()
+            .unwrap();
+        let headers = |url: &str, response| precondition_headers(&url.parse().unwrap(), response);
+        assert_eq!(
+            vec![("If-Match", "\"abc\"".to_owned())],
+            headers("s3://bucket/index.esi", Some(&response))
+        );
+        assert_eq!(
+            vec![("If-None-Match", "*".to_owned())],
+            headers("az://account/container/index.esi", None)
+        );
+        assert_eq!(
+            vec![("x-goog-if-generation-match", "42".to_owned())],
+            headers("gs://bucket/index.esi", Some(&response))
+        );
+        assert_eq!(
+            vec![("x-goog-if-generation-match", "0".to_owned())],
+            headers("gs://bucket/index.esi", None)
+        );
+    }
+}
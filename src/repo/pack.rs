@@ -3,33 +3,47 @@
 
 use serde::{Deserialize, Serialize};
 use std::{
-    fmt,
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt, fs,
     fs::File,
-    fs::{self, Permissions},
     io,
-    io::{BufReader, Read, Write},
-    os::unix::prelude::PermissionsExt,
+    io::{BufReader, Read},
+    panic,
     path::{Path, PathBuf},
+    sync::mpsc,
 };
 use std::{fmt::Display, str::FromStr};
 
-use crypto::digest::Digest;
-use crypto::sha1::Sha1;
-
 use log::info;
 
-use zstd::stream::raw::DParameter;
-use zstd::Decoder;
+use zstd::zstd_safe;
 
-use super::constants::{
-    DEFAULT_WINDOW_LOG_MAX, PACKS_DIR, PACK_EXTENSION, PACK_HEADER_MAGIC, PACK_INDEX_EXTENSION,
-};
+use super::algo::{run_in_parallel, run_in_pool};
+use super::constants::DOT_PACK_INDEX_EXTENSION;
+use super::constants::{PACKS_DIR, PACK_EXTENSION, PACK_INDEX_EXTENSION};
+use super::encryption::{is_encrypted_index, Cipher, EncryptionKey};
 use super::error::Error;
-use super::fs::{create_file, open_file};
-use super::{algo::run_in_parallel, constants::DOT_PACK_INDEX_EXTENSION};
-use crate::packidx::{FileEntry, ObjectChecksum, PackError};
+use super::fs::{
+    create_entry_dir, create_entry_parents, create_file, create_symlink, open_file, replace_file,
+    set_file_mode, write_sparse,
+};
+use crate::checksum::ChecksumAlgorithm;
+use crate::codec::{Codec, Decoder};
+use crate::delta::decode_delta;
+use crate::frame::{
+    compute_frame_decompressed_offset, compute_frame_offsets, read_skippable_frame, PackEncryption,
+    PackFrame, PackHeader,
+};
+use crate::packidx::{
+    FileEntry, FrameBoundary, ObjectChecksum, ObjectChunk, ObjectDelta, PackError, PackIndex,
+};
 use crate::{log::measure_ok, packidx::ObjectMetadata};
 
+/// Objects stored as chunks are read in batches of at most this size (unless a
+/// single object is larger), which bounds the memory needed to read them.
+const CHUNKED_BATCH_SIZE: u64 = 256 * 1024 * 1024;
+
 /// Pack and snapshots IDs can contain latin letter, digits or the following characters.
 const EXTRA_ID_CHARS: &[char] = &['-', '_', '/'];
 
@@ -39,6 +53,7 @@ pub enum IdError {
     BadFormat(String),
     InvalidPack(String),
     InvalidSnapshot(String),
+    InvalidRef(String),
 }
 
 impl Display for IdError {
@@ -47,12 +62,16 @@ impl Display for IdError {
             Self::BadFormat(s) => write!(f, "Unrecognized identifier format '{s}'!"),
             Self::InvalidPack(s) => write!(
                 f,
-                "Invalid pack identifier '{s}'! Latin letters, digits, -, _ and / are allowed!"
+                "Invalid pack identifier '{s}'! Latin letters, digits, -, _, . and / are allowed!"
             ),
             Self::InvalidSnapshot(s) => write!(
                 f,
                 "Invalid snapshot identifier '{s}'! Latin letters, digits, - and _ are allowed!"
             ),
+            Self::InvalidRef(s) => write!(
+                f,
+                "Invalid ref name '{s}'! Latin letters, digits, -, _, . and / are allowed!"
+            ),
         }
     }
 }
@@ -74,12 +93,51 @@ impl PackId {
             .map(|s| PackId::Pack(s.to_owned()))
     }
 
+    /// Resolves the name of a pack storing some of the objects of this pack
+    /// (see [`ObjectMetadata::pack`]). The names are relative to the directory
+    /// of this pack, so that the packs of a remote read their objects from the
+    /// packs of the same remote.
+    pub fn resolve_external(&self, name: &str) -> Result<PackId, IdError> {
+        let PackId::Pack(pack) = self;
+        match pack.rsplit_once('/') {
+            Some((dir, _)) => PackId::from_str(&format!("{dir}/{name}")),
+            None => PackId::from_str(name),
+        }
+    }
+
+    /// The name by which this pack refers to the other pack, when it reads
+    /// some of its objects from it (see [`PackId::resolve_external`]), or
+    /// `None` if the other pack is not in the directory of this pack (nor in
+    /// one of its subdirectories).
+    pub fn external_name(&self, other: &PackId) -> Option<String> {
+        let (PackId::Pack(pack), PackId::Pack(other)) = (self, other);
+        match pack.rsplit_once('/') {
+            Some((dir, _)) => other
+                .strip_prefix(dir)
+                .and_then(|name| name.strip_prefix('/'))
+                .map(str::to_owned),
+            None => Some(other.clone()),
+        }
+    }
+
+    /// Pack IDs can also contain `.` (e.g. the parts of a pack split by
+    /// `elfshaker pack --max-size`), but no component of the path can start
+    /// with it, so that the pack files stay in the packs directory.
     fn is_valid(s: &str) -> bool {
         s.chars()
-            .all(|c| c.is_ascii_alphanumeric() || EXTRA_ID_CHARS.contains(&c))
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || EXTRA_ID_CHARS.contains(&c))
+            && s.split('/').all(|component| !component.starts_with('.'))
     }
 }
 
+/// The names of the refs (see
+/// [`Repository::set_ref`](super::Repository::set_ref)) follow the rules of the
+/// pack IDs, without empty components, since they are the paths of the refs in
+/// the refs directory.
+pub(crate) fn is_valid_ref_name(name: &str) -> bool {
+    PackId::is_valid(name) && name.split('/').all(|component| !component.is_empty())
+}
+
 impl FromStr for PackId {
     type Err = IdError;
 
@@ -165,53 +223,176 @@ impl FromStr for SnapshotId {
     }
 }
 
-/// A magic constant that has no use other than to indicate a custom .pack header
-/// stored in a Zstandard skippable frame.
-const SKIPPABLE_MAGIC_MASK: u32 = 0x184D2A50;
+/// The nonce the dictionary of encrypted packs is encrypted with (the frames
+/// are encrypted with their index).
+const DICTIONARY_NONCE: u64 = u64::MAX;
+
+/// The .pack file, from which the frames are read (see [`Pack::open_frame`]).
+#[derive(Clone)]
+struct PackFile {
+    /// The path of the .pack file.
+    path: PathBuf,
+    /// With the `mmap` feature, the .pack file is mapped into memory once,
+    /// when the pack is opened, and the frames are decompressed straight from
+    /// the mapping. This avoids a read syscall and a copy per buffer of
+    /// compressed data, and the mapping is shared by all the threads reading
+    /// the pack.
+    #[cfg(feature = "mmap")]
+    map: Option<std::sync::Arc<memmap2::Mmap>>,
+    /// How the frames are encrypted, if they are (see [`PackHeader::encryption`]).
+    encryption: Option<FrameEncryption>,
+}
+
+#[derive(Clone)]
+struct FrameEncryption {
+    key_id: String,
+    /// The cipher of the pack, if the key is configured.
+    cipher: Option<Cipher>,
+}
 
-/// Reads a Zstandard skippable frame from reader and writes the result to `buf`.
-/// Returns the size of the frame, *not* the number of bytes read.
-pub fn read_skippable_frame(mut reader: impl Read, buf: &mut Vec<u8>) -> io::Result<u64> {
-    fn read_u32_le(mut reader: impl Read) -> io::Result<u32> {
-        let mut bytes = [0u8; 4];
-        reader.read_exact(&mut bytes)?;
-        Ok(u32::from_le_bytes(bytes))
+/// The location of a frame in the .pack file.
+#[derive(Clone, Copy, Debug)]
+struct FrameLocation {
+    /// The index of the frame in [`PackHeader::frames`].
+    index: usize,
+    offset: u64,
+    size: u64,
+}
+
+impl PackFile {
+    #[cfg(not(feature = "mmap"))]
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            encryption: None,
+        }
     }
 
-    // Ensure this is a skippable frame.
-    let magic = read_u32_le(&mut reader)?;
-    if magic & SKIPPABLE_MAGIC_MASK != SKIPPABLE_MAGIC_MASK {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Not a Zstandard skippable frame!",
-        ));
+    #[cfg(feature = "mmap")]
+    fn new(path: PathBuf) -> Self {
+        // SAFETY: Packs are never modified once created (they are written to a
+        // temporary file, then renamed), so the mapping stays valid.
+        let map = open_file(&path).and_then(|file| unsafe { memmap2::Mmap::map(&file) });
+        match map {
+            Ok(map) => Self {
+                path,
+                map: Some(std::sync::Arc::new(map)),
+                encryption: None,
+            },
+            Err(e) => {
+                info!("Failed to map {:?}, reading it instead: {}", path, e);
+                Self {
+                    path,
+                    map: None,
+                    encryption: None,
+                }
+            }
+        }
     }
 
-    let frame_size = read_u32_le(&mut reader)?;
-    buf.resize(frame_size as usize, 0);
-    reader.read_exact(buf)?;
-    // Compute overall frame size.
-    Ok((std::mem::size_of::<u32>() * 2 + buf.len()) as u64)
+    /// Opens the frame, decrypting it first if the pack is encrypted.
+    fn open_frame(&self, frame: FrameLocation) -> Result<FrameSource, Error> {
+        let encryption = match &self.encryption {
+            Some(encryption) => encryption,
+            None => return self.open_at(frame.offset),
+        };
+        let cipher = encryption.cipher.as_ref().ok_or_else(|| {
+            Error::Encryption(format!(
+                "The pack is encrypted with the key {}, which is not configured \
+                 (see encryption.key)",
+                encryption.key_id
+            ))
+        })?;
+        // The whole frame is needed to authenticate it, so it is decrypted
+        // into memory.
+        let mut sealed = vec![];
+        self.open_at(frame.offset)?
+            .take(frame.size)
+            .read_to_end(&mut sealed)?;
+        if sealed.len() as u64 != frame.size {
+            return Err(Error::CorruptPack);
+        }
+        let data = cipher.open(frame.index as u64, &sealed).ok_or_else(|| {
+            Error::Encryption(format!(
+                "Failed to decrypt frame {} with the key {} (the pack is corrupt)",
+                frame.index, encryption.key_id
+            ))
+        })?;
+        Ok(FrameSource::Decrypted(io::Cursor::new(data)))
+    }
+
+    /// Opens the .pack file at the specified byte offset.
+    fn open_at(&self, offset: u64) -> Result<FrameSource, Error> {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            if offset > map.len() as u64 {
+                return Err(Error::CorruptPack);
+            }
+            return Ok(FrameSource::Mapped {
+                map: map.clone(),
+                pos: offset as usize,
+            });
+        }
+        let mut reader = open_file(&self.path)?;
+        io::Seek::seek(&mut reader, io::SeekFrom::Start(offset))?;
+        Ok(FrameSource::File(BufReader::with_capacity(
+            zstd_safe::DCtx::in_size(),
+            reader,
+        )))
+    }
+}
+
+/// The compressed data of the pack, from the start of a frame.
+enum FrameSource {
+    File(BufReader<File>),
+    /// The frame of an encrypted pack, once decrypted.
+    Decrypted(io::Cursor<Vec<u8>>),
+    #[cfg(feature = "mmap")]
+    Mapped {
+        map: std::sync::Arc<memmap2::Mmap>,
+        /// The current position in the .pack file.
+        pos: usize,
+    },
 }
 
-/// Writes a Zstandard skippable frame with the given user data.
-/// Returns the number of bytes written to writer (the size of the frame, including the magic number and header).
-pub fn write_skippable_frame(mut writer: impl Write, buf: &[u8]) -> io::Result<u64> {
-    fn write_u32_le(mut writer: impl Write, value: u32) -> io::Result<()> {
-        writer.write_all(&value.to_le_bytes())
+impl Read for FrameSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(reader) => reader.read(buf),
+            Self::Decrypted(reader) => reader.read(buf),
+            #[cfg(feature = "mmap")]
+            Self::Mapped { .. } => {
+                let n = io::BufRead::fill_buf(self)?.read(buf)?;
+                io::BufRead::consume(self, n);
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl io::BufRead for FrameSource {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Self::File(reader) => reader.fill_buf(),
+            Self::Decrypted(reader) => reader.fill_buf(),
+            #[cfg(feature = "mmap")]
+            Self::Mapped { map, pos } => Ok(&map[*pos..]),
+        }
     }
 
-    // Ensure this is a skippable frame.
-    write_u32_le(&mut writer, SKIPPABLE_MAGIC_MASK)?;
-    write_u32_le(&mut writer, buf.len() as u32)?;
-    writer.write_all(buf)?;
-    // Compute overall frame size.
-    Ok((std::mem::size_of::<u32>() * 2 + buf.len()) as u64)
+    fn consume(&mut self, amt: usize) {
+        match self {
+            Self::File(reader) => reader.consume(amt),
+            Self::Decrypted(reader) => reader.consume(amt),
+            #[cfg(feature = "mmap")]
+            Self::Mapped { map, pos } => *pos = std::cmp::min(*pos + amt, map.len()),
+        }
+    }
 }
 
 /// The unidirectional stream of data stored in the pack.
 enum PackReader {
-    Compressed(Decoder<'static, BufReader<File>>),
+    Compressed(Decoder<FrameSource>),
 }
 
 impl PackReader {
@@ -231,44 +412,144 @@ impl PackReader {
     }
 }
 
-/// Represents a compressed Zstandard frame in the .pack file.
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PackFrame {
-    /// The size of the frame, in bytes.
-    pub frame_size: u64,
-    /// The size of the data stream in the frame, once decompressed, in bytes.
-    pub decompressed_size: u64,
+/// Reads the objects of a frame in the order in which they are stored,
+/// decoding the objects stored as deltas (see [`ObjectDelta`]).
+struct FrameReader {
+    reader: PackReader,
+    /// The current position in the decompressed frame.
+    pos: u64,
+    /// The delta bases of the objects to read, by offset.
+    bases: BTreeMap<u64, DeltaBase>,
+    /// The time spent seeking, in seconds.
+    seek_time: f64,
 }
 
-/// Represents the custom header in the beginning of a .pack file.
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PackHeader {
-    /// Valid pack headers have this value set to [`PACK_HEADER_MAGIC`].
-    magic: u64,
-    /// The list of frames in the pack file, sorted by their byte offsets.
-    frames: Vec<PackFrame>,
+struct DeltaBase {
+    size: u64,
+    /// The number of objects to read which are deltas against the base.
+    refs: usize,
+    /// The contents of the base, once read.
+    contents: Option<Vec<u8>>,
 }
 
-impl PackHeader {
-    /// Create a new pack header.
-    pub fn new(frames: Vec<PackFrame>) -> Self {
+impl FrameReader {
+    /// Creates a reader for the specified entries, which must be sorted by
+    /// [`ObjectMetadata`] (the order in which they are read).
+    fn new(reader: PackReader, entries: &[FileEntry]) -> Self {
+        let mut bases = BTreeMap::new();
+        let mut last = None;
+        for metadata in entries.iter().map(|entry| &entry.object_metadata) {
+            // Repeated objects are only read once.
+            if last == Some(metadata) {
+                continue;
+            }
+            last = Some(metadata);
+            if let Some(delta) = metadata.delta {
+                bases
+                    .entry(delta.base_offset)
+                    .or_insert(DeltaBase {
+                        size: delta.base_size,
+                        refs: 0,
+                        contents: None,
+                    })
+                    .refs += 1;
+            }
+        }
         Self {
-            magic: PACK_HEADER_MAGIC,
-            frames,
+            reader,
+            pos: 0,
+            bases,
+            seek_time: 0.0,
+        }
+    }
+
+    /// Reads the object into `buf`. Objects must be read in order, at
+    /// most once.
+    fn read_object(&mut self, metadata: &ObjectMetadata, buf: &mut Vec<u8>) -> io::Result<()> {
+        let Self {
+            reader,
+            pos,
+            bases,
+            seek_time,
+        } = self;
+        // Read the bases stored before the object, which are needed by the
+        // objects which follow.
+        for (&offset, base) in bases.range_mut(..metadata.offset) {
+            if base.contents.is_none() {
+                let mut contents = vec![];
+                Self::read_at(reader, pos, seek_time, offset, base.size, &mut contents)?;
+                base.contents = Some(contents);
+            }
+        }
+
+        match metadata.delta {
+            Some(delta) => {
+                let mut delta_buf = vec![];
+                Self::read_at(
+                    reader,
+                    pos,
+                    seek_time,
+                    metadata.offset,
+                    delta.delta_size,
+                    &mut delta_buf,
+                )?;
+                let base = bases.get_mut(&delta.base_offset).unwrap();
+                *buf = decode_delta(base.contents.as_ref().unwrap(), &delta_buf, metadata.size)?;
+                base.refs -= 1;
+                if base.refs == 0 {
+                    bases.remove(&delta.base_offset);
+                }
+            }
+            None => {
+                Self::read_at(reader, pos, seek_time, metadata.offset, metadata.size, buf)?;
+                // Keep the object if it is the base of the objects which follow.
+                if let Some(base) = bases.get_mut(&metadata.offset) {
+                    if base.size == metadata.size {
+                        base.contents = Some(buf.clone());
+                    }
+                }
+            }
         }
+        Ok(())
     }
-    /// Verifies the header magic.
-    pub fn is_valid(&self) -> bool {
-        self.magic == PACK_HEADER_MAGIC
+
+    fn read_at(
+        reader: &mut PackReader,
+        pos: &mut u64,
+        seek_time: &mut f64,
+        offset: u64,
+        size: u64,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        *seek_time += measure_ok(|| reader.seek(offset - *pos))?.0.as_secs_f64();
+        buf.resize(size as usize, 0);
+        reader.read_exact(&mut buf[..])?;
+        *pos = offset + size;
+        Ok(())
     }
 }
 
-impl Default for PackHeader {
-    fn default() -> Self {
-        Self {
-            magic: PACK_HEADER_MAGIC,
-            frames: vec![],
+/// The encryption of the packs, which needs the keys of the repository.
+impl PackHeader {
+    /// Encrypt the frames with the key (see [`Cipher::seal`]), with the index
+    /// of each frame as its nonce. The frame sizes are updated to the ones of
+    /// the encrypted frames. The dictionary is encrypted too, since it is
+    /// trained on the contents of the objects.
+    pub(crate) fn encrypt_frames<'a>(
+        &mut self,
+        key: &EncryptionKey,
+        frames: impl IntoIterator<Item = &'a mut Vec<u8>>,
+    ) {
+        let encryption = PackEncryption::new(key);
+        let cipher = encryption.cipher(std::slice::from_ref(key)).unwrap();
+        for (i, (frame, buf)) in self.frames.iter_mut().zip(frames).enumerate() {
+            *buf = cipher.seal(i as u64, buf);
+            frame.frame_size = buf.len() as u64;
         }
+        if let Some(dictionary) = &mut self.dictionary {
+            *dictionary = cipher.seal(DICTIONARY_NONCE, dictionary);
+        }
+        self.encryption = Some(encryption);
     }
 }
 
@@ -276,81 +557,146 @@ impl Default for PackHeader {
 pub struct Pack {
     /// The base filename ([`Path::file_stem`]) of the pack.
     name: String,
+    /// The .pack file.
+    file: PackFile,
     /// The header of the pack.
     header: PackHeader,
-    /// PackReader instances for each frame in the .pack file.
-    frame_readers: Vec<PackReader>,
+    /// The locations of the frames in the .pack file. The frames are only
+    /// opened (see [`Pack::open_frame`]) when objects are read from them, so
+    /// packs with many frames can be opened cheaply.
+    frames: Vec<FrameLocation>,
     /// The file size of the pack (in bytes).
     file_size: u64,
+    /// The algorithm used to compute the object checksums (from the index).
+    checksum_algorithm: ChecksumAlgorithm,
+    /// The index of the pack, for the packs opened without a repository (see
+    /// [`Pack::open`]). The repositories load the indexes themselves.
+    index: Option<PackIndex>,
 }
 
 impl Pack {
-    /// Open a pack file.
+    /// Opens a pack without a repository (e.g. a pack shipped on its own),
+    /// from the .pack file at `path` (or the .pack.idx, for the same pack) and
+    /// the .pack.idx next to it. The index is kept along with the pack, so
+    /// that its snapshots can be listed ([`Pack::snapshots`]), read
+    /// ([`Pack::read_file`]) and extracted ([`Pack::extract_snapshot`]).
+    ///
+    /// The objects stored in other packs (see
+    /// [`PackOptions::dedup`](super::PackOptions::dedup)) can't be read, and
+    /// neither can the encrypted packs, which need the keys of a repository.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::PackNotFound(path.display().to_string()))?;
+        let name = file_name
+            .strip_suffix(DOT_PACK_INDEX_EXTENSION)
+            .or_else(|| file_name.strip_suffix(&format!(".{PACK_EXTENSION}")))
+            .unwrap_or(file_name);
+        let pack_path = path.with_file_name(format!("{name}.{PACK_EXTENSION}"));
+        let pack_index_path = path.with_file_name(format!("{name}.{PACK_INDEX_EXTENSION}"));
+
+        let index = fs::read(pack_index_path)?;
+        if is_encrypted_index(&index) {
+            return Err(Error::Encryption(format!(
+                "The index of the pack {name} is encrypted, so the pack can only be read \
+                 from a repository with its key (see encryption.key)"
+            )));
+        }
+        let index = PackIndex::parse(&*index)?;
+        let mut pack = Self::open_path(name, pack_path, index.checksum_algorithm())?;
+        if pack.header.is_encrypted() {
+            return Err(Error::Encryption(format!(
+                "The pack {name} is encrypted, so it can only be read from a repository \
+                 with its key (see encryption.key)"
+            )));
+        }
+        pack.index = Some(index);
+        Ok(pack)
+    }
+
+    /// Same as [`Pack::open`], for the pack of the repository whose data
+    /// directory is `data_dir`.
     ///
     /// # Arguments
     ///
     /// * `pack_name` - The base filename ([`Path::file_stem`]) of the pack.
-    pub fn open<P>(data_dir: P, pack_name: &PackId) -> Result<Self, Error>
+    pub fn open_in<P>(data_dir: P, pack_name: &PackId) -> Result<Self, Error>
     where
         P: AsRef<Path>,
     {
         let PackId::Pack(pack_name) = pack_name;
-        let packs_data = data_dir.as_ref().join(PACKS_DIR);
-
-        let mut pack_index_path = packs_data.join(pack_name);
-        pack_index_path.set_extension(PACK_INDEX_EXTENSION);
-
-        let mut pack_path = packs_data.join(pack_name);
-        pack_path.set_extension(PACK_EXTENSION);
+        let pack_path = data_dir
+            .as_ref()
+            .join(PACKS_DIR)
+            .join(format!("{pack_name}.{PACK_EXTENSION}"));
+        Self::open(pack_path)
+    }
 
+    /// Opens the .pack file at `pack_path`, which might be outside of the
+    /// packs directory (e.g. the frames of a remote pack fetched to a
+    /// temporary file).
+    pub(crate) fn open_path(
+        name: &str,
+        pack_path: PathBuf,
+        checksum_algorithm: ChecksumAlgorithm,
+    ) -> Result<Self, Error> {
         info!("Opening pack file {:?}...", pack_path);
-        let (file_size, header, frame_readers) =
+        let (file_size, header, frame_offsets) =
             Self::open_pack(&pack_path).or_else(|_| Self::open_pack_legacy(&pack_path))?;
+        let frames = frame_offsets
+            .into_iter()
+            .zip(&header.frames)
+            .enumerate()
+            .map(|(index, (offset, frame))| FrameLocation {
+                index,
+                offset,
+                size: frame.frame_size,
+            })
+            .collect();
+        let mut file = PackFile::new(pack_path);
+        file.encryption = header
+            .encryption
+            .as_ref()
+            .map(|encryption| FrameEncryption {
+                key_id: encryption.key_id(),
+                cipher: None,
+            });
 
         Ok(Pack {
-            name: pack_name.to_owned(),
+            name: name.to_owned(),
+            file,
             header,
-            frame_readers,
+            frames,
             file_size,
+            checksum_algorithm,
+            index: None,
         })
     }
 
-    /// Opens the pack file for reading.
-    fn open_pack(pack_path: &Path) -> Result<(u64, PackHeader, Vec<PackReader>), Error> {
+    /// Opens the pack file for reading. Returns the file size, the header and
+    /// the byte offsets of the frames.
+    fn open_pack(pack_path: &Path) -> Result<(u64, PackHeader, Vec<u64>), Error> {
         let file = open_file(pack_path)?;
         let file_size = file.metadata()?.len();
         let mut reader = io::BufReader::new(file);
 
         let mut header = vec![];
         let header_size = read_skippable_frame(&mut reader, &mut header)?;
-        let header: PackHeader =
-            rmp_serde::decode::from_read(&header[..]).map_err(|_| Error::CorruptPack)?;
-
+        let header = PackHeader::from_bytes(&header).map_err(|_| Error::CorruptPack)?;
         drop(reader);
 
-        if !header.is_valid() {
-            return Err(Error::CorruptPack);
-        }
-
-        let frame_offsets = compute_frame_offsets(&header.frames);
-        let frame_readers = frame_offsets
-            .iter()
-            .map(|offset| -> Result<_, Error> {
-                let mut reader = open_file(pack_path)?;
-                io::Seek::seek(&mut reader, io::SeekFrom::Start(header_size + offset))?;
-                let mut reader = Decoder::new(reader)?;
-                reader.set_parameter(DParameter::WindowLogMax(DEFAULT_WINDOW_LOG_MAX))?;
-                // Wrap in a `PackReader`.
-                let reader = PackReader::Compressed(reader);
-                Ok(reader)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+        let frame_offsets = compute_frame_offsets(&header.frames)
+            .into_iter()
+            .map(|offset| header_size + offset)
+            .collect();
 
-        Ok((file_size, header, frame_readers))
+        Ok((file_size, header, frame_offsets))
     }
 
     /// Backwards-compatible open_pack for the legacy pack format (no skippable frame/no header).
-    fn open_pack_legacy(pack_path: &Path) -> Result<(u64, PackHeader, Vec<PackReader>), Error> {
+    fn open_pack_legacy(pack_path: &Path) -> Result<(u64, PackHeader, Vec<u64>), Error> {
         let file = open_file(pack_path)?;
         let file_size = file.metadata()?.len();
 
@@ -361,11 +707,52 @@ impl Pack {
             decompressed_size: u64::MAX,
         }]);
 
-        let mut reader = Decoder::new(file)?;
-        reader.set_parameter(DParameter::WindowLogMax(DEFAULT_WINDOW_LOG_MAX))?;
-        let frame_reader = PackReader::Compressed(reader);
+        Ok((file_size, header, vec![0]))
+    }
 
-        Ok((file_size, header, vec![frame_reader]))
+    /// Decrypts the frames of the pack with the key it is encrypted with (see
+    /// [`PackHeader::is_encrypted`]), among the specified ones. If none of
+    /// them is, reading the objects of the pack fails.
+    pub(crate) fn decrypt_with(&mut self, keys: &[EncryptionKey]) -> Result<(), Error> {
+        let (encryption, file_encryption) =
+            match (&self.header.encryption, &mut self.file.encryption) {
+                (Some(encryption), Some(file_encryption)) => (encryption, file_encryption),
+                _ => return Ok(()),
+            };
+        if file_encryption.cipher.is_some() {
+            return Ok(());
+        }
+        let cipher = match encryption.cipher(keys) {
+            Some(cipher) => cipher,
+            None => return Ok(()),
+        };
+        if let Some(dictionary) = &mut self.header.dictionary {
+            *dictionary = cipher
+                .open(DICTIONARY_NONCE, dictionary)
+                .ok_or(Error::CorruptPack)?;
+        }
+        file_encryption.cipher = Some(cipher);
+        Ok(())
+    }
+
+    /// Opens a reader for the frame, compressed with the specified codec and
+    /// dictionary (empty for none).
+    fn open_frame(
+        pack_file: &PackFile,
+        frame: FrameLocation,
+        codec: Codec,
+        dictionary: &[u8],
+    ) -> Result<PackReader, Error> {
+        let mut reader = pack_file.open_frame(frame)?;
+        // Catch packs whose header does not match the frames.
+        if let Some(frame_codec) = Codec::detect(io::BufRead::fill_buf(&mut reader)?) {
+            if frame_codec != codec {
+                return Err(Error::CorruptPack);
+            }
+        }
+        Ok(PackReader::Compressed(Decoder::new(
+            codec, reader, dictionary,
+        )?))
     }
 
     /// The base filename ([`Path::file_stem`]) of the pack.
@@ -378,6 +765,64 @@ impl Pack {
         self.file_size
     }
 
+    /// The index of the pack, if it was opened with [`Pack::open`].
+    pub fn index(&self) -> Option<&PackIndex> {
+        self.index.as_ref()
+    }
+
+    /// The tags of the snapshots of the pack (none, unless it was opened with
+    /// [`Pack::open`]).
+    pub fn snapshots(&self) -> &[String] {
+        self.index().map_or(&[], |index| index.snapshot_tags())
+    }
+
+    /// The files of the snapshot of the pack opened with [`Pack::open`].
+    pub fn snapshot_entries(&self, tag: &str) -> Result<Vec<FileEntry>, Error> {
+        let not_found = || PackError::SnapshotNotFound(tag.to_owned());
+        let index = self.index().ok_or_else(not_found)?;
+        let handles = index.resolve_snapshot(tag).ok_or_else(not_found)?;
+        let entries = index.entries_from_handles(handles.iter())?;
+        // Only the objects of this pack can be read without a repository.
+        if let Some(pack) = entries.iter().find_map(|e| e.object_metadata.pack.as_ref()) {
+            return Err(Error::PackNotFound(pack.clone()));
+        }
+        Ok(entries)
+    }
+
+    /// Returns the contents of the file at `path` in the snapshot of the pack
+    /// opened with [`Pack::open`], or `None` if the snapshot has no such file.
+    /// The checksum of the contents is verified.
+    pub fn read_file(self, tag: &str, path: &Path) -> Result<Option<Vec<u8>>, Error> {
+        let entry = match self
+            .snapshot_entries(tag)?
+            .into_iter()
+            .find(|e| Path::new(&e.path) == path)
+        {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut stream = self.stream_entries(&[entry], true)?;
+        Ok(stream.next().transpose()?.map(|(_, buf)| buf))
+    }
+
+    /// Extracts the files of the snapshot of the pack opened with
+    /// [`Pack::open`] into `output_dir`, decompressing the frames of the pack
+    /// with `num_workers` threads.
+    pub fn extract_snapshot<P>(
+        self,
+        tag: &str,
+        output_dir: P,
+        verify: bool,
+        num_workers: u32,
+    ) -> Result<(), Error>
+    where
+        P: AsRef<Path> + Sync,
+    {
+        let entries = self.snapshot_entries(tag)?;
+        fs::create_dir_all(output_dir.as_ref())?;
+        self.extract_entries(&entries, output_dir, verify, num_workers.max(1))
+    }
+
     /// Extracts the specified entries from the pack into the specified directory.
     /// This operation consumes the pack, since [`Pack`] objects contain a unidirectional
     /// data stream that becomes unusable after it is read.
@@ -387,10 +832,8 @@ impl Pack {
     /// * `entries` - The list of entries to extract. These *must* be entries contained in the pack index.
     /// * `output_dir` - The directory relative to which the files will be extracted.
     /// * `verify` - Enable/disable checksum verification.
-    #[allow(unused_mut)]
-    #[allow(clippy::needless_collect)]
     pub(crate) fn extract_entries<P>(
-        mut self,
+        self,
         entries: &[FileEntry],
         output_dir: P,
         verify: bool,
@@ -399,10 +842,37 @@ impl Pack {
     where
         P: AsRef<Path> + Sync,
     {
-        if entries.is_empty() {
-            return Ok(());
+        let (entries, chunked) = split_chunked(entries);
+        let mut chunked = ChunkedObjects::new(&self, chunked);
+        let checksum_algorithm = self.checksum_algorithm;
+        if !entries.is_empty() {
+            self.extract_frames(&entries, &output_dir, verify, num_workers)?;
         }
 
+        // The objects stored as chunks are extracted once the others are.
+        let verify = verify.then_some(checksum_algorithm);
+        while let Some(objects) = chunked.next_batch(verify, num_workers) {
+            for (entry, buf) in objects? {
+                write_object(&buf, output_dir.as_ref(), &entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts the entries which are stored in the frames (not as chunks),
+    /// decompressing the frames in parallel.
+    #[allow(unused_mut)]
+    #[allow(clippy::needless_collect)]
+    fn extract_frames<P>(
+        mut self,
+        entries: &[FileEntry],
+        output_dir: P,
+        verify: bool,
+        num_workers: u32,
+    ) -> Result<(), Error>
+    where
+        P: AsRef<Path> + Sync,
+    {
         let num_frames = self.header.frames.len();
         assert_ne!(0, num_workers);
         assert_ne!(0, num_frames);
@@ -425,7 +895,7 @@ impl Pack {
                 // Compute maximum extent of data read.
                 entries
                     .iter()
-                    .map(|e| e.object_metadata.offset + e.object_metadata.size)
+                    .map(|e| e.object_metadata.offset + e.object_metadata.stored_size())
                     .max()
             })
             .sum::<u64>();
@@ -436,21 +906,38 @@ impl Pack {
             entries.len(),
         );
 
-        // Collect required for run_in_parallel ExactSizeIterator argument.
-        let tasks = self
-            .frame_readers
+        // Collect required for run_in_pool ExactSizeIterator argument.
+        let mut tasks = self
+            .frames
             .into_iter()
-            .zip(frame_to_entries.into_iter())
+            .zip(frame_to_entries)
             // Skip empty frames.
             .filter(|(_, entries)| !entries.is_empty())
             .collect::<Vec<_>>();
+        // Start with the frames which take the longest to decompress, so they
+        // don't end up running alone on a single thread.
+        tasks.sort_by_key(|(_, entries)| {
+            std::cmp::Reverse(
+                entries
+                    .iter()
+                    .map(|e| e.object_metadata.offset + e.object_metadata.stored_size())
+                    .max(),
+            )
+        });
 
         // Record start time
         let start_time = std::time::Instant::now();
-        let results = run_in_parallel(
+        let verify = verify.then_some(self.checksum_algorithm);
+        let pack_file = &self.file;
+        let codec = self.header.codec;
+        let dictionary = self.header.dictionary();
+        let results = run_in_pool(
             num_workers as usize,
             tasks.into_iter(),
-            |(frame_reader, entries)| extract_files(frame_reader, &entries, &output_dir, verify),
+            |(frame, entries)| {
+                let frame_reader = Self::open_frame(pack_file, frame, codec, dictionary)?;
+                extract_files(frame_reader, &entries, &output_dir, verify)
+            },
         );
         // Collect stats
         let stats = results
@@ -476,49 +963,392 @@ impl Pack {
         );
         Ok(())
     }
+
+    /// Reads the specified entries from the pack into memory, calling `f` with
+    /// the contents of each entry. Only the frames containing the requested
+    /// entries are decompressed, and only up to the end of the last requested
+    /// object in each frame. This operation consumes the pack, for the same
+    /// reason as [`Pack::extract_entries`].
+    ///
+    /// The entries passed to `f` are visited in the order in which they are
+    /// stored in the pack (not in the order of `entries`) and their offsets
+    /// are relative to the containing frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The list of entries to read. These *must* be entries contained in the pack index.
+    /// * `verify` - Enable/disable checksum verification.
+    /// * `f` - Called with each entry and its contents.
+    pub(crate) fn read_entries<F>(
+        self,
+        entries: &[FileEntry],
+        verify: bool,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&FileEntry, &[u8]) -> Result<(), Error>,
+    {
+        let mut stream = self.stream_entries(entries, verify)?;
+        while let Some((entry, buf)) = stream.next().transpose()? {
+            f(&entry, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Same as [`Pack::read_entries`], but the entries are read lazily, one at
+    /// a time, by iterating the returned [`PackStream`].
+    pub(crate) fn stream_entries(
+        self,
+        entries: &[FileEntry],
+        verify: bool,
+    ) -> Result<PackStream, Error> {
+        let (entries, chunked) = split_chunked(entries);
+        let chunked = ChunkedObjects::new(&self, chunked);
+        let frame_to_entries = assign_to_frames(&self.header.frames, &entries)?;
+        let frames: Vec<_> = self
+            .frames
+            .into_iter()
+            .zip(frame_to_entries)
+            .filter(|(_, entries)| !entries.is_empty())
+            .collect();
+
+        Ok(PackStream {
+            file: self.file,
+            codec: self.header.codec,
+            dictionary: self.header.dictionary.unwrap_or_default(),
+            frames: frames.into_iter(),
+            current: None,
+            chunked,
+            ready: Vec::new().into_iter(),
+            verify: if verify {
+                Some(self.checksum_algorithm)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Reads the contents of the entries of a pack in the order in which they are
+/// stored (see [`Pack::stream_entries`]). Iteration stops after the first error.
+pub(crate) struct PackStream {
+    /// The .pack file.
+    file: PackFile,
+    /// The codec and dictionary (empty for none) the frames are compressed
+    /// with.
+    codec: Codec,
+    dictionary: Vec<u8>,
+    /// The remaining frames, along with the entries to read from each of
+    /// them.
+    frames: std::vec::IntoIter<(FrameLocation, Vec<FileEntry>)>,
+    /// The frame being read.
+    current: Option<FrameStream>,
+    /// The objects stored as chunks, which are read after the other ones.
+    chunked: ChunkedObjects,
+    /// The objects of the last batch read from `chunked`.
+    ready: std::vec::IntoIter<EntryContents>,
+    verify: Option<ChecksumAlgorithm>,
+}
+
+struct FrameStream {
+    reader: FrameReader,
+    entries: std::iter::Peekable<std::vec::IntoIter<FileEntry>>,
+    /// The contents of the last object read, when the next entry references
+    /// the same object.
+    last: Vec<u8>,
+}
+
+impl FrameStream {
+    fn read_next(
+        &mut self,
+        verify: Option<ChecksumAlgorithm>,
+    ) -> Option<Result<(FileEntry, Vec<u8>), Error>> {
+        let entry = self.entries.next()?;
+        let metadata = &entry.object_metadata;
+        // Same as in extract_files, the current position can only be after the
+        // object offset if the previous object is the same.
+        let buf = if self.reader.pos <= metadata.offset {
+            let mut buf = vec![];
+            if let Err(e) = self.reader.read_object(metadata, &mut buf) {
+                return Some(Err(e.into()));
+            }
+            if let Some(algorithm) = verify {
+                if let Err(e) = verify_entry(&buf, &entry, algorithm) {
+                    return Some(Err(e));
+                }
+            }
+            buf
+        } else {
+            std::mem::take(&mut self.last)
+        };
+        // Keep a copy only if the same object is needed again.
+        if self.entries.peek().map(|next| &next.object_metadata) == Some(&entry.object_metadata) {
+            self.last = buf.clone();
+        }
+        Some(Ok((entry, buf)))
+    }
+}
+
+impl Iterator for PackStream {
+    type Item = Result<(FileEntry, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = &mut self.current {
+                match frame.read_next(self.verify) {
+                    Some(Ok(object)) => return Some(Ok(object)),
+                    Some(Err(e)) => {
+                        self.current = None;
+                        self.frames = Vec::new().into_iter();
+                        return Some(Err(e));
+                    }
+                    None => self.current = None,
+                }
+            }
+            if let Some(object) = self.ready.next() {
+                return Some(Ok(object));
+            }
+            let (frame, mut entries) = match self.frames.next() {
+                Some(frame) => frame,
+                None => match self.chunked.next_batch(self.verify, 1)? {
+                    Ok(objects) => {
+                        self.ready = objects.into_iter();
+                        continue;
+                    }
+                    Err(e) => {
+                        self.chunked.batches = Vec::new().into_iter();
+                        return Some(Err(e));
+                    }
+                },
+            };
+            let reader = match Pack::open_frame(&self.file, frame, self.codec, &self.dictionary) {
+                Ok(reader) => reader,
+                Err(e) => {
+                    self.frames = Vec::new().into_iter();
+                    return Some(Err(e));
+                }
+            };
+            // Sort objects to allow for forward-only seeking (see extract_files)
+            entries.sort_by(|a, b| a.object_metadata.cmp(&b.object_metadata));
+            self.current = Some(FrameStream {
+                reader: FrameReader::new(reader, &entries),
+                entries: entries.into_iter().peekable(),
+                last: vec![],
+            });
+        }
+    }
+}
+
+/// Separates the entries of the objects stored as chunks, which are read
+/// separately (see [`ChunkedObjects`]), from the other entries.
+fn split_chunked(entries: &[FileEntry]) -> (Cow<'_, [FileEntry]>, Vec<FileEntry>) {
+    if entries
+        .iter()
+        .all(|entry| entry.object_metadata.chunks.is_none())
+    {
+        return (Cow::Borrowed(entries), vec![]);
+    }
+    let (chunked, entries) = entries
+        .iter()
+        .cloned()
+        .partition(|entry| entry.object_metadata.chunks.is_some());
+    (Cow::Owned(entries), chunked)
+}
+
+/// An entry, along with the contents of its object.
+type EntryContents = (FileEntry, Vec<u8>);
+
+/// Reads the objects stored as chunks (see [`ObjectMetadata::chunks`]). The
+/// chunks of an object can be stored in any frame, so the objects are
+/// assembled in memory, a batch of objects at a time.
+struct ChunkedObjects {
+    /// The .pack file.
+    file: PackFile,
+    codec: Codec,
+    dictionary: Vec<u8>,
+    frames: Vec<PackFrame>,
+    frame_locations: Vec<FrameLocation>,
+    /// The remaining batches of entries. Entries referencing the same object
+    /// are in the same batch.
+    batches: std::vec::IntoIter<Vec<FileEntry>>,
+}
+
+impl ChunkedObjects {
+    fn new(pack: &Pack, mut entries: Vec<FileEntry>) -> Self {
+        entries.sort_by(|a, b| a.object_metadata.cmp(&b.object_metadata));
+        let mut batches = vec![];
+        let mut batch: Vec<FileEntry> = vec![];
+        let mut batch_size = 0;
+        for entry in entries {
+            let size = entry.object_metadata.size;
+            if batch.last().map(|last| &last.object_metadata) != Some(&entry.object_metadata) {
+                if !batch.is_empty() && batch_size + size > CHUNKED_BATCH_SIZE {
+                    batches.push(std::mem::take(&mut batch));
+                    batch_size = 0;
+                }
+                batch_size += size;
+            }
+            batch.push(entry);
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+
+        Self {
+            file: pack.file.clone(),
+            codec: pack.header.codec,
+            dictionary: pack.header.dictionary().to_vec(),
+            frames: pack.header.frames.clone(),
+            frame_locations: pack.frames.clone(),
+            batches: batches.into_iter(),
+        }
+    }
+
+    /// Reads the objects of the next batch, returning each entry along with
+    /// the contents of its object.
+    fn next_batch(
+        &mut self,
+        verify: Option<ChecksumAlgorithm>,
+        num_workers: u32,
+    ) -> Option<Result<Vec<EntryContents>, Error>> {
+        let batch = self.batches.next()?;
+        Some(self.read_batch(batch, verify, num_workers))
+    }
+
+    fn read_batch(
+        &self,
+        batch: Vec<FileEntry>,
+        verify: Option<ChecksumAlgorithm>,
+        num_workers: u32,
+    ) -> Result<Vec<EntryContents>, Error> {
+        // Find the frames containing the (distinct) chunks of the objects.
+        let frame_decompressed_offset = compute_frame_decompressed_offset(&self.frames);
+        let chunks: BTreeSet<ObjectChunk> = batch
+            .iter()
+            .flat_map(|entry| entry.object_metadata.chunks.iter().flatten().copied())
+            .collect();
+        let mut frame_to_chunks: BTreeMap<usize, Vec<ObjectChunk>> = BTreeMap::new();
+        for chunk in chunks {
+            let frame_index = frame_decompressed_offset
+                .partition_point(|&offset| offset <= chunk.offset)
+                .checked_sub(1)
+                .ok_or(Error::CorruptPack)?;
+            let frame_end =
+                frame_decompressed_offset[frame_index] + self.frames[frame_index].decompressed_size;
+            if chunk.offset + chunk.size > frame_end {
+                return Err(Error::CorruptPack);
+            }
+            frame_to_chunks.entry(frame_index).or_default().push(chunk);
+        }
+
+        let frame_decompressed_offset = &frame_decompressed_offset;
+        let results = run_in_parallel(
+            std::cmp::max(1, num_workers) as usize,
+            frame_to_chunks.into_iter(),
+            |(frame_index, chunks)| -> Result<Vec<(u64, Vec<u8>)>, Error> {
+                let mut reader = Pack::open_frame(
+                    &self.file,
+                    self.frame_locations[frame_index],
+                    self.codec,
+                    &self.dictionary,
+                )?;
+                let mut pos = frame_decompressed_offset[frame_index];
+                let mut contents = vec![];
+                for chunk in chunks {
+                    reader.seek(chunk.offset.checked_sub(pos).ok_or(Error::CorruptPack)?)?;
+                    let mut buf = vec![0; chunk.size as usize];
+                    reader.read_exact(&mut buf)?;
+                    pos = chunk.offset + chunk.size;
+                    contents.push((chunk.offset, buf));
+                }
+                Ok(contents)
+            },
+        );
+        let mut chunk_contents = HashMap::new();
+        for result in results {
+            chunk_contents.extend(result?);
+        }
+
+        let mut objects: Vec<EntryContents> = Vec::with_capacity(batch.len());
+        for entry in batch {
+            let buf = match objects.last() {
+                // Entries referencing the same object are consecutive.
+                Some((last, buf)) if last.object_metadata == entry.object_metadata => buf.clone(),
+                _ => {
+                    let mut buf = Vec::with_capacity(entry.object_metadata.size as usize);
+                    for chunk in entry.object_metadata.chunks.iter().flatten() {
+                        buf.extend_from_slice(&chunk_contents[&chunk.offset]);
+                    }
+                    if let Some(algorithm) = verify {
+                        verify_entry(&buf, &entry, algorithm)?;
+                    }
+                    buf
+                }
+            };
+            objects.push((entry, buf));
+        }
+        Ok(objects)
+    }
 }
 
 /// Verifies that the object has the expected checksum.
-fn verify_object(buf: &[u8], exp_checksum: &ObjectChecksum) -> Result<(), Error> {
-    // Verify checksum
-    let mut checksum = [0u8; 20];
-    let mut hasher = Sha1::new();
-    hasher.input(buf);
-    hasher.result(&mut checksum);
+pub(crate) fn verify_object(
+    buf: &[u8],
+    exp_checksum: &ObjectChecksum,
+    algorithm: ChecksumAlgorithm,
+) -> Result<(), Error> {
+    let checksum = algorithm.checksum(buf);
     if &checksum != exp_checksum {
         return Err(PackError::ChecksumMismatch(*exp_checksum, checksum).into());
     }
     Ok(())
 }
 
-/// Writes the object to the specified path, taking care
-/// of adjusting file permissions.
-fn write_object(buf: &[u8], path: &Path, perm: Option<Permissions>) -> Result<(), Error> {
-    fs::create_dir_all(path.parent().unwrap())?;
-    let mut f = create_file(path, perm)?;
-    f.write_all(buf)?;
-    Ok(())
+/// Verifies the contents of the object of the entry, which are corrupt
+/// ([`Error::CorruptFile`]) if they don't have its checksum.
+pub(crate) fn verify_entry(
+    buf: &[u8],
+    entry: &FileEntry,
+    algorithm: ChecksumAlgorithm,
+) -> Result<(), Error> {
+    check_entry_checksum(entry, algorithm.checksum(buf))
 }
 
-/// Returns a list of the frame offsets, computed
-/// using the order and sizes of the given frames.
-fn compute_frame_offsets(frames: &[PackFrame]) -> Vec<u64> {
-    let mut frame_offsets: Vec<_> = vec![0; frames.len()];
-    for i in 1..frame_offsets.len() {
-        frame_offsets[i] = frames[i - 1].frame_size + frame_offsets[i - 1];
+/// Checks the checksum computed for the contents of the entry.
+pub(crate) fn check_entry_checksum(
+    entry: &FileEntry,
+    checksum: ObjectChecksum,
+) -> Result<(), Error> {
+    if checksum != entry.checksum {
+        return Err(Error::CorruptFile(
+            entry.path.clone(),
+            entry.checksum,
+            checksum,
+        ));
     }
-    frame_offsets
+    Ok(())
 }
 
-/// Returns a list of the data offsets, computed using the order and
-/// decompressed sizes of the given frames.
-fn compute_frame_decompressed_offset(frames: &[PackFrame]) -> Vec<u64> {
-    let mut frame_decompressed_offset: Vec<_> = vec![0; frames.len()];
-    for i in 1..frame_decompressed_offset.len() {
-        frame_decompressed_offset[i] =
-            frames[i - 1].decompressed_size + frame_decompressed_offset[i - 1];
+/// Writes the object to the path of the entry, relative to `output_dir`,
+/// taking care of adjusting file permissions.
+fn write_object(buf: &[u8], output_dir: &Path, entry: &FileEntry) -> Result<(), Error> {
+    let file_metadata = entry.file_metadata;
+    let path = create_entry_parents(output_dir, &entry.path)?;
+    let path = path.as_path();
+    if file_metadata.is_dir() {
+        create_entry_dir(path)?;
+        set_file_mode(path, file_metadata.mode)?;
+        return Ok(());
+    }
+    if file_metadata.is_symlink() {
+        create_symlink(buf, path)?;
+        return Ok(());
     }
-    frame_decompressed_offset
+    replace_file(path, |temp_path| {
+        write_sparse(&mut create_file(temp_path, None)?, buf)?;
+        set_file_mode(temp_path, file_metadata.mode)
+    })?;
+    Ok(())
 }
 
 /// Groups and transforms the list of [`FileEntry`]-s taken from a pack index
@@ -535,20 +1365,34 @@ fn assign_to_frames(
     // using the frame offset and the object offset.
     let mut frames: Vec<Vec<FileEntry>> = (0..frames.len()).map(|_| Vec::new()).collect();
     for entry in entries {
+        // Find the index of the (last) frame starting at or before the object
+        // (objects are assumed to not be split across two frames). Packs can
+        // have many small frames, so use a binary search.
         let frame_index = frame_decompressed_offset
-            .iter()
-            // Find the index of the frame containing the object (objects are
-            // assumed to not be split across two frames)
-            .rposition(|&x| x <= entry.object_metadata.offset)
+            .partition_point(|&x| x <= entry.object_metadata.offset)
+            .checked_sub(1)
             .ok_or(Error::CorruptPack)?;
         // Compute the offset relative to that frame.
-        let local_offset = entry.object_metadata.offset - frame_decompressed_offset[frame_index];
+        let frame_offset = frame_decompressed_offset[frame_index];
+        let local_offset = entry.object_metadata.offset - frame_offset;
+        // Delta bases are stored in the same frame.
+        let delta = match entry.object_metadata.delta {
+            Some(delta) if delta.base_offset < frame_offset => return Err(Error::CorruptPack),
+            Some(delta) => Some(ObjectDelta {
+                base_offset: delta.base_offset - frame_offset,
+                ..delta
+            }),
+            None => None,
+        };
         let local_entry = FileEntry::new(
             entry.path.clone(),
             entry.checksum,
             ObjectMetadata {
                 offset: local_offset, // Replace global offset -> local offset
                 size: entry.object_metadata.size,
+                delta,
+                chunks: None,
+                pack: None,
             },
             entry.file_metadata,
         );
@@ -558,6 +1402,35 @@ fn assign_to_frames(
     Ok(frames)
 }
 
+/// Returns the indices of the frames (see [`PackIndex::frames`]) which need to
+/// be decompressed to read the entries, in order. Delta bases are stored in
+/// the same frame as their deltas, and the chunks of the objects stored as
+/// chunks in the frames containing their offsets.
+pub(crate) fn frames_containing(frames: &[FrameBoundary], entries: &[FileEntry]) -> Vec<usize> {
+    let mut frame_decompressed_offset = Vec::with_capacity(frames.len());
+    let mut offset = 0;
+    for frame in frames {
+        frame_decompressed_offset.push(offset);
+        offset += frame.decompressed_size;
+    }
+    let frame_index = |offset: u64| {
+        frame_decompressed_offset
+            .partition_point(|&x| x <= offset)
+            .saturating_sub(1)
+    };
+
+    let mut indices = BTreeSet::new();
+    for entry in entries {
+        match &entry.object_metadata.chunks {
+            Some(chunks) => indices.extend(chunks.iter().map(|chunk| frame_index(chunk.offset))),
+            None => {
+                indices.insert(frame_index(entry.object_metadata.offset));
+            }
+        }
+    }
+    indices.into_iter().collect()
+}
+
 /// Used for timing the different parts of the extraction process.
 #[derive(Default)]
 struct ExtractStats {
@@ -612,79 +1485,142 @@ impl std::iter::Sum<ExtractStats> for ExtractStats {
     }
 }
 
+/// The number of decompressed objects of a frame which can be waiting to be
+/// written (see [`extract_files`]).
+const WRITE_QUEUE_LEN: usize = 16;
+
 /// Extracts the given entries from the pack reader into the specified output directory.
-/// Checksum verification can be toggled on/off.
+/// The checksums are verified with the given algorithm, if any. The objects are
+/// verified and written by another thread, while the next ones are decompressed.
 fn extract_files(
-    mut reader: PackReader,
+    reader: PackReader,
     entries: &[FileEntry],
     output_dir: impl AsRef<Path>,
-    verify: bool,
+    verify: Option<ChecksumAlgorithm>,
 ) -> Result<ExtractStats, Error> {
     let mut entries: Vec<FileEntry> = entries.to_vec();
-    // Sort objects to allow for forward-only seeking
-    entries.sort_by(|x, y| {
-        let offset_x = x.object_metadata.offset;
-        let offset_y = y.object_metadata.offset;
-        offset_x.cmp(&offset_y)
-    });
-
-    // Used for timing
+    // Sort objects to allow for forward-only seeking. Empty objects have the
+    // same offset as the object following them, so ties are broken by size,
+    // to make sure that they are read first.
+    entries.sort_by(|a, b| a.object_metadata.cmp(&b.object_metadata));
+    let mut reader = FrameReader::new(reader, &entries);
+
+    // The entries referencing the same object are adjacent, the object is
+    // only read once.
+    let mut objects: Vec<Vec<FileEntry>> = vec![];
+    for entry in entries {
+        match objects.last_mut() {
+            Some(last) if last[0].object_metadata == entry.object_metadata => last.push(entry),
+            _ => objects.push(vec![entry]),
+        }
+    }
+
+    let output_dir = output_dir.as_ref();
+    let (sender, receiver) = mpsc::sync_channel(WRITE_QUEUE_LEN);
+    let (read_stats, write_stats) = crossbeam_utils::thread::scope(|s| {
+        let writer = s.spawn(move |_| write_files(receiver, output_dir, verify));
+        let read_stats = read_objects(&mut reader, objects, sender);
+        (read_stats, writer.join())
+    })
+    // These errors only contain panic information (see run_in_parallel).
+    .unwrap_or_else(|payload| panic::resume_unwind(payload));
+    let write_stats = write_stats.unwrap_or_else(|payload| panic::resume_unwind(payload));
+    // If writing failed, reading stopped early because of it.
+    let write_stats = write_stats?;
+    Ok(read_stats? + write_stats)
+}
+
+/// Reads the objects of the frame, sending the contents of each object along
+/// with its entries to `sender`. Stops early if the receiver is dropped.
+fn read_objects(
+    reader: &mut FrameReader,
+    objects: Vec<Vec<FileEntry>>,
+    sender: mpsc::SyncSender<(Vec<u8>, Vec<FileEntry>)>,
+) -> Result<ExtractStats, Error> {
     let mut stats = ExtractStats::default();
     let total_time = measure_ok(|| -> Result<(), Error> {
-        // Decompression buffer
-        let mut buf = vec![];
-        let mut path_buf = PathBuf::new();
-        let mut pos = 0;
-        for entry in entries {
-            let metadata = &entry.object_metadata;
-            // Seek forward
-            let discard_bytes = metadata.offset - pos;
-            // Check if we need to read a new object.
-            // The current position in stream can be AFTER the object offset only
-            // if the previous and this object are the same. This is because the objects
-            // are sorted by offset, and the current position is set to the offset at the
-            // end of each object, after that object is consumed.
-            if pos <= metadata.offset {
-                stats.seek_time += measure_ok(|| reader.seek(discard_bytes))?.0.as_secs_f64();
-                // Resize buf
-                buf.resize(metadata.size as usize, 0);
-                // Read object
-                stats.object_time += measure_ok(|| reader.read_exact(&mut buf[..]))?
+        for entries in objects {
+            let seek_time = reader.seek_time;
+            // Read object (seeking forward)
+            let mut buf = vec![];
+            stats.object_time +=
+                measure_ok(|| reader.read_object(&entries[0].object_metadata, &mut buf))?
                     .0
                     .as_secs_f64();
-                pos = metadata.offset + metadata.size;
-                if verify {
-                    stats.verify_time += measure_ok(|| verify_object(&buf[..], &entry.checksum))?
-                        .0
-                        .as_secs_f64();
-                }
+            // The time spent seeking is measured separately.
+            stats.seek_time += reader.seek_time - seek_time;
+            stats.object_time -= reader.seek_time - seek_time;
+            if sender.send((buf, entries)).is_err() {
+                // The writer failed and reports the error.
+                break;
             }
-            // Output path
-            path_buf.clear();
-            path_buf.push(&output_dir);
-            path_buf.push(&entry.path);
-            stats.write_time += measure_ok(|| {
-                let perm = Permissions::from_mode(entry.file_metadata.mode);
-                write_object(&buf[..], &path_buf, Some(perm))
-            })?
-            .0
-            .as_secs_f64();
         }
-
         Ok(())
     })?
     .0;
     stats.total_time = total_time.as_secs_f64();
+    Ok(stats)
+}
 
+/// Verifies and writes the objects received from [`read_objects`] to the
+/// paths of their entries, relative to `output_dir`.
+fn write_files(
+    receiver: mpsc::Receiver<(Vec<u8>, Vec<FileEntry>)>,
+    output_dir: &Path,
+    verify: Option<ChecksumAlgorithm>,
+) -> Result<ExtractStats, Error> {
+    let mut stats = ExtractStats::default();
+    let total_time = measure_ok(|| -> Result<(), Error> {
+        for (buf, entries) in receiver {
+            if let Some(algorithm) = verify {
+                stats.verify_time += measure_ok(|| verify_entry(&buf[..], &entries[0], algorithm))?
+                    .0
+                    .as_secs_f64();
+            }
+            for entry in &entries {
+                stats.write_time += measure_ok(|| write_object(&buf[..], output_dir, entry))?
+                    .0
+                    .as_secs_f64();
+            }
+        }
+        Ok(())
+    })?
+    .0;
+    stats.total_time = total_time.as_secs_f64();
     Ok(stats)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::frame::write_skippable_frame;
+    use crate::packidx::FileMetadata;
+
+    #[test]
+    fn external_pack_names_work() {
+        let id = |s| PackId::from_str(s).unwrap();
+        for (pack, name, resolved) in [
+            ("p2", "p1", "p1"),
+            ("p2", "origin/p1", "origin/p1"),
+            ("origin/p2", "p1", "origin/p1"),
+            ("origin/sub/p2", "p1", "origin/sub/p1"),
+        ] {
+            assert_eq!(id(pack).resolve_external(name).unwrap(), id(resolved));
+            assert_eq!(id(pack).external_name(&id(resolved)).as_deref(), Some(name));
+        }
+        assert!(id("origin/p2").external_name(&id("p1")).is_none());
+        assert!(id("origin/p2").external_name(&id("origin2/p1")).is_none());
+        assert!(id("p2").resolve_external("../p1").is_err());
+    }
 
     fn make_md(offset: u64, size: u64) -> ObjectMetadata {
-        ObjectMetadata { offset, size }
+        ObjectMetadata {
+            offset,
+            size,
+            delta: None,
+            chunks: None,
+            pack: None,
+        }
     }
 
     #[test]
@@ -695,7 +1631,11 @@ mod tests {
         assert!(PackId::is_valid("____"));
         assert!(PackId::is_valid("----"));
         assert!(PackId::is_valid("ABCD-132_TAG"));
+        assert!(PackId::is_valid("dir/pack.0"));
         // NOT VALID
+        // components starting with .
+        assert!(!PackId::is_valid("../pack"));
+        assert!(!PackId::is_valid("dir/.pack"));
         // spaces
         assert!(!PackId::is_valid("Some Text"));
         // non-latin alphabets
@@ -704,6 +1644,20 @@ mod tests {
         assert!(!PackId::is_valid("QWERTY-^!$^%^@!#"));
     }
     #[test]
+    fn ref_name_validation_works() {
+        // VALID
+        assert!(is_valid_ref_name("latest"));
+        assert!(is_valid_ref_name("release-1.0"));
+        assert!(is_valid_ref_name("release/1.0"));
+        // NOT VALID
+        assert!(!is_valid_ref_name(""));
+        assert!(!is_valid_ref_name("/latest"));
+        assert!(!is_valid_ref_name("release/"));
+        assert!(!is_valid_ref_name("release//1.0"));
+        assert!(!is_valid_ref_name("../latest"));
+        assert!(!is_valid_ref_name("pack:snapshot"));
+    }
+    #[test]
     fn snapshot_tag_validation_works() {
         // VALID
         assert!(SnapshotId::is_valid("ABCD"));
@@ -726,8 +1680,18 @@ mod tests {
             decompressed_size: 1000,
         }];
         let entries = [
-            FileEntry::new("A".into(), [0; 20], make_md(50, 1), Default::default()),
-            FileEntry::new("B".into(), [1; 20], make_md(50, 1), Default::default()),
+            FileEntry::new(
+                "A".into(),
+                ObjectChecksum::from([0; 20]),
+                make_md(50, 1),
+                Default::default(),
+            ),
+            FileEntry::new(
+                "B".into(),
+                ObjectChecksum::from([1; 20]),
+                make_md(50, 1),
+                Default::default(),
+            ),
         ];
         let result = assign_to_frames(&frames, &entries).unwrap();
         assert_eq!(1, result.len());
@@ -746,20 +1710,210 @@ mod tests {
             },
         ];
         let entries = [
-            FileEntry::new("A".into(), [0; 20], make_md(800, 200), Default::default()),
-            FileEntry::new("B".into(), [1; 20], make_md(1200, 200), Default::default()),
+            FileEntry::new(
+                "A".into(),
+                ObjectChecksum::from([0; 20]),
+                make_md(800, 200),
+                Default::default(),
+            ),
+            FileEntry::new(
+                "B".into(),
+                ObjectChecksum::from([1; 20]),
+                make_md(1200, 200),
+                Default::default(),
+            ),
         ];
         let frame_1_entries = [
             // Offset is same
-            FileEntry::new("A".into(), [0; 20], make_md(800, 200), Default::default()),
+            FileEntry::new(
+                "A".into(),
+                ObjectChecksum::from([0; 20]),
+                make_md(800, 200),
+                Default::default(),
+            ),
         ];
         let frame_2_entries = [
             // Offset 1200 -> 200
-            FileEntry::new("B".into(), [1; 20], make_md(200, 200), Default::default()),
+            FileEntry::new(
+                "B".into(),
+                ObjectChecksum::from([1; 20]),
+                make_md(200, 200),
+                Default::default(),
+            ),
         ];
         let result = assign_to_frames(&frames, &entries).unwrap();
         assert_eq!(2, result.len());
         assert_eq!(&frame_1_entries, result[0].as_slice());
         assert_eq!(&frame_2_entries, result[1].as_slice());
     }
+
+    #[test]
+    fn frames_containing_works() {
+        let frame = |offset, decompressed_size| FrameBoundary {
+            offset,
+            size: 10,
+            decompressed_size,
+        };
+        let frames = [frame(20, 1000), frame(30, 1000), frame(40, 1000)];
+        let entry = |offset, chunks: Option<Vec<ObjectChunk>>| {
+            let mut md = make_md(offset, 10);
+            md.chunks = chunks;
+            FileEntry::new(
+                "A".into(),
+                ObjectChecksum::from([0; 20]),
+                md,
+                Default::default(),
+            )
+        };
+        assert_eq!(
+            vec![0, 2],
+            frames_containing(&frames, &[entry(2500, None), entry(10, None)])
+        );
+        let chunks = vec![
+            ObjectChunk {
+                offset: 1000,
+                size: 10,
+            },
+            ObjectChunk {
+                offset: 2990,
+                size: 10,
+            },
+        ];
+        assert_eq!(
+            vec![1, 2],
+            frames_containing(&frames, &[entry(1000, Some(chunks))])
+        );
+    }
+
+    #[test]
+    fn pack_encryption_works() {
+        let path = std::env::temp_dir().join(format!("elfshaker_encrypted_{}", std::process::id()));
+        let key = EncryptionKey::generate();
+        let data: Vec<Vec<u8>> = vec![b"first frame".to_vec(), b"second frame".to_vec()];
+        let mut bufs: Vec<_> = data
+            .iter()
+            .map(|data| zstd::encode_all(&data[..], 1).unwrap())
+            .collect();
+        let frames = bufs
+            .iter()
+            .zip(&data)
+            .map(|(buf, data)| PackFrame {
+                frame_size: buf.len() as u64,
+                decompressed_size: data.len() as u64,
+            })
+            .collect();
+        let mut header = PackHeader::with_codec(frames, Codec::Zstd);
+        header.encrypt_frames(&key, &mut bufs);
+        assert!(header.is_encrypted());
+        let mut file = vec![];
+        write_skippable_frame(&mut file, &header.to_bytes()).unwrap();
+        for buf in &bufs {
+            file.extend_from_slice(buf);
+        }
+        fs::write(&path, &file).unwrap();
+
+        let read_frame = |pack: &Pack, index: usize| -> Result<Vec<u8>, Error> {
+            let frame = pack.frames[index];
+            let mut reader = Pack::open_frame(&pack.file, frame, pack.header.codec, &[])?;
+            let mut buf = vec![0; data[index].len()];
+            reader.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+        let mut pack = Pack::open_path("encrypted", path.clone(), ChecksumAlgorithm::Sha1).unwrap();
+        assert!(pack.header.is_encrypted());
+        assert!(matches!(read_frame(&pack, 0), Err(Error::Encryption(_))));
+        pack.decrypt_with(&[EncryptionKey::generate()]).unwrap();
+        assert!(matches!(read_frame(&pack, 0), Err(Error::Encryption(_))));
+        pack.decrypt_with(std::slice::from_ref(&key)).unwrap();
+        assert_eq!(data[0], read_frame(&pack, 0).unwrap());
+        assert_eq!(data[1], read_frame(&pack, 1).unwrap());
+
+        // The frames are authenticated along with their position.
+        pack.frames[0] = FrameLocation {
+            index: 0,
+            ..pack.frames[1]
+        };
+        assert!(matches!(read_frame(&pack, 0), Err(Error::Encryption(_))));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn standalone_pack_works() {
+        let dir = std::env::temp_dir().join(format!("elfshaker_standalone_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let objects: [&[u8]; 2] = [b"hello", b"world!"];
+        let data = objects.concat();
+        let buf = zstd::encode_all(&data[..], 1).unwrap();
+        let header = PackHeader::with_codec(
+            vec![PackFrame {
+                frame_size: buf.len() as u64,
+                decompressed_size: data.len() as u64,
+            }],
+            Codec::Zstd,
+        );
+        let mut file = vec![];
+        write_skippable_frame(&mut file, &header.to_bytes()).unwrap();
+        file.extend_from_slice(&buf);
+        fs::write(dir.join("p.pack"), &file).unwrap();
+
+        let mut index = PackIndex::new();
+        let mut offset = 0;
+        let entries: Vec<_> = ["a", "b/c"]
+            .iter()
+            .zip(objects)
+            .map(|(path, object)| {
+                let entry = FileEntry::new(
+                    path.into(),
+                    index.checksum_algorithm().checksum(object),
+                    make_md(offset, object.len() as u64),
+                    FileMetadata::default(),
+                );
+                offset += object.len() as u64;
+                entry
+            })
+            .collect();
+        index.push_snapshot("s".into(), entries).unwrap();
+        index.save(dir.join("p.pack.idx")).unwrap();
+
+        let pack = Pack::open(dir.join("p.pack.idx")).unwrap();
+        assert_eq!("p", pack.name());
+        assert_eq!(&["s".to_owned()], pack.snapshots());
+        assert_eq!(2, pack.snapshot_entries("s").unwrap().len());
+        assert!(matches!(
+            pack.snapshot_entries("t"),
+            Err(Error::PackError(PackError::SnapshotNotFound(_)))
+        ));
+        let read = |path: &str| {
+            let pack = Pack::open(dir.join("p.pack")).unwrap();
+            pack.read_file("s", Path::new(path)).unwrap()
+        };
+        assert_eq!(Some(b"world!".to_vec()), read("b/c"));
+        assert_eq!(None, read("d"));
+
+        let output_dir = dir.join("out");
+        pack.extract_snapshot("s", &output_dir, true, 2).unwrap();
+        assert_eq!(b"hello", &fs::read(output_dir.join("a")).unwrap()[..]);
+        assert_eq!(b"world!", &fs::read(output_dir.join("b/c")).unwrap()[..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pack_file_open_at_works() {
+        let path = std::env::temp_dir().join(format!("elfshaker_pack_file_{}", std::process::id()));
+        let data: Vec<u8> = (0..100u8).collect();
+        fs::write(&path, &data).unwrap();
+        let file = PackFile::new(path.clone());
+
+        let mut buf = vec![];
+        file.open_at(40).unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(&data[40..], &buf[..]);
+        let mut reader = file.open_at(0).unwrap();
+        assert_eq!(&data[..], io::BufRead::fill_buf(&mut reader).unwrap());
+        io::BufRead::consume(&mut reader, 90);
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&data[90..], &buf[..]);
+        assert_eq!(0, reader.read(&mut buf).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
 }
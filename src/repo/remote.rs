@@ -3,6 +3,7 @@ use std::{
     ffi::OsStr,
     fs, io,
     io::{BufRead, BufReader, Read, Write},
+    ops::Range,
     path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
@@ -10,17 +11,33 @@ use std::{
 use chrono::{offset::Utc, DateTime};
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
+use rand::Rng;
 use ureq::Agent;
 use url::Url;
 
 use super::constants::{PACK_EXTENSION, REMOTE_INDEX_EXTENSION};
+use super::encryption;
 use super::error::Error;
-use super::fs::{create_file, open_file};
-use crate::packidx::{ObjectChecksum, PackIndex};
+use super::fs::{create_file, open_file, replace_file};
+use super::object_storage;
+use super::repository::check_build_cache_key;
+use crate::packidx::PackIndex;
 use crate::progress::{ProgressReporter, ProgressWriter};
 
+/// The SHA-1 checksum of a file listed in a .esi (the remote index format does
+/// not depend on the checksum algorithm of the packs).
+pub type FileChecksum = [u8; 20];
+
 const HTTP_STATUS_OK: u16 = 200;
+const HTTP_STATUS_PARTIAL_CONTENT: u16 = 206;
 const HTTP_STATUS_NOT_MODIFIED: u16 = 304;
+const HTTP_STATUS_NOT_FOUND: u16 = 404;
+const HTTP_STATUS_CONFLICT: u16 = 409;
+const HTTP_STATUS_PRECONDITION_FAILED: u16 = 412;
+
+/// The number of times a .esi is read and written back (see
+/// [`add_pack_to_remote_index`]) when it keeps being modified concurrently.
+const MAX_REMOTE_INDEX_UPDATES: u32 = 10;
 
 /// The .esi file is corrupted.
 #[derive(Debug)]
@@ -77,17 +94,26 @@ impl<T> ReifyRemoteResult for Result<T, Error> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RemotePack {
-    pub index_checksum: ObjectChecksum,
-    pub pack_checksum: ObjectChecksum,
+    pub index_checksum: FileChecksum,
+    pub pack_checksum: FileChecksum,
     pub url: String,
+    /// The URLs of the copies of the pack on the mirrors of the remote (see
+    /// [`RemoteIndex::set_mirrors`]), which are tried in order when the
+    /// requests to `url` fail.
+    pub mirror_urls: Vec<String>,
 }
 
 impl RemotePack {
     pub fn file_name(&self) -> &str {
         self.url.rsplit_once('/').unwrap().1
     }
+
+    /// The URL of the pack, then the ones of its mirrors.
+    fn urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.mirror_urls.iter().map(String::as_str))
+    }
 }
 
 #[derive(Debug)]
@@ -98,16 +124,18 @@ pub struct RemoteIndex {
     meta: String,
     url: String,
     packs: Vec<RemotePack>,
+    /// The URLs of the .esi of the mirrors of the remote.
+    mirrors: Vec<String>,
 }
 
 impl RemoteIndex {
-    #[allow(dead_code)]
     pub fn new(url: String) -> RemoteIndex {
         Self {
             path: None,
             meta: "v1".to_owned(),
             url,
             packs: vec![],
+            mirrors: vec![],
         }
     }
 
@@ -116,6 +144,41 @@ impl RemoteIndex {
         &self.packs
     }
 
+    /// The URL of the .esi file.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn push_pack(&mut self, pack: RemotePack) {
+        self.packs.push(pack);
+    }
+
+    /// Sets the URLs of the .esi of the mirrors of the remote, i.e. of the
+    /// copies of the remote hosted by other servers. The files listed below
+    /// the directory of the .esi of the remote are expected at the same paths
+    /// below the directory of the .esi of each mirror. The requests fail over
+    /// to the mirrors, in order.
+    pub fn set_mirrors(&mut self, mirrors: Vec<String>) {
+        let mirrors: Vec<_> = mirrors
+            .iter()
+            .filter_map(|mirror| mirror.parse::<Url>().ok())
+            .map(|mirror| remote_index_url(&mirror).to_string())
+            .collect();
+        let url = &self.url;
+        for pack in &mut self.packs {
+            pack.mirror_urls = mirrors
+                .iter()
+                .filter_map(|mirror| mirror_url(url, mirror, &pack.url))
+                .collect();
+        }
+        self.mirrors = mirrors;
+    }
+
+    /// The URL of the .esi, then the ones of its mirrors.
+    fn urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.mirrors.iter().map(String::as_str))
+    }
+
     pub fn path(&self) -> Option<&Path> {
         self.path.as_deref()
     }
@@ -227,6 +290,7 @@ impl RemoteIndex {
                 url: absolute_url.as_str().to_owned(),
                 index_checksum,
                 pack_checksum,
+                mirror_urls: vec![],
             });
         }
 
@@ -235,9 +299,26 @@ impl RemoteIndex {
             meta,
             url,
             packs,
+            mirrors: vec![],
         })
     }
 
+    /// Writes the index in the .esi format, see [`RemoteIndex::read`].
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "meta\t{}", self.meta)?;
+        writeln!(writer, "url\t{}", self.url)?;
+        for pack in &self.packs {
+            writeln!(
+                writer,
+                "{}\t{}\t{}",
+                hex::encode(pack.index_checksum),
+                hex::encode(pack.pack_checksum),
+                pack.url
+            )?;
+        }
+        Ok(())
+    }
+
     fn read_keyed_line<R: BufRead>(lines: &mut io::Lines<R>, key: &str) -> Result<String, Error> {
         let line = match lines.next() {
             None => Err(RemoteIndexFormatError::new(format!(
@@ -296,6 +377,167 @@ pub fn load_remotes(base_path: &Path) -> Result<Vec<RemoteIndex>, Error> {
         .collect()
 }
 
+/// The URL of the copy of the file at `url` on the mirror of the remote, if
+/// the file is below the directory of the .esi at `index_url`.
+fn mirror_url(index_url: &str, mirror_index_url: &str, url: &str) -> Option<String> {
+    let base_url = index_url.parse::<Url>().ok()?.join("./").ok()?;
+    let mirror_base_url = mirror_index_url.parse::<Url>().ok()?.join("./").ok()?;
+    let path = url.strip_prefix(base_url.as_str())?;
+    Some(mirror_base_url.join(path).ok()?.to_string())
+}
+
+/// Calls `f` with each of the URLs (of a file, then of its copies on the
+/// mirrors) until it succeeds. Only the failed requests and downloads fail over
+/// to the next URL, the other errors (e.g. of the local files) are returned.
+fn with_failover<'a, T, F>(urls: impl IntoIterator<Item = &'a str>, mut f: F) -> Result<T, Error>
+where
+    F: FnMut(&Url) -> Result<T, Error>,
+{
+    let mut urls = urls.into_iter().peekable();
+    loop {
+        let url = urls.next().expect("There is at least one URL!");
+        let url = url
+            .parse::<Url>()
+            .map_err(|e| Error::HttpError(format!("{url}: {e}").into()))?;
+        match f(&url) {
+            Err(e @ (Error::HttpError(_) | Error::CorruptPack)) if urls.peek().is_some() => {
+                log::warn!(
+                    "Fetching {} failed ({}), trying the mirror {}...",
+                    url,
+                    e,
+                    urls.peek().unwrap()
+                );
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The credentials sent with the HTTP requests to a server (see
+/// [`Repository::set_http_auth`](super::Repository::set_http_auth)).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HttpAuth {
+    /// Only the requests to the URLs starting with the prefix (e.g.
+    /// `https://example.com/`) are authorized, or all of them if [`None`].
+    pub url_prefix: Option<String>,
+    /// Sent in the `Authorization: Bearer` header.
+    pub token: Option<String>,
+    /// The user and the password, sent in the `Authorization: Basic` header.
+    pub basic: Option<(String, String)>,
+    /// Additional headers (names and values), e.g. the API key of an artifact
+    /// server.
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpAuth {
+    fn applies_to(&self, url: &str) -> bool {
+        self.url_prefix
+            .as_ref()
+            .is_none_or(|prefix| url.starts_with(prefix.as_str()))
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        let authorization = match (&self.token, &self.basic) {
+            (Some(token), _) => Some(format!("Bearer {token}")),
+            (None, Some((user, password))) => Some(format!(
+                "Basic {}",
+                base64::encode(format!("{user}:{password}"))
+            )),
+            (None, None) => None,
+        };
+        authorization
+            .map(|authorization| ("Authorization".to_owned(), authorization))
+            .into_iter()
+            .chain(self.headers.iter().cloned())
+            .collect()
+    }
+}
+
+/// The headers authorizing a request to the URL: the ones of the [`HttpAuth`]s
+/// which apply to it, where the earlier ones take precedence.
+fn auth_headers(auth: &[HttpAuth], url: &str) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = vec![];
+    for (name, value) in auth
+        .iter()
+        .filter(|auth| auth.applies_to(url))
+        .flat_map(HttpAuth::headers)
+    {
+        if !headers.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+            headers.push((name, value));
+        }
+    }
+    headers
+}
+
+/// Sets the headers of the [`HttpAuth`]s on the requests, unless they are
+/// already set (e.g. the `Authorization` of the signed requests to object
+/// storage, or of a push).
+struct AuthMiddleware(Vec<HttpAuth>);
+
+impl ureq::Middleware for AuthMiddleware {
+    fn handle(
+        &self,
+        mut request: ureq::Request,
+        next: ureq::MiddlewareNext,
+    ) -> Result<ureq::Response, ureq::Error> {
+        for (name, value) in auth_headers(&self.0, request.url()) {
+            if request.header(&name).is_none() {
+                request = request.set(&name, &value);
+            }
+        }
+        next.handle(request)
+    }
+}
+
+/// Builds the agent of the requests to the remotes, which are authorized with
+/// the [`HttpAuth`]s.
+pub fn build_agent(auth: &[HttpAuth]) -> Agent {
+    let mut builder = ureq::AgentBuilder::new();
+    if !auth.is_empty() {
+        builder = builder.middleware(AuthMiddleware(auth.to_vec()));
+    }
+    builder.build()
+}
+
+/// Whether the URL is the one of an object storage bucket (`s3://`, `az://`
+/// or `gs://`), rather than of an HTTP server. A repository in a bucket has
+/// the layout used by `elfshaker serve`: the `index.esi` and the `packs/`
+/// directory.
+pub fn is_object_storage_url(url: &Url) -> bool {
+    object_storage::is_object_storage_url(url)
+}
+
+/// The URL of the .esi of the repository at the URL: for object storage (see
+/// [`is_object_storage_url`]), `s3://bucket/prefix` is the directory which
+/// contains the `index.esi`.
+pub fn remote_index_url(url: &Url) -> Url {
+    if !is_object_storage_url(url) || url.path().ends_with(".esi") {
+        return url.clone();
+    }
+    let mut url = url.clone();
+    url.set_path(&format!("{}/", url.path().trim_end_matches('/')));
+    url.join("index.esi").unwrap()
+}
+
+/// Starts a request to the URL with the headers. The requests to object
+/// storage are sent to the API of the service, with the credentials of the
+/// user (see the `object_storage` module).
+fn start_request(
+    agent: &Agent,
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+) -> Result<ureq::Request, Error> {
+    if is_object_storage_url(url) {
+        return object_storage::request(agent, method, url, headers);
+    }
+    let mut request = agent.request_url(method, url);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    Ok(request)
+}
+
 /// Sends an HTTP GET request with the specified URL.
 ///
 /// # Arguments
@@ -309,16 +551,18 @@ fn open_remote_resource(
     timeout: Option<Duration>,
     if_modified_since: Option<SystemTime>,
 ) -> Result<Option<(usize, impl Read)>, Error> {
-    let mut request = agent.get(url.as_ref());
+    let if_modified_since = if_modified_since.map(format_http_date);
+    let headers: Vec<_> = if_modified_since
+        .iter()
+        .map(|date| ("If-Modified-Since", date.as_str()))
+        .collect();
+    let mut request = start_request(agent, "GET", url, &headers)?;
     // Alternatively, we could have used Duration::MAX to indicate no timeout.
     // Unfortunately, the ureq crashes at some unwrap() somewhere then MAX is
     // provided.
     if let Some(timeout) = timeout {
         request = request.timeout(timeout);
     }
-    if let Some(if_modified_since) = if_modified_since {
-        request = request.set("If-Modified-Since", &format_http_date(if_modified_since));
-    }
 
     let response = request.call().map_err(|e| Error::HttpError(e.into()))?;
 
@@ -372,40 +616,468 @@ fn read_remote_resource(
 
 /// Updates the specified pack file by fetching the URL in the [`RemotePack`]
 /// only when necessary.
+///
+/// The pack is downloaded to a `.part` file next to it (see
+/// [`partial_pack_path`]), which is kept when the download is interrupted: the
+/// next download resumes from its end with an HTTP Range request. Once the
+/// checksum of the whole pack is verified, the `.part` file replaces the pack.
 pub fn update_remote_pack(
     agent: &Agent,
     remote_pack: &RemotePack,
     pack_path: &Path,
     reporter: &ProgressReporter,
 ) -> Result<(), Error> {
-    let date_modified = fs::metadata(pack_path).ok().and_then(|x| x.modified().ok());
+    let part_path = partial_pack_path(pack_path);
+    // The download interrupted by the failure of a server is resumed from
+    // the next mirror.
+    with_failover(remote_pack.urls(), |url| {
+        let resume_from = fs::metadata(&part_path).map_or(0, |m| m.len());
+        match download_pack(
+            agent,
+            url,
+            remote_pack,
+            pack_path,
+            &part_path,
+            resume_from,
+            reporter,
+        ) {
+            // The partial download can be of another version of the pack.
+            Err(Error::CorruptPack) if resume_from > 0 => {
+                log::warn!(
+                    "The resumed download of {} is corrupt, restarting it...",
+                    url
+                );
+                download_pack(agent, url, remote_pack, pack_path, &part_path, 0, reporter)
+            }
+            result => result,
+        }
+    })
+}
 
-    let url = remote_pack.url.parse::<Url>().unwrap();
-    if let Some((content_length, mut reader)) =
-        open_remote_resource(agent, &url, None, date_modified)?
-    {
-        let mut data = vec![];
+/// The path of the partial download of the pack.
+pub fn partial_pack_path(pack_path: &Path) -> PathBuf {
+    let mut path = pack_path.as_os_str().to_owned();
+    path.push(".part");
+    path.into()
+}
 
-        let mut writer = ProgressWriter::with_known_size(&mut data, reporter, content_length);
-        io::copy(&mut reader, &mut writer)?;
+/// Downloads the pack from the URL, from the `resume_from` offset of the
+/// partial download at `part_path`, if not 0.
+fn download_pack(
+    agent: &Agent,
+    url: &Url,
+    remote_pack: &RemotePack,
+    pack_path: &Path,
+    part_path: &Path,
+    resume_from: u64,
+    reporter: &ProgressReporter,
+) -> Result<(), Error> {
+    let if_modified_since = fs::metadata(pack_path)
+        .ok()
+        .and_then(|x| x.modified().ok())
+        .map(format_http_date);
+    let range = format!("bytes={resume_from}-");
+    let mut headers = vec![];
+    if let Some(if_modified_since) = &if_modified_since {
+        headers.push(("If-Modified-Since", if_modified_since.as_str()));
+    }
+    if resume_from > 0 {
+        log::info!("Resuming the download of {} from {} B", url, resume_from);
+        headers.push(("Range", range.as_str()));
+    }
 
-        let mut checksum = [0u8; 20];
-        let mut hasher = Sha1::new();
-        hasher.input(&data);
-        hasher.result(&mut checksum);
+    let response = match start_request(agent, "GET", url, &headers)?.call() {
+        Ok(response) => response,
+        // The partial download is as large as the pack (e.g. it was
+        // interrupted before replacing the pack), or larger.
+        Err(ureq::Error::Status(416, _)) if resume_from > 0 => {
+            return finish_download(
+                remote_pack,
+                pack_path,
+                part_path,
+                compute_checksum(part_path)?,
+            )
+        }
+        Err(e) => return Err(Error::HttpError(e.into())),
+    };
+    let content_length = response
+        .header("Content-Length")
+        .and_then(|length| length.parse::<usize>().ok());
+    log::info!(
+        "HTTP GET {} -> {} (Content-Length: {})",
+        url,
+        response.status(),
+        content_length.unwrap_or(0)
+    );
 
-        if checksum == remote_pack.pack_checksum {
-            create_file(pack_path, None)?.write_all(&data)?;
-        } else {
-            log::error!(
-                "The pack checksum did not match the one in the .esi! The download failed."
-            );
-            return Err(Error::CorruptPack);
+    let mut sha1 = Sha1::new();
+    let file = match response.status() {
+        HTTP_STATUS_NOT_MODIFIED => return Ok(()),
+        HTTP_STATUS_PARTIAL_CONTENT if resume_from > 0 => {
+            let expected_range = format!("bytes {resume_from}-");
+            if !response
+                .header("Content-Range")
+                .is_some_and(|h| h.starts_with(&expected_range))
+            {
+                return Err(Error::HttpError(
+                    format!("The server did not resume the download from {resume_from} B!").into(),
+                ));
+            }
+            let mut file = fs::OpenOptions::new()
+                .read(true)
+                .append(true)
+                .open(part_path)?;
+            hash_reader(&mut sha1, (&mut file).take(resume_from))?;
+            file
         }
+        // The server does not support range requests: start over.
+        HTTP_STATUS_OK => create_file(part_path, None)?,
+        status => {
+            return Err(Error::HttpError(
+                format!("Response status code {status} indicates failure!").into(),
+            ))
+        }
+    };
+
+    let file = io::BufWriter::new(file);
+    let mut writer = match content_length {
+        Some(content_length) => ProgressWriter::with_known_size(file, reporter, content_length),
+        None => ProgressWriter::new(file, reporter),
+    };
+    let mut reader = response.into_reader();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = reader
+            .read(&mut buf)
+            .map_err(|e| Error::HttpError(e.into()))?;
+        if len == 0 {
+            break;
+        }
+        sha1.input(&buf[..len]);
+        writer.write_all(&buf[..len])?;
     }
+    writer.flush()?;
+
+    let mut checksum = [0u8; 20];
+    sha1.result(&mut checksum);
+    finish_download(remote_pack, pack_path, part_path, checksum)
+}
+
+/// Replaces the pack with the partial download, if it is complete (its
+/// checksum matches the one in the .esi), otherwise deletes it.
+fn finish_download(
+    remote_pack: &RemotePack,
+    pack_path: &Path,
+    part_path: &Path,
+    checksum: FileChecksum,
+) -> Result<(), Error> {
+    if checksum == remote_pack.pack_checksum {
+        fs::rename(part_path, pack_path)?;
+        Ok(())
+    } else {
+        log::error!("The pack checksum did not match the one in the .esi! The download failed.");
+        fs::remove_file(part_path)?;
+        Err(Error::CorruptPack)
+    }
+}
+
+/// Fetches the byte ranges of the [`RemotePack`] with HTTP Range requests (one
+/// per range), calling `f` with the offset and the contents of each range.
+/// Returns `false`, before calling `f`, if the server does not support range
+/// requests (i.e. it responds with the whole file).
+///
+/// Only the whole pack can be verified against the checksum in the .esi, so the
+/// checksums of the objects read from the ranges should be verified instead.
+/// When a request fails, all the ranges are fetched again from the next
+/// mirror (if any).
+pub fn fetch_remote_pack_ranges<F>(
+    agent: &Agent,
+    remote_pack: &RemotePack,
+    ranges: &[Range<u64>],
+    reporter: &ProgressReporter,
+    mut f: F,
+) -> Result<bool, Error>
+where
+    F: FnMut(u64, &[u8]) -> Result<(), Error>,
+{
+    with_failover(remote_pack.urls(), |url| {
+        fetch_ranges(agent, url, ranges, reporter, &mut f)
+    })
+}
+
+fn fetch_ranges<F>(
+    agent: &Agent,
+    url: &Url,
+    ranges: &[Range<u64>],
+    reporter: &ProgressReporter,
+    f: &mut F,
+) -> Result<bool, Error>
+where
+    F: FnMut(u64, &[u8]) -> Result<(), Error>,
+{
+    let mut fetched_bytes = 0;
+    for (i, range) in ranges.iter().enumerate() {
+        let bytes = format!("bytes={}-{}", range.start, range.end - 1);
+        let response = start_request(agent, "GET", url, &[("Range", &bytes)])?
+            .call()
+            .map_err(|e| Error::HttpError(e.into()))?;
+        log::info!(
+            "HTTP GET {} (bytes {}-{}) -> {}",
+            url,
+            range.start,
+            range.end - 1,
+            response.status()
+        );
+        match response.status() {
+            HTTP_STATUS_PARTIAL_CONTENT => {}
+            HTTP_STATUS_OK if i == 0 => return Ok(false),
+            status => {
+                return Err(Error::HttpError(
+                    format!("Response status code {status} indicates failure!").into(),
+                ))
+            }
+        }
+        let expected_range = format!("bytes {}-{}/", range.start, range.end - 1);
+        if !response
+            .header("Content-Range")
+            .is_some_and(|h| h.starts_with(&expected_range))
+        {
+            return Err(Error::HttpError(
+                format!("The server did not return the {expected_range} range!").into(),
+            ));
+        }
+
+        let mut data = Vec::with_capacity((range.end - range.start) as usize);
+        response
+            .into_reader()
+            .take(range.end - range.start)
+            .read_to_end(&mut data)
+            .map_err(|e| Error::HttpError(e.into()))?;
+        if data.len() as u64 != range.end - range.start {
+            return Err(Error::HttpError(
+                format!("The {expected_range} range is incomplete!").into(),
+            ));
+        }
+        f(range.start, &data)?;
+
+        fetched_bytes += data.len() as u64;
+        reporter.checkpoint_with_bytes(i + 1, Some(ranges.len() - i - 1), fetched_bytes);
+    }
+    Ok(true)
+}
+
+/// Uploads the file to the URL with an HTTP PUT request, or with a multipart
+/// upload for the large files pushed to S3. The `token` (if any) is sent in
+/// the `Authorization: Bearer` header, except to object storage.
+pub fn upload_file(
+    agent: &Agent,
+    url: &Url,
+    path: &Path,
+    token: Option<&str>,
+) -> Result<(), Error> {
+    let file = open_file(path)?;
+    let content_length = file.metadata()?.len();
+    if object_storage::is_multipart_upload(url, content_length) {
+        object_storage::upload_multipart(agent, url, io::BufReader::new(file), content_length)?;
+        log::info!("HTTP multipart upload {} ({} B)", url, content_length);
+        return Ok(());
+    }
+
+    let mut request = start_request(
+        agent,
+        "PUT",
+        url,
+        &[
+            ("Content-Length", &content_length.to_string()),
+            ("Content-Type", "application/octet-stream"),
+        ],
+    )?;
+    // The requests to object storage are already authorized by their
+    // signature.
+    if let Some(token) = token.filter(|_| !is_object_storage_url(url)) {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+
+    let response = request
+        .send(io::BufReader::new(file))
+        .map_err(|e| Error::HttpError(e.into()))?;
+    log::info!(
+        "HTTP PUT {} ({} B) -> {}",
+        url,
+        content_length,
+        response.status()
+    );
     Ok(())
 }
 
+/// The URL of the entry of the build cache at `url` (see
+/// [`Repository::write_build_cache_entry`](super::Repository::write_build_cache_entry)).
+fn build_cache_entry_url(url: &Url, key: &str) -> Result<Url, Error> {
+    check_build_cache_key(key)?;
+    // Make sure the URL is treated as a directory when joining.
+    let url = if url.path().ends_with('/') {
+        url.join(key)
+    } else {
+        Url::parse(&format!("{url}/")).and_then(|url| url.join(key))
+    };
+    url.map_err(|e| Error::HttpError(e.into()))
+}
+
+/// Fetches the entry of the build cache at `url` (e.g. the `/cache/` of
+/// `elfshaker serve --cache`). Returns [`None`] if there is no such entry.
+pub fn fetch_build_cache_entry(
+    agent: &Agent,
+    url: &Url,
+    key: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    let url = build_cache_entry_url(url, key)?;
+    let response = match start_request(agent, "GET", &url, &[])?.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(HTTP_STATUS_NOT_FOUND, _)) => {
+            log::info!("HTTP GET {} -> {}", url, HTTP_STATUS_NOT_FOUND);
+            return Ok(None);
+        }
+        Err(e) => return Err(Error::HttpError(e.into())),
+    };
+    log::info!("HTTP GET {} -> {}", url, response.status());
+    let mut buf = vec![];
+    response
+        .into_reader()
+        .read_to_end(&mut buf)
+        .map_err(|e| Error::HttpError(e.into()))?;
+    Ok(Some(buf))
+}
+
+/// Uploads the entry to the build cache at `url`, sending the token (if any)
+/// in the `Authorization: Bearer` header.
+pub fn upload_build_cache_entry(
+    agent: &Agent,
+    url: &Url,
+    key: &str,
+    contents: &[u8],
+    token: Option<&str>,
+) -> Result<(), Error> {
+    let url = build_cache_entry_url(url, key)?;
+    let mut request = start_request(
+        agent,
+        "PUT",
+        &url,
+        &[
+            ("Content-Length", &contents.len().to_string()),
+            ("Content-Type", "application/octet-stream"),
+        ],
+    )?;
+    if let Some(token) = token.filter(|_| !is_object_storage_url(&url)) {
+        request = request.set("Authorization", &format!("Bearer {token}"));
+    }
+    let response = request
+        .send_bytes(contents)
+        .map_err(|e| Error::HttpError(e.into()))?;
+    log::info!(
+        "HTTP PUT {} ({} B) -> {}",
+        url,
+        contents.len(),
+        response.status()
+    );
+    Ok(())
+}
+
+/// Adds the pack to the .esi at `index_url` (replacing the pack with the same
+/// file name, if any), creating the .esi if it does not exist. The .esi is
+/// read, then written back only if it was not modified in between (see
+/// [`object_storage::precondition_headers`]), otherwise it is read again, so
+/// that the concurrent pushes don't lose any of the packs.
+pub fn add_pack_to_remote_index(
+    agent: &Agent,
+    index_url: &Url,
+    pack: RemotePack,
+) -> Result<(), Error> {
+    for attempt in 1..=MAX_REMOTE_INDEX_UPDATES {
+        if try_add_pack_to_remote_index(agent, index_url, &pack)? {
+            return Ok(());
+        }
+        log::warn!(
+            "{} was modified concurrently, retrying ({}/{})...",
+            index_url,
+            attempt,
+            MAX_REMOTE_INDEX_UPDATES
+        );
+        // The random delay keeps the concurrent pushes from colliding again.
+        let delay = rand::thread_rng().gen_range(50..200) * u64::from(attempt);
+        std::thread::sleep(Duration::from_millis(delay));
+    }
+    Err(Error::HttpError(
+        format!("{index_url} was modified concurrently {MAX_REMOTE_INDEX_UPDATES} times").into(),
+    ))
+}
+
+/// Adds the pack to the .esi (see [`add_pack_to_remote_index`]). Returns false
+/// if the .esi was modified between the GET and the PUT.
+fn try_add_pack_to_remote_index(
+    agent: &Agent,
+    index_url: &Url,
+    pack: &RemotePack,
+) -> Result<bool, Error> {
+    let response = start_request(agent, "GET", index_url, &[])?
+        .timeout(Duration::from_secs(15))
+        .call();
+    let (mut remote, preconditions) = match response {
+        Ok(response) => {
+            let preconditions = object_storage::precondition_headers(index_url, Some(&response));
+            let mut data = vec![];
+            response
+                .into_reader()
+                .read_to_end(&mut data)
+                .map_err(|e| Error::HttpError(e.into()))?;
+            let remote = RemoteIndex::read(BufReader::new(data.as_slice())).reify(index_url)?;
+            (remote, preconditions)
+        }
+        Err(ureq::Error::Status(HTTP_STATUS_NOT_FOUND, _)) => (
+            RemoteIndex::new(index_url.to_string()),
+            object_storage::precondition_headers(index_url, None),
+        ),
+        Err(e) => return Err(Error::HttpError(e.into())),
+    };
+
+    remote.packs.retain(|p| p.file_name() != pack.file_name());
+    remote.packs.push(pack.clone());
+    // Keep the URLs of the packs next to the .esi relative, so that the
+    // repository can be moved.
+    let base_url = index_url
+        .join("./")
+        .map_err(|e| Error::HttpError(e.into()))?;
+    for pack in &mut remote.packs {
+        if let Some(relative_url) = pack.url.strip_prefix(base_url.as_str()) {
+            pack.url = relative_url.to_owned();
+        }
+    }
+
+    let mut data = vec![];
+    remote.write(&mut data)?;
+    let content_length = data.len().to_string();
+    let mut headers = vec![
+        ("Content-Length", content_length.as_str()),
+        ("Content-Type", "text/plain"),
+    ];
+    headers.extend(
+        preconditions
+            .iter()
+            .map(|(name, value)| (*name, value.as_str())),
+    );
+    let status = match start_request(agent, "PUT", index_url, &headers)?.send_bytes(&data) {
+        Ok(response) => response.status(),
+        // S3 reports the conditional writes which conflict with another
+        // one in progress as 409 Conflict.
+        Err(ureq::Error::Status(
+            status @ (HTTP_STATUS_PRECONDITION_FAILED | HTTP_STATUS_CONFLICT),
+            _,
+        )) => status,
+        Err(e) => return Err(Error::HttpError(e.into())),
+    };
+    log::info!("HTTP PUT {} ({} B) -> {}", index_url, data.len(), status);
+    Ok(status != HTTP_STATUS_PRECONDITION_FAILED && status != HTTP_STATUS_CONFLICT)
+}
+
 /// Updates all pack index files by fetching the URLs in the [`RemoteIndex`]
 /// only when necessary.
 pub fn update_remote_pack_indexes(
@@ -418,15 +1090,16 @@ pub fn update_remote_pack_indexes(
     let mut remaining = remote.packs.len();
 
     for pack in &remote.packs {
-        let url = (pack.url.to_string() + ".idx").parse::<Url>().unwrap();
-        let pack_index_file_name = url.path_segments().unwrap().last().unwrap();
-        let pack_index_path = base_dir.join(pack_index_file_name);
+        let urls: Vec<_> = pack.urls().map(|url| url.to_owned() + ".idx").collect();
+        let pack_index_path = base_dir.join(pack.file_name().to_owned() + ".idx");
 
         if verify_checksum(&pack_index_path, &pack.index_checksum)? {
             // The file exists and the checksums match -> skip
             log::info!("{} is up to date", pack_index_path.display());
         } else {
-            update_pack_index(agent, &url, &pack_index_path)?;
+            with_failover(urls.iter().map(String::as_str), |url| {
+                update_pack_index(agent, url, &pack_index_path)
+            })?;
         }
 
         done += 1;
@@ -447,7 +1120,14 @@ fn update_pack_index(agent: &Agent, url: &Url, pack_index_path: &Path) -> Result
         read_remote_resource(agent, url, Duration::from_secs(15), date_modified)?;
 
     if let Some(pack_index_bytes) = pack_index_bytes {
-        if let Err(e) = PackIndex::parse(pack_index_bytes.as_slice()) {
+        // Encrypted indexes can't be checked without the key, they are
+        // authenticated once decrypted.
+        let result = if encryption::is_encrypted_index(&pack_index_bytes) {
+            Ok(())
+        } else {
+            PackIndex::parse(pack_index_bytes.as_slice()).map(|_| ())
+        };
+        if let Err(e) = result {
             log::error!(
                 "Failed to fetch {} from remote: The remote returned a broken .pack.idx! {}",
                 url,
@@ -459,16 +1139,71 @@ fn update_pack_index(agent: &Agent, url: &Url, pack_index_path: &Path) -> Result
                 pack_index_path.display(),
                 pack_index_bytes.len()
             );
-            fs::write(pack_index_path, pack_index_bytes.as_slice()).map_err(Error::IOError)?;
+            // The index is replaced rather than written in place, since flat
+            // indexes are mapped into memory (see `FlatIndex::map`).
+            replace_file(pack_index_path, |temp_path| {
+                fs::write(temp_path, pack_index_bytes.as_slice())
+            })
+            .map_err(Error::IOError)?;
         }
     }
     Ok(())
 }
 
-/// Fetches the remote index from the server.
-pub fn fetch_remote(agent: &Agent, url: &str, path: &Path) -> Result<RemoteIndex, Error> {
-    let url = url.parse::<Url>().unwrap();
-    let response = read_remote_resource(agent, &url, Duration::from_secs(15), None)?;
+/// Fetches the detached signature of the pack (see
+/// [`PackSignature`](super::PackSignature)), from the URL of the pack +
+/// `.sig`, to the file. Returns `false` if the pack is not signed (the
+/// signature is not found).
+pub fn fetch_pack_signature(
+    agent: &Agent,
+    remote_pack: &RemotePack,
+    path: &Path,
+) -> Result<bool, Error> {
+    let urls: Vec<_> = remote_pack
+        .urls()
+        .map(|url| url.to_owned() + ".sig")
+        .collect();
+    let data = match with_failover(urls.iter().map(String::as_str), |url| {
+        read_remote_resource(agent, url, Duration::from_secs(15), None)
+    }) {
+        Ok(data) => data
+            .expect("Unexpected Not-Modified response from server given an unconditional request"),
+        Err(Error::HttpError(e))
+            if matches!(
+                e.downcast_ref::<ureq::Error>(),
+                Some(ureq::Error::Status(404, _))
+            ) =>
+        {
+            return Ok(false)
+        }
+        Err(e) => return Err(e),
+    };
+    log::info!("Writing {} ({} B)...", path.display(), data.len());
+    fs::write(path, data)?;
+    Ok(true)
+}
+
+/// Fetches the remote index from the server, or else from its mirrors (the
+/// URLs of their .esi).
+pub fn fetch_remote(
+    agent: &Agent,
+    url: &str,
+    mirrors: &[String],
+    path: &Path,
+) -> Result<RemoteIndex, Error> {
+    let url = remote_index_url(&url.parse::<Url>().unwrap());
+    let mirror_urls: Vec<_> = mirrors
+        .iter()
+        .filter_map(|mirror| mirror.parse::<Url>().ok())
+        .map(|mirror| remote_index_url(&mirror).to_string())
+        .collect();
+    let (url, response) = with_failover(
+        std::iter::once(url.as_str()).chain(mirror_urls.iter().map(String::as_str)),
+        |url| {
+            read_remote_resource(agent, url, Duration::from_secs(15), None)
+                .map(|response| (url.clone(), response))
+        },
+    )?;
 
     match response {
         None => unreachable!(
@@ -476,6 +1211,7 @@ pub fn fetch_remote(agent: &Agent, url: &str, path: &Path) -> Result<RemoteIndex
         ),
         Some(data) => {
             let mut remote = RemoteIndex::read(BufReader::new(data.as_slice())).reify(url)?;
+            remote.set_mirrors(mirrors.to_vec());
             // Update the .esi
             create_file(path, None)
                 .map_err(Error::IOError)?
@@ -488,8 +1224,9 @@ pub fn fetch_remote(agent: &Agent, url: &str, path: &Path) -> Result<RemoteIndex
     }
 }
 
-/// Fetches the new newest version of the [`RemoteIndex`] from the server and
-/// overwrites its backing file only when the remote file is newer.
+/// Fetches the new newest version of the [`RemoteIndex`] from the server (or
+/// else from its mirrors) and overwrites its backing file only when the remote
+/// file is newer. The mirrors of the [`RemoteIndex`] are kept.
 pub fn update_remote(agent: &Agent, remote: &RemoteIndex) -> Result<RemoteIndex, Error> {
     let path = remote
         .path
@@ -498,29 +1235,33 @@ pub fn update_remote(agent: &Agent, remote: &RemoteIndex) -> Result<RemoteIndex,
 
     // Read the modification date of the .esi.
     let date_modified = fs::metadata(path).ok().and_then(|x| x.modified().ok());
-    let url = remote.url.parse::<Url>().unwrap();
-    let response = read_remote_resource(agent, &url, Duration::from_secs(15), date_modified)?;
+    let (url, response) = with_failover(remote.urls(), |url| {
+        read_remote_resource(agent, url, Duration::from_secs(15), date_modified)
+            .map(|response| (url.clone(), response))
+    })?;
 
-    match response {
+    let mut updated = match response {
         // The local version is up-to-date.
-        None => RemoteIndex::load(path).reify(path.display()),
+        None => RemoteIndex::load(path).reify(path.display())?,
         Some(data) => {
-            let mut remote = RemoteIndex::read(BufReader::new(data.as_slice())).reify(url)?;
+            let mut updated = RemoteIndex::read(BufReader::new(data.as_slice())).reify(url)?;
             // Update the .esi
             create_file(path, None)
                 .map_err(Error::IOError)?
                 .write_all(data.as_slice())
                 .map_err(Error::IOError)?;
-            // And return the parsed index
-            remote.path = Some(path.clone());
-            Ok(remote)
+            updated.path = Some(path.clone());
+            updated
         }
-    }
+    };
+    // And return the parsed index
+    updated.set_mirrors(remote.mirrors.clone());
+    Ok(updated)
 }
 
 /// A convenience function which verifies the checksum of the file
 /// and coerces ENOENT to false.
-fn verify_checksum(path: &Path, checksum: &ObjectChecksum) -> io::Result<bool> {
+fn verify_checksum(path: &Path, checksum: &FileChecksum) -> io::Result<bool> {
     match compute_checksum(path) {
         Ok(actual_checksum) => Ok(actual_checksum == *checksum),
         Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
@@ -528,9 +1269,20 @@ fn verify_checksum(path: &Path, checksum: &ObjectChecksum) -> io::Result<bool> {
     }
 }
 
-fn compute_checksum(path: &Path) -> io::Result<ObjectChecksum> {
-    let mut reader = io::BufReader::new(fs::File::open(path)?);
+/// Computes the SHA-1 checksum of the contents of the file.
+pub fn compute_checksum(path: &Path) -> io::Result<FileChecksum> {
     let mut sha1 = Sha1::new();
+    hash_reader(&mut sha1, fs::File::open(path)?)?;
+
+    let mut checksum = [0u8; 20];
+    sha1.result(&mut checksum);
+
+    Ok(checksum)
+}
+
+/// Feeds the contents of the reader to the hasher.
+fn hash_reader<R: Read>(sha1: &mut Sha1, reader: R) -> io::Result<()> {
+    let mut reader = io::BufReader::new(reader);
     loop {
         let buf = reader.fill_buf()?;
         let len = buf.len();
@@ -540,11 +1292,7 @@ fn compute_checksum(path: &Path) -> io::Result<ObjectChecksum> {
         sha1.input(buf);
         reader.consume(len);
     }
-
-    let mut checksum = [0u8; 20];
-    sha1.result(&mut checksum);
-
-    Ok(checksum)
+    Ok(())
 }
 
 /// Formats a [`SystemTime`] as an HTTP date string. HTTP dates are always in
@@ -552,7 +1300,7 @@ fn compute_checksum(path: &Path) -> io::Result<ObjectChecksum> {
 ///
 /// HTTP date format: `<day-name>, <day> <month> <year> <hour>:<minute>:<second>
 /// GMT`
-fn format_http_date(t: SystemTime) -> String {
+pub fn format_http_date(t: SystemTime) -> String {
     let datetime: DateTime<Utc> = t.into();
     format!("{}", datetime.format("%a, %d %h %Y %H:%M:%S GMT"))
 }
@@ -633,6 +1381,192 @@ url\thttps://github.com/elfshaker/releases/download/index.esi"
         Ok(())
     }
 
+    #[test]
+    fn test_add_pack_to_remote_index_retries() -> Result<(), Error> {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let index_url: Url = format!("http://{}/index.esi", server.server_addr().to_ip().unwrap())
+            .parse()
+            .unwrap();
+        let pack = |name: &str| RemotePack {
+            index_checksum: [0x12; 20],
+            pack_checksum: [0x34; 20],
+            url: format!("packs/{name}.pack"),
+            mirror_urls: vec![],
+        };
+        let mut concurrent = RemoteIndex::new(index_url.to_string());
+        concurrent.push_pack(pack("B"));
+        let mut concurrent_data = vec![];
+        concurrent.write(&mut concurrent_data)?;
+
+        let thread = std::thread::spawn(move || {
+            // The .esi (and its ETag), and the method and precondition of each
+            // request.
+            let mut index: Option<(Vec<u8>, &str)> = None;
+            let mut requests = vec![];
+            loop {
+                let mut request = server.recv().unwrap();
+                let precondition = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("If-Match") || h.field.equiv("If-None-Match"))
+                    .map(|h| format!("{}: {}", h.field, h.value));
+                requests.push((request.method().to_string(), precondition));
+                match (request.method(), &index) {
+                    (tiny_http::Method::Get, None) => request
+                        .respond(tiny_http::Response::empty(HTTP_STATUS_NOT_FOUND))
+                        .unwrap(),
+                    (tiny_http::Method::Get, Some((data, etag))) => {
+                        let etag = tiny_http::Header::from_bytes("ETag", *etag).unwrap();
+                        request
+                            .respond(tiny_http::Response::from_data(data.clone()).with_header(etag))
+                            .unwrap()
+                    }
+                    // Another push creates the .esi first.
+                    (_, None) => {
+                        index = Some((concurrent_data.clone(), "\"1\""));
+                        request
+                            .respond(tiny_http::Response::empty(HTTP_STATUS_PRECONDITION_FAILED))
+                            .unwrap();
+                    }
+                    (_, Some(_)) => {
+                        let mut data = vec![];
+                        request.as_reader().read_to_end(&mut data).unwrap();
+                        request.respond(tiny_http::Response::empty(200)).unwrap();
+                        return (data, requests);
+                    }
+                }
+            }
+        });
+
+        add_pack_to_remote_index(&build_agent(&[]), &index_url, pack("A"))?;
+        let (data, requests) = thread.join().unwrap();
+        assert_eq!(
+            vec![
+                ("GET".to_owned(), None),
+                ("PUT".to_owned(), Some("If-None-Match: *".to_owned())),
+                ("GET".to_owned(), None),
+                ("PUT".to_owned(), Some("If-Match: \"1\"".to_owned())),
+            ],
+            requests
+        );
+        let index = RemoteIndex::read(BufReader::new(data.as_slice()))?;
+        let packs: Vec<_> = index.packs().iter().map(|p| p.file_name()).collect();
+        assert_eq!(vec!["B.pack", "A.pack"], packs);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_index_write_works() -> Result<(), Error> {
+        let mut r = RemoteIndex::new("http://localhost:8080/index.esi".to_owned());
+        r.push_pack(RemotePack {
+            index_checksum: [0x12; 20],
+            pack_checksum: [0x34; 20],
+            url: "packs/A.pack".to_owned(),
+            mirror_urls: vec![],
+        });
+        let mut buf = vec![];
+        r.write(&mut buf)?;
+
+        let r = RemoteIndex::read(BufReader::new(buf.as_slice()))?;
+        assert_eq!(r.meta, "v1");
+        assert_eq!(r.url, "http://localhost:8080/index.esi");
+        assert_eq!(r.packs.len(), 1);
+        assert_eq!(r.packs[0].url, "http://localhost:8080/packs/A.pack");
+        assert_eq!(r.packs[0].index_checksum, [0x12; 20]);
+        assert_eq!(r.packs[0].pack_checksum, [0x34; 20]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_index_url_works() {
+        let index_url = |url: &str| remote_index_url(&url.parse().unwrap()).to_string();
+        assert_eq!("s3://bucket/repo/index.esi", index_url("s3://bucket/repo"));
+        assert_eq!("s3://bucket/repo/index.esi", index_url("s3://bucket/repo/"));
+        assert_eq!("s3://bucket/index.esi", index_url("s3://bucket"));
+        assert_eq!("s3://bucket/a.esi", index_url("s3://bucket/a.esi"));
+        assert_eq!(
+            "az://account/container/repo/index.esi",
+            index_url("az://account/container/repo")
+        );
+        assert_eq!("gs://bucket/index.esi", index_url("gs://bucket/"));
+        assert_eq!(
+            "https://example.com/repo",
+            index_url("https://example.com/repo")
+        );
+    }
+
+    #[test]
+    fn test_remote_index_set_mirrors_works() -> Result<(), Error> {
+        let mut r = RemoteIndex::read(BufReader::new(
+            "\
+meta\tv1
+url\thttps://example.com/llvm/index.esi
+90765d432f15eda9b42e0ed747ceaa9b5f8237de\t3fc6c1b427b19217cdd9c4eecf0c74943fa4adb2\tpacks/A.pack
+f3a50129b7ac872b63585f884f1a73e013d51f85\td8a41c1859d6276f0dd74fdd7e4513d89f68600f\thttps://cdn.example.com/B.pack"
+                .as_bytes(),
+        ))?;
+        r.set_mirrors(vec![
+            "https://mirror.example.com/elfshaker/llvm.esi".to_owned(),
+            "s3://bucket/llvm".to_owned(),
+        ]);
+
+        assert_eq!(
+            vec![
+                "https://example.com/llvm/index.esi",
+                "https://mirror.example.com/elfshaker/llvm.esi",
+                "s3://bucket/llvm/index.esi"
+            ],
+            r.urls().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                "https://example.com/llvm/packs/A.pack",
+                "https://mirror.example.com/elfshaker/packs/A.pack",
+                "s3://bucket/llvm/packs/A.pack"
+            ],
+            r.packs[0].urls().collect::<Vec<_>>()
+        );
+        // Only the packs next to the .esi are mirrored.
+        assert!(r.packs[1].mirror_urls.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_auth_headers_works() {
+        let auth = [
+            HttpAuth {
+                url_prefix: Some("https://a.example.com/".to_owned()),
+                token: Some("t0k3n".to_owned()),
+                headers: vec![("X-Api-Key".to_owned(), "k3y".to_owned())],
+                ..Default::default()
+            },
+            HttpAuth {
+                basic: Some(("user".to_owned(), "pa:ss".to_owned())),
+                headers: vec![("x-api-key".to_owned(), "other".to_owned())],
+                ..Default::default()
+            },
+        ];
+        let header = |name: &str, value: &str| (name.to_owned(), value.to_owned());
+        assert_eq!(
+            vec![
+                header("Authorization", "Bearer t0k3n"),
+                header("X-Api-Key", "k3y")
+            ],
+            auth_headers(&auth, "https://a.example.com/index.esi")
+        );
+        assert_eq!(
+            vec![
+                header("Authorization", "Basic dXNlcjpwYTpzcw=="),
+                header("x-api-key", "other")
+            ],
+            auth_headers(&auth, "https://b.example.com/index.esi")
+        );
+        assert!(auth_headers(&[], "https://a.example.com/").is_empty());
+    }
+
     #[test]
     fn test_remote_index_bad_meta_read_fails() {
         let _no_meta = RemoteIndex::read(BufReader::new(
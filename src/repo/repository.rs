@@ -4,11 +4,11 @@
 use super::{constants::*, pack::IdError};
 
 use std::{
-    collections::{HashMap, HashSet},
-    fs::{self, File, Permissions},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{self, File},
     io,
-    io::{Read, Write},
-    os::unix::prelude::PermissionsExt,
+    io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     str::FromStr,
     sync::atomic::{AtomicBool, Ordering},
@@ -16,22 +16,54 @@ use std::{
     time::SystemTime,
 };
 
-use crypto::digest::Digest;
-use crypto::sha1::Sha1;
+use chrono::Utc;
 use fs2::FileExt;
-use log::{error, info, warn};
+use glob::{MatchOptions, Pattern};
+use log::{debug, error, info, trace, warn};
 use walkdir::WalkDir;
 
-use super::algo::run_in_parallel;
+use super::algo::{partition_by_u64, run_in_parallel, split_by_u64};
+use super::bloom::BloomFilter;
+use super::cache::StatCache;
+use super::case::{find_case_collisions, rename_case_collisions, CaseCollisions};
+use super::chunk::{chunk_boundaries, MIN_CHUNKED_OBJECT_SIZE};
 use super::constants::REPO_DIR;
+use super::dictionary::{
+    compressed_sizes, has_enough_samples, is_small_group, select_samples, split_small_objects,
+    train_dictionary,
+};
+use super::encryption::{self, EncryptionKey};
 use super::error::Error;
 use super::fs::{
-    create_file, create_temp_path, ensure_dir, get_last_modified, open_file, write_file_atomic,
-    EmptyDirectoryCleanupQueue,
+    copy_file_sparse, create_entry_dir, create_entry_parents, create_file, create_hidden_temp_path,
+    create_symlink, create_temp_path, ensure_dir, extended_length_path, file_mode,
+    get_last_modified, hard_link_file, join_entry_path, modified_time, open_file, push_entry_path,
+    read_sparse, read_xattrs, reflink_file, replace_file, set_file_mode, set_modified_time,
+    sync_dir, to_entry_path, write_file_atomic, write_file_atomic_limited,
+    write_sparse_file_atomic, write_xattrs, EmptyDirectoryCleanupQueue,
+};
+use super::index_cache::{IndexCache, IndexKey};
+use super::modified::{backup_path, ModifiedFiles};
+use super::object_index::{ObjectIndex, ObjectLocation};
+use super::pack::{
+    check_entry_checksum, frames_containing, is_valid_ref_name, verify_object, Pack, PackId,
+    SnapshotId,
+};
+use super::remote::{self, HttpAuth};
+use super::signature::{PackSignature, PublicKey, SigningKey, TrustPolicy};
+use super::similarity::{signature, Signature};
+use super::stream::SnapshotStream;
+use super::worktree::{common_data_dir, link_data_dir, read_worktrees, write_worktrees};
+use crate::checksum::ChecksumAlgorithm;
+use crate::codec::Codec;
+use crate::delta::{encode_delta, MAX_DELTA_RATIO};
+use crate::entrypool::Handle;
+use crate::frame::{write_skippable_frame, PackFrame, PackHeader};
+use crate::log::Span;
+use crate::packidx::{
+    os_str_as_bytes, CompressionParameters, FileEntry, FileMetadata, FlatIndex, FrameBoundary,
+    IndexFormat, ObjectChecksum, ObjectChunk, PackError, PackIndex, SnapshotMetadata,
 };
-use super::pack::{write_skippable_frame, Pack, PackFrame, PackHeader, PackId, SnapshotId};
-use super::remote;
-use crate::packidx::{FileEntry, FileMetadata, ObjectChecksum, PackError, PackIndex};
 use crate::progress::ProgressReporter;
 use crate::{
     batch,
@@ -49,6 +81,26 @@ pub struct ExtractOptions {
     force: bool,
     /// Number of decompression threads (this is an upper-limit).
     num_workers: u32,
+    /// Toggle extraction through the object cache (see [`CACHE_DIR`]).
+    reflink: bool,
+    /// Toggle extraction as hard links into the object cache.
+    link: bool,
+    /// Toggle restoring the recorded modification times of the files.
+    preserve_times: bool,
+    /// Toggle restoring the recorded extended attributes of the files.
+    xattrs: bool,
+    /// How the paths which differ only by case are handled.
+    case_collisions: CaseCollisions,
+    /// How the files of HEAD modified in the working directory are handled.
+    modified_files: ModifiedFiles,
+    /// The patterns of the paths to extract (all of them if empty).
+    paths: Vec<Pattern>,
+    /// The patterns of the paths not to extract.
+    excludes: Vec<Pattern>,
+    /// Toggle updating HEAD after the extraction.
+    update_head: bool,
+    /// Toggle extracting all the files to a staging directory first.
+    stage: bool,
 }
 
 impl ExtractOptions {
@@ -84,6 +136,108 @@ impl ExtractOptions {
     pub fn set_num_workers(&mut self, value: u32) {
         self.num_workers = value;
     }
+    /// Toggle extraction through the object cache (see [`CACHE_DIR`]): each
+    /// object is decompressed once into the cache, then reflinked to the
+    /// extracted files (or copied, if the filesystem does not support it).
+    pub fn reflink(&self) -> bool {
+        self.reflink
+    }
+    /// Toggle extraction through the object cache (see [`CACHE_DIR`]).
+    pub fn set_reflink(&mut self, value: bool) {
+        self.reflink = value;
+    }
+    /// Toggle extraction as hard links into the object cache (see
+    /// [`CACHE_DIR`]), which must be on the same filesystem. The files are
+    /// read-only, since modifying them would modify the cached objects (and
+    /// the files linked to them in other directories).
+    pub fn link(&self) -> bool {
+        self.link
+    }
+    /// Toggle extraction as hard links into the object cache.
+    pub fn set_link(&mut self, value: bool) {
+        self.link = value;
+    }
+    /// Toggle restoring the modification times of the files, if they were
+    /// recorded (see [`StoreOptions::record_times`]). Otherwise, the files
+    /// are modified at the time of the extraction.
+    pub fn preserve_times(&self) -> bool {
+        self.preserve_times
+    }
+    /// Toggle restoring the recorded modification times of the files.
+    pub fn set_preserve_times(&mut self, value: bool) {
+        self.preserve_times = value;
+    }
+    /// Toggle restoring the extended attributes of the files, if they were
+    /// recorded (see [`StoreOptions::xattrs`]).
+    pub fn xattrs(&self) -> bool {
+        self.xattrs
+    }
+    /// Toggle restoring the recorded extended attributes of the files.
+    pub fn set_xattrs(&mut self, value: bool) {
+        self.xattrs = value;
+    }
+    /// How the paths of the snapshot which differ only by case are handled.
+    /// They are checked before any file is modified.
+    pub fn case_collisions(&self) -> CaseCollisions {
+        self.case_collisions
+    }
+    /// How the paths which differ only by case are handled.
+    pub fn set_case_collisions(&mut self, value: CaseCollisions) {
+        self.case_collisions = value;
+    }
+    /// How the files of HEAD which were modified since they were extracted
+    /// are handled, when they would be updated or removed. They are checked
+    /// before any file is modified, unless [`ExtractOptions::force`] is set.
+    pub fn modified_files(&self) -> ModifiedFiles {
+        self.modified_files
+    }
+    /// How the files of HEAD modified since they were extracted are handled.
+    pub fn set_modified_files(&mut self, value: ModifiedFiles) {
+        self.modified_files = value;
+    }
+    /// The glob patterns of the paths to extract (see [`Pattern`]), which
+    /// match the paths of the files, or of their parent directories. `*` does
+    /// not match `/`, but `**` does. If there are any, only the matching files
+    /// are updated, and HEAD is left as it is, since the other files are not.
+    pub fn paths(&self) -> &[Pattern] {
+        &self.paths
+    }
+    /// The glob patterns of the paths to extract.
+    pub fn set_paths(&mut self, value: Vec<Pattern>) {
+        self.paths = value;
+    }
+    /// The glob patterns of the paths not to extract, which match like the
+    /// ones of [`ExtractOptions::paths`], so that a pattern matching a
+    /// directory excludes all of its files. They take precedence over the
+    /// paths to extract and, like them, prevent HEAD from being updated.
+    pub fn excludes(&self) -> &[Pattern] {
+        &self.excludes
+    }
+    /// The glob patterns of the paths not to extract.
+    pub fn set_excludes(&mut self, value: Vec<Pattern>) {
+        self.excludes = value;
+    }
+    /// Toggle updating HEAD after the extraction. Without it, the next
+    /// extraction to the same directory must be done with `reset`, since the
+    /// files extracted are unknown.
+    pub fn update_head(&self) -> bool {
+        self.update_head
+    }
+    /// Toggle updating HEAD after the extraction.
+    pub fn set_update_head(&mut self, value: bool) {
+        self.update_head = value;
+    }
+    /// Toggle extracting (and verifying) all the files to a staging directory
+    /// in the destination first, then moving them into place. The directory is
+    /// only modified once all the files are available, which takes twice as
+    /// much space for the files which are replaced.
+    pub fn stage(&self) -> bool {
+        self.stage
+    }
+    /// Toggle extracting all the files to a staging directory first.
+    pub fn set_stage(&mut self, value: bool) {
+        self.stage = value;
+    }
 }
 
 impl Default for ExtractOptions {
@@ -97,10 +251,62 @@ impl Default for ExtractOptions {
             force: false,
             // Default to single-thread decompression.
             num_workers: 1,
+            reflink: false,
+            link: false,
+            preserve_times: false,
+            xattrs: false,
+            case_collisions: CaseCollisions::default(),
+            modified_files: ModifiedFiles::default(),
+            paths: vec![],
+            excludes: vec![],
+            update_head: true,
+            stage: false,
         }
     }
 }
 
+/// A struct specifying the options used when creating a snapshot.
+#[derive(Clone, Debug, Default)]
+pub struct StoreOptions {
+    /// Toggle recording the modification times of the files.
+    record_times: bool,
+    /// Toggle recording the extended attributes of the files.
+    xattrs: bool,
+    /// Toggle reading all the files, instead of reusing the cached checksums.
+    ignore_cache: bool,
+}
+
+impl StoreOptions {
+    /// Toggle recording the modification times of the files, which can be
+    /// restored on extraction (see [`ExtractOptions::preserve_times`]).
+    pub fn record_times(&self) -> bool {
+        self.record_times
+    }
+    /// Toggle recording the modification times of the files.
+    pub fn set_record_times(&mut self, value: bool) {
+        self.record_times = value;
+    }
+    /// Toggle recording the extended attributes of the files, which can be
+    /// restored on extraction (see [`ExtractOptions::xattrs`]).
+    pub fn xattrs(&self) -> bool {
+        self.xattrs
+    }
+    /// Toggle recording the extended attributes of the files.
+    pub fn set_xattrs(&mut self, value: bool) {
+        self.xattrs = value;
+    }
+    /// Toggle reading all the files, instead of reusing the checksums of the
+    /// files whose size, times and inode did not change since the last
+    /// snapshot of the working directory.
+    pub fn ignore_cache(&self) -> bool {
+        self.ignore_cache
+    }
+    /// Toggle reading all the files, instead of reusing the cached checksums.
+    pub fn set_ignore_cache(&mut self, value: bool) {
+        self.ignore_cache = value;
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PackDiskStats {
     pub len: u64,
@@ -114,10 +320,124 @@ pub struct ObjectDiskStats {
 /// A struct specifying the the packing options.
 #[derive(Clone, Debug)]
 pub struct PackOptions {
+    /// The codec to compress the frames with. The compression level and window
+    /// log are interpreted as described in [`batch::CompressionOptions`].
+    pub codec: Codec,
     pub compression_window_log: u32,
     pub compression_level: i32,
     pub num_workers: u32,
     pub num_frames: u32,
+    /// The maximum decompressed size of each frame, if any. Only the frames
+    /// containing the requested objects are decompressed when reading a pack,
+    /// so smaller frames speed up extracting a few files, at the cost of
+    /// poorer compression.
+    pub max_frame_size: Option<u64>,
+    /// Store objects as deltas against a previous version of the same path,
+    /// when the delta is much smaller than the object. Shrinks packs of many
+    /// snapshots of slightly different files, at the cost of slower packing.
+    pub delta: bool,
+    /// Split large objects into content-defined chunks, which are stored once
+    /// even if they are part of several objects. Shrinks packs of large files
+    /// which change little between snapshots, even if the versions are too far
+    /// apart to be compressed together.
+    pub chunk: bool,
+    /// The size of the Zstandard dictionary to train on a sample of the small
+    /// objects and to compress the frames with, if any. The small objects are
+    /// then compressed in frames of their own. Shrinks packs of many small
+    /// files sharing content, especially when the frames are small. Skipped
+    /// when there are too few small objects, or when the dictionary does not
+    /// shrink them. Only supported by [`Codec::Zstd`].
+    pub dictionary_size: Option<usize>,
+    /// Sort the objects by the similarity of their contents (a MinHash of
+    /// their contents), rather than by size, so that the similar objects are
+    /// compressed together, even if their sizes differ. Makes packing slower,
+    /// since the objects are read twice.
+    pub similarity: bool,
+    /// Skip the objects already stored in the other packs of the repository
+    /// (found with the index of the objects of all the packs), which the pack
+    /// index references instead (see [`ObjectMetadata::pack`]). The referenced
+    /// packs cannot be deleted, renamed or upgraded while they are referenced.
+    pub dedup: bool,
+    /// The key to encrypt the frames of the pack with, if any (see
+    /// [`EncryptionKey`]). The packs can then be hosted on untrusted storage,
+    /// and are only readable with the key (see
+    /// [`Repository::set_encryption_keys`]).
+    pub encryption_key: Option<EncryptionKey>,
+    /// Encrypt the pack index too (with [`PackOptions::encryption_key`]), so
+    /// that the paths and the checksums of the files are not readable either.
+    pub encrypt_index: bool,
+    /// The format to write the pack index in. Indexes in the
+    /// [`IndexFormat::Flat`] format are queried in place (see
+    /// [`Repository::load_flat_index`]), which speeds up listing and
+    /// extracting the snapshots of packs containing many files, but they are
+    /// only readable by the versions of elfshaker supporting them.
+    pub index_format: IndexFormat,
+}
+
+/// The index of a pack whose snapshots are looked up (see
+/// [`Repository::load_snapshot_index`]).
+enum SnapshotIndex {
+    Full(Arc<PackIndex>),
+    Flat(Box<FlatIndex>),
+}
+
+impl SnapshotIndex {
+    /// The files of the snapshot, or [`None`] if no such snapshot exists.
+    fn snapshot_entries(&self, tag: &str) -> Result<Option<Vec<FileEntry>>, PackError> {
+        match self {
+            SnapshotIndex::Full(index) => index
+                .resolve_snapshot(tag)
+                .map(|handles| index.entries_from_handles(handles.iter()))
+                .transpose(),
+            SnapshotIndex::Flat(index) => index.snapshot_entries(tag),
+        }
+    }
+}
+
+/// The objects stored as deltas in a pack being created (see
+/// [`Repository::compute_pack_deltas`]). The temporary files containing the
+/// deltas are removed on drop.
+#[derive(Default)]
+struct PackDeltas {
+    /// Maps the objects stored as deltas to their base and the size of their
+    /// delta.
+    deltas: HashMap<Handle, (Handle, u64)>,
+    /// The files containing the deltas.
+    files: HashMap<Handle, PathBuf>,
+}
+
+impl Drop for PackDeltas {
+    fn drop(&mut self) {
+        for path in self.files.values() {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// The objects stored as chunks in a pack being created (see
+/// [`Repository::compute_pack_chunks`]).
+#[derive(Default)]
+struct PackChunks {
+    /// Maps the objects stored as chunks to their chunks. The chunk offsets are
+    /// relative to the start of the chunks (stored after the other objects).
+    objects: HashMap<Handle, Vec<ObjectChunk>>,
+    /// The distinct chunks, in the order in which they are stored.
+    sources: Vec<ChunkSource>,
+}
+
+/// The location of a chunk in the (loose) object it was first found in.
+struct ChunkSource {
+    object: Handle,
+    offset: u64,
+    size: u64,
+}
+
+/// The contents of a frame of a pack being created.
+enum FrameContents<'a> {
+    Objects(&'a [Vec<Handle>]),
+    Chunks(&'a [ChunkSource]),
 }
 
 #[derive(Clone, Debug)]
@@ -125,7 +445,34 @@ pub struct ExtractResult {
     pub modified_file_count: u32,
     pub added_file_count: u32,
     pub removed_file_count: u32,
+    /// The modified files which were left as they are (see
+    /// [`ModifiedFiles::Keep`]).
+    pub kept_paths: Vec<OsString>,
+    /// The modified files which were moved, and the paths they were moved to
+    /// (see [`ModifiedFiles::Backup`]).
+    pub backup_paths: Vec<(OsString, OsString)>,
+}
+/// The differences between two snapshots, as computed by [`Repository::diff_snapshots`].
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotDiff {
+    /// Files which are only present in the second snapshot.
+    pub added: Vec<FileEntry>,
+    /// Files which are only present in the first snapshot.
+    pub removed: Vec<FileEntry>,
+    /// Pairs of (old, new) entries with the same path, but different contents or mode.
+    pub modified: Vec<(FileEntry, FileEntry)>,
+}
+
+/// A problem found when verifying the integrity of a pack, as reported by
+/// [`Repository::verify_pack`].
+#[derive(Clone, Debug)]
+pub enum VerifyIssue {
+    /// The contents of the object do not match its checksum (expected, actual).
+    CorruptObject(ObjectChecksum, ObjectChecksum),
+    /// The object is referenced by the pack index, but is not available.
+    MissingObject(ObjectChecksum),
 }
+
 /// Contains methods for interfacing with elfshaker repositories, including
 /// methods to create snapshots and pack files, and to extract files from them.
 pub struct Repository {
@@ -133,6 +480,10 @@ pub struct Repository {
     path: PathBuf,
     /// The path for the elfshaker repository.
     data_dir: PathBuf,
+    /// The data directory of the linked working tree the repository was
+    /// opened in, which contains its HEAD, if any (see
+    /// [`Repository::add_worktree`]).
+    worktree_dir: Option<PathBuf>,
     /// Since there might be multiple long running sub-tasks invoked in each
     /// macro tasks (e.g. extract snapshot includes fetching the .esi,
     /// fetching individual pack, etc.), it is useful to use a "factory",
@@ -142,6 +493,25 @@ pub struct Repository {
     /// when the repository instance is created/destroyed.
     lock_file: fs::File,
     is_locked_exclusively: AtomicBool,
+    /// The file locked exclusively by the operations modifying the repository
+    /// or the working directory (see [`WRITE_LOCK_FILE`]).
+    write_lock_file: fs::File,
+    is_locked_for_writing: AtomicBool,
+    /// The credentials of the HTTP requests to the remotes.
+    http_auth: Vec<HttpAuth>,
+    /// The URLs of the .esi of the mirrors of the remotes, by name.
+    remote_mirrors: HashMap<String, Vec<String>>,
+    /// The keys trusted to sign the packs of all the remotes.
+    trusted_keys: Vec<PublicKey>,
+    /// The trust policies of the remotes, by name.
+    trust_policies: HashMap<String, TrustPolicy>,
+    /// The keys to decrypt the encrypted packs with.
+    encryption_keys: Vec<EncryptionKey>,
+    /// The parsed pack indexes (see [`Repository::load_index_cached`]).
+    index_cache: IndexCache,
+    /// Whether the objects of the packs are looked up in the object index
+    /// (see [`Repository::set_object_index`]).
+    is_object_index_enabled: bool,
 }
 
 impl Repository {
@@ -171,8 +541,8 @@ impl Repository {
         P1: AsRef<Path>,
         P2: AsRef<Path>,
     {
-        let path = path.as_ref().to_path_buf();
-        let data_dir = data_dir.as_ref().canonicalize()?;
+        let path = extended_length_path(path.as_ref())?;
+        let data_dir = extended_length_path(&data_dir.as_ref().canonicalize()?)?;
 
         if !Path::exists(&data_dir) {
             error!(
@@ -182,22 +552,48 @@ impl Repository {
             return Err(Error::RepositoryNotFound);
         }
 
+        // The data directory of a linked working tree only contains its HEAD
+        // (and the files next to it), the packs are in the shared one.
+        let (data_dir, worktree_dir) = match common_data_dir(&data_dir)? {
+            common_dir if common_dir == data_dir => (data_dir, None),
+            common_dir if !common_dir.exists() => {
+                error!(
+                    "The repository {:?} of the working tree no longer exists!",
+                    common_dir
+                );
+                return Err(Error::RepositoryNotFound);
+            }
+            common_dir => (common_dir.canonicalize()?, Some(data_dir)),
+        };
+
         let lock_file = fs::File::create(data_dir.join("mutex"))?;
-        if let Err(e) = lock_file.try_lock_shared() {
+        if let Err(e) = FileExt::try_lock_shared(&lock_file) {
             if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
                 warn!("Blocking until the repository mutex is unlocked...");
-                lock_file.try_lock_shared()?;
+                lock_file.lock_shared()?;
             } else {
                 return Err(e.into());
             }
         }
 
+        let write_lock_file = fs::File::create(data_dir.join(WRITE_LOCK_FILE))?;
+
         Ok(Repository {
             path,
             data_dir,
+            worktree_dir,
             progress_reporter_factory: Box::new(|_| ProgressReporter::dummy()),
             lock_file,
             is_locked_exclusively: AtomicBool::new(false),
+            write_lock_file,
+            is_locked_for_writing: AtomicBool::new(false),
+            http_auth: vec![],
+            remote_mirrors: HashMap::new(),
+            trusted_keys: vec![],
+            trust_policies: HashMap::new(),
+            encryption_keys: vec![],
+            index_cache: IndexCache::new(),
+            is_object_index_enabled: false,
         })
     }
 
@@ -205,6 +601,9 @@ impl Repository {
         if self.is_locked_exclusively.load(Ordering::Acquire) {
             return Ok(());
         }
+        // The write lock is always taken first, so that the mutex is never
+        // released while it is locked exclusively (see `lock_write`).
+        self.lock_write()?;
         if let Err(e) = self.lock_file.try_lock_exclusive() {
             if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
                 warn!("Blocking until the repository mutex is unlocked...");
@@ -217,11 +616,49 @@ impl Repository {
         Ok(())
     }
 
+    /// Locks the repository for writing until it is closed, so that the
+    /// operations modifying it (or the working directory) in concurrent
+    /// processes run one after the other, while other processes can still read
+    /// it. Must be called before reading the state the operation depends on.
+    fn lock_write(&self) -> io::Result<()> {
+        if self.is_locked_for_writing.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        if let Err(e) = self.write_lock_file.try_lock_exclusive() {
+            if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                warn!("Blocking until the other operation on the repository completes...");
+                // The other operation might need to lock the mutex exclusively
+                // (e.g. to delete a pack), so it is released while waiting.
+                self.lock_file.unlock()?;
+                self.write_lock_file.lock_exclusive()?;
+                self.lock_file.lock_shared()?;
+            } else {
+                return Err(e);
+            }
+        }
+        self.is_locked_for_writing.store(true, Ordering::Release);
+        Ok(())
+    }
+
     // Reads the state of HEAD. If the file does not exist, returns None values.
     // If ctime/mtime cannot be determined, returns None.
     pub fn read_head(&self) -> Result<(Option<SnapshotId>, Option<SystemTime>), Error> {
-        let path = self.data_dir().join(HEAD_FILE);
+        self.read_head_of(&self.path)
+    }
 
+    /// Reads the state of the HEAD of a directory snapshots are extracted to
+    /// (see [`Repository::extract_snapshot_to`]), like [`Repository::read_head`].
+    pub fn read_head_of(
+        &self,
+        work_dir: &Path,
+    ) -> Result<(Option<SnapshotId>, Option<SystemTime>), Error> {
+        self.read_head_file(&self.head_path(work_dir))
+    }
+
+    fn read_head_file(
+        &self,
+        path: &Path,
+    ) -> Result<(Option<SnapshotId>, Option<SystemTime>), Error> {
         let (head, mtime) = match open_file(path) {
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => (None, None),
             Err(e) => return Err(e.into()),
@@ -233,6 +670,12 @@ impl Repository {
 
                 let text = std::str::from_utf8(&buf).map_err(|_| Error::CorruptHead)?;
                 let snapshot = SnapshotId::from_str(text).map_err(|_| Error::CorruptHead)?;
+                // The snapshot may have been packed since it was extracted,
+                // from another working tree (see Repository::add_worktree).
+                let snapshot = match self.locate_snapshot(&snapshot) {
+                    Ok(Some(located)) => located,
+                    _ => snapshot,
+                };
                 (Some(snapshot), time)
             }
         };
@@ -255,7 +698,23 @@ impl Repository {
 
     /// Open the pack.
     pub fn open_pack(&self, pack: &PackId) -> Result<Pack, Error> {
-        Pack::open(self.data_dir(), pack)
+        let PackId::Pack(pack_name) = pack;
+        let pack_path = self
+            .data_dir()
+            .join(PACKS_DIR)
+            .join(format!("{pack_name}.{PACK_EXTENSION}"));
+        let mut pack = Pack::open_path(pack_name, pack_path, self.checksum_algorithm(pack)?)?;
+        pack.decrypt_with(&self.encryption_keys)?;
+        Ok(pack)
+    }
+
+    /// Opens the pack, unless its .pack is not available locally (e.g. only
+    /// its index was fetched from the remote).
+    fn open_local_pack(&self, pack: &PackId) -> Result<Option<Pack>, Error> {
+        if !self.get_pack_path(pack).exists() {
+            return Ok(None);
+        }
+        self.open_pack(pack).map(Some)
     }
 
     pub fn packs(&self) -> Result<Vec<PackId>, Error> {
@@ -313,7 +772,17 @@ impl Repository {
         } else {
             // Search packs.
             let tag = maybe_canonical_snapshot_tag;
-            Ok(SnapshotId::new(self.find_pack_with_snapshot(tag)?, tag)?)
+            match self.find_pack_with_snapshot(tag) {
+                Ok(pack) => Ok(SnapshotId::new(pack, tag)?),
+                // The snapshots take precedence over the refs of the same name.
+                Err(Error::PackError(PackError::SnapshotNotFound(e))) => {
+                    match self.resolve_ref(tag)? {
+                        Some(snapshot) => Ok(snapshot),
+                        None => Err(Error::PackError(PackError::SnapshotNotFound(e))),
+                    }
+                }
+                Err(e) => Err(e),
+            }
         }
     }
 
@@ -345,8 +814,7 @@ impl Repository {
         let pack_index_path = self
             .data_dir()
             .join(PACKS_DIR)
-            .join(pack_id)
-            .with_extension(PACK_INDEX_EXTENSION);
+            .join(format!("{pack_id}.{PACK_INDEX_EXTENSION}"));
         pack_index_path
             .exists()
             .then(|| PackId::from_str(pack_id))
@@ -361,30 +829,329 @@ impl Repository {
             return false;
         }
 
-        let pack_index_path = self
-            .data_dir()
-            .join(PACKS_DIR)
-            .join(pack_name)
-            .with_extension(PACK_INDEX_EXTENSION);
-
-        pack_index_path.exists()
+        self.get_pack_index_path(pack_id).exists()
     }
 
     pub fn load_index(&self, pack_id: &PackId) -> Result<PackIndex, Error> {
         let pack_index_path = self.get_pack_index_path(pack_id);
+        let key = self.disk_cache_key(pack_id)?;
+        if let Some(index) = key.and_then(|key| self.index_cache.load(pack_id, key)) {
+            return Ok(index);
+        }
         info!("Load index {} {}", pack_id, pack_index_path.display());
-        Ok(PackIndex::load(pack_index_path)?)
+        let index = PackIndex::parse(self.open_index(&pack_index_path)?)?;
+        if let Some(key) = key {
+            if let Err(e) = self
+                .index_cache
+                .save(pack_id, key, &index, &self.temp_dir())
+            {
+                warn!("Failed to cache the index of {}: {}", pack_id, e);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Same as [`Repository::load_index`], but the index is kept in memory
+    /// and shared, until the .pack.idx changes, for the callers which only
+    /// read it and may read the same packs again.
+    pub fn load_index_cached(&self, pack_id: &PackId) -> Result<Arc<PackIndex>, Error> {
+        let key = IndexKey::of(&self.get_pack_index_path(pack_id))?;
+        if let Some(index) = self.index_cache.get(pack_id, key) {
+            return Ok(index);
+        }
+        let index = Arc::new(self.load_index(pack_id)?);
+        self.index_cache.insert(pack_id, key, index.clone());
+        Ok(index)
+    }
+
+    /// The key of the pack index in the disk cache (see
+    /// [`Repository::set_index_disk_cache`]), if it is cached on disk. The
+    /// indexes which are already flat are not, nor are the encrypted and the
+    /// loose ones.
+    fn disk_cache_key(&self, pack_id: &PackId) -> Result<Option<IndexKey>, Error> {
+        if !self.index_cache.is_on_disk() || self.is_pack_loose(pack_id) {
+            return Ok(None);
+        }
+        let pack_index_path = self.get_pack_index_path(pack_id);
+        let mut header = vec![];
+        open_file(&pack_index_path)?
+            .take(8)
+            .read_to_end(&mut header)?;
+        if FlatIndex::is_flat(&header) || encryption::is_encrypted_index(&header) {
+            return Ok(None);
+        }
+        Ok(Some(IndexKey::of(&pack_index_path)?))
+    }
+
+    /// The Bloom filter of the paths and objects of the pack, read from its
+    /// .pack.bloom file, or built from its index (and written, for the next
+    /// lookups) if the file is missing or stale. The loose packs, which change
+    /// often, have none, nor have the encrypted ones, since the filter would
+    /// reveal which paths they contain.
+    fn bloom_filter(&self, pack_id: &PackId) -> Result<Option<BloomFilter>, Error> {
+        if self.is_pack_loose(pack_id) {
+            return Ok(None);
+        }
+        let pack_index_path = self.get_pack_index_path(pack_id);
+        let key = IndexKey::of(&pack_index_path)?;
+        let bloom_path = self.get_pack_bloom_path(pack_id);
+        if let Some(filter) = fs::read(&bloom_path)
+            .ok()
+            .and_then(|bytes| BloomFilter::from_bytes(&bytes, key))
+        {
+            return Ok(Some(filter));
+        }
+
+        if self.is_index_encrypted(pack_id)? {
+            return Ok(None);
+        }
+        let filter = BloomFilter::of_index(&*self.load_index_cached(pack_id)?);
+        let temp_dir = self.temp_dir();
+        let written = ensure_dir(&temp_dir).and_then(|()| {
+            write_file_atomic(filter.to_bytes(key).as_slice(), &temp_dir, &bloom_path)
+        });
+        if let Err(e) = written {
+            warn!("Failed to write the Bloom filter of {}: {}", pack_id, e);
+        }
+        Ok(Some(filter))
+    }
+
+    fn is_index_encrypted(&self, pack_id: &PackId) -> Result<bool, Error> {
+        let mut header = vec![];
+        open_file(self.get_pack_index_path(pack_id))?
+            .take(8)
+            .read_to_end(&mut header)?;
+        Ok(encryption::is_encrypted_index(&header))
+    }
+
+    /// The object index (see [`Repository::set_object_index`]), once the
+    /// objects of the packs which changed since it was written are recorded,
+    /// if it is enabled.
+    fn updated_object_index(&self) -> Result<Option<ObjectIndex>, Error> {
+        if !self.is_object_index_enabled {
+            return Ok(None);
+        }
+        self.load_object_index().map(Some)
+    }
+
+    /// Same as [`Repository::updated_object_index`], even if the object index
+    /// is not enabled (e.g. to find the objects stored in other packs, see
+    /// [`PackOptions::dedup`]).
+    fn load_object_index(&self) -> Result<ObjectIndex, Error> {
+        let path = self.data_dir().join(OBJECT_INDEX_FILE);
+        let mut object_index = ObjectIndex::load(&path);
+        let packs = self.packs()?;
+        let mut is_changed = object_index.retain(&packs);
+        for pack_id in &packs {
+            let key = IndexKey::of(&self.get_pack_index_path(pack_id))?;
+            if object_index.is_up_to_date(pack_id, key) {
+                continue;
+            }
+            if self.is_index_encrypted(pack_id)? {
+                object_index.insert(pack_id, key, None);
+            } else {
+                object_index.insert(pack_id, key, Some(&*self.load_index_cached(pack_id)?));
+            }
+            is_changed = true;
+        }
+        if is_changed {
+            self.save_object_index(&object_index, &path);
+        }
+        Ok(object_index)
+    }
+
+    /// Records the objects of the pack which was just written in the object
+    /// index, if it is enabled.
+    fn record_pack_objects(&self, pack_id: &PackId, index: &PackIndex, is_encrypted: bool) {
+        if !self.is_object_index_enabled {
+            return;
+        }
+        let path = self.data_dir().join(OBJECT_INDEX_FILE);
+        let mut object_index = ObjectIndex::load(&path);
+        match IndexKey::of(&self.get_pack_index_path(pack_id)) {
+            Ok(key) => object_index.insert(pack_id, key, Some(index).filter(|_| !is_encrypted)),
+            Err(e) => {
+                warn!("Failed to record the objects of {}: {}", pack_id, e);
+                return;
+            }
+        }
+        self.save_object_index(&object_index, &path);
+    }
+
+    /// The object index is only a cache, which is rebuilt if it can't be
+    /// written.
+    fn save_object_index(&self, object_index: &ObjectIndex, path: &Path) {
+        let temp_dir = self.temp_dir();
+        if let Err(e) = ensure_dir(&temp_dir).and_then(|()| object_index.save(path, &temp_dir)) {
+            warn!("Failed to write the object index: {}", e);
+        }
+    }
+
+    /// The packs which contain the object, with the offset of the object in
+    /// each (see [`ObjectMetadata::offset`], which is 0 in the packs reading it
+    /// from another pack), according to the object index,
+    /// or [`None`] if it is not enabled (see [`Repository::set_object_index`]).
+    /// The packs with an encrypted index are listed too, with no offset, since
+    /// their objects are not recorded.
+    pub fn locate_object(
+        &self,
+        checksum: &ObjectChecksum,
+    ) -> Result<Option<Vec<ObjectLocation>>, Error> {
+        Ok(self
+            .updated_object_index()?
+            .map(|object_index| object_index.get(checksum)))
+    }
+
+    /// Whether the pack may contain a file with the path, according to the
+    /// Bloom filter in its .pack.bloom file. If not, the pack surely doesn't,
+    /// and its index doesn't need to be loaded. The loose and the encrypted
+    /// packs may contain any path.
+    pub fn may_contain_path(&self, pack_id: &PackId, path: &OsStr) -> Result<bool, Error> {
+        Ok(self
+            .bloom_filter(pack_id)?
+            .is_none_or(|filter| filter.contains_path(path)))
+    }
+
+    /// Same as [`Repository::may_contain_path`], for an object.
+    pub fn may_contain_object(
+        &self,
+        pack_id: &PackId,
+        checksum: &ObjectChecksum,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .bloom_filter(pack_id)?
+            .is_none_or(|filter| filter.contains_object(checksum)))
+    }
+
+    /// The algorithm used to compute the object checksums of the pack, which
+    /// is read from the pack index header.
+    pub fn checksum_algorithm(&self, pack_id: &PackId) -> Result<ChecksumAlgorithm, Error> {
+        Ok(PackIndex::parse_only_checksum_algorithm(
+            self.open_index(&self.get_pack_index_path(pack_id))?,
+        )?)
     }
 
     pub fn load_index_snapshots(&self, pack_id: &PackId) -> Result<Vec<String>, Error> {
-        let pack_index_path = match pack_id {
-            PackId::Pack(name) => self
-                .data_dir()
-                .join(PACKS_DIR)
-                .join(name)
-                .with_extension(PACK_INDEX_EXTENSION),
+        let key = IndexKey::of(&self.get_pack_index_path(pack_id))?;
+        if let Some(index) = self.index_cache.get(pack_id, key) {
+            return Ok(index.snapshot_tags().to_vec());
+        }
+        if let Some(index) = self.load_flat_index(pack_id)? {
+            return Ok(index.snapshot_tags()?);
+        }
+        Ok(PackIndex::parse_only_snapshots(
+            self.open_index(&self.get_pack_index_path(pack_id))?,
+        )?)
+    }
+
+    /// Opens the pack index, decrypting it if it is encrypted (see
+    /// [`PackOptions::encrypt_index`]).
+    fn open_index(&self, path: &Path) -> Result<Box<dyn Read>, Error> {
+        let mut file = open_file(path)?;
+        let mut magic = vec![];
+        (&mut file)
+            .take(encryption::ENCRYPTED_INDEX_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if !encryption::is_encrypted_index(&magic) {
+            return Ok(Box::new(io::Cursor::new(magic).chain(file)));
+        }
+        file.read_to_end(&mut magic)?;
+        let index = encryption::decrypt_index(&self.encryption_keys, &magic)?;
+        Ok(Box::new(io::Cursor::new(index)))
+    }
+
+    /// The key the pack index is encrypted with, if it is.
+    fn index_encryption_key(&self, pack_id: &PackId) -> Result<Option<&EncryptionKey>, Error> {
+        let mut header = vec![];
+        open_file(self.get_pack_index_path(pack_id))?
+            .take(64)
+            .read_to_end(&mut header)?;
+        Ok(encryption::index_key_id(&header)
+            .and_then(|key_id| self.encryption_keys.iter().find(|key| key.id() == key_id)))
+    }
+
+    /// Saves the pack index, encrypted with the key, if any.
+    fn save_index(
+        &self,
+        index: &PackIndex,
+        path: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<(), Error> {
+        let key = match key {
+            Some(key) => key,
+            None => return Ok(index.save(path)?),
         };
-        Ok(PackIndex::load_only_snapshots(pack_index_path)?)
+        let mut buf = vec![];
+        index.write(&mut buf)?;
+        let buf = encryption::encrypt_index(key, &buf);
+        // Same as in PackIndex::save.
+        replace_file(path, |temp_path| {
+            let mut file = create_file(temp_path, None)?;
+            file.write_all(&buf)?;
+            file.sync_data()
+        })?;
+        Ok(())
+    }
+
+    /// Opens the pack index as a [`FlatIndex`], if it is in the flat format
+    /// (see [`PackOptions::index_format`]) or cached on disk (see
+    /// [`Repository::set_index_disk_cache`]), to query it in place. With the
+    /// `mmap` feature, the index is mapped into memory rather than read.
+    /// Returns [`None`] for the other indexes, including the encrypted ones,
+    /// which have to be decrypted (and loaded) in full.
+    pub fn load_flat_index(&self, pack_id: &PackId) -> Result<Option<FlatIndex>, Error> {
+        let pack_index_path = self.get_pack_index_path(pack_id);
+        let mut file = open_file(&pack_index_path)?;
+        let mut header = vec![];
+        (&mut file).take(8).read_to_end(&mut header)?;
+        if !FlatIndex::is_flat(&header) {
+            let key = match self.disk_cache_key(pack_id)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            if let Some(index) = self.index_cache.load_flat(pack_id, key) {
+                return Ok(Some(index));
+            }
+            // Loading the index caches it on disk.
+            self.load_index_cached(pack_id)?;
+            return Ok(self.index_cache.load_flat(pack_id, key));
+        }
+        info!("Load flat index {} {}", pack_id, pack_index_path.display());
+        file.rewind()?;
+        Ok(Some(FlatIndex::from_file(file)?))
+    }
+
+    /// Loads the index of the pack to look up the files of its snapshots,
+    /// which only loads the whole index if it is not a [`FlatIndex`].
+    fn load_snapshot_index(&self, pack_id: &PackId) -> Result<SnapshotIndex, Error> {
+        Ok(match self.load_flat_index(pack_id)? {
+            Some(index) => SnapshotIndex::Flat(Box::new(index)),
+            None => SnapshotIndex::Full(self.load_index_cached(pack_id)?),
+        })
+    }
+
+    /// Loads the list of files stored in the specified snapshot.
+    pub fn load_snapshot_entries(&self, snapshot_id: &SnapshotId) -> Result<Vec<FileEntry>, Error> {
+        self.load_snapshot_index(snapshot_id.pack())?
+            .snapshot_entries(snapshot_id.tag())?
+            .ok_or_else(|| PackError::SnapshotNotFound(snapshot_id.tag().to_owned()).into())
+    }
+
+    /// Computes the differences between two snapshots, using only the pack
+    /// indexes (no objects need to be read). The result is sorted by path.
+    pub fn diff_snapshots(
+        &self,
+        from: &SnapshotId,
+        to: &SnapshotId,
+    ) -> Result<SnapshotDiff, Error> {
+        let from_algorithm = self.checksum_algorithm(from.pack())?;
+        let to_algorithm = self.checksum_algorithm(to.pack())?;
+        if from_algorithm != to_algorithm {
+            return Err(PackError::ChecksumAlgorithmMismatch(from_algorithm, to_algorithm).into());
+        }
+        let from_entries = self.load_snapshot_entries(from)?;
+        let to_entries = self.load_snapshot_entries(to)?;
+        Ok(Self::compute_snapshot_diff(from_entries, to_entries))
     }
 
     /// Checks-out the specified snapshot.
@@ -397,7 +1164,38 @@ impl Repository {
         snapshot_id: SnapshotId,
         opts: ExtractOptions,
     ) -> Result<ExtractResult, Error> {
-        let (head, head_time) = self.read_head()?;
+        let path = self.path.clone();
+        self.extract_snapshot_to(snapshot_id, &path, opts)
+    }
+
+    /// Checks-out the specified snapshot into a directory other than the
+    /// working directory, which is created if needed. The directory has a HEAD
+    /// of its own (see [`Repository::read_head_of`]), so that snapshots can be
+    /// extracted incrementally to several directories side by side.
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot_id` - The snapshot to extract.
+    /// * `path` - The directory to extract the snapshot to.
+    pub fn extract_snapshot_to(
+        &mut self,
+        snapshot_id: SnapshotId,
+        path: &Path,
+        opts: ExtractOptions,
+    ) -> Result<ExtractResult, Error> {
+        self.lock_write()?;
+        ensure_dir(path)?;
+        let path = path.to_owned();
+        let (head, head_time) = self.read_head_of(&path)?;
+
+        let extracting_path = self.head_path(&path).with_file_name(EXTRACTING_FILE);
+        match fs::read_to_string(&extracting_path) {
+            Ok(snapshot) if !opts.reset() && !opts.force() => {
+                return Err(Error::InterruptedExtraction(snapshot.trim().to_owned()));
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
 
         if head.is_some() && head_time.is_none() && !opts.force() {
             warn!("The OS/filesystem does not support file creation timestamps!");
@@ -405,48 +1203,103 @@ impl Repository {
         }
 
         // Open the pack and find the snapshot specified in SnapshotId.
-        let source_index = self.load_index(snapshot_id.pack())?;
+        let source_index = self.load_snapshot_index(snapshot_id.pack())?;
 
-        let entries = source_index
-            .resolve_snapshot(snapshot_id.tag())
+        let mut entries = source_index
+            .snapshot_entries(snapshot_id.tag())?
             .expect("failed to resolve snapshot"); // TODO: Temporary.
-        let entries = source_index.entries_from_handles(entries.iter())?;
+        match opts.case_collisions() {
+            CaseCollisions::Allow => {}
+            CaseCollisions::Error => {
+                let collisions = find_case_collisions(&entries);
+                if !collisions.is_empty() {
+                    return Err(Error::CaseCollisions(collisions));
+                }
+            }
+            CaseCollisions::Rename => rename_case_collisions(&mut entries),
+        }
 
-        let (new_entries, old_entries) = if opts.reset || head.is_none() {
+        let is_full = opts.reset || head.is_none();
+        let (new_entries, old_entries) = if is_full {
             // Extract all, remove nothing
             (entries, vec![])
-        } else if let Some(head) = head {
+        } else if let Some(head) = &head {
             // HEAD and new snapshot packs might differ
-            if snapshot_id.pack() == head.pack() {
-                let head_entries = source_index.entries_from_handles(
-                    source_index
-                        .resolve_snapshot(head.tag())
-                        .expect("failed to resolve snapshot")
-                        .iter(), // TODO: Temporary.
-                )?;
-                Self::compute_entry_diff(&head_entries, &entries)
+            let mut head_entries = if snapshot_id.pack() == head.pack() {
+                source_index
+                    .snapshot_entries(head.tag())?
+                    .expect("failed to resolve snapshot") // TODO: Temporary.
             } else {
-                let head_index = self.load_index(head.pack())?;
-                let head_entries = head_index
-                    .entries_from_handles(
-                        head_index
-                            .resolve_snapshot(head.tag())
-                            .expect("failed to resolve snapshot")
-                            .iter(), // TODO: Temporary.
-                    )
+                let head_index = self.load_snapshot_index(head.pack())?;
+                head_index
+                    .snapshot_entries(head.tag())
+                    .and_then(|entries| {
+                        // TODO: Temporary.
+                        entries.ok_or_else(|| PackError::SnapshotNotFound(head.tag().to_owned()))
+                    })
                     .map_err(|e| {
                         if matches!(e, PackError::SnapshotNotFound(_)) {
                             Error::BrokenHeadRef(Box::new(Error::PackError(e)))
                         } else {
                             Error::PackError(e)
                         }
-                    })?;
-                Self::compute_entry_diff(&head_entries, &entries)
+                    })?
+            };
+            // The files of HEAD were extracted under the same names.
+            if opts.case_collisions() == CaseCollisions::Rename {
+                rename_case_collisions(&mut head_entries);
             }
+            Self::compute_entry_diff(&head_entries, &entries)
         } else {
             unreachable!();
         };
 
+        // Only the matching files are updated, from their state in HEAD.
+        let is_partial = !opts.paths().is_empty() || !opts.excludes().is_empty();
+        let (new_entries, old_entries) = if is_partial {
+            let is_extracted = |e: &FileEntry| {
+                (opts.paths().is_empty() || matches_any(opts.paths(), &e.path))
+                    && !matches_any(opts.excludes(), &e.path)
+            };
+            (
+                new_entries.into_iter().filter(is_extracted).collect(),
+                old_entries.into_iter().filter(is_extracted).collect(),
+            )
+        } else {
+            (new_entries, old_entries)
+        };
+
+        let modified_files = opts.modified_files();
+        // The files of HEAD are checked before any file is modified.
+        let mut modified_paths = vec![];
+        if !opts.force() && modified_files == ModifiedFiles::Error {
+            let mut path_buf = PathBuf::new();
+            for entry in &old_entries {
+                path_buf.clear();
+                path_buf.push(&path);
+                push_entry_path(&mut path_buf, &entry.path)?;
+                self.check_changed_since(head_time.unwrap(), &path_buf)?;
+            }
+        } else if !opts.force() && !old_entries.is_empty() {
+            let algorithm = self.checksum_algorithm(head.as_ref().unwrap().pack())?;
+            for entry in &old_entries {
+                if self.is_modified_since(head_time.unwrap(), &path, entry, algorithm)? {
+                    modified_paths.push(entry.path.clone());
+                }
+            }
+        }
+        // The kept files are neither updated nor removed.
+        let (new_entries, old_entries) = if modified_files == ModifiedFiles::Keep {
+            let modified: HashSet<_> = modified_paths.iter().collect();
+            let is_updated = |e: &FileEntry| !modified.contains(&e.path);
+            (
+                new_entries.into_iter().filter(is_updated).collect(),
+                old_entries.into_iter().filter(is_updated).collect(),
+            )
+        } else {
+            (new_entries, old_entries)
+        };
+
         // There is no point in deleting files which will be overwritten by the extract, so
         // we identify and ignore them beforehand.
         let (updated_paths, removed_paths) = {
@@ -461,42 +1314,105 @@ impl Repository {
             removed.sort();
             (updated, removed)
         };
+        debug!(
+            "Extracting {}: writing {} files ({} updated), removing {} files{}",
+            snapshot_id,
+            new_entries.len(),
+            updated_paths.len(),
+            removed_paths.len(),
+            if is_full {
+                " (full extraction)"
+            } else {
+                " (incremental extraction from HEAD)"
+            },
+        );
 
-        let mut path_buf = PathBuf::new();
-        if !opts.force() {
-            for entry in &old_entries {
-                path_buf.clear();
-                path_buf.push(&self.path);
-                path_buf.push(&entry.path);
-                self.check_changed_since(head_time.unwrap(), &path_buf)?;
+        let staging_dir = if opts.stage() {
+            let staging_dir = create_hidden_temp_path(&path);
+            ensure_dir(&staging_dir)?;
+            let result =
+                self.extract_entries(snapshot_id.pack(), &new_entries, &staging_dir, opts.clone());
+            if let Err(e) = result {
+                fs::remove_dir_all(&staging_dir)?;
+                return Err(e);
+            }
+            Some(staging_dir)
+        } else {
+            None
+        };
+
+        // HEAD and the files are out of sync until HEAD is updated, which is
+        // recorded in case the extraction is interrupted.
+        let update_head = opts.update_head() && !is_partial;
+        if update_head {
+            self.write_snapshot_id(&extracting_path, &snapshot_id)?;
+        }
+
+        let mut backup_paths = vec![];
+        if modified_files == ModifiedFiles::Backup {
+            for modified_path in &modified_paths {
+                let backup = backup_path(&path, modified_path);
+                info!(
+                    "Moving the modified file {} to {}",
+                    modified_path.to_string_lossy(),
+                    backup.to_string_lossy()
+                );
+                fs::rename(
+                    join_entry_path(&path, modified_path)?,
+                    join_entry_path(&path, &backup)?,
+                )?;
+                backup_paths.push((modified_path.clone(), backup));
             }
         }
 
+        let mut path_buf = PathBuf::new();
         let mut dir_queue = EmptyDirectoryCleanupQueue::new();
+        let removed_dirs: HashSet<_> = old_entries
+            .iter()
+            .filter(|e| e.file_metadata.is_dir())
+            .map(|e| &e.path)
+            .collect();
 
-        for path in &removed_paths {
+        for removed_path in &removed_paths {
             path_buf.clear();
-            path_buf.push(&self.path);
-            path_buf.push(path);
+            path_buf.push(&path);
+            push_entry_path(&mut path_buf, removed_path)?;
+            trace!("Removing {}", path_buf.display());
+            if removed_dirs.contains(removed_path) {
+                // Only removed if it is still empty.
+                dir_queue.enqueue(&path_buf, path.clone())?;
+                continue;
+            }
             // Delete the file
             match fs::remove_file(&path_buf) {
                 Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
                 r => r,
             }?;
 
-            dir_queue.enqueue(path_buf.parent().unwrap(), self.path.clone())?;
+            dir_queue.enqueue(path_buf.parent().unwrap(), path.clone())?;
         }
 
         // Process the enqueued directories.
         dir_queue.process()?;
 
-        self.extract_entries(snapshot_id.pack(), &new_entries, self.path.clone(), opts)?;
-        self.update_head(&snapshot_id)?;
+        match staging_dir {
+            Some(staging_dir) => move_staged_entries(&new_entries, &staging_dir, &path)?,
+            None => self.extract_entries(snapshot_id.pack(), &new_entries, &path, opts)?,
+        }
+        if update_head {
+            self.update_head_of(&path, &snapshot_id)?;
+            fs::remove_file(&extracting_path)?;
+        }
 
         Ok(ExtractResult {
             added_file_count: (new_entries.len() - updated_paths.len()) as u32,
             removed_file_count: removed_paths.len() as u32,
             modified_file_count: updated_paths.len() as u32,
+            kept_paths: match modified_files {
+                ModifiedFiles::Keep => modified_paths,
+                _ => vec![],
+            },
+            backup_paths,
         })
     }
 
@@ -518,45 +1434,409 @@ impl Repository {
     where
         P: AsRef<Path>,
     {
-        if self.is_pack_loose(pack_id) {
-            self.copy_loose_entries(entries, path.as_ref(), opts.verify())
-        } else if let Ok(pack) = self.open_pack(pack_id) {
-            pack.extract_entries(entries, path.as_ref(), opts.verify(), opts.num_workers())
-        } else {
-            info!("Pack not available locally! Fetching from remote...");
-            self.update_remote_pack(pack_id)?;
-            self.open_pack(pack_id).and_then(|pack| {
-                pack.extract_entries(entries, path.as_ref(), opts.verify(), opts.num_workers())
-            })
+        let _span = Span::enter("extract_entries");
+        let preserve_times = opts.preserve_times();
+        let xattrs = opts.xattrs();
+        self.extract_entry_contents(pack_id, entries, path.as_ref(), &opts)?;
+
+        // The attributes and times are set once all the files are written,
+        // since writing them modifies their directories (the empty ones are
+        // entries too).
+        if preserve_times || xattrs {
+            let mut dest_path = PathBuf::new();
+            for entry in entries {
+                dest_path.clear();
+                dest_path.push(path.as_ref());
+                push_entry_path(&mut dest_path, &entry.path)?;
+                if xattrs && !entry.xattrs.is_empty() {
+                    write_xattrs(&dest_path, &entry.xattrs)?;
+                }
+                if let (true, Some(mtime)) = (preserve_times, entry.file_metadata.mtime) {
+                    set_modified_time(&dest_path, mtime)?;
+                }
+            }
         }
+        Ok(())
     }
 
-    fn update_remote_pack(&self, pack: &PackId) -> Result<(), Error> {
-        let remotes_dir = self.data_dir().join(REMOTES_DIR);
-        let remotes = remote::load_remotes(&remotes_dir)?;
-
-        let pack = match pack {
-            PackId::Pack(p) => p.rsplit_once('/').map(|x| x.1).unwrap_or(p),
-        };
-        let pack_file_name = pack.to_string() + "." + PACK_EXTENSION;
-
-        let agent = ureq::AgentBuilder::new().build();
-        let reporter = (self.progress_reporter_factory)(&format!("Fetching {pack_file_name}"));
-
+    /// Writes the contents of the entries (see [`Repository::extract_entries`]).
+    /// The entries whose objects are stored in other packs are extracted from
+    /// these packs.
+    fn extract_entry_contents(
+        &mut self,
+        pack_id: &PackId,
+        entries: &[FileEntry],
+        path: &Path,
+        opts: &ExtractOptions,
+    ) -> Result<(), Error> {
+        if entries.iter().any(|e| e.object_metadata.pack.is_some()) {
+            for (pack_id, entries) in self.split_external_entries(pack_id, entries)? {
+                self.extract_entry_contents(&pack_id, &entries, path, opts)?;
+            }
+            return Ok(());
+        }
+        if opts.reflink() || opts.link() {
+            self.extract_entries_from_cache(pack_id, entries, path, opts.clone())?;
+        } else if self.is_pack_loose(pack_id) {
+            let verify = if opts.verify() {
+                Some(self.checksum_algorithm(pack_id)?)
+            } else {
+                None
+            };
+            self.copy_loose_entries(entries, path, verify)?;
+        } else if let Some(pack) = self.open_local_pack(pack_id)? {
+            pack.extract_entries(entries, path, opts.verify(), opts.num_workers())?;
+        } else if let Some(partial_path) = self.fetch_remote_pack_frames(pack_id, entries)? {
+            // The fetched frames can't be verified against the checksum of the
+            // pack, so verify the objects instead.
+            let result = Pack::open_path(
+                &pack_id.to_string(),
+                partial_path.clone(),
+                self.checksum_algorithm(pack_id)?,
+            )
+            .and_then(|mut pack| {
+                pack.decrypt_with(&self.encryption_keys)?;
+                pack.extract_entries(entries, path, true, opts.num_workers())
+            });
+            fs::remove_file(&partial_path)?;
+            result?;
+        } else {
+            info!("Pack not available locally! Fetching from remote...");
+            self.update_remote_pack(pack_id)?;
+            self.open_pack(pack_id).and_then(|pack| {
+                pack.extract_entries(entries, path, opts.verify(), opts.num_workers())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reads the specified entries into memory, without writing them to disk,
+    /// calling `f` with the contents of each entry. The entries are visited in
+    /// the order in which they are stored, which might differ from the order
+    /// of `entries`.
+    ///
+    /// # Arguments
+    ///
+    /// * `pack_id` - The pack_id containing the entries.
+    /// * `entries` - The list of entries to read.
+    /// * `verify` - Set to true to verify object checksums.
+    /// * `f` - Called with each entry and its contents.
+    pub fn read_entries<F>(
+        &self,
+        pack_id: &PackId,
+        entries: &[FileEntry],
+        verify: bool,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&FileEntry, &[u8]) -> Result<(), Error>,
+    {
+        let mut stream = self.stream_entries(pack_id, entries, verify)?;
+        while let Some((entry, buf)) = stream.next_object().transpose()? {
+            f(&entry, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the files of the snapshot along with readers for their contents,
+    /// without writing anything to disk (see [`SnapshotStream`]).
+    ///
+    /// # Arguments
+    ///
+    /// * `snapshot` - The snapshot to read.
+    /// * `verify` - Set to true to verify object checksums.
+    pub fn stream_snapshot(
+        &self,
+        snapshot: &SnapshotId,
+        verify: bool,
+    ) -> Result<SnapshotStream, Error> {
+        let entries = self.load_snapshot_entries(snapshot)?;
+        self.stream_entries(snapshot.pack(), &entries, verify)
+    }
+
+    /// Returns the contents of the file at `path` in the snapshot, or `None` if
+    /// the snapshot has no such file. The checksum of the contents is verified.
+    pub fn read_snapshot_file(
+        &self,
+        snapshot: &SnapshotId,
+        path: &Path,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let entries = self.load_snapshot_entries(snapshot)?;
+        let entry = match entries.into_iter().find(|e| Path::new(&e.path) == path) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let mut stream = self.stream_entries(snapshot.pack(), &[entry], true)?;
+        Ok(stream.next_object().transpose()?.map(|(_, buf)| buf))
+    }
+
+    /// Same as [`Repository::stream_snapshot`], but only reads the specified
+    /// entries (e.g. a subset of [`Repository::load_snapshot_entries`]).
+    pub fn stream_entries(
+        &self,
+        pack_id: &PackId,
+        entries: &[FileEntry],
+        verify: bool,
+    ) -> Result<SnapshotStream, Error> {
+        if entries.iter().any(|e| e.object_metadata.pack.is_some()) {
+            let streams = self
+                .split_external_entries(pack_id, entries)?
+                .into_iter()
+                .map(|(pack_id, entries)| self.stream_entries(&pack_id, &entries, verify))
+                .collect::<Result<_, _>>()?;
+            return Ok(SnapshotStream::chain(streams));
+        }
+        if self.is_pack_loose(pack_id) {
+            let verify = if verify {
+                Some(self.checksum_algorithm(pack_id)?)
+            } else {
+                None
+            };
+            let objects = entries
+                .iter()
+                .map(|entry| (entry.clone(), self.loose_object_path(&entry.checksum)))
+                .collect();
+            Ok(SnapshotStream::loose(objects, verify))
+        } else if let Some(pack) = self.open_local_pack(pack_id)? {
+            Ok(SnapshotStream::pack(pack.stream_entries(entries, verify)?))
+        } else {
+            info!("Pack not available locally! Fetching from remote...");
+            self.update_remote_pack(pack_id)?;
+            let pack = self.open_pack(pack_id)?;
+            Ok(SnapshotStream::pack(pack.stream_entries(entries, verify)?))
+        }
+    }
+
+    /// Groups the entries by the pack storing their objects: the pack itself,
+    /// then the packs storing the objects stored in other packs (see
+    /// [`ObjectMetadata::pack`]), whose entries get the metadata of their
+    /// objects in these packs. The empty groups are skipped.
+    fn split_external_entries(
+        &self,
+        pack_id: &PackId,
+        entries: &[FileEntry],
+    ) -> Result<Vec<(PackId, Vec<FileEntry>)>, Error> {
+        let mut stored = vec![];
+        let mut external: BTreeMap<&str, Vec<FileEntry>> = BTreeMap::new();
+        for entry in entries {
+            match &entry.object_metadata.pack {
+                Some(pack) => external.entry(pack).or_default().push(entry.clone()),
+                None => stored.push(entry.clone()),
+            }
+        }
+
+        let mut groups = vec![];
+        if !stored.is_empty() {
+            groups.push((pack_id.clone(), stored));
+        }
+        for (pack, mut entries) in external {
+            let external_id = pack_id.resolve_external(pack)?;
+            let missing = |entry: &FileEntry| {
+                Error::MissingExternalObject(entry.checksum, external_id.to_string())
+            };
+            if !self.get_pack_index_path(&external_id).exists() {
+                return Err(missing(&entries[0]));
+            }
+            let index = self.load_index_cached(&external_id)?;
+            for entry in &mut entries {
+                // The packs storing the objects of other packs store them
+                // themselves, so the references are never chained.
+                match index.lookup_object_metadata(&entry.checksum) {
+                    Some(md) if md.pack.is_none() => entry.object_metadata = md.clone(),
+                    _ => return Err(missing(entry)),
+                }
+            }
+            groups.push((external_id, entries));
+        }
+        Ok(groups)
+    }
+
+    /// Fetches the .pack file from the remote providing the pack (only if the
+    /// remote has a newer version than the local file).
+    pub fn update_remote_pack(&self, pack: &PackId) -> Result<(), Error> {
+        let (remote_name, remote_pack) = self.find_remote_pack(pack)?;
+        let pack_file_name = remote_pack.file_name();
+        let pack_path = self
+            .data_dir()
+            .join(PACKS_DIR)
+            .join(&remote_name)
+            .join(pack_file_name);
+
+        let agent = self.http_agent();
+        let reporter = (self.progress_reporter_factory)(&format!("Fetching {pack_file_name}"));
+        // Immediately shows some progress, without waiting for the HTTP
+        // response for the pack.
+        reporter.checkpoint(0, Some(1));
+        remote::update_remote_pack(&agent, &remote_pack, &pack_path, &reporter)?;
+        self.verify_remote_pack(&agent, &remote_name, &remote_pack, &pack_path)?;
+        Ok(())
+    }
+
+    /// Verifies the signature of the pack of a remote against its trust policy
+    /// (see [`Repository::set_trust_policies`]): that it is signed by the
+    /// trusted keys, and that the .pack.idx (and the .pack, if it is
+    /// available) are the signed ones. The signature is fetched, unless it is
+    /// already available and newer than the .pack.idx. The files of the pack
+    /// which violate the policy are deleted.
+    fn verify_remote_pack(
+        &self,
+        agent: &ureq::Agent,
+        remote_name: &str,
+        remote_pack: &remote::RemotePack,
+        pack_path: &Path,
+    ) -> Result<(), Error> {
+        let policy = self.trust_policy(remote_name);
+        if !policy.is_enforced() {
+            return Ok(());
+        }
+        let index_path = path_with_suffix(pack_path, ".idx");
+        let signature_path = path_with_suffix(pack_path, ".sig");
+        let file_name = remote_pack.file_name();
+        let is_signed = if is_signature_stale(&signature_path, &index_path) {
+            remote::fetch_pack_signature(agent, remote_pack, &signature_path)
+                .map_err(|e| Error::BadSignature(format!("Failed to fetch the signature: {e}")))
+        } else {
+            Ok(true)
+        };
+        let result = is_signed.and_then(|is_signed| {
+            if !is_signed {
+                if policy.allow_unsigned {
+                    warn!("{}/{} is not signed!", remote_name, file_name);
+                    return Ok(());
+                }
+                return Err(Error::BadSignature(
+                    "Not signed, and the remote does not allow unsigned packs".to_owned(),
+                ));
+            }
+            let signature = PackSignature::load(&signature_path)?;
+            let signers = policy.check(&signature)?;
+            signature.verify_index(&index_path)?;
+            if pack_path.exists() {
+                signature.verify_pack(pack_path)?;
+            }
+            let signers: Vec<_> = signers.iter().map(|key| key.to_string()).collect();
+            info!(
+                "{}/{} is signed by {}",
+                remote_name,
+                file_name,
+                signers.join(", ")
+            );
+            Ok(())
+        });
+
+        match result {
+            Err(Error::BadSignature(e)) => {
+                for path in [pack_path, &index_path, &signature_path] {
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+                Err(Error::BadSignature(format!(
+                    "{remote_name}/{file_name}: {e}"
+                )))
+            }
+            result => result,
+        }
+    }
+
+    /// Fetches the header of the remote pack and the frames containing the
+    /// entries (see [`PackIndex::frames`]) with HTTP Range requests, to a
+    /// temporary file in which the other frames are left empty (as holes), so
+    /// that the entries can be read with [`Pack::open_path`]. The caller
+    /// deletes the file.
+    ///
+    /// Returns [`None`] if the whole pack should be fetched instead: when the
+    /// index does not record the frames of the pack, when all the frames are
+    /// needed, or when the server does not support range requests.
+    fn fetch_remote_pack_frames(
+        &self,
+        pack: &PackId,
+        entries: &[FileEntry],
+    ) -> Result<Option<PathBuf>, Error> {
+        let index = self.load_index_cached(pack)?;
+        let frames = index.frames();
+        let needed = frames_containing(frames, entries);
+        if frames.is_empty() || needed.len() == frames.len() {
+            return Ok(None);
+        }
+        let (remote_name, remote_pack) = self.find_remote_pack(pack)?;
+        let agent = self.http_agent();
+        // Only the index can be verified against the signature, then the
+        // objects are verified against the index.
+        self.verify_remote_pack(
+            &agent,
+            &remote_name,
+            &remote_pack,
+            &self.get_pack_path(pack),
+        )?;
+
+        // The header, then the frames, merging the consecutive ones.
+        let header = 0..frames[0].offset;
+        let mut ranges = vec![header];
+        for frame in needed.iter().map(|&i| &frames[i]) {
+            let range = frame.offset..frame.offset + frame.size;
+            match ranges.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => ranges.push(range),
+            }
+        }
+        let file_size = frames.last().map_or(0, |frame| frame.offset + frame.size);
+        info!(
+            "Fetching {} of the {} frames of {} ({} requests)...",
+            needed.len(),
+            frames.len(),
+            pack,
+            ranges.len()
+        );
+
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        let temp_path = create_temp_path(&temp_dir);
+        let mut file = create_file(&temp_path, None)?;
+        file.set_len(file_size)?;
+
+        let reporter = (self.progress_reporter_factory)(&format!(
+            "Fetching frames of {}",
+            remote_pack.file_name()
+        ));
+        reporter.checkpoint(0, Some(ranges.len()));
+        let fetched = remote::fetch_remote_pack_ranges(
+            &agent,
+            &remote_pack,
+            &ranges,
+            &reporter,
+            |offset, data| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(data)?;
+                Ok(())
+            },
+        );
+        match fetched {
+            Ok(true) => Ok(Some(temp_path)),
+            Ok(false) => {
+                info!("The server does not support range requests!");
+                fs::remove_file(&temp_path)?;
+                Ok(None)
+            }
+            Err(e) => {
+                fs::remove_file(&temp_path)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Finds the remote providing the pack. Returns the name of the remote and
+    /// the pack as listed in its index.
+    fn find_remote_pack(&self, pack: &PackId) -> Result<(String, remote::RemotePack), Error> {
+        let remotes = self.load_remotes()?;
+
+        let pack = match pack {
+            PackId::Pack(p) => p.rsplit_once('/').map(|x| x.1).unwrap_or(p),
+        };
         for remote in remotes {
             if let Some(remote_pack) = remote.find_pack(pack) {
-                info!("Found {} in {}. Updating...", pack, remote);
-                let pack_path = self
-                    .data_dir()
-                    .join(PACKS_DIR)
-                    .join(remote.name().unwrap())
-                    .join(pack_file_name);
-
-                // Immediately shows some progress, without waiting for the
-                // HTTP response for the pack.
-                reporter.checkpoint(0, Some(1));
-                remote::update_remote_pack(&agent, remote_pack, &pack_path, &reporter)?;
-                return Ok(());
+                info!("Found {} in {}", pack, remote);
+                return Ok((remote.name().unwrap(), remote_pack.clone()));
             }
         }
 
@@ -568,13 +1848,22 @@ impl Repository {
         &self.data_dir
     }
 
-    pub fn create_snapshot<I, P>(&mut self, snapshot: &SnapshotId, files: I) -> Result<(), Error>
+    /// Creates a loose snapshot from the specified files and updates HEAD to
+    /// point to it.
+    pub fn create_snapshot<I, P>(
+        &mut self,
+        snapshot: &SnapshotId,
+        files: I,
+        metadata: SnapshotMetadata,
+        opts: StoreOptions,
+    ) -> Result<(), Error>
     where
         I: Iterator<Item = P>,
         P: AsRef<Path>,
     {
+        self.lock_write()?;
         let files =
-            clean_file_list(self.path.as_ref(), self.data_dir(), files)?.collect::<Vec<_>>();
+            clean_file_list(self.path.as_ref(), self.work_data_dir(), files)?.collect::<Vec<_>>();
         info!("Computing checksums for {} files...", files.len());
 
         let temp_dir = self.temp_dir();
@@ -582,35 +1871,183 @@ impl Repository {
 
         let threads = num_cpus::get();
 
-        let pack_entries = run_in_parallel(threads, files.into_iter(), |file_path| {
-            let mut fd = File::open(&file_path)?;
-            let (buf, mode) = {
-                let mut buf = vec![];
-                fd.read_to_end(&mut buf)?;
-                let mode = fd.metadata()?.permissions().mode();
-                (buf, mode)
-            };
-            let mut checksum = [0u8; 20];
-            let mut hasher = Sha1::new();
-            hasher.input(&buf);
-            hasher.result(&mut checksum);
-            self.write_loose_object(&*buf, &temp_dir, &checksum)?;
-
-            Ok(FileEntry::new(
-                file_path.into(),
-                checksum,
-                ObjectMetadata {
-                    offset: LOOSE_OBJECT_OFFSET,
-                    size: buf.len() as u64,
-                },
-                FileMetadata { mode },
-            ))
+        let algorithm = self.default_checksum_algorithm()?;
+        let cache_path = self.work_data_dir().join(STAT_CACHE_FILE);
+        let cache = if opts.ignore_cache() {
+            StatCache::new(&self.path, algorithm)
+        } else {
+            StatCache::load(&cache_path, &self.path, algorithm)
+        };
+        // The cached checksums can only be used if the objects are still
+        // loose, since the snapshot references loose objects.
+        let cached_checksum = |path: &OsStr, metadata: &fs::Metadata| {
+            cache
+                .get(path, metadata)
+                .filter(|checksum| self.loose_object_path(checksum).exists())
+        };
+        let span = Span::enter("compute_checksums");
+        let results = run_in_parallel(threads, files.into_iter(), |file_path| {
+            let (entry, buf, file_metadata) =
+                read_file_entry(&self.path, file_path, algorithm, &opts, cached_checksum)?;
+            if let Some(buf) = &buf {
+                self.write_loose_object(buf, &temp_dir, &entry.checksum)?;
+            }
+            Ok((entry, file_metadata, buf.is_none()))
         })
         .into_iter()
         .collect::<io::Result<Vec<_>>>()?;
+        drop(span);
+
+        let num_cached = results
+            .iter()
+            .filter(|(_, _, is_cached)| *is_cached)
+            .count();
+        info!("Reused the cached checksums of {} files", num_cached);
+        let mut new_cache = StatCache::new(&self.path, algorithm);
+        let pack_entries = results
+            .into_iter()
+            .map(|(entry, file_metadata, _)| {
+                new_cache.insert(entry.path.clone(), &file_metadata, entry.checksum);
+                entry
+            })
+            .collect::<Vec<_>>();
+
+        self.save_loose_snapshot(snapshot, pack_entries, metadata, algorithm)?;
+        self.update_head(snapshot)?;
+        // The temporary file must be on the same filesystem as the cache.
+        match &self.worktree_dir {
+            Some(worktree_dir) => new_cache.save(&cache_path, worktree_dir)?,
+            None => new_cache.save(&cache_path, &temp_dir)?,
+        }
+
+        Ok(())
+    }
+
+    /// Creates a loose snapshot from (path, file metadata, contents) tuples,
+    /// without reading or modifying the working directory, e.g. when importing
+    /// an archive. If a path appears more than once, the last occurrence wins.
+    /// HEAD is not updated, since the snapshot is not extracted.
+    pub fn create_snapshot_from_contents<I>(
+        &mut self,
+        snapshot: &SnapshotId,
+        files: I,
+        metadata: SnapshotMetadata,
+    ) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = Result<(PathBuf, FileMetadata, Vec<u8>), Error>>,
+    {
+        self.lock_write()?;
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+
+        let algorithm = self.default_checksum_algorithm()?;
+        let mut entries = HashMap::new();
+        for file in files {
+            let (path, file_metadata, buf) = file?;
+            let entry = make_file_entry(path.clone().into(), &buf, file_metadata, algorithm);
+            self.write_loose_object(&buf, &temp_dir, &entry.checksum)?;
+            entries.insert(path, entry);
+        }
+
+        let file_count = entries.len();
+        self.save_loose_snapshot(snapshot, entries.into_values(), metadata, algorithm)?;
+        Ok(file_count)
+    }
+
+    /// Stores an entry of the build cache: the contents are written as a loose
+    /// object, and the key (a relative path, e.g. the hash of the inputs of a
+    /// compilation) is recorded as a file of the loose snapshot
+    /// `cache-<date>[-<n>]`, which can later be packed as usual. Returns the
+    /// snapshot.
+    pub fn write_build_cache_entry(
+        &mut self,
+        key: &str,
+        contents: &[u8],
+    ) -> Result<SnapshotId, Error> {
+        check_build_cache_key(key)?;
+        self.lock_write()?;
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+
+        let date = Utc::now().format("%Y%m%d");
+        let mut n = 1;
+        let (snapshot, mut entries, metadata, algorithm) = loop {
+            let tag = match n {
+                1 => format!("{BUILD_CACHE_PREFIX}{date}"),
+                n => format!("{BUILD_CACHE_PREFIX}{date}-{n}"),
+            };
+            let snapshot = SnapshotId::new(PackId::Pack(format!("{LOOSE_DIR}/{tag}")), &tag)?;
+            if !self.get_pack_index_path(snapshot.pack()).exists() {
+                let algorithm = self.default_checksum_algorithm()?;
+                break (snapshot, vec![], SnapshotMetadata::default(), algorithm);
+            }
+            let index = self.load_index(snapshot.pack())?;
+            let handles = index
+                .resolve_snapshot(&tag)
+                .ok_or_else(|| PackError::SnapshotNotFound(tag.clone()))?;
+            if handles.len() < BUILD_CACHE_SNAPSHOT_FILES {
+                let entries = index.entries_from_handles(handles.iter())?;
+                let metadata = index.snapshot_metadata(&tag).cloned().unwrap_or_default();
+                break (snapshot, entries, metadata, index.checksum_algorithm());
+            }
+            n += 1;
+        };
+
+        let file_metadata = FileMetadata {
+            mode: 0o100644,
+            mtime: None,
+        };
+        let entry = make_file_entry(key.into(), contents, file_metadata, algorithm);
+        self.write_loose_object(contents, &temp_dir, &entry.checksum)?;
+        entries.retain(|e| e.path != entry.path);
+        entries.push(entry);
+        self.save_loose_snapshot(&snapshot, entries, metadata, algorithm)?;
+        Ok(snapshot)
+    }
+
+    /// Returns the contents of the entry of the build cache (see
+    /// [`Repository::write_build_cache_entry`]), from the loose or packed
+    /// snapshots, or `None` if there is no such entry. The most recent
+    /// snapshot wins if several have the entry.
+    pub fn read_build_cache_entry(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        check_build_cache_key(key)?;
+        let mut snapshots: Vec<_> = self
+            .find_exact_paths(&[OsStr::new(key)])?
+            .into_iter()
+            .map(|(snapshot, _)| snapshot)
+            .filter(|snapshot| snapshot.tag().starts_with(BUILD_CACHE_PREFIX))
+            .collect();
+        // The tags of the snapshots of the same day only differ by the number
+        // which follows them.
+        snapshots.sort_by_cached_key(|snapshot| {
+            let (date, n) = snapshot.tag()[BUILD_CACHE_PREFIX.len()..]
+                .split_once('-')
+                .unwrap_or((&snapshot.tag()[BUILD_CACHE_PREFIX.len()..], "1"));
+            std::cmp::Reverse((date.to_owned(), n.parse::<u64>().unwrap_or(0)))
+        });
+        for snapshot in snapshots {
+            if let Some(buf) = self.read_snapshot_file(&snapshot, Path::new(key))? {
+                return Ok(Some(buf));
+            }
+        }
+        Ok(None)
+    }
 
-        let mut index = PackIndex::new();
-        index.push_snapshot(snapshot.tag().to_owned(), pack_entries)?;
+    /// Writes the index of a loose snapshot, referencing loose objects which
+    /// must already exist.
+    fn save_loose_snapshot(
+        &self,
+        snapshot: &SnapshotId,
+        entries: impl IntoIterator<Item = FileEntry>,
+        mut metadata: SnapshotMetadata,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<(), Error> {
+        if metadata.created.is_none() {
+            metadata.created = Some(Utc::now().timestamp());
+        }
+        let mut index = PackIndex::with_checksum_algorithm(algorithm);
+        index.push_snapshot(snapshot.tag().to_owned(), entries)?;
+        index.set_snapshot_metadata(snapshot.tag(), metadata)?;
 
         let loose_path = self.data_dir().join(PACKS_DIR).join(LOOSE_DIR);
         ensure_dir(&loose_path)?;
@@ -620,10 +2057,43 @@ impl Repository {
                 .join(snapshot.tag())
                 .with_extension(PACK_INDEX_EXTENSION),
         )?;
+        Ok(())
+    }
 
-        self.update_head(snapshot)?;
+    /// Computes the differences between the HEAD snapshot and the specified
+    /// files in the working directory, by computing the checksums of the files.
+    /// The `added` files are the ones which are not present in HEAD (untracked),
+    /// and `removed` are the ones which are present in HEAD, but not on disk
+    /// (missing). If there is no HEAD, all files are reported as `added`.
+    pub fn compute_work_dir_diff<I, P>(&self, files: I) -> Result<SnapshotDiff, Error>
+    where
+        I: Iterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        // The checksums of the files must be comparable to the ones in HEAD.
+        let (head_entries, algorithm) = match self.read_head()?.0 {
+            Some(head) => (
+                self.load_snapshot_entries(&head)?,
+                self.checksum_algorithm(head.pack())?,
+            ),
+            None => (vec![], self.default_checksum_algorithm()?),
+        };
 
-        Ok(())
+        // The extended attributes are only compared if HEAD recorded them.
+        let mut opts = StoreOptions::default();
+        opts.set_xattrs(head_entries.iter().any(|e| !e.xattrs.is_empty()));
+
+        let files =
+            clean_file_list(self.path.as_ref(), self.work_data_dir(), files)?.collect::<Vec<_>>();
+        info!("Computing checksums for {} files...", files.len());
+        let work_dir_entries = run_in_parallel(num_cpus::get(), files.into_iter(), |file_path| {
+            read_file_entry(&self.path, file_path, algorithm, &opts, |_, _| None)
+                .map(|(entry, _, _)| entry)
+        })
+        .into_iter()
+        .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self::compute_snapshot_diff(head_entries, work_dir_entries))
     }
 
     /// Creates a pack file.
@@ -640,6 +2110,21 @@ impl Repository {
         opts: &PackOptions,
         reporter: &ProgressReporter,
     ) -> Result<(), Error> {
+        self.create_pack_with(pack, index, opts, reporter, &[])
+    }
+
+    /// Same as [`Repository::create_pack`], but the objects stored in the
+    /// `excluded` packs (e.g. the packs about to be replaced by the new one)
+    /// are not referenced (see [`PackOptions::dedup`]).
+    fn create_pack_with(
+        &mut self,
+        pack: &PackId,
+        index: PackIndex,
+        opts: &PackOptions,
+        reporter: &ProgressReporter,
+        excluded: &[PackId],
+    ) -> Result<(), Error> {
+        self.lock_write()?;
         let PackId::Pack(pack_name) = pack;
 
         // Construct output file path.
@@ -655,22 +2140,136 @@ impl Repository {
         ensure_dir(&temp_dir)?;
         let temp_path = create_temp_path(&temp_dir);
 
-        let (index, ordering) = index.compute_object_offsets_and_ordering();
+        // The objects already stored in other packs are only referenced, so
+        // they are left out of the chunks and deltas too. The objects of the
+        // entries copied from other packs are stored, unless found again.
+        let stored = if opts.dedup && opts.index_format == IndexFormat::Flat {
+            warn!("Flat indexes can't reference the objects stored in other packs!");
+            HashMap::new()
+        } else if opts.dedup {
+            info!("Finding the objects stored in other packs...");
+            let _span = Span::enter("find_stored_objects");
+            self.find_stored_objects(pack, &index, excluded)?
+        } else {
+            HashMap::new()
+        };
+        info!("Referencing {} objects stored in other packs", stored.len());
+        let mut index = index;
+        index.set_external_objects(&stored);
+        self.check_replaced_objects(pack, &index)?;
+
+        // The offsets of the objects depend on their chunks and on the size of
+        // their deltas, so compute those first.
+        let chunks = if opts.chunk {
+            info!("Splitting objects into chunks...");
+            let _span = Span::enter("compute_chunks");
+            self.compute_pack_chunks(&index, opts)?
+        } else {
+            PackChunks::default()
+        };
+        info!(
+            "Storing {} objects as {} chunks",
+            chunks.objects.len(),
+            chunks.sources.len()
+        );
+        let deltas = if opts.delta {
+            info!("Computing object deltas...");
+            let _span = Span::enter("compute_deltas");
+            self.compute_pack_deltas(&index, &chunks.objects, opts)?
+        } else {
+            PackDeltas::default()
+        };
+        info!("Storing {} objects as deltas", deltas.deltas.len());
+        let signatures = if opts.similarity {
+            info!("Computing object similarity signatures...");
+            let _span = Span::enter("compute_signatures");
+            self.compute_pack_signatures(&index, &deltas, &chunks, opts)?
+        } else {
+            HashMap::new()
+        };
+        let (mut index, groups) =
+            index.compute_object_offsets_and_ordering(&deltas.deltas, &chunks.objects, &signatures);
+        index.set_compression(CompressionParameters {
+            codec: opts.codec,
+            level: opts.compression_level,
+            window_log: opts.compression_window_log,
+        });
 
         // Gather a list of all objects to compress.
-        let object_partitions = index.objects_partitioned_by_size(opts.num_frames, &ordering);
+        let mut object_partitions = index.objects_partitioned_by_size(opts.num_frames, &groups);
+        if let Some(max_frame_size) = opts.max_frame_size {
+            object_partitions = object_partitions
+                .into_iter()
+                .flat_map(|objects| index.objects_split_by_size(max_frame_size, objects))
+                .collect();
+        }
+        let dictionary = match opts.dictionary_size {
+            Some(dictionary_size) if opts.codec == Codec::Zstd => {
+                info!("Training the compression dictionary...");
+                let _span = Span::enter("train_dictionary");
+                self.compute_pack_dictionary(&index, &object_partitions, opts, dictionary_size)?
+            }
+            Some(_) => {
+                warn!("Compression dictionaries are only supported by zstd!");
+                None
+            }
+            None => None,
+        };
+        if dictionary.is_some() {
+            object_partitions = split_small_objects(&index, object_partitions);
+        }
+        // The chunks are stored after the other objects, in their own frames.
+        let chunk_partitions = match opts.max_frame_size {
+            Some(max_frame_size) => split_by_u64(&chunks.sources, max_frame_size, |c| c.size),
+            None => {
+                // Share the frames according to the sizes of the chunks and of
+                // the other objects.
+                let chunks_size: u64 = chunks.sources.iter().map(|c| c.size).sum();
+                let objects_size: u64 = groups.iter().map(|g| index.group_stored_size(g)).sum();
+                let num_frames = (opts.num_frames as u64 * chunks_size)
+                    .div_ceil(std::cmp::max(1, chunks_size + objects_size));
+                partition_by_u64(&chunks.sources, std::cmp::max(1, num_frames) as u32, |c| {
+                    c.size
+                })
+            }
+        };
+        let object_partitions: Vec<_> = object_partitions
+            .into_iter()
+            .map(FrameContents::Objects)
+            .chain(
+                chunk_partitions
+                    .into_iter()
+                    .filter(|chunks| !chunks.is_empty())
+                    .map(FrameContents::Chunks),
+            )
+            .collect();
+        for (i, contents) in object_partitions.iter().enumerate() {
+            match contents {
+                FrameContents::Objects(groups) => debug!(
+                    "Frame {}: {} objects",
+                    i,
+                    groups.iter().map(|g| g.len()).sum::<usize>()
+                ),
+                FrameContents::Chunks(sources) => {
+                    debug!("Frame {}: {} chunks", i, sources.len())
+                }
+            }
+        }
 
         let workers_per_task = (opts.num_workers + object_partitions.len() as u32 - 1)
             / std::cmp::max(1, object_partitions.len()) as u32;
 
         let task_opts = batch::CompressionOptions {
+            codec: opts.codec,
             window_log: opts.compression_window_log,
             level: opts.compression_level,
             num_workers: workers_per_task,
+            dictionary: dictionary.as_deref().unwrap_or_default(),
         };
 
         // Keep count of done compression tasks
         let done_task_count = std::sync::atomic::AtomicUsize::new(0);
+        let done_bytes = std::sync::atomic::AtomicU64::new(0);
         let total_task_count = object_partitions.len();
 
         info!("Creating {} compressed frames...", total_task_count);
@@ -678,31 +2277,59 @@ impl Repository {
         let mut frames = vec![];
         let mut frame_bufs = vec![];
 
+        let span = Span::enter("compress_frames");
         let frame_results = run_in_parallel(
             opts.num_workers as usize,
             object_partitions.into_iter(),
-            |objects| {
-                let object_readers = objects.iter().map(|&handle| {
-                    // TODO: Method of obtaining readers from packs? Or we can
-                    // just assume packs first get unpacked.
-                    Ok(Box::new(open_file(
-                        self.loose_object_path(index.handle_to_checksum(handle)),
-                    )?))
-                });
-
+            |contents| {
                 let mut buf = vec![];
-                // Compress all the object files.
-                let r = batch::compress_files(
-                    &mut buf,
-                    object_readers,
-                    &task_opts,
-                    &ProgressReporter::dummy(),
-                )
+                let r = match contents {
+                    FrameContents::Objects(groups) => {
+                        let objects: Vec<_> = groups.iter().flatten().collect();
+                        let object_readers = objects.iter().map(|&handle| {
+                            // TODO: Method of obtaining readers from packs? Or we can
+                            // just assume packs first get unpacked.
+                            let path = match deltas.files.get(handle) {
+                                Some(delta_path) => delta_path.clone(),
+                                None => self.loose_object_path(index.handle_to_checksum(*handle)),
+                            };
+                            Ok(Box::new(open_file(path)?))
+                        });
+                        // Compress all the object files.
+                        batch::compress_files(
+                            &mut buf,
+                            object_readers,
+                            &task_opts,
+                            &ProgressReporter::dummy(),
+                        )
+                    }
+                    FrameContents::Chunks(sources) => {
+                        let chunk_readers = sources.iter().map(|source| {
+                            let path =
+                                self.loose_object_path(index.handle_to_checksum(source.object));
+                            let mut file = open_file(path)?;
+                            file.seek(io::SeekFrom::Start(source.offset))?;
+                            Ok(file.take(source.size))
+                        });
+                        batch::compress_files(
+                            &mut buf,
+                            chunk_readers,
+                            &task_opts,
+                            &ProgressReporter::dummy(),
+                        )
+                    }
+                }
                 .map(move |bytes| (bytes, buf));
                 // Update done count.
                 let done = done_task_count.fetch_add(1, std::sync::atomic::Ordering::AcqRel) + 1;
+                let bytes = match &r {
+                    Ok((bytes, _)) => *bytes,
+                    Err(_) => 0,
+                };
+                let bytes =
+                    done_bytes.fetch_add(bytes, std::sync::atomic::Ordering::AcqRel) + bytes;
                 // And report the change.
-                reporter.checkpoint(done, Some(total_task_count - done));
+                reporter.checkpoint_with_bytes(done, Some(total_task_count - done), bytes);
                 r
             },
         );
@@ -715,38 +2342,598 @@ impl Repository {
             });
             // Note: storing the whole file in memory at this point.
             // Could write them out, except that the header needs to be prepended.
-            frame_bufs.push(compressed_buffer);
+            frame_bufs.push((decompressed_size, compressed_buffer));
         }
+        drop(span);
 
         // Report that all compression tasks are done.
-        reporter.checkpoint(total_task_count, Some(0));
+        reporter.checkpoint_with_bytes(
+            total_task_count,
+            Some(0),
+            done_bytes.load(std::sync::atomic::Ordering::Acquire),
+        );
 
         // Create and serialize header.
-        let header = PackHeader::new(frames);
-        let header_bytes = rmp_serde::encode::to_vec(&header).expect("Serialization failed!");
+        let mut header = match dictionary {
+            Some(dictionary) => PackHeader::with_dictionary(frames, dictionary),
+            None => PackHeader::with_codec(frames, opts.codec),
+        };
+        if let Some(key) = &opts.encryption_key {
+            info!("Encrypting the frames with the key {}...", key.id());
+            header.encrypt_frames(key, frame_bufs.iter_mut().map(|(_, buf)| buf));
+            // So that the new pack can be read.
+            if self.encryption_keys.iter().all(|k| k.id() != key.id()) {
+                self.encryption_keys.push(key.clone());
+            }
+        }
+        let header_bytes = header.to_bytes();
 
         // And a writer to that temporary file.
+        let _span = Span::enter("write_pack");
         let mut pack_writer = io::BufWriter::new(create_file(&temp_path, None)?);
         // Write header and frames.
-        write_skippable_frame(&mut pack_writer, &header_bytes)?;
-        for frame_buf in frame_bufs {
+        // The frames are recorded in the index too, so that the frames
+        // containing some objects can be located without reading the pack.
+        let mut offset = write_skippable_frame(&mut pack_writer, &header_bytes)?;
+        let mut frame_boundaries = vec![];
+        for (decompressed_size, frame_buf) in frame_bufs {
             pack_writer.write_all(&frame_buf)?;
+            frame_boundaries.push(FrameBoundary {
+                offset,
+                size: frame_buf.len() as u64,
+                decompressed_size,
+            });
+            offset += frame_buf.len() as u64;
         }
+        index.set_frames(frame_boundaries);
         pack_writer.flush()?;
-        drop(pack_writer);
+        pack_writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_data()?;
 
         let index_path = pack_path.with_extension(PACK_INDEX_EXTENSION);
         info!("Write index: {}", index_path.display());
-        index.save(index_path)?;
-
-        // Finally, move the .pack file itself to the packs/ dir.
+        let temp_index_path = create_temp_path(&temp_dir);
+        let index_key = opts.encryption_key.as_ref().filter(|_| opts.encrypt_index);
+        index.set_format(opts.index_format);
+        self.save_index(&index, &temp_index_path, index_key)?;
+
+        // Finally, move both files to the packs/ dir. Packs are found through
+        // their index, so the index is moved last: the pack is not visible
+        // until both files are complete. Syncing the directory makes the
+        // renames themselves durable. The signatures of a previous pack with
+        // the same name are not valid for the new one.
+        self.remove_pack_signature(pack)?;
         fs::rename(&temp_path, &pack_path)?;
+        fs::rename(&temp_index_path, &index_path)?;
+        sync_dir(pack_path.parent().unwrap())?;
+        self.record_pack_objects(pack, &index, index_key.is_some());
 
         Ok(())
     }
 
-    /// Deletes ALL loose snapshots and objects.
-    pub fn remove_loose_all(&mut self) -> Result<(), Error> {
+    /// Finds the objects of the index which are already stored in other packs
+    /// (see [`PackOptions::dedup`]), with the name of the pack storing each.
+    /// Only the local packs whose index isn't encrypted are considered, except
+    /// the `excluded` ones.
+    fn find_stored_objects(
+        &self,
+        pack_id: &PackId,
+        index: &PackIndex,
+        excluded: &[PackId],
+    ) -> Result<HashMap<ObjectChecksum, String>, Error> {
+        let object_index = self.load_object_index()?;
+        // The candidate packs, or None for the packs which are skipped.
+        let mut candidates: HashMap<PackId, Option<Arc<PackIndex>>> = HashMap::new();
+        let mut stored = HashMap::new();
+        for checksum in index.object_checksums() {
+            if index.object_metadata(checksum).size == 0 {
+                continue;
+            }
+            for (candidate, _) in object_index.get(checksum) {
+                if !candidates.contains_key(&candidate) {
+                    let is_skipped = candidate == *pack_id
+                        || pack_id.external_name(&candidate).is_none()
+                        || excluded.contains(&candidate)
+                        || self.is_pack_loose(&candidate)
+                        || !self.get_pack_path(&candidate).exists()
+                        || self.is_index_encrypted(&candidate)?;
+                    let candidate_index = if is_skipped {
+                        None
+                    } else {
+                        Some(self.load_index_cached(&candidate)?)
+                            .filter(|i| i.checksum_algorithm() == index.checksum_algorithm())
+                    };
+                    candidates.insert(candidate.clone(), candidate_index);
+                }
+                // The objects the candidate reads from other packs are skipped,
+                // so that the references are never chained.
+                let is_stored = candidates[&candidate]
+                    .as_ref()
+                    .and_then(|i| i.lookup_object_metadata(checksum))
+                    .is_some_and(|md| md.pack.is_none());
+                if is_stored {
+                    // The candidates are in the directory of the pack, so
+                    // that they can be named.
+                    let name = pack_id.external_name(&candidate).unwrap();
+                    stored.insert(*checksum, name);
+                    break;
+                }
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Checks that the objects which other packs read from the pack (see
+    /// [`ObjectMetadata::pack`]) are still stored in its new index, when it
+    /// replaces the pack (e.g. when removing some of its snapshots).
+    fn check_replaced_objects(&self, pack_id: &PackId, index: &PackIndex) -> Result<(), Error> {
+        if self.is_pack_loose(pack_id) || !self.get_pack_index_path(pack_id).exists() {
+            return Ok(());
+        }
+        let packs: Vec<_> = self
+            .external_references(pack_id)?
+            .into_iter()
+            .filter(|(_, checksums)| {
+                checksums.iter().any(|checksum| {
+                    index
+                        .lookup_object_metadata(checksum)
+                        .is_none_or(|md| md.pack.is_some())
+                })
+            })
+            .map(|(pack, _)| pack)
+            .collect();
+        if packs.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::PackReferenced(pack_id.clone(), packs))
+        }
+    }
+
+    /// The packs which read some of their objects from the pack (see
+    /// [`ObjectMetadata::pack`]), along with these objects. The packs whose
+    /// index can't be decrypted are skipped.
+    fn external_references(
+        &self,
+        pack_id: &PackId,
+    ) -> Result<Vec<(PackId, Vec<ObjectChecksum>)>, Error> {
+        let mut references = vec![];
+        for other in self.packs()? {
+            if other == *pack_id || self.is_pack_loose(&other) {
+                continue;
+            }
+            let mut header = vec![];
+            open_file(self.get_pack_index_path(&other))?
+                .take(8)
+                .read_to_end(&mut header)?;
+            if !PackIndex::may_have_external_objects(&header) {
+                continue;
+            }
+            let index = match self.load_index_cached(&other) {
+                Ok(index) => index,
+                Err(e) if encryption::is_encrypted_index(&header) => {
+                    warn!("Skipping the references of {}: {}", other, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let checksums: Vec<_> = index
+                .object_checksums()
+                .filter(|checksum| {
+                    index
+                        .object_metadata(checksum)
+                        .pack
+                        .as_ref()
+                        .is_some_and(|pack| {
+                            other.resolve_external(pack).ok().as_ref() == Some(pack_id)
+                        })
+                })
+                .copied()
+                .collect();
+            if !checksums.is_empty() {
+                references.push((other, checksums));
+            }
+        }
+        Ok(references)
+    }
+
+    /// The names of the packs which the pack reads some of its objects from
+    /// (see [`ObjectMetadata::pack`] and [`PackId::resolve_external`]).
+    pub fn external_packs(&self, pack_id: &PackId) -> Result<Vec<String>, Error> {
+        let mut header = vec![];
+        open_file(self.get_pack_index_path(pack_id))?
+            .take(8)
+            .read_to_end(&mut header)?;
+        if !PackIndex::may_have_external_objects(&header) {
+            return Ok(vec![]);
+        }
+        let index = self.load_index_cached(pack_id)?;
+        Ok(Self::index_external_packs(&index))
+    }
+
+    fn index_external_packs(index: &PackIndex) -> Vec<String> {
+        let packs: BTreeSet<_> = index
+            .object_checksums()
+            .filter_map(|checksum| index.object_metadata(checksum).pack.clone())
+            .collect();
+        packs.into_iter().collect()
+    }
+
+    /// Fails with [`Error::PackReferenced`] if other packs read some of their
+    /// objects from the pack (see [`PackOptions::dedup`]), which then can't be
+    /// deleted, renamed or upgraded.
+    pub fn check_pack_unreferenced(&self, pack_id: &PackId) -> Result<(), Error> {
+        let packs: Vec<_> = self
+            .external_references(pack_id)?
+            .into_iter()
+            .map(|(pack, _)| pack)
+            .collect();
+        if packs.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::PackReferenced(pack_id.clone(), packs))
+        }
+    }
+
+    /// Chooses the objects of the pack to store as deltas (see
+    /// [`PackOptions::delta`]) and writes their deltas to temporary files. Each
+    /// version of a path is encoded as a delta against the last version of the
+    /// path which is stored in full, unless the delta is too large, in which
+    /// case the version is stored in full (and becomes the new base).
+    fn compute_pack_deltas(
+        &self,
+        index: &PackIndex,
+        chunked: &HashMap<Handle, Vec<ObjectChunk>>,
+        opts: &PackOptions,
+    ) -> Result<PackDeltas, Error> {
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        let read_object = |handle| -> Result<Vec<u8>, Error> {
+            let mut buf = vec![];
+            open_file(self.loose_object_path(index.handle_to_checksum(handle)))?
+                .read_to_end(&mut buf)?;
+            Ok(buf)
+        };
+
+        // The objects stored as chunks are not stored as deltas (nor used as
+        // their base).
+        let versions: Vec<_> = index
+            .object_versions_by_path()
+            .into_iter()
+            .map(|versions| {
+                versions
+                    .into_iter()
+                    .filter(|handle| !chunked.contains_key(handle))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|versions| versions.len() > 1)
+            .collect();
+        let results = run_in_parallel(
+            std::cmp::max(1, opts.num_workers) as usize,
+            versions.into_iter(),
+            |versions| -> Result<Vec<(Handle, Handle, u64, PathBuf)>, Error> {
+                let mut deltas = vec![];
+                let mut base = (versions[0], read_object(versions[0])?);
+                for &handle in &versions[1..] {
+                    let object = read_object(handle)?;
+                    let delta = encode_delta(&base.1, &object, opts.compression_level)?;
+                    if delta.len() as f64 <= MAX_DELTA_RATIO * object.len() as f64 {
+                        let delta_path = create_temp_path(&temp_dir);
+                        create_file(&delta_path, None)?.write_all(&delta)?;
+                        deltas.push((handle, base.0, delta.len() as u64, delta_path));
+                    } else {
+                        base = (handle, object);
+                    }
+                }
+                Ok(deltas)
+            },
+        );
+
+        // Record all the files written before checking for errors, so they
+        // get removed.
+        let mut pack_deltas = PackDeltas::default();
+        let mut error = None;
+        for result in results {
+            match result {
+                Ok(deltas) => {
+                    for (handle, base, delta_size, delta_path) in deltas {
+                        pack_deltas.deltas.insert(handle, (base, delta_size));
+                        pack_deltas.files.insert(handle, delta_path);
+                    }
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(pack_deltas),
+        }
+    }
+
+    /// Splits the large objects of the pack into chunks (see
+    /// [`PackOptions::chunk`]). Each distinct chunk is stored once, in the order
+    /// in which it is first found in the objects (the versions of each path, in
+    /// order), so the new chunks of each version are stored together.
+    fn compute_pack_chunks(
+        &self,
+        index: &PackIndex,
+        opts: &PackOptions,
+    ) -> Result<PackChunks, Error> {
+        let objects: Vec<Handle> = index
+            .object_versions_by_path()
+            .into_iter()
+            .flatten()
+            .filter(|&handle| {
+                let checksum = index.handle_to_checksum(handle);
+                index.object_metadata(checksum).size >= MIN_CHUNKED_OBJECT_SIZE
+            })
+            .collect();
+        let algorithm = index.checksum_algorithm();
+        let results = run_in_parallel(
+            std::cmp::max(1, opts.num_workers) as usize,
+            objects.iter(),
+            |&handle| -> Result<Vec<(ObjectChecksum, u64)>, Error> {
+                let mut buf = vec![];
+                open_file(self.loose_object_path(index.handle_to_checksum(handle)))?
+                    .read_to_end(&mut buf)?;
+                let mut start = 0;
+                Ok(chunk_boundaries(&buf)
+                    .into_iter()
+                    .map(|end| {
+                        let chunk = (algorithm.checksum(&buf[start..end]), (end - start) as u64);
+                        start = end;
+                        chunk
+                    })
+                    .collect())
+            },
+        );
+
+        let mut pack_chunks = PackChunks::default();
+        let mut chunk_offsets: HashMap<ObjectChecksum, u64> = HashMap::new();
+        let mut chunks_size = 0;
+        for (&handle, result) in objects.iter().zip(results) {
+            let mut start = 0;
+            let chunks = result?
+                .into_iter()
+                .map(|(checksum, size)| {
+                    let offset = *chunk_offsets.entry(checksum).or_insert_with(|| {
+                        pack_chunks.sources.push(ChunkSource {
+                            object: handle,
+                            offset: start,
+                            size,
+                        });
+                        chunks_size += size;
+                        chunks_size - size
+                    });
+                    start += size;
+                    ObjectChunk { offset, size }
+                })
+                .collect();
+            pack_chunks.objects.insert(handle, chunks);
+        }
+        Ok(pack_chunks)
+    }
+
+    /// Computes the similarity signatures of the objects of the pack which are
+    /// stored in full (see [`PackOptions::similarity`]), by which they are
+    /// sorted.
+    fn compute_pack_signatures(
+        &self,
+        index: &PackIndex,
+        deltas: &PackDeltas,
+        chunks: &PackChunks,
+        opts: &PackOptions,
+    ) -> Result<HashMap<Handle, Signature>, Error> {
+        let objects: HashSet<Handle> = index
+            .object_versions_by_path()
+            .into_iter()
+            .flatten()
+            .filter(|handle| {
+                !deltas.deltas.contains_key(handle) && !chunks.objects.contains_key(handle)
+            })
+            .collect();
+        run_in_parallel(
+            std::cmp::max(1, opts.num_workers) as usize,
+            objects.into_iter(),
+            |handle| {
+                let buf = fs::read(self.loose_object_path(index.handle_to_checksum(handle)))?;
+                Ok((handle, signature(&buf)))
+            },
+        )
+        .into_iter()
+        .collect()
+    }
+
+    /// Trains a compression dictionary of the specified size on a sample of
+    /// the small objects of the pack (see [`PackOptions::dictionary_size`]),
+    /// which are then compressed in frames of their own instead of in
+    /// `frames`. Returns [`None`] if there are too few samples to train a
+    /// dictionary, or if the dictionary doesn't pay for itself, which is
+    /// estimated by compressing the samples both ways.
+    fn compute_pack_dictionary(
+        &self,
+        index: &PackIndex,
+        frames: &[&[Vec<Handle>]],
+        opts: &PackOptions,
+        dictionary_size: usize,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        // The small objects, along with the index of their frame.
+        let objects: Vec<(usize, Handle)> = frames
+            .iter()
+            .enumerate()
+            .flat_map(|(i, groups)| {
+                groups
+                    .iter()
+                    .filter(|group| is_small_group(index, group))
+                    .map(move |group| (i, group[0]))
+            })
+            .collect();
+        let sizes: Vec<u64> = objects
+            .iter()
+            .map(|&(_, handle)| index.object_metadata(index.handle_to_checksum(handle)).size)
+            .collect();
+        let selected = select_samples(&sizes, dictionary_size);
+        let samples_size: u64 = selected.iter().map(|&i| sizes[i]).sum();
+        if !has_enough_samples(selected.len(), samples_size, dictionary_size) {
+            info!(
+                "Not using a compression dictionary: there are too few small objects \
+                ({} bytes in {} objects)",
+                samples_size,
+                selected.len()
+            );
+            return Ok(None);
+        }
+        let samples = selected
+            .into_iter()
+            .map(|i| {
+                let (frame, handle) = objects[i];
+                let sample = fs::read(self.loose_object_path(index.handle_to_checksum(handle)))?;
+                Ok((frame, sample))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let sample_bufs: Vec<_> = samples.iter().map(|(_, sample)| sample).collect();
+        let dictionary = match train_dictionary(&sample_bufs, dictionary_size) {
+            Ok(dictionary) => dictionary,
+            Err(e) => {
+                warn!("Not using a compression dictionary: {}", e);
+                return Ok(None);
+            }
+        };
+        info!(
+            "Trained a {} byte dictionary on {} objects",
+            dictionary.len(),
+            samples.len()
+        );
+
+        // The sizes of the samples are extrapolated to all the small objects.
+        let (without_dictionary, with_dictionary) =
+            compressed_sizes(&samples, &dictionary, opts.compression_level)?;
+        let scale = sizes.iter().sum::<u64>() as f64 / samples_size as f64;
+        let without_dictionary = (without_dictionary as f64 * scale) as u64;
+        let with_dictionary = (with_dictionary as f64 * scale) as u64 + dictionary.len() as u64;
+        if with_dictionary >= without_dictionary {
+            info!(
+                "Not using the compression dictionary, which doesn't shrink the small \
+                objects (about {} bytes with it, {} bytes without)",
+                with_dictionary, without_dictionary
+            );
+            return Ok(None);
+        }
+        info!(
+            "The compression dictionary shrinks the small objects from about {} to {} bytes",
+            without_dictionary, with_dictionary
+        );
+        Ok(Some(dictionary))
+    }
+
+    /// Creates a pack file containing the specified snapshots, which can be
+    /// stored in any pack (including loose snapshots and the pack which is
+    /// being created, which gets replaced). The objects which are only
+    /// available in packs are first unpacked into the loose object store and
+    /// are removed from it once the new pack is created.
+    ///
+    /// # Arguments
+    ///
+    /// * `pack` - The name of the pack file to create
+    /// * `snapshots` - The snapshots to include in the new pack, in order
+    /// * `opts` - Additional options to use during pack creation
+    pub fn create_pack_from_snapshots(
+        &mut self,
+        pack: &PackId,
+        snapshots: &[SnapshotId],
+        opts: &PackOptions,
+        reporter: &ProgressReporter,
+    ) -> Result<(), Error> {
+        self.lock_write()?;
+        // The checksums are copied from the source indexes, so the new index
+        // uses the same checksum algorithm.
+        let algorithm = match snapshots.first() {
+            Some(snapshot) => self.checksum_algorithm(snapshot.pack())?,
+            None => self.default_checksum_algorithm()?,
+        };
+        let mut index = PackIndex::with_checksum_algorithm(algorithm);
+        // The entries that need to be unpacked, grouped by the source pack.
+        let mut packed_entries: HashMap<PackId, HashMap<ObjectChecksum, FileEntry>> =
+            HashMap::new();
+        for snapshot in snapshots {
+            let source_index = self.load_index_cached(snapshot.pack())?;
+            if source_index.checksum_algorithm() != algorithm {
+                return Err(PackError::ChecksumAlgorithmMismatch(
+                    algorithm,
+                    source_index.checksum_algorithm(),
+                )
+                .into());
+            }
+            let handles = source_index
+                .resolve_snapshot(snapshot.tag())
+                .ok_or_else(|| PackError::SnapshotNotFound(snapshot.tag().to_owned()))?;
+            let entries = source_index.entries_from_handles(handles.iter())?;
+            if !self.is_pack_loose(snapshot.pack()) {
+                let pack_entries = packed_entries.entry(snapshot.pack().clone()).or_default();
+                for entry in &entries {
+                    pack_entries
+                        .entry(entry.checksum)
+                        .or_insert_with(|| entry.clone());
+                }
+            }
+            index.push_snapshot(snapshot.tag().to_owned(), entries)?;
+            if let Some(metadata) = source_index.snapshot_metadata(snapshot.tag()) {
+                index.set_snapshot_metadata(snapshot.tag(), metadata.clone())?;
+            }
+        }
+
+        let mut unpacked = vec![];
+        let result = (|| -> Result<(), Error> {
+            for (pack_id, entries) in &packed_entries {
+                let entries: Vec<_> = entries.values().cloned().collect();
+                info!("Unpacking {} objects from {}...", entries.len(), pack_id);
+                self.unpack_entries(pack_id, &entries, &mut unpacked)?;
+            }
+            // The source packs may be deleted once the pack is created, so
+            // their objects are not referenced.
+            let sources: Vec<_> = packed_entries.keys().cloned().collect();
+            self.create_pack_with(pack, index, opts, reporter, &sources)
+        })();
+
+        // Remove the objects that were unpacked temporarily.
+        if !unpacked.is_empty() {
+            info!("Removing {} unpacked objects...", unpacked.len());
+        }
+        for checksum in &unpacked {
+            self.delete_object(checksum)?;
+        }
+
+        result
+    }
+
+    /// Writes the objects of the specified entries which are not already
+    /// present to the loose object store. The checksums of the newly written
+    /// objects are appended to `unpacked`.
+    fn unpack_entries(
+        &self,
+        pack_id: &PackId,
+        entries: &[FileEntry],
+        unpacked: &mut Vec<ObjectChecksum>,
+    ) -> Result<(), Error> {
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+
+        let missing: Vec<_> = entries
+            .iter()
+            .filter(|entry| !self.loose_object_path(&entry.checksum).exists())
+            .cloned()
+            .collect();
+
+        self.read_entries(pack_id, &missing, true, |entry, buf| {
+            self.write_loose_object(buf, &temp_dir, &entry.checksum)?;
+            unpacked.push(entry.checksum);
+            Ok(())
+        })
+    }
+
+    /// Deletes ALL loose snapshots and objects.
+    pub fn remove_loose_all(&mut self) -> Result<(), Error> {
         let loose_dir = self.data_dir().join(LOOSE_DIR);
 
         match fs::remove_dir_all(loose_dir) {
@@ -758,12 +2945,66 @@ impl Repository {
 
     /// Updates the HEAD snapshot id.
     pub fn update_head(&mut self, snapshot_id: &SnapshotId) -> Result<(), Error> {
+        let path = self.path.clone();
+        self.update_head_of(&path, snapshot_id)
+    }
+
+    /// Updates the HEAD snapshot id of a directory snapshots are extracted to
+    /// (see [`Repository::extract_snapshot_to`]).
+    fn update_head_of(&mut self, work_dir: &Path, snapshot_id: &SnapshotId) -> Result<(), Error> {
+        self.lock_write()?;
+        let head_path = self.head_path(work_dir);
+        self.write_snapshot_id(&head_path, snapshot_id)
+    }
+
+    /// Writes the snapshot id to HEAD (or a file next to it) atomically.
+    fn write_snapshot_id(&self, path: &Path, snapshot_id: &SnapshotId) -> Result<(), Error> {
         let snapshot_string = format!("{snapshot_id}\n");
+        // The temporary file must be on the same filesystem as HEAD.
+        let temp_dir = if path.starts_with(self.data_dir()) {
+            self.temp_dir()
+        } else {
+            path.parent().unwrap().to_owned()
+        };
+        ensure_dir(&temp_dir)?;
+        write_file_atomic(snapshot_string.as_bytes(), &temp_dir, path)?;
+        Ok(())
+    }
+
+    /// The path of the HEAD of a directory snapshots are extracted to. The HEAD
+    /// of the working directory is in the repository data directory (unless it
+    /// is a linked working tree), the others are in a data directory of their
+    /// own, which only contains HEAD.
+    fn head_path(&self, work_dir: &Path) -> PathBuf {
+        if work_dir == self.path {
+            self.work_data_dir().join(HEAD_FILE)
+        } else {
+            work_dir.join(REPO_DIR).join(HEAD_FILE)
+        }
+    }
+
+    /// The checksum algorithm used for the new snapshots created in the
+    /// repository. Defaults to [`ChecksumAlgorithm::default`].
+    pub fn default_checksum_algorithm(&self) -> Result<ChecksumAlgorithm, Error> {
+        match fs::read_to_string(self.data_dir().join(CHECKSUM_ALGORITHM_FILE)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ChecksumAlgorithm::default()),
+            Err(e) => Err(e.into()),
+            Ok(name) => name.trim().parse().map_err(Error::BadChecksumAlgorithm),
+        }
+    }
+
+    /// Changes the checksum algorithm used for new snapshots. Existing packs
+    /// keep their algorithm until converted with [`Repository::upgrade_pack`].
+    pub fn set_default_checksum_algorithm(
+        &mut self,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<(), Error> {
+        let name = format!("{}\n", algorithm.name());
         ensure_dir(&self.temp_dir())?;
         write_file_atomic(
-            snapshot_string.as_bytes(),
+            name.as_bytes(),
             &self.temp_dir(),
-            &self.data_dir().join(HEAD_FILE),
+            &self.data_dir().join(CHECKSUM_ALGORITHM_FILE),
         )?;
         Ok(())
     }
@@ -774,23 +3015,218 @@ impl Repository {
         path.push(name);
         path.set_extension("esi");
 
-        let agent = ureq::AgentBuilder::new().build();
+        let agent = self.http_agent();
         let reporter = (self.progress_reporter_factory)(&format!(
             "Fetching remote repository index from {name}"
         ));
 
         reporter.checkpoint_with_detail(0, Some(1), url.to_owned());
-        remote::fetch_remote(&agent, url, &path)?;
+        let mirrors = self.remote_mirrors.get(name).cloned().unwrap_or_default();
+        remote::fetch_remote(&agent, url, &mirrors, &path)?;
         reporter.checkpoint_with_detail(1, Some(0), url.to_owned());
 
         Ok(())
     }
 
+    /// Uploads the .pack and .pack.idx files of the pack (and its .pack.sig, if
+    /// it is signed) to `destination`,
+    /// which is either the URL of a directory or the name of a remote. For a
+    /// remote, the files are uploaded to the `packs/` directory next to its
+    /// .esi (the layout used by `elfshaker serve`). The .pack is uploaded
+    /// first, so that the pack is complete once its index is available.
+    ///
+    /// The `s3://bucket/prefix` URLs (or remotes) of object storage name the
+    /// directory of the repository: the files are uploaded to its `packs/`,
+    /// then the pack is added to its `index.esi`.
+    pub fn push_pack(
+        &self,
+        pack_id: &PackId,
+        destination: &str,
+        token: Option<&str>,
+    ) -> Result<(), Error> {
+        let pack_path = self.get_pack_path(pack_id);
+        let pack_index_path = self.get_pack_index_path(pack_id);
+        if self.is_pack_loose(pack_id) || !pack_path.exists() {
+            return Err(Error::PackNotFound(pack_id.to_string()));
+        }
+
+        let destination_url = match url::Url::parse(destination) {
+            Ok(url) => url,
+            Err(_) => {
+                let remotes = remote::load_remotes(&self.data_dir().join(REMOTES_DIR))?;
+                let remote = remotes
+                    .iter()
+                    .find(|r| r.name().as_deref() == Some(destination))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("'{destination}' is neither a URL nor a remote"),
+                        )
+                    })?;
+                let url = url::Url::parse(remote.url()).map_err(|e| Error::HttpError(e.into()))?;
+                if remote::is_object_storage_url(&url) {
+                    url
+                } else {
+                    url.join("packs/").map_err(|e| Error::HttpError(e.into()))?
+                }
+            }
+        };
+        // The .esi of a repository in object storage is updated by the push,
+        // since there is no server to generate it.
+        let (base_url, index_url) = if remote::is_object_storage_url(&destination_url) {
+            let index_url = remote::remote_index_url(&destination_url);
+            let base_url = index_url
+                .join("packs/")
+                .map_err(|e| Error::HttpError(e.into()))?;
+            (base_url, Some(index_url))
+        } else {
+            (destination_url, None)
+        };
+
+        // The pack is pushed to the packs directory of the destination, where
+        // only the packs it names without a directory can be found.
+        let unavailable: Vec<_> = self
+            .external_packs(pack_id)?
+            .into_iter()
+            .filter(|pack| pack.contains('/'))
+            .collect();
+        if !unavailable.is_empty() {
+            return Err(Error::MissingExternalPacks(pack_id.clone(), unavailable));
+        }
+
+        let agent = self.http_agent();
+        let reporter = (self.progress_reporter_factory)(&format!("Pushing {pack_id}"));
+        // The signature (if any) is available before the index, so that the
+        // index can be verified once it is available.
+        let signature_path = self.get_pack_signature_path(pack_id);
+        let mut files = vec![&pack_path];
+        if signature_path.exists() {
+            files.push(&signature_path);
+        }
+        files.push(&pack_index_path);
+        for (i, path) in files.iter().enumerate() {
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            reporter.checkpoint_with_detail(i, Some(files.len() - i), file_name.to_string());
+            // Make sure the base URL is treated as a directory when joining.
+            let url = if base_url.path().ends_with('/') {
+                base_url.join(&file_name)
+            } else {
+                url::Url::parse(&format!("{base_url}/")).and_then(|url| url.join(&file_name))
+            }
+            .map_err(|e| Error::HttpError(e.into()))?;
+            remote::upload_file(&agent, &url, path, token)?;
+        }
+        if let Some(index_url) = index_url {
+            let file_name = pack_path.file_name().unwrap().to_string_lossy();
+            let pack = remote::RemotePack {
+                index_checksum: remote::compute_checksum(&pack_index_path)?,
+                pack_checksum: remote::compute_checksum(&pack_path)?,
+                url: format!("packs/{file_name}"),
+                mirror_urls: vec![],
+            };
+            remote::add_pack_to_remote_index(&agent, &index_url, pack)?;
+        }
+        reporter.checkpoint(files.len(), Some(0));
+
+        Ok(())
+    }
+
+    /// Writes a .pack, .pack.idx or .pack.sig file received from another
+    /// repository (see [`Repository::push_pack`]) to the packs directory,
+    /// atomically replacing any existing file. Pack indexes and signatures are
+    /// read to memory and validated (see [`PackIndex::check_consistency`])
+    /// before being written. The files larger than `max_pack_size` (for the
+    /// .pack) or `max_index_size` (for the others) bytes are rejected.
+    pub fn write_pack_file(
+        &self,
+        file_name: &str,
+        reader: impl Read,
+        max_pack_size: u64,
+        max_index_size: u64,
+    ) -> Result<(), Error> {
+        let index_suffix = format!(".{PACK_INDEX_EXTENSION}");
+        let signature_suffix = format!(".{PACK_SIGNATURE_EXTENSION}");
+        let pack_suffix = format!(".{PACK_EXTENSION}");
+        let (pack, extension) = if let Some(pack) = file_name.strip_suffix(&index_suffix) {
+            (pack, PACK_INDEX_EXTENSION)
+        } else if let Some(pack) = file_name.strip_suffix(&signature_suffix) {
+            (pack, PACK_SIGNATURE_EXTENSION)
+        } else if let Some(pack) = file_name.strip_suffix(&pack_suffix) {
+            (pack, PACK_EXTENSION)
+        } else {
+            return Err(IdError::InvalidPack(file_name.to_owned()).into());
+        };
+        // Only top-level packs can be written.
+        let pack_id = PackId::from_str(pack)?;
+        if pack.contains('/') || self.is_pack_loose(&pack_id) {
+            return Err(IdError::InvalidPack(pack.to_owned()).into());
+        }
+
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        ensure_dir(&self.data_dir().join(PACKS_DIR))?;
+        if extension == PACK_EXTENSION {
+            let path = self.get_pack_path(&pack_id);
+            if !write_file_atomic_limited(reader, max_pack_size, &temp_dir, &path)? {
+                return Err(Error::FileTooLarge(file_name.to_owned(), max_pack_size));
+            }
+            return Ok(());
+        }
+
+        let mut buf = vec![];
+        reader
+            .take(max_index_size.saturating_add(1))
+            .read_to_end(&mut buf)?;
+        if buf.len() as u64 > max_index_size {
+            return Err(Error::FileTooLarge(file_name.to_owned(), max_index_size));
+        }
+        match extension {
+            PACK_INDEX_EXTENSION => {
+                // Encrypted indexes can only be checked with the key. The packs
+                // storing some of the objects of the pack must be pushed first.
+                if !encryption::is_encrypted_index(&buf) {
+                    let index = PackIndex::parse(buf.as_slice())?;
+                    index.check_consistency()?;
+                    let missing: Vec<_> = Self::index_external_packs(&index)
+                        .into_iter()
+                        .filter(|pack| {
+                            pack.contains('/')
+                                || pack_id
+                                    .resolve_external(pack)
+                                    .map_or(true, |id| !self.get_pack_index_path(&id).exists())
+                        })
+                        .collect();
+                    if !missing.is_empty() {
+                        return Err(Error::MissingExternalPacks(pack_id, missing));
+                    }
+                }
+                write_file_atomic(
+                    buf.as_slice(),
+                    &temp_dir,
+                    &self.get_pack_index_path(&pack_id),
+                )?;
+            }
+            _ => {
+                PackSignature::read(buf.as_slice())?;
+                write_file_atomic(
+                    buf.as_slice(),
+                    &temp_dir,
+                    &self.get_pack_signature_path(&pack_id),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
     /// Identifies duplicate snapshots in the given packs.
     /// (two snapshots are equal if their checksums computed by compute_snapshot_checksum are equal).
     ///
     /// Returns a mapping of snapshot checksum to `SnapshotId`s with that checksum (>= 1).
     ///
+    /// Loose snapshots are also grouped by the checksums computed with the
+    /// algorithms of the other packs, so that they are found to be equal to
+    /// snapshots in packs which have not been upgraded yet.
+    ///
     /// # Algorithm
     /// 1. Load up all packs indexes
     /// 2. Compute snapshot checksums and store in Checksum -> SnapshotId map
@@ -798,6 +3234,12 @@ impl Repository {
         &self,
         packs: &[PackId],
     ) -> Result<HashMap<ObjectChecksum, Vec<SnapshotId>>, Error> {
+        let pack_algorithms = packs
+            .iter()
+            .filter(|pack_id| !self.is_pack_loose(pack_id))
+            .map(|pack_id| self.checksum_algorithm(pack_id))
+            .collect::<Result<HashSet<_>, _>>()?;
+
         // Deduplicate the snapshots in all packs (group by checksum)
         let checksum_to_group =
             Arc::new(Mutex::new(HashMap::<ObjectChecksum, Vec<SnapshotId>>::new()));
@@ -808,10 +3250,10 @@ impl Repository {
             num_cpus::get(),
             packs.iter().by_ref(),
             |pack_id| -> Result<_, Error> {
-                let pack = self.load_index(pack_id)?;
+                let pack = self.load_index_cached(pack_id)?;
                 // Task local map of snapshot -> checksum that will be merged
                 // into the results map.
-                let snapshot_checksums: HashMap<String, _> = pack
+                let mut snapshot_checksums: Vec<(String, _)> = pack
                     .snapshot_tags()
                     .iter()
                     .map(|tag| {
@@ -822,11 +3264,19 @@ impl Repository {
                         )
                     })
                     .collect();
+                if self.is_pack_loose(pack_id) {
+                    for &algorithm in &pack_algorithms {
+                        if algorithm != pack.checksum_algorithm() {
+                            snapshot_checksums
+                                .extend(self.compute_loose_snapshot_checksums(pack_id, algorithm)?);
+                        }
+                    }
+                }
 
                 // Lock the results map and add the checksums in this pack
                 let mut checksum_to_group = checksum_to_group_clone.lock().unwrap();
                 for (tag, checksum) in snapshot_checksums {
-                    let entry = checksum_to_group.entry(checksum).or_insert_with(Vec::new);
+                    let entry = checksum_to_group.entry(checksum).or_default();
                     entry.push(SnapshotId::new(pack_id.clone(), &tag).unwrap());
                 }
                 Ok(())
@@ -839,18 +3289,312 @@ impl Repository {
         Ok(std::mem::take(&mut *results))
     }
 
+    /// Computes the checksums of the snapshots in the loose pack as if its
+    /// index used the given checksum algorithm, by reading all of its objects.
+    fn compute_loose_snapshot_checksums(
+        &self,
+        pack_id: &PackId,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<Vec<(String, ObjectChecksum)>, Error> {
+        let mut index = self.load_index(pack_id)?;
+        let mut checksums = HashMap::new();
+        let mut buf = vec![];
+        for checksum in index.object_checksums() {
+            buf.clear();
+            open_file(self.loose_object_path(checksum))?.read_to_end(&mut buf)?;
+            checksums.insert(*checksum, algorithm.checksum(&buf));
+        }
+        index.convert_checksums(algorithm, |checksum| checksums.get(checksum).copied())?;
+
+        Ok(index
+            .snapshot_tags()
+            .iter()
+            .map(|tag| {
+                let checksum = index
+                    .compute_snapshot_checksum(tag)
+                    .expect("failed to resolve snapshot");
+                (tag.clone(), checksum)
+            })
+            .collect())
+    }
+
+    /// Finds all snapshots that contain files whose path matches, searching
+    /// the packs in parallel.
+    ///
+    /// Returns the list of snapshots, along with the matching paths (there is
+    /// one item in the list per path).
+    pub fn find_paths<F>(&self, is_match: F) -> Result<Vec<(SnapshotId, OsString)>, Error>
+    where
+        F: Fn(&OsStr) -> bool + Sync,
+    {
+        let packs = self.packs()?;
+        let pack_results = run_in_parallel(
+            num_cpus::get(),
+            packs.iter(),
+            |pack_id| -> Result<_, Error> {
+                let index = self.load_index_cached(pack_id)?;
+                let mut results = vec![];
+                for (tag, paths) in index.find_paths(&is_match)? {
+                    let snapshot = SnapshotId::new(pack_id.clone(), tag)?;
+                    for path in paths {
+                        results.push((snapshot.clone(), path.to_owned()));
+                    }
+                }
+                Ok(results)
+            },
+        );
+
+        let mut results = vec![];
+        for pack_result in pack_results {
+            results.append(&mut pack_result?);
+        }
+        Ok(results)
+    }
+
+    /// Same as [`Repository::find_paths`], for the files with one of the
+    /// paths, skipping the packs which don't contain any of them according to
+    /// their Bloom filter.
+    pub fn find_exact_paths(&self, paths: &[&OsStr]) -> Result<Vec<(SnapshotId, OsString)>, Error> {
+        let packs = self.packs()?;
+        let pack_results = run_in_parallel(
+            num_cpus::get(),
+            packs.iter(),
+            |pack_id| -> Result<_, Error> {
+                let mut candidates = vec![];
+                for path in paths {
+                    if self.may_contain_path(pack_id, path)? {
+                        candidates.push(*path);
+                    }
+                }
+                if candidates.is_empty() {
+                    return Ok(vec![]);
+                }
+                let index = self.load_index_cached(pack_id)?;
+                let mut results = vec![];
+                for (tag, paths) in index.find_paths(|path| candidates.contains(&path))? {
+                    let snapshot = SnapshotId::new(pack_id.clone(), tag)?;
+                    for path in paths {
+                        results.push((snapshot.clone(), path.to_owned()));
+                    }
+                }
+                Ok(results)
+            },
+        );
+
+        let mut results = vec![];
+        for pack_result in pack_results {
+            results.append(&mut pack_result?);
+        }
+        Ok(results)
+    }
+
+    /// Finds all snapshots that contain the object with the given checksum.
+    ///
+    /// Returns the list of snapshots, along with the path at which the object
+    /// is found in the snapshot (there is one item in the list per path).
+    pub fn find_object(
+        &self,
+        checksum: &ObjectChecksum,
+    ) -> Result<Vec<(SnapshotId, OsString)>, Error> {
+        // Only the packs containing the object are searched, if they are known.
+        let packs = match self.locate_object(checksum)? {
+            Some(locations) => {
+                let mut packs: Vec<_> = locations.into_iter().map(|(pack, _)| pack).collect();
+                packs.sort();
+                packs.dedup();
+                packs
+            }
+            None => self.packs()?,
+        };
+        let pack_results = run_in_parallel(
+            num_cpus::get(),
+            packs.iter(),
+            |pack_id| -> Result<_, Error> {
+                if !self.may_contain_object(pack_id, checksum)? {
+                    return Ok(vec![]);
+                }
+                let index = self.load_index_cached(pack_id)?;
+                let mut results = vec![];
+                for (tag, paths) in index.find_object(checksum)? {
+                    let snapshot = SnapshotId::new(pack_id.clone(), tag)?;
+                    for path in paths {
+                        results.push((snapshot.clone(), path.to_owned()));
+                    }
+                }
+                Ok(results)
+            },
+        );
+
+        let mut results = vec![];
+        for pack_result in pack_results {
+            results.append(&mut pack_result?);
+        }
+        Ok(results)
+    }
+
+    /// Verifies the integrity of the pack. Checks the consistency of the pack
+    /// index, then reads all objects referenced by the index (decompressing
+    /// the whole .pack file or reading the loose objects) and recomputes their
+    /// checksums.
+    ///
+    /// Returns the list of objects which are corrupt or missing. Errors which
+    /// prevent the verification from completing (e.g. corrupt index or pack
+    /// file) are returned as [`Error`].
+    pub fn verify_pack(&self, pack_id: &PackId) -> Result<Vec<VerifyIssue>, Error> {
+        let index = self.load_index(pack_id)?;
+        index.check_consistency()?;
+
+        // Converts checksum mismatches to issues, other errors are propagated.
+        let algorithm = index.checksum_algorithm();
+        let check =
+            |buf: &[u8], checksum: &ObjectChecksum| match verify_object(buf, checksum, algorithm) {
+                Ok(()) => Ok(None),
+                Err(Error::PackError(PackError::ChecksumMismatch(expected, actual))) => {
+                    Ok(Some(VerifyIssue::CorruptObject(expected, actual)))
+                }
+                Err(e) => Err(e),
+            };
+
+        let mut issues = vec![];
+        if self.is_pack_loose(pack_id) {
+            for checksum in index.object_checksums() {
+                let buf = match fs::read(self.loose_object_path(checksum)) {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        issues.push(VerifyIssue::MissingObject(*checksum));
+                        continue;
+                    }
+                    r => r?,
+                };
+                issues.extend(check(&buf, checksum)?);
+            }
+        } else {
+            if !self.get_pack_path(pack_id).exists() {
+                return Err(Error::PackNotFound(pack_id.to_string()));
+            }
+            // The objects stored in other packs are verified with these packs,
+            // but must be stored there.
+            let (external, entries): (Vec<_>, Vec<_>) = index
+                .object_checksums()
+                .map(|checksum| {
+                    FileEntry::new(
+                        hex::encode(checksum).into(),
+                        *checksum,
+                        index.object_metadata(checksum).clone(),
+                        FileMetadata::default(),
+                    )
+                })
+                .partition(|entry| entry.object_metadata.pack.is_some());
+            for entry in external {
+                match self.split_external_entries(pack_id, std::slice::from_ref(&entry)) {
+                    Ok(_) => {}
+                    Err(Error::MissingExternalObject(..)) => {
+                        issues.push(VerifyIssue::MissingObject(entry.checksum))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            self.open_pack(pack_id)?
+                .read_entries(&entries, false, |entry, buf| {
+                    issues.extend(check(buf, &entry.checksum)?);
+                    Ok(())
+                })?;
+        }
+
+        Ok(issues)
+    }
+
+    /// Converts the pack index to the checksum algorithm of the repository
+    /// (see [`Repository::default_checksum_algorithm`]), by reading all
+    /// objects of the pack (verifying their current checksums) and
+    /// recomputing their checksums. The .pack file is left untouched, the
+    /// objects of loose packs are written to the loose object store under
+    /// their new checksums (use [`Repository::find_unreferenced_objects`] to
+    /// find the old ones).
+    ///
+    /// Returns false if the index already uses that algorithm.
+    pub fn upgrade_pack(&self, pack_id: &PackId) -> Result<bool, Error> {
+        self.lock_exclusive()?;
+
+        let algorithm = self.default_checksum_algorithm()?;
+        let mut index = self.load_index(pack_id)?;
+        if index.checksum_algorithm() == algorithm {
+            return Ok(false);
+        }
+        // The objects are found by their checksum in the packs storing them.
+        if !self.is_pack_loose(pack_id) {
+            self.check_pack_unreferenced(pack_id)?;
+        }
+        if let Some(pack) = index.referenced_packs().into_iter().next() {
+            return Err(Error::PackReferenced(
+                PackId::from_str(pack)?,
+                vec![pack_id.clone()],
+            ));
+        }
+        info!(
+            "Converting the checksums of {} from {} to {}...",
+            pack_id,
+            index.checksum_algorithm(),
+            algorithm
+        );
+
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+
+        let is_loose = self.is_pack_loose(pack_id);
+        let entries: Vec<_> = index
+            .object_checksums()
+            .map(|checksum| {
+                FileEntry::new(
+                    hex::encode(checksum).into(),
+                    *checksum,
+                    index.object_metadata(checksum).clone(),
+                    FileMetadata::default(),
+                )
+            })
+            .collect();
+        let mut checksums = HashMap::new();
+        self.read_entries(pack_id, &entries, true, |entry, buf| {
+            let checksum = algorithm.checksum(buf);
+            if is_loose {
+                self.write_loose_object(buf, &temp_dir, &checksum)?;
+            }
+            checksums.insert(entry.checksum, checksum);
+            Ok(())
+        })?;
+
+        index.convert_checksums(algorithm, |checksum| checksums.get(checksum).copied())?;
+        // Replace the index atomically, it is the only copy of the snapshots.
+        let temp_path = create_temp_path(&temp_dir);
+        self.save_index(&index, &temp_path, self.index_encryption_key(pack_id)?)?;
+        self.remove_pack_signature(pack_id)?;
+        fs::rename(&temp_path, self.get_pack_index_path(pack_id))?;
+
+        Ok(true)
+    }
+
     /// Identifies loose packs that are redundant -- have been packed.
     ///
     /// # Algorithm
     /// 1. Find duplicate snapshots according to snapshot checksums
     /// 2. Filter out non-loose snapshots (identify what can be removed)
     pub fn find_redundant_loose_packs(&self) -> Result<Vec<PackId>, Error> {
+        self.lock_write()?;
         let is_any_non_loose =
             |snapshots: &[SnapshotId]| snapshots.iter().any(|s| !self.is_pack_loose(s.pack()));
+        // A pinned snapshot is only redundant if it is packed under the same
+        // tag, so that it is not removed.
+        let pins = self.pins()?;
         let filter_out_packed_snapshots = |snapshots: Vec<SnapshotId>| {
+            let is_packed_as = |tag: &str| {
+                snapshots
+                    .iter()
+                    .any(|s| !self.is_pack_loose(s.pack()) && s.tag() == tag)
+            };
             snapshots
-                .into_iter()
+                .iter()
                 .filter(|s| self.is_pack_loose(s.pack()))
+                .filter(|s| !pins.contains(s.tag()) || is_packed_as(s.tag()))
+                .cloned()
                 .collect::<Vec<_>>()
         };
 
@@ -865,7 +3609,7 @@ impl Repository {
             .flat_map(|(_, snapshots)| filter_out_packed_snapshots(snapshots));
 
         // Loose snapshot ID -> loose pack ID
-        let packs_to_remove: Vec<PackId> = loose_snapshots_present_in_packs
+        let mut packs_to_remove: Vec<PackId> = loose_snapshots_present_in_packs
             .map(|loose_snapshot| {
                 // Some sanity checking that we only have loose snapshots here
                 let PackId::Pack(pack_id) = loose_snapshot.pack();
@@ -874,61 +3618,551 @@ impl Repository {
                 loose_snapshot.pack().clone()
             })
             .collect();
+        // A loose snapshot can be in several groups (one per checksum algorithm).
+        packs_to_remove.sort();
+        packs_to_remove.dedup();
+
+        Ok(packs_to_remove)
+    }
+
+    /// Identifies loose objects that are not referenced from any of the packs.
+    ///
+    /// # Arguments
+    /// * `roots` - the set of packs to consider as the sole referees to objects
+    ///
+    /// # Algorithm
+    /// 1. Read the loose object checksums from disk
+    /// 2. Load the indexes for the packs acting as roots in the object graph
+    /// 3. Tracing: Process the roots and mark loose objects as reachable
+    /// 4. Return the unreachable set of objects
+    pub fn find_unreferenced_objects(
+        &self,
+        roots: impl ExactSizeIterator<Item = PackId>,
+    ) -> Result<Vec<ObjectChecksum>, Error> {
+        self.lock_write()?;
+        // 1. Read the loose object checksums from disk
+        let loose_dir = self.data_dir().join(LOOSE_DIR);
+        let objects_on_disk = WalkDir::new(loose_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| self.loose_object_checksum(&e.into_path()))
+            .collect::<Result<HashSet<ObjectChecksum>, _>>()?;
+
+        info!(
+            "find_unreferenced_objects: Found {} objects on disk",
+            objects_on_disk.len()
+        );
+
+        let unreferenced_objects = Arc::new(Mutex::new(objects_on_disk));
+        let unreferenced_objects_clone = unreferenced_objects.clone();
+        run_in_parallel(num_cpus::get(), roots, |pack_id| -> Result<_, Error> {
+            // 2. Load the indexes for the packs acting as roots in the graph
+            let pack = self.load_index_cached(&pack_id)?;
+
+            // 3. Tracing: Process the roots and mark loose objects as reachable
+            let mut unreferenced_objects = unreferenced_objects_clone.lock().unwrap();
+            for checksum in pack.object_checksums() {
+                unreferenced_objects.remove(checksum);
+            }
+            Ok(())
+        })
+        .into_iter()
+        .collect::<Result<Vec<()>, _>>()?;
+
+        // 4. Return the unreachable set of objects
+        let results = unreferenced_objects.lock().unwrap();
+        info!(
+            "find_unreferenced_objects: {} objects are not referenced from any of the roots",
+            results.len()
+        );
+        Ok(results.iter().copied().collect())
+    }
+
+    /// Changes the tag of the snapshot, rewriting the pack index. Loose
+    /// snapshots are moved to the loose pack matching the new tag. The HEADs
+    /// of the working trees are updated if they reference the snapshot.
+    ///
+    /// Returns the new [`SnapshotId`] of the snapshot.
+    pub fn rename_snapshot(
+        &mut self,
+        snapshot: &SnapshotId,
+        new_tag: &str,
+    ) -> Result<SnapshotId, Error> {
+        self.lock_exclusive()?;
+
+        let new_pack = if self.is_pack_loose(snapshot.pack()) {
+            PackId::Pack(format!("{LOOSE_DIR}/{new_tag}"))
+        } else {
+            snapshot.pack().clone()
+        };
+        let new_snapshot = SnapshotId::new(new_pack, new_tag)?;
+
+        let refs = self.resolved_refs()?;
+        let heads = self.worktree_heads()?;
+        let mut index = self.load_index(snapshot.pack())?;
+        index
+            .rename_snapshot(snapshot.tag(), new_tag)
+            .map_err(|e| match e {
+                PackError::SnapshotAlreadyExists(_, s) => {
+                    PackError::SnapshotAlreadyExists(snapshot.pack().to_string(), s)
+                }
+                e => e,
+            })?;
+
+        let new_index_path = self.get_pack_index_path(new_snapshot.pack());
+        if new_snapshot.pack() != snapshot.pack() && new_index_path.exists() {
+            return Err(PackError::SnapshotAlreadyExists(
+                new_snapshot.pack().to_string(),
+                new_tag.to_owned(),
+            )
+            .into());
+        }
+        // Replace the index atomically, since it may be mapped into memory
+        // (see Repository::load_flat_index).
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        let temp_path = create_temp_path(&temp_dir);
+        self.save_index(
+            &index,
+            &temp_path,
+            self.index_encryption_key(snapshot.pack())?,
+        )?;
+        // The signatures of the pack cover its index.
+        self.remove_pack_signature(snapshot.pack())?;
+        fs::rename(&temp_path, &new_index_path)?;
+        if new_snapshot.pack() != snapshot.pack() {
+            fs::remove_file(self.get_pack_index_path(snapshot.pack()))?;
+        }
+
+        // The snapshot stays pinned under its new tag.
+        let mut pins = self.pins()?;
+        if pins.contains(snapshot.tag()) {
+            pins.insert(new_tag.to_owned());
+            // Unless other snapshots have the old tag.
+            if let Err(Error::PackError(PackError::SnapshotNotFound(_))) =
+                self.find_snapshot(snapshot.tag())
+            {
+                pins.remove(snapshot.tag());
+            }
+            self.save_pins(&pins)?;
+        }
+
+        for (name, _) in refs.iter().filter(|(_, target)| target == snapshot) {
+            self.set_ref(name, &new_snapshot)?;
+        }
+
+        for (head_path, _) in heads.iter().filter(|(_, head)| head == snapshot) {
+            self.write_snapshot_id(head_path, &new_snapshot)?;
+        }
+
+        Ok(new_snapshot)
+    }
+
+    /// Renames the .pack, .pack.idx (and .pack.bloom, .pack.sig) files of the pack. The HEADs of the
+    /// working trees are updated if they reference a snapshot in the pack. Loose packs cannot be renamed, since
+    /// their names are determined by the snapshot tag.
+    pub fn rename_pack(&mut self, pack_id: &PackId, new_pack_id: &PackId) -> Result<(), Error> {
+        self.lock_exclusive()?;
+
+        let pack_path = self.get_pack_path(pack_id);
+        let new_pack_path = self.get_pack_path(new_pack_id);
+        let new_pack_index_path = self.get_pack_index_path(new_pack_id);
+
+        if self.is_pack_loose(pack_id) || self.is_pack_loose(new_pack_id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot rename loose pack {pack_id} (rename the snapshot instead)"),
+            )
+            .into());
+        } else if !pack_path.exists() {
+            return Err(Error::PackNotFound(pack_id.to_string()));
+        } else if new_pack_path.exists() || new_pack_index_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{new_pack_path:?} already exists"),
+            )
+            .into());
+        }
+        self.check_pack_unreferenced(pack_id)?;
+
+        let refs = self.resolved_refs()?;
+        let heads = self.worktree_heads()?;
+        ensure_dir(new_pack_path.parent().unwrap())?;
+        fs::rename(self.get_pack_index_path(pack_id), &new_pack_index_path)?;
+        fs::rename(&pack_path, &new_pack_path)?;
+        // The index is unchanged, so is its Bloom filter.
+        match fs::rename(
+            self.get_pack_bloom_path(pack_id),
+            self.get_pack_bloom_path(new_pack_id),
+        ) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        // The signatures only cover the contents of the pack, not its name.
+        match fs::rename(
+            self.get_pack_signature_path(pack_id),
+            self.get_pack_signature_path(new_pack_id),
+        ) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        self.index_cache.remove(pack_id)?;
+
+        for (name, target) in refs.iter().filter(|(_, target)| target.pack() == pack_id) {
+            self.set_ref(name, &SnapshotId::new(new_pack_id.clone(), target.tag())?)?;
+        }
+
+        for (head_path, head) in heads.iter().filter(|(_, head)| head.pack() == pack_id) {
+            self.write_snapshot_id(
+                head_path,
+                &SnapshotId::new(new_pack_id.clone(), head.tag())?,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The tags of the pinned snapshots (see [`Repository::pin_snapshot`]).
+    pub fn pins(&self) -> Result<BTreeSet<String>, Error> {
+        match fs::read_to_string(self.data_dir().join(PINS_FILE)) {
+            Ok(pins) => Ok(pins
+                .lines()
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned)
+                .collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether the snapshot is pinned (see [`Repository::pin_snapshot`]).
+    pub fn is_pinned(&self, snapshot: &SnapshotId) -> Result<bool, Error> {
+        Ok(self.pins()?.contains(snapshot.tag()))
+    }
+
+    /// Pins the snapshot, so that it is not removed (e.g. by `elfshaker rm`)
+    /// until it is unpinned. The snapshots are pinned by tag (see
+    /// [`PINS_FILE`]), so that they stay pinned when their packs are
+    /// rewritten, merged or renamed. The tag must therefore not be the one of
+    /// different snapshots in other packs ([`Error::AmbiguousSnapshotMatch`]),
+    /// although it can be the one of copies of the snapshot (e.g. loose ones).
+    /// Returns false if it was already pinned.
+    pub fn pin_snapshot(&mut self, snapshot: &SnapshotId) -> Result<bool, Error> {
+        self.lock_write()?;
+        self.find_pack_with_snapshot(snapshot.tag())?;
+        let mut pins = self.pins()?;
+        let is_new = pins.insert(snapshot.tag().to_owned());
+        if is_new {
+            self.save_pins(&pins)?;
+        }
+        Ok(is_new)
+    }
+
+    /// Unpins the snapshots with the tag (see [`Repository::pin_snapshot`]),
+    /// which may no longer exist. Returns false if it was not pinned.
+    pub fn unpin_snapshot(&mut self, tag: &str) -> Result<bool, Error> {
+        self.lock_write()?;
+        let mut pins = self.pins()?;
+        let was_pinned = pins.remove(tag);
+        if was_pinned {
+            self.save_pins(&pins)?;
+        }
+        Ok(was_pinned)
+    }
+
+    fn save_pins(&self, pins: &BTreeSet<String>) -> Result<(), Error> {
+        let mut contents = String::new();
+        for tag in pins {
+            contents.push_str(tag);
+            contents.push('\n');
+        }
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        write_file_atomic(
+            contents.as_bytes(),
+            &temp_dir,
+            &self.data_dir().join(PINS_FILE),
+        )?;
+        Ok(())
+    }
+
+    fn ref_path(&self, name: &str) -> Result<PathBuf, Error> {
+        if !is_valid_ref_name(name) {
+            return Err(IdError::InvalidRef(name.to_owned()).into());
+        }
+        Ok(self.data_dir().join(REFS_DIR).join(name))
+    }
+
+    /// The snapshot the ref points to, if it exists (see
+    /// [`Repository::set_ref`]).
+    pub fn read_ref(&self, name: &str) -> Result<Option<SnapshotId>, Error> {
+        if !is_valid_ref_name(name) {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.ref_path(name)?) {
+            Ok(text) => Ok(Some(SnapshotId::from_str(text.trim())?)),
+            // A directory of refs (e.g. release for release/1.0) is not a ref.
+            Err(e) if e.kind() == io::ErrorKind::NotFound || self.ref_path(name)?.is_dir() => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The snapshot the ref points to, if it exists (see
+    /// [`Repository::locate_snapshot`]).
+    fn resolve_ref(&self, name: &str) -> Result<Option<SnapshotId>, Error> {
+        let snapshot = match self.read_ref(name)? {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        match self.locate_snapshot(&snapshot)? {
+            Some(snapshot) => Ok(Some(snapshot)),
+            None => Err(Error::BrokenRef(name.to_owned(), snapshot)),
+        }
+    }
+
+    /// The snapshot, if it still exists. When the snapshot is no longer in the
+    /// pack (e.g. a loose snapshot which was packed since), the snapshot with
+    /// the same tag is used instead, if there is only one.
+    fn locate_snapshot(&self, snapshot: &SnapshotId) -> Result<Option<SnapshotId>, Error> {
+        if self.get_pack_index_path(snapshot.pack()).exists()
+            && self
+                .load_index_snapshots(snapshot.pack())?
+                .iter()
+                .any(|tag| tag == snapshot.tag())
+        {
+            return Ok(Some(snapshot.clone()));
+        }
+        match self.find_pack_with_snapshot(snapshot.tag()) {
+            Ok(pack) => Ok(Some(SnapshotId::new(pack, snapshot.tag())?)),
+            Err(Error::PackError(PackError::SnapshotNotFound(_))) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The refs, by name, with the snapshots they point to.
+    pub fn refs(&self) -> Result<BTreeMap<String, SnapshotId>, Error> {
+        let root = self.data_dir().join(REFS_DIR);
+        let mut refs = BTreeMap::new();
+        if !root.exists() {
+            return Ok(refs);
+        }
+        for entry in WalkDir::new(&root) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&root)
+                .unwrap()
+                .to_str()
+                .ok_or_else(|| Error::Utf8Error(entry.path().into()))?
+                .to_owned();
+            if let Some(snapshot) = self.read_ref(&name)? {
+                refs.insert(name, snapshot);
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Points the ref (e.g. `latest` or `release/1.0`) to the snapshot, so
+    /// that the ref can be used instead of the snapshot (see
+    /// [`Repository::find_snapshot`]). The refs are stored in [`REFS_DIR`].
+    /// Returns the snapshot the ref pointed to before, if any.
+    pub fn set_ref(
+        &mut self,
+        name: &str,
+        snapshot: &SnapshotId,
+    ) -> Result<Option<SnapshotId>, Error> {
+        self.lock_write()?;
+        let path = self.ref_path(name)?;
+        let old = self.read_ref(name)?;
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        ensure_dir(path.parent().unwrap())?;
+        write_file_atomic(format!("{snapshot}\n").as_bytes(), &temp_dir, &path)?;
+        Ok(old)
+    }
+
+    /// Deletes the ref. Returns false if it did not exist.
+    pub fn delete_ref(&mut self, name: &str) -> Result<bool, Error> {
+        self.lock_write()?;
+        let path = self.ref_path(name)?;
+        if self.read_ref(name)?.is_none() {
+            return Ok(false);
+        }
+        fs::remove_file(&path)?;
+        // Remove the directories which only contained the ref.
+        let root = self.data_dir().join(REFS_DIR);
+        let mut dir = path.parent();
+        while let Some(parent) = dir.filter(|dir| *dir != root) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(true)
+    }
+
+    /// The refs with the snapshots they resolve to (see
+    /// [`Repository::find_snapshot`]), ignoring the broken ones, so that they
+    /// can be updated when the snapshots are renamed.
+    fn resolved_refs(&self) -> Result<Vec<(String, SnapshotId)>, Error> {
+        let mut refs = vec![];
+        for name in self.refs()?.into_keys() {
+            match self.resolve_ref(&name) {
+                Ok(Some(snapshot)) => refs.push((name, snapshot)),
+                Ok(None) | Err(Error::BrokenRef(..)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Creates a linked working tree at `path`, which shares the data
+    /// directory of the repository but has a HEAD of its own, so that another
+    /// snapshot can be extracted to it (see [`Repository::extract_snapshot_to`])
+    /// without copying the packs. Its data directory only contains HEAD and
+    /// the path of the shared one ([`COMMON_DIR_FILE`]).
+    ///
+    /// Returns the canonical path of the working tree.
+    pub fn add_worktree(&mut self, path: &Path) -> Result<PathBuf, Error> {
+        self.lock_write()?;
+        ensure_dir(path)?;
+        let path = path.canonicalize()?;
+        let worktree_data_dir = path.join(REPO_DIR);
+        if worktree_data_dir.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{worktree_data_dir:?} already exists"),
+            )
+            .into());
+        }
+        link_data_dir(&worktree_data_dir, self.data_dir())?;
+        let mut worktrees = read_worktrees(self.data_dir())?;
+        if !worktrees.contains(&path) {
+            worktrees.push(path.clone());
+            write_worktrees(self.data_dir(), &self.temp_dir(), &worktrees)?;
+        }
+        Ok(path)
+    }
+
+    /// The working trees sharing the data directory, with their HEAD: the main
+    /// working tree first, then the linked ones (see
+    /// [`Repository::add_worktree`]), including those which no longer exist.
+    pub fn worktrees(&self) -> Result<Vec<(PathBuf, Option<SnapshotId>)>, Error> {
+        self.worktree_head_paths()?
+            .into_iter()
+            .map(|(path, head_path)| Ok((path, self.read_head_file(&head_path)?.0)))
+            .collect()
+    }
+
+    /// Removes the linked working tree at `path`, with its files, and
+    /// unregisters it. Fails if it contains files more recent than its HEAD
+    /// (i.e. files which would be lost), unless `force` is set.
+    pub fn remove_worktree(&mut self, path: &Path, force: bool) -> Result<(), Error> {
+        self.lock_write()?;
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let mut worktrees = read_worktrees(self.data_dir())?;
+        if !worktrees.contains(&path) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{path:?} is not a linked working tree"),
+            )
+            .into());
+        } else if path == self.path {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot remove the working tree in use (run the command from another one)",
+            )
+            .into());
+        }
 
-        Ok(packs_to_remove)
+        if path.exists() {
+            if !force {
+                let head_time = self.read_head_of(&path)?.1;
+                self.check_work_dir_unchanged(&path, head_time)?;
+            }
+            fs::remove_dir_all(&path)?;
+        }
+        worktrees.retain(|worktree| *worktree != path);
+        write_worktrees(self.data_dir(), &self.temp_dir(), &worktrees)?;
+        Ok(())
     }
 
-    /// Identifies loose objects that are not referenced from any of the packs.
-    ///
-    /// # Arguments
-    /// * `roots` - the set of packs to consider as the sole referees to objects
-    ///
-    /// # Algorithm
-    /// 1. Read the loose object checksums from disk
-    /// 2. Load the indexes for the packs acting as roots in the object graph
-    /// 3. Tracing: Process the roots and mark loose objects as reachable
-    /// 4. Return the unreachable set of objects
-    pub fn find_unreferenced_objects(
-        &self,
-        roots: impl ExactSizeIterator<Item = PackId>,
-    ) -> Result<Vec<ObjectChecksum>, Error> {
-        // 1. Read the loose object checksums from disk
-        let loose_dir = self.data_dir().join(LOOSE_DIR);
-        let objects_on_disk = WalkDir::new(loose_dir)
+    /// Unregisters the linked working trees which no longer exist. Returns
+    /// their paths.
+    pub fn prune_worktrees(&mut self) -> Result<Vec<PathBuf>, Error> {
+        self.lock_write()?;
+        let (worktrees, missing): (Vec<_>, Vec<_>) = read_worktrees(self.data_dir())?
             .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-            .map(|e| self.loose_object_checksum(&e.into_path()))
-            .collect::<Result<HashSet<ObjectChecksum>, _>>()?;
+            .partition(|worktree| worktree.join(REPO_DIR).exists());
+        if !missing.is_empty() {
+            write_worktrees(self.data_dir(), &self.temp_dir(), &worktrees)?;
+        }
+        Ok(missing)
+    }
 
-        info!(
-            "find_unreferenced_objects: Found {} objects on disk",
-            objects_on_disk.len()
-        );
+    /// The paths of the working trees sharing the data directory, with the
+    /// paths of their HEAD (see [`Repository::worktrees`]).
+    fn worktree_head_paths(&self) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        let main = self.data_dir().parent().unwrap_or(self.data_dir());
+        let mut paths = vec![(main.to_owned(), self.data_dir().join(HEAD_FILE))];
+        for worktree in read_worktrees(self.data_dir())? {
+            let head_path = worktree.join(REPO_DIR).join(HEAD_FILE);
+            paths.push((worktree, head_path));
+        }
+        Ok(paths)
+    }
 
-        let unreferenced_objects = Arc::new(Mutex::new(objects_on_disk));
-        let unreferenced_objects_clone = unreferenced_objects.clone();
-        run_in_parallel(num_cpus::get(), roots, |pack_id| -> Result<_, Error> {
-            // 2. Load the indexes for the packs acting as roots in the graph
-            let pack = self.load_index(&pack_id)?;
+    /// The HEADs of the working trees, by the path of the HEAD file, so that
+    /// they can be updated when the snapshots are renamed.
+    fn worktree_heads(&self) -> Result<Vec<(PathBuf, SnapshotId)>, Error> {
+        let mut heads = vec![];
+        for (_, head_path) in self.worktree_head_paths()? {
+            if let (Some(head), _) = self.read_head_file(&head_path)? {
+                heads.push((head_path, head));
+            }
+        }
+        Ok(heads)
+    }
 
-            // 3. Tracing: Process the roots and mark loose objects as reachable
-            let mut unreferenced_objects = unreferenced_objects_clone.lock().unwrap();
-            for checksum in pack.object_checksums() {
-                unreferenced_objects.remove(checksum);
+    /// Fails with [`Error::DirtyWorkDir`] if a file of the directory (outside
+    /// of its data directory) is more recent than its HEAD, or if there is any
+    /// file but no HEAD.
+    fn check_work_dir_unchanged(
+        &self,
+        dir: &Path,
+        head_time: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let entries = WalkDir::new(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|e| !(e.depth() == 1 && e.file_name() == REPO_DIR));
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type().is_dir() {
+                continue;
             }
-            Ok(())
-        })
-        .into_iter()
-        .collect::<Result<Vec<()>, _>>()?;
+            match head_time {
+                Some(head_time) => self.check_changed_since(head_time, entry.path())?,
+                None => {
+                    warn!("File {} is not in HEAD!", entry.path().to_string_lossy());
+                    return Err(Error::DirtyWorkDir);
+                }
+            }
+        }
+        Ok(())
+    }
 
-        // 4. Return the unreachable set of objects
-        let results = unreferenced_objects.lock().unwrap();
-        info!(
-            "find_unreferenced_objects: {} objects are not referenced from any of the roots",
-            results.len()
-        );
-        Ok(results.iter().copied().collect())
+    /// The data directory of the working tree: the one of the linked working
+    /// tree the repository was opened in, or the repository data directory.
+    fn work_data_dir(&self) -> &Path {
+        self.worktree_dir.as_deref().unwrap_or(self.data_dir())
     }
 
     /// Deletes the files related to the pack on disk. Deleting a non-existent pack is an error.
@@ -941,21 +4175,30 @@ impl Repository {
         let is_loose = self.is_pack_loose(pack_id);
         let pack_exsits = pack_path.exists();
         if is_loose && pack_exsits {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Unexpected .pack for loose {pack_id:?}"),
-            ));
+            return Err(io::Error::other(format!(
+                "Unexpected .pack for loose {pack_id:?}"
+            )));
         } else if !is_loose && !pack_exsits {
             return Err(io::Error::new(
                 io::ErrorKind::NotFound,
                 format!("{pack_path:?} not found"),
             ));
         }
+        if !is_loose {
+            self.check_pack_unreferenced(pack_id)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+        }
 
         fs::remove_file(&pack_idx_path)?;
         if !is_loose {
             fs::remove_file(&pack_path)?;
+            match fs::remove_file(self.get_pack_bloom_path(pack_id)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            self.remove_pack_signature(pack_id)?;
         }
+        self.index_cache.remove(pack_id)?;
 
         info!("Deleted pack index {:?}", &pack_idx_path);
         Ok(())
@@ -980,12 +4223,11 @@ impl Repository {
     }
 
     pub fn get_pack_disk_stats(&self, pack_id: &PackId) -> io::Result<PackDiskStats> {
-        let mut pack_path = self.get_pack_index_path(pack_id);
-        let pack_len = fs::metadata(&pack_path).map(|x| x.len()).unwrap_or(0);
-        pack_path.set_extension("");
-        pack_path.set_extension(PACK_INDEX_EXTENSION);
-        println!("{pack_path:?}");
-        let pack_idx_stats = fs::metadata(&pack_path)?;
+        // Loose packs only have a .pack.idx.
+        let pack_len = fs::metadata(self.get_pack_path(pack_id))
+            .map(|x| x.len())
+            .unwrap_or(0);
+        let pack_idx_stats = fs::metadata(self.get_pack_index_path(pack_id))?;
 
         Ok(PackDiskStats {
             len: pack_len + pack_idx_stats.len(),
@@ -1003,22 +4245,151 @@ impl Repository {
         self.progress_reporter_factory = Box::new(factory);
     }
 
+    /// Sets the credentials of the HTTP requests to the remotes (e.g. when
+    /// fetching the indexes and the packs), where the earlier ones take
+    /// precedence.
+    pub fn set_http_auth(&mut self, auth: Vec<HttpAuth>) {
+        self.http_auth = auth;
+    }
+
+    fn http_agent(&self) -> ureq::Agent {
+        remote::build_agent(&self.http_auth)
+    }
+
+    /// Sets the URLs of the .esi of the mirrors of the remotes, by name (see
+    /// [`remote::RemoteIndex::set_mirrors`]), which the requests to the
+    /// remotes fail over to.
+    pub fn set_remote_mirrors(&mut self, mirrors: HashMap<String, Vec<String>>) {
+        self.remote_mirrors = mirrors;
+    }
+
+    /// Sets the keys trusted to sign the packs of all the remotes (see
+    /// [`PackSignature`]), in addition to the ones of their trust policies
+    /// (see [`Repository::set_trust_policies`]).
+    pub fn set_trusted_keys(&mut self, keys: Vec<PublicKey>) {
+        self.trusted_keys = keys;
+    }
+
+    /// Sets the trust policies of the remotes, by name. Unless the policy of a
+    /// remote lists no keys (and no keys are trusted by
+    /// [`Repository::set_trusted_keys`]), its packs which violate the policy are
+    /// rejected when fetched (e.g. by [`Repository::update_remotes`] and
+    /// [`Repository::update_remote_pack`]).
+    pub fn set_trust_policies(&mut self, policies: HashMap<String, TrustPolicy>) {
+        self.trust_policies = policies;
+    }
+
+    /// Caches the parsed pack indexes on disk too (see [`INDEX_CACHE_DIR`]),
+    /// in the flat format (see [`FlatIndex`]), so that the later commands
+    /// query them in place, rather than parsing them again. The copies are
+    /// made when the indexes are loaded, and replaced when they change.
+    pub fn set_index_disk_cache(&mut self, enabled: bool) {
+        let dir = enabled.then(|| self.data_dir().join(INDEX_CACHE_DIR));
+        self.index_cache.set_dir(dir);
+    }
+
+    /// Looks the objects up in the object index (see [`OBJECT_INDEX_FILE`]),
+    /// which records the packs containing each object, so that e.g.
+    /// [`Repository::find_object`] only loads the indexes of these packs. The
+    /// index is updated when a pack is created, and when it is used, with the
+    /// packs which changed since.
+    pub fn set_object_index(&mut self, enabled: bool) {
+        self.is_object_index_enabled = enabled;
+    }
+
+    /// Sets the keys to decrypt the encrypted packs (and pack indexes) with
+    /// (see [`PackOptions::encryption_key`]). The key of each pack is found by
+    /// its ID (see [`EncryptionKey::id`]).
+    pub fn set_encryption_keys(&mut self, keys: Vec<EncryptionKey>) {
+        self.encryption_keys = keys;
+    }
+
+    /// The trust policy of the remote, including the keys trusted for all the
+    /// remotes.
+    fn trust_policy(&self, remote_name: &str) -> TrustPolicy {
+        let mut policy = self
+            .trust_policies
+            .get(remote_name)
+            .cloned()
+            .unwrap_or_default();
+        policy.trusted_keys.extend(&self.trusted_keys);
+        policy
+    }
+
+    /// Signs the pack with the key, adding the signature to the ones of the
+    /// other keys in its .pack.sig file (see [`PackSignature`]), unless the
+    /// pack changed since.
+    pub fn sign_pack(&self, pack_id: &PackId, key: &SigningKey) -> Result<(), Error> {
+        let pack_path = self.get_pack_path(pack_id);
+        if self.is_pack_loose(pack_id) || !pack_path.exists() {
+            return Err(Error::PackNotFound(pack_id.to_string()));
+        }
+        let signature_path = self.get_pack_signature_path(pack_id);
+        let mut signature =
+            PackSignature::for_pack(&pack_path, &self.get_pack_index_path(pack_id))?;
+        if let Ok(previous) = PackSignature::load(&signature_path) {
+            signature.extend(previous);
+        }
+        signature.sign(key);
+
+        let mut buf = vec![];
+        signature.write(&mut buf)?;
+        let temp_dir = self.temp_dir();
+        ensure_dir(&temp_dir)?;
+        write_file_atomic(buf.as_slice(), &temp_dir, &signature_path)?;
+        Ok(())
+    }
+
+    /// Removes the .pack.sig file of the pack, if any, once the pack (or its
+    /// index) is replaced or deleted.
+    fn remove_pack_signature(&self, pack_id: &PackId) -> io::Result<()> {
+        match fs::remove_file(self.get_pack_signature_path(pack_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Loads the remotes, with their mirrors.
+    fn load_remotes(&self) -> Result<Vec<remote::RemoteIndex>, Error> {
+        let mut remotes = remote::load_remotes(&self.data_dir().join(REMOTES_DIR))?;
+        for remote in &mut remotes {
+            if let Some(mirrors) = remote
+                .name()
+                .and_then(|name| self.remote_mirrors.get(&name))
+            {
+                remote.set_mirrors(mirrors.clone());
+            }
+        }
+        Ok(remotes)
+    }
+
+    /// Copies the loose objects of the entries to the specified directory. The
+    /// checksums of the copied files are verified with the given algorithm,
+    /// if any.
     fn copy_loose_entries(
         &mut self,
         entries: &[FileEntry],
         path: &Path,
-        verify: bool,
+        verify: Option<ChecksumAlgorithm>,
     ) -> Result<(), Error> {
         let mut dest_paths = vec![];
-        let mut dest_path = PathBuf::new();
         for entry in entries {
-            dest_path.clear();
-            dest_path.push(path);
-            dest_path.push(&entry.path);
-            dest_paths.push(dest_path.clone());
-            fs::create_dir_all(dest_path.parent().unwrap())?;
+            let dest_path = create_entry_parents(path, &entry.path)?;
             let object_path = self.loose_object_path(&entry.checksum);
-            fs::copy(&object_path, &dest_path).map_err(|e| {
+            if entry.file_metadata.is_symlink() || entry.file_metadata.is_dir() {
+                create_special_file(&object_path, &dest_path, entry.file_metadata)?;
+                // Neither links nor directories are followed to verify them,
+                // their objects are.
+                dest_paths.push(object_path);
+                continue;
+            }
+            dest_paths.push(dest_path.clone());
+            let mode = entry.file_metadata.mode;
+            replace_file(&dest_path, |temp_path| {
+                copy_file_sparse(&object_path, temp_path)?;
+                set_file_mode(temp_path, mode)
+            })
+            .map_err(|e| {
                 io::Error::new(
                     e.kind(),
                     format!(
@@ -1028,41 +4399,187 @@ impl Repository {
                     ),
                 )
             })?;
+        }
+
+        if let Some(algorithm) = verify {
+            let checksums = batch::compute_checksums(&dest_paths, algorithm)?;
+            for (entry, checksum) in entries.iter().zip(checksums) {
+                check_entry_checksum(entry, checksum)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extracts the entries through the object cache (see [`CACHE_DIR`]), by
+    /// reflinking or hard linking them to the cached objects (see
+    /// [`ExtractOptions::reflink`] and [`ExtractOptions::link`]). The objects
+    /// missing from the cache are extracted into it first. When reflinking,
+    /// the objects of loose snapshots are reflinked from the loose object store
+    /// instead.
+    fn extract_entries_from_cache(
+        &mut self,
+        pack_id: &PackId,
+        entries: &[FileEntry],
+        path: &Path,
+        mut opts: ExtractOptions,
+    ) -> Result<(), Error> {
+        let is_loose = self.is_pack_loose(pack_id);
+        let link = opts.link();
+        // Hard links share the permissions of the object they link to, so the
+        // objects of loose snapshots are copied into the cache, which only
+        // contains read-only objects.
+        let objects_dir = if is_loose && !link {
+            LOOSE_DIR
+        } else {
+            CACHE_DIR
+        };
+        ensure_dir(&self.temp_dir())?;
 
-            let perm = Permissions::from_mode(entry.file_metadata.mode);
-            fs::set_permissions(&dest_path, perm)?
+        // The objects which are already files (and need verifying, since they
+        // were not just extracted), along with their (first) entries.
+        let mut existing = vec![];
+        let mut existing_entries = vec![];
+        let mut missing = vec![];
+        let mut seen = HashSet::new();
+        for entry in entries {
+            if !seen.insert(entry.checksum) {
+                continue;
+            }
+            let object_path = self.object_path(objects_dir, &entry.checksum);
+            if objects_dir == LOOSE_DIR || object_path.exists() {
+                existing.push(object_path);
+                existing_entries.push(entry);
+            } else {
+                // Extracted to a temporary directory, then moved into the cache,
+                // so the cache never contains partially written objects.
+                let mut entry = entry.clone();
+                entry.path = hex::encode(entry.checksum).into();
+                entry.file_metadata.mode = 0o444;
+                missing.push(entry);
+            }
         }
 
-        if verify {
-            let checksums = batch::compute_checksums(&dest_paths)?;
-            let expected_checksums = entries.iter().map(|e| &e.checksum);
-            for (expected, actual) in expected_checksums.zip(checksums) {
-                if *expected != actual {
-                    return Err(PackError::ChecksumMismatch(*expected, actual).into());
+        if !missing.is_empty() {
+            let temp_dir = create_temp_path(&self.temp_dir());
+            ensure_dir(&temp_dir)?;
+            if is_loose {
+                for entry in &missing {
+                    let temp_path = join_entry_path(&temp_dir, &entry.path)?;
+                    reflink_file(&self.loose_object_path(&entry.checksum), &temp_path)?;
+                    set_file_mode(&temp_path, 0o444)?;
+                }
+            } else {
+                opts.set_reflink(false);
+                opts.set_link(false);
+                opts.set_preserve_times(false);
+                opts.set_xattrs(false);
+                self.extract_entries(pack_id, &missing, &temp_dir, opts.clone())
+                    .map_err(|e| match e {
+                        // The path of the file, rather than of the cached object.
+                        Error::CorruptFile(_, expected, actual) => {
+                            let entry = entries.iter().find(|e| e.checksum == expected).unwrap();
+                            Error::CorruptFile(entry.path.clone(), expected, actual)
+                        }
+                        e => e,
+                    })?;
+            }
+            for entry in &missing {
+                let cached_path = self.object_path(CACHE_DIR, &entry.checksum);
+                ensure_dir(cached_path.parent().unwrap())?;
+                fs::rename(join_entry_path(&temp_dir, &entry.path)?, &cached_path)?;
+                if is_loose {
+                    existing.push(cached_path);
+                    let entry = entries.iter().find(|e| e.checksum == entry.checksum);
+                    existing_entries.push(entry.unwrap());
                 }
             }
+            fs::remove_dir(&temp_dir)?;
+        }
+        if opts.verify() && !existing.is_empty() {
+            let algorithm = self.checksum_algorithm(pack_id)?;
+            let checksums = batch::compute_checksums(&existing, algorithm)?;
+            for (entry, checksum) in existing_entries.into_iter().zip(checksums) {
+                check_entry_checksum(entry, checksum)?;
+            }
         }
 
+        for entry in entries {
+            let dest_path = create_entry_parents(path, &entry.path)?;
+            let object_path = self.object_path(objects_dir, &entry.checksum);
+            if entry.file_metadata.is_symlink() || entry.file_metadata.is_dir() {
+                create_special_file(&object_path, &dest_path, entry.file_metadata)?;
+            } else if link {
+                let object_path =
+                    self.linked_object_path(&object_path, entry.file_metadata.mode)?;
+                hard_link_file(&object_path, &dest_path)?;
+            } else {
+                reflink_file(&object_path, &dest_path)?;
+                set_file_mode(&dest_path, entry.file_metadata.mode)?;
+            }
+        }
         Ok(())
     }
 
-    fn get_pack_path(&self, pack_id: &PackId) -> PathBuf {
+    /// Returns the path of the cached object at `object_path` (which is
+    /// read-only) with the permissions of `mode`, minus the write permissions,
+    /// since the files linked to it must not be modified. The cached object
+    /// with these permissions is created, if needed.
+    fn linked_object_path(&self, object_path: &Path, mode: u32) -> Result<PathBuf, Error> {
+        let mode = mode & 0o555;
+        if mode == 0o444 {
+            return Ok(object_path.to_owned());
+        }
+        let linked_path = object_path.with_extension(format!("{mode:o}"));
+        if !linked_path.exists() {
+            let temp_path = create_temp_path(&self.temp_dir());
+            reflink_file(object_path, &temp_path)?;
+            set_file_mode(&temp_path, mode)?;
+            fs::rename(&temp_path, &linked_path)?;
+        }
+        Ok(linked_path)
+    }
+
+    /// The path of the .pack file of the pack (which might not exist locally).
+    pub fn get_pack_path(&self, pack_id: &PackId) -> PathBuf {
         match pack_id {
+            // Pack names can contain dots, which are not extensions.
             PackId::Pack(name) => self
                 .data_dir()
                 .join(PACKS_DIR)
-                .join(name)
-                .with_extension(PACK_EXTENSION),
+                .join(format!("{name}.{PACK_EXTENSION}")),
         }
     }
 
-    fn get_pack_index_path(&self, pack_id: &PackId) -> PathBuf {
+    /// The path of the .pack.sig file of the pack (see [`PackSignature`]).
+    pub fn get_pack_signature_path(&self, pack_id: &PackId) -> PathBuf {
         match pack_id {
             PackId::Pack(name) => self
                 .data_dir()
                 .join(PACKS_DIR)
-                .join(name)
-                .with_extension(PACK_INDEX_EXTENSION),
+                .join(format!("{name}.{PACK_SIGNATURE_EXTENSION}")),
+        }
+    }
+
+    /// The path of the .pack.bloom file of the pack (see
+    /// [`Repository::may_contain_path`]).
+    fn get_pack_bloom_path(&self, pack_id: &PackId) -> PathBuf {
+        match pack_id {
+            PackId::Pack(name) => self
+                .data_dir()
+                .join(PACKS_DIR)
+                .join(format!("{name}.{PACK_BLOOM_EXTENSION}")),
+        }
+    }
+
+    /// The path of the .pack.idx file of the pack.
+    pub fn get_pack_index_path(&self, pack_id: &PackId) -> PathBuf {
+        match pack_id {
+            // Pack names can contain dots, which are not extensions.
+            PackId::Pack(name) => self
+                .data_dir()
+                .join(PACKS_DIR)
+                .join(format!("{name}.{PACK_INDEX_EXTENSION}")),
         }
     }
 
@@ -1079,11 +4596,11 @@ impl Repository {
         // but not, for example, the offset of the object in the pack file.
         let from_lookup: HashMap<_, _> = from_entries
             .iter()
-            .map(|e| ((&e.path, &e.checksum, &e.file_metadata), e))
+            .map(|e| ((&e.path, &e.checksum, &e.file_metadata, &e.xattrs), e))
             .collect();
         let to_lookup: HashMap<_, _> = to_entries
             .iter()
-            .map(|e| ((&e.path, &e.checksum, &e.file_metadata), e))
+            .map(|e| ((&e.path, &e.checksum, &e.file_metadata, &e.xattrs), e))
             .collect();
 
         let mut added = vec![];
@@ -1105,8 +4622,78 @@ impl Repository {
         (added, removed)
     }
 
+    /// Same as [`Repository::compute_entry_diff`], but matches the entries by
+    /// path, so that changes to the same path are reported as modifications.
+    fn compute_snapshot_diff(
+        from_entries: Vec<FileEntry>,
+        to_entries: Vec<FileEntry>,
+    ) -> SnapshotDiff {
+        let mut from_lookup: HashMap<_, _> = from_entries
+            .into_iter()
+            .map(|e| (e.path.clone(), e))
+            .collect();
+
+        let mut diff = SnapshotDiff::default();
+        for entry in to_entries {
+            match from_lookup.remove(&entry.path) {
+                None => diff.added.push(entry),
+                Some(old) => {
+                    // The modification times are not compared, since they are
+                    // only recorded on request.
+                    if old.checksum != entry.checksum
+                        || old.file_metadata.mode != entry.file_metadata.mode
+                        || old.xattrs != entry.xattrs
+                    {
+                        diff.modified.push((old, entry));
+                    }
+                }
+            }
+        }
+        diff.removed = from_lookup.into_values().collect();
+
+        diff.added.sort_by(|a, b| a.path.cmp(&b.path));
+        diff.removed.sort_by(|a, b| a.path.cmp(&b.path));
+        diff.modified.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+        diff
+    }
+
+    /// Whether the file of the entry of HEAD was modified since HEAD was
+    /// extracted to `dir` (at `head_time`), i.e. its contents or type are not
+    /// the recorded ones. Only the files more recent than HEAD are read. A
+    /// missing file is not modified, since there is nothing to preserve.
+    fn is_modified_since(
+        &self,
+        head_time: SystemTime,
+        dir: &Path,
+        entry: &FileEntry,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<bool, Error> {
+        // Directories are only removed when they are empty.
+        if entry.file_metadata.is_dir() {
+            return Ok(false);
+        }
+        let metadata = match fs::symlink_metadata(join_entry_path(dir, &entry.path)?) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        if get_last_modified(metadata).is_some_and(|time| time <= head_time) {
+            return Ok(false);
+        }
+        let (work_entry, _, _) = read_file_entry(
+            dir,
+            PathBuf::from(&entry.path),
+            algorithm,
+            &StoreOptions::default(),
+            |_, _| None,
+        )?;
+        let file_type = |md: &FileMetadata| (md.is_dir(), md.is_symlink());
+        Ok(work_entry.checksum != entry.checksum
+            || file_type(&work_entry.file_metadata) != file_type(&entry.file_metadata))
+    }
+
     fn check_changed_since(&self, head_time: SystemTime, path: &Path) -> Result<(), Error> {
-        let last_modified = fs::metadata(path)
+        let last_modified = fs::symlink_metadata(path)
             // The modification date of the file is unknown, there is no other
             // option to fallback on, so we mark the directory as dirty.
             .map_err(|_| {
@@ -1142,7 +4729,7 @@ impl Repository {
     /// * `repo_path` - The root of the repository.
     fn write_loose_object(
         &self,
-        mut reader: impl Read,
+        buf: &[u8],
         temp_dir: &Path,
         checksum: &ObjectChecksum,
     ) -> io::Result<()> {
@@ -1155,7 +4742,7 @@ impl Repository {
 
         // Write to disk
         fs::create_dir_all(obj_path.parent().unwrap())?;
-        write_file_atomic(&mut reader, temp_dir, &obj_path)?;
+        write_sparse_file_atomic(buf, temp_dir, &obj_path)?;
         Ok(())
     }
 
@@ -1167,36 +4754,35 @@ impl Repository {
             .nth(num_components - 3)
             .ok_or_else(bad_object_error)?;
         let hex2_3 = path.components().nth(num_components - 2).unwrap();
-        let hex4_19 = path.components().last().unwrap();
+        let hex_rest = path.components().next_back().unwrap();
 
-        let bytes0_1 =
-            hex::decode(&*hex0_1.as_os_str().to_string_lossy()).map_err(|_| bad_object_error())?;
-        let bytes2_3 =
-            hex::decode(&*hex2_3.as_os_str().to_string_lossy()).map_err(|_| bad_object_error())?;
-        let bytes4_19 =
-            hex::decode(&*hex4_19.as_os_str().to_string_lossy()).map_err(|_| bad_object_error())?;
-
-        if bytes0_1.len() + bytes2_3.len() + bytes4_19.len() != 20 {
-            return Err(bad_object_error());
-        }
+        let bytes: Vec<u8> = [hex0_1, hex2_3, hex_rest]
+            .iter()
+            .map(|hex| hex::decode(&*hex.as_os_str().to_string_lossy()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad_object_error())?
+            .concat();
 
-        // Copy into array (try_into not supported by our minimum Rust version)
-        let mut result: ObjectChecksum = [0; 20];
-        for (i, byte) in bytes0_1
-            .into_iter()
-            .chain(bytes2_3)
-            .chain(bytes4_19)
-            .enumerate()
+        // Loose objects of any of the checksum algorithms can be present.
+        if !ChecksumAlgorithm::ALL
+            .iter()
+            .any(|algorithm| algorithm.size() == bytes.len())
         {
-            result[i] = byte;
+            return Err(bad_object_error());
         }
-        Ok(result)
+        Ok(ObjectChecksum::from_slice(&bytes).unwrap())
     }
 
     pub fn loose_object_path(&self, checksum: &ObjectChecksum) -> PathBuf {
+        self.object_path(LOOSE_DIR, checksum)
+    }
+
+    /// The path of an object in the specified directory of the repository data
+    /// (the loose object store, or the object cache, see [`CACHE_DIR`]).
+    fn object_path(&self, objects_dir: &str, checksum: &ObjectChecksum) -> PathBuf {
         let checksum_str = hex::encode(&checksum[..]);
         // $REPO_DIR/$LOOSE
-        let mut obj_path = self.data_dir().join(LOOSE_DIR);
+        let mut obj_path = self.data_dir().join(objects_dir);
         // $REPO_DIR/$LOOSE/FA/
         obj_path.push(&checksum_str[..2]);
         // $REPO_DIR/$LOOSE/FA/F0/
@@ -1208,14 +4794,16 @@ impl Repository {
 
     /// Updates all remotes and their associated .pack.idx files.
     pub fn update_remotes(&self) -> Result<(), Error> {
-        let remotes_dir = self.data_dir().join(REMOTES_DIR);
-        let remotes = remote::load_remotes(&remotes_dir)?;
+        let remotes = self.load_remotes()?;
 
-        let agent = ureq::AgentBuilder::new().build();
+        let agent = self.http_agent();
         let reporter = (self.progress_reporter_factory)("Fetching pack indexes from origin");
         // Display the progress bar immediately.
         reporter.checkpoint(0, Some(1));
 
+        // The packs failing the verification are all deleted (see
+        // Repository::verify_remote_pack) before the failures are reported.
+        let mut failures = vec![];
         for remote in remotes {
             // .path() is Some, because load_remotes guarantees it
             let remote_name = remote.path().unwrap().file_stem().unwrap();
@@ -1226,14 +4814,35 @@ impl Repository {
             let remote = remote::update_remote(&agent, &remote)?;
             fs::create_dir_all(&remote_packs_dir)?;
             remote::update_remote_pack_indexes(&agent, &remote, &remote_packs_dir, &reporter)?;
+            // The pack indexes are verified once, when they are fetched.
+            for pack in remote.packs() {
+                let pack_path = remote_packs_dir.join(pack.file_name());
+                let index_path = path_with_suffix(&pack_path, ".idx");
+                let signature_path = path_with_suffix(&pack_path, ".sig");
+                if index_path.exists() && is_signature_stale(&signature_path, &index_path) {
+                    match self.verify_remote_pack(
+                        &agent,
+                        &remote_name.to_string_lossy(),
+                        pack,
+                        &pack_path,
+                    ) {
+                        Err(Error::BadSignature(e)) => failures.push(e),
+                        result => result?,
+                    }
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::BadSignature(failures.join("; ")))
         }
-        Ok(())
     }
 
     /// Checks whether the snapshots have the same content checksum.
     fn are_snapshots_equal(&self, packs: &[PackId], snapshot: &str) -> Result<bool, Error> {
         let mut snapshot_checksums = packs.iter().map(|pack| {
-            self.load_index(pack)
+            self.load_index_cached(pack)
                 .map(|packidx| packidx.compute_snapshot_checksum(snapshot))
                 .expect("failed to resolve snapshot")
         });
@@ -1269,6 +4878,137 @@ impl Repository {
     }
 }
 
+/// Reads the file and computes its checksum. Returns the loose [`FileEntry`]
+/// for the file, along with its contents. Symbolic links are not followed: the
+/// contents of a link are the path it points to. The contents of a directory
+/// (which is stored as an empty directory) are empty. The file is read
+/// from `repo_dir`, at `file_path`, which is its path relative to the root.
+/// Creates the [`FileEntry`] of the file at `file_path` (relative to
+/// `repo_dir`). The file is only read if `cached_checksum` returns [`None`],
+/// in which case its contents are also returned, along with its metadata.
+fn read_file_entry(
+    repo_dir: &Path,
+    file_path: PathBuf,
+    algorithm: ChecksumAlgorithm,
+    opts: &StoreOptions,
+    cached_checksum: impl FnOnce(&OsStr, &fs::Metadata) -> Option<ObjectChecksum>,
+) -> io::Result<(FileEntry, Option<Vec<u8>>, fs::Metadata)> {
+    let entry_path = to_entry_path(&file_path);
+    let file_path = join_entry_path(repo_dir, &file_path)?;
+    let metadata = fs::symlink_metadata(&file_path)?;
+    let file_metadata = FileMetadata {
+        mode: file_mode(&metadata),
+        mtime: opts.record_times().then(|| modified_time(&metadata)),
+    };
+    let (mut entry, buf) = match cached_checksum(&entry_path, &metadata) {
+        Some(checksum) => (
+            make_loose_entry(entry_path, checksum, metadata.len(), file_metadata),
+            None,
+        ),
+        None => {
+            let buf = if metadata.is_dir() {
+                vec![]
+            } else if metadata.file_type().is_symlink() {
+                os_str_as_bytes(fs::read_link(&file_path)?.as_os_str()).into_owned()
+            } else {
+                let mut fd = File::open(&file_path)?;
+                let mut buf = vec![];
+                read_sparse(&mut fd, &mut buf)?;
+                buf
+            };
+            let entry = make_file_entry(entry_path, &buf, file_metadata, algorithm);
+            (entry, Some(buf))
+        }
+    };
+    if opts.xattrs() {
+        entry.xattrs = read_xattrs(&file_path)?;
+    }
+    Ok((entry, buf, metadata))
+}
+
+/// Moves the files of the entries extracted to `staging_dir` (see
+/// [`ExtractOptions::stage`]) to `path`, then removes `staging_dir`.
+fn move_staged_entries(entries: &[FileEntry], staging_dir: &Path, path: &Path) -> io::Result<()> {
+    for entry in entries {
+        let staged_path = join_entry_path(staging_dir, &entry.path)?;
+        let dest_path = create_entry_parents(path, &entry.path)?;
+        let is_existing_dir = fs::symlink_metadata(&dest_path).is_ok_and(|m| m.is_dir());
+        if entry.file_metadata.is_dir() && is_existing_dir {
+            // The directory is kept, since it can't be replaced if it is not
+            // empty (it might contain untracked files).
+            set_file_mode(&dest_path, entry.file_metadata.mode)?;
+        } else {
+            fs::rename(&staged_path, &dest_path)?;
+        }
+    }
+    fs::remove_dir_all(staging_dir)
+}
+
+/// Creates the symbolic link or the empty directory of an entry at `path`,
+/// from its object at `object_path`.
+fn create_special_file(
+    object_path: &Path,
+    path: &Path,
+    file_metadata: FileMetadata,
+) -> io::Result<()> {
+    if file_metadata.is_dir() {
+        create_entry_dir(path)?;
+        set_file_mode(path, file_metadata.mode)
+    } else {
+        create_symlink(&fs::read(object_path)?, path)
+    }
+}
+
+/// Checks that the key of a build cache entry is a relative path, whose
+/// parts are made of Latin letters, digits, -, _ and . (but are not . or ..).
+pub(crate) fn check_build_cache_key(key: &str) -> Result<(), Error> {
+    let is_valid = key.split('/').all(|part| {
+        !part.is_empty()
+            && part != "."
+            && part != ".."
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    });
+    if is_valid {
+        Ok(())
+    } else {
+        Err(Error::BadBuildCacheKey(key.to_owned()))
+    }
+}
+
+/// Creates the [`FileEntry`] of a loose object with the specified contents.
+fn make_file_entry(
+    path: OsString,
+    buf: &[u8],
+    file_metadata: FileMetadata,
+    algorithm: ChecksumAlgorithm,
+) -> FileEntry {
+    let checksum = algorithm.checksum(buf);
+    make_loose_entry(path, checksum, buf.len() as u64, file_metadata)
+}
+
+/// Creates the [`FileEntry`] of a loose object with the specified checksum.
+fn make_loose_entry(
+    path: OsString,
+    checksum: ObjectChecksum,
+    size: u64,
+    file_metadata: FileMetadata,
+) -> FileEntry {
+    FileEntry::new(
+        path,
+        checksum,
+        ObjectMetadata {
+            offset: LOOSE_OBJECT_OFFSET,
+            size,
+            delta: None,
+            chunks: None,
+            pack: None,
+        },
+        file_metadata,
+    )
+}
+
 /// Cleans the list of file paths relative to the repository root,
 /// and skips any paths pointing into the repository data directory.
 fn clean_file_list<P>(
@@ -1297,8 +5037,7 @@ where
             }
         })
         .map(|p| {
-            Ok(p.as_ref()
-                .canonicalize()?
+            Ok(canonicalize_link(p.as_ref())?
                 .components()
                 .skip(repo_dir.components().count())
                 .collect::<PathBuf>())
@@ -1316,28 +5055,95 @@ where
     Ok(files)
 }
 
+/// Returns true if the path of an entry, or the path of one of its parent
+/// directories, matches one of the patterns (see [`ExtractOptions::paths`]
+/// and [`ExtractOptions::excludes`]).
+fn matches_any(patterns: &[Pattern], path: &OsStr) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let path = path.to_string_lossy();
+    Path::new(path.as_ref())
+        .ancestors()
+        .filter_map(Path::to_str)
+        .filter(|p| !p.is_empty())
+        .any(|p| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches_with(p, options))
+        })
+}
+
+/// The path with the suffix appended (e.g. `.idx` to the path of a .pack).
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Whether the signature of the pack index is missing, or older than the
+/// pack index (i.e. the pack index was fetched since the signature).
+fn is_signature_stale(signature_path: &Path, index_path: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified());
+    match (modified(signature_path), modified(index_path)) {
+        (Ok(signature_time), Ok(index_time)) => signature_time < index_time,
+        _ => true,
+    }
+}
+
+/// Canonicalizes the path, without resolving its last component, so that the
+/// paths of symbolic links are kept.
+fn canonicalize_link(path: &Path) -> io::Result<PathBuf> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            Ok(parent.canonicalize()?.join(file_name))
+        }
+        _ => path.canonicalize(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::packidx::Xattr;
 
-    static EXAMPLE_MD: ObjectMetadata = ObjectMetadata {
+    const EXAMPLE_MD: ObjectMetadata = ObjectMetadata {
         size: 1,
         offset: LOOSE_OBJECT_OFFSET,
+        delta: None,
+        chunks: None,
+        pack: None,
     };
 
     #[test]
     fn building_loose_object_paths_works() {
-        let checksum = [
+        let checksum = ObjectChecksum::from([
             0xFA, 0xF0, 0xDE, 0xAD, 0xBE, 0xEF, 0xBA, 0xDC, 0x0D, 0xE0, 0xFA, 0xF0, 0xDE, 0xAD,
             0xBE, 0xEF, 0xBA, 0xDC, 0x0D, 0xE0,
-        ];
+        ]);
         let test_lock = std::env::temp_dir().join("elfshaker_test_lock");
         let repo = Repository {
             path: "/repo".into(),
             data_dir: "/repo/elfshaker_data".into(),
+            worktree_dir: None,
             progress_reporter_factory: Box::new(|_| ProgressReporter::dummy()),
             lock_file: fs::File::create(&test_lock).unwrap(),
             is_locked_exclusively: AtomicBool::new(false),
+            write_lock_file: fs::File::create(&test_lock).unwrap(),
+            is_locked_for_writing: AtomicBool::new(false),
+            http_auth: vec![],
+            remote_mirrors: HashMap::new(),
+            trusted_keys: vec![],
+            trust_policies: HashMap::new(),
+            encryption_keys: vec![],
+            index_cache: IndexCache::new(),
+            is_object_index_enabled: false,
         };
         fs::remove_file(&test_lock).unwrap();
         let path = repo.loose_object_path(&checksum);
@@ -1349,13 +5155,18 @@ mod tests {
             ),
             path.to_str().unwrap(),
         );
+        assert_eq!(checksum, repo.loose_object_checksum(&path).unwrap());
+
+        let checksum = ChecksumAlgorithm::Sha256.checksum(b"elfshaker");
+        let path = repo.loose_object_path(&checksum);
+        assert_eq!(checksum, repo.loose_object_checksum(&path).unwrap());
     }
 
     #[test]
     fn compute_entry_diff_finds_updates() {
         let path = "/path/to/A";
-        let old_checksum = [0; 20];
-        let new_checksum = [1; 20];
+        let old_checksum = ObjectChecksum::from([0; 20]);
+        let new_checksum = ObjectChecksum::from([1; 20]);
         let old_entries = [FileEntry::new(
             path.into(),
             old_checksum,
@@ -1378,10 +5189,10 @@ mod tests {
     #[test]
     fn compute_entry_diff_finds_update_of_duplicated() {
         let path_a = "/path/to/A";
-        let path_a_old_checksum = [0; 20];
+        let path_a_old_checksum = ObjectChecksum::from([0; 20]);
         let path_b = "/path/to/B";
-        let path_b_old_checksum = [0; 20];
-        let path_a_new_checksum = [1; 20];
+        let path_b_old_checksum = ObjectChecksum::from([0; 20]);
+        let path_a_new_checksum = ObjectChecksum::from([1; 20]);
         let old_entries = [
             FileEntry::new(
                 path_a.into(),
@@ -1410,14 +5221,85 @@ mod tests {
         assert!(removed.iter().any(|e| path_b == e.path));
     }
 
+    #[test]
+    fn compute_snapshot_diff_works() {
+        let entry = |path: &str, checksum, mode| {
+            let file_metadata = FileMetadata { mode, mtime: None };
+            FileEntry::new(path.into(), checksum, EXAMPLE_MD, file_metadata)
+        };
+        let old_entries = vec![
+            entry("same", ObjectChecksum::from([0; 20]), 0o100644),
+            entry("changed", ObjectChecksum::from([1; 20]), 0o100644),
+            entry("chmod", ObjectChecksum::from([2; 20]), 0o100644),
+            entry("removed", ObjectChecksum::from([3; 20]), 0o100644),
+            entry("xattr", ObjectChecksum::from([5; 20]), 0o100644),
+        ];
+        let mut new_entries = vec![
+            entry("added", ObjectChecksum::from([3; 20]), 0o100644),
+            entry("chmod", ObjectChecksum::from([2; 20]), 0o100755),
+            entry("changed", ObjectChecksum::from([4; 20]), 0o100644),
+            entry("same", ObjectChecksum::from([0; 20]), 0o100644),
+            entry("xattr", ObjectChecksum::from([5; 20]), 0o100644),
+        ];
+        new_entries[4].xattrs = vec![Xattr {
+            name: b"user.a".to_vec(),
+            value: b"b".to_vec(),
+        }];
+        let diff = Repository::compute_snapshot_diff(old_entries, new_entries);
+        assert_eq!(1, diff.added.len());
+        assert_eq!("added", diff.added[0].path);
+        assert_eq!(1, diff.removed.len());
+        assert_eq!("removed", diff.removed[0].path);
+        assert_eq!(3, diff.modified.len());
+        assert_eq!("changed", diff.modified[0].0.path);
+        assert_eq!(ObjectChecksum::from([4; 20]), diff.modified[0].1.checksum);
+        assert_eq!("chmod", diff.modified[1].0.path);
+        assert_eq!(0o100755, diff.modified[1].1.file_metadata.mode);
+        assert_eq!("xattr", diff.modified[2].0.path);
+        assert_eq!(1, diff.modified[2].1.xattrs.len());
+    }
+
+    #[test]
+    fn matches_any_works() {
+        let patterns = [
+            Pattern::new("lib/**/*.o").unwrap(),
+            Pattern::new("include").unwrap(),
+        ];
+        let matches = |path: &str| matches_any(&patterns, OsStr::new(path));
+        assert!(matches("lib/a.o"));
+        assert!(matches("lib/x/y/a.o"));
+        assert!(matches("include/elfshaker.h"));
+        assert!(matches("include"));
+        assert!(!matches("lib/a.c"));
+        assert!(!matches("src/lib/a.o"));
+        assert!(!matches("include.h"));
+        assert!(!matches_any(
+            &[Pattern::new("*.o").unwrap()],
+            OsStr::new("lib/a.o")
+        ));
+    }
+
+    #[test]
+    fn check_build_cache_key_works() {
+        for key in ["ab", "ab/cdef", "a.b_c-D/0"] {
+            assert!(check_build_cache_key(key).is_ok(), "{}", key);
+        }
+        for key in ["", "/ab", "ab/", "a//b", "../ab", "a/./b", "a b", "a\\b"] {
+            assert!(matches!(
+                check_build_cache_key(key),
+                Err(Error::BadBuildCacheKey(_))
+            ));
+        }
+    }
+
     #[test]
     fn compute_entry_diff_path_switch() {
         let path_a = "/path/to/A";
-        let path_a_old_checksum = [0; 20];
-        let path_a_new_checksum = [1; 20];
+        let path_a_old_checksum = ObjectChecksum::from([0; 20]);
+        let path_a_new_checksum = ObjectChecksum::from([1; 20]);
         let path_b = "/path/to/B";
-        let path_b_old_checksum = [1; 20];
-        let path_b_new_checksum = [0; 20];
+        let path_b_old_checksum = ObjectChecksum::from([1; 20]);
+        let path_b_new_checksum = ObjectChecksum::from([0; 20]);
         let old_entries = [
             FileEntry::new(
                 path_a.into(),
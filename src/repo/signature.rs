@@ -0,0 +1,421 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the detached Ed25519 signatures of the packs (the .pack.sig files
+//! stored alongside them), which are used to detect the packs tampered with by
+//! untrusted servers (e.g. mirrors).
+//!
+//! A signature file lists the SHA-256 digests of the .pack and of the
+//! .pack.idx, then the signatures of the digests by any number of keys:
+//!
+//! ```text
+//! elfshaker-pack-signature    v1
+//! pack-sha256     <hex digest of the .pack>
+//! index-sha256    <hex digest of the .pack.idx>
+//! ed25519 <hex public key>    <hex signature>
+//! ```
+//!
+//! The signed message is the first three lines (each ending with `\n`), so
+//! that the pack index can be verified before the pack is fetched.
+use std::{
+    convert::TryInto,
+    fmt::Display,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crypto::digest::Digest;
+use crypto::ed25519;
+use crypto::sha2::Sha256;
+use rand::RngCore;
+
+use super::error::Error;
+use super::fs::open_file;
+
+const SIGNATURE_HEADER: &str = "elfshaker-pack-signature";
+const SIGNATURE_VERSION: &str = "v1";
+
+pub type Digest256 = [u8; 32];
+
+/// The public key of an Ed25519 key pair, formatted as hex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublicKey([u8; 32]);
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        hex::decode(s.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(PublicKey)
+            .ok_or_else(|| {
+                Error::BadSignature(format!(
+                    "'{s}' is not an Ed25519 public key (64 hex digits)"
+                ))
+            })
+    }
+}
+
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+/// The secret key of an Ed25519 key pair (the 32-byte seed of RFC 8032),
+/// formatted as hex.
+#[derive(Clone)]
+pub struct SigningKey([u8; 32]);
+
+impl SigningKey {
+    /// Generates a new random key.
+    pub fn generate() -> Self {
+        let mut seed = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        Self(seed)
+    }
+
+    /// Loads the key from the file (see [`SigningKey::save`]).
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        hex::decode(text.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(SigningKey)
+            .ok_or_else(|| {
+                Error::BadSignature(format!(
+                    "{} does not contain an Ed25519 secret key (64 hex digits)",
+                    path.display()
+                ))
+            })
+    }
+
+    /// Writes the key to the file, which is only readable by the user.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        writeln!(options.open(path)?, "{}", hex::encode(self.0))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(ed25519::keypair(&self.0).1)
+    }
+
+    fn sign(&self, message: &[u8]) -> [u8; 64] {
+        ed25519::signature(message, &ed25519::keypair(&self.0).0)
+    }
+}
+
+/// The detached signature of a pack (see the module documentation).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackSignature {
+    pack_digest: Digest256,
+    index_digest: Digest256,
+    signatures: Vec<(PublicKey, [u8; 64])>,
+}
+
+impl PackSignature {
+    /// An unsigned signature of the pack, with the digests of its files.
+    pub fn for_pack(pack_path: &Path, index_path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            pack_digest: compute_digest(pack_path)?,
+            index_digest: compute_digest(index_path)?,
+            signatures: vec![],
+        })
+    }
+
+    /// The keys which signed the pack (the signatures are not verified).
+    pub fn signers(&self) -> impl Iterator<Item = &PublicKey> {
+        self.signatures.iter().map(|(key, _)| key)
+    }
+
+    /// Adds the signatures of `other` by the other keys, if it is the signature
+    /// of the same files.
+    pub fn extend(&mut self, other: PackSignature) {
+        if (other.pack_digest, other.index_digest) != (self.pack_digest, self.index_digest) {
+            return;
+        }
+        for (key, signature) in other.signatures {
+            if !self.signers().any(|signer| *signer == key) {
+                self.signatures.push((key, signature));
+            }
+        }
+    }
+
+    /// Signs the pack with the key, replacing its previous signature, if any.
+    pub fn sign(&mut self, key: &SigningKey) {
+        let public_key = key.public_key();
+        let signature = key.sign(self.message().as_bytes());
+        self.signatures.retain(|(signer, _)| *signer != public_key);
+        self.signatures.push((public_key, signature));
+    }
+
+    /// Verifies that the pack is signed by one of the trusted keys, and returns
+    /// it. The invalid signatures of the trusted keys are errors, while the
+    /// ones of the other keys are ignored.
+    pub fn verify(&self, trusted_keys: &[PublicKey]) -> Result<PublicKey, Error> {
+        self.valid_signers(trusted_keys)?
+            .first()
+            .copied()
+            .ok_or_else(|| Error::BadSignature("Not signed by any trusted key".to_owned()))
+    }
+
+    /// The keys of `keys` which signed the pack. Their invalid signatures are
+    /// errors.
+    fn valid_signers(&self, keys: &[PublicKey]) -> Result<Vec<PublicKey>, Error> {
+        let message = self.message();
+        let mut signers = vec![];
+        for (key, signature) in self.signatures.iter().filter(|(key, _)| keys.contains(key)) {
+            if !ed25519::verify(message.as_bytes(), &key.0, signature) {
+                return Err(Error::BadSignature(format!(
+                    "The signature of the trusted key {key} is invalid"
+                )));
+            }
+            signers.push(*key);
+        }
+        Ok(signers)
+    }
+
+    /// Verifies that the .pack.idx file is the signed one.
+    pub fn verify_index(&self, index_path: &Path) -> Result<(), Error> {
+        verify_digest(index_path, &self.index_digest)
+    }
+
+    /// Verifies that the .pack file is the signed one.
+    pub fn verify_pack(&self, pack_path: &Path) -> Result<(), Error> {
+        verify_digest(pack_path, &self.pack_digest)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::read(BufReader::new(open_file(path)?)).map_err(|e| match e {
+            Error::BadSignature(e) => Error::BadSignature(format!("{}: {}", path.display(), e)),
+            e => e,
+        })
+    }
+
+    /// Reads the signature file, see the module documentation.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let bad_format = |message: String| Error::BadSignature(message);
+        let mut lines = reader.lines();
+        let mut next_fields = |key: &str, count: usize| -> Result<Vec<String>, Error> {
+            let line = lines.next().ok_or_else(|| {
+                bad_format(format!("Expected '{key} ...', reached end of file"))
+            })??;
+            let fields: Vec<_> = line.split_whitespace().map(str::to_owned).collect();
+            if fields.len() != count + 1 || fields[0] != key {
+                return Err(bad_format(format!("Expected '{key} ...', found '{line}'")));
+            }
+            Ok(fields[1..].to_vec())
+        };
+        if next_fields(SIGNATURE_HEADER, 1)? != [SIGNATURE_VERSION] {
+            return Err(bad_format(format!(
+                "Expected version {SIGNATURE_VERSION} of the signature format"
+            )));
+        }
+        let digest = |fields: Vec<String>| {
+            hex::decode(&fields[0])
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| bad_format(format!("Bad SHA-256 digest '{}'", fields[0])))
+        };
+        let pack_digest = digest(next_fields("pack-sha256", 1)?)?;
+        let index_digest = digest(next_fields("index-sha256", 1)?)?;
+
+        let mut signatures = vec![];
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let signature = match fields[..] {
+                ["ed25519", key, signature] => hex::decode(signature)
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(|signature| (key, signature)),
+                _ => None,
+            };
+            let (key, signature) = signature.ok_or_else(|| {
+                bad_format(format!(
+                    "Expected 'ed25519 <public key> <signature>', found '{line}'"
+                ))
+            })?;
+            signatures.push((key.parse()?, signature));
+        }
+
+        Ok(Self {
+            pack_digest,
+            index_digest,
+            signatures,
+        })
+    }
+
+    /// Writes the signature file, see the module documentation.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.message().as_bytes())?;
+        for (key, signature) in &self.signatures {
+            writeln!(writer, "ed25519\t{}\t{}", key, hex::encode(signature))?;
+        }
+        Ok(())
+    }
+
+    /// The signed message: the lines of the digests.
+    fn message(&self) -> String {
+        format!(
+            "{SIGNATURE_HEADER}\t{SIGNATURE_VERSION}\n\
+             pack-sha256\t{}\n\
+             index-sha256\t{}\n",
+            hex::encode(self.pack_digest),
+            hex::encode(self.index_digest)
+        )
+    }
+}
+
+/// The policy which the signatures of the packs of a remote must satisfy
+/// (see [`Repository::set_trust_policies`](super::Repository::set_trust_policies)).
+/// The policy is only enforced if it lists keys.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustPolicy {
+    /// The pack must be signed by one of the keys (unless `required_signers`
+    /// are set).
+    pub trusted_keys: Vec<PublicKey>,
+    /// The pack must be signed by all the keys.
+    pub required_signers: Vec<PublicKey>,
+    /// Whether the packs without a signature are accepted. The packs with a
+    /// signature must still satisfy the policy.
+    pub allow_unsigned: bool,
+}
+
+impl TrustPolicy {
+    /// Whether the policy lists keys, so that the signatures are verified.
+    pub fn is_enforced(&self) -> bool {
+        !self.trusted_keys.is_empty() || !self.required_signers.is_empty()
+    }
+
+    /// Verifies that the signature satisfies the policy, and returns the keys
+    /// of the policy which signed the pack.
+    pub fn check(&self, signature: &PackSignature) -> Result<Vec<PublicKey>, Error> {
+        let keys: Vec<_> = self
+            .trusted_keys
+            .iter()
+            .chain(&self.required_signers)
+            .copied()
+            .collect();
+        let signers = signature.valid_signers(&keys)?;
+        if let Some(key) = self
+            .required_signers
+            .iter()
+            .find(|key| !signers.contains(key))
+        {
+            return Err(Error::BadSignature(format!(
+                "Not signed by the required signer {key}"
+            )));
+        }
+        if signers.is_empty() {
+            return Err(Error::BadSignature(
+                "Not signed by any trusted key".to_owned(),
+            ));
+        }
+        Ok(signers)
+    }
+}
+
+/// Computes the SHA-256 digest of the contents of the file.
+fn compute_digest(path: &Path) -> io::Result<Digest256> {
+    let mut sha256 = Sha256::new();
+    let mut reader = BufReader::new(open_file(path)?);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        sha256.input(&buf[..len]);
+    }
+    let mut digest = [0u8; 32];
+    sha256.result(&mut digest);
+    Ok(digest)
+}
+
+fn verify_digest(path: &Path, digest: &Digest256) -> Result<(), Error> {
+    if compute_digest(path)? != *digest {
+        return Err(Error::BadSignature(format!(
+            "{} is not the signed file",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify_works() -> Result<(), Error> {
+        // The first test vector of RFC 8032.
+        let key = SigningKey(
+            hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            key.public_key().to_string()
+        );
+        assert_eq!(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            hex::encode(key.sign(b""))
+        );
+
+        let mut signature = PackSignature {
+            pack_digest: [1; 32],
+            index_digest: [2; 32],
+            signatures: vec![],
+        };
+        let other_key = SigningKey([7; 32]);
+        signature.sign(&other_key);
+        signature.sign(&key);
+        assert_eq!(key.public_key(), signature.verify(&[key.public_key()])?);
+        assert!(signature
+            .verify(&[SigningKey([8; 32]).public_key()])
+            .is_err());
+
+        let mut buf = vec![];
+        signature.write(&mut buf)?;
+        let read = PackSignature::read(buf.as_slice())?;
+        assert_eq!(signature, read);
+
+        let policy = |trusted_keys: &[&SigningKey], required_signers: &[&SigningKey]| TrustPolicy {
+            trusted_keys: trusted_keys.iter().map(|key| key.public_key()).collect(),
+            required_signers: required_signers
+                .iter()
+                .map(|key| key.public_key())
+                .collect(),
+            allow_unsigned: false,
+        };
+        let third_key = SigningKey([9; 32]);
+        assert_eq!(
+            vec![key.public_key()],
+            policy(&[&key, &third_key], &[]).check(&signature)?
+        );
+        assert_eq!(2, policy(&[], &[&key, &other_key]).check(&signature)?.len());
+        assert!(policy(&[&key], &[&third_key]).check(&signature).is_err());
+        assert!(policy(&[&third_key], &[]).check(&signature).is_err());
+
+        // The signatures do not apply to other digests.
+        let mut tampered = read;
+        tampered.index_digest = [3; 32];
+        assert!(tampered.verify(&[key.public_key()]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the similarity signatures of objects (see
+//! [`PackOptions::similarity`](super::PackOptions::similarity)). The signature
+//! is a MinHash of the features of the contents, which are sampled with a
+//! rolling (gear) hash: objects sharing most of their contents share most of
+//! their features, so they likely have the same minimums, and sorting the
+//! objects by signature puts them next to each other in the pack.
+
+use super::chunk::GEAR;
+
+/// The number of minimums in a [`Signature`]. The later ones only break the
+/// ties between the objects with the same earlier minimums.
+const SIGNATURE_SIZE: usize = 4;
+
+/// A position is a feature when these bits of the rolling hash are all 0,
+/// which samples one position every 16 bytes on average.
+const FEATURE_MASK: u64 = 0xf << 60;
+
+/// The MinHash signature of an object. Objects without features (e.g. empty
+/// ones) have a signature of [`u64::MAX`]s, so they are sorted last.
+pub(crate) type Signature = [u64; SIGNATURE_SIZE];
+
+/// Computes the signature of `data`.
+pub(crate) fn signature(data: &[u8]) -> Signature {
+    let mut signature = [u64::MAX; SIGNATURE_SIZE];
+    // Like for chunk boundaries, the hash only depends on the last 64 bytes.
+    let mut hash: u64 = 0;
+    for &byte in data {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & FEATURE_MASK != 0 {
+            continue;
+        }
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = std::cmp::min(*min, mix(hash, i as u64));
+        }
+    }
+    signature
+}
+
+/// Derives the `i`-th independent hash of the feature (with the SplitMix64
+/// finalizer).
+fn mix(feature: u64, i: u64) -> u64 {
+    let mut z = feature ^ i.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_bytes(size: usize, mut state: u64) -> Vec<u8> {
+        (0..size)
+            .map(|_| {
+                // xorshift64
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn signature_works() {
+        let a = random_bytes(64 * 1024, 1);
+        let b = random_bytes(64 * 1024, 2);
+        let mut a2 = a.clone();
+        a2[1000] ^= 0xff;
+        a2.extend_from_slice(b"appended");
+
+        assert_eq!(signature(&a), signature(&a));
+        assert_eq!([u64::MAX; SIGNATURE_SIZE], signature(b""));
+        // Similar objects share most of their minimums, unlike different ones.
+        let shared = |x: &[u8], y: &[u8]| {
+            let (x, y) = (signature(x), signature(y));
+            x.iter().zip(&y).filter(|(x, y)| x == y).count()
+        };
+        assert!(shared(&a, &a2) >= SIGNATURE_SIZE - 1);
+        assert_eq!(0, shared(&a, &b));
+        // So sorting by signature puts the similar objects next to each other.
+        let mut sorted = [signature(&a), signature(&b), signature(&a2)];
+        sorted.sort();
+        assert_ne!(signature(&b), sorted[1]);
+    }
+}
@@ -0,0 +1,253 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains types for reading the contents of snapshots without extracting
+//! them to disk.
+use std::io::{self, BufRead, Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::fs::open_file;
+use super::pack::{verify_entry, PackStream};
+use crate::checksum::ChecksumAlgorithm;
+use crate::packidx::FileEntry;
+
+/// Iterates the files of a snapshot (or a subset of them), yielding the path
+/// of each file along with a reader for its contents. Returned by
+/// [`Repository::stream_snapshot`](super::Repository::stream_snapshot).
+///
+/// The files are yielded in the order in which their objects are stored, not
+/// in path order. Objects are read lazily, one at a time, so only a single
+/// object is held in memory. Iteration stops after the first error.
+pub struct SnapshotStream {
+    source: StreamSource,
+}
+
+enum StreamSource {
+    /// The entries of a loose snapshot, along with the paths of their objects
+    /// and the algorithm used to verify them (if enabled).
+    Loose(
+        std::vec::IntoIter<(FileEntry, PathBuf)>,
+        Option<ChecksumAlgorithm>,
+    ),
+    Pack(Box<PackStream>),
+    /// The streams of several packs, read one after the other (in reverse
+    /// order, so the next one is popped).
+    Chain(Vec<SnapshotStream>),
+}
+
+impl SnapshotStream {
+    pub(crate) fn loose(
+        objects: Vec<(FileEntry, PathBuf)>,
+        verify: Option<ChecksumAlgorithm>,
+    ) -> Self {
+        Self {
+            source: StreamSource::Loose(objects.into_iter(), verify),
+        }
+    }
+
+    pub(crate) fn pack(stream: PackStream) -> Self {
+        Self {
+            source: StreamSource::Pack(Box::new(stream)),
+        }
+    }
+
+    /// Reads the streams one after the other (e.g. of the packs storing the
+    /// objects of a snapshot, see [`ObjectMetadata::pack`](crate::packidx::ObjectMetadata::pack)).
+    pub(crate) fn chain(mut streams: Vec<SnapshotStream>) -> Self {
+        streams.reverse();
+        Self {
+            source: StreamSource::Chain(streams),
+        }
+    }
+
+    /// Reads the next object, along with the entry referencing it.
+    pub(crate) fn next_object(&mut self) -> Option<Result<(FileEntry, Vec<u8>), Error>> {
+        match &mut self.source {
+            StreamSource::Loose(objects, verify) => {
+                let (entry, path) = objects.next()?;
+                let object = read_loose_object(&path, &entry, *verify);
+                if object.is_err() {
+                    *objects = Vec::new().into_iter();
+                }
+                Some(object.map(|buf| (entry, buf)))
+            }
+            StreamSource::Pack(stream) => stream.next(),
+            StreamSource::Chain(streams) => loop {
+                match streams.last_mut()?.next_object() {
+                    Some(Err(e)) => {
+                        streams.clear();
+                        return Some(Err(e));
+                    }
+                    Some(object) => return Some(object),
+                    None => {
+                        streams.pop();
+                    }
+                }
+            },
+        }
+    }
+}
+
+fn read_loose_object(
+    path: &Path,
+    entry: &FileEntry,
+    verify: Option<ChecksumAlgorithm>,
+) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(entry.object_metadata.size as usize);
+    open_file(path)?.read_to_end(&mut buf)?;
+    if let Some(algorithm) = verify {
+        verify_entry(&buf, entry, algorithm)?;
+    }
+    Ok(buf)
+}
+
+impl Iterator for SnapshotStream {
+    type Item = Result<(PathBuf, ObjectReader), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_object()?.map(|(entry, buf)| {
+            let path = PathBuf::from(&entry.path);
+            (
+                path,
+                ObjectReader {
+                    entry,
+                    contents: Cursor::new(buf),
+                },
+            )
+        }))
+    }
+}
+
+/// Reads the contents of a file yielded by [`SnapshotStream`].
+pub struct ObjectReader {
+    entry: FileEntry,
+    contents: Cursor<Vec<u8>>,
+}
+
+impl ObjectReader {
+    /// The entry of the file in the snapshot (its checksum, permissions, etc.).
+    /// The object offset is relative to the containing frame of the pack
+    /// (unless the object is stored as chunks).
+    pub fn entry(&self) -> &FileEntry {
+        &self.entry
+    }
+
+    /// The size of the contents, in bytes.
+    pub fn len(&self) -> u64 {
+        self.contents.get_ref().len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the contents, without copying them.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.contents.into_inner()
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.contents.read(buf)
+    }
+}
+
+impl BufRead for ObjectReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.contents.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.contents.consume(amt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{PackId, PackOptions, Repository, SnapshotId, REPO_DIR};
+    use super::*;
+    use crate::codec::Codec;
+    use crate::packidx::{FileMetadata, IndexFormat, SnapshotMetadata};
+    use crate::progress::ProgressReporter;
+
+    fn read_stream(stream: SnapshotStream) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+        let mut files = stream
+            .map(|file| file.map(|(path, reader)| (path, reader.into_inner())))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.sort();
+        Ok(files)
+    }
+
+    #[test]
+    fn stream_snapshot_works() {
+        let dir =
+            std::env::temp_dir().join(format!("elfshaker_stream_test_{}", std::process::id()));
+        let data_dir = dir.join(REPO_DIR);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let mut repo = Repository::open_with_data_dir(&dir, &data_dir).unwrap();
+
+        let loose = SnapshotId::new(PackId::Pack("loose/s1".into()), "s1").unwrap();
+        let files = vec![
+            Ok(("a".into(), FileMetadata::default(), b"elf".to_vec())),
+            Ok(("b/c".into(), FileMetadata::default(), b"shaker".to_vec())),
+            Ok(("d".into(), FileMetadata::default(), b"elf".to_vec())),
+        ];
+        repo.create_snapshot_from_contents(&loose, files, SnapshotMetadata::default())
+            .unwrap();
+        let expected = vec![
+            (PathBuf::from("a"), b"elf".to_vec()),
+            (PathBuf::from("b/c"), b"shaker".to_vec()),
+            (PathBuf::from("d"), b"elf".to_vec()),
+        ];
+        let stream = repo.stream_snapshot(&loose, true).unwrap();
+        assert_eq!(expected, read_stream(stream).unwrap());
+
+        let pack = PackId::Pack("p".into());
+        let opts = PackOptions {
+            codec: Codec::Zstd,
+            compression_window_log: 0,
+            compression_level: 1,
+            num_workers: 1,
+            num_frames: 1,
+            max_frame_size: None,
+            delta: false,
+            chunk: false,
+            dictionary_size: None,
+            similarity: false,
+            dedup: false,
+            encryption_key: None,
+            encrypt_index: false,
+            index_format: IndexFormat::default(),
+        };
+        repo.create_pack_from_snapshots(
+            &pack,
+            std::slice::from_ref(&loose),
+            &opts,
+            &ProgressReporter::dummy(),
+        )
+        .unwrap();
+        let packed = SnapshotId::new(pack, "s1").unwrap();
+        let stream = repo.stream_snapshot(&packed, true).unwrap();
+        assert_eq!(expected, read_stream(stream).unwrap());
+
+        // The corrupted objects are only detected when verifying.
+        let checksum = repo.load_snapshot_entries(&loose).map(|entries| {
+            entries
+                .into_iter()
+                .find(|e| e.path.to_string_lossy() == "b/c")
+                .unwrap()
+                .checksum
+        });
+        let object_path = repo.loose_object_path(&checksum.unwrap());
+        std::fs::write(object_path, b"shaken").unwrap();
+        let stream = repo.stream_snapshot(&loose, false).unwrap();
+        assert_eq!(3, read_stream(stream).unwrap().len());
+        let mut stream = repo.stream_snapshot(&loose, true).unwrap();
+        assert!(stream.any(|file| file.is_err()));
+        assert!(stream.next().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
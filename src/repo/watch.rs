@@ -0,0 +1,317 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains [`Watcher`], which waits for the files of a working directory to
+//! change (see `elfshaker store --watch`). On Linux the changes are reported
+//! by inotify, elsewhere the directory is polled.
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Waits for the files of a directory (and of its subdirectories) to change.
+/// The files in the ignored directory (e.g. the data directory of the
+/// repository, which is modified when storing a snapshot) are not watched.
+pub struct Watcher {
+    root: PathBuf,
+    ignored: PathBuf,
+    inner: imp::Watcher,
+}
+
+impl Watcher {
+    /// Starts watching `root`, except `ignored` (both should be canonical).
+    /// The changes made after this returns are reported by
+    /// [`Watcher::wait_for_changes`].
+    pub fn new(root: &Path, ignored: &Path) -> io::Result<Self> {
+        let inner = imp::Watcher::new(root, ignored)?;
+        Ok(Self {
+            root: root.to_owned(),
+            ignored: ignored.to_owned(),
+            inner,
+        })
+    }
+
+    /// Blocks until some files change, then until no file changes for
+    /// `settle`, so that a single call covers all the changes made by e.g. a
+    /// build.
+    pub fn wait_for_changes(&mut self, settle: Duration) -> io::Result<()> {
+        self.inner.wait(&self.root, &self.ignored, None)?;
+        while self.inner.wait(&self.root, &self.ignored, Some(settle))? {}
+        Ok(())
+    }
+}
+
+/// Lists the directories to watch: `root` and its subdirectories, except
+/// `ignored` (and its subdirectories). Symbolic links are not followed.
+fn find_dirs(root: &Path, ignored: &Path) -> impl Iterator<Item = PathBuf> {
+    let ignored = ignored.to_owned();
+    walkdir::WalkDir::new(root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(move |e| !e.path().starts_with(&ignored))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+        .map(|e| e.into_path())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use super::find_dirs;
+
+    const EVENT_MASK: u32 = libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DONT_FOLLOW
+        | libc::IN_ONLYDIR;
+
+    /// Reports the changes through an inotify instance, with a watch on each
+    /// directory.
+    pub(super) struct Watcher {
+        fd: OwnedFd,
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl Watcher {
+        pub(super) fn new(root: &Path, ignored: &Path) -> io::Result<Self> {
+            // SAFETY: inotify_init1 only takes flags, and returns a new file
+            // descriptor or -1.
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut watcher = Self {
+                // SAFETY: The file descriptor was just opened, and nothing else
+                // owns (or closes) it.
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                dirs: HashMap::new(),
+            };
+            watcher.add_dirs(root, ignored)?;
+            Ok(watcher)
+        }
+
+        /// Watches `dir` and its subdirectories.
+        fn add_dirs(&mut self, dir: &Path, ignored: &Path) -> io::Result<()> {
+            for dir in find_dirs(dir, ignored) {
+                let c_path = CString::new(dir.as_os_str().as_bytes())?;
+                // SAFETY: The file descriptor is open while self is alive, and
+                // c_path is a NUL-terminated string outliving the call.
+                let wd = unsafe {
+                    libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), EVENT_MASK)
+                };
+                if wd >= 0 {
+                    self.dirs.insert(wd, dir);
+                } else {
+                    let e = io::Error::last_os_error();
+                    // The directory may have been removed in the meantime.
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Waits for changes for up to `timeout` (forever if [`None`]).
+        /// Returns whether there were any changes.
+        pub(super) fn wait(
+            &mut self,
+            root: &Path,
+            ignored: &Path,
+            timeout: Option<Duration>,
+        ) -> io::Result<bool> {
+            let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+            loop {
+                let mut pollfd = libc::pollfd {
+                    fd: self.fd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                // SAFETY: pollfd is a valid array of 1 pollfd, which is only
+                // borrowed for the call.
+                let n = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+                if n < 0 {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                if n == 0 {
+                    return Ok(false);
+                }
+                // If all the changes were ignored, keep waiting (for the whole
+                // timeout again, which is good enough).
+                if self.read_events(root, ignored)? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        /// Reads the pending events, watching the new directories. Returns
+        /// whether any of them is a change outside of `ignored`.
+        fn read_events(&mut self, root: &Path, ignored: &Path) -> io::Result<bool> {
+            let mut buf = [0u8; 16384];
+            // SAFETY: The kernel writes at most buf.len() bytes to buf, which
+            // is borrowed mutably for the call.
+            let len = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut changed = false;
+            let mut new_dirs = vec![];
+            let mut offset = 0;
+            let header_size = std::mem::size_of::<libc::inotify_event>();
+            while offset + header_size <= len as usize {
+                // SAFETY: The loop condition ensures that buf holds a whole
+                // inotify_event at offset, which is read without assuming that
+                // it is aligned; any bit pattern is a valid inotify_event.
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+                let name = &buf[offset + header_size..offset + header_size + event.len as usize];
+                offset += header_size + event.len as usize;
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    // Events were lost, including the creation of directories.
+                    new_dirs.push(root.to_owned());
+                    changed = true;
+                    continue;
+                }
+                if event.mask & libc::IN_IGNORED != 0 {
+                    self.dirs.remove(&event.wd);
+                    continue;
+                }
+                let Some(dir) = self.dirs.get(&event.wd) else {
+                    continue;
+                };
+                // The name is padded with NULs.
+                let name = name.split(|&c| c == 0).next().unwrap_or_default();
+                let path = dir.join(OsStr::from_bytes(name));
+                if path.starts_with(ignored) {
+                    continue;
+                }
+                if event.mask & libc::IN_ISDIR != 0
+                    && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+                {
+                    new_dirs.push(path);
+                }
+                changed = true;
+            }
+            for dir in new_dirs {
+                self.add_dirs(&dir, ignored)?;
+            }
+            Ok(changed)
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use super::find_dirs;
+
+    /// How often the directory is scanned when waiting without a timeout.
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+    type Snapshot = HashMap<PathBuf, (u64, Option<SystemTime>)>;
+
+    /// Reports the changes by comparing the size and modification time of
+    /// the files to the ones of the previous scan.
+    pub(super) struct Watcher {
+        files: Snapshot,
+    }
+
+    fn scan(root: &Path, ignored: &Path) -> Snapshot {
+        find_dirs(root, ignored)
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let metadata = e.metadata().ok()?;
+                Some((e.path(), (metadata.len(), metadata.modified().ok())))
+            })
+            .collect()
+    }
+
+    impl Watcher {
+        pub(super) fn new(root: &Path, ignored: &Path) -> io::Result<Self> {
+            Ok(Self {
+                files: scan(root, ignored),
+            })
+        }
+
+        /// Waits for changes for up to `timeout` (forever if [`None`]).
+        /// Returns whether there were any changes.
+        pub(super) fn wait(
+            &mut self,
+            root: &Path,
+            ignored: &Path,
+            timeout: Option<Duration>,
+        ) -> io::Result<bool> {
+            loop {
+                std::thread::sleep(timeout.unwrap_or(POLL_INTERVAL));
+                let files = scan(root, ignored);
+                let changed = files != self.files;
+                self.files = files;
+                if changed || timeout.is_some() {
+                    return Ok(changed);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::create_temp_path;
+    use std::fs;
+
+    #[test]
+    fn watcher_works() {
+        let root = create_temp_path(&std::env::temp_dir());
+        let ignored = root.join("elfshaker_data");
+        fs::create_dir_all(&ignored).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        let mut watcher = Watcher::new(&root, &ignored).unwrap();
+        let timeout = Some(Duration::from_millis(500));
+        let mut has_changes = || watcher.inner.wait(&root, &ignored, timeout).unwrap();
+
+        // Changes in the ignored directory are not reported.
+        fs::write(ignored.join("HEAD"), b"ignored").unwrap();
+        assert!(!has_changes());
+        // Changes in new directories are reported.
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        assert!(has_changes());
+        while has_changes() {}
+        fs::write(root.join("a/b/c"), b"changed").unwrap();
+        assert!(has_changes());
+        while has_changes() {}
+        // Settles once no file changes.
+        fs::write(root.join("a/b/c"), b"changed again").unwrap();
+        watcher
+            .wait_for_changes(Duration::from_millis(200))
+            .unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,96 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! Contains the bookkeeping of the linked working trees, which share the data
+//! directory of a repository but have a HEAD of their own (see
+//! [`Repository::add_worktree`](super::Repository::add_worktree)).
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::constants::{COMMON_DIR_FILE, WORKTREES_FILE};
+use super::fs::{ensure_dir, write_file_atomic};
+
+/// The data directory shared by the linked working tree whose data directory
+/// is `data_dir`, or `data_dir` itself if it is not the one of a linked
+/// working tree.
+pub(crate) fn common_data_dir(data_dir: &Path) -> io::Result<PathBuf> {
+    match fs::read_to_string(data_dir.join(COMMON_DIR_FILE)) {
+        Ok(common_dir) => Ok(PathBuf::from(common_dir.trim_end_matches('\n'))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(data_dir.to_owned()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Makes `data_dir` the data directory of a linked working tree, sharing
+/// `common_dir`.
+pub(crate) fn link_data_dir(data_dir: &Path, common_dir: &Path) -> io::Result<()> {
+    ensure_dir(data_dir)?;
+    let mut common_dir = common_dir.as_os_str().to_owned();
+    common_dir.push("\n");
+    fs::write(
+        data_dir.join(COMMON_DIR_FILE),
+        common_dir.as_encoded_bytes(),
+    )
+}
+
+/// The paths of the linked working trees registered in the shared data
+/// directory.
+pub(crate) fn read_worktrees(common_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    match fs::read_to_string(common_dir.join(WORKTREES_FILE)) {
+        Ok(worktrees) => Ok(worktrees
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replaces the paths of the linked working trees registered in the shared
+/// data directory.
+pub(crate) fn write_worktrees(
+    common_dir: &Path,
+    temp_dir: &Path,
+    worktrees: &[PathBuf],
+) -> io::Result<()> {
+    let mut text = String::new();
+    for worktree in worktrees {
+        let worktree = worktree.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The path {worktree:?} is not valid UTF-8!"),
+            )
+        })?;
+        text.push_str(worktree);
+        text.push('\n');
+    }
+    ensure_dir(temp_dir)?;
+    write_file_atomic(text.as_bytes(), temp_dir, &common_dir.join(WORKTREES_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::create_temp_path;
+
+    #[test]
+    fn worktrees_work() {
+        let dir = create_temp_path(&std::env::temp_dir());
+        let common_dir = dir.join("main");
+        let data_dir = dir.join("linked").join("elfshaker_data");
+        fs::create_dir_all(&common_dir).unwrap();
+
+        assert_eq!(common_dir, common_data_dir(&common_dir).unwrap());
+        link_data_dir(&data_dir, &common_dir).unwrap();
+        assert_eq!(common_dir, common_data_dir(&data_dir).unwrap());
+
+        assert!(read_worktrees(&common_dir).unwrap().is_empty());
+        let worktrees = vec![dir.join("linked"), dir.join("other")];
+        write_worktrees(&common_dir, &dir.join("trash"), &worktrees).unwrap();
+        assert_eq!(worktrees, read_worktrees(&common_dir).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,416 @@
+//! SPDX-License-Identifier: Apache-2.0
+//! Copyright (C) 2021 Arm Limited or its affiliates and Contributors. All rights reserved.
+
+//! A small writer of SQLite databases, used to export the indexes of the
+//! packs. It writes the tables (without indexes) of a new database in a
+//! single pass, in the file format of SQLite 3: the rows are appended to the
+//! leaf pages of their table, which are written as they fill up, and the
+//! interior pages of the B-trees (and the schema, in the first page) are
+//! written once all the rows are inserted.
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const PAGE_SIZE: usize = 4096;
+/// The header of the database, at the start of the first page.
+const DATABASE_HEADER_SIZE: usize = 100;
+const LEAF_HEADER_SIZE: usize = 8;
+const INTERIOR_HEADER_SIZE: usize = 12;
+const TABLE_LEAF_PAGE: u8 = 0x0d;
+const TABLE_INTERIOR_PAGE: u8 = 0x05;
+/// The most children of an interior page, whose cells (a page number and a
+/// varint) take at most 15 bytes with their pointer.
+const MAX_INTERIOR_CHILDREN: usize = (PAGE_SIZE - INTERIOR_HEADER_SIZE) / 15;
+/// The version of SQLite recorded as the last one which wrote the database.
+const SQLITE_VERSION_NUMBER: u32 = 3_040_001;
+
+/// The value of a column.
+#[derive(Clone, Copy, Debug)]
+pub enum Value<'a> {
+    Null,
+    Integer(i64),
+    Text(&'a str),
+    Blob(&'a [u8]),
+}
+
+/// A table being written.
+struct Table {
+    name: String,
+    sql: String,
+    next_rowid: i64,
+    /// The cells of the leaf page being filled.
+    cells: Vec<Vec<u8>>,
+    cells_size: usize,
+    /// The pages which are full, with the largest rowid of each.
+    pages: Vec<(u32, i64)>,
+}
+
+/// A table of a [`Database`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableId(usize);
+
+/// A new SQLite database, which is only valid once [`Database::finish`]
+/// returns.
+pub struct Database {
+    writer: BufWriter<File>,
+    page_count: u32,
+    tables: Vec<Table>,
+}
+
+impl Database {
+    /// Creates the database, replacing the file if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        // The first page is written last, with the schema.
+        writer.write_all(&[0; PAGE_SIZE])?;
+        Ok(Self {
+            writer,
+            page_count: 1,
+            tables: vec![],
+        })
+    }
+
+    /// Creates the table, whose columns are defined as in `CREATE TABLE`
+    /// (e.g. `id INTEGER PRIMARY KEY, name TEXT`).
+    pub fn create_table(&mut self, name: &str, columns: &str) -> TableId {
+        self.tables.push(Table {
+            name: name.to_owned(),
+            sql: format!("CREATE TABLE {name} ({columns})"),
+            next_rowid: 1,
+            cells: vec![],
+            cells_size: 0,
+            pages: vec![],
+        });
+        TableId(self.tables.len() - 1)
+    }
+
+    /// Inserts the row in the table, and returns its rowid. The column which
+    /// is the `INTEGER PRIMARY KEY` of the table, if any, must be
+    /// [`Value::Null`], since it is the rowid.
+    pub fn insert(&mut self, table: TableId, values: &[Value]) -> io::Result<i64> {
+        let rowid = self.tables[table.0].next_rowid;
+        let payload = encode_record(values);
+        let local_size = local_payload_size(payload.len());
+
+        let mut cell = vec![];
+        put_varint(&mut cell, payload.len() as u64);
+        put_varint(&mut cell, rowid as u64);
+        cell.extend_from_slice(&payload[..local_size]);
+        if local_size < payload.len() {
+            let first_page = self.write_overflow_pages(&payload[local_size..])?;
+            cell.extend_from_slice(&first_page.to_be_bytes());
+        }
+
+        let t = &self.tables[table.0];
+        if LEAF_HEADER_SIZE + t.cells_size + cell.len() + 2 > PAGE_SIZE {
+            let page = leaf_page(&t.cells, 0);
+            let last_rowid = t.next_rowid - 1;
+            let page_number = self.write_page(&page)?;
+            let t = &mut self.tables[table.0];
+            t.pages.push((page_number, last_rowid));
+            t.cells.clear();
+            t.cells_size = 0;
+        }
+        let t = &mut self.tables[table.0];
+        t.cells_size += cell.len() + 2;
+        t.cells.push(cell);
+        t.next_rowid += 1;
+        Ok(rowid)
+    }
+
+    /// Writes the remaining pages of the tables and the schema.
+    pub fn finish(mut self) -> io::Result<()> {
+        let mut schema = vec![];
+        for i in 0..self.tables.len() {
+            let root = self.write_tree(i)?;
+            let table = &self.tables[i];
+            let record = encode_record(&[
+                Value::Text("table"),
+                Value::Text(&table.name),
+                Value::Text(&table.name),
+                Value::Integer(root.into()),
+                Value::Text(&table.sql),
+            ]);
+            let mut cell = vec![];
+            put_varint(&mut cell, record.len() as u64);
+            put_varint(&mut cell, i as u64 + 1);
+            cell.extend_from_slice(&record);
+            schema.push(cell);
+        }
+        let schema_size: usize = schema.iter().map(|cell| cell.len() + 2).sum();
+        if DATABASE_HEADER_SIZE + LEAF_HEADER_SIZE + schema_size > PAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The schema of the database doesn't fit in a page!",
+            ));
+        }
+
+        let mut page = leaf_page(&schema, DATABASE_HEADER_SIZE);
+        page[..DATABASE_HEADER_SIZE].copy_from_slice(&database_header(self.page_count));
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&page)?;
+        file.sync_all()
+    }
+
+    /// Writes the pages of the B-tree of the table which are not written yet,
+    /// and returns its root page.
+    fn write_tree(&mut self, table: usize) -> io::Result<u32> {
+        let t = &mut self.tables[table];
+        let cells = std::mem::take(&mut t.cells);
+        let last_rowid = t.next_rowid - 1;
+        let mut level = std::mem::take(&mut t.pages);
+        // An empty table is a leaf page with no cells.
+        if !cells.is_empty() || level.is_empty() {
+            let page_number = self.write_page(&leaf_page(&cells, 0))?;
+            level.push((page_number, last_rowid));
+        }
+        while level.len() > 1 {
+            let mut parents = vec![];
+            for children in level.chunks(MAX_INTERIOR_CHILDREN) {
+                let (right_child, last_rowid) = *children.last().unwrap();
+                let page = interior_page(&children[..children.len() - 1], right_child);
+                parents.push((self.write_page(&page)?, last_rowid));
+            }
+            level = parents;
+        }
+        Ok(level[0].0)
+    }
+
+    /// Writes the overflowing part of a payload, and returns its first page.
+    fn write_overflow_pages(&mut self, payload: &[u8]) -> io::Result<u32> {
+        let chunks: Vec<_> = payload.chunks(PAGE_SIZE - 4).collect();
+        let first_page = self.page_count + 1;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut page = vec![0; PAGE_SIZE];
+            // The pages are written in order, so the next one follows.
+            let next_page = if i + 1 < chunks.len() {
+                first_page + i as u32 + 1
+            } else {
+                0
+            };
+            page[..4].copy_from_slice(&next_page.to_be_bytes());
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.write_page(&page)?;
+        }
+        Ok(first_page)
+    }
+
+    /// Appends the page to the database, and returns its (1-based) number.
+    fn write_page(&mut self, page: &[u8]) -> io::Result<u32> {
+        assert_eq!(PAGE_SIZE, page.len());
+        self.writer.write_all(page)?;
+        self.page_count += 1;
+        Ok(self.page_count)
+    }
+}
+
+/// The header of a database of `page_count` pages, which is only written once.
+fn database_header(page_count: u32) -> [u8; DATABASE_HEADER_SIZE] {
+    let mut header = [0; DATABASE_HEADER_SIZE];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    // The file format versions (legacy, not WAL).
+    header[18] = 1;
+    header[19] = 1;
+    // The payload fractions, which must be 64, 32 and 32.
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    // The file change counter, page count, schema cookie, schema format and
+    // text encoding (UTF-8).
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&page_count.to_be_bytes());
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());
+    // The change counter the page count is valid for, and the SQLite version.
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+    header[96..100].copy_from_slice(&SQLITE_VERSION_NUMBER.to_be_bytes());
+    header
+}
+
+/// A table leaf page with the cells, whose header starts at `offset`.
+fn leaf_page(cells: &[Vec<u8>], offset: usize) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let mut content_start = PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(cell);
+        let pointer = offset + LEAF_HEADER_SIZE + 2 * i;
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+    page[offset] = TABLE_LEAF_PAGE;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[offset + 5..offset + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    page
+}
+
+/// A table interior page with the children (and the largest rowid of each),
+/// followed by the rightmost child.
+fn interior_page(children: &[(u32, i64)], right_child: u32) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let mut content_start = PAGE_SIZE;
+    for (i, (child, rowid)) in children.iter().enumerate() {
+        let mut cell = child.to_be_bytes().to_vec();
+        put_varint(&mut cell, *rowid as u64);
+        content_start -= cell.len();
+        page[content_start..content_start + cell.len()].copy_from_slice(&cell);
+        let pointer = INTERIOR_HEADER_SIZE + 2 * i;
+        page[pointer..pointer + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+    }
+    page[0] = TABLE_INTERIOR_PAGE;
+    page[3..5].copy_from_slice(&(children.len() as u16).to_be_bytes());
+    page[5..7].copy_from_slice(&(content_start as u16).to_be_bytes());
+    page[8..12].copy_from_slice(&right_child.to_be_bytes());
+    page
+}
+
+/// The number of bytes of a payload stored in its table leaf cell, the rest
+/// being stored in overflow pages.
+fn local_payload_size(payload_size: usize) -> usize {
+    let max_local = PAGE_SIZE - 35;
+    if payload_size <= max_local {
+        return payload_size;
+    }
+    let min_local = (PAGE_SIZE - 12) * 32 / 255 - 23;
+    let size = min_local + (payload_size - min_local) % (PAGE_SIZE - 4);
+    if size <= max_local {
+        size
+    } else {
+        min_local
+    }
+}
+
+/// Encodes the values in the record format: a header with the (varint)
+/// serial type of each value, followed by the values.
+fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut types = vec![];
+    let mut body = vec![];
+    for value in values {
+        let serial_type = match *value {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(n) => {
+                let (serial_type, size) = match n {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&n.to_be_bytes()[8 - size..]);
+                serial_type
+            }
+            Value::Text(s) => {
+                body.extend_from_slice(s.as_bytes());
+                s.len() as u64 * 2 + 13
+            }
+            Value::Blob(b) => {
+                body.extend_from_slice(b);
+                b.len() as u64 * 2 + 12
+            }
+        };
+        put_varint(&mut types, serial_type);
+    }
+    // The size of the header includes its own varint.
+    let mut header_size = types.len() + 1;
+    while varint_len(header_size as u64) + types.len() != header_size {
+        header_size = varint_len(header_size as u64) + types.len();
+    }
+    let mut record = vec![];
+    put_varint(&mut record, header_size as u64);
+    record.extend_from_slice(&types);
+    record.extend_from_slice(&body);
+    record
+}
+
+/// Appends the value as a big-endian varint of 1 to 9 bytes, with 7 bits in
+/// each byte (but 8 in the 9th).
+fn put_varint(buf: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        let mut bytes = [0; 9];
+        bytes[8] = value as u8;
+        let mut value = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (value & 0x7f) as u8 | 0x80;
+            value >>= 7;
+        }
+        buf.extend_from_slice(&bytes);
+        return;
+    }
+    let len = varint_len(value);
+    for i in (0..len).rev() {
+        let byte = ((value >> (7 * i)) & 0x7f) as u8;
+        buf.push(if i > 0 { byte | 0x80 } else { byte });
+    }
+}
+
+fn varint_len(value: u64) -> usize {
+    if value >> 56 != 0 {
+        return 9;
+    }
+    let bits = 64 - value.leading_zeros() as usize;
+    bits.max(1).div_ceil(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::fs::create_temp_path;
+    use std::convert::TryInto;
+
+    #[test]
+    fn sqlite_works() {
+        let varint = |value| {
+            let mut buf = vec![];
+            put_varint(&mut buf, value);
+            buf
+        };
+        assert_eq!(vec![0x00], varint(0));
+        assert_eq!(vec![0x7f], varint(0x7f));
+        assert_eq!(vec![0x81, 0x00], varint(0x80));
+        assert_eq!(vec![0xff; 9], varint(u64::MAX));
+        assert_eq!(
+            vec![5, 0, 1, 0x0f, 0x10, 0xff, b'a', 0xde, 0xad],
+            encode_record(&[
+                Value::Null,
+                Value::Integer(-1),
+                Value::Text("a"),
+                Value::Blob(&[0xde, 0xad]),
+            ])
+        );
+        assert_eq!(100, local_payload_size(100));
+        assert_eq!(PAGE_SIZE - 35, local_payload_size(PAGE_SIZE - 35));
+        assert_eq!(489, local_payload_size(PAGE_SIZE - 34));
+
+        // Enough rows for interior pages, and a row with overflow pages.
+        let dir = create_temp_path(&std::env::temp_dir());
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.sqlite");
+        let mut db = Database::create(&path).unwrap();
+        let a = db.create_table("a", "id INTEGER PRIMARY KEY, name TEXT");
+        let b = db.create_table("b", "value BLOB");
+        db.create_table("c", "value TEXT");
+        for i in 0..100_000 {
+            let name = format!("row-{i}");
+            let rowid = db.insert(a, &[Value::Null, Value::Text(&name)]).unwrap();
+            assert_eq!(i + 1, rowid);
+        }
+        db.insert(b, &[Value::Blob(&[7; 3 * PAGE_SIZE])]).unwrap();
+        db.finish().unwrap();
+
+        let buf = std::fs::read(&path).unwrap();
+        assert_eq!(b"SQLite format 3\0", &buf[..16]);
+        assert_eq!(0, buf.len() % PAGE_SIZE);
+        let page_count = u32::from_be_bytes(buf[28..32].try_into().unwrap());
+        assert_eq!(buf.len() / PAGE_SIZE, page_count as usize);
+        // The schema has the 3 tables.
+        assert_eq!(TABLE_LEAF_PAGE, buf[DATABASE_HEADER_SIZE]);
+        assert_eq!(3, u16::from_be_bytes([buf[103], buf[104]]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -47,8 +47,13 @@ all_pwd_sha1sums() {
     awk '{print $1" "$2}' | sort -k2,2
 }
 
+# The checksums stored in the pack index depend on the checksum algorithm of
+# the pack (SHA-1 or BLAKE3), so the snapshot is exported and hashed instead.
 elfshaker_sha1sums() {
-  "$elfshaker" list-files "$@" 2> /dev/null | awk '{print $1" "$3}' | sort -k2,2
+  export_dir=$(mktemp -d)
+  "$elfshaker" export "$@" --tar 2> /dev/null | tar -x -C "$export_dir"
+  (cd "$export_dir" && all_pwd_sha1sums)
+  rm -rf "$export_dir"
 }
 
 verify_snapshot() {
@@ -126,6 +131,278 @@ test_list_works() {
   "$elfshaker" list
 }
 
+test_list_filter_works() {
+  echo a > a
+  "$elfshaker" store list-filter-a
+  echo b > b
+  "$elfshaker" store list-filter-b
+  echo c > c
+  "$elfshaker" store release-1
+  filtered() {
+    "$elfshaker" list --format %t --filter "$1" | sort | paste -sd ' '
+  }
+  [ "$(filtered 'list-filter-*')" == 'list-filter-a list-filter-b' ]
+  [ "$(filtered 'release-?')" == 'release-1' ]
+  [ "$(filtered '*-b')" == 'list-filter-b' ]
+  [ "$(filtered 'none-*')" == '' ]
+  # The pattern must be a valid glob.
+  if "$elfshaker" list --filter '['; then exit 1; fi
+}
+
+test_diff_works() {
+  mkdir diff
+  echo a > diff/a
+  echo b > diff/b
+  echo c > diff/c
+  "$elfshaker" store diff-1
+  chmod +x diff/a
+  echo B > diff/b
+  rm diff/c
+  echo d > diff/d
+  "$elfshaker" store diff-2
+  [ "$("$elfshaker" diff diff-1 diff-2)" == "$(printf 'M\tdiff/a\nM\tdiff/b\nD\tdiff/c\nA\tdiff/d')" ]
+  [ "$("$elfshaker" diff diff-2 diff-1)" == "$(printf 'M\tdiff/a\nM\tdiff/b\nA\tdiff/c\nD\tdiff/d')" ]
+  [ "$("$elfshaker" diff diff-1 diff-1)" == '' ]
+  # Also between a packed and a loose snapshot.
+  "$elfshaker" pack diff-p
+  "$elfshaker" gc --loose-snapshots
+  echo e > diff/e
+  "$elfshaker" store diff-3
+  [ "$("$elfshaker" diff diff-p:diff-2 diff-3)" == "$(printf 'A\tdiff/e')" ]
+  if "$elfshaker" diff diff-1 diff-4; then exit 1; fi
+  rm elfshaker_data/packs/diff-p.pack{,.idx}
+}
+
+test_gc_default_works() {
+  echo a > a
+  "$elfshaker" store gc-default-1
+  echo b > a
+  "$elfshaker" store gc-default-2
+  "$elfshaker" pack gc-default-p
+  "$elfshaker" gc --dry-run
+  [ -f elfshaker_data/packs/loose/gc-default-1.pack.idx ]
+  [ -n "$(find elfshaker_data/loose -type f)" ]
+  # Without --loose-snapshots or --loose-objects, gc cleans up both.
+  "$elfshaker" gc
+  [ ! -e elfshaker_data/packs/loose/gc-default-1.pack.idx ]
+  [ ! -e elfshaker_data/packs/loose/gc-default-2.pack.idx ]
+  [ -z "$(find elfshaker_data/loose -type f)" ]
+  "$elfshaker" extract --reset --verify gc-default-p:gc-default-1
+  [ "$(cat a)" == a ]
+  rm elfshaker_data/packs/gc-default-p.pack{,.idx}
+}
+
+test_upgrade_works() {
+  # The verification pack was created by an older version of elfshaker, which
+  # used SHA-1 checksums.
+  cp "elfshaker_data/packs/$pack.pack" elfshaker_data/packs/upgrade-p.pack
+  cp "elfshaker_data/packs/$pack.pack.idx" elfshaker_data/packs/upgrade-p.pack.idx
+  "$elfshaker" upgrade upgrade-p
+  if cmp -s "elfshaker_data/packs/$pack.pack.idx" elfshaker_data/packs/upgrade-p.pack.idx; then
+    echo 'Expected the index to be upgraded'
+    exit 1
+  fi
+  cmp "elfshaker_data/packs/$pack.pack" elfshaker_data/packs/upgrade-p.pack
+  # The objects are now verified against their BLAKE3 checksums.
+  "$elfshaker" extract --reset --verify upgrade-p:"$snapshot_a"
+  verify_snapshot upgrade-p:"$snapshot_a"
+  "$elfshaker" extract --verify upgrade-p:"$snapshot_b"
+  verify_snapshot upgrade-p:"$snapshot_b"
+  # The upgraded packs are left as they are.
+  cp elfshaker_data/packs/upgrade-p.pack.idx upgraded.idx
+  "$elfshaker" upgrade upgrade-p
+  cmp upgraded.idx elfshaker_data/packs/upgrade-p.pack.idx
+  rm elfshaker_data/packs/upgrade-p.pack{,.idx}
+}
+
+test_checksum_algorithm_works() {
+  echo a > a
+  "$elfshaker" store sha256-0
+  # Changes the algorithm of the repository, and upgrades the loose snapshot.
+  "$elfshaker" upgrade --checksum-algorithm sha256 loose/sha256-0
+  [ "$(cat elfshaker_data/checksum_algorithm)" == sha256 ]
+  echo b > b
+  "$elfshaker" store sha256-1
+  [ "$("$elfshaker" list-files --format %o sha256-1 | sort)" == "$(sha256sum a b | cut -d ' ' -f 1 | sort)" ]
+  [ "$("$elfshaker" list-files --format %o sha256-0)" == "$(sha256sum a | cut -d ' ' -f 1)" ]
+  "$elfshaker" pack sha256-p
+  "$elfshaker" gc --loose-snapshots
+  "$elfshaker" verify
+  rm a b
+  "$elfshaker" extract --reset --verify sha256-p:sha256-1
+  [ "$(cat a b)" == "$(printf 'a\nb')" ]
+  "$elfshaker" extract --verify sha256-p:sha256-0
+  [ ! -e b ]
+  # The BLAKE3 checksums have the same size as the SHA-256 ones.
+  "$elfshaker" upgrade --checksum-algorithm blake3 sha256-p
+  [ "$("$elfshaker" list-files --format %o sha256-p:sha256-0)" != "$(sha256sum a | cut -d ' ' -f 1)" ]
+  [ "$("$elfshaker" list-files --format %o sha256-p:sha256-0 | wc -c)" == 65 ]
+  "$elfshaker" extract --verify sha256-p:sha256-1
+  if "$elfshaker" upgrade --checksum-algorithm md5; then exit 1; fi
+  rm elfshaker_data/packs/sha256-p.pack{,.idx}
+}
+
+test_pack_max_frame_size_works() {
+  for i in 1 2 3; do
+    rand_megs 1 > foo-$i
+  done
+  sha1=$(cat foo-* | sha1sum)
+  "$elfshaker" store max-frame-size-1
+  # The single frame is split, so that each frame holds one file.
+  output=$("$elfshaker" --verbose pack --max-frame-size 1 max-frame-size-p 2>&1)
+  echo "$output" | grep -q 'Creating 3 compressed frames'
+  "$elfshaker" gc --loose-snapshots
+  rm foo-*
+  "$elfshaker" extract --reset --verify max-frame-size-p:max-frame-size-1
+  [ "$(cat foo-* | sha1sum)" == "$sha1" ]
+  rm elfshaker_data/packs/max-frame-size-p.pack{,.idx}
+}
+
+test_pack_delta_works() {
+  seq 1 100000 > foo
+  sha1_1=$(sha1sum < foo)
+  "$elfshaker" store delta-1
+  sed -i 's/^50000$/fifty thousand/' foo
+  sha1_2=$(sha1sum < foo)
+  "$elfshaker" store delta-2
+  output=$("$elfshaker" --verbose pack --delta delta-p 2>&1)
+  echo "$output" | grep -q 'Storing 1 objects as deltas'
+  "$elfshaker" gc --loose-snapshots
+  rm foo
+  "$elfshaker" extract --reset --verify delta-p:delta-1
+  [ "$(sha1sum < foo)" == "$sha1_1" ]
+  "$elfshaker" extract --verify delta-p:delta-2
+  [ "$(sha1sum < foo)" == "$sha1_2" ]
+  rm elfshaker_data/packs/delta-p.pack{,.idx}
+}
+
+test_pack_chunk_works() {
+  rand_megs 2 > foo
+  sha1_1=$(sha1sum < foo)
+  "$elfshaker" store chunk-1
+  printf 'changed' | dd of=foo bs=1 seek=1000000 conv=notrunc 2> /dev/null
+  sha1_2=$(sha1sum < foo)
+  "$elfshaker" store chunk-2
+  output=$("$elfshaker" --verbose pack --chunk chunk-p 2>&1)
+  echo "$output" | grep -q 'Storing 2 objects as [0-9]* chunks'
+  "$elfshaker" gc --loose-snapshots
+  rm foo
+  "$elfshaker" extract --reset --verify chunk-p:chunk-1
+  [ "$(sha1sum < foo)" == "$sha1_1" ]
+  "$elfshaker" extract --verify chunk-p:chunk-2
+  [ "$(sha1sum < foo)" == "$sha1_2" ]
+  rm elfshaker_data/packs/chunk-p.pack{,.idx}
+}
+
+test_pack_codec_works() {
+  rand_megs 1 > foo
+  seq 1 10000 > bar
+  sha1=$(cat foo bar | sha1sum)
+  for codec in lz4 xz; do
+    "$elfshaker" store codec-$codec
+    "$elfshaker" pack --codec $codec codec-$codec-p
+    "$elfshaker" gc --loose-snapshots
+    rm foo bar
+    # The codec is detected when extracting.
+    "$elfshaker" extract --reset --verify codec-$codec-p:codec-$codec
+    [ "$(cat foo bar | sha1sum)" == "$sha1" ]
+  done
+  if "$elfshaker" pack --codec lzma codec-p; then exit 1; fi
+  rm elfshaker_data/packs/codec-{lz4,xz}-p.pack{,.idx}
+}
+
+test_sparse_files_works() {
+  printf 'start' > sparse
+  truncate -s 32M sparse
+  echo 'end' >> sparse
+  if [ "$(du -k sparse | cut -f 1)" -ge 1024 ]; then
+    echo 'Skipped, the filesystem does not support sparse files'
+    return
+  fi
+  sha1=$(sha1sum < sparse)
+  "$elfshaker" store sparse-1
+  # The loose object is written sparsely, then the extracted file.
+  [ "$(du -sk elfshaker_data/loose | cut -f 1)" -lt 1024 ]
+  "$elfshaker" pack sparse-p
+  "$elfshaker" gc --loose-snapshots
+  rm sparse
+  "$elfshaker" extract --reset --verify sparse-p:sparse-1
+  [ "$(sha1sum < sparse)" == "$sha1" ]
+  [ "$(du -k sparse | cut -f 1)" -lt 1024 ]
+  rm elfshaker_data/packs/sparse-p.pack{,.idx}
+}
+
+test_xattrs_works() {
+  echo a > a
+  echo b > b
+  set_xattr() {
+    python3 -c 'import os, sys; os.setxattr(sys.argv[1], "user.elfshaker", sys.argv[2].encode())' "$@"
+  }
+  get_xattr() {
+    python3 -c 'import os, sys; print(os.getxattr(sys.argv[1], "user.elfshaker").decode())' "$1" 2> /dev/null
+  }
+  if ! set_xattr a value-a 2> /dev/null; then
+    echo 'Skipped, the filesystem does not support extended attributes'
+    return
+  fi
+  "$elfshaker" store --xattrs xattrs-1
+  "$elfshaker" pack xattrs-p
+  "$elfshaker" gc --loose-snapshots
+  rm a b
+  # The attributes are only restored with --xattrs.
+  "$elfshaker" extract --reset --verify xattrs-p:xattrs-1
+  if get_xattr a; then exit 1; fi
+  rm a b
+  "$elfshaker" extract --reset --verify --xattrs xattrs-p:xattrs-1
+  [ "$(get_xattr a)" == value-a ]
+  if get_xattr b; then exit 1; fi
+  # The changes of the attributes alone are reported by status and diff.
+  [ -z "$("$elfshaker" status)" ]
+  set_xattr a value-c
+  [ "$("$elfshaker" status)" == "$(printf 'M\ta')" ]
+  "$elfshaker" store --xattrs xattrs-2
+  [ "$("$elfshaker" diff xattrs-p:xattrs-1 xattrs-2)" == "$(printf 'M\ta')" ]
+  rm elfshaker_data/packs/xattrs-p.pack{,.idx}
+}
+
+test_pack_similarity_works() {
+  mkdir similarity
+  for i in 1 2 3; do
+    seq 1 $((i * 10000)) > similarity/seq-$i
+    rand_megs 1 > similarity/random-$i
+  done
+  sha1=$(cat similarity/* | sha1sum)
+  "$elfshaker" store similarity-1
+  output=$("$elfshaker" --verbose pack --similarity similarity-p 2>&1)
+  echo "$output" | grep -q 'Computing object similarity signatures'
+  "$elfshaker" gc --loose-snapshots
+  rm -rf similarity
+  "$elfshaker" extract --reset --verify similarity-p:similarity-1
+  [ "$(cat similarity/* | sha1sum)" == "$sha1" ]
+  rm elfshaker_data/packs/similarity-p.pack{,.idx}
+}
+
+test_pack_frame_size_works() {
+  for i in 1 2 3 4; do
+    rand_megs 1 > foo-$i
+  done
+  sha1=$(cat foo-* | sha1sum)
+  "$elfshaker" store frame-size-1
+  # One frame per MiB of objects, rather than a single one.
+  output=$("$elfshaker" --verbose pack --frame-size 1 frame-size-p 2>&1)
+  echo "$output" | grep -q 'Creating 4 compressed frames'
+  "$elfshaker" gc --loose-snapshots
+  rm foo-*
+  "$elfshaker" extract --reset --verify frame-size-p:frame-size-1
+  [ "$(cat foo-* | sha1sum)" == "$sha1" ]
+  # --frames takes precedence.
+  "$elfshaker" store frame-size-2
+  output=$("$elfshaker" --verbose pack --frame-size 1 --frames 2 frame-size-q 2>&1)
+  echo "$output" | grep -q 'Creating 2 compressed frames'
+  "$elfshaker" gc --loose-snapshots
+  rm elfshaker_data/packs/frame-size-{p,q}.pack{,.idx}
+}
+
 test_extract_reset_on_empty_works() {
   "$elfshaker" list-files "$pack":"$snapshot_a"
   "$elfshaker" --verbose extract --reset --verify "$pack":"$snapshot_a"
@@ -144,6 +421,28 @@ test_extract_different_works() {
   verify_snapshot "$pack":"$snapshot_b"
 }
 
+test_extract_interrupted_works() {
+  "$elfshaker" --verbose extract --reset --verify "$pack":"$snapshot_a"
+  # An extraction which was interrupted leaves its snapshot next to HEAD.
+  echo "$pack":"$snapshot_b" > elfshaker_data/EXTRACTING
+  if "$elfshaker" extract "$pack":"$snapshot_b"; then
+    echo "Failed."
+    exit 1
+  fi
+  "$elfshaker" --verbose extract --reset --verify --stage "$pack":"$snapshot_b"
+  verify_snapshot "$pack":"$snapshot_b"
+  [[ ! -e elfshaker_data/EXTRACTING ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" --verbose extract --verify --stage "$pack":"$snapshot_a"
+  verify_snapshot "$pack":"$snapshot_a"
+  [[ -z "$(find . -maxdepth 1 -name '.elfshaker-*')" ]] || {
+    echo "Failed."
+    exit 1
+  }
+}
+
 test_extract_file_modes_preserved() {
   umask 0002
   touch foobar
@@ -196,9 +495,2309 @@ test_extract_file_modes_preserved() {
     exit 1
   }
 
+  # Verify that extracting through the object cache sets the mode (files
+  # linked to the cache are read-only).
+  "$elfshaker" extract --reflink p0:s1
+  [[ "$(stat -c %A foobar)" == "-rwxrwxr-x" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract --link p0:s2
+  [[ "$(stat -c %A foobar)" == "-r--r--r--" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract --link p0:s1
+  [[ "$(stat -c %A foobar)" == "-r-xr-xr-x" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract p0:s2
+  [[ "$(stat -c %A foobar)" == "-rw-rw-r--" ]] || {
+    echo "Failed."
+    exit 1
+  }
+
+  rm elfshaker_data/packs/p0.pack{,.idx}
+  rm -r elfshaker_data/cache
+}
+
+test_store_symlinks_works() {
+  mkdir dir
+  echo "contents" > dir/file
+  ln -s file dir/link
+  ln -s dir dir_link
+  ln -s missing dangling
+  "$elfshaker" store s0
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+  rm -r dir dir_link dangling
+
+  for extract_args in "" "--reflink" "--link"; do
+    "$elfshaker" extract --force --reset $extract_args p0:s0
+    # The links are recreated, rather than storing what they point to.
+    [[ -L dir/link && "$(readlink dir/link)" == "file" ]] || {
+      echo "Failed."
+      exit 1
+    }
+    [[ -L dir_link && "$(readlink dir_link)" == "dir" ]] || {
+      echo "Failed."
+      exit 1
+    }
+    [[ -L dangling && "$(readlink dangling)" == "missing" ]] || {
+      echo "Failed."
+      exit 1
+    }
+    [[ "$(cat dir/link)" == "contents" ]] || {
+      echo "Failed."
+      exit 1
+    }
+  done
+
+  # Changing the target of a link is detected.
+  ln -sf other dir/link
+  "$elfshaker" status | grep -q "dir/link" || {
+    echo "Failed."
+    exit 1
+  }
+
+  rm elfshaker_data/packs/p0.pack{,.idx}
+  rm -r elfshaker_data/cache
+}
+
+test_store_empty_directories_works() {
+  mkdir -p dir/empty other
+  echo "contents" > other/file
+  "$elfshaker" store s0
+  "$elfshaker" store --empty-dirs s1
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+  rm -r dir
+
+  # Empty directories are only stored when requested.
+  "$elfshaker" extract --force --reset p0:s0
+  [[ ! -e dir ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract --force p0:s1
+  [[ -d dir/empty ]] || {
+    echo "Failed."
+    exit 1
+  }
+  [[ -z "$("$elfshaker" status --empty-dirs)" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  # They are removed along with the files of the snapshot.
+  "$elfshaker" extract --force p0:s0
+  [[ ! -e dir && -f other/file ]] || {
+    echo "Failed."
+    exit 1
+  }
+
   rm elfshaker_data/packs/p0.pack{,.idx}
 }
 
+test_extract_preserve_times_works() {
+  echo "contents" > foobar
+  touch -d "@981173106.123456789" foobar
+  "$elfshaker" store s0
+  "$elfshaker" store --record-times s1
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+
+  # The times are only restored if they were recorded and it is requested.
+  for snapshot in p0:s0 p0:s1; do
+    "$elfshaker" extract --force --reset "$snapshot"
+    [[ "$(stat -c %Y foobar)" != "981173106" ]] || {
+      echo "Failed."
+      exit 1
+    }
+  done
+  "$elfshaker" extract --force --reset --preserve-times p0:s0
+  [[ "$(stat -c %Y foobar)" != "981173106" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  for extract_args in "" "--reflink"; do
+    "$elfshaker" extract --force --reset --preserve-times $extract_args p0:s1
+    [[ "$(stat -c %.9Y foobar)" == "981173106.123456789" ]] || {
+      echo "Failed."
+      exit 1
+    }
+  done
+
+  rm elfshaker_data/packs/p0.pack{,.idx}
+  rm -r elfshaker_data/cache
+}
+
+test_extract_case_collisions_works() {
+  mkdir case
+  echo "upper" > case/README
+  echo "lower" > case/readme
+  # A file and a directory which differ only by case.
+  mkdir -p case/inc/foo
+  echo "file" > case/inc/Foo
+  echo "header" > case/inc/foo/bar.h
+  "$elfshaker" store s0
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+  rm -r case
+
+  # The extraction fails before creating any file.
+  if "$elfshaker" extract --force --reset --case-collisions error p0:s0; then
+    echo "Failed."
+    exit 1
+  fi
+  [[ ! -e case ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract --force --reset --case-collisions rename p0:s0
+  [[ "$(cat case/README)" == "upper" && "$(cat case/readme~1)" == "lower" && ! -e case/readme ]] || {
+    echo "Failed."
+    exit 1
+  }
+  [[ "$(cat case/inc/Foo)" == "file" && "$(cat case/inc/foo~1/bar.h)" == "header" && ! -e case/inc/foo ]] || {
+    echo "Failed."
+    exit 1
+  }
+
+  rm -r case
+  rm elfshaker_data/packs/p0.pack{,.idx}
+}
+
+test_extract_paths_works() {
+  mkdir -p part/lib/x
+  echo "a" > part/lib/a.o
+  echo "b" > part/lib/x/b.o
+  echo "c" > part/lib/c.c
+  echo "d" > part/d.o
+  "$elfshaker" store s0
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+  rm -r part
+  head=$(cat elfshaker_data/HEAD)
+
+  # Only the matching files are extracted, and HEAD is not updated.
+  "$elfshaker" extract --force --reset --path 'part/lib/**/*.o' p0:s0
+  [[ -f part/lib/a.o && -f part/lib/x/b.o && ! -e part/lib/c.c && ! -e part/d.o ]] || {
+    echo "Failed."
+    exit 1
+  }
+  [[ "$(cat elfshaker_data/HEAD)" == "$head" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  rm -r part
+
+  # The excluded directories are skipped, even if they match --path.
+  "$elfshaker" extract --force --reset --path part --exclude part/lib --exclude '*.c' p0:s0
+  [[ -f part/d.o && ! -e part/lib ]] || {
+    echo "Failed."
+    exit 1
+  }
+
+  rm -r part
+  rm elfshaker_data/packs/p0.pack{,.idx}
+}
+
+test_extract_output_works() {
+  echo "s0" > output_file
+  "$elfshaker" store s0
+  echo "s1" > output_file
+  "$elfshaker" store s1
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+  rm output_file
+  head=$(cat elfshaker_data/HEAD)
+
+  # Each directory has its own HEAD, and the one of the repository is kept.
+  "$elfshaker" extract --output out0 p0:s0
+  "$elfshaker" extract --output out1 p0:s1
+  [[ "$(cat out0/output_file)" == "s0" && "$(cat out1/output_file)" == "s1" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  [[ "$(cat out0/elfshaker_data/HEAD)" == "p0:s0" && "$(cat elfshaker_data/HEAD)" == "$head" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract --output out0 p0:s1
+  [[ "$(cat out0/output_file)" == "s1" && "$(cat out0/elfshaker_data/HEAD)" == "p0:s1" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" extract --output out2 --no-head p0:s0
+  [[ -f out2/output_file && ! -e out2/elfshaker_data ]] || {
+    echo "Failed."
+    exit 1
+  }
+
+  rm -r out0 out1 out2
+  rm elfshaker_data/packs/p0.pack{,.idx}
+}
+
+test_extract_to_stdout_works() {
+  head=$(cat elfshaker_data/HEAD 2> /dev/null || true)
+  "$elfshaker" extract --to-stdout "$pack":"$snapshot_a" > snapshot.tar
+  [[ "$(cat elfshaker_data/HEAD 2> /dev/null || true)" == "$head" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  if ! DIFF=$(diff -u1 <(elfshaker_sha1sums "$pack":"$snapshot_a") <(mkdir stdout_dir &&
+    tar -x -C stdout_dir -f snapshot.tar && cd stdout_dir && all_pwd_sha1sums)); then
+    echo "$DIFF"
+    exit 1
+  fi
+  rm -r snapshot.tar stdout_dir
+}
+
+test_concurrent_stores_work() {
+  echo "contents" > concurrent_file
+  for i in 1 2 3 4; do
+    "$elfshaker" store "concurrent-$i" &
+  done
+  wait
+  [[ "$("$elfshaker" list | grep -c concurrent-)" == 4 ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" pack p0
+  "$elfshaker" gc --loose-snapshots
+  rm concurrent_file
+  rm elfshaker_data/packs/p0.pack{,.idx}
+}
+
+test_pack_is_written_atomically() {
+  echo "contents" > atomic_pack_file
+  "$elfshaker" store atomic-pack
+  "$elfshaker" pack p0
+  # The pack and its index are moved into place, no temporary file is left.
+  [[ -z "$(ls -A elfshaker_data/trash)" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  "$elfshaker" verify p0
+  "$elfshaker" gc --loose-snapshots
+  rm atomic_pack_file
+  rm elfshaker_data/packs/p0.pack{,.idx}
+}
+
+test_store_cache_works() {
+  echo "version 1" > cached_file
+  echo "unchanged" > unchanged_file
+  # Files modified just before store are not cached.
+  sleep 3
+  "$elfshaker" store cache-1 --files-from <(echo cached_file; echo unchanged_file)
+  echo "version 2" > cached_file
+  output=$("$elfshaker" --verbose store cache-2 --files-from <(echo cached_file; echo unchanged_file) 2>&1)
+  [[ "$output" == *"Reused the cached checksums of 1 files"* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  rm cached_file unchanged_file
+  "$elfshaker" extract --reset cache-2
+  [[ "$(cat cached_file)" == "version 2" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  output=$("$elfshaker" --verbose store --no-cache cache-3 --files-from <(echo cached_file; echo unchanged_file) 2>&1)
+  [[ "$output" == *"Reused the cached checksums of 0 files"* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  "$elfshaker" extract --reset "$pack":"$snapshot_a"
+  rm elfshaker_data/packs/loose/cache-{1,2,3}.pack.idx
+}
+
+test_store_watch_works() {
+  "$elfshaker" store --watch --settle 0.5 'watch-%n' &
+  watch_pid=$!
+  sleep 1
+  echo "watched" > watched_file
+  for _ in $(seq 1 20); do
+    "$elfshaker" list | grep -q watch-1 && break
+    sleep 0.5
+  done
+  kill "$watch_pid"
+  wait "$watch_pid" || true
+  "$elfshaker" list-files watch-1 | grep -q watched_file || {
+    echo "Failed."
+    exit 1
+  }
+  rm watched_file
+  rm elfshaker_data/packs/loose/watch-*.pack.idx
+}
+
+test_store_null_files_from_works() {
+  touch $'null list\nfile' 'null list file'
+  find . -name 'null list*' -print0 | "$elfshaker" store --files-from - --null null-list
+  [[ "$("$elfshaker" list-files null-list | grep -c 'null list')" == 2 ]] || {
+    echo "Failed."
+    exit 1
+  }
+  rm $'null list\nfile' 'null list file'
+  rm elfshaker_data/packs/loose/null-list.pack.idx
+}
+
+test_store_ignore_file_works() {
+  mkdir -p ignored_dir/sub kept_dir
+  touch ignored_dir/sub/file kept_dir/file kept_dir/scratch.log kept_dir/keep.log
+  printf '# Scratch files\n*.log\n!keep.log\n/ignored_dir/\n' > .elfshakerignore
+  "$elfshaker" store ignore-file
+  files=$("$elfshaker" list-files ignore-file | awk '{ print $3 }' | sort | tr '\n' ' ')
+  [[ "$files" == *".elfshakerignore kept_dir/file kept_dir/keep.log "* ]] || {
+    echo "Failed: $files"
+    exit 1
+  }
+  [[ "$files" != *"scratch.log"* && "$files" != *"ignored_dir"* ]] || {
+    echo "Failed: $files"
+    exit 1
+  }
+  rm -r ignored_dir kept_dir .elfshakerignore
+  rm elfshaker_data/packs/loose/ignore-file.pack.idx
+}
+
+test_config_works() {
+  config_home=$(mktemp -d)
+  mkdir "$config_home/elfshaker"
+  printf '[pack]\n\tcompression-level = 5\n\tthreads = 1\n[store]\n\tignore = *.tmp\n' > "$config_home/elfshaker/config"
+  # The configuration of the repository takes precedence.
+  printf '[pack]\n\tcompression-level = 3\n' > elfshaker_data/config
+  touch config_file scratch.tmp
+  XDG_CONFIG_HOME="$config_home" "$elfshaker" store config-1
+  files=$("$elfshaker" list-files config-1)
+  [[ "$files" == *config_file* && "$files" != *scratch.tmp* ]] || {
+    echo "Failed: $files"
+    exit 1
+  }
+  XDG_CONFIG_HOME="$config_home" "$elfshaker" pack config-p1 --snapshots-from <(echo config-1)
+  [[ "$("$elfshaker" list-packs --format '%p %l' | grep config-p1)" == "config-p1 3" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  # The command-line takes precedence over the configuration.
+  XDG_CONFIG_HOME="$config_home" "$elfshaker" store config-2
+  XDG_CONFIG_HOME="$config_home" "$elfshaker" pack config-p2 --snapshots-from <(echo config-2) --compression-level 7
+  [[ "$("$elfshaker" list-packs --format '%p %l' | grep config-p2)" == "config-p2 7" ]] || {
+    echo "Failed."
+    exit 1
+  }
+  printf '[pack\n' > elfshaker_data/config
+  if "$elfshaker" list 2> /dev/null; then
+    echo "A bad configuration should be an error."
+    exit 1
+  fi
+  rm -r "$config_home" elfshaker_data/config config_file scratch.tmp
+  "$elfshaker" gc --loose-snapshots
+  rm elfshaker_data/packs/config-p{1,2}.pack{,.idx}
+}
+
+test_directory_works() {
+  mkdir -p sub/dir
+  echo 'Directory' > sub/dir/file
+  # -C runs as if elfshaker was started in the directory.
+  (cd / && "$elfshaker" -C "$temp_dir" store from-subdir)
+  # The repository is found from its subdirectories.
+  (cd sub/dir && "$elfshaker" list-files from-subdir) | grep -q 'sub/dir/file'
+  (cd / && "$elfshaker" -C "$temp_dir/sub" list-files from-subdir) | grep -q 'sub/dir/file'
+  rm -r sub
+  (cd / && "$elfshaker" --directory "$temp_dir" extract --reset from-subdir)
+  if [ "$(cat sub/dir/file)" != "Directory" ]; then
+    echo "Failed to extract with --directory!"
+    exit 1
+  fi
+  rm -r sub
+  rm elfshaker_data/packs/loose/from-subdir.pack.idx
+}
+
+test_completions_works() {
+  "$elfshaker" completions bash > completions.bash
+  bash -c 'source completions.bash && complete -p elfshaker' > /dev/null
+  grep -q 'list-packs' completions.bash
+  "$elfshaker" completions zsh > completions.zsh
+  grep -q '#compdef elfshaker' completions.zsh
+  rm completions.bash completions.zsh
+}
+
+test_progress_json_works() {
+  echo 'Progress' > progress_file
+  "$elfshaker" store progress-1
+  "$elfshaker" --progress json pack progress-p 2> progress.log
+  if [ "$(tail -n 1 progress.log)" != '{"bytes":9,"detail":null,"done":1,"stage":"Compressing objects","total":1}' ]; then
+    echo "Unexpected progress report: $(tail -n 1 progress.log)"
+    exit 1
+  fi
+  rm progress_file progress.log
+  "$elfshaker" gc --loose-snapshots
+  rm elfshaker_data/packs/progress-p.pack{,.idx}
+}
+
+test_verbosity_works() {
+  echo 'Verbosity' > verbosity_file
+  "$elfshaker" store verbosity-1
+  rm verbosity_file
+  "$elfshaker" store verbosity-2
+  output=$("$elfshaker" -q extract verbosity-1 2>&1)
+  if [ -n "$output" ]; then
+    echo "Expected no output with --quiet: $output"
+    exit 1
+  fi
+  output=$("$elfshaker" -vv extract verbosity-2 2>&1)
+  [[ "$output" == *DEBUG*"removing 1 files"* && "$output" != *TRACE* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  "$elfshaker" extract verbosity-1
+  output=$("$elfshaker" -vvv extract verbosity-2 2>&1)
+  [[ "$output" == *TRACE*"Removing "*verbosity_file* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  rm elfshaker_data/packs/loose/verbosity-{1,2}.pack.idx
+}
+
+test_log_format_json_works() {
+  echo 'Log format' > log_file
+  output=$("$elfshaker" -vv --log-format json store log-1 2>&1)
+  [[ "$output" == *'"level":"DEBUG"'*'"span":"compute_checksums"'* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  rm log_file
+  rm elfshaker_data/packs/loose/log-1.pack.idx
+}
+
+test_pack_max_size_works() {
+  for i in 1 2 3; do
+    rand_megs 1 > max_size_file
+    sha1[$i]=$(sha1sum < max_size_file)
+    "$elfshaker" store max-size-$i
+  done
+  # The first two snapshots fit in the first pack.
+  "$elfshaker" pack --compression-level 1 --max-size 2200000 max-size-p
+  if [ -e elfshaker_data/packs/max-size-p.pack ] ||
+    [ "$(wc -c < elfshaker_data/packs/max-size-p.0.pack)" -gt 2200000 ] ||
+    [ -e elfshaker_data/packs/max-size-p.2.pack ]; then
+    echo "Unexpected packs: $(ls elfshaker_data/packs)"
+    exit 1
+  fi
+  "$elfshaker" extract --reset max-size-p.0:max-size-2
+  [ "$(sha1sum < max_size_file)" == "${sha1[2]}" ]
+  "$elfshaker" extract --reset max-size-p.1:max-size-3
+  [ "$(sha1sum < max_size_file)" == "${sha1[3]}" ]
+  # A snapshot can't be split, so the pack is kept whole.
+  if "$elfshaker" pack --compression-level 1 --max-size 1000 max-size-q; then
+    echo "Expected the pack to be too large to split"
+    exit 1
+  fi
+  [ -e elfshaker_data/packs/max-size-q.pack ]
+  [ ! -e elfshaker_data/packs/max-size-q.0.pack ]
+  rm max_size_file
+  "$elfshaker" gc --loose-snapshots
+  rm elfshaker_data/packs/max-size-{p.0,p.1,q}.pack{,.idx}
+}
+
+test_pack_dictionary_works() {
+  mkdir dictionary
+  for i in $(seq 1 1200); do
+    printf '#include <stdio.h>\n#include <stdlib.h>\n\nstatic int value_%d = %d;\n\nint function_%d(int x) {\n  if (x > %d)\n    return x * value_%d;\n  printf("function %d: %%d\\n", x);\n  return x + %d;\n}\n' \
+      $i $((i * 7919 % 1000)) $i $((i % 13)) $i $i $((i * 31 % 97)) > dictionary/f$i.c
+  done
+  sha1=$(cat dictionary/* | sha1sum)
+  # Packs the snapshot into the pack $1 with the options $2..., and sets size.
+  pack_snapshot() {
+    "$elfshaker" store dictionary-1 > /dev/null
+    "$elfshaker" pack "${@:2}" "$1" > /dev/null
+    "$elfshaker" gc --loose-snapshots > /dev/null
+    size=$(wc -c < "elfshaker_data/packs/$1.pack")
+  }
+  # In small frames, the files are compressed with the dictionary instead.
+  pack_snapshot dictionary-p --frames 600
+  plain_size=$size
+  pack_snapshot dictionary-q --frames 600 --dictionary-size 16
+  if [ "$size" -ge $((plain_size * 3 / 4)) ]; then
+    echo "Expected the dictionary to shrink the pack ($size bytes, $plain_size without)"
+    exit 1
+  fi
+  rm -rf dictionary
+  "$elfshaker" extract --reset --verify dictionary-q:dictionary-1
+  [ "$(cat dictionary/* | sha1sum)" == "$sha1" ]
+  # The files compress better together, so the dictionary isn't used. Nor is
+  # it trained on too few files, for the larger dictionary.
+  pack_snapshot dictionary-r
+  plain_size=$size
+  for dictionary_size in 16 64; do
+    pack_snapshot dictionary-s$dictionary_size --dictionary-size $dictionary_size
+    if [ "$size" -gt $((plain_size + 100)) ]; then
+      echo "Expected no dictionary of $dictionary_size KiB ($size bytes, $plain_size without)"
+      exit 1
+    fi
+  done
+  rm -rf dictionary
+  "$elfshaker" extract --reset --verify dictionary-s16:dictionary-1
+  [ "$(cat dictionary/* | sha1sum)" == "$sha1" ]
+  rm elfshaker_data/packs/dictionary-{p,q,r,s16,s64}.pack{,.idx}
+}
+
+test_extract_fetches_frames_works() {
+  mkdir frames_server
+  cd frames_server
+  rand_megs 1 > frames_file
+  sha1=$(sha1sum < frames_file)
+  "$elfshaker" store frames-1
+  rand_megs 1 > frames_file
+  "$elfshaker" store frames-2
+  "$elfshaker" pack --frames 2 frames-p
+  "$elfshaker" serve --bind 127.0.0.1:43110 &
+  server_pid=$!
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43110/index.esi frames_clone
+  cd frames_clone
+  # Only the frame containing the file of the snapshot is fetched.
+  output=$("$elfshaker" -v extract origin/frames-p:frames-1 2>&1) || {
+    kill $server_pid
+    echo "Failed: $output"
+    exit 1
+  }
+  kill $server_pid
+  [[ "$output" == *"Fetching 1 of the 2 frames"* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  [ "$(sha1sum < frames_file)" == "$sha1" ]
+  [ ! -e elfshaker_data/packs/origin/frames-p.pack ]
+  cd ..
+  rm -rf frames_server frames_clone
+}
+
+test_extract_resumes_downloads_works() {
+  mkdir resume_server
+  cd resume_server
+  rand_megs 1 > resume_file
+  sha1=$(sha1sum < resume_file)
+  "$elfshaker" store resume-1
+  rand_megs 1 > resume_file
+  sha2=$(sha1sum < resume_file)
+  "$elfshaker" store resume-2
+  # A single frame, so that extracting fetches the whole pack.
+  "$elfshaker" pack --frames 1 resume-p
+  "$elfshaker" serve --bind 127.0.0.1:43111 &
+  server_pid=$!
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43111/index.esi resume_clone
+  cd resume_clone
+  pack=../resume_server/elfshaker_data/packs/resume-p.pack
+  part=elfshaker_data/packs/origin/resume-p.pack.part
+  # The download interrupted halfway is resumed.
+  head -c $(($(wc -c < $pack) / 2)) $pack > $part
+  output=$("$elfshaker" -v extract origin/resume-p:resume-1 2>&1) || {
+    kill $server_pid
+    echo "Failed: $output"
+    exit 1
+  }
+  [[ "$output" == *"Resuming the download"* ]] || {
+    kill $server_pid
+    echo "Failed: $output"
+    exit 1
+  }
+  [ "$(sha1sum < resume_file)" == "$sha1" ]
+  [ ! -e $part ]
+  cmp $pack elfshaker_data/packs/origin/resume-p.pack
+  # A corrupt partial download is restarted.
+  rm elfshaker_data/packs/origin/resume-p.pack
+  head -c 1000 /dev/zero > $part
+  "$elfshaker" extract origin/resume-p:resume-2 || {
+    kill $server_pid
+    exit 1
+  }
+  kill $server_pid
+  [ "$(sha1sum < resume_file)" == "$sha2" ]
+  [ ! -e $part ]
+  cmp $pack elfshaker_data/packs/origin/resume-p.pack
+  cd ..
+  rm -rf resume_server resume_clone
+}
+
+test_extract_fails_over_to_mirrors_works() {
+  mkdir mirror_server
+  cd mirror_server
+  rand_megs 1 > mirror_file
+  sha1=$(sha1sum < mirror_file)
+  "$elfshaker" store mirror-1
+  "$elfshaker" pack mirror-p
+  "$elfshaker" serve --bind 127.0.0.1:43112 &
+  echo $! > ../mirror_primary.pid
+  "$elfshaker" serve --bind 127.0.0.1:43113 &
+  echo $! > ../mirror_secondary.pid
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43112/index.esi mirror_clone
+  cd mirror_clone
+  printf '[remote "origin"]\n  mirror = http://127.0.0.1:43113/index.esi\n' >> elfshaker_data/config
+  # The primary server is down.
+  kill "$(cat ../mirror_primary.pid)"
+  sleep 1
+  output=$("$elfshaker" -v extract origin/mirror-p:mirror-1 2>&1) || {
+    kill "$(cat ../mirror_secondary.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  [[ "$output" == *"trying the mirror http://127.0.0.1:43113/packs/mirror-p.pack"* ]] || {
+    kill "$(cat ../mirror_secondary.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  [ "$(sha1sum < mirror_file)" == "$sha1" ]
+  output=$("$elfshaker" -v update 2>&1) || {
+    kill "$(cat ../mirror_secondary.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  kill "$(cat ../mirror_secondary.pid)"
+  [[ "$output" == *"trying the mirror http://127.0.0.1:43113/index.esi"* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  cd ..
+  rm -rf mirror_server mirror_clone mirror_primary.pid mirror_secondary.pid
+}
+
+test_sign_works() {
+  mkdir sign_server
+  cd sign_server
+  rand_megs 1 > sign_file
+  sha1=$(sha1sum < sign_file)
+  "$elfshaker" store sign-1
+  "$elfshaker" pack sign-p
+  public_key=$("$elfshaker" sign --generate-key ../sign.key)
+  [ "$(cat ../sign.key.pub)" == "$public_key" ]
+  "$elfshaker" sign --key ../sign.key sign-p
+  [ -f elfshaker_data/packs/sign-p.pack.sig ]
+  "$elfshaker" serve --bind 127.0.0.1:43114 &
+  echo $! > ../sign_server.pid
+  cd ..
+  sleep 1
+
+  # The pack is signed by the trusted key.
+  "$elfshaker" clone http://127.0.0.1:43114/index.esi sign_clone
+  cd sign_clone
+  printf '[trust]\n  key = %s\n' "$public_key" >> elfshaker_data/config
+  "$elfshaker" update || {
+    kill "$(cat ../sign_server.pid)"
+    exit 1
+  }
+  [ -f elfshaker_data/packs/origin/sign-p.pack.sig ]
+  "$elfshaker" extract origin/sign-p:sign-1 || {
+    kill "$(cat ../sign_server.pid)"
+    exit 1
+  }
+  [ "$(sha1sum < sign_file)" == "$sha1" ]
+  cd ..
+
+  # The pack is not signed by the trusted key.
+  "$elfshaker" clone http://127.0.0.1:43114/index.esi sign_untrusted
+  cd sign_untrusted
+  other_key=$("$elfshaker" sign --generate-key ../other.key)
+  printf '[trust]\n  key = %s\n' "$other_key" >> elfshaker_data/config
+  if output=$("$elfshaker" update 2>&1); then
+    kill "$(cat ../sign_server.pid)"
+    echo "Failed: the pack of the untrusted key was accepted"
+    exit 1
+  fi
+  kill "$(cat ../sign_server.pid)"
+  [[ "$output" == *"Not signed by any trusted key"* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  # The unverified pack index is deleted.
+  [ ! -e elfshaker_data/packs/origin/sign-p.pack.idx ]
+  cd ..
+  rm -rf sign_server sign_clone sign_untrusted sign.key sign.key.pub other.key other.key.pub \
+    sign_server.pid
+}
+
+test_sign_follows_pack_works() {
+  mkdir sign_pack
+  cd sign_pack
+  for i in 1 2 3; do
+    echo "$i" > a
+    "$elfshaker" store "sp-$i"
+  done
+  "$elfshaker" pack sp-p
+  "$elfshaker" sign --generate-key ../sp.key > /dev/null
+  "$elfshaker" sign --key ../sp.key sp-p
+  [ -f elfshaker_data/packs/sp-p.pack.sig ]
+  # The signature is renamed with its pack.
+  "$elfshaker" rename --pack sp-p sp-q
+  [ ! -e elfshaker_data/packs/sp-p.pack.sig ]
+  [ -f elfshaker_data/packs/sp-q.pack.sig ]
+  # It is removed when the pack or its index is rewritten.
+  "$elfshaker" rm sp-q:sp-1
+  [ ! -e elfshaker_data/packs/sp-q.pack.sig ]
+  "$elfshaker" sign --key ../sp.key sp-q
+  "$elfshaker" rename sp-q:sp-2 sp-4
+  [ ! -e elfshaker_data/packs/sp-q.pack.sig ]
+  # And when the pack is deleted.
+  "$elfshaker" store sp-5
+  "$elfshaker" sign --key ../sp.key sp-q
+  [ -f elfshaker_data/packs/sp-q.pack.sig ]
+  "$elfshaker" rm sp-q:sp-3 sp-q:sp-4
+  [ ! -e elfshaker_data/packs/sp-q.pack.idx ]
+  [ ! -e elfshaker_data/packs/sp-q.pack.sig ]
+  cd ..
+  rm -rf sign_pack sp.key sp.key.pub
+}
+
+test_trust_policy_works() {
+  mkdir policy_server
+  cd policy_server
+  rand_megs 1 > policy_file
+  sha1=$(sha1sum < policy_file)
+  "$elfshaker" store policy-1
+  "$elfshaker" pack policy-signed
+  rand_megs 1 > policy_file
+  sha2=$(sha1sum < policy_file)
+  "$elfshaker" store policy-2
+  "$elfshaker" pack policy-unsigned
+  "$elfshaker" gc --loose-snapshots
+  echo 'other' > other_file
+  "$elfshaker" store policy-3
+  "$elfshaker" pack policy-unsigned-2
+  key=$("$elfshaker" sign --generate-key ../policy.key)
+  other_key=$("$elfshaker" sign --generate-key ../policy_other.key)
+  "$elfshaker" sign --key ../policy.key policy-signed
+  "$elfshaker" serve --bind 127.0.0.1:43115 &
+  echo $! > ../policy_server.pid
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43115/index.esi policy_clone
+  cd policy_clone
+  printf '[remote "origin"]\n  signer = %s\n' "$key" >> elfshaker_data/config
+  # The unsigned pack violates the policy.
+  if output=$("$elfshaker" update 2>&1); then
+    kill "$(cat ../policy_server.pid)"
+    echo "Failed: the unsigned pack was accepted"
+    exit 1
+  fi
+  [[ "$output" == *"origin/policy-unsigned.pack: Not signed"* &&
+     "$output" == *"origin/policy-unsigned-2.pack: Not signed"* ]] || {
+    kill "$(cat ../policy_server.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  # Every pack violating the policy is deleted, not only the first one.
+  for pack in policy-unsigned policy-unsigned-2; do
+    [ ! -e "elfshaker_data/packs/origin/$pack.pack.idx" ] || {
+      kill "$(cat ../policy_server.pid)"
+      echo "Failed: $pack was kept"
+      exit 1
+    }
+  done
+  printf '  allow-unsigned = true\n' >> elfshaker_data/config
+  output=$("$elfshaker" update 2>&1) || {
+    kill "$(cat ../policy_server.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  [[ "$output" == *"origin/policy-unsigned.pack is not signed"* ]] || {
+    kill "$(cat ../policy_server.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  "$elfshaker" extract origin/policy-unsigned:policy-2 || {
+    kill "$(cat ../policy_server.pid)"
+    exit 1
+  }
+  [ "$(sha1sum < policy_file)" == "$sha2" ]
+  # The signed pack misses the signature of the other required signer.
+  printf '  signer = %s\n' "$other_key" >> elfshaker_data/config
+  if output=$("$elfshaker" extract origin/policy-signed:policy-1 2>&1); then
+    kill "$(cat ../policy_server.pid)"
+    echo "Failed: the pack without the required signer was accepted"
+    exit 1
+  fi
+  [[ "$output" == *"Not signed by the required signer $other_key"* ]] || {
+    kill "$(cat ../policy_server.pid)"
+    echo "Failed: $output"
+    exit 1
+  }
+  # Once signed by both, the pack is accepted (the rejected pack interrupted
+  # the extraction).
+  (cd ../policy_server && "$elfshaker" sign --key ../policy_other.key policy-signed)
+  "$elfshaker" update && "$elfshaker" extract --reset origin/policy-signed:policy-1 || {
+    kill "$(cat ../policy_server.pid)"
+    exit 1
+  }
+  kill "$(cat ../policy_server.pid)"
+  [ "$(sha1sum < policy_file)" == "$sha1" ]
+  cd ..
+  rm -rf policy_server policy_clone policy.key policy.key.pub policy_other.key \
+    policy_other.key.pub policy_server.pid
+}
+
+test_encryption_works() {
+  mkdir encryption_server
+  cd encryption_server
+  od -An -tx1 -N32 /dev/urandom | tr -d ' \n' > ../encryption.key
+  printf 'proprietary contents %.0s' $(seq 100) > secret_file
+  sha1=$(sha1sum < secret_file)
+  "$elfshaker" store encryption-1
+  "$elfshaker" pack --encryption-key ../encryption.key --encrypt-index encryption-p
+  # Neither the contents nor the paths of the files are readable.
+  if grep -q proprietary elfshaker_data/packs/encryption-p.pack; then
+    echo "Failed: the pack is not encrypted"
+    exit 1
+  fi
+  if grep -q secret_file elfshaker_data/packs/encryption-p.pack.idx; then
+    echo "Failed: the pack index is not encrypted"
+    exit 1
+  fi
+  # The repository is served without the key.
+  if output=$("$elfshaker" list encryption-p 2>&1); then
+    echo "Failed: the encrypted pack was read without the key"
+    exit 1
+  fi
+  [[ "$output" == *"which is not configured"* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  "$elfshaker" serve --bind 127.0.0.1:43116 &
+  echo $! > ../encryption_server.pid
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43116/index.esi encryption_clone
+  cd encryption_clone
+  printf '[encryption]\n  key = %s\n' "$(realpath ../encryption.key)" >> elfshaker_data/config
+  "$elfshaker" extract origin/encryption-p:encryption-1 || {
+    kill "$(cat ../encryption_server.pid)"
+    exit 1
+  }
+  kill "$(cat ../encryption_server.pid)"
+  [ "$(sha1sum < secret_file)" == "$sha1" ]
+  cd ..
+  rm -rf encryption_server encryption_clone encryption.key encryption_server.pid
+}
+
+test_flat_index_works() {
+  mkdir flat_index
+  cd flat_index
+  echo 'a' > a
+  mkdir dir
+  echo 'b' > dir/b
+  "$elfshaker" store flat-1
+  echo 'c' > dir/b
+  echo 'd' > d
+  "$elfshaker" store flat-2
+  "$elfshaker" pack --index-format flat flat-p
+  # Version 9 indexes are in the flat format.
+  [ "$(od -An -tx1 -j4 -N4 elfshaker_data/packs/flat-p.pack.idx | tr -d ' ')" == "00000009" ]
+  [ "$("$elfshaker" list --format '%t %n' flat-p)" == "$(printf 'flat-1 2\nflat-2 3')" ]
+  "$elfshaker" extract --reset flat-p:flat-2
+  [ "$(cat a dir/b d)" == "$(printf 'a\nc\nd')" ]
+  "$elfshaker" extract flat-p:flat-1
+  [ "$(cat a dir/b)" == "$(printf 'a\nb')" ]
+  [ ! -e d ]
+  # The index stays flat when it is rewritten.
+  "$elfshaker" rename flat-p:flat-2 flat-3
+  [ "$(od -An -tx1 -j4 -N4 elfshaker_data/packs/flat-p.pack.idx | tr -d ' ')" == "00000009" ]
+  "$elfshaker" extract --reset flat-p:flat-3
+  [ "$(cat a dir/b d)" == "$(printf 'a\nc\nd')" ]
+  "$elfshaker" verify flat-p
+  cd ..
+  rm -rf flat_index
+}
+
+test_index_cache_works() {
+  mkdir index_cache
+  cd index_cache
+  echo 'a' > a
+  "$elfshaker" store cache-1
+  echo 'b' > a
+  "$elfshaker" store cache-2
+  "$elfshaker" pack cache-p
+  printf '[cache]\n\tindexes = true\n' > elfshaker_data/config
+  [ ! -e elfshaker_data/index_cache ]
+  [ "$("$elfshaker" list --format '%t %n' cache-p)" == "$(printf 'cache-1 1\ncache-2 1')" ]
+  # The index is cached on disk, in the flat format.
+  [ "$(ls elfshaker_data/index_cache | wc -l)" == "1" ]
+  cached_index=$(ls elfshaker_data/index_cache/*.pack.idx)
+  [ "$(od -An -tx1 -j4 -N4 "$cached_index" | tr -d ' ')" == "00000009" ]
+  [ "$("$elfshaker" list --format '%t %n' cache-p)" == "$(printf 'cache-1 1\ncache-2 1')" ]
+  [ "$(ls elfshaker_data/index_cache)" == "$(basename "$cached_index")" ]
+  # The cached copy is replaced when the index changes.
+  "$elfshaker" rename cache-p:cache-2 cache-3
+  [ "$("$elfshaker" list --format '%t' cache-p)" == "$(printf 'cache-1\ncache-3')" ]
+  [ "$(ls elfshaker_data/index_cache | wc -l)" == "1" ]
+  [ "$(ls elfshaker_data/index_cache)" != "$(basename "$cached_index")" ]
+  "$elfshaker" extract --reset cache-p:cache-3
+  [ "$(cat a)" == "b" ]
+  # It is removed with the pack, and cached again under its new name.
+  "$elfshaker" rename --pack cache-p cache-q
+  [ "$("$elfshaker" list --format '%t' cache-q)" == "$(printf 'cache-1\ncache-3')" ]
+  "$elfshaker" extract --reset cache-q:cache-1
+  [ "$(cat a)" == "a" ]
+  [ "$(ls elfshaker_data/index_cache | wc -l)" == "1" ]
+  [ "$(ls elfshaker_data/index_cache)" != "$(basename "$cached_index")" ]
+  cd ..
+  rm -rf index_cache
+}
+
+test_extract_verify_works() {
+  mkdir extract_verify
+  cd extract_verify
+  mkdir dir
+  echo 'a' > dir/a
+  "$elfshaker" store verify-1
+  echo 'b' > dir/a
+  "$elfshaker" extract --reset --verify verify-1
+  [ "$(cat dir/a)" == "a" ]
+  # Corrupt the object, keeping its size.
+  object=$(find elfshaker_data/loose -type f)
+  chmod u+w "$object"
+  echo 'c' > "$object"
+  echo 'b' > dir/a
+  if output=$("$elfshaker" extract --reset --verify verify-1 2>&1); then exit 1; fi
+  echo "$output" | grep -q 'The contents of dir/a are corrupt'
+  # Also with extract.verify, but not by default.
+  printf '[extract]\n\tverify = true\n' > elfshaker_data/config
+  if "$elfshaker" extract --reset verify-1; then exit 1; fi
+  rm elfshaker_data/config
+  "$elfshaker" extract --reset verify-1
+  [ "$(cat dir/a)" == "c" ]
+  cd ..
+  rm -rf extract_verify
+}
+
+test_extract_modified_works() {
+  mkdir extract_modified
+  cd extract_modified
+  echo 'a1' > a
+  echo 'b1' > b
+  echo 'c1' > c
+  "$elfshaker" store modified-1
+  echo 'a2' > a
+  echo 'b2' > b
+  rm c
+  "$elfshaker" store modified-2
+  "$elfshaker" extract modified-1
+  # The modified files fail the extraction by default. Their times are
+  # set in the future, so that they are more recent than HEAD.
+  later="@$(($(date +%s) + 10))"
+  echo 'local' > a
+  touch -d "$later" a b
+  if "$elfshaker" extract modified-2; then exit 1; fi
+  [ "$(cat a b c)" == "$(printf 'local\nb1\nc1')" ]
+  # Only a is modified, since b has the contents recorded in HEAD.
+  output=$("$elfshaker" extract --modified keep modified-2 2>&1)
+  echo "$output" | grep -q 'Kept the modified file a'
+  if echo "$output" | grep -q 'modified file b'; then exit 1; fi
+  [ "$(cat a b)" == "$(printf 'local\nb2')" ]
+  [ ! -e c ]
+  "$elfshaker" extract --reset modified-1
+  echo 'local' > a
+  echo 'local' > c
+  touch -d "$later" a c
+  echo 'backup' > c.orig
+  output=$("$elfshaker" extract --modified backup modified-2 2>&1)
+  echo "$output" | grep -q 'Moved the modified file a to a.orig'
+  echo "$output" | grep -q 'Moved the modified file c to c.orig~1'
+  [ "$(cat a a.orig b c.orig c.orig~1)" == "$(printf 'a2\nlocal\nb2\nbackup\nlocal')" ]
+  [ ! -e c ]
+  # The default can be configured.
+  "$elfshaker" extract --reset modified-1
+  echo 'local' > b
+  touch -d "$later" b
+  printf '[extract]\n\tmodified = keep\n' > elfshaker_data/config
+  "$elfshaker" extract modified-2
+  [ "$(cat a b)" == "$(printf 'a2\nlocal')" ]
+  cd ..
+  rm -rf extract_modified
+}
+
+test_pin_works() {
+  mkdir pin
+  cd pin
+  echo 'a' > a
+  "$elfshaker" store pin-1
+  "$elfshaker" pack pin-p
+  "$elfshaker" gc
+  # A loose copy of pin-1, under another tag.
+  "$elfshaker" store pin-2
+  echo 'b' > a
+  "$elfshaker" store pin-3
+  "$elfshaker" pin pin-p:pin-1 pin-2
+  [ "$("$elfshaker" pin)" == "$(printf 'pin-1\npin-2')" ]
+  if output=$("$elfshaker" rm pin-p:pin-1 2>&1); then exit 1; fi
+  echo "$output" | grep -q 'is pinned'
+  if "$elfshaker" rm pin-2; then exit 1; fi
+  # gc keeps pin-2, although it is the same as pin-p:pin-1.
+  "$elfshaker" gc -s
+  [ -f elfshaker_data/packs/loose/pin-2.pack.idx ]
+  # Renamed snapshots stay pinned.
+  "$elfshaker" rename pin-2 pin-4
+  [ "$("$elfshaker" pin)" == "$(printf 'pin-1\npin-4')" ]
+  "$elfshaker" pin --remove pin-4
+  "$elfshaker" gc -s
+  [ ! -f elfshaker_data/packs/loose/pin-4.pack.idx ]
+  "$elfshaker" pin --remove pin-p:pin-1
+  [ -z "$("$elfshaker" pin)" ]
+  # The tag of different snapshots can't be pinned.
+  echo 'c' > a
+  "$elfshaker" store pin-1
+  if output=$("$elfshaker" pin pin-p:pin-1 2>&1); then exit 1; fi
+  echo "$output" | grep -q 'multiple packs'
+  [ -z "$("$elfshaker" pin)" ]
+  "$elfshaker" extract pin-3
+  "$elfshaker" rm pin-p:pin-1
+  cd ..
+  rm -rf pin
+}
+
+test_prune_works() {
+  mkdir prune
+  cd prune
+  for i in 1 2 3; do
+    echo "$i" > a
+    "$elfshaker" store prune-p$i
+  done
+  "$elfshaker" pack prune-p
+  "$elfshaker" gc
+  for i in 1 2 3 4; do
+    echo "l$i" > a
+    "$elfshaker" store prune-l$i
+    # The loose snapshots are newer than the pack, in order.
+    touch -d "@$(($(date +%s) + 10 * i))" elfshaker_data/packs/loose/prune-l$i.pack.idx
+  done
+  "$elfshaker" pin prune-p:prune-p1
+  "$elfshaker" extract --reset prune-l1
+  if "$elfshaker" prune --dry-run; then exit 1; fi
+  output=$("$elfshaker" prune --keep-last 2 --dry-run)
+  [ "$(echo "$output" | grep -c 'Would remove .*:')" == 3 ]
+  echo "$output" | grep -q 'Would remove prune-p:prune-p2'
+  echo "$output" | grep -q 'Would remove prune-p:prune-p3'
+  echo "$output" | grep -q 'Would remove 3 snapshot(s) and keep 4'
+  [ "$("$elfshaker" list | wc -l)" == 7 ]
+  # Keeps prune-l3 and prune-l4, HEAD (prune-l1) and the pinned prune-p1.
+  "$elfshaker" prune --keep-last 2
+  [ "$("$elfshaker" list | sort)" == "$(printf 'loose/prune-l1:prune-l1\nloose/prune-l3:prune-l3\nloose/prune-l4:prune-l4\nprune-p:prune-p1')" ]
+  # Everything was stored since yesterday.
+  "$elfshaker" prune --keep-since 1d
+  [ "$("$elfshaker" list | wc -l)" == 4 ]
+  "$elfshaker" prune --keep-since "$(date -u -d '+1 hour' +%Y-%m-%dT%H:%M:%SZ)"
+  [ "$("$elfshaker" list | sort)" == "$(printf 'loose/prune-l1:prune-l1\nprune-p:prune-p1')" ]
+  if "$elfshaker" prune --keep-since yesterday; then exit 1; fi
+  "$elfshaker" extract --verify prune-p1
+  cd ..
+  rm -rf prune
+}
+
+test_snapshot_time_works() {
+  mkdir snapshot-time
+  cd snapshot-time
+  echo 'a' > a
+  "$elfshaker" store --author ci-1 time-1
+  printf '[store]\n\tauthor = ci-2\n' > elfshaker_data/config
+  echo 'b' > a
+  "$elfshaker" store time-2
+  "$elfshaker" pack time-p
+  # The times and authors are kept when the snapshots are packed.
+  output=$("$elfshaker" list --format '%t %u %d' time-p)
+  echo "$output" | grep -qE '^time-1 ci-1 [0-9]{4}-[0-9]{2}-[0-9]{2}T[0-9:]{8}Z$'
+  echo "$output" | grep -qE '^time-2 ci-2 [0-9]{4}-'
+  [ "$("$elfshaker" list --since 1h --format '%t' time-p | wc -l)" == 2 ]
+  [ -z "$("$elfshaker" list --until 1h time-p)" ]
+  output=$("$elfshaker" list --json time-p)
+  echo "$output" | grep -q '"author": "ci-1"'
+  if output=$("$elfshaker" list --json --format '%t' time-p 2>&1); then exit 1; fi
+  echo "$output" | grep -q "can't be used with --format"
+  cd ..
+  rm -rf snapshot-time
+}
+
+test_log_works() {
+  mkdir log
+  cd log
+  echo 'a' > a
+  "$elfshaker" store --author ci-1 -m 'First build' --meta commit=abc log-1
+  echo 'b' > a
+  "$elfshaker" store -m "$(printf 'Second build\n\nWith details')" log-2
+  "$elfshaker" pack log-p
+  "$elfshaker" gc
+  echo 'c' > a
+  "$elfshaker" store log-3
+  "$elfshaker" pin log-1
+  output=$("$elfshaker" log)
+  echo "$output" | grep -q '^snapshot loose/log-3:log-3 (HEAD)$'
+  echo "$output" | grep -q '^snapshot log-p:log-1 (pinned)$'
+  echo "$output" | grep -q '^Author: ci-1$'
+  echo "$output" | grep -q '^Meta:   commit=abc$'
+  echo "$output" | grep -q '^    With details$'
+  # Most recent first.
+  [ "$(echo "$output" | grep '^snapshot' | cut -d' ' -f2)" == "$(printf 'loose/log-3:log-3\nlog-p:log-2\nlog-p:log-1')" ]
+  [ "$("$elfshaker" log -n 1 --oneline | cut -d' ' -f2-)" == 'loose/log-3:log-3 ' ]
+  [ "$("$elfshaker" log --oneline | tail -n 1 | cut -d' ' -f2-)" == 'log-p:log-1 First build' ]
+  [ "$("$elfshaker" log --since 1h --oneline | wc -l)" == 3 ]
+  [ -z "$("$elfshaker" log --until 1h)" ]
+  cd ..
+  rm -rf log
+}
+
+test_tag_works() {
+  mkdir tag
+  cd tag
+  echo 'a' > a
+  "$elfshaker" store tag-1
+  echo 'b' > a
+  "$elfshaker" store tag-2
+  # HEAD by default.
+  "$elfshaker" tag latest
+  "$elfshaker" tag release-1.0 tag-1
+  "$elfshaker" tag release/2.0 loose/tag-2:tag-2
+  [ "$("$elfshaker" tag)" == "$(printf 'latest loose/tag-2:tag-2\nrelease-1.0 loose/tag-1:tag-1\nrelease/2.0 loose/tag-2:tag-2')" ]
+  "$elfshaker" extract release-1.0
+  [ "$(cat a)" == a ]
+  [[ "$("$elfshaker" list-files latest)" == *' 2 a' ]]
+  # The refs follow the snapshots when they are packed or renamed.
+  "$elfshaker" pack tag-p
+  "$elfshaker" gc
+  "$elfshaker" extract latest
+  [ "$(cat a)" == b ]
+  "$elfshaker" rename tag-p:tag-1 tag-3
+  [ "$(cat elfshaker_data/refs/release-1.0)" == tag-p:tag-3 ]
+  "$elfshaker" rename --pack tag-p tag-q
+  [ "$(cat elfshaker_data/refs/latest)" == tag-q:tag-2 ]
+  # Updating a ref.
+  "$elfshaker" tag latest tag-3
+  [ "$(cat elfshaker_data/refs/latest)" == tag-q:tag-3 ]
+  if "$elfshaker" tag 'bad:name' tag-3; then exit 1; fi
+  if "$elfshaker" tag ../bad tag-3; then exit 1; fi
+  "$elfshaker" tag -d release/2.0 latest
+  [ ! -d elfshaker_data/refs/release ]
+  if "$elfshaker" tag -d latest; then exit 1; fi
+  "$elfshaker" extract release-1.0
+  "$elfshaker" rm tag-2
+  "$elfshaker" list-files release-1.0 > /dev/null
+  "$elfshaker" tag missing tag-q:tag-3
+  echo 'c' > a
+  "$elfshaker" store tag-4
+  "$elfshaker" rm tag-q:tag-3
+  if output=$("$elfshaker" extract missing 2>&1); then exit 1; fi
+  echo "$output" | grep -q 'The ref missing points to'
+  cd ..
+  rm -rf tag
+}
+
+test_worktree_works() {
+  mkdir -p worktree/main
+  cd worktree/main
+  echo 'a' > a
+  "$elfshaker" store wt-1
+  echo 'b' > a
+  "$elfshaker" store wt-2
+  # HEAD by default.
+  "$elfshaker" worktree ../linked
+  [ "$(cat ../linked/a)" == b ]
+  [ ! -d ../linked/elfshaker_data/packs ]
+  "$elfshaker" worktree ../other wt-1
+  [ "$(cat ../other/a)" == a ]
+  output=$("$elfshaker" worktree)
+  [ "$(echo "$output" | wc -l)" == 3 ]
+  [[ "$output" == *'/other loose/wt-1:wt-1'* ]]
+  # Each working tree has its own HEAD, and stores to the shared repository.
+  cd ../linked
+  "$elfshaker" extract wt-1
+  [ "$(cat a)" == a ]
+  echo 'c' > a
+  "$elfshaker" store wt-3
+  cd ../main
+  [ "$(cat a)" == b ]
+  [[ "$("$elfshaker" list-files wt-3)" == *' 2 a' ]]
+  # The HEADs of the other working trees are kept, and follow the snapshots.
+  if "$elfshaker" rm wt-3; then exit 1; fi
+  "$elfshaker" pack wt-p
+  "$elfshaker" gc
+  cd ../linked
+  "$elfshaker" extract wt-2
+  [ "$(cat a)" == b ]
+  cd ../main
+  "$elfshaker" rename --pack wt-p wt-q
+  [ "$(cat ../linked/elfshaker_data/HEAD)" == wt-q:wt-2 ]
+  [ "$(cat elfshaker_data/HEAD)" == wt-q:wt-2 ]
+  # The working trees with new files are only removed with --force.
+  sleep 1
+  echo 'd' > ../other/d
+  if "$elfshaker" worktree --remove ../other; then exit 1; fi
+  "$elfshaker" worktree --remove ../other --force
+  [ ! -d ../other ]
+  "$elfshaker" worktree --remove ../linked
+  [ ! -d ../linked ]
+  "$elfshaker" worktree ../gone
+  rm -rf ../gone
+  "$elfshaker" worktree --prune
+  [ "$("$elfshaker" worktree)" == "$PWD wt-q:wt-2" ]
+  cd ../..
+  rm -rf worktree
+}
+
+test_find_paths_works() {
+  mkdir find
+  cd find
+  mkdir -p lib/sub
+  echo 'a' > lib/a.o
+  echo 'b' > lib/sub/b.o
+  echo 'c' > lib/libc.so
+  "$elfshaker" store find-1
+  rm lib/a.o
+  "$elfshaker" store find-2
+  "$elfshaker" pack find-p
+  "$elfshaker" gc
+  echo 'd' > d.o
+  "$elfshaker" store find-3
+  output=$("$elfshaker" find --path 'lib/*.o')
+  [[ "$output" == *'find-p:find-1'*'lib/a.o'* ]]
+  [[ "$output" != *'find-2'* ]]
+  [[ "$output" != *'sub/b.o'* ]]
+  [ "$("$elfshaker" find --path 'lib/**/*.o' --snapshots-only)" == "$(printf 'find-p:find-1\nfind-p:find-2\nloose/find-3:find-3')" ]
+  [ "$("$elfshaker" find --regex '^[a-z]+\.o$' --snapshots-only)" == loose/find-3:find-3 ]
+  [ "$("$elfshaker" find find-2 --regex 'lib.*\.so$' --snapshots-only)" == find-p:find-2 ]
+  [ -z "$("$elfshaker" find --regex 'missing' --snapshots-only)" ]
+  if "$elfshaker" find --regex '(lib'; then exit 1; fi
+  output=$("$elfshaker" find --regex '((a{1000}){1000}){1000}' 2>&1 || true)
+  [[ "$output" == *'limited to 100000 instructions'* ]]
+  output=$("$elfshaker" find --regex "$(printf '(%.0s' {1..100000})" 2>&1 || true)
+  [[ "$output" == *'nesting is limited to 256 levels'* ]]
+  cd ..
+  rm -rf find
+}
+
+test_grep_works() {
+  mkdir grep
+  cd grep
+  printf 'int main;\nvoid helper_fn(void);\n' > a.h
+  printf 'nothing\n' > b.txt
+  printf 'bin\0helper_fn\0' > c.o
+  "$elfshaker" store grep-1
+  printf 'void other_fn(void);\n' > a.h
+  cp c.o d.o
+  "$elfshaker" store grep-2
+  [ "$("$elfshaker" grep -n 'helper_\w+' grep-1)" == "$(printf 'loose/grep-1:grep-1:a.h:2:void helper_fn(void);\nBinary file loose/grep-1:grep-1:c.o matches')" ]
+  [ "$("$elfshaker" grep --text -F helper_fn grep-1)" == 'loose/grep-1:grep-1:a.h:void helper_fn(void);' ]
+  "$elfshaker" pack grep-p
+  # All the snapshots of the pack.
+  [ "$("$elfshaker" grep -l '_fn' grep-p)" == "$(printf 'grep-p:grep-1:a.h\ngrep-p:grep-1:c.o\ngrep-p:grep-2:a.h\ngrep-p:grep-2:c.o\ngrep-p:grep-2:d.o')" ]
+  [ -z "$("$elfshaker" grep missing grep-p)" ]
+  if "$elfshaker" grep x no-such-snapshot; then exit 1; fi
+  cd ..
+  rm -rf grep
+}
+
+test_bloom_filters_works() {
+  mkdir bloom
+  cd bloom
+  mkdir lib
+  echo 'a' > lib/a.o
+  echo 'b' > b.o
+  "$elfshaker" store bloom-1
+  "$elfshaker" pack bloom-p
+  "$elfshaker" gc
+  [ "$("$elfshaker" find --path lib/a.o --snapshots-only)" == bloom-p:bloom-1 ]
+  [ -f elfshaker_data/packs/bloom-p.pack.bloom ]
+  [ -z "$("$elfshaker" find --path lib/missing.o --path 'b' --snapshots-only)" ]
+  [ "$("$elfshaker" show bloom-1 b.o lib/a.o)" == "$(printf 'b\na')" ]
+  if "$elfshaker" show bloom-1 lib/missing.o; then exit 1; fi
+  # The stale and the invalid filters are rebuilt.
+  cp elfshaker_data/packs/bloom-p.pack.bloom ../bloom-p.pack.bloom
+  touch -d '2001-01-01' elfshaker_data/packs/bloom-p.pack.idx
+  [ "$("$elfshaker" find --path b.o --snapshots-only)" == bloom-p:bloom-1 ]
+  if cmp -s elfshaker_data/packs/bloom-p.pack.bloom ../bloom-p.pack.bloom; then exit 1; fi
+  echo 'invalid' > elfshaker_data/packs/bloom-p.pack.bloom
+  [ "$("$elfshaker" find --path lib/a.o --snapshots-only)" == bloom-p:bloom-1 ]
+  echo 'c' > c.o
+  "$elfshaker" store bloom-2
+  "$elfshaker" rm bloom-p:bloom-1
+  [ ! -e elfshaker_data/packs/bloom-p.pack.bloom ]
+  cd ..
+  rm -rf bloom bloom-p.pack.bloom
+}
+
+test_object_index_works() {
+  mkdir object-index
+  cd object-index
+  echo 'a' > a
+  echo 'b' > b
+  "$elfshaker" store oi-1
+  "$elfshaker" pack oi-p
+  "$elfshaker" gc
+  echo 'c' > c
+  "$elfshaker" store oi-2
+  printf '[cache]\n  objects = true\n' > elfshaker_data/config
+  files=$("$elfshaker" list-files oi-2)
+  a_checksum=$(awk '$3 == "a" { print $1 }' <<< "$files")
+  c_checksum=$(awk '$3 == "c" { print $1 }' <<< "$files")
+  output=$("$elfshaker" find-object "$a_checksum")
+  [[ "$output" == *'loose/oi-2:oi-2'*'oi-p:oi-1'* ]]
+  [ -f elfshaker_data/object_index ]
+  output=$("$elfshaker" find-object "$c_checksum")
+  [[ "$output" == *'loose/oi-2:oi-2'* ]]
+  [[ "$output" != *'oi-p'* ]]
+  # The new packs are recorded, and the deleted ones forgotten.
+  "$elfshaker" pack oi-q
+  "$elfshaker" gc
+  "$elfshaker" rm oi-p:oi-1
+  output=$("$elfshaker" find-object "$a_checksum")
+  [[ "$output" == *'oi-q:oi-2'* ]]
+  [[ "$output" != *'oi-p'* ]]
+  [[ "$output" != *'loose'* ]]
+  rm elfshaker_data/object_index
+  [[ "$("$elfshaker" find-object "$c_checksum")" == *'oi-q:oi-2'* ]]
+  cd ..
+  rm -rf object-index
+}
+
+test_dedup_works() {
+  mkdir dedup
+  cd dedup
+  head -c 200000 /dev/urandom > big
+  cp big ../dedup-big
+  echo 'a' > a
+  "$elfshaker" store dd-1
+  "$elfshaker" pack dd-p
+  "$elfshaker" gc
+  echo 'b' > b
+  "$elfshaker" store dd-2
+  "$elfshaker" pack --dedup dd-q
+  "$elfshaker" gc
+  # The new pack only stores the new file.
+  [ "$(stat -c %s elfshaker_data/packs/dd-q.pack)" -lt 100000 ]
+  rm big a b
+  "$elfshaker" extract --verify --reset dd-q:dd-2
+  cmp big ../dedup-big
+  [ "$("$elfshaker" show dd-2 a b)" == "$(printf 'a\nb')" ]
+  "$elfshaker" verify dd-q
+  # The referenced pack can't be removed or renamed.
+  if "$elfshaker" rm dd-p:dd-1; then exit 1; fi
+  if "$elfshaker" rename --pack dd-p dd-r; then exit 1; fi
+  [ "$("$elfshaker" list dd-p)" == dd-p:dd-1 ]
+  # Once repacked without --dedup, it can.
+  "$elfshaker" repack dd-q
+  "$elfshaker" rm dd-p:dd-1
+  "$elfshaker" extract --verify --reset dd-q:dd-2
+  cmp big ../dedup-big
+  cd ..
+  rm -rf dedup dedup-big
+}
+
+test_pack_selection_works() {
+  mkdir pack-selection
+  cd pack-selection
+  echo 'a' > a
+  "$elfshaker" store sel-1
+  echo 'b' > b
+  "$elfshaker" store sel-2
+  "$elfshaker" pack sel-p
+  "$elfshaker" gc
+  echo 'c' > c
+  "$elfshaker" store sel-3
+  echo 'd' > d
+  "$elfshaker" store sel-4
+  # Cherry-picks a packed and a loose snapshot.
+  "$elfshaker" pack sel-q --snapshots sel-p:sel-2,sel-3
+  [ "$("$elfshaker" list sel-q)" == "$(printf 'sel-q:sel-2\nsel-q:sel-3')" ]
+  [ "$("$elfshaker" list sel-p)" == "$(printf 'sel-p:sel-1\nsel-p:sel-2')" ]
+  "$elfshaker" extract --verify --reset sel-q:sel-3
+  [ "$(cat a b c)" == "$(printf 'a\nb\nc')" ]
+  [[ "$("$elfshaker" list-files sel-q:sel-3)" != *' d'* ]]
+  printf 'sel-1\n\nloose/sel-4:sel-4\n' > ../selection
+  "$elfshaker" pack sel-r --snapshots ../selection
+  [ "$("$elfshaker" list sel-r)" == "$(printf 'sel-r:sel-1\nsel-r:sel-4')" ]
+  if "$elfshaker" pack sel-s --snapshots sel-p:sel-2,sel-q:sel-2; then exit 1; fi
+  if "$elfshaker" pack sel-q --snapshots sel-4; then exit 1; fi
+  cd ..
+  rm -rf pack-selection selection
+}
+
+test_hooks_works() {
+  mkdir hooks
+  cd hooks
+  echo 'a' > a
+  "$elfshaker" store hook-1
+  mkdir elfshaker_data/hooks
+  printf '#!/bin/sh\necho "$ELFSHAKER_HOOK $ELFSHAKER_SNAPSHOT $(cat "$ELFSHAKER_WORK_DIR"/b)" >> ../hooks.log\n' > elfshaker_data/hooks/pre-store
+  printf '#!/bin/sh\necho "$ELFSHAKER_HOOK $ELFSHAKER_PACK $(basename "$ELFSHAKER_PACK_PATH")" >> ../hooks.log\n' > elfshaker_data/hooks/post-pack
+  printf '#!/bin/sh\necho "$ELFSHAKER_HOOK $ELFSHAKER_SNAPSHOT $ELFSHAKER_PREVIOUS_HEAD $ELFSHAKER_ADDED $ELFSHAKER_REMOVED"  >> ../hooks.log\nexit 1\n' > elfshaker_data/hooks/post-extract
+  chmod +x elfshaker_data/hooks/*
+  echo 'b' > b
+  "$elfshaker" store hook-2
+  "$elfshaker" pack hook-p
+  # The failure of a post- hook is only reported.
+  "$elfshaker" extract hook-p:hook-1
+  [ "$(cat ../hooks.log)" == "$(printf 'pre-store loose/hook-2:hook-2 b\npost-pack hook-p hook-p.pack\npost-extract hook-p:hook-1 hook-p:hook-2 0 1')" ]
+  # The failure of the pre-store hook refuses to store the snapshot.
+  printf '#!/bin/sh\nexit 1\n' > elfshaker_data/hooks/pre-store
+  if "$elfshaker" store hook-3; then exit 1; fi
+  if "$elfshaker" extract hook-3; then exit 1; fi
+  # The hooks which are not executable are ignored.
+  chmod -x elfshaker_data/hooks/pre-store
+  "$elfshaker" store hook-3
+  cd ..
+  rm -rf hooks hooks.log
+}
+
+test_store_git_works() {
+  mkdir store-git
+  cd store-git
+  git init -q
+  echo 'a' > a
+  git add a
+  git -c user.name=test -c user.email=test@example.com commit -qm 'Add a'
+  commit=$(git rev-parse HEAD)
+  echo 'o' > a.o
+  "$elfshaker" store git-1 --git
+  echo 'b' > a
+  "$elfshaker" store git-2 --git
+  "$elfshaker" store git-3 --git --meta commit=0123abcd
+  "$elfshaker" store git-4
+  [ "$("$elfshaker" list --format '%t %a')" == "$(printf 'git-1 commit=%s,dirty=false\ngit-2 commit=%s,dirty=true\ngit-3 commit=0123abcd,dirty=true\ngit-4 ' "$commit" "$commit")" ]
+  found=$("$elfshaker" find --commit "${commit:0:7}")
+  [[ "$found" == *"loose/git-1:git-1"*"$commit"* ]]
+  [[ "$found" == *"loose/git-2:git-2"*"$commit-dirty"* ]]
+  [[ "$found" != *git-3* ]]
+  [[ "$("$elfshaker" find 3 --commit 0123ABCD)" == *"loose/git-3:git-3"* ]]
+  [[ "$("$elfshaker" find 1 --commit "$commit")" != *git-2* ]]
+  if "$elfshaker" find --commit xyz; then exit 1; fi
+  rm -rf .git
+  if "$elfshaker" store git-5 --git; then exit 1; fi
+  cd ..
+  rm -rf store-git
+}
+
+test_index_export_works() {
+  mkdir index-export
+  cd index-export
+  echo 'a' > a
+  "$elfshaker" store ei-1 --meta commit=c1
+  echo 'b' > b
+  "$elfshaker" store ei-2
+  "$elfshaker" pack ei-p
+  "$elfshaker" index export --sqlite ../index.sqlite ei-p
+  [ "$(head -c 15 ../index.sqlite)" == 'SQLite format 3' ]
+  if python3 -c 'import sqlite3' 2>/dev/null; then
+    query() {
+      python3 -c 'import sqlite3, sys; print(sqlite3.connect(sys.argv[1]).execute(sys.argv[2]).fetchall())' ../index.sqlite "$1"
+    }
+    [ "$(query 'PRAGMA integrity_check')" == "[('ok',)]" ]
+    [ "$(query 'SELECT pack, tag, file_count FROM snapshots')" == "[('ei-p', 'ei-1', 1), ('ei-p', 'ei-2', 2)]" ]
+    [ "$(query 'SELECT s.tag, a.key, a.value FROM attributes a JOIN snapshots s ON s.id = a.snapshot')" == "[('ei-1', 'commit', 'c1')]" ]
+    [ "$(query "SELECT path, size FROM entries WHERE snapshot = 2")" == "[('a', 2), ('b', 2)]" ]
+    [ "$(query 'SELECT COUNT(*) FROM objects')" == "[(2,)]" ]
+  fi
+  if "$elfshaker" index export --sqlite ../index.sqlite missing; then exit 1; fi
+  # The subcommand of index is required.
+  if "$elfshaker" index; then exit 1; fi
+  cd ..
+  rm -rf index-export index.sqlite
+}
+
+test_build_cache_works() {
+  mkdir build-cache
+  cd build-cache
+  echo 'first' > object.o
+  "$elfshaker" cache ab/cdef --put object.o
+  [ "$("$elfshaker" cache ab/cdef)" == 'first' ]
+  echo 'second' | "$elfshaker" cache ab/cdef --put -
+  "$elfshaker" cache ab/cdef -o out.o
+  [ "$(cat out.o)" == 'second' ]
+  if "$elfshaker" cache missing; then exit 1; fi
+  if "$elfshaker" cache ../escape --put object.o; then exit 1; fi
+
+  "$elfshaker" serve --bind 127.0.0.1:43117 --cache --push-token secret --max-cache-entry-size 100 &
+  server_pid=$!
+  sleep 1
+  url=http://127.0.0.1:43117/cache/
+  output=$("$elfshaker" cache ab/cdef --url "$url") || {
+    kill $server_pid
+    exit 1
+  }
+  if "$elfshaker" cache remote --url "$url" --put object.o; then
+    kill $server_pid
+    exit 1
+  fi
+  ELFSHAKER_TOKEN=secret "$elfshaker" cache remote --url "$url" --put object.o || {
+    kill $server_pid
+    exit 1
+  }
+  remote=$("$elfshaker" cache remote --url "$url") || {
+    kill $server_pid
+    exit 1
+  }
+  # The entries larger than --max-cache-entry-size are rejected.
+  head -c 101 /dev/zero > large.o
+  if ELFSHAKER_TOKEN=secret "$elfshaker" cache large --url "$url" --put large.o; then
+    kill $server_pid
+    exit 1
+  fi
+  status=$(curl -s -o /dev/null -w '%{http_code}' -X PUT -H 'Authorization: Bearer secret' \
+    --data-binary @large.o "${url}large")
+  kill $server_pid
+  [ "$status" == 413 ]
+  if "$elfshaker" cache large; then exit 1; fi
+  [ "$output" == 'second' ]
+  [ "$remote" == 'first' ]
+
+  # The entries are still found once packed.
+  "$elfshaker" pack build-cache-p
+  "$elfshaker" gc --loose-snapshots
+  if "$elfshaker" list | grep -q '^loose/'; then exit 1; fi
+  [ "$("$elfshaker" cache remote)" == 'first' ]
+  [ "$("$elfshaker" cache ab/cdef)" == 'second' ]
+  cd ..
+  rm -rf build-cache
+}
+
+test_list_sort_works() {
+  mkdir list-sort
+  cd list-sort
+  head -c 100 /dev/zero > big
+  "$elfshaker" store ls-c
+  sleep 1
+  rm big
+  echo a > a
+  echo b > b
+  "$elfshaker" store ls-a
+  rm b
+  "$elfshaker" store ls-b
+  sorted() {
+    "$elfshaker" list --format %t "$@" | paste -sd ' '
+  }
+  [ "$(sorted --sort name)" == 'ls-a ls-b ls-c' ]
+  [ "$(sorted --sort size)" == 'ls-b ls-a ls-c' ]
+  # Equal snapshots are sorted by name.
+  [ "$(sorted --sort files)" == 'ls-b ls-c ls-a' ]
+  [ "$(sorted --sort date)" == 'ls-c ls-a ls-b' ]
+  [ "$(sorted --sort date --reverse)" == 'ls-b ls-a ls-c' ]
+  [ "$(sorted --reverse)" == 'ls-c ls-b ls-a' ]
+  output=$("$elfshaker" list --json --sort size --reverse)
+  grep -m1 '"snapshot"' <<< "$output" | grep -q 'ls-c'
+  if "$elfshaker" list --sort mtime; then exit 1; fi
+  cd ..
+  rm -rf list-sort
+}
+
+test_list_files_works() {
+  mkdir -p list-files/d
+  cd list-files
+  echo 'abc' > a
+  echo 'hello' > d/b
+  chmod 755 a
+  "$elfshaker" store lf
+  [ "$("$elfshaker" list-files lf --format '%f %b' | paste -sd ' ')" == 'a 4 d/b 6' ]
+  "$elfshaker" list-files lf --format '%p %f' | grep -x '100755 a'
+  "$elfshaker" list-files lf --json | grep '"path": "d/b"'
+  if output=$("$elfshaker" list-files lf --json --format '%f' 2>&1); then exit 1; fi
+  echo "$output" | grep -q "can't be used with --format"
+  if "$elfshaker" list-files missing; then exit 1; fi
+  cd ..
+  rm -rf list-files
+}
+
+test_show_order_works() {
+  echo 1 > a
+  echo 2 > b
+  echo 3 > c
+  "$elfshaker" store show-order
+  [ "$("$elfshaker" show show-order c a b a | paste -sd ' ')" == '3 1 2 1' ]
+  "$elfshaker" pack show-order-p
+  # The files are printed in the order of the command line, not of the pack.
+  [ "$("$elfshaker" show show-order-p:show-order c a b a | paste -sd ' ')" == '3 1 2 1' ]
+  [ "$("$elfshaker" show show-order-p:show-order a b c | paste -sd ' ')" == '1 2 3' ]
+  if "$elfshaker" show show-order-p:show-order a missing; then exit 1; fi
+}
+
+test_find_object_works() {
+  mkdir -p find-object/d
+  cd find-object
+  echo 'shared' > a
+  "$elfshaker" store fo-1
+  cp a d/copy
+  echo 'other' > b
+  "$elfshaker" store fo-2
+  "$elfshaker" pack fo-p
+  rm d/copy
+  "$elfshaker" store fo-3
+  checksum="$("$elfshaker" list-files fo-p:fo-1 --format '%o')"
+  # The header is printed to stderr.
+  [ "$("$elfshaker" find-object "$checksum" | awk '{print $1, $2}' | paste -sd ' ')" == \
+    'fo-p:fo-1 a fo-p:fo-2 a fo-p:fo-2 d/copy loose/fo-1:fo-1 a loose/fo-2:fo-2 a loose/fo-2:fo-2 d/copy loose/fo-3:fo-3 a' ]
+  other="$("$elfshaker" list-files fo-p:fo-2 --format '%o %f' | awk '$2 == "b" {print $1}')"
+  [ "$("$elfshaker" find-object "$other" | awk '{print $1}' | paste -sd ' ')" == 'fo-p:fo-2 loose/fo-2:fo-2 loose/fo-3:fo-3' ]
+  if "$elfshaker" find-object not-a-checksum; then exit 1; fi
+  cd ..
+  rm -rf find-object
+}
+
+test_status_works() {
+  mkdir status
+  cd status
+  echo 'a' > a
+  echo 'b' > b
+  echo 'c' > c
+  "$elfshaker" store status-1
+  [ -z "$("$elfshaker" status)" ]
+  echo 'modified' > a
+  rm b
+  echo 'new' > d
+  [ "$("$elfshaker" status | paste -sd ' ')" == "$(printf 'M\ta D\tb ?\td')" ]
+  "$elfshaker" store status-2
+  [ -z "$("$elfshaker" status)" ]
+  cd ..
+  rm -rf status
+}
+
+test_verify_works() {
+  mkdir verify
+  cd verify
+  echo 'first' > a
+  echo 'second' > b
+  "$elfshaker" store verify-1
+  "$elfshaker" pack verify-p
+  "$elfshaker" store verify-2
+  output="$("$elfshaker" verify)"
+  echo "$output" | grep -x 'verify-p: OK'
+  echo "$output" | grep -x 'loose/verify-2: OK'
+  "$elfshaker" fsck verify-p
+
+  # A corrupt loose object is reported, and so is a missing one.
+  object="$(find elfshaker_data/loose -type f | head -n1)"
+  chmod u+w "$object"
+  echo 'rotten' > "$object"
+  if output="$("$elfshaker" verify loose/verify-2)"; then exit 1; fi
+  echo "$output" | grep 'loose/verify-2: corrupt object'
+  rm "$object"
+  if output="$("$elfshaker" verify loose/verify-2)"; then exit 1; fi
+  echo "$output" | grep 'loose/verify-2: missing object'
+  "$elfshaker" verify verify-p
+
+  # HEAD must reference an existing snapshot.
+  echo 'verify-p:missing' > elfshaker_data/HEAD
+  if output="$("$elfshaker" verify verify-p)"; then exit 1; fi
+  echo "$output" | grep '^HEAD: dangling reference to verify-p:missing'
+  cd ..
+  rm -rf verify
+}
+
+test_rm_works() {
+  mkdir rm
+  cd rm
+  echo 'kept' > a
+  "$elfshaker" store rm-1
+  head -c 100000 /dev/urandom > big
+  "$elfshaker" store rm-2
+  rm big
+  echo 'other' > b
+  "$elfshaker" store rm-3
+  "$elfshaker" pack rm-p
+  "$elfshaker" gc --loose-snapshots
+  size_before="$(stat -c %s elfshaker_data/packs/rm-p.pack)"
+  # HEAD can't be removed.
+  if "$elfshaker" rm rm-p:rm-3; then exit 1; fi
+  "$elfshaker" rm rm-p:rm-2
+  [ "$("$elfshaker" list rm-p | paste -sd ' ')" == 'rm-p:rm-1 rm-p:rm-3' ]
+  # The object which is no longer referenced is dropped.
+  [ "$(stat -c %s elfshaker_data/packs/rm-p.pack)" -lt "$size_before" ]
+  "$elfshaker" extract --verify rm-p:rm-1
+  [ "$(cat a)" == 'kept' ]
+  [ ! -e b ]
+  "$elfshaker" verify rm-p
+  # The pack is deleted once it has no snapshots.
+  "$elfshaker" rm rm-p:rm-3
+  "$elfshaker" store rm-loose
+  "$elfshaker" rm rm-p:rm-1
+  [ ! -e elfshaker_data/packs/rm-p.pack ]
+  [ ! -e elfshaker_data/packs/rm-p.pack.idx ]
+  cd ..
+  rm -rf rm
+}
+
+test_rename_works() {
+  mkdir rename
+  cd rename
+  echo 'a' > a
+  "$elfshaker" store rn-1
+  echo 'b' > b
+  "$elfshaker" store rn-2
+  "$elfshaker" pack rn-p
+  "$elfshaker" gc --loose-snapshots
+  # The snapshots of packs and the loose ones can be renamed.
+  "$elfshaker" rename rn-p:rn-2 rn-3
+  [ "$("$elfshaker" list rn-p | paste -sd ' ')" == 'rn-p:rn-1 rn-p:rn-3' ]
+  [ "$(cat elfshaker_data/HEAD)" == 'rn-p:rn-3' ]
+  if "$elfshaker" rename rn-p:rn-3 rn-1; then exit 1; fi
+  echo 'c' > c
+  "$elfshaker" store rn-loose
+  "$elfshaker" rename rn-loose rn-loose-2
+  [ "$("$elfshaker" list loose/rn-loose-2)" == 'loose/rn-loose-2:rn-loose-2' ]
+  [ "$(cat elfshaker_data/HEAD)" == 'loose/rn-loose-2:rn-loose-2' ]
+  "$elfshaker" extract --verify rn-p:rn-3
+  # The pack files are renamed, along with HEAD.
+  "$elfshaker" rename --pack rn-p rn-q
+  [ -e elfshaker_data/packs/rn-q.pack ] && [ ! -e elfshaker_data/packs/rn-p.pack ]
+  [ "$(cat elfshaker_data/HEAD)" == 'rn-q:rn-3' ]
+  "$elfshaker" extract --verify rn-q:rn-1
+  [ ! -e b ]
+  if "$elfshaker" rename --pack loose/rn-loose-2 other; then exit 1; fi
+  cd ..
+  rm -rf rename
+}
+
+test_export_works() {
+  mkdir export
+  cd export
+  echo 'a' > a
+  chmod 755 a
+  mkdir d
+  echo 'b' > d/b
+  ln -s a l
+  "$elfshaker" store ex-1
+  "$elfshaker" pack ex-p
+  # Exporting the same snapshot twice gives the same archive.
+  "$elfshaker" export ex-p:ex-1 --tar -o ../ex-1.tar
+  sleep 1
+  "$elfshaker" export ex-p:ex-1 --tar > ../ex-2.tar
+  cmp ../ex-1.tar ../ex-2.tar
+  # Only the permission bits are stored, and the symlinks are kept.
+  tar tvf ../ex-1.tar | grep -E '^-rwxr-xr-x .* a$'
+  tar tvf ../ex-1.tar | grep -E '^lrwxrwxrwx .* l -> a$'
+  TZ=UTC tar tvf ../ex-1.tar | grep -E ' 1970-01-01 '
+  "$elfshaker" export ex-1 --tar --zstd -o ../ex-1.tar.zst
+  mkdir ../out
+  if command -v zstd; then
+    zstd -dc ../ex-1.tar.zst | tar -x -C ../out
+  else
+    tar -x -C ../out < ../ex-1.tar
+  fi
+  [ "$(cat ../out/d/b)" == 'b' ] && [ "$(readlink ../out/l)" == 'a' ] && [ -x ../out/a ]
+  if "$elfshaker" export ex-p:missing --tar -o ../missing.tar; then exit 1; fi
+  cd ..
+  rm -rf export out ex-1.tar ex-2.tar ex-1.tar.zst
+}
+
+test_import_works() {
+  mkdir import-src
+  echo 'a' > import-src/a
+  mkdir import-src/d
+  echo 'b' > import-src/d/b
+  ln -s a import-src/l
+  tar -c -f import.tar -C import-src .
+  mkdir import
+  cd import
+  echo 'x' > x
+  "$elfshaker" store im-0
+  "$elfshaker" import ../import.tar im-1 --message 'imported'
+  # HEAD isn't updated, nothing is extracted.
+  [ "$(cat elfshaker_data/HEAD)" == 'loose/im-0:im-0' ]
+  [ ! -e a ]
+  [ "$("$elfshaker" list-files im-1 --format '%f' | paste -sd ' ')" == 'a d/b l' ]
+  "$elfshaker" import - im-2 < ../import.tar
+  "$elfshaker" export im-1 --tar --zstd -o ../import.tar.zst
+  "$elfshaker" import ../import.tar.zst im-3
+  "$elfshaker" extract --verify im-3
+  [ "$(cat d/b)" == 'b' ] && [ "$(readlink l)" == 'a' ] && [ ! -e x ]
+  "$elfshaker" pack im-p
+  "$elfshaker" extract --verify im-p:im-2
+  # The paths leaving the archive are rejected.
+  tar -c -f ../import-bad.tar -C ../import-src a --transform 's,^,../,' 2> /dev/null
+  if "$elfshaker" import ../import-bad.tar im-4; then exit 1; fi
+  if "$elfshaker" import ../missing.tar im-4; then exit 1; fi
+  # So are the members shorter than their header says (which isn't trusted to
+  # allocate their contents).
+  python3 -c 'import sys, tarfile
+info = tarfile.TarInfo("big")
+info.size = 1 << 40
+sys.stdout.buffer.write(info.tobuf(tarfile.GNU_FORMAT) + b"x" * 512)' > ../import-huge.tar
+  if output=$("$elfshaker" import ../import-huge.tar im-4 2>&1); then exit 1; fi
+  [[ "$output" == *'"big" is truncated'* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  cd ..
+  rm -rf import import-src import.tar import.tar.zst import-bad.tar import-huge.tar
+}
+
+test_clone_works() {
+  mkdir clone_server
+  cd clone_server
+  echo 'a' > a
+  "$elfshaker" store clone-1
+  "$elfshaker" pack clone-p
+  "$elfshaker" serve --bind 127.0.0.1:43120 &
+  server_pid=$!
+  cd ..
+  sleep 1
+
+  mkdir clone_dest
+  cd clone_dest
+  # The directory is named after the host, since the index is index.esi.
+  "$elfshaker" clone http://127.0.0.1:43120/index.esi || { kill $server_pid; exit 1; }
+  [ -d 127.0.0.1/elfshaker_data ] || { kill $server_pid; exit 1; }
+  [ ! -e 127.0.0.1/elfshaker_data/packs/origin/clone-p.pack ] || { kill $server_pid; exit 1; }
+  if "$elfshaker" clone http://127.0.0.1:43120/index.esi; then kill $server_pid; exit 1; fi
+  # All the packs are fetched upfront with --fetch-packs.
+  "$elfshaker" clone --fetch-packs http://127.0.0.1:43120/index.esi all || { kill $server_pid; exit 1; }
+  kill $server_pid
+  [ -e all/elfshaker_data/packs/origin/clone-p.pack ]
+  cd all
+  "$elfshaker" extract --verify origin/clone-p:clone-1
+  [ "$(cat a)" == 'a' ]
+  cd ../..
+  rm -rf clone_server clone_dest
+}
+
+test_push_works() {
+  mkdir push_server
+  cd push_server
+  echo 'a' > a
+  "$elfshaker" store push-1
+  "$elfshaker" pack push-p
+  "$elfshaker" serve --bind 127.0.0.1:43121 --push-token secret &
+  server_pid=$!
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43121/index.esi push_clone || { kill $server_pid; exit 1; }
+  cd push_clone
+  echo 'b' > b
+  "$elfshaker" store push-2
+  "$elfshaker" pack push-q
+  # The uploads without the token (or with another one) are rejected.
+  if "$elfshaker" push origin push-q; then kill $server_pid; exit 1; fi
+  if "$elfshaker" push origin push-q --token secreT; then kill $server_pid; exit 1; fi
+  if "$elfshaker" push origin push-q --token secret2; then kill $server_pid; exit 1; fi
+  [ ! -e ../push_server/elfshaker_data/packs/push-q.pack ] || { kill $server_pid; exit 1; }
+  ELFSHAKER_TOKEN=secret "$elfshaker" push origin push-q || { kill $server_pid; exit 1; }
+  kill $server_pid
+  cd ../push_server
+  "$elfshaker" extract --verify push-q:push-2
+  [ "$(cat b)" == 'b' ]
+  cd ..
+  rm -rf push_server push_clone
+}
+
+test_push_limits_works() {
+  mkdir push_limits_server
+  cd push_limits_server
+  echo 'a' > a
+  "$elfshaker" store pl-1
+  "$elfshaker" pack pl-p
+  "$elfshaker" serve --bind 127.0.0.1:43124 --push-token secret \
+    --max-pack-size 1000 --max-index-size 1000 &
+  server_pid=$!
+  cd ..
+  sleep 1
+
+  "$elfshaker" clone http://127.0.0.1:43124/index.esi push_limits_clone || { kill $server_pid; exit 1; }
+  cd push_limits_clone
+  # The packs larger than --max-pack-size are rejected.
+  head -c 5000 /dev/urandom > big
+  "$elfshaker" store pl-2
+  "$elfshaker" pack pl-q
+  "$elfshaker" gc --loose-snapshots
+  if "$elfshaker" push origin pl-q --token secret; then kill $server_pid; exit 1; fi
+  [ ! -e ../push_limits_server/elfshaker_data/packs/pl-q.pack ] || { kill $server_pid; exit 1; }
+  # So are the indexes larger than --max-index-size.
+  url=http://127.0.0.1:43124/packs/
+  head -c 1001 /dev/zero > large.pack.idx
+  status=$(curl -s -o /dev/null -w '%{http_code}' -X PUT -H 'Authorization: Bearer secret' \
+    --data-binary @large.pack.idx "${url}pl-s.pack.idx")
+  [ "$status" == 413 ] || { kill $server_pid; exit 1; }
+  # And the indexes with paths leaving the extracted directory.
+  rm big large.pack.idx
+  mkdir xx
+  echo 'x' > xx/evil
+  "$elfshaker" store pl-3
+  "$elfshaker" pack pl-r
+  sed -i 's,xx/evil,../evil,' elfshaker_data/packs/pl-r.pack.idx
+  status=$(curl -s -o /dev/null -w '%{http_code}' -X PUT -H 'Authorization: Bearer secret' \
+    --data-binary @elfshaker_data/packs/pl-r.pack.idx "${url}pl-r.pack.idx")
+  kill $server_pid
+  [ "$status" == 400 ]
+  [ ! -e ../push_limits_server/elfshaker_data/packs/pl-r.pack.idx ]
+  cd ..
+  rm -rf push_limits_server push_limits_clone
+}
+
+test_dedup_clone_works() {
+  mkdir dedup_server
+  cd dedup_server
+  head -c 200000 /dev/urandom > big
+  "$elfshaker" store dc-1
+  "$elfshaker" pack dc-p
+  "$elfshaker" gc --loose-snapshots
+  echo 'b' > b
+  "$elfshaker" store dc-2
+  "$elfshaker" pack --dedup dc-q
+  "$elfshaker" gc --loose-snapshots
+  "$elfshaker" serve --bind 127.0.0.1:43122 --push-token secret &
+  server_pid=$!
+  cd ..
+  sleep 1
+
+  # The cloned pack reads its objects from the pack of the same remote.
+  "$elfshaker" clone http://127.0.0.1:43122/index.esi dedup_clone || { kill $server_pid; exit 1; }
+  cd dedup_clone
+  "$elfshaker" extract --verify origin/dc-q:dc-2 || { kill $server_pid; exit 1; }
+  cmp big ../dedup_server/big || { kill $server_pid; exit 1; }
+  "$elfshaker" verify origin/dc-q || { kill $server_pid; exit 1; }
+
+  # The packs referencing the remote packs can't be pushed.
+  "$elfshaker" store dc-3
+  "$elfshaker" pack --dedup dc-r
+  "$elfshaker" gc --loose-snapshots
+  [ "$(stat -c %s elfshaker_data/packs/dc-r.pack)" -lt 100000 ] || { kill $server_pid; exit 1; }
+  if "$elfshaker" push origin dc-r --token secret; then kill $server_pid; exit 1; fi
+  # Nor served.
+  "$elfshaker" serve --bind 127.0.0.1:43123 &
+  clone_server_pid=$!
+  sleep 1
+  index=$(curl -s http://127.0.0.1:43123/index.esi)
+  kill $clone_server_pid
+  [[ "$index" == *'dc-q.pack'* && "$index" != *'dc-r.pack'* ]] || { kill $server_pid; exit 1; }
+
+  # The local packs are pushed after the packs they reference.
+  rm big b
+  echo 'c' > c
+  "$elfshaker" store dc-4
+  "$elfshaker" pack dc-s
+  "$elfshaker" gc --loose-snapshots
+  echo 'd' > d
+  "$elfshaker" store dc-5
+  "$elfshaker" pack --dedup dc-t
+  "$elfshaker" gc --loose-snapshots
+  if "$elfshaker" push origin dc-t --token secret; then kill $server_pid; exit 1; fi
+  [ ! -e ../dedup_server/elfshaker_data/packs/dc-t.pack.idx ] || { kill $server_pid; exit 1; }
+  "$elfshaker" push origin dc-s dc-t --token secret || { kill $server_pid; exit 1; }
+  kill $server_pid
+  cd ../dedup_server
+  "$elfshaker" extract --verify --reset dc-t:dc-5
+  [ "$(cat c d | paste -sd ' ')" == 'c d' ]
+  cd ..
+  rm -rf dedup_server dedup_clone
+}
+
+test_merge_packs_works() {
+  mkdir merge
+  cd merge
+  echo 'a' > a
+  "$elfshaker" store mg-1
+  "$elfshaker" pack mg-p
+  "$elfshaker" gc --loose-snapshots
+  echo 'b' > b
+  "$elfshaker" store mg-2
+  "$elfshaker" pack mg-q
+  "$elfshaker" gc --loose-snapshots
+  "$elfshaker" merge-packs --keep-packs mg-r mg-p mg-q
+  [ "$("$elfshaker" list mg-r | paste -sd ' ')" == 'mg-r:mg-1 mg-r:mg-2' ]
+  [ -e elfshaker_data/packs/mg-p.pack ]
+  # HEAD is updated when it references one of the sources.
+  [ "$(cat elfshaker_data/HEAD)" == 'mg-r:mg-2' ]
+  "$elfshaker" extract --verify mg-q:mg-2
+  # The sources are deleted otherwise.
+  "$elfshaker" merge-packs mg-s mg-p mg-q
+  [ ! -e elfshaker_data/packs/mg-p.pack ] && [ ! -e elfshaker_data/packs/mg-q.pack ]
+  [ "$(cat elfshaker_data/HEAD)" == 'mg-s:mg-2' ]
+  "$elfshaker" extract --verify mg-s:mg-1
+  [ ! -e b ]
+  # The tags must be unique across the sources.
+  if "$elfshaker" merge-packs mg-t mg-r mg-s; then exit 1; fi
+  [ -e elfshaker_data/packs/mg-r.pack ] && [ ! -e elfshaker_data/packs/mg-t.pack ]
+  cd ..
+  rm -rf merge
+}
+
+test_split_pack_works() {
+  mkdir split
+  cd split
+  for i in 1 2 3 4; do
+    echo "$i" > "f$i"
+    "$elfshaker" store "sp-$i"
+  done
+  "$elfshaker" pack sp-p
+  "$elfshaker" gc --loose-snapshots
+  "$elfshaker" split-pack --keep-pack sp-p 2
+  [ "$("$elfshaker" list sp-p-1 sp-p-2 | paste -sd ' ')" == 'sp-p-1:sp-1 sp-p-1:sp-2 sp-p-2:sp-3 sp-p-2:sp-4' ]
+  [ -e elfshaker_data/packs/sp-p.pack ]
+  [ "$(cat elfshaker_data/HEAD)" == 'sp-p-2:sp-4' ]
+  "$elfshaker" extract --verify sp-p:sp-4
+  "$elfshaker" split-pack --prefix sp-q sp-p 2
+  [ ! -e elfshaker_data/packs/sp-p.pack ]
+  [ "$(cat elfshaker_data/HEAD)" == 'sp-q-2:sp-4' ]
+  "$elfshaker" extract --verify sp-q-1:sp-2
+  [ -e f2 ] && [ ! -e f3 ]
+  "$elfshaker" extract --verify sp-q-2:sp-4
+  [ "$(cat f1 f4 | paste -sd ' ')" == '1 4' ]
+  if "$elfshaker" split-pack sp-q-1 3; then exit 1; fi
+  cd ..
+  rm -rf split
+}
+
+test_repack_works() {
+  mkdir repack
+  cd repack
+  echo 'a' > a
+  "$elfshaker" store rp-1
+  "$elfshaker" pack --compression-level 1 rp-p
+  "$elfshaker" gc --loose-snapshots
+  "$elfshaker" stats rp-p | grep 'compression: .*(level 1, '
+  # The objects are read from the pack itself.
+  "$elfshaker" repack rp-p --compression-level 19 --window-log 20
+  "$elfshaker" stats rp-p | grep 'compression: .*(level 19, window log 20)'
+  # The options which aren't specified are kept.
+  "$elfshaker" repack rp-p --window-log 21
+  "$elfshaker" stats rp-p | grep 'compression: .*(level 19, window log 21)'
+  rm a
+  "$elfshaker" extract --verify --reset rp-p:rp-1
+  [ "$(cat a)" == 'a' ]
+  if "$elfshaker" repack missing-p; then exit 1; fi
+  cd ..
+  rm -rf repack
+}
+
+test_stats_works() {
+  mkdir stats
+  cd stats
+  echo 'aaaa' > a
+  cp a b
+  "$elfshaker" store st-1
+  echo 'cc' > c
+  "$elfshaker" store st-2
+  "$elfshaker" pack st-p
+  "$elfshaker" gc --loose-snapshots
+  output=$("$elfshaker" stats st-p)
+  [[ "$output" == *"objects:           2"* ]]
+  [[ "$output" == *"unique size:       8B"* ]]
+  [[ "$output" == *"referenced size:   23B"* ]]
+  [[ "$output" == *"dedup ratio:       2.88x"* ]]
+  # The largest objects are listed first.
+  "$elfshaker" stats --json --top 1 > ../stats.json
+  grep '"referenced_size": 23' ../stats.json
+  [ "$(grep -c '"checksum"' ../stats.json)" == 1 ]
+  grep '"size": 5' ../stats.json
+  if "$elfshaker" stats missing-p; then exit 1; fi
+  cd ..
+  rm -rf stats stats.json
+}
+
+test_du_works() {
+  mkdir du
+  cd du
+  echo 'shared' > shared
+  "$elfshaker" store du-1
+  # The object of the duplicated files is only counted once.
+  echo 'dup' > a
+  cp a b
+  "$elfshaker" store du-2
+  [ "$("$elfshaker" du --bytes | paste -sd ' ')" == "0	7	loose/du-1:du-1 4	15	loose/du-2:du-2" ]
+  "$elfshaker" pack du-p
+  "$elfshaker" gc --loose-snapshots
+  [ "$("$elfshaker" du du-p --bytes | paste -sd ' ')" == "0	7	du-p:du-1 4	15	du-p:du-2" ]
+  "$elfshaker" du du-p | grep 'du-p:du-2'
+  cd ..
+  rm -rf du
+}
+
+test_python_bindings_works() {
+  # The pyelfshaker module is only tested when it is installed (or found in
+  # PYTHONPATH), see docs/users/installing.md.
+  if ! python3 -c 'import pyelfshaker' 2> /dev/null; then
+    echo 'Skipped, pyelfshaker is not installed'
+    return
+  fi
+  mkdir python
+  cd python
+  echo 'a' > a
+  mkdir b
+  echo 'c' > b/c
+  "$elfshaker" store py-1
+  "$elfshaker" pack py-p
+  "$elfshaker" gc --loose-snapshots
+  rm -rf a b
+  python3 - <<'PY'
+import pyelfshaker
+
+repo = pyelfshaker.Repository(".")
+assert repo.packs() == ["py-p"], repo.packs()
+assert repo.snapshots() == ["py-p:py-1"], repo.snapshots()
+assert repo.find_snapshot("py-1") == "py-p:py-1"
+assert sorted(repo.files("py-1")) == ["a", "b/c"]
+assert repo.read_file("py-1", "b/c") == b"c\n"
+assert repo.extract("py-1", verify=True, reset=True)["added"] == 2
+try:
+    repo.find_snapshot("missing")
+    raise AssertionError("missing snapshot found")
+except pyelfshaker.Error:
+    pass
+PY
+  [ "$(cat b/c)" == 'c' ]
+  [ "$(cat elfshaker_data/HEAD)" == 'py-p:py-1' ]
+  cd ..
+  rm -rf python
+}
+
+test_extract_link_works() {
+  mkdir link
+  cd link
+  echo 'a' > a
+  echo 'b' > b
+  "$elfshaker" store ln-1
+  echo 'b2' > b
+  "$elfshaker" store ln-2
+  "$elfshaker" pack ln-p
+  "$elfshaker" gc --loose-snapshots
+  "$elfshaker" extract --reset --link ln-p:ln-1
+  # The files are read-only hard links to the cached objects.
+  [ "$(stat -c %h a)" -ge 2 ] && [[ "$(stat -c %A a)" != *w* ]]
+  inode=$(stat -c %i a)
+  cached=$(find elfshaker_data/cache -type f -inum "$inode")
+  [ -n "$cached" ]
+  # Another working directory sharing the data directory shares the files.
+  mkdir ../link-2
+  (cd ../link-2 && "$elfshaker" --data-dir ../link/elfshaker_data extract --reset --link ln-p:ln-1)
+  [ "$(stat -c %i ../link-2/a)" == "$inode" ]
+  # The linked files are replaced, rather than written to.
+  "$elfshaker" extract --link ln-p:ln-2
+  [ "$(cat b)" == 'b2' ] && [ "$(cat ../link-2/b)" == 'b' ]
+  "$elfshaker" extract --verify --reset ln-p:ln-1
+  [ "$(cat b)" == 'b' ] && [[ "$(stat -c %A b)" == -rw* ]]
+  [ "$(cat ../link-2/b)" == 'b' ]
+  if "$elfshaker" extract --link --preserve-times ln-p:ln-2; then exit 1; fi
+  cd ..
+  chmod -R u+w link link-2
+  rm -rf link link-2
+}
+
+test_extract_exclude_works() {
+  mkdir exclude
+  cd exclude
+  mkdir -p src/test docs
+  echo 'main' > src/main.c
+  echo 'test' > src/test/t.c
+  echo 'doc' > docs/a.md
+  echo 'o' > src/main.o
+  "$elfshaker" store ex-1
+  "$elfshaker" pack ex-p
+  "$elfshaker" gc --loose-snapshots
+  head=$(cat elfshaker_data/HEAD)
+  rm -rf src docs
+  # The matching directories and files are skipped, and can be repeated.
+  "$elfshaker" extract --reset --exclude src/test --exclude '**/*.o' ex-p:ex-1
+  [ -e src/main.c ] && [ -e docs/a.md ] && [ ! -e src/test ] && [ ! -e src/main.o ]
+  [ "$(cat elfshaker_data/HEAD)" == "$head" ]
+  # The skipped files are left as they are.
+  echo 'changed' > docs/a.md
+  "$elfshaker" extract --reset --exclude docs ex-p:ex-1
+  [ "$(cat docs/a.md)" == 'changed' ] && [ -e src/test/t.c ]
+  # --exclude takes precedence over --path.
+  "$elfshaker" extract --reset --path src --exclude 'src/*.c' --output ../exclude-out ex-p:ex-1
+  [ "$(cd ../exclude-out && find . -type f | sort | paste -sd ' ')" == './src/main.o ./src/test/t.c' ]
+  cd ..
+  rm -rf exclude exclude-out
+}
+
+test_progress_bars_works() {
+  # The terminal is emulated with script (from util-linux).
+  if ! script -qec true /dev/null < /dev/null > /dev/null 2>&1; then
+    echo 'Skipped, script is not available'
+    return
+  fi
+  mkdir progress-bars
+  cd progress-bars
+  echo 'a' > a
+  "$elfshaker" store pb-1
+  # The bars are drawn when stderr is a terminal, with the throughput.
+  output=$(script -qec "'$elfshaker' pack pb-p" /dev/null < /dev/null)
+  [[ "$output" == *'Compressing objects'*'[1/1] in '*'/s)'* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  # Plain lines are printed otherwise.
+  output=$("$elfshaker" pack pb-q 2>&1)
+  [[ "$output" == *'Compressing objects [1/1]'* && "$output" != *'/s)'* ]] || {
+    echo "Failed: $output"
+    exit 1
+  }
+  cd ..
+  rm -rf progress-bars
+}
+
+test_extract_symlink_escape_works() {
+  mkdir escape escape-outside
+  cd escape
+  outside=$(cd ../escape-outside && pwd)
+  mkdir d
+  echo 'x' > d/x
+  "$elfshaker" store esc-b
+  rm -r d
+  ln -s "$outside" d
+  "$elfshaker" store esc-a
+  "$elfshaker" pack esc-p
+  # The symbolic links of the previous snapshot are not followed.
+  for pack in loose/esc-b esc-p; do
+    rm -rf d
+    "$elfshaker" extract --force --reset "${pack/esc-b/esc-a}:esc-a"
+    [ -L d ]
+    "$elfshaker" extract --path d/x "$pack:esc-b"
+    [ -f d/x ] && [ ! -L d ] && [ ! -e "$outside/x" ]
+  done
+  # Neither are the ones in the working directory.
+  for extract_args in "" "--reflink" "--link"; do
+    "$elfshaker" extract --reset esc-p:esc-b
+    rm -r d
+    ln -s "$outside" d
+    "$elfshaker" extract --force --reset $extract_args esc-p:esc-b
+    [ -f d/x ] && [ ! -L d ] && [ ! -e "$outside/x" ]
+  done
+  # The snapshots with files inside their symbolic links are rejected.
+  mkdir ../escape-tar
+  ln -s "$outside" ../escape-tar/l
+  mkdir ../escape-tar/dir
+  echo 'y' > ../escape-tar/dir/y
+  tar -c -f ../escape.tar -C ../escape-tar l
+  tar -r -f ../escape.tar -C ../escape-tar dir/y --transform 's,^dir,l,'
+  if "$elfshaker" import ../escape.tar esc-c; then exit 1; fi
+  [ ! -e elfshaker_data/packs/loose/esc-c.pack.idx ]
+  cd ..
+  rm -rf escape escape-outside escape-tar escape.tar
+}
+
+test_extract_path_escape_works() {
+  mkdir path-escape
+  cd path-escape
+  mkdir xx
+  echo 'x' > xx/evil
+  "$elfshaker" store pe-s
+  "$elfshaker" pack --index-format flat pe-p
+  # The paths leaving the directory are rejected when the index is loaded.
+  for index in elfshaker_data/packs/loose/pe-s.pack.idx elfshaker_data/packs/pe-p.pack.idx; do
+    sed -i 's,xx/evil,../evil,' "$index"
+  done
+  for pack in loose/pe-s pe-p; do
+    if "$elfshaker" extract --force --reset "$pack:pe-s"; then exit 1; fi
+    if "$elfshaker" verify "$pack"; then exit 1; fi
+    [ ! -e ../evil ]
+  done
+  cd ..
+  rm -rf path-escape
+}
+
 test_store_works() {
   "$elfshaker" --verbose extract --verify --reset "$pack":"$snapshot_b"
   "$elfshaker" --verbose store "$snapshot_b"
@@ -493,6 +3092,16 @@ test_show_from_loose_works() {
   fi
 }
 
+test_show_empty_file_works() {
+  echo 'data' > a
+  touch b
+  "$elfshaker" store show-empty
+  "$elfshaker" pack show-empty-p
+  # The empty object has the same offset as the object following it.
+  [ "$("$elfshaker" show show-empty-p:show-empty a b)" == 'data' ]
+  [ "$("$elfshaker" show show-empty-p:show-empty b a)" == 'data' ]
+}
+
 test_head_updated_after_packing() {
   rand_megs 1 > ./foo
   "$elfshaker" store test-snapshot
@@ -728,10 +3337,97 @@ main() {
   fi
 
   run_test test_list_works
+  run_test test_list_filter_works
+  run_test test_diff_works
+  run_test test_gc_default_works
+  run_test test_upgrade_works
+  run_test test_checksum_algorithm_works
+  run_test test_pack_max_frame_size_works
+  run_test test_pack_delta_works
+  run_test test_pack_chunk_works
+  run_test test_pack_codec_works
+  run_test test_sparse_files_works
+  run_test test_xattrs_works
+  run_test test_pack_similarity_works
+  run_test test_pack_frame_size_works
   run_test test_extract_reset_on_empty_works
   run_test test_extract_again_works
   run_test test_extract_different_works
+  run_test test_extract_interrupted_works
   run_test test_extract_file_modes_preserved
+  run_test test_store_symlinks_works
+  run_test test_store_empty_directories_works
+  run_test test_extract_preserve_times_works
+  run_test test_extract_case_collisions_works
+  run_test test_extract_paths_works
+  run_test test_extract_output_works
+  run_test test_extract_to_stdout_works
+  run_test test_concurrent_stores_work
+  run_test test_pack_is_written_atomically
+  run_test test_store_cache_works
+  run_test test_store_watch_works
+  run_test test_store_null_files_from_works
+  run_test test_store_ignore_file_works
+  run_test test_config_works
+  run_test test_directory_works
+  run_test test_completions_works
+  run_test test_progress_json_works
+  run_test test_verbosity_works
+  run_test test_log_format_json_works
+  run_test test_pack_max_size_works
+  run_test test_pack_dictionary_works
+  run_test test_extract_fetches_frames_works
+  run_test test_extract_resumes_downloads_works
+  run_test test_extract_fails_over_to_mirrors_works
+  run_test test_sign_works
+  run_test test_sign_follows_pack_works
+  run_test test_trust_policy_works
+  run_test test_encryption_works
+  run_test test_flat_index_works
+  run_test test_index_cache_works
+  run_test test_extract_verify_works
+  run_test test_extract_modified_works
+  run_test test_pin_works
+  run_test test_prune_works
+  run_test test_snapshot_time_works
+  run_test test_log_works
+  run_test test_tag_works
+  run_test test_worktree_works
+  run_test test_find_paths_works
+  run_test test_grep_works
+  run_test test_bloom_filters_works
+  run_test test_object_index_works
+  run_test test_dedup_works
+  run_test test_pack_selection_works
+  run_test test_hooks_works
+  run_test test_store_git_works
+  run_test test_index_export_works
+  run_test test_build_cache_works
+  run_test test_list_sort_works
+  run_test test_list_files_works
+  run_test test_show_order_works
+  run_test test_find_object_works
+  run_test test_status_works
+  run_test test_verify_works
+  run_test test_rm_works
+  run_test test_rename_works
+  run_test test_export_works
+  run_test test_import_works
+  run_test test_clone_works
+  run_test test_push_works
+  run_test test_push_limits_works
+  run_test test_dedup_clone_works
+  run_test test_merge_packs_works
+  run_test test_split_pack_works
+  run_test test_repack_works
+  run_test test_stats_works
+  run_test test_du_works
+  run_test test_python_bindings_works
+  run_test test_extract_link_works
+  run_test test_extract_exclude_works
+  run_test test_progress_bars_works
+  run_test test_extract_symlink_escape_works
+  run_test test_extract_path_escape_works
   run_test test_store_works
   run_test test_store_and_extract_different_works
   run_test test_store_twice_works
@@ -747,6 +3443,7 @@ main() {
   run_test test_pack_order_by_mtime
   run_test test_show_from_pack_works
   run_test test_show_from_loose_works
+  run_test test_show_empty_file_works
   run_test test_head_updated_after_packing
   run_test test_touched_file_dirties_repo
   run_test test_dirty_repo_can_be_forced